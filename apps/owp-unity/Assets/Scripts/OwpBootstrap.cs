@@ -851,90 +851,28 @@ public class OwpBootstrap : MonoBehaviour
         AppendLog($"Net: welcome → {task.Result.motd}");
     }
 
-    private static async Task<HandshakeResult> ConnectAndHandshakeAsync(string host, int port, string worldId)
+    private static Task<HandshakeResult> ConnectAndHandshakeAsync(string host, int port, string worldId)
     {
-        try
+        // The wire format and handshake live in the owp_ffi native library (crates/owp-ffi).
+        return Task.Run(() =>
         {
-            using (var client = new TcpClient())
+            try
             {
-                await client.ConnectAsync(host, port);
-                using (var stream = client.GetStream())
+                using (var conn = OwpConnection.Connect($"{host}:{port}", worldId, "{\"client_name\":\"owp-unity\"}"))
                 {
-                    var hello = new HelloMessage
-                    {
-                        type = "hello",
-                        protocol_version = "0.1",
-                        request_id = Guid.NewGuid().ToString(),
-                        world_id = worldId,
-                        client_name = "owp-unity"
-                    };
-                    var helloJson = JsonUtility.ToJson(hello);
-                    await WriteFrame(stream, helloJson);
-
-                    var msgJson = await ReadFrame(stream);
-                    if (string.IsNullOrEmpty(msgJson))
-                    {
-                        return new HandshakeResult { ok = false, error = "empty response" };
-                    }
-
-                    var env = JsonUtility.FromJson<MessageEnvelope>(msgJson);
-                    if (env == null || string.IsNullOrEmpty(env.type))
-                    {
-                        return new HandshakeResult { ok = false, error = "invalid message envelope" };
-                    }
-
-                    if (env.type != "welcome")
-                    {
-                        return new HandshakeResult { ok = false, error = "unexpected message: " + env.type };
-                    }
-
-                    var welcome = JsonUtility.FromJson<WelcomeMessage>(msgJson);
-                    return new HandshakeResult { ok = true, motd = welcome != null ? welcome.motd : "Welcome" };
+                    var welcome = JsonUtility.FromJson<WelcomeMessage>(conn.WelcomeJson());
+                    return new HandshakeResult { ok = true, motd = welcome != null && !string.IsNullOrEmpty(welcome.motd) ? welcome.motd : "Welcome" };
                 }
             }
-        }
-        catch (Exception ex)
-        {
-            return new HandshakeResult { ok = false, error = ex.Message };
-        }
-    }
-
-    private static async Task WriteFrame(NetworkStream stream, string json)
-    {
-        var payload = Encoding.UTF8.GetBytes(json);
-        var len = payload.Length;
-        var header = new byte[4];
-        header[0] = (byte)((len >> 24) & 0xFF);
-        header[1] = (byte)((len >> 16) & 0xFF);
-        header[2] = (byte)((len >> 8) & 0xFF);
-        header[3] = (byte)(len & 0xFF);
-        await stream.WriteAsync(header, 0, 4);
-        await stream.WriteAsync(payload, 0, payload.Length);
-        await stream.FlushAsync();
-    }
-
-    private static async Task<string> ReadFrame(NetworkStream stream)
-    {
-        var header = await ReadExact(stream, 4);
-        if (header == null || header.Length != 4) return null;
-        var len = (header[0] << 24) | (header[1] << 16) | (header[2] << 8) | header[3];
-        if (len <= 0 || len > 4 * 1024 * 1024) return null;
-        var payload = await ReadExact(stream, len);
-        if (payload == null) return null;
-        return Encoding.UTF8.GetString(payload);
-    }
-
-    private static async Task<byte[]> ReadExact(NetworkStream stream, int n)
-    {
-        var buf = new byte[n];
-        var off = 0;
-        while (off < n)
-        {
-            var read = await stream.ReadAsync(buf, off, n - off);
-            if (read <= 0) return null;
-            off += read;
-        }
-        return buf;
+            catch (DllNotFoundException)
+            {
+                return new HandshakeResult { ok = false, error = "owp_ffi native library not found (see README)" };
+            }
+            catch (Exception ex)
+            {
+                return new HandshakeResult { ok = false, error = ex.Message };
+            }
+        });
     }
 
     private static string InferArchetype(AvatarSpec avatar)
@@ -3010,22 +2948,6 @@ public class OwpBootstrap : MonoBehaviour
         public int asset_port;
     }
 
-    [Serializable]
-    private class MessageEnvelope
-    {
-        public string type;
-    }
-
-    [Serializable]
-    private class HelloMessage
-    {
-        public string type;
-        public string protocol_version;
-        public string request_id;
-        public string world_id;
-        public string client_name;
-    }
-
     [Serializable]
     private class WelcomeMessage
     {
@@ -0,0 +1,117 @@
+using System;
+using System.Runtime.InteropServices;
+
+// Managed side of the owp_ffi native library (crates/owp-ffi). Framing, the handshake and
+// message validation live in Rust; messages cross over as the protocol's JSON.
+
+public class OwpException : Exception
+{
+    public OwpException(string message) : base(message ?? "owp_ffi call failed") { }
+}
+
+public sealed class OwpConnection : IDisposable
+{
+    private IntPtr _handle;
+
+    private OwpConnection(IntPtr handle)
+    {
+        _handle = handle;
+    }
+
+    // Connect to host:port and handshake. optionsJson: null or e.g. {"client_name":"owp-unity"}.
+    public static OwpConnection Connect(string addr, string worldId, string optionsJson = null)
+    {
+        var handle = OwpNative.owp_connect(addr, worldId, optionsJson);
+        if (handle == IntPtr.Zero) throw new OwpException(LastError());
+        return new OwpConnection(handle);
+    }
+
+    public string WelcomeJson()
+    {
+        return Take(OwpNative.owp_welcome(Handle));
+    }
+
+    public void Send(string messageJson)
+    {
+        if (OwpNative.owp_send_message(Handle, messageJson) != 0) throw new OwpException(LastError());
+    }
+
+    // Next message from the server as JSON, or null if none arrived within timeoutMs.
+    // Throws once the connection is closed.
+    public string Poll(uint timeoutMs)
+    {
+        var json = Take(OwpNative.owp_poll_message(Handle, timeoutMs));
+        if (json == null)
+        {
+            var err = LastError();
+            if (err != null) throw new OwpException(err);
+        }
+        return json;
+    }
+
+    public void Dispose()
+    {
+        if (_handle == IntPtr.Zero) return;
+        OwpNative.owp_connection_free(_handle);
+        _handle = IntPtr.Zero;
+    }
+
+    private IntPtr Handle
+    {
+        get
+        {
+            if (_handle == IntPtr.Zero) throw new ObjectDisposedException(nameof(OwpConnection));
+            return _handle;
+        }
+    }
+
+    internal static string Take(IntPtr p)
+    {
+        if (p == IntPtr.Zero) return null;
+        try
+        {
+            return Marshal.PtrToStringUTF8(p);
+        }
+        finally
+        {
+            OwpNative.owp_string_free(p);
+        }
+    }
+
+    // Must run on the thread that made the failed call.
+    internal static string LastError()
+    {
+        return Take(OwpNative.owp_last_error());
+    }
+}
+
+public static class OwpAdmin
+{
+    // Blocking admin API call; status is the HTTP status. Throws if the server can't be reached.
+    public static string Request(
+        string baseUrl,
+        string token,
+        string method,
+        string path,
+        string bodyJson,
+        uint timeoutSecs,
+        out ushort status
+    )
+    {
+        var body = OwpConnection.Take(
+            OwpNative.owp_admin_request(baseUrl, token, method, path, bodyJson, timeoutSecs, out status)
+        );
+        if (body == null) throw new OwpException(OwpConnection.LastError());
+        return body;
+    }
+
+    // Redeem a pairing code shown by the host (`owp-server pair`). Returns the response JSON,
+    // whose "api_key" is the bearer token to use from now on. Throws if the code is refused.
+    public static string Pair(string baseUrl, string code)
+    {
+        var clean = code.Replace("\\", "").Replace("\"", "");
+        var body = Request(baseUrl, null, "POST", "/auth/pair/complete", "{\"code\":\"" + clean + "\"}", 30, out var status);
+        if (status != 200) throw new OwpException("pairing failed (" + status + "): " + body);
+        return body;
+    }
+}
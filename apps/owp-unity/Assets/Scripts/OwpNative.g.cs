@@ -0,0 +1,87 @@
+// Generated from crates/owp-ffi/src/lib.rs; do not edit. Regenerate with
+// `OWP_UPDATE_BINDINGS=1 cargo test -p owp-ffi`.
+
+using System;
+using System.Runtime.InteropServices;
+
+internal static class OwpNative
+{
+    private const string Lib = "owp_ffi";
+
+    // Protocol version this library speaks.
+    [DllImport(Lib, CallingConvention = CallingConvention.Cdecl)]
+    internal static extern IntPtr owp_protocol_version();
+
+    // Why the last failed call on this thread failed, or null.
+    [DllImport(Lib, CallingConvention = CallingConvention.Cdecl)]
+    internal static extern IntPtr owp_last_error();
+
+    // Release a string returned by this library. Null is ignored.
+    [DllImport(Lib, CallingConvention = CallingConvention.Cdecl)]
+    internal static extern void owp_string_free(IntPtr s);
+
+    // Split `owp://host:port?world=<uuid>` into `{"addr": "host:port", "world_id": "..."}`.
+    [DllImport(Lib, CallingConvention = CallingConvention.Cdecl)]
+    internal static extern IntPtr owp_parse_connect_string([MarshalAs(UnmanagedType.LPUTF8Str)] string connect);
+
+    // Connect to `addr` (`host:port`) and handshake for `world_id`. `options_json` is null or an
+    // object with any of `client_name`, `resume_token`, `party_token`, `wallet_pubkey`,
+    // `wallet_proof` (`{timestamp, nonce, signature}` signed by that wallet), `profile_id`,
+    // `frame_protection` (default `["crc32c"]`), `wire_format` (e.g. `["msgpack"]`; default JSON),
+    // `compression` (default `["zstd"]`; `[]` for uncompressed frames),
+    // `world_pubkey` (base58; connect over Noise, refuse
+    // a server that doesn't hold the world authority key or attests for another host), `noise`
+    // (Noise without checking the server) and `proxy` (`socks5://`, `socks5h://` or `http://` URL;
+    // defaults to `OWP_PROXY`, then `ALL_PROXY`). Release the connection with `owp_connection_free`.
+    [DllImport(Lib, CallingConvention = CallingConvention.Cdecl)]
+    internal static extern IntPtr owp_connect([MarshalAs(UnmanagedType.LPUTF8Str)] string addr, [MarshalAs(UnmanagedType.LPUTF8Str)] string world_id, [MarshalAs(UnmanagedType.LPUTF8Str)] string options_json);
+
+    // The server's `welcome` message as JSON.
+    [DllImport(Lib, CallingConvention = CallingConvention.Cdecl)]
+    internal static extern IntPtr owp_welcome(IntPtr conn);
+
+    // Send one message, given as its JSON. It is checked against the protocol first, so a
+    // malformed message fails here instead of on the server. Returns 0, or -1 on failure.
+    [DllImport(Lib, CallingConvention = CallingConvention.Cdecl)]
+    internal static extern int owp_send_message(IntPtr conn, [MarshalAs(UnmanagedType.LPUTF8Str)] string message_json);
+
+    // The next message from the server as JSON, waiting up to `timeout_ms` (0 = don't wait).
+    // Null when nothing arrived in time; null with `owp_last_error` set once the connection is
+    // closed.
+    [DllImport(Lib, CallingConvention = CallingConvention.Cdecl)]
+    internal static extern IntPtr owp_poll_message(IntPtr conn, uint timeout_ms);
+
+    // Close the connection and release it. Null is ignored.
+    [DllImport(Lib, CallingConvention = CallingConvention.Cdecl)]
+    internal static extern void owp_connection_free(IntPtr conn);
+
+    // Call the admin API: `method` `base_url` + `path` with an optional JSON body and bearer
+    // token. Returns the response body and stores the HTTP status in `status`; null when the
+    // server couldn't be reached. `timeout_secs` 0 means 30 seconds. Requests go through the
+    // `http://` proxy in `OWP_PROXY` (or `ALL_PROXY`) when one is set.
+    [DllImport(Lib, CallingConvention = CallingConvention.Cdecl)]
+    internal static extern IntPtr owp_admin_request([MarshalAs(UnmanagedType.LPUTF8Str)] string base_url, [MarshalAs(UnmanagedType.LPUTF8Str)] string token, [MarshalAs(UnmanagedType.LPUTF8Str)] string method, [MarshalAs(UnmanagedType.LPUTF8Str)] string path, [MarshalAs(UnmanagedType.LPUTF8Str)] string body_json, uint timeout_secs, out ushort status);
+
+    // Bring `cache_dir` up to date with the world's assets: read `GET /worlds/<world_id>/sync` from
+    // the admin API at `base_url` and download each listed blob that isn't cached yet, named by its
+    // sha256. An interrupted download is kept as `<sha256>.part` and resumed on the next call.
+    // `peers_json` is null or `{"<sha256>": ["host:port", ...]}` from `peer_list` replies; those
+    // players are tried first and anything that fails the hash check comes from the host instead.
+    // Returns `{cached, downloaded, resumed, from_peers, bytes}` as JSON, or null on failure.
+    [DllImport(Lib, CallingConvention = CallingConvention.Cdecl)]
+    internal static extern IntPtr owp_sync_assets([MarshalAs(UnmanagedType.LPUTF8Str)] string base_url, [MarshalAs(UnmanagedType.LPUTF8Str)] string token, [MarshalAs(UnmanagedType.LPUTF8Str)] string world_id, [MarshalAs(UnmanagedType.LPUTF8Str)] string cache_dir, [MarshalAs(UnmanagedType.LPUTF8Str)] string peers_json);
+
+    // Serve the blobs in `cache_dir` to other players on `listen` (`host:port`; port 0 picks one),
+    // for worlds with the `peer_assist` capability. Announce them with a `peer_announce` message
+    // carrying the port from `owp_peer_server_addr`. Stop with `owp_peer_server_free`.
+    [DllImport(Lib, CallingConvention = CallingConvention.Cdecl)]
+    internal static extern IntPtr owp_peer_serve([MarshalAs(UnmanagedType.LPUTF8Str)] string cache_dir, [MarshalAs(UnmanagedType.LPUTF8Str)] string listen);
+
+    // The `host:port` the cache server listens on.
+    [DllImport(Lib, CallingConvention = CallingConvention.Cdecl)]
+    internal static extern IntPtr owp_peer_server_addr(IntPtr server);
+
+    // Stop serving and release the server. Null is ignored.
+    [DllImport(Lib, CallingConvention = CallingConvention.Cdecl)]
+    internal static extern void owp_peer_server_free(IntPtr server);
+}
@@ -1,23 +1,54 @@
 use anyhow::{Context, Result};
-use clap::Parser;
-use owp_protocol::{wire, Hello, Message, OWP_PROTOCOL_VERSION};
-use std::net::SocketAddr;
-use tokio::net::TcpStream;
+use clap::{Parser, Subcommand};
+use ed25519_dalek::SigningKey;
+
+mod session;
+
+use owp_discovery::favorites::{self, favorites_path};
+use owp_discovery::proxy::Proxy;
+use owp_discovery::{
+    alias, connect_string_pubkey, decode_world_pubkey, directory, parse_connect_string, probe,
+};
+use owp_protocol::{
+    ChatChannel, ChatSend, ChunkCoord, ChunkDeltaRequest, Emote, Message, PartyCreate, PartyInvite,
+    PartyJoin, PartyLeave, PartyResult, PartyTravel, PathQuery, PlayerPosition,
+    WorldDirectoryEntry,
+};
+use session::{Identity, RetryPolicy, Session, Transport};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tracing_subscriber::EnvFilter;
-use url::Url;
 use uuid::Uuid;
 
 #[derive(Debug, Parser)]
-#[command(
-    name = "owp-client",
-    version,
-    about = "OWP minimal test client (handshake)"
-)]
+#[command(name = "owp-client", version, about = "OWP minimal test client")]
 struct Cli {
-    /// Connect string like `owp://127.0.0.1:7777?world=<uuid>`
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Where client state (favorites) lives; defaults to `~/.owp`
+    #[arg(long, env = "OWP_DATA_DIR", global = true)]
+    data_dir: Option<PathBuf>,
+
+    /// Proxy for world connections and discovery: `socks5://`, `socks5h://` (e.g. Tor) or
+    /// `http://`; defaults to `OWP_PROXY`, then `ALL_PROXY`
+    #[arg(long, global = true)]
+    proxy: Option<Proxy>,
+
+    /// Connect string like `owp://127.0.0.1:7777?world=<uuid>`, or `owp://name/<alias>` for a
+    /// world alias claimed in the registry
     #[arg(long)]
     connect: Option<String>,
 
+    /// Solana RPC to resolve `owp://name/<alias>` connect strings with, and to look up the world
+    /// authority the server must prove it holds
+    #[arg(long, env = "OWP_SOLANA_RPC_URL")]
+    solana_rpc_url: Option<String>,
+
+    /// Registry program to resolve aliases and world authorities in
+    #[arg(long, env = "OWP_REGISTRY_PROGRAM_ID")]
+    registry_program_id: Option<String>,
+
     /// Host:port (used if --connect is not provided)
     #[arg(long)]
     addr: Option<String>,
@@ -25,6 +56,155 @@ struct Cli {
     /// World id (used if --connect is not provided)
     #[arg(long)]
     world_id: Option<String>,
+
+    /// After the handshake, request a chunk delta for `<x>,<z>`
+    #[arg(long)]
+    chunk: Option<String>,
+
+    /// Chunk version the client already holds (0 = full chunk)
+    #[arg(long, default_value_t = 0)]
+    since: u64,
+
+    /// Keep the session open and read commands from stdin (`chunk <x>,<z> [since]`,
+    /// `pos <x>,<y>,<z>`, `path <x>,<y>,<z> <x>,<y>,<z>`, `emote <id> [ms]`, `say|near|party <text>`,
+    /// `whisper <player_id> <text>`, `party-create`, `party-invite <player_id>`,
+    /// `party-join <party_id>`, `party-leave`, `party-travel <connect>`, `listen <secs>`, `quit`)
+    #[arg(long)]
+    interactive: bool,
+
+    /// Wallet pubkey to go by in the world, so friends can find you
+    #[arg(long)]
+    wallet_pubkey: Option<String>,
+
+    /// Solana CLI keypair file (JSON array of 64 bytes) to prove --wallet-pubkey with; sets it
+    /// if omitted
+    #[arg(long)]
+    wallet_keypair: Option<PathBuf>,
+
+    /// Pay the entry fee of a world that charges one from --wallet-keypair, through
+    /// --solana-rpc-url
+    #[arg(long)]
+    pay_entry_fee: bool,
+
+    /// Accept the rules of a world that asks players to acknowledge them. Read them first: the
+    /// refusal without this flag names where they are
+    #[arg(long)]
+    accept_rules: bool,
+
+    /// Profile on the world's host to go by; its friends list decides whose arrivals you hear
+    /// about. Defaults to the wallet proven with --wallet-keypair
+    #[arg(long)]
+    profile_id: Option<String>,
+
+    /// World authority pubkey (base58, as in the registry entry). Connects over Noise (or TLS
+    /// with --tls), refuses a server that can't prove it holds this key, and checks the server's
+    /// attestation for the host dialed. Taken from the connect string's `pubkey=` if not given.
+    #[arg(long)]
+    world_pubkey: Option<String>,
+
+    /// Encrypt with Noise even without a --world-pubkey to check the server against
+    #[arg(long)]
+    noise: bool,
+
+    /// Connect over TLS instead of Noise. With a world pubkey the server's certificate is pinned
+    /// to it; without one it must be from a public CA for the host dialed
+    #[arg(long, conflicts_with = "noise")]
+    tls: bool,
+
+    /// Token from an earlier `party_result`, to bring the party along to this world
+    #[arg(long)]
+    party_token: Option<String>,
+
+    /// First reconnect delay after a dropped connection; doubles per attempt
+    #[arg(long, default_value_t = 250)]
+    retry_initial_ms: u64,
+
+    /// Upper bound for the reconnect delay
+    #[arg(long, default_value_t = 10_000)]
+    retry_max_ms: u64,
+
+    /// Give up after this many failed reconnects in a row (0 = keep trying)
+    #[arg(long, default_value_t = 10)]
+    retry_max_attempts: u32,
+
+    /// Ping the server every this many seconds until interrupted, printing each round trip and
+    /// anything the server pushes
+    #[arg(long, value_name = "SECS", conflicts_with = "interactive")]
+    keepalive: Option<u64>,
+
+    /// Send a connection quality report (`NetReport`) at most this often (0 = never)
+    #[arg(long, default_value_t = 5)]
+    net_report_secs: u64,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// List worlds from the registry and/or directory files, probed from this machine
+    Discover {
+        /// Directory file URL or path (repeatable)
+        #[arg(long = "directory", env = "OWP_DIRECTORY_URLS", value_delimiter = ',')]
+        directories: Vec<String>,
+        /// Trusted directory publisher key, base58 (repeatable)
+        #[arg(
+            long = "directory-key",
+            env = "OWP_DIRECTORY_TRUSTED_KEYS",
+            value_delimiter = ','
+        )]
+        trusted_keys: Vec<String>,
+        #[arg(long, env = "OWP_SOLANA_RPC_URL")]
+        solana_rpc_url: Option<String>,
+        #[arg(long, env = "OWP_REGISTRY_PROGRAM_ID")]
+        registry_program_id: Option<String>,
+        /// Print how many registry accounts couldn't be read, by reason, to stderr
+        #[arg(long)]
+        registry_report: bool,
+        #[arg(long, value_enum, default_value_t = DiscoverSort::Latency)]
+        sort: DiscoverSort,
+        /// Group worlds by hosting region
+        #[arg(long)]
+        group_by_region: bool,
+        /// Per-world handshake timeout
+        #[arg(long, default_value_t = 2000)]
+        timeout_ms: u64,
+        /// Print JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Bookmarked worlds
+    Favorites {
+        #[command(subcommand)]
+        action: FavoritesAction,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum FavoritesAction {
+    /// Add a world, or update its name / note
+    Add {
+        /// Connect string like `owp://127.0.0.1:7777?world=<uuid>`
+        connect: String,
+        #[arg(long)]
+        name: Option<String>,
+        #[arg(long)]
+        note: Option<String>,
+    },
+    /// Remove a world by id or connect string
+    Remove { world: String },
+    List {
+        /// Print JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum DiscoverSort {
+    /// Fastest first; unreachable worlds last
+    Latency,
+    /// Most registry stake first
+    Stake,
+    /// As listed
+    None,
 }
 
 #[tokio::main]
@@ -34,7 +214,108 @@ async fn main() -> Result<()> {
         .init();
 
     let cli = Cli::parse();
+    let data_dir = match cli.data_dir {
+        Some(dir) => dir,
+        None => default_data_dir()?,
+    };
+    let proxy = match cli.proxy.clone() {
+        Some(p) => Some(p),
+        None => Proxy::from_env()?,
+    };
+    match cli.command {
+        Some(Command::Discover {
+            directories,
+            trusted_keys,
+            solana_rpc_url,
+            registry_program_id,
+            registry_report,
+            sort,
+            group_by_region,
+            timeout_ms,
+            json,
+        }) => {
+            let chain = solana_rpc_url
+                .as_deref()
+                .zip(registry_program_id.as_deref());
+            if chain.is_none() && directories.is_empty() {
+                anyhow::bail!(
+                "nothing to discover: pass --directory or --solana-rpc-url and --registry-program-id"
+            );
+            }
+            let mut worlds = match chain {
+                Some((rpc, program)) if registry_report => {
+                    let scan = owp_discovery::scan_registry(rpc, program, proxy.as_ref()).await?;
+                    eprintln!(
+                        "registry: {} accounts, {} worlds, {} skipped",
+                        scan.accounts,
+                        scan.worlds.len(),
+                        scan.skipped_total()
+                    );
+                    for (reason, count) in &scan.skipped {
+                        eprintln!("  {reason}: {count}");
+                    }
+                    scan.worlds
+                }
+                Some((rpc, program)) => {
+                    owp_discovery::fetch_worlds_from_rpc(rpc, program, proxy.as_ref()).await?
+                }
+                None => Vec::new(),
+            };
+            for location in &directories {
+                for e in directory::fetch_directory(location, &trusted_keys, proxy.as_ref()).await?
+                {
+                    if !worlds.iter().any(|w| w.world_id == e.world_id) {
+                        worlds.push(e);
+                    }
+                }
+            }
+
+            let results =
+                probe::probe_all(&worlds, Duration::from_millis(timeout_ms), proxy.as_ref()).await;
+            probe::apply_rtts(&mut worlds, &results);
+            match sort {
+                DiscoverSort::Latency => probe::sort_by_latency(&mut worlds, group_by_region),
+                DiscoverSort::Stake => {
+                    owp_discovery::sort_by_stake(&mut worlds);
+                    if group_by_region {
+                        worlds.sort_by_key(|w| (w.region.is_none(), w.region.clone()))
+                    }
+                }
+                DiscoverSort::None if group_by_region => {
+                    worlds.sort_by_key(|w| (w.region.is_none(), w.region.clone()))
+                }
+                DiscoverSort::None => {}
+            }
+            if json {
+                println!("{}", serde_json::to_string_pretty(&worlds)?);
+            } else {
+                print_worlds(&worlds);
+            }
+            return Ok(());
+        }
+        Some(Command::Favorites { action }) => return run_favorites(action, &data_dir),
+        None => {}
+    }
+
+    let identity = Identity {
+        accept_rules: cli.accept_rules,
+        ..identity(
+            cli.wallet_pubkey.clone(),
+            cli.wallet_keypair.as_deref(),
+            cli.profile_id.clone(),
+            cli.pay_entry_fee.then(|| cli.solana_rpc_url.clone()),
+        )?
+    };
+    let mut world_pubkey = cli.world_pubkey.clone();
     let (addr, world_id) = if let Some(connect) = cli.connect {
+        let chain = cli
+            .solana_rpc_url
+            .as_deref()
+            .zip(cli.registry_program_id.as_deref());
+        let connect = alias::resolve_connect(&connect, chain, proxy.as_ref()).await?;
+        if world_pubkey.is_none() {
+            world_pubkey = connect_string_pubkey(&connect)?;
+        }
         parse_connect_string(&connect)?
     } else {
         let addr = cli.addr.context("missing --addr or --connect")?;
@@ -44,38 +325,382 @@ async fn main() -> Result<()> {
             Uuid::parse_str(&world_id).context("invalid --world-id")?,
         )
     };
+    if let Some((rpc_url, program_id)) = cli
+        .solana_rpc_url
+        .as_deref()
+        .zip(cli.registry_program_id.as_deref())
+    {
+        world_pubkey =
+            onchain_authority(rpc_url, program_id, world_id, world_pubkey, proxy.as_ref()).await?;
+    }
 
-    let addr: SocketAddr = addr.parse().context("invalid addr")?;
-    let mut stream = TcpStream::connect(addr).await.context("connect")?;
+    let policy = RetryPolicy {
+        initial_backoff: Duration::from_millis(cli.retry_initial_ms),
+        max_backoff: Duration::from_millis(cli.retry_max_ms),
+        max_attempts: cli.retry_max_attempts,
+    };
+    let mut session = Session::connect(
+        &addr,
+        world_id,
+        policy,
+        identity,
+        transport(world_pubkey, cli.noise, cli.tls)?,
+        proxy,
+        cli.party_token.clone(),
+    )
+    .await?;
+    if cli.net_report_secs > 0 {
+        session.set_report_interval(Some(Duration::from_secs(cli.net_report_secs)));
+    }
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&Message::Welcome(session.welcome().clone()))?
+    );
+    if let Err(e) = favorites::mark_joined(&favorites_path(&data_dir), world_id) {
+        tracing::warn!("updating favorites failed: {e:#}");
+    }
+
+    if let Some(chunk) = cli.chunk {
+        request_chunk(&mut session, parse_chunk_coord(&chunk)?, cli.since).await?;
+    }
+    if let Some(secs) = cli.keepalive {
+        let every = Duration::from_secs(secs.max(1));
+        tokio::select! {
+            r = keepalive(&mut session, every) => r?,
+            _ = tokio::signal::ctrl_c() => {}
+        }
+    }
+    if cli.interactive {
+        for line in std::io::stdin().lines() {
+            let line = line.context("read stdin")?;
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("chunk") => {
+                    let Some(coord) = words.next() else {
+                        eprintln!("usage: chunk <x>,<z> [since]");
+                        continue;
+                    };
+                    let since = words.next().and_then(|v| v.parse().ok()).unwrap_or(0);
+                    match parse_chunk_coord(coord) {
+                        Ok(coord) => request_chunk(&mut session, coord, since).await?,
+                        Err(e) => eprintln!("{e:#}"),
+                    }
+                }
+                Some("pos") => match words.next().map(parse_position) {
+                    Some(Ok(position)) => {
+                        session
+                            .send(Message::PlayerPosition(PlayerPosition { position }))
+                            .await?
+                    }
+                    Some(Err(e)) => eprintln!("{e:#}"),
+                    None => eprintln!("usage: pos <x>,<y>,<z>"),
+                },
+                Some("path") => {
+                    let (Some(from), Some(to)) = (words.next(), words.next()) else {
+                        eprintln!("usage: path <x>,<y>,<z> <x>,<y>,<z>");
+                        continue;
+                    };
+                    match parse_position(from).and_then(|f| Ok((f, parse_position(to)?))) {
+                        Ok((from, to)) => {
+                            let reply = session
+                                .request(Message::PathQuery(PathQuery {
+                                    request_id: Uuid::new_v4(),
+                                    from,
+                                    to,
+                                }))
+                                .await?;
+                            println!("{}", serde_json::to_string_pretty(&reply)?);
+                        }
+                        Err(e) => eprintln!("{e:#}"),
+                    }
+                }
+                Some("emote") => {
+                    let Some(id) = words.next() else {
+                        eprintln!("usage: emote <id> [ms]");
+                        continue;
+                    };
+                    let duration_ms = words.next().and_then(|v| v.parse().ok()).unwrap_or(3000);
+                    session
+                        .send(Message::Emote(Emote {
+                            id: id.to_string(),
+                            duration_ms,
+                            player_id: None,
+                        }))
+                        .await?;
+                }
+                Some(cmd @ ("say" | "near" | "party" | "whisper")) => {
+                    let channel = match cmd {
+                        "say" => ChatChannel::World,
+                        "near" => ChatChannel::Proximity,
+                        "party" => ChatChannel::Party,
+                        _ => match words.next().map(Uuid::parse_str) {
+                            Some(Ok(to)) => ChatChannel::Whisper { to },
+                            _ => {
+                                eprintln!("usage: whisper <player_id> <text>");
+                                continue;
+                            }
+                        },
+                    };
+                    let text = words.collect::<Vec<_>>().join(" ");
+                    session
+                        .send(Message::ChatSend(ChatSend { channel, text }))
+                        .await?;
+                }
+                Some(cmd @ ("party-create" | "party-invite" | "party-join" | "party-leave")) => {
+                    let request_id = Uuid::new_v4();
+                    let id = words.next().map(Uuid::parse_str);
+                    let req = match (cmd, id) {
+                        ("party-create", _) => Message::PartyCreate(PartyCreate { request_id }),
+                        ("party-leave", _) => Message::PartyLeave(PartyLeave { request_id }),
+                        ("party-invite", Some(Ok(player_id))) => {
+                            Message::PartyInvite(PartyInvite {
+                                request_id,
+                                player_id,
+                            })
+                        }
+                        ("party-join", Some(Ok(party_id))) => Message::PartyJoin(PartyJoin {
+                            request_id,
+                            party_id,
+                        }),
+                        _ => {
+                            eprintln!("usage: {cmd} <id>");
+                            continue;
+                        }
+                    };
+                    let reply = session.request(req).await?;
+                    if let Message::PartyResult(PartyResult {
+                        token: Some(token), ..
+                    }) = &reply
+                    {
+                        session.set_party_token(token.clone());
+                    }
+                    println!("{}", serde_json::to_string(&reply)?);
+                }
+                Some("party-travel") => {
+                    let Some(connect) = words.next() else {
+                        eprintln!("usage: party-travel <connect>");
+                        continue;
+                    };
+                    session
+                        .send(Message::PartyTravel(PartyTravel {
+                            connect: connect.to_string(),
+                            from: None,
+                        }))
+                        .await?;
+                }
+                Some("listen") => {
+                    let secs = words.next().and_then(|v| v.parse().ok()).unwrap_or(5);
+                    for m in session.listen(Duration::from_secs(secs)).await? {
+                        println!("{}", serde_json::to_string(&m)?);
+                    }
+                    continue;
+                }
+                Some("quit") | Some("exit") => break,
+                Some(other) => {
+                    eprintln!("unknown command {other:?} (try `chunk <x>,<z>` or `quit`)")
+                }
+                None => {}
+            }
+            for m in session.take_events() {
+                println!("{}", serde_json::to_string(&m)?);
+            }
+        }
+    }
+    session.close().await
+}
 
-    let request_id = Uuid::new_v4();
-    let hello = Message::Hello(Hello {
-        protocol_version: OWP_PROTOCOL_VERSION.to_string(),
-        request_id,
-        world_id: Some(world_id),
-        client_name: Some("owp-client-cli".to_string()),
-    });
+/// Ping every `every` until the session fails, printing round trips and pushed messages.
+async fn keepalive(session: &mut Session, every: Duration) -> Result<()> {
+    loop {
+        let rtt = session.ping().await?;
+        println!("pong in {}ms", rtt.as_millis());
+        for m in session.listen(every).await? {
+            println!("{}", serde_json::to_string(&m)?);
+        }
+        session.maybe_send_report().await?;
+    }
+}
 
-    wire::write_message(&mut stream, &hello).await?;
-    let msg = wire::read_message(&mut stream).await?;
-    println!("{}", serde_json::to_string_pretty(&msg)?);
-    Ok(())
+async fn request_chunk(session: &mut Session, chunk: ChunkCoord, since: u64) -> Result<()> {
+    let reply = session
+        .request(Message::ChunkDeltaRequest(ChunkDeltaRequest {
+            request_id: Uuid::new_v4(),
+            chunk,
+            since_version: since,
+        }))
+        .await?;
+    println!("{}", serde_json::to_string_pretty(&reply)?);
+    // The journal no longer covered what we had, so updates were missed.
+    if since > 0 && matches!(&reply, Message::ChunkDelta(d) if d.full) {
+        session.note_update_gap();
+    }
+    session.maybe_send_report().await
 }
 
-fn parse_connect_string(connect: &str) -> Result<(String, Uuid)> {
-    let url = Url::parse(connect).context("invalid connect string url")?;
-    if url.scheme() != "owp" {
-        anyhow::bail!("invalid scheme (expected owp://)");
+fn print_worlds(worlds: &[WorldDirectoryEntry]) {
+    for w in worlds {
+        let rtt = w
+            .rtt_ms
+            .map(|ms| format!("{ms}ms"))
+            .unwrap_or_else(|| "unreachable".to_string());
+        let region = w.region.as_deref().unwrap_or("-");
+        let trust = w.trust.map(|t| format!("  [{t:?}]")).unwrap_or_default();
+        let players = match (w.player_count, w.max_players) {
+            (Some(n), Some(max)) => format!("{n}/{max}"),
+            (Some(n), None) => n.to_string(),
+            (None, _) => "-".to_string(),
+        };
+        println!(
+            "{rtt:>11}  {region:<12} {players:>7}  {}  owp://{}:{}?world={}{trust}",
+            w.name, w.endpoint, w.port, w.world_id
+        );
     }
-    let host = url.host_str().context("missing host")?;
-    let port = url.port().context("missing port")?;
+}
 
-    let mut world_id: Option<Uuid> = None;
-    for (k, v) in url.query_pairs() {
-        if k == "world" {
-            world_id = Some(Uuid::parse_str(&v).context("invalid world query param")?);
+fn identity(
+    mut wallet_pubkey: Option<String>,
+    wallet_keypair: Option<&Path>,
+    profile_id: Option<String>,
+    pay_rpc_url: Option<Option<String>>,
+) -> Result<Identity> {
+    let wallet_key = match wallet_keypair {
+        Some(path) => {
+            let json = std::fs::read_to_string(path).with_context(|| format!("read {path:?}"))?;
+            let bytes: Vec<u8> = serde_json::from_str(&json)
+                .with_context(|| format!("{path:?} is not a keypair file"))?;
+            let bytes: [u8; 64] = bytes
+                .try_into()
+                .ok()
+                .with_context(|| format!("{path:?} does not hold 64 bytes"))?;
+            let key = SigningKey::from_keypair_bytes(&bytes)
+                .with_context(|| format!("{path:?} is not a valid keypair"))?;
+            let pubkey = bs58::encode(key.verifying_key().as_bytes()).into_string();
+            if wallet_pubkey.as_ref().is_some_and(|w| *w != pubkey) {
+                anyhow::bail!("--wallet-keypair is for {pubkey}, not --wallet-pubkey");
+            }
+            wallet_pubkey = Some(pubkey);
+            Some(key)
         }
+        None => None,
+    };
+    let pay_rpc_url = match pay_rpc_url {
+        Some(_) if wallet_key.is_none() => anyhow::bail!("--pay-entry-fee needs --wallet-keypair"),
+        Some(None) => anyhow::bail!("--pay-entry-fee needs --solana-rpc-url"),
+        Some(url) => url,
+        None => None,
+    };
+    Ok(Identity {
+        wallet_pubkey,
+        wallet_key,
+        profile_id,
+        pay_rpc_url,
+        accept_rules: false,
+    })
+}
+
+/// The world authority to hold the server to: the registry's `world_pubkey` for the world, which
+/// a --world-pubkey or connect string `pubkey=` must agree with. An unlisted world keeps `given`.
+async fn onchain_authority(
+    rpc_url: &str,
+    program_id: &str,
+    world_id: Uuid,
+    given: Option<String>,
+    proxy: Option<&Proxy>,
+) -> Result<Option<String>> {
+    match alias::lookup_world(rpc_url, program_id, world_id, proxy).await {
+        Ok(listing) => match (listing.world_pubkey, given) {
+            (Some(onchain), Some(given)) if onchain != given => anyhow::bail!(
+                "the registry lists {onchain} as the authority of world {world_id}, not {given}"
+            ),
+            (Some(onchain), _) => Ok(Some(onchain)),
+            (None, given) => Ok(given),
+        },
+        Err(owp_discovery::Error::NotFound(_)) => {
+            tracing::warn!("world {world_id} is not in the registry; can't check its authority");
+            Ok(given)
+        }
+        Err(e) => Err(e).context("look up the world in the registry"),
     }
-    let world_id = world_id.context("missing world query param")?;
-    Ok((format!("{host}:{port}"), world_id))
+}
+
+fn transport(world_pubkey: Option<String>, noise: bool, tls: bool) -> Result<Transport> {
+    if let Some(pubkey) = &world_pubkey {
+        decode_world_pubkey(pubkey).context("invalid --world-pubkey")?;
+    }
+    Ok(match world_pubkey {
+        _ if tls => Transport::Tls(world_pubkey),
+        Some(pubkey) => Transport::Authority(pubkey),
+        None if noise => Transport::Noise,
+        None => Transport::Plain,
+    })
+}
+
+fn parse_position(s: &str) -> Result<[f32; 3]> {
+    let mut out = [0.0; 3];
+    let mut parts = s.split(',');
+    for v in out.iter_mut() {
+        let p = parts.next().context("position must be <x>,<y>,<z>")?;
+        *v = p.trim().parse().context("invalid position")?;
+    }
+    Ok(out)
+}
+
+fn parse_chunk_coord(s: &str) -> Result<ChunkCoord> {
+    let (x, z) = s.split_once(',').context("chunk must be <x>,<z>")?;
+    Ok(ChunkCoord {
+        x: x.trim().parse().context("invalid chunk x")?,
+        z: z.trim().parse().context("invalid chunk z")?,
+    })
+}
+
+fn default_data_dir() -> Result<PathBuf> {
+    let user_dirs = directories::UserDirs::new().context("resolve user dirs")?;
+    Ok(user_dirs.home_dir().join(".owp"))
+}
+
+fn run_favorites(action: FavoritesAction, data_dir: &Path) -> Result<()> {
+    let path = favorites_path(data_dir);
+    match action {
+        FavoritesAction::Add {
+            connect,
+            name,
+            note,
+        } => {
+            let (world_id, _) = favorites::add(&path, &connect, name, note)?;
+            println!("added {world_id}");
+        }
+        FavoritesAction::Remove { world } => {
+            let world_id = match Uuid::parse_str(&world) {
+                Ok(id) => id,
+                Err(_) => parse_connect_string(&world)?.1,
+            };
+            if !favorites::remove(&path, world_id)? {
+                anyhow::bail!("{world_id} is not a favorite");
+            }
+            println!("removed {world_id}");
+        }
+        FavoritesAction::List { json } => {
+            let favorites = favorites::load(&path)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&favorites)?);
+                return Ok(());
+            }
+            for (world_id, f) in &favorites.worlds {
+                let joined = f
+                    .last_joined_at
+                    .map(|t| t.date().to_string())
+                    .unwrap_or_else(|| "never".to_string());
+                println!(
+                    "{world_id}  {}  {}  (last joined: {joined}){}",
+                    f.name.as_deref().unwrap_or("-"),
+                    f.connect,
+                    f.note
+                        .as_deref()
+                        .map(|n| format!("  # {n}"))
+                        .unwrap_or_default()
+                );
+            }
+        }
+    }
+    Ok(())
 }
@@ -0,0 +1,763 @@
+use anyhow::{Context, Result};
+use ed25519_dalek::SigningKey;
+use owp_discovery::attestation::{self, AttestationError, Expected};
+use owp_discovery::endpoint;
+use owp_discovery::payment;
+use owp_discovery::proxy::{self, Proxy};
+use owp_discovery::tls;
+use owp_discovery::{decode_world_pubkey, wallet_auth};
+use owp_protocol::noise::server_static_public;
+use owp_protocol::session::{Event, ProtocolError, ProtocolStateMachine};
+use owp_protocol::{
+    wire::WireError, Compression, Disconnect, DisconnectReason, ErrorMessage, FrameProtection,
+    Handoff, Hello, Message, NetReport, PaymentSent, Ping, RulesAck, Welcome, WireFormat,
+    WorldMoved, OWP_PROTOCOL_VERSION,
+};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Exponential backoff between reconnect attempts.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Give up after this many failed attempts in a row (0 = never).
+    pub max_attempts: u32,
+}
+
+impl RetryPolicy {
+    /// Wait before attempt `attempt` (1-based): initial, 2x, 4x, ... capped at `max_backoff`.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+/// Round trips and gaps collected for the next `NetReport`.
+struct NetStats {
+    since: Instant,
+    rtt_sum: u64,
+    rtt_count: u32,
+    jitter_sum: u64,
+    last_rtt: Option<u32>,
+    gaps: u32,
+}
+
+impl NetStats {
+    fn new() -> Self {
+        Self {
+            since: Instant::now(),
+            rtt_sum: 0,
+            rtt_count: 0,
+            jitter_sum: 0,
+            last_rtt: None,
+            gaps: 0,
+        }
+    }
+
+    fn record_rtt(&mut self, rtt: Duration) {
+        let ms = rtt.as_millis().min(u32::MAX as u128) as u32;
+        if let Some(prev) = self.last_rtt {
+            self.jitter_sum += prev.abs_diff(ms) as u64;
+        }
+        self.last_rtt = Some(ms);
+        self.rtt_sum += ms as u64;
+        self.rtt_count += 1;
+    }
+
+    /// Build a report and start a new interval.
+    fn take_report(&mut self) -> NetReport {
+        let report = NetReport {
+            request_id: Uuid::new_v4(),
+            interval_ms: self.since.elapsed().as_millis().min(u32::MAX as u128) as u32,
+            rtt_ms: (self.rtt_count > 0).then(|| (self.rtt_sum / self.rtt_count as u64) as u32),
+            jitter_ms: (self.rtt_count > 1)
+                .then(|| (self.jitter_sum / (self.rtt_count - 1) as u64) as u32),
+            update_gaps: self.gaps,
+        };
+        // Keep the last round trip so jitter carries across intervals.
+        let last_rtt = self.last_rtt;
+        *self = Self::new();
+        self.last_rtt = last_rtt;
+        report
+    }
+}
+
+/// Who the player goes by, sent in every `Hello` so friends can find it.
+#[derive(Debug, Clone, Default)]
+pub struct Identity {
+    pub wallet_pubkey: Option<String>,
+    /// Signs a fresh `wallet_proof` on every connect, so the server accepts `wallet_pubkey` as
+    /// ours rather than self-declared.
+    pub wallet_key: Option<SigningKey>,
+    pub profile_id: Option<String>,
+    /// Solana RPC to pay entry fees through with `wallet_key`; without it, a world that charges
+    /// one ends the handshake.
+    pub pay_rpc_url: Option<String>,
+    /// Acknowledge a world's rules when asked; without it, a world with rules ends the
+    /// handshake.
+    pub accept_rules: bool,
+}
+
+/// How the connection is secured.
+#[derive(Debug, Clone, Default)]
+pub enum Transport {
+    #[default]
+    Plain,
+    /// Noise, encrypted but accepting whichever server answers.
+    Noise,
+    /// Noise to the holder of this world authority key (base58 `world_pubkey`), which must also
+    /// attest for the endpoint dialed if the server attests at all.
+    Authority(String),
+    /// TLS, pinned to this world authority key (base58 `world_pubkey`) if given: then only a
+    /// certificate for that key is accepted, otherwise one from a public CA for the host dialed.
+    Tls(Option<String>),
+}
+
+/// What a session speaks over: TCP, or TLS on top of it.
+trait Conn: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Conn for T {}
+
+/// Request/reply pairing for the messages that expect an answer.
+fn request_id(msg: &Message) -> Option<Uuid> {
+    match msg {
+        Message::ChunkDeltaRequest(r) => Some(r.request_id),
+        Message::ChunkDelta(d) => Some(d.request_id),
+        Message::AvatarSubmit(s) => Some(s.request_id),
+        Message::AvatarResult(r) => Some(r.request_id),
+        Message::AvatarSwitch(s) => Some(s.request_id),
+        Message::AvatarAnnounce(a) => a.request_id,
+        Message::AvatarRequest(r) => Some(r.request_id),
+        Message::PartyCreate(r) => Some(r.request_id),
+        Message::PartyInvite(r) => Some(r.request_id),
+        Message::PartyJoin(r) => Some(r.request_id),
+        Message::PartyLeave(r) => Some(r.request_id),
+        Message::PartyResult(r) => Some(r.request_id),
+        Message::PathQuery(q) => Some(q.request_id),
+        Message::PathResult(r) => Some(r.request_id),
+        Message::PeerQuery(q) => Some(q.request_id),
+        Message::PeerList(l) => Some(l.request_id),
+        Message::Error(e) => e.request_id,
+        Message::PaymentRequired(r) => Some(r.request_id),
+        Message::RulesRequired(r) => Some(r.request_id),
+        Message::Ping(p) => Some(p.nonce),
+        Message::Pong(p) => Some(p.nonce),
+        Message::Hello(_)
+        | Message::Welcome(_)
+        | Message::WorldSnapshot(_)
+        | Message::PaymentSent(_)
+        | Message::RulesAck(_)
+        | Message::Disconnect(_)
+        | Message::AssetToken(_)
+        | Message::NetReport(_)
+        | Message::PeerAnnounce(_)
+        | Message::PlayerPosition(_)
+        | Message::Emote(_)
+        | Message::ChatSend(_)
+        | Message::ChatBroadcast(_)
+        | Message::PartyInvited(_)
+        | Message::PartyUpdate(_)
+        | Message::PartyTravel(_)
+        | Message::FriendPresence(_)
+        | Message::Handoff(_)
+        | Message::WorldMoved(_) => None,
+    }
+}
+
+async fn handshake(
+    addr: &str,
+    world_id: Uuid,
+    identity: &Identity,
+    transport: &Transport,
+    proxy: Option<&Proxy>,
+    resume_token: Option<String>,
+    party_token: Option<String>,
+) -> Result<(Box<dyn Conn>, ProtocolStateMachine, Welcome)> {
+    let (host, port) = proxy::split_addr(addr)?;
+    let tcp = endpoint::connect(proxy, host, port).await?;
+    // Whether the connection itself proved the server holds the world authority key.
+    let mut key_proven = matches!(transport, Transport::Authority(_));
+    let mut stream: Box<dyn Conn> = match transport {
+        Transport::Tls(world_pubkey) => {
+            let pinned = world_pubkey
+                .as_deref()
+                .map(decode_world_pubkey)
+                .transpose()?;
+            let stream = tls::connect(tcp, host, pinned).await?;
+            key_proven = pinned.is_some();
+            Box::new(stream)
+        }
+        _ => Box::new(tcp),
+    };
+    let nonce = Uuid::new_v4().simple().to_string();
+    let hello = Hello {
+        protocol_version: OWP_PROTOCOL_VERSION.to_string(),
+        request_id: Uuid::new_v4(),
+        world_id: Some(world_id),
+        client_name: Some("owp-client-cli".to_string()),
+        resume_token,
+        party_token,
+        wallet_pubkey: identity.wallet_pubkey.clone(),
+        wallet_proof: identity.wallet_key.as_ref().map(|key| {
+            wallet_auth::sign(key, world_id, OffsetDateTime::now_utc().unix_timestamp())
+        }),
+        profile_id: identity.profile_id.clone(),
+        frame_protection: vec![FrameProtection::Crc32c],
+        wire_format: vec![WireFormat::Msgpack],
+        compression: Compression::ALL.to_vec(),
+        attestation_nonce: Some(nonce.clone()),
+        capabilities: vec![],
+    };
+    let mut proto = match transport {
+        Transport::Plain | Transport::Tls(_) => ProtocolStateMachine::client(hello)?,
+        Transport::Noise => ProtocolStateMachine::noise_client(hello, None)?,
+        Transport::Authority(pubkey) => {
+            let authority = decode_world_pubkey(pubkey)?;
+            ProtocolStateMachine::noise_client(hello, Some(server_static_public(&authority)?))?
+        }
+    };
+    let welcome = loop {
+        match proto
+            .next_handshake_event(&mut stream)
+            .await
+            .context("handshake")?
+        {
+            Event::Welcome(w) => break w,
+            Event::PaymentRequired(request) => {
+                let (Some(key), Some(rpc_url)) = (&identity.wallet_key, &identity.pay_rpc_url)
+                else {
+                    anyhow::bail!(
+                        "{addr} charges an entry fee ({}); pass --pay-entry-fee to pay it",
+                        request.pay_url
+                    );
+                };
+                info!("paying the entry fee for {addr}: {}", request.pay_url);
+                let signature = payment::pay(rpc_url, proxy, key, &request)
+                    .await
+                    .context("pay entry fee")?;
+                proto.payment_sent(PaymentSent {
+                    reference: request.reference,
+                    signature: Some(signature),
+                })?;
+            }
+            Event::RulesRequired(rules) => {
+                if !identity.accept_rules {
+                    anyhow::bail!(
+                        "{addr} asks players to accept its rules ({}); read them and pass \
+                         --accept-rules",
+                        rules.uri
+                    );
+                }
+                info!("accepting the rules of {addr} ({})", rules.uri);
+                proto.ack_rules(RulesAck {
+                    sha256: rules.sha256,
+                })?;
+            }
+            other => anyhow::bail!("unexpected reply to hello: {other:?}"),
+        }
+    };
+    if welcome.wallet_verified == Some(false) {
+        warn!("{addr} refused the wallet proof; connected without the wallet");
+    }
+    if let Transport::Authority(world_pubkey) | Transport::Tls(Some(world_pubkey)) = transport {
+        let expected = Expected {
+            world_id,
+            world_pubkey,
+            endpoint: attestation::host(addr),
+            nonce: &nonce,
+            now: OffsetDateTime::now_utc().unix_timestamp(),
+        };
+        match attestation::verify(welcome.attestation.as_ref(), &expected) {
+            Ok(()) => {}
+            // The handshake already proved the key; the endpoint just isn't attested.
+            Err(AttestationError::Missing) if key_proven => {
+                warn!("{addr} does not attest its endpoint")
+            }
+            Err(e) => anyhow::bail!("server attestation failed: {e}"),
+        }
+    }
+    Ok((stream, proto, welcome))
+}
+
+fn now_ms() -> i64 {
+    (OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000) as i64
+}
+
+/// The server ends the session after an `Error`; reconnecting won't help.
+fn closed_by_server(e: &ErrorMessage) -> anyhow::Error {
+    anyhow::anyhow!("server closed the session ({:?}): {}", e.code, e.message)
+}
+
+/// Whether an error means the connection dropped (so reconnecting may help).
+fn dropped(e: &ProtocolError) -> bool {
+    matches!(e, ProtocolError::Wire(WireError::Io(_)))
+}
+
+/// A connection to one world that survives drops: it reconnects with backoff, resumes the
+/// session with the token from `Welcome`, and resends requests that were never answered.
+pub struct Session {
+    addr: String,
+    world_id: Uuid,
+    policy: RetryPolicy,
+    stream: Box<dyn Conn>,
+    /// Framing and sequencing for `stream`; replaced on every reconnect.
+    proto: ProtocolStateMachine,
+    welcome: Welcome,
+    /// Sent but unanswered requests, oldest first.
+    unacked: VecDeque<(Uuid, Message)>,
+    stats: NetStats,
+    /// Send a `NetReport` at most this often (`None` = never).
+    report_every: Option<Duration>,
+    /// Bumped on every reconnect so round trips that spanned one aren't sampled.
+    reconnects: u64,
+    /// Messages the server pushed on its own (e.g. other players' emotes), oldest first.
+    events: VecDeque<Message>,
+    identity: Identity,
+    transport: Transport,
+    /// Every (re)connect tunnels through it.
+    proxy: Option<Proxy>,
+    /// Keeps the player in its party when connecting to another world.
+    party_token: Option<String>,
+}
+
+impl Session {
+    pub async fn connect(
+        addr: &str,
+        world_id: Uuid,
+        policy: RetryPolicy,
+        identity: Identity,
+        transport: Transport,
+        proxy: Option<Proxy>,
+        party_token: Option<String>,
+    ) -> Result<Self> {
+        let (stream, proto, welcome) = handshake(
+            addr,
+            world_id,
+            &identity,
+            &transport,
+            proxy.as_ref(),
+            None,
+            party_token.clone(),
+        )
+        .await?;
+        Ok(Self {
+            addr: addr.to_string(),
+            world_id,
+            policy,
+            stream,
+            proto,
+            welcome,
+            unacked: VecDeque::new(),
+            stats: NetStats::new(),
+            report_every: None,
+            reconnects: 0,
+            events: VecDeque::new(),
+            identity,
+            transport,
+            proxy,
+            party_token,
+        })
+    }
+
+    /// Enable periodic `NetReport`s, sent from `maybe_send_report`.
+    pub fn set_report_interval(&mut self, every: Option<Duration>) {
+        self.report_every = every;
+    }
+
+    /// Count an update the client noticed it missed.
+    pub fn note_update_gap(&mut self) {
+        self.stats.gaps += 1;
+    }
+
+    /// Send a `NetReport` if the report interval has passed. Reports are best effort: one that
+    /// can't be sent is dropped, and the connection is re-established for the next request.
+    pub async fn maybe_send_report(&mut self) -> Result<()> {
+        let Some(every) = self.report_every else {
+            return Ok(());
+        };
+        if self.stats.since.elapsed() < every {
+            return Ok(());
+        }
+        let report = Message::NetReport(self.stats.take_report());
+        self.send(report).await
+    }
+
+    /// Send a message that has no reply. If the connection is down it is re-established, but
+    /// the message itself is not resent.
+    pub async fn send(&mut self, msg: Message) -> Result<()> {
+        self.proto.send(&msg)?;
+        if let Err(e) = self.proto.flush(&mut self.stream).await {
+            self.reconnect(&e.to_string()).await?;
+        }
+        Ok(())
+    }
+
+    /// Ping the server and wait for its `Pong`; returns the round trip.
+    pub async fn ping(&mut self) -> Result<Duration> {
+        let sent_at = Instant::now();
+        self.request(Message::Ping(Ping::new(now_ms()))).await?;
+        Ok(sent_at.elapsed())
+    }
+
+    /// Answer a server's keepalive `Ping`.
+    async fn pong(&mut self, ping: &Ping) -> Result<()> {
+        self.send(Message::Pong(ping.pong(now_ms()))).await
+    }
+
+    /// Follow a server's `Disconnect`: come back after a world restart or an idle drop,
+    /// otherwise end the session.
+    async fn said_goodbye(&mut self, bye: Disconnect) -> Result<()> {
+        let why = bye.message.unwrap_or_else(|| format!("{:?}", bye.reason));
+        match bye.reason {
+            DisconnectReason::WorldRestart | DisconnectReason::IdleTimeout => {
+                self.reconnect(&format!("server disconnected: {why}")).await
+            }
+            reason => anyhow::bail!("server closed the session ({reason:?}): {why}"),
+        }
+    }
+
+    /// Say goodbye; the server drops the session instead of holding it for a resume.
+    pub async fn close(mut self) -> Result<()> {
+        let bye = Message::Disconnect(Disconnect {
+            reason: DisconnectReason::ClientQuit,
+            message: None,
+        });
+        self.proto.send(&bye)?;
+        self.proto.flush(&mut self.stream).await?;
+        Ok(())
+    }
+
+    /// Messages pushed by the server since the last call.
+    pub fn take_events(&mut self) -> Vec<Message> {
+        self.events.drain(..).collect()
+    }
+
+    /// Collect pushed messages for `wait`, then return everything received so far.
+    pub async fn listen(&mut self, wait: Duration) -> Result<Vec<Message>> {
+        let deadline = tokio::time::Instant::now() + wait;
+        // `next_event` keeps partial frames buffered, so timing out mid-frame is harmless.
+        while let Ok(read) =
+            tokio::time::timeout_at(deadline, self.proto.next_event(&mut self.stream)).await
+        {
+            match read {
+                Ok(Event::Message(Message::Handoff(h))) => self.follow(h).await?,
+                Ok(Event::Message(Message::WorldMoved(m))) => self.relocate(m).await?,
+                Ok(Event::Message(Message::Error(e))) => return Err(closed_by_server(&e)),
+                Ok(Event::Message(Message::Ping(p))) => self.pong(&p).await?,
+                Ok(Event::Message(Message::Disconnect(d))) => self.said_goodbye(d).await?,
+                Ok(Event::Message(m)) => self.events.push_back(m),
+                Ok(other) => anyhow::bail!("unexpected event on an open session: {other:?}"),
+                Err(e) if dropped(&e) => self.reconnect(&e.to_string()).await?,
+                Err(e) => return Err(e).context("read message"),
+            }
+        }
+        Ok(self.take_events())
+    }
+
+    /// Remember the token from a `PartyResult`; later handshakes present it.
+    pub fn set_party_token(&mut self, token: String) {
+        self.party_token = Some(token);
+    }
+
+    /// The latest `Welcome`, from the initial handshake or the last reconnect.
+    pub fn welcome(&self) -> &Welcome {
+        &self.welcome
+    }
+
+    /// Move to the cluster node named in a `Handoff`, resuming with the token it carries.
+    async fn follow(&mut self, handoff: Handoff) -> Result<()> {
+        info!("handed off from {} to {}", self.addr, handoff.addr);
+        self.addr = handoff.addr;
+        self.welcome.session_token = Some(handoff.session_token);
+        self.reconnect("handed off to another node").await
+    }
+
+    /// Follow the world to the host named in a `WorldMoved`, starting a new session there.
+    async fn relocate(&mut self, moved: WorldMoved) -> Result<()> {
+        let (addr, world_id) = owp_discovery::parse_connect_string(&moved.connect)
+            .context("world_moved connect string")?;
+        if world_id != self.world_id {
+            anyhow::bail!("world_moved points at another world ({world_id})");
+        }
+        info!("world moved from {} to {addr}", self.addr);
+        self.addr = addr;
+        self.welcome.session_token = None;
+        self.reconnect("world moved to another host").await
+    }
+
+    async fn reconnect(&mut self, cause: &str) -> Result<()> {
+        let mut attempt = 0u32;
+        loop {
+            attempt += 1;
+            if self.policy.max_attempts > 0 && attempt > self.policy.max_attempts {
+                anyhow::bail!(
+                    "connection to {} lost ({cause}); gave up after {} attempts",
+                    self.addr,
+                    self.policy.max_attempts
+                );
+            }
+            let delay = self.policy.delay(attempt);
+            warn!(
+                "connection to {} lost ({cause}); reconnect attempt {attempt} in {}ms",
+                self.addr,
+                delay.as_millis()
+            );
+            tokio::time::sleep(delay).await;
+
+            let resume = self.welcome.session_token.clone();
+            let (stream, proto, welcome) = match handshake(
+                &self.addr,
+                self.world_id,
+                &self.identity,
+                &self.transport,
+                self.proxy.as_ref(),
+                resume,
+                self.party_token.clone(),
+            )
+            .await
+            {
+                Ok(v) => v,
+                Err(e) => {
+                    warn!("reconnect attempt {attempt} failed: {e:#}");
+                    continue;
+                }
+            };
+            if welcome.resumed {
+                info!("reconnected to {}; session resumed", self.addr);
+            } else {
+                info!("reconnected to {}; started a new session", self.addr);
+            }
+            self.stream = stream;
+            self.proto = proto;
+            self.welcome = welcome;
+            self.reconnects += 1;
+            if let Err(e) = self.replay().await {
+                warn!("replay after reconnect failed: {e:#}");
+                continue;
+            }
+            return Ok(());
+        }
+    }
+
+    async fn replay(&mut self) -> Result<(), ProtocolError> {
+        if !self.unacked.is_empty() {
+            info!("replaying {} unacknowledged request(s)", self.unacked.len());
+        }
+        for (_, msg) in &self.unacked {
+            self.proto.send(msg)?;
+        }
+        self.proto.flush(&mut self.stream).await?;
+        Ok(())
+    }
+
+    /// Send a request and wait for the reply with the same `request_id`, reconnecting as needed.
+    pub async fn request(&mut self, msg: Message) -> Result<Message> {
+        let id = request_id(&msg).context("message does not expect a reply")?;
+        self.unacked.push_back((id, msg.clone()));
+        let sent_at = Instant::now();
+        let reconnects = self.reconnects;
+        self.proto.send(&msg)?;
+        if let Err(e) = self.proto.flush(&mut self.stream).await {
+            self.reconnect(&e.to_string()).await?;
+        }
+        loop {
+            let reply = match self.proto.next_event(&mut self.stream).await {
+                Ok(Event::Message(Message::Handoff(h))) => {
+                    self.follow(h).await?;
+                    continue;
+                }
+                Ok(Event::Message(Message::WorldMoved(m))) => {
+                    self.relocate(m).await?;
+                    continue;
+                }
+                Ok(Event::Message(Message::Error(e))) => return Err(closed_by_server(&e)),
+                Ok(Event::Message(Message::Ping(p))) => {
+                    self.pong(&p).await?;
+                    continue;
+                }
+                Ok(Event::Message(Message::Disconnect(d))) => {
+                    self.said_goodbye(d).await?;
+                    continue;
+                }
+                Ok(Event::Message(m)) => m,
+                Ok(other) => anyhow::bail!("unexpected event on an open session: {other:?}"),
+                Err(e) if dropped(&e) => {
+                    self.reconnect(&e.to_string()).await?;
+                    continue;
+                }
+                Err(e) => return Err(e).context("read reply"),
+            };
+            let Some(reply_id) = request_id(&reply) else {
+                self.events.push_back(reply);
+                continue;
+            };
+            // A replay can answer a request twice; only the first answer counts.
+            self.unacked.retain(|(pending, _)| *pending != reply_id);
+            if reply_id == id {
+                if self.reconnects == reconnects {
+                    self.stats.record_rtt(sent_at.elapsed());
+                }
+                return Ok(reply);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use owp_protocol::{wire, ChunkCoord, ChunkDelta, ChunkDeltaRequest};
+    use tokio::net::{TcpListener, TcpStream};
+
+    async fn accept_hello(listener: &TcpListener) -> (TcpStream, Hello) {
+        let (mut stream, _) = listener.accept().await.expect("accept");
+        match wire::read_message(&mut stream).await.expect("hello") {
+            Message::Hello(h) => (stream, h),
+            other => panic!("expected hello, got {other:?}"),
+        }
+    }
+
+    fn welcome(world_id: Uuid, resumed: bool) -> Message {
+        Message::Welcome(Welcome {
+            protocol_version: OWP_PROTOCOL_VERSION.to_string(),
+            request_id: Uuid::nil(),
+            world_id,
+            token_mint: None,
+            motd: None,
+            capabilities: vec![],
+            session_token: Some("tok".to_string()),
+            resumed,
+            prefetch: vec![],
+            player_id: None,
+            party: None,
+            player_count: None,
+            frame_protection: None,
+            wire_format: None,
+            compression: None,
+            attestation: None,
+            wallet_verified: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn resumes_and_replays_after_drop() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = listener.local_addr().expect("addr").to_string();
+        let world_id = Uuid::new_v4();
+
+        let server = tokio::spawn(async move {
+            // First connection: take the request, then drop without answering.
+            let (mut s, hello) = accept_hello(&listener).await;
+            assert!(hello.resume_token.is_none());
+            wire::write_message(&mut s, &welcome(world_id, false))
+                .await
+                .expect("welcome");
+            wire::read_message(&mut s).await.expect("request");
+            drop(s);
+
+            let (mut s, hello) = accept_hello(&listener).await;
+            assert_eq!(hello.resume_token.as_deref(), Some("tok"));
+            wire::write_message(&mut s, &welcome(world_id, true))
+                .await
+                .expect("welcome");
+            let Message::ChunkDeltaRequest(req) = wire::read_message(&mut s).await.expect("replay")
+            else {
+                panic!("expected replayed request");
+            };
+            let reply = Message::ChunkDelta(ChunkDelta {
+                request_id: req.request_id,
+                chunk: req.chunk,
+                base_version: 0,
+                version: 1,
+                full: true,
+                objects: vec![],
+                changes: vec![],
+            });
+            wire::write_message(&mut s, &reply).await.expect("reply");
+        });
+
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(50),
+            max_attempts: 5,
+        };
+        let mut session = Session::connect(
+            &addr,
+            world_id,
+            policy,
+            Identity::default(),
+            Transport::Plain,
+            None,
+            None,
+        )
+        .await
+        .expect("connect");
+        let req = ChunkDeltaRequest {
+            request_id: Uuid::new_v4(),
+            chunk: ChunkCoord { x: 0, z: 0 },
+            since_version: 0,
+        };
+        let reply = session
+            .request(Message::ChunkDeltaRequest(req.clone()))
+            .await
+            .expect("request");
+        assert_eq!(request_id(&reply), Some(req.request_id));
+        assert!(session.welcome().resumed);
+        server.await.expect("server");
+    }
+
+    #[tokio::test]
+    async fn answers_server_pings_while_pinging() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = listener.local_addr().expect("addr").to_string();
+        let world_id = Uuid::new_v4();
+
+        let server = tokio::spawn(async move {
+            let (mut s, _) = accept_hello(&listener).await;
+            wire::write_message(&mut s, &welcome(world_id, false))
+                .await
+                .expect("welcome");
+            let Message::Ping(theirs) = wire::read_message(&mut s).await.expect("ping") else {
+                panic!("expected the client's ping");
+            };
+            let ours = Ping::new(7);
+            wire::write_message(&mut s, &Message::Ping(ours.clone()))
+                .await
+                .expect("ping");
+            let Message::Pong(pong) = wire::read_message(&mut s).await.expect("pong") else {
+                panic!("expected a pong");
+            };
+            assert_eq!(pong.nonce, ours.nonce);
+            assert_eq!(pong.ping_sent_at_ms, 7);
+            wire::write_message(&mut s, &Message::Pong(theirs.pong(8)))
+                .await
+                .expect("pong");
+        });
+
+        let mut session = Session::connect(
+            &addr,
+            world_id,
+            RetryPolicy {
+                initial_backoff: Duration::from_millis(10),
+                max_backoff: Duration::from_millis(50),
+                max_attempts: 1,
+            },
+            Identity::default(),
+            Transport::Plain,
+            None,
+            None,
+        )
+        .await
+        .expect("connect");
+        session.ping().await.expect("ping");
+        assert!(session.take_events().is_empty());
+        server.await.expect("server");
+    }
+}
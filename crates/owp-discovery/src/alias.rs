@@ -0,0 +1,264 @@
+//! `owp://name/<alias>` connect strings, resolved through the registry's alias accounts, and
+//! other reads of single registry accounts by their program address.
+
+use base64::Engine;
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use owp_protocol::WorldDirectoryEntry;
+use owp_registry_types::{
+    alias_hash, normalize_alias, AliasEntry, WorldEntry, SEED_ALIAS, SEED_WORLD,
+};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use url::Url;
+use uuid::Uuid;
+
+use crate::error::ResultExt;
+use crate::{proxy::Proxy, rpc_call, Error, ProgramAccountData, Result};
+
+/// Solana's `Pubkey::find_program_address`: the first off-curve address for bumps 255 down
+/// to 0, and its bump.
+pub fn find_program_address(seeds: &[&[u8]], program_id: &[u8; 32]) -> Option<([u8; 32], u8)> {
+    (0..=u8::MAX).rev().find_map(|bump| {
+        let mut hasher = Sha256::new();
+        for seed in seeds {
+            hasher.update(seed);
+        }
+        hasher.update([bump]);
+        hasher.update(program_id);
+        hasher.update(b"ProgramDerivedAddress");
+        let address: [u8; 32] = hasher.finalize().into();
+        let on_curve = CompressedEdwardsY(address).decompress().is_some();
+        (!on_curve).then_some((address, bump))
+    })
+}
+
+/// The normalized alias of an `owp://name/<alias>` connect string, or `None` for any other
+/// connect string.
+pub fn parse_alias_connect(connect: &str) -> Result<Option<String>> {
+    let url = Url::parse(connect).err_as(Error::Invalid, "invalid connect string url")?;
+    if url.scheme() != "owp" || url.host_str() != Some("name") || url.port().is_some() {
+        return Ok(None);
+    }
+    let alias = url.path().trim_start_matches('/');
+    normalize_alias(alias)
+        .map(Some)
+        .err_as(Error::Invalid, format!("invalid alias {alias:?}"))
+}
+
+#[derive(Debug, Deserialize)]
+struct AccountInfo {
+    value: Option<ProgramAccountData>,
+}
+
+async fn account_data(
+    rpc_url: &str,
+    proxy: Option<&Proxy>,
+    address: [u8; 32],
+) -> Result<Option<Vec<u8>>> {
+    let info: AccountInfo = rpc_call(
+        rpc_url,
+        proxy,
+        "getAccountInfo",
+        json!([bs58::encode(address).into_string(), { "encoding": "base64" }]),
+    )
+    .await?;
+    info.value
+        .map(|v| {
+            base64::engine::general_purpose::STANDARD
+                .decode(v.data.0)
+                .err_as(Error::RpcError, "base64 decode")
+        })
+        .transpose()
+}
+
+/// The registry account at the program address for `seeds`.
+async fn registry_account(
+    rpc_url: &str,
+    registry_program_id: &str,
+    seeds: &[&[u8]],
+    proxy: Option<&Proxy>,
+) -> Result<Option<Vec<u8>>> {
+    let program: [u8; 32] = bs58::decode(registry_program_id)
+        .into_vec()
+        .ok()
+        .and_then(|b| b.try_into().ok())
+        .err_as(
+            Error::Invalid,
+            "registry program id must be a base58 32-byte key",
+        )?;
+    let (address, _) = find_program_address(seeds, &program)
+        .err_as(Error::Invalid, "no program address for seeds")?;
+    account_data(rpc_url, proxy, address).await
+}
+
+/// Decode a world entry read for `what`; delisting entries count as not found.
+fn listed_world(data: Vec<u8>, what: &str) -> Result<WorldDirectoryEntry> {
+    let entry = WorldEntry::decode(&data).err_as(Error::Corrupt, what.to_string())?;
+    if entry.is_delisting() {
+        return Err(Error::NotFound(format!("{what} is being delisted")));
+    }
+    WorldDirectoryEntry::try_from(entry).err_as(Error::Corrupt, what.to_string())
+}
+
+/// Look up the world an alias points at. `NotFound` if nobody claimed the alias or its world
+/// is delisted or delisting.
+pub async fn resolve_alias(
+    rpc_url: &str,
+    registry_program_id: &str,
+    alias: &str,
+    proxy: Option<&Proxy>,
+) -> Result<WorldDirectoryEntry> {
+    let alias =
+        normalize_alias(alias).err_as(Error::Invalid, format!("invalid alias {alias:?}"))?;
+    let seeds: [&[u8]; 2] = [SEED_ALIAS, &alias_hash(&alias)];
+    let data = registry_account(rpc_url, registry_program_id, &seeds, proxy)
+        .await?
+        .err_as(Error::NotFound, format!("alias {alias} is not claimed"))?;
+    let claim = AliasEntry::decode(&data).err_as(Error::Corrupt, format!("alias {alias}"))?;
+
+    let seeds: [&[u8]; 2] = [SEED_WORLD, &claim.world_id];
+    let data = registry_account(rpc_url, registry_program_id, &seeds, proxy)
+        .await?
+        .err_as(
+            Error::NotFound,
+            format!("alias {alias} points at a delisted world"),
+        )?;
+    listed_world(data, &format!("world of alias {alias}"))
+}
+
+/// The registry entry of `world_id`, for its `world_pubkey` (the on-chain authority). `NotFound`
+/// if the world isn't listed or is being delisted.
+pub async fn lookup_world(
+    rpc_url: &str,
+    registry_program_id: &str,
+    world_id: Uuid,
+    proxy: Option<&Proxy>,
+) -> Result<WorldDirectoryEntry> {
+    let seeds: [&[u8]; 2] = [SEED_WORLD, world_id.as_bytes()];
+    let data = registry_account(rpc_url, registry_program_id, &seeds, proxy)
+        .await?
+        .err_as(Error::NotFound, format!("world {world_id} is not listed"))?;
+    listed_world(data, &format!("world {world_id}"))
+}
+
+/// `owp://host:port?world=<uuid>`, with `mint=` and `pubkey=` when the listing has them.
+pub fn listing_connect_string(w: &WorldDirectoryEntry) -> String {
+    let host = if w.endpoint.contains(':') {
+        format!("[{}]", w.endpoint)
+    } else {
+        w.endpoint.clone()
+    };
+    let mut connect = format!("owp://{host}:{}?world={}", w.port, w.world_id);
+    if let Some(mint) = &w.token_mint {
+        connect.push_str(&format!("&mint={mint}"));
+    }
+    if let Some(pubkey) = &w.world_pubkey {
+        connect.push_str(&format!("&pubkey={pubkey}"));
+    }
+    connect
+}
+
+/// `connect` itself, or for `owp://name/<alias>` the connect string of the world the alias
+/// points at. `chain` is the `(rpc_url, registry_program_id)` to resolve aliases with.
+pub async fn resolve_connect(
+    connect: &str,
+    chain: Option<(&str, &str)>,
+    proxy: Option<&Proxy>,
+) -> Result<String> {
+    let Some(alias) = parse_alias_connect(connect)? else {
+        return Ok(connect.to_string());
+    };
+    let (rpc_url, program_id) = chain.err_as(
+        Error::Invalid,
+        "resolving an alias needs a Solana RPC url and registry program id",
+    )?;
+    let world = resolve_alias(rpc_url, program_id, &alias, proxy).await?;
+    Ok(listing_connect_string(&world))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alias_connect_strings_and_program_addresses() {
+        assert_eq!(
+            parse_alias_connect("owp://name/My-World").expect("alias"),
+            Some("my-world".to_string())
+        );
+        assert_eq!(
+            parse_alias_connect("owp://name:7777?world=00000000-0000-0000-0000-000000000000")
+                .expect("not an alias"),
+            None
+        );
+        assert!(matches!(
+            parse_alias_connect("owp://name/no--way"),
+            Err(Error::Invalid(_))
+        ));
+
+        let program = [3u8; 32];
+        let (address, bump) =
+            find_program_address(&[SEED_ALIAS, &alias_hash("my-world")], &program).expect("pda");
+        assert!(CompressedEdwardsY(address).decompress().is_none());
+        assert_eq!(
+            find_program_address(&[SEED_ALIAS, &alias_hash("my-world")], &program),
+            Some((address, bump))
+        );
+    }
+
+    /// Answers each `getAccountInfo` with the next of `accounts` (`None` = no such account).
+    async fn rpc_server(accounts: Vec<Option<Vec<u8>>>) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind");
+        let url = format!("http://{}", listener.local_addr().expect("addr"));
+        tokio::spawn(async move {
+            for account in accounts {
+                let (mut conn, _) = listener.accept().await.expect("accept");
+                let mut req = vec![0u8; 64 * 1024];
+                let _ = conn.read(&mut req).await;
+                let value = account.map(|data| {
+                    json!({ "data": [base64::engine::general_purpose::STANDARD.encode(data), "base64"] })
+                });
+                let body = json!({ "jsonrpc": "2.0", "id": 1, "result": { "value": value } });
+                let body = body.to_string();
+                let reply = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                let _ = conn.write_all(reply.as_bytes()).await;
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn worlds_are_looked_up_by_id() {
+        let world_id = Uuid::new_v4();
+        let authority = [9u8; 32];
+        let entry = owp_registry_types::WorldEntryBuilder::new(*world_id.as_bytes(), authority)
+            .endpoint("play.example.com")
+            .game_port(7777)
+            .build()
+            .expect("entry");
+        let mut data = vec![0u8; WorldEntry::LEN_V3];
+        entry.write_to(&mut data).expect("write");
+        let program = bs58::encode([3u8; 32]).into_string();
+
+        let url = rpc_server(vec![Some(data), None]).await;
+        let listing = lookup_world(&url, &program, world_id, None)
+            .await
+            .expect("listed");
+        assert_eq!(listing.world_id, world_id);
+        assert_eq!(
+            listing.world_pubkey,
+            Some(bs58::encode(authority).into_string())
+        );
+        assert!(matches!(
+            lookup_world(&url, &program, world_id, None).await,
+            Err(Error::NotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,158 @@
+//! `welcome.attestation`: the world authority (the key in the registry entry) signing that this
+//! server hosts `world_id` at `endpoint`, bound to the client's `hello.attestation_nonce`. A client
+//! that found the world on-chain checks it against the listing to spot a DNS/IP hijack.
+
+use base64::Engine;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use owp_protocol::ServerAttestation;
+use uuid::Uuid;
+
+/// How far the signing time may be from the client's clock.
+pub const MAX_CLOCK_SKEW_SECS: i64 = 300;
+
+/// Servers don't sign longer nonces.
+pub const MAX_NONCE_LEN: usize = 128;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum AttestationError {
+    #[error("server sent no attestation")]
+    Missing,
+    #[error("attested by {0}, not the world authority")]
+    WrongKey(String),
+    #[error("attested for endpoint {0}")]
+    WrongEndpoint(String),
+    #[error("attestation is {0}s away from our clock")]
+    Stale(i64),
+    #[error("attestation signature is invalid")]
+    BadSignature,
+}
+
+/// The signed bytes: a version line, then world id, endpoint, timestamp and nonce, one per line.
+pub fn signed_bytes(world_id: Uuid, endpoint: &str, timestamp: i64, nonce: &str) -> Vec<u8> {
+    format!("owp-attestation-v1\n{world_id}\n{endpoint}\n{timestamp}\n{nonce}").into_bytes()
+}
+
+pub fn sign(
+    key: &SigningKey,
+    world_id: Uuid,
+    endpoint: &str,
+    timestamp: i64,
+    nonce: &str,
+) -> ServerAttestation {
+    let sig = key.sign(&signed_bytes(world_id, endpoint, timestamp, nonce));
+    ServerAttestation {
+        endpoint: endpoint.to_string(),
+        timestamp,
+        public_key: bs58::encode(key.verifying_key().as_bytes()).into_string(),
+        signature: base64::engine::general_purpose::STANDARD.encode(sig.to_bytes()),
+    }
+}
+
+/// The endpoint of a `host:port` address, to compare with `ServerAttestation.endpoint`.
+pub fn host(addr: &str) -> &str {
+    let host = addr.rsplit_once(':').map_or(addr, |(h, _)| h);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+/// What the client expects: the listing's authority and endpoint, and the nonce it sent.
+pub struct Expected<'a> {
+    pub world_id: Uuid,
+    /// Base58, as in `WorldDirectoryEntry.world_pubkey`.
+    pub world_pubkey: &'a str,
+    pub endpoint: &'a str,
+    pub nonce: &'a str,
+    /// The client's clock, unix seconds.
+    pub now: i64,
+}
+
+pub fn verify(
+    attestation: Option<&ServerAttestation>,
+    expected: &Expected<'_>,
+) -> Result<(), AttestationError> {
+    let a = attestation.ok_or(AttestationError::Missing)?;
+    if a.public_key != expected.world_pubkey {
+        return Err(AttestationError::WrongKey(a.public_key.clone()));
+    }
+    if !a.endpoint.eq_ignore_ascii_case(expected.endpoint) {
+        return Err(AttestationError::WrongEndpoint(a.endpoint.clone()));
+    }
+    let skew = a.timestamp.saturating_sub(expected.now);
+    if skew.saturating_abs() > MAX_CLOCK_SKEW_SECS {
+        return Err(AttestationError::Stale(skew));
+    }
+    let key: [u8; 32] = bs58::decode(&a.public_key)
+        .into_vec()
+        .ok()
+        .and_then(|b| b.try_into().ok())
+        .ok_or(AttestationError::BadSignature)?;
+    let key = VerifyingKey::from_bytes(&key).map_err(|_| AttestationError::BadSignature)?;
+    let sig: [u8; 64] = base64::engine::general_purpose::STANDARD
+        .decode(&a.signature)
+        .ok()
+        .and_then(|b| b.try_into().ok())
+        .ok_or(AttestationError::BadSignature)?;
+    let msg = signed_bytes(expected.world_id, &a.endpoint, a.timestamp, expected.nonce);
+    key.verify(&msg, &Signature::from_bytes(&sig))
+        .map_err(|_| AttestationError::BadSignature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attestation_binds_world_endpoint_time_and_nonce() {
+        let key = SigningKey::from_bytes(&[1u8; 32]);
+        let pubkey = bs58::encode(key.verifying_key().as_bytes()).into_string();
+        let world_id = Uuid::new_v4();
+        let a = sign(&key, world_id, "play.example.com", 1_000, "n1");
+        let expected = Expected {
+            world_id,
+            world_pubkey: &pubkey,
+            endpoint: "Play.Example.com",
+            nonce: "n1",
+            now: 1_100,
+        };
+        assert_eq!(verify(Some(&a), &expected), Ok(()));
+        assert_eq!(host("play.example.com:7777"), "play.example.com");
+        assert_eq!(host("[::1]:7777"), "::1");
+
+        assert_eq!(verify(None, &expected), Err(AttestationError::Missing));
+        let replayed = Expected {
+            nonce: "n2",
+            ..expected
+        };
+        assert_eq!(
+            verify(Some(&a), &replayed),
+            Err(AttestationError::BadSignature)
+        );
+        let hijacked = Expected {
+            endpoint: "evil.example.com",
+            ..expected
+        };
+        assert!(matches!(
+            verify(Some(&a), &hijacked),
+            Err(AttestationError::WrongEndpoint(_))
+        ));
+        let late = Expected {
+            now: 1_000 + MAX_CLOCK_SKEW_SECS + 1,
+            ..expected
+        };
+        assert!(matches!(
+            verify(Some(&a), &late),
+            Err(AttestationError::Stale(_))
+        ));
+        let other = SigningKey::from_bytes(&[2u8; 32]);
+        let forged = sign(&other, world_id, "play.example.com", 1_000, "n1");
+        assert!(matches!(
+            verify(Some(&forged), &expected),
+            Err(AttestationError::WrongKey(_))
+        ));
+        let mut tampered = a.clone();
+        tampered.timestamp += 1;
+        assert_eq!(
+            verify(Some(&tampered), &expected),
+            Err(AttestationError::BadSignature)
+        );
+    }
+}
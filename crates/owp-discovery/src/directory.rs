@@ -0,0 +1,235 @@
+use base64::Engine;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use owp_protocol::{ListingTrust, WorldDirectoryEntry};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::{Error, Result, ResultExt};
+use crate::proxy::{self, Proxy};
+
+/// Bump when the directory file layout changes incompatibly.
+pub const DIRECTORY_SCHEMA_VERSION: u32 = 1;
+
+pub const SIGNATURE_ALGORITHM: &str = "ed25519";
+
+/// A static, hostable list of worlds (`owp-server export-directory`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirectoryFileV1 {
+    pub schema_version: u32,
+    /// RFC 3339 timestamp.
+    pub generated_at: String,
+    /// Where the entries came from: `local` or `chain`.
+    pub source: String,
+    pub worlds: Vec<WorldDirectoryEntry>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<DirectorySignature>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirectorySignature {
+    pub algorithm: String,
+    /// Publisher public key, base58.
+    pub public_key: String,
+    /// Signature over `canonical_bytes`, base64.
+    pub signature: String,
+}
+
+fn write_canonical(v: &Value, out: &mut String) {
+    match v {
+        Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            out.push('{');
+            for (i, k) in keys.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&Value::String((*k).clone()).to_string());
+                out.push(':');
+                write_canonical(&map[*k], out);
+            }
+            out.push('}');
+        }
+        Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(item, out);
+            }
+            out.push(']');
+        }
+        other => out.push_str(&other.to_string()),
+    }
+}
+
+impl DirectoryFileV1 {
+    /// Compact JSON with sorted keys and the `signature` field removed. The same bytes come out
+    /// whether the file was stored as JSON or TOML.
+    pub fn canonical_bytes(&self) -> Result<Vec<u8>> {
+        let mut unsigned = self.clone();
+        unsigned.signature = None;
+        let value =
+            serde_json::to_value(&unsigned).err_as(Error::Invalid, "serialize directory")?;
+        let mut out = String::new();
+        write_canonical(&value, &mut out);
+        Ok(out.into_bytes())
+    }
+
+    pub fn sign(&mut self, key: &SigningKey) -> Result<()> {
+        let sig = key.sign(&self.canonical_bytes()?);
+        self.signature = Some(DirectorySignature {
+            algorithm: SIGNATURE_ALGORITHM.to_string(),
+            public_key: bs58::encode(key.verifying_key().as_bytes()).into_string(),
+            signature: base64::engine::general_purpose::STANDARD.encode(sig.to_bytes()),
+        });
+        Ok(())
+    }
+}
+
+fn decode_public_key(b58: &str) -> Option<VerifyingKey> {
+    let bytes: [u8; 32] = bs58::decode(b58.trim()).into_vec().ok()?.try_into().ok()?;
+    VerifyingKey::from_bytes(&bytes).ok()
+}
+
+impl DirectoryFileV1 {
+    fn signed_by(&self, sig: &DirectorySignature) -> Option<VerifyingKey> {
+        if sig.algorithm != SIGNATURE_ALGORITHM {
+            return None;
+        }
+        let key = decode_public_key(&sig.public_key)?;
+        let raw: [u8; 64] = base64::engine::general_purpose::STANDARD
+            .decode(sig.signature.trim())
+            .ok()?
+            .try_into()
+            .ok()?;
+        let msg = self.canonical_bytes().ok()?;
+        key.verify(&msg, &Signature::from_bytes(&raw)).ok()?;
+        Some(key)
+    }
+
+    /// Check the signature against the publisher keys (base58) we were told to trust.
+    pub fn verify(&self, trusted_keys: &[String]) -> ListingTrust {
+        let Some(sig) = &self.signature else {
+            return ListingTrust::Unsigned;
+        };
+        let Some(signer) = self.signed_by(sig) else {
+            return ListingTrust::InvalidSignature;
+        };
+        if trusted_keys
+            .iter()
+            .any(|k| decode_public_key(k) == Some(signer))
+        {
+            ListingTrust::Verified
+        } else {
+            ListingTrust::UnknownPublisher
+        }
+    }
+}
+
+/// Parse a directory file stored as JSON or TOML.
+pub fn parse_directory(text: &str) -> Result<DirectoryFileV1> {
+    let file: DirectoryFileV1 = if text.trim_start().starts_with('{') {
+        serde_json::from_str(text).err_as(Error::Corrupt, "parse directory json")?
+    } else {
+        toml::from_str(text).err_as(Error::Corrupt, "parse directory toml")?
+    };
+    if file.schema_version > DIRECTORY_SCHEMA_VERSION {
+        return Err(Error::Corrupt(format!(
+            "directory schema_version {} is newer than supported ({DIRECTORY_SCHEMA_VERSION})",
+            file.schema_version
+        )));
+    }
+    Ok(file)
+}
+
+/// Load a directory file from an http(s) URL or a local path and return its entries, each
+/// annotated with the file's trust level.
+pub async fn fetch_directory(
+    location: &str,
+    trusted_keys: &[String],
+    proxy: Option<&Proxy>,
+) -> Result<Vec<WorldDirectoryEntry>> {
+    let text = if location.starts_with("http://") || location.starts_with("https://") {
+        proxy::http_client(proxy)?
+            .get(location)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| Error::http(format!("fetch {location}"), e, Error::ProviderUnavailable))?
+            .text()
+            .await
+            .map_err(|e| Error::http(format!("read {location}"), e, Error::ProviderUnavailable))?
+    } else {
+        std::fs::read_to_string(location).map_err(|e| Error::io(format!("read {location:?}"), e))?
+    };
+    let file = parse_directory(&text)?;
+    let trust = file.verify(trusted_keys);
+    Ok(file
+        .worlds
+        .into_iter()
+        .map(|mut e| {
+            e.trust = Some(trust);
+            e
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn sample() -> DirectoryFileV1 {
+        DirectoryFileV1 {
+            schema_version: DIRECTORY_SCHEMA_VERSION,
+            generated_at: "2025-01-01T00:00:00Z".to_string(),
+            source: "local".to_string(),
+            worlds: vec![WorldDirectoryEntry {
+                world_id: Uuid::nil(),
+                name: "Test".to_string(),
+                endpoint: "play.example.com".to_string(),
+                port: 7777,
+                token_mint: None,
+                dbc_pool: None,
+                world_pubkey: None,
+                last_seen: None,
+                trust: None,
+                region: None,
+                rtt_ms: None,
+                tags: vec![],
+                icon_sha256: None,
+                player_count: None,
+                max_players: None,
+                stake_lamports: None,
+                rules: None,
+            }],
+            signature: None,
+        }
+    }
+
+    #[test]
+    fn signature_survives_toml_roundtrip_and_detects_tampering() {
+        let key = SigningKey::from_bytes(&[7u8; 32]);
+        let publisher = bs58::encode(key.verifying_key().as_bytes()).into_string();
+        let mut file = sample();
+        assert_eq!(file.verify(&[]), ListingTrust::Unsigned);
+        file.sign(&key).expect("sign");
+
+        let as_toml = toml::to_string(&file).expect("toml");
+        let parsed = parse_directory(&as_toml).expect("parse");
+        assert_eq!(
+            parsed.verify(std::slice::from_ref(&publisher)),
+            ListingTrust::Verified
+        );
+        assert_eq!(parsed.verify(&[]), ListingTrust::UnknownPublisher);
+
+        let mut tampered = parsed;
+        tampered.worlds[0].endpoint = "evil.example.com".to_string();
+        assert_eq!(
+            tampered.verify(&[publisher]),
+            ListingTrust::InvalidSignature
+        );
+    }
+}
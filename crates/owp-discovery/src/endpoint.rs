@@ -0,0 +1,273 @@
+//! World endpoints: the host part of a listing or connect string. Publishers normalize it with
+//! `normalize`; clients dial it with `connect`, which follows `_owp._tcp` SRV records when the host
+//! has any, so a world can move port or machine behind a stable name.
+
+use owp_registry_types::is_valid_endpoint;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::net::{TcpStream, UdpSocket};
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::error::{Error, Result, ResultExt};
+use crate::proxy::{self, Proxy};
+
+const SRV_PREFIX: &str = "_owp._tcp.";
+
+const DNS_TIMEOUT: Duration = Duration::from_secs(2);
+
+const TYPE_SRV: u16 = 33;
+
+/// Trim, lower-case and drop a trailing dot and IPv6 brackets, then check the result is a bare
+/// host (no scheme, port or path).
+pub fn normalize(endpoint: &str) -> Result<String> {
+    let mut host = endpoint.trim().trim_end_matches('.').to_ascii_lowercase();
+    if host.starts_with('[') && host.ends_with(']') {
+        host = host[1..host.len() - 1].to_string();
+    }
+    if host.contains("://") || host.contains('/') {
+        return Err(Error::Invalid(format!(
+            "endpoint {endpoint:?} must be a host name or address, not a URL"
+        )));
+    }
+    if host.parse::<IpAddr>().is_err() && host.contains(':') {
+        return Err(Error::Invalid(format!(
+            "endpoint {endpoint:?} must not include a port; the port is listed separately"
+        )));
+    }
+    if !is_valid_endpoint(&host) {
+        return Err(Error::Invalid(format!(
+            "endpoint {endpoint:?} is not a valid host name or IP address"
+        )));
+    }
+    Ok(host)
+}
+
+/// One `_owp._tcp` SRV record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SrvTarget {
+    pub priority: u16,
+    pub weight: u16,
+    pub host: String,
+    pub port: u16,
+}
+
+/// SRV targets for `host`, best first (lowest priority, then highest weight). Empty when the
+/// name has none, or when no resolver is configured (only `/etc/resolv.conf` is read).
+pub async fn lookup_srv(host: &str) -> Result<Vec<SrvTarget>> {
+    let Some(server) = nameserver() else {
+        return Ok(vec![]);
+    };
+    let name = format!("{SRV_PREFIX}{}", host.trim_end_matches('.'));
+    let nonce = Uuid::new_v4();
+    let id = u16::from_be_bytes([nonce.as_bytes()[0], nonce.as_bytes()[1]]);
+    let query = srv_query(id, &name)?;
+    let bind: SocketAddr = if server.is_ipv4() {
+        (std::net::Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (std::net::Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = UdpSocket::bind(bind)
+        .await
+        .err_as(Error::ProviderUnavailable, "dns socket")?;
+    socket
+        .connect(server)
+        .await
+        .err_as(Error::ProviderUnavailable, "dns connect")?;
+    socket
+        .send(&query)
+        .await
+        .err_as(Error::ProviderUnavailable, "dns send")?;
+    let mut buf = vec![0u8; 4096];
+    loop {
+        let n = tokio::time::timeout(DNS_TIMEOUT, socket.recv(&mut buf))
+            .await
+            .err_as(
+                Error::ProviderUnavailable,
+                format!("no SRV answer for {name} from {server}"),
+            )?
+            .err_as(Error::ProviderUnavailable, "dns recv")?;
+        // Ignore stray datagrams that don't answer our query.
+        if n >= 2 && buf[..2] == id.to_be_bytes() {
+            let mut targets = parse_srv_response(&buf[..n])?;
+            targets.sort_by_key(|t| (t.priority, std::cmp::Reverse(t.weight)));
+            return Ok(targets);
+        }
+    }
+}
+
+/// Connect to a world listed at `host:port`. Names with SRV records are dialed at their targets
+/// in order, falling back to `host:port`. Through a proxy, the proxy resolves the name itself and
+/// no SRV lookup is made, so DNS doesn't leak around it.
+pub async fn connect(proxy: Option<&Proxy>, host: &str, port: u16) -> Result<TcpStream> {
+    if proxy.is_none() && host.parse::<IpAddr>().is_err() {
+        match lookup_srv(host).await {
+            Ok(targets) => {
+                for t in &targets {
+                    match proxy::connect(None, &t.host, t.port).await {
+                        Ok(stream) => return Ok(stream),
+                        Err(e) => debug!("SRV target {}:{} failed: {e:#}", t.host, t.port),
+                    }
+                }
+            }
+            Err(e) => debug!("SRV lookup for {host} failed: {e:#}"),
+        }
+    }
+    proxy::connect(proxy, host, port).await
+}
+
+fn nameserver() -> Option<SocketAddr> {
+    let conf = std::fs::read_to_string("/etc/resolv.conf").ok()?;
+    conf.lines().find_map(|line| {
+        let mut words = line.split_whitespace();
+        (words.next() == Some("nameserver"))
+            .then(|| words.next()?.parse::<IpAddr>().ok())
+            .flatten()
+            .map(|ip| SocketAddr::new(ip, 53))
+    })
+}
+
+fn srv_query(id: u16, name: &str) -> Result<Vec<u8>> {
+    let mut q = Vec::with_capacity(18 + name.len());
+    q.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired; one question.
+    q.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.split('.') {
+        let len = u8::try_from(label.len())
+            .ok()
+            .filter(|l| (1..=63).contains(l))
+            .err_as(Error::Invalid, format!("bad DNS label in {name:?}"))?;
+        q.push(len);
+        q.extend_from_slice(label.as_bytes());
+    }
+    q.push(0);
+    q.extend_from_slice(&TYPE_SRV.to_be_bytes());
+    q.extend_from_slice(&1u16.to_be_bytes());
+    Ok(q)
+}
+
+fn read_u16(msg: &[u8], at: usize) -> Result<u16> {
+    msg.get(at..at + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .err_as(Error::Corrupt, "truncated DNS message")
+}
+
+/// Read the (possibly compressed) name at `at`; returns it and the offset just past it.
+fn read_name(msg: &[u8], mut at: usize) -> Result<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    // Each pointer must go backwards, so this bounds the walk even on hostile input.
+    let mut limit = at;
+    loop {
+        let len = *msg.get(at).err_as(Error::Corrupt, "truncated DNS name")? as usize;
+        match len {
+            0 => {
+                return Ok((labels.join("."), end.unwrap_or(at + 1)));
+            }
+            l if l & 0xc0 == 0xc0 => {
+                let target = (read_u16(msg, at)? & 0x3fff) as usize;
+                if target >= limit {
+                    return Err(Error::Corrupt("DNS name pointer loops".to_string()));
+                }
+                end.get_or_insert(at + 2);
+                limit = target;
+                at = target;
+            }
+            l if l <= 63 => {
+                let label = msg
+                    .get(at + 1..at + 1 + l)
+                    .err_as(Error::Corrupt, "truncated DNS label")?;
+                labels.push(String::from_utf8_lossy(label).to_ascii_lowercase());
+                at += 1 + l;
+            }
+            _ => return Err(Error::Corrupt("bad DNS label length".to_string())),
+        }
+    }
+}
+
+fn parse_srv_response(msg: &[u8]) -> Result<Vec<SrvTarget>> {
+    let flags = read_u16(msg, 2)?;
+    match flags & 0x000f {
+        0 => {}
+        // NXDOMAIN: no records, the plain host still applies.
+        3 => return Ok(vec![]),
+        rcode => {
+            return Err(Error::ProviderUnavailable(format!(
+                "DNS error code {rcode}"
+            )))
+        }
+    }
+    let questions = read_u16(msg, 4)?;
+    let answers = read_u16(msg, 6)?;
+    let mut at = 12;
+    for _ in 0..questions {
+        at = read_name(msg, at)?.1 + 4;
+    }
+    let mut out = Vec::new();
+    for _ in 0..answers {
+        at = read_name(msg, at)?.1;
+        let rtype = read_u16(msg, at)?;
+        let rdlen = read_u16(msg, at + 8)? as usize;
+        let rdata = at + 10;
+        if rtype == TYPE_SRV {
+            let (host, _) = read_name(msg, rdata + 6)?;
+            // "." means the service is explicitly not offered here.
+            if !host.is_empty() {
+                out.push(SrvTarget {
+                    priority: read_u16(msg, rdata)?,
+                    weight: read_u16(msg, rdata + 2)?,
+                    port: read_u16(msg, rdata + 4)?,
+                    host,
+                });
+            }
+        }
+        at = rdata + rdlen;
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_hosts_and_parses_srv_answers() {
+        assert_eq!(
+            normalize(" MyGame.Example.com. ").expect("name"),
+            "mygame.example.com"
+        );
+        assert_eq!(normalize("[::1]").expect("v6"), "::1");
+        assert!(normalize("mygame.example.com:7777").is_err());
+        assert!(normalize("owp://mygame.example.com").is_err());
+
+        let name = "_owp._tcp.mygame.example.com";
+        let mut msg = srv_query(0x1234, name).expect("query");
+        // Turn the query into a response with two answers pointing back at the question name.
+        msg[2] = 0x81;
+        msg[3] = 0x80;
+        msg[7] = 2;
+        for (priority, weight, port, target) in [
+            (20u16, 0u16, 7000u16, &b"\x06backup\xc0\x1d"[..]),
+            (10, 5, 7777, &b"\x05game1\xc0\x1d"[..]),
+        ] {
+            msg.extend_from_slice(&[0xc0, 12]);
+            msg.extend_from_slice(&TYPE_SRV.to_be_bytes());
+            msg.extend_from_slice(&[0, 1, 0, 0, 0, 60]);
+            msg.extend_from_slice(&((6 + target.len()) as u16).to_be_bytes());
+            msg.extend_from_slice(&priority.to_be_bytes());
+            msg.extend_from_slice(&weight.to_be_bytes());
+            msg.extend_from_slice(&port.to_be_bytes());
+            msg.extend_from_slice(target);
+        }
+        let targets = parse_srv_response(&msg).expect("parse");
+        assert_eq!(targets.len(), 2);
+        assert_eq!(targets[1].host, "game1.example.com");
+        assert_eq!((targets[1].priority, targets[1].port), (10, 7777));
+        assert_eq!(targets[0].host, "backup.example.com");
+
+        let mut looping = msg[..12].to_vec();
+        looping[5] = 1;
+        looping.extend_from_slice(&[0xc0, 12]);
+        assert!(parse_srv_response(&looping).is_err());
+    }
+}
@@ -0,0 +1,115 @@
+//! Errors from discovery, grouped by what a caller can do about them rather than by where they
+//! came from: fix the input, give up on a missing world, ask for credentials, retry later, or
+//! repair a damaged file.
+
+use std::fmt::Display;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Malformed input: a connect string, endpoint, proxy URL or key.
+    #[error("{0}")]
+    Invalid(String),
+    /// The directory file, DNS name or HTTP resource doesn't exist.
+    #[error("{0}")]
+    NotFound(String),
+    /// A proxy or directory host refused our credentials.
+    #[error("{0}")]
+    Unauthorized(String),
+    /// A proxy, RPC node, directory host or world couldn't be reached or stopped answering.
+    #[error("{0}")]
+    ProviderUnavailable(String),
+    /// The Solana RPC answered, but with an error or a body we can't read.
+    #[error("{0}")]
+    RpcError(String),
+    /// A favorites or directory file, or a DNS answer, that doesn't parse.
+    #[error("{0}")]
+    Corrupt(String),
+    /// Reading or writing a local file failed.
+    #[error("{context}: {source}")]
+    Io {
+        context: String,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+impl Error {
+    /// A failed read: `Io`, except a missing file is `NotFound`.
+    pub(crate) fn io(context: impl Display, source: std::io::Error) -> Self {
+        if source.kind() == std::io::ErrorKind::NotFound {
+            Self::NotFound(format!("{context}: {source}"))
+        } else {
+            Self::Io {
+                context: context.to_string(),
+                source,
+            }
+        }
+    }
+
+    /// A failed HTTP request: no answer is `ProviderUnavailable`, 404 `NotFound`, 401/403
+    /// `Unauthorized`, 5xx `ProviderUnavailable` and anything else `other`.
+    pub(crate) fn http(
+        context: impl Display,
+        source: reqwest::Error,
+        other: fn(String) -> Self,
+    ) -> Self {
+        let msg = format!("{context}: {source}");
+        match source.status().map(|s| s.as_u16()) {
+            None => Self::ProviderUnavailable(msg),
+            Some(404) => Self::NotFound(msg),
+            Some(401 | 403) => Self::Unauthorized(msg),
+            Some(500..) => Self::ProviderUnavailable(msg),
+            Some(_) => other(msg),
+        }
+    }
+}
+
+/// Wrap a lower-level error as one of the message variants, like `anyhow::Context`.
+pub(crate) trait ResultExt<T> {
+    fn err_as(self, kind: fn(String) -> Error, context: impl Display) -> Result<T>;
+}
+
+impl<T, E: Display> ResultExt<T> for std::result::Result<T, E> {
+    fn err_as(self, kind: fn(String) -> Error, context: impl Display) -> Result<T> {
+        self.map_err(|e| kind(format!("{context}: {e}")))
+    }
+}
+
+impl<T> ResultExt<T> for Option<T> {
+    fn err_as(self, kind: fn(String) -> Error, context: impl Display) -> Result<T> {
+        self.ok_or_else(|| kind(context.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn failures_land_in_their_category() {
+        assert!(matches!(
+            crate::parse_connect_string("http://127.0.0.1:7777"),
+            Err(Error::Invalid(_))
+        ));
+        assert!(matches!(
+            "ftp://proxy".parse::<crate::proxy::Proxy>(),
+            Err(Error::Invalid(_))
+        ));
+
+        let dir = tempfile::tempdir().expect("tempdir");
+        let missing = dir.path().join("directory.json");
+        assert!(matches!(
+            crate::directory::fetch_directory(missing.to_str().expect("utf-8"), &[], None).await,
+            Err(Error::NotFound(_))
+        ));
+
+        let favorites = crate::favorites::favorites_path(dir.path());
+        std::fs::write(&favorites, "{ not json").expect("write");
+        assert!(matches!(
+            crate::favorites::load(&favorites),
+            Err(Error::Corrupt(_))
+        ));
+    }
+}
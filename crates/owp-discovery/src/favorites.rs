@@ -0,0 +1,149 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::error::{Error, Result, ResultExt};
+use crate::parse_connect_string;
+
+/// Serializes read-modify-write of the file within this process.
+static FAVORITES_LOCK: Mutex<()> = Mutex::new(());
+
+/// A bookmarked world.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Favorite {
+    /// `owp://host:port?world=<uuid>`
+    pub connect: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub added_at: OffsetDateTime,
+    /// Last handshake that got a `Welcome`.
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub last_joined_at: Option<OffsetDateTime>,
+}
+
+/// Favorites file shared by `owp-client-cli favorites` and the admin API (`/favorites`).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FavoritesV1 {
+    #[serde(default)]
+    pub worlds: BTreeMap<Uuid, Favorite>,
+}
+
+pub fn favorites_path(data_dir: &Path) -> PathBuf {
+    data_dir.join("favorites.json")
+}
+
+pub fn load(path: &Path) -> Result<FavoritesV1> {
+    if !path.exists() {
+        return Ok(FavoritesV1::default());
+    }
+    let data = fs::read_to_string(path).map_err(|e| Error::io(format!("read {path:?}"), e))?;
+    serde_json::from_str(&data).err_as(Error::Corrupt, format!("parse {path:?}"))
+}
+
+fn save(path: &Path, favorites: &FavoritesV1) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|source| Error::Io {
+            context: format!("create {dir:?}"),
+            source,
+        })?;
+    }
+    let json =
+        serde_json::to_string_pretty(favorites).err_as(Error::Invalid, "serialize favorites")?;
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, format!("{json}\n"))
+        .and_then(|()| fs::rename(&tmp, path))
+        .map_err(|source| Error::Io {
+            context: format!("write {path:?}"),
+            source,
+        })
+}
+
+/// Add a world, or update the connect string / name / note of an existing one. Join history is
+/// kept.
+pub fn add(
+    path: &Path,
+    connect: &str,
+    name: Option<String>,
+    note: Option<String>,
+) -> Result<(Uuid, Favorite)> {
+    let (_, world_id) = parse_connect_string(connect)?;
+    let _guard = FAVORITES_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut favorites = load(path)?;
+    let entry = favorites
+        .worlds
+        .entry(world_id)
+        .or_insert_with(|| Favorite {
+            connect: connect.to_string(),
+            name: None,
+            note: None,
+            added_at: OffsetDateTime::now_utc(),
+            last_joined_at: None,
+        });
+    entry.connect = connect.to_string();
+    if name.is_some() {
+        entry.name = name;
+    }
+    if note.is_some() {
+        entry.note = note;
+    }
+    let out = entry.clone();
+    save(path, &favorites)?;
+    Ok((world_id, out))
+}
+
+/// Returns whether the world was a favorite.
+pub fn remove(path: &Path, world_id: Uuid) -> Result<bool> {
+    let _guard = FAVORITES_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut favorites = load(path)?;
+    let removed = favorites.worlds.remove(&world_id).is_some();
+    if removed {
+        save(path, &favorites)?;
+    }
+    Ok(removed)
+}
+
+/// Record a successful join. Worlds that aren't favorites are ignored.
+pub fn mark_joined(path: &Path, world_id: Uuid) -> Result<Option<Favorite>> {
+    let _guard = FAVORITES_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut favorites = load(path)?;
+    let Some(entry) = favorites.worlds.get_mut(&world_id) else {
+        return Ok(None);
+    };
+    entry.last_joined_at = Some(OffsetDateTime::now_utc());
+    let out = entry.clone();
+    save(path, &favorites)?;
+    Ok(Some(out))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_keeps_join_history_and_remove_forgets() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = favorites_path(dir.path());
+        let id = Uuid::new_v4();
+        let connect = format!("owp://127.0.0.1:7777?world={id}");
+
+        add(&path, &connect, Some("Home".to_string()), None).expect("add");
+        assert!(mark_joined(&path, id).expect("join").is_some());
+        assert!(mark_joined(&path, Uuid::new_v4()).expect("join").is_none());
+
+        let moved = format!("owp://play.example.com:7777?world={id}");
+        let (_, fav) = add(&path, &moved, None, Some("moved".to_string())).expect("update");
+        assert_eq!(fav.name.as_deref(), Some("Home"));
+        assert!(fav.last_joined_at.is_some());
+        assert_eq!(load(&path).expect("load").worlds.len(), 1);
+
+        assert!(remove(&path, id).expect("remove"));
+        assert!(!remove(&path, id).expect("remove again"));
+    }
+}
@@ -1,15 +1,40 @@
-use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+
 use base64::Engine;
-use borsh::BorshDeserialize;
 use owp_protocol::WorldDirectoryEntry;
-use owp_registry_types::{read_fixed_string, WorldEntry};
-use serde::Deserialize;
+use owp_registry_types::WorldEntry;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::json;
+use tracing::warn;
+use url::Url;
 use uuid::Uuid;
 
+pub mod alias;
+pub mod attestation;
+pub mod directory;
+pub mod endpoint;
+pub mod error;
+pub mod favorites;
+pub mod payment;
+pub mod probe;
+pub mod proxy;
+pub mod tls;
+pub mod transaction;
+pub mod wallet_auth;
+
+use error::ResultExt;
+pub use error::{Error, Result};
+
 #[derive(Debug, Clone, Deserialize)]
 struct RpcResponse<T> {
-    result: T,
+    result: Option<T>,
+    error: Option<RpcErrorBody>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct RpcErrorBody {
+    code: i64,
+    message: String,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -24,21 +49,98 @@ struct ProgramAccountData {
     data: (String, String),
 }
 
-/// Fetch all published worlds from a Solana RPC via `getProgramAccounts`.
+/// Worlds read from the registry, and how many accounts couldn't be read and why.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RegistryScan {
+    pub worlds: Vec<WorldDirectoryEntry>,
+    /// Accounts the program owns, readable or not.
+    pub accounts: usize,
+    /// Unlisted accounts by reason: `bad_encoding` (not base64), `delisting` (waiting out the
+    /// stake cooldown), or an `EntryError::reason` such as `bad_magic`, `unsupported_version` or
+    /// `bad_length`.
+    pub skipped: BTreeMap<String, usize>,
+}
+
+impl RegistryScan {
+    pub fn skipped_total(&self) -> usize {
+        self.skipped.values().sum()
+    }
+
+    fn skip(&mut self, reason: &str) {
+        *self.skipped.entry(reason.to_string()).or_default() += 1;
+    }
+}
+
+/// Fetch all published worlds from a Solana RPC via `getProgramAccounts`, logging a warning if
+/// some accounts couldn't be read (see `scan_registry` for the details).
 pub async fn fetch_worlds_from_rpc(
     rpc_url: &str,
     registry_program_id: &str,
+    proxy: Option<&proxy::Proxy>,
 ) -> Result<Vec<WorldDirectoryEntry>> {
-    let client = reqwest::Client::new();
+    let scan = scan_registry(rpc_url, registry_program_id, proxy).await?;
+    if !scan.skipped.is_empty() {
+        warn!(
+            "registry {registry_program_id}: skipped {} of {} accounts: {:?}",
+            scan.skipped_total(),
+            scan.accounts,
+            scan.skipped
+        );
+    }
+    Ok(scan.worlds)
+}
 
+/// Read every account of the registry program. Accounts that aren't world entries are counted
+/// in `skipped` rather than failing the scan; only the RPC call itself can fail.
+pub async fn scan_registry(
+    rpc_url: &str,
+    registry_program_id: &str,
+    proxy: Option<&proxy::Proxy>,
+) -> Result<RegistryScan> {
+    let accounts: Vec<ProgramAccount> = rpc_call(
+        rpc_url,
+        proxy,
+        "getProgramAccounts",
+        json!([registry_program_id, { "encoding": "base64" }]),
+    )
+    .await?;
+
+    let mut scan = RegistryScan {
+        accounts: accounts.len(),
+        ..Default::default()
+    };
+    for acc in accounts {
+        let (data_b64, _encoding) = acc.account.data;
+        let Ok(data) = base64::engine::general_purpose::STANDARD.decode(data_b64) else {
+            scan.skip("bad_encoding");
+            continue;
+        };
+        match WorldEntry::decode(&data) {
+            Ok(entry) if entry.is_delisting() => scan.skip("delisting"),
+            Ok(entry) => match WorldDirectoryEntry::try_from(entry) {
+                Ok(listing) => scan.worlds.push(listing),
+                Err(e) => scan.skip(e.reason()),
+            },
+            Err(e) => scan.skip(e.reason()),
+        }
+    }
+
+    Ok(scan)
+}
+
+/// One Solana JSON-RPC call, returning its `result`.
+async fn rpc_call<T: DeserializeOwned>(
+    rpc_url: &str,
+    proxy: Option<&proxy::Proxy>,
+    method: &str,
+    params: serde_json::Value,
+) -> Result<T> {
+    let client = proxy::http_client(proxy)?;
     let body = json!({
       "jsonrpc": "2.0",
       "id": 1,
-      "method": "getProgramAccounts",
-      "params": [
-        registry_program_id,
-        { "encoding": "base64" }
-      ]
+      "method": method,
+      "params": params
     });
 
     let resp = client
@@ -46,52 +148,65 @@ pub async fn fetch_worlds_from_rpc(
         .json(&body)
         .send()
         .await
-        .context("rpc request")?
-        .error_for_status()
-        .context("rpc status")?;
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| Error::http(format!("rpc {rpc_url}"), e, Error::RpcError))?;
 
-    let parsed: RpcResponse<Vec<ProgramAccount>> = resp.json().await.context("rpc parse")?;
-
-    let mut out = Vec::new();
-    for acc in parsed.result {
-        let (data_b64, _encoding) = acc.account.data;
-        let data = base64::engine::general_purpose::STANDARD
-            .decode(data_b64)
-            .context("base64 decode")?;
-
-        let entry = match WorldEntry::try_from_slice(&data) {
-            Ok(v) => v,
-            Err(_) => continue,
-        };
+    let parsed: RpcResponse<T> = resp
+        .json()
+        .await
+        .err_as(Error::RpcError, "parse rpc response")?;
+    if let Some(e) = parsed.error {
+        return Err(Error::RpcError(format!(
+            "{method} failed ({}): {}",
+            e.code, e.message
+        )));
+    }
+    parsed
+        .result
+        .err_as(Error::RpcError, "rpc response has no result")
+}
 
-        let world_id = Uuid::from_bytes(entry.world_id);
-        let name = read_fixed_string(&entry.name);
-        let endpoint = read_fixed_string(&entry.endpoint);
+/// Most staked first; unstaked entries keep their order after them.
+pub fn sort_by_stake(entries: &mut [WorldDirectoryEntry]) {
+    entries.sort_by_key(|e| std::cmp::Reverse(e.stake_lamports.unwrap_or(0)));
+}
 
-        let token_mint = if entry.token_mint == [0u8; 32] {
-            None
-        } else {
-            Some(bs58::encode(entry.token_mint).into_string())
-        };
-        let dbc_pool = if entry.dbc_pool == [0u8; 32] {
-            None
-        } else {
-            Some(bs58::encode(entry.dbc_pool).into_string())
-        };
+/// Split `owp://host:port?world=<uuid>` into `host:port` and the world id.
+pub fn parse_connect_string(connect: &str) -> Result<(String, Uuid)> {
+    let url = Url::parse(connect).err_as(Error::Invalid, "invalid connect string url")?;
+    if url.scheme() != "owp" {
+        return Err(Error::Invalid(
+            "invalid scheme (expected owp://)".to_string(),
+        ));
+    }
+    let host = url.host_str().err_as(Error::Invalid, "missing host")?;
+    let port = url.port().err_as(Error::Invalid, "missing port")?;
 
-        let world_pubkey = Some(bs58::encode(entry.authority).into_string());
-
-        out.push(WorldDirectoryEntry {
-            world_id,
-            name,
-            endpoint,
-            port: entry.game_port,
-            token_mint,
-            dbc_pool,
-            world_pubkey,
-            last_seen: Some(entry.last_update_slot.to_string()),
-        });
+    let mut world_id: Option<Uuid> = None;
+    for (k, v) in url.query_pairs() {
+        if k == "world" {
+            world_id =
+                Some(Uuid::parse_str(&v).err_as(Error::Invalid, "invalid world query param")?);
+        }
     }
+    let world_id = world_id.err_as(Error::Invalid, "missing world query param")?;
+    Ok((format!("{host}:{port}"), world_id))
+}
+
+/// Raw bytes of a base58 `world_pubkey` (the world authority's ed25519 key).
+pub fn decode_world_pubkey(pubkey: &str) -> Result<[u8; 32]> {
+    bs58::decode(pubkey)
+        .into_vec()
+        .ok()
+        .and_then(|b| b.try_into().ok())
+        .err_as(Error::Invalid, "world pubkey must be a base58 32-byte key")
+}
 
-    Ok(out)
+/// The `pubkey=<world_pubkey>` of a connect string, if it has one.
+pub fn connect_string_pubkey(connect: &str) -> Result<Option<String>> {
+    let url = Url::parse(connect).err_as(Error::Invalid, "invalid connect string url")?;
+    Ok(url
+        .query_pairs()
+        .find(|(k, _)| k == "pubkey")
+        .map(|(_, v)| v.into_owned()))
 }
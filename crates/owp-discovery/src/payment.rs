@@ -0,0 +1,316 @@
+//! Entry-fee payments: paying a `PaymentRequired` and finding the payment on chain by its
+//! reference key, the way Solana Pay does.
+
+use std::collections::HashMap;
+
+use ed25519_dalek::SigningKey;
+use owp_protocol::PaymentRequired;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::alias::find_program_address;
+use crate::error::ResultExt;
+use crate::transaction::{
+    parse_pubkey, pubkey, send_transaction, AccountMeta, Instruction, Pubkey, SYSTEM_PROGRAM_ID,
+};
+use crate::{proxy::Proxy, rpc_call, Error, Result};
+
+pub const SPL_TOKEN_PROGRAM_ID: &str = "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA";
+pub const ASSOCIATED_TOKEN_PROGRAM_ID: &str = "ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL";
+
+/// How many of the reference's most recent transactions `find_payment` looks at.
+const SIGNATURE_LIMIT: usize = 20;
+
+/// A payment found on chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Payment {
+    pub signature: String,
+    /// The fee payer, base58; the wallet the entry pass goes to.
+    pub payer: String,
+}
+
+/// Pay `request` from `payer`'s wallet: a system transfer for SOL, or a token transfer between
+/// the associated token accounts for an SPL mint (the recipient's must exist). Returns the
+/// signature once confirmed.
+pub async fn pay(
+    rpc_url: &str,
+    proxy: Option<&Proxy>,
+    payer: &SigningKey,
+    request: &PaymentRequired,
+) -> Result<String> {
+    let ix = transfer_instruction(&pubkey(payer), request)?;
+    send_transaction(rpc_url, proxy, payer, &[], &[ix]).await
+}
+
+fn transfer_instruction(payer: &Pubkey, request: &PaymentRequired) -> Result<Instruction> {
+    let recipient = parse_pubkey("recipient", &request.recipient)?;
+    let reference = AccountMeta::readonly(parse_pubkey("reference", &request.reference)?, false);
+    let Some(mint) = &request.mint else {
+        // System program `Transfer`: u32 index 2, then the lamports.
+        let mut data = 2u32.to_le_bytes().to_vec();
+        data.extend_from_slice(&request.amount.to_le_bytes());
+        return Ok(Instruction {
+            program_id: SYSTEM_PROGRAM_ID,
+            accounts: vec![
+                AccountMeta::writable(*payer, true),
+                AccountMeta::writable(recipient, false),
+                reference,
+            ],
+            data,
+        });
+    };
+    let mint = parse_pubkey("mint", mint)?;
+    let token_program = parse_pubkey("token program", SPL_TOKEN_PROGRAM_ID)?;
+    // SPL token `Transfer`: tag 3, then the amount in base units.
+    let mut data = vec![3];
+    data.extend_from_slice(&request.amount.to_le_bytes());
+    Ok(Instruction {
+        program_id: token_program,
+        accounts: vec![
+            AccountMeta::writable(associated_token_account(payer, &mint)?, false),
+            AccountMeta::writable(associated_token_account(&recipient, &mint)?, false),
+            AccountMeta::readonly(*payer, true),
+            reference,
+        ],
+        data,
+    })
+}
+
+/// The associated token account holding `mint` for `owner`.
+pub fn associated_token_account(owner: &Pubkey, mint: &Pubkey) -> Result<Pubkey> {
+    let token_program = parse_pubkey("token program", SPL_TOKEN_PROGRAM_ID)?;
+    let ata_program = parse_pubkey("associated token program", ASSOCIATED_TOKEN_PROGRAM_ID)?;
+    find_program_address(&[owner, &token_program, mint], &ata_program)
+        .map(|(address, _)| address)
+        .err_as(Error::Invalid, "no associated token address")
+}
+
+#[derive(Debug, Deserialize)]
+struct SignatureInfo {
+    signature: String,
+    err: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ConfirmedTransaction {
+    meta: Option<TransactionMeta>,
+    transaction: TransactionBody,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TransactionMeta {
+    err: Option<serde_json::Value>,
+    pre_balances: Vec<u64>,
+    post_balances: Vec<u64>,
+    #[serde(default)]
+    pre_token_balances: Vec<TokenBalance>,
+    #[serde(default)]
+    post_token_balances: Vec<TokenBalance>,
+    #[serde(default)]
+    loaded_addresses: Option<LoadedAddresses>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct LoadedAddresses {
+    writable: Vec<String>,
+    readonly: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TokenBalance {
+    account_index: usize,
+    mint: String,
+    owner: Option<String>,
+    ui_token_amount: TokenAmount,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenAmount {
+    amount: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TransactionBody {
+    signatures: Vec<String>,
+    message: TransactionMessage,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TransactionMessage {
+    account_keys: Vec<String>,
+}
+
+/// Look for a confirmed, successful transaction that lists `request.reference` and pays
+/// `request.recipient` at least `request.amount` of the requested token. `None` until one lands.
+pub async fn find_payment(
+    rpc_url: &str,
+    proxy: Option<&Proxy>,
+    request: &PaymentRequired,
+) -> Result<Option<Payment>> {
+    let signatures: Vec<SignatureInfo> = rpc_call(
+        rpc_url,
+        proxy,
+        "getSignaturesForAddress",
+        json!([request.reference, { "limit": SIGNATURE_LIMIT, "commitment": "confirmed" }]),
+    )
+    .await?;
+    for info in signatures.iter().filter(|s| s.err.is_none()) {
+        let tx: Option<ConfirmedTransaction> = rpc_call(
+            rpc_url,
+            proxy,
+            "getTransaction",
+            json!([info.signature, {
+                "encoding": "json",
+                "commitment": "confirmed",
+                "maxSupportedTransactionVersion": 0,
+            }]),
+        )
+        .await?;
+        if let Some(payment) = tx.and_then(|tx| received(&tx, request)) {
+            return Ok(Some(payment));
+        }
+    }
+    Ok(None)
+}
+
+/// The payment `tx` makes toward `request`, judged by the recipient's balance change.
+fn received(tx: &ConfirmedTransaction, request: &PaymentRequired) -> Option<Payment> {
+    let meta = tx.meta.as_ref().filter(|m| m.err.is_none())?;
+    let loaded = meta.loaded_addresses.as_ref();
+    // Addresses loaded from lookup tables follow the static keys, writable ones first.
+    let keys: Vec<&str> = tx
+        .transaction
+        .message
+        .account_keys
+        .iter()
+        .chain(loaded.into_iter().flat_map(|l| &l.writable))
+        .chain(loaded.into_iter().flat_map(|l| &l.readonly))
+        .map(String::as_str)
+        .collect();
+    if !keys.contains(&request.reference.as_str()) {
+        return None;
+    }
+    let gained = match &request.mint {
+        None => {
+            let i = keys.iter().position(|k| *k == request.recipient)?;
+            let pre = *meta.pre_balances.get(i)?;
+            let post = *meta.post_balances.get(i)?;
+            post.saturating_sub(pre)
+        }
+        Some(mint) => {
+            let held = |balances: &[TokenBalance]| -> HashMap<usize, u64> {
+                balances
+                    .iter()
+                    .filter(|b| &b.mint == mint && b.owner.as_deref() == Some(&request.recipient))
+                    .filter_map(|b| Some((b.account_index, b.ui_token_amount.amount.parse().ok()?)))
+                    .collect()
+            };
+            let pre = held(&meta.pre_token_balances);
+            held(&meta.post_token_balances)
+                .iter()
+                .map(|(i, post)| post.saturating_sub(pre.get(i).copied().unwrap_or(0)))
+                .sum()
+        }
+    };
+    if gained < request.amount {
+        return None;
+    }
+    Some(Payment {
+        signature: tx.transaction.signatures.first()?.clone(),
+        payer: keys.first()?.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn request(mint: Option<&str>) -> PaymentRequired {
+        PaymentRequired {
+            request_id: Uuid::nil(),
+            recipient: "Recipient".into(),
+            amount: 1_000,
+            mint: mint.map(String::from),
+            reference: "Reference".into(),
+            expires_at: 0,
+            pay_url: String::new(),
+        }
+    }
+
+    fn tx(value: serde_json::Value) -> ConfirmedTransaction {
+        serde_json::from_value(value).expect("transaction")
+    }
+
+    #[test]
+    fn payments_count_what_the_recipient_received() {
+        let sol = tx(json!({
+            "meta": {
+                "err": null,
+                "preBalances": [5_000_000, 10, 0],
+                "postBalances": [4_990_000, 1_010, 0],
+                "preTokenBalances": [],
+                "postTokenBalances": [],
+            },
+            "transaction": {
+                "signatures": ["Sig"],
+                "message": { "accountKeys": ["Payer", "Recipient", "Reference"] },
+            },
+        }));
+        let paid = received(&sol, &request(None)).expect("paid");
+        assert_eq!(paid.payer, "Payer");
+        assert_eq!(paid.signature, "Sig");
+        let mut more = request(None);
+        more.amount = 1_001;
+        assert_eq!(received(&sol, &more), None);
+        let mut elsewhere = request(None);
+        elsewhere.reference = "Other".into();
+        assert_eq!(received(&sol, &elsewhere), None);
+        // SOL moving says nothing about a token fee.
+        assert_eq!(received(&sol, &request(Some("Mint"))), None);
+
+        // A token transfer with the reference loaded from a lookup table.
+        let balance = |index: usize, owner: &str, amount: &str| {
+            json!({
+                "accountIndex": index,
+                "mint": "Mint",
+                "owner": owner,
+                "uiTokenAmount": { "amount": amount },
+            })
+        };
+        let token = tx(json!({
+            "meta": {
+                "err": null,
+                "preBalances": [5_000_000, 0, 0],
+                "postBalances": [4_995_000, 0, 0],
+                "preTokenBalances": [balance(1, "Payer", "5000"), balance(2, "Recipient", "7")],
+                "postTokenBalances": [balance(1, "Payer", "4000"), balance(2, "Recipient", "1007")],
+                "loadedAddresses": { "writable": [], "readonly": ["Reference"] },
+            },
+            "transaction": {
+                "signatures": ["TokenSig"],
+                "message": { "accountKeys": ["Payer", "PayerAta", "RecipientAta"] },
+            },
+        }));
+        let paid = received(&token, &request(Some("Mint"))).expect("paid");
+        assert_eq!(paid.payer, "Payer");
+        assert_eq!(received(&token, &request(Some("OtherMint"))), None);
+        assert_eq!(received(&token, &request(None)), None);
+
+        let failed = tx(json!({
+            "meta": {
+                "err": { "InstructionError": [0, "Custom"] },
+                "preBalances": [5_000_000, 10, 0],
+                "postBalances": [4_990_000, 1_010, 0],
+            },
+            "transaction": {
+                "signatures": ["Sig"],
+                "message": { "accountKeys": ["Payer", "Recipient", "Reference"] },
+            },
+        }));
+        assert_eq!(received(&failed, &request(None)), None);
+    }
+}
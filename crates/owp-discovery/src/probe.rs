@@ -0,0 +1,153 @@
+use owp_protocol::{wire, Hello, Message, WorldDirectoryEntry, OWP_PROTOCOL_VERSION};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::attestation::{self, Expected};
+use crate::endpoint;
+use crate::proxy::Proxy;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProbeResult {
+    pub world_id: Uuid,
+    /// Handshake completed and the server answered for the expected world.
+    pub ok: bool,
+    /// Connect + hello/welcome round trip, when `ok`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rtt_ms: Option<u32>,
+    /// Players online as reported in the server's `Welcome` (the probe itself included).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub player_count: Option<u32>,
+    /// Whether `welcome.attestation` proved the listed authority hosts the world at the listed
+    /// endpoint; `None` for listings without a `world_pubkey`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attested: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Player count from `Welcome`, and the attestation check.
+async fn handshake(
+    entry: &WorldDirectoryEntry,
+    proxy: Option<&Proxy>,
+) -> Result<(Option<u32>, Option<bool>), String> {
+    let mut stream = endpoint::connect(proxy, &entry.endpoint, entry.port)
+        .await
+        .map_err(|e| format!("{e:#}"))?;
+    let nonce = Uuid::new_v4().simple().to_string();
+    let hello = Message::Hello(Hello {
+        protocol_version: OWP_PROTOCOL_VERSION.to_string(),
+        request_id: Uuid::new_v4(),
+        world_id: Some(entry.world_id),
+        client_name: Some("owp-discovery-probe".to_string()),
+        resume_token: None,
+        party_token: None,
+        wallet_pubkey: None,
+        wallet_proof: None,
+        profile_id: None,
+        frame_protection: vec![],
+        wire_format: vec![],
+        compression: vec![],
+        attestation_nonce: Some(nonce.clone()),
+        capabilities: vec![],
+    });
+    wire::write_message(&mut stream, &hello)
+        .await
+        .map_err(|e| format!("send hello: {e}"))?;
+    match wire::read_message(&mut stream).await {
+        Ok(Message::Welcome(w)) if w.world_id == entry.world_id => {
+            let attested = entry.world_pubkey.as_deref().map(|world_pubkey| {
+                let expected = Expected {
+                    world_id: entry.world_id,
+                    world_pubkey,
+                    endpoint: &entry.endpoint,
+                    nonce: &nonce,
+                    now: OffsetDateTime::now_utc().unix_timestamp(),
+                };
+                attestation::verify(w.attestation.as_ref(), &expected).is_ok()
+            });
+            Ok((w.player_count, attested))
+        }
+        Ok(Message::Welcome(w)) => Err(format!("serves a different world ({})", w.world_id)),
+        Ok(other) => Err(format!("unexpected reply: {other:?}")),
+        Err(e) => Err(format!("read welcome: {e}")),
+    }
+}
+
+/// Check that a listed world is actually reachable and speaks OWP.
+pub async fn probe_world(
+    entry: &WorldDirectoryEntry,
+    timeout: Duration,
+    proxy: Option<&Proxy>,
+) -> ProbeResult {
+    let started = Instant::now();
+    let outcome = match tokio::time::timeout(timeout, handshake(entry, proxy)).await {
+        Ok(r) => r,
+        Err(_) => Err(format!("timed out after {}ms", timeout.as_millis())),
+    };
+    match outcome {
+        Ok((player_count, attested)) => ProbeResult {
+            world_id: entry.world_id,
+            ok: true,
+            rtt_ms: Some(started.elapsed().as_millis().min(u32::MAX as u128) as u32),
+            // Don't count the probe's own connection.
+            player_count: player_count.map(|n| n.saturating_sub(1)),
+            attested,
+            error: None,
+        },
+        Err(e) => ProbeResult {
+            world_id: entry.world_id,
+            ok: false,
+            rtt_ms: None,
+            player_count: None,
+            attested: None,
+            error: Some(e),
+        },
+    }
+}
+
+/// Probe many entries concurrently.
+pub async fn probe_all(
+    entries: &[WorldDirectoryEntry],
+    timeout: Duration,
+    proxy: Option<&Proxy>,
+) -> Vec<ProbeResult> {
+    let mut set = tokio::task::JoinSet::new();
+    for e in entries {
+        let e = e.clone();
+        let proxy = proxy.cloned();
+        set.spawn(async move { probe_world(&e, timeout, proxy.as_ref()).await });
+    }
+    let mut out = Vec::with_capacity(entries.len());
+    while let Some(r) = set.join_next().await {
+        if let Ok(r) = r {
+            out.push(r);
+        }
+    }
+    out
+}
+
+/// Copy round-trip times from `results` onto the matching entries.
+pub fn apply_rtts(entries: &mut [WorldDirectoryEntry], results: &[ProbeResult]) {
+    for e in entries.iter_mut() {
+        if let Some(r) = results.iter().find(|r| r.world_id == e.world_id) {
+            e.rtt_ms = r.rtt_ms;
+        }
+    }
+}
+
+/// Fastest first; entries without a round trip (unprobed or unreachable) go last. With
+/// `group_by_region`, entries are grouped by region first (unknown region last), keeping the
+/// latency order within each group.
+pub fn sort_by_latency(entries: &mut [WorldDirectoryEntry], group_by_region: bool) {
+    entries.sort_by(|a, b| {
+        let region = |e: &WorldDirectoryEntry| (e.region.is_none(), e.region.clone());
+        let rtt = |e: &WorldDirectoryEntry| (e.rtt_ms.is_none(), e.rtt_ms);
+        if group_by_region {
+            region(a).cmp(&region(b)).then(rtt(a).cmp(&rtt(b)))
+        } else {
+            rtt(a).cmp(&rtt(b))
+        }
+    });
+}
@@ -0,0 +1,178 @@
+//! TLS to a world's game port (`tls` in the world manifest). A client that knows the world
+//! authority key accepts only a certificate for that key, whatever signed it: the server's
+//! default self-signed one. A client without it checks the certificate against public CAs for the
+//! host dialed, and then only the server's attestation ties the endpoint to the world.
+
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use tokio_rustls::rustls::crypto::{
+    ring, verify_tls12_signature, verify_tls13_signature, CryptoProvider,
+};
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use tokio_rustls::rustls::{
+    self, CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore,
+};
+use tokio_rustls::TlsConnector;
+
+use crate::error::{Error, Result, ResultExt};
+
+/// DER `SubjectPublicKeyInfo` of an ed25519 key, minus the 32 key bytes (RFC 8410).
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+/// Whether `cert` is for the ed25519 key `world_pubkey`.
+pub fn certificate_has_key(cert: &CertificateDer<'_>, world_pubkey: &[u8; 32]) -> bool {
+    let Ok(cert) = webpki::EndEntityCert::try_from(cert) else {
+        return false;
+    };
+    let spki = cert.subject_public_key_info();
+    spki.as_ref() == [&ED25519_SPKI_PREFIX[..], &world_pubkey[..]].concat()
+}
+
+fn public_roots() -> RootCertStore {
+    RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned())
+}
+
+/// Accepts only a certificate for the world authority key. A CA-issued one is refused: it would
+/// leave the handshake unbound to the key, and the attestation alone can be replayed.
+#[derive(Debug)]
+pub struct PinnedAuthority {
+    world_pubkey: [u8; 32],
+    provider: Arc<CryptoProvider>,
+}
+
+impl PinnedAuthority {
+    pub fn new(world_pubkey: [u8; 32]) -> Self {
+        Self {
+            world_pubkey,
+            provider: Arc::new(ring::default_provider()),
+        }
+    }
+}
+
+impl ServerCertVerifier for PinnedAuthority {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        if certificate_has_key(end_entity, &self.world_pubkey) {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        let algs = &self.provider.signature_verification_algorithms;
+        verify_tls12_signature(message, cert, dss, algs)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        let algs = &self.provider.signature_verification_algorithms;
+        verify_tls13_signature(message, cert, dss, algs)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// Client settings for a world: pinned to `world_pubkey` if known, else checked against public
+/// CAs. A pinned connection has proven the server holds the key once the handshake succeeds.
+pub fn client_config(world_pubkey: Option<[u8; 32]>) -> Result<ClientConfig> {
+    let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .err_as(Error::Invalid, "tls versions")?;
+    Ok(match world_pubkey {
+        Some(key) => builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(PinnedAuthority::new(key)))
+            .with_no_client_auth(),
+        None => builder
+            .with_root_certificates(public_roots())
+            .with_no_client_auth(),
+    })
+}
+
+/// TLS over an open connection to `host` (the host part of the address dialed).
+pub async fn connect(
+    stream: TcpStream,
+    host: &str,
+    world_pubkey: Option<[u8; 32]>,
+) -> Result<TlsStream<TcpStream>> {
+    let name = ServerName::try_from(host.to_string())
+        .err_as(Error::Invalid, format!("tls server name {host:?}"))?;
+    let connector = TlsConnector::from(Arc::new(client_config(world_pubkey)?));
+    connector.connect(name, stream).await.err_as(
+        Error::ProviderUnavailable,
+        format!("tls handshake with {host}"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A self-signed certificate and the raw ed25519 key it is for.
+    fn self_signed() -> (CertificateDer<'static>, [u8; 32]) {
+        let key_pair = rcgen::KeyPair::generate_for(&rcgen::PKCS_ED25519).expect("key");
+        let cert = rcgen::CertificateParams::new(vec!["owp".to_string()])
+            .expect("params")
+            .self_signed(&key_pair)
+            .expect("self-sign");
+        let key = key_pair.public_key_raw().try_into().expect("ed25519 key");
+        (cert.der().clone(), key)
+    }
+
+    #[test]
+    fn certificates_match_only_their_own_key() {
+        let (cert, key) = self_signed();
+        let (_, other) = self_signed();
+        assert!(certificate_has_key(&cert, &key));
+        assert!(!certificate_has_key(&cert, &other));
+        assert!(!certificate_has_key(
+            &CertificateDer::from(vec![0u8; 16]),
+            &key
+        ));
+    }
+
+    #[test]
+    fn pinned_clients_refuse_any_other_certificate() {
+        let (cert, key) = self_signed();
+        let (other_cert, _) = self_signed();
+        let name = ServerName::try_from("owp.example").expect("name");
+        let verify = |cert: &CertificateDer<'_>| {
+            PinnedAuthority::new(key).verify_server_cert(cert, &[], &name, &[], UnixTime::now())
+        };
+        assert!(verify(&cert).is_ok());
+        assert!(matches!(
+            verify(&other_cert),
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure
+            ))
+        ));
+    }
+}
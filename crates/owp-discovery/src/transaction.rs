@@ -0,0 +1,318 @@
+//! Legacy Solana transactions, built, signed and sent without the Solana SDK: enough for the host
+//! to submit a few instructions signed by local keypairs (the token launch in `owp-server`).
+
+use std::time::Duration;
+
+use base64::Engine;
+use ed25519_dalek::{Signer, SigningKey};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::error::ResultExt;
+use crate::{proxy::Proxy, rpc_call, Error, Result};
+
+pub type Pubkey = [u8; 32];
+
+pub const SYSTEM_PROGRAM_ID: Pubkey = [0u8; 32];
+
+/// How long `send_transaction` waits for the cluster to confirm.
+const CONFIRM_POLLS: u32 = 60;
+const CONFIRM_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountMeta {
+    pub pubkey: Pubkey,
+    pub is_signer: bool,
+    pub is_writable: bool,
+}
+
+impl AccountMeta {
+    pub fn writable(pubkey: Pubkey, is_signer: bool) -> Self {
+        Self {
+            pubkey,
+            is_signer,
+            is_writable: true,
+        }
+    }
+
+    pub fn readonly(pubkey: Pubkey, is_signer: bool) -> Self {
+        Self {
+            pubkey,
+            is_signer,
+            is_writable: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Instruction {
+    pub program_id: Pubkey,
+    pub accounts: Vec<AccountMeta>,
+    pub data: Vec<u8>,
+}
+
+/// Raw bytes of a base58 account address; `field` names it in the error.
+pub fn parse_pubkey(field: &str, b58: &str) -> Result<Pubkey> {
+    bs58::decode(b58)
+        .into_vec()
+        .ok()
+        .and_then(|b| b.try_into().ok())
+        .err_as(
+            Error::Invalid,
+            format!("{field} must be a base58 32-byte key"),
+        )
+}
+
+pub fn pubkey(key: &SigningKey) -> Pubkey {
+    key.verifying_key().to_bytes()
+}
+
+/// A compiled message: the bytes every signer signs, and whose signatures go first, in order.
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub bytes: Vec<u8>,
+    pub signers: Vec<Pubkey>,
+}
+
+/// Compile `instructions` into a legacy message paid for by `payer`. Accounts are merged (a key
+/// used twice gets the union of its flags) and ordered writable signers (payer first), read-only
+/// signers, writable others, read-only others, each in order of first use.
+pub fn compile_message(
+    payer: &Pubkey,
+    instructions: &[Instruction],
+    blockhash: &[u8; 32],
+) -> Message {
+    let mut keys = vec![AccountMeta::writable(*payer, true)];
+    let uses = instructions.iter().flat_map(|ix| {
+        ix.accounts
+            .iter()
+            .cloned()
+            .chain([AccountMeta::readonly(ix.program_id, false)])
+    });
+    for meta in uses {
+        match keys.iter_mut().find(|k| k.pubkey == meta.pubkey) {
+            Some(k) => {
+                k.is_signer |= meta.is_signer;
+                k.is_writable |= meta.is_writable;
+            }
+            None => keys.push(meta),
+        }
+    }
+    // Stable, so the payer stays first and first use decides order within a group.
+    keys.sort_by_key(|k| (!k.is_signer, !k.is_writable));
+
+    let signers: Vec<Pubkey> = keys
+        .iter()
+        .filter(|k| k.is_signer)
+        .map(|k| k.pubkey)
+        .collect();
+    let readonly_signed = keys.iter().filter(|k| k.is_signer && !k.is_writable);
+    let readonly_unsigned = keys.iter().filter(|k| !k.is_signer && !k.is_writable);
+    let index = |key: &Pubkey| {
+        // Every key was collected above; legacy messages hold at most 256 of them.
+        keys.iter()
+            .position(|k| k.pubkey == *key)
+            .expect("collected") as u8
+    };
+
+    let mut bytes = vec![
+        signers.len() as u8,
+        readonly_signed.count() as u8,
+        readonly_unsigned.count() as u8,
+    ];
+    compact_len(&mut bytes, keys.len());
+    for k in &keys {
+        bytes.extend_from_slice(&k.pubkey);
+    }
+    bytes.extend_from_slice(blockhash);
+    compact_len(&mut bytes, instructions.len());
+    for ix in instructions {
+        bytes.push(index(&ix.program_id));
+        compact_len(&mut bytes, ix.accounts.len());
+        bytes.extend(ix.accounts.iter().map(|a| index(&a.pubkey)));
+        compact_len(&mut bytes, ix.data.len());
+        bytes.extend_from_slice(&ix.data);
+    }
+    Message { bytes, signers }
+}
+
+/// Solana's `short_vec` length: 7 bits per byte, low bits first.
+fn compact_len(out: &mut Vec<u8>, len: usize) {
+    let mut rest = len;
+    loop {
+        let byte = (rest & 0x7f) as u8;
+        rest >>= 7;
+        if rest == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// The wire transaction for `message`, signed by `keys` (in any order). Fails if one of the
+/// message's signers has no key here.
+pub fn sign_transaction(message: &Message, keys: &[&SigningKey]) -> Result<Vec<u8>> {
+    let mut tx = vec![];
+    compact_len(&mut tx, message.signers.len());
+    for signer in &message.signers {
+        let key = keys.iter().find(|k| pubkey(k) == *signer).err_as(
+            Error::Invalid,
+            format!("no key for signer {}", bs58::encode(signer).into_string()),
+        )?;
+        tx.extend_from_slice(&key.sign(&message.bytes).to_bytes());
+    }
+    tx.extend_from_slice(&message.bytes);
+    Ok(tx)
+}
+
+#[derive(Debug, Deserialize)]
+struct Blockhash {
+    value: BlockhashValue,
+}
+
+#[derive(Debug, Deserialize)]
+struct BlockhashValue {
+    blockhash: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SignatureStatuses {
+    value: Vec<Option<SignatureStatus>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SignatureStatus {
+    err: Option<serde_json::Value>,
+    confirmation_status: Option<String>,
+}
+
+/// Lamports an account of `len` bytes needs to be rent exempt.
+pub async fn minimum_balance_for_rent_exemption(
+    rpc_url: &str,
+    proxy: Option<&Proxy>,
+    len: usize,
+) -> Result<u64> {
+    rpc_call(
+        rpc_url,
+        proxy,
+        "getMinimumBalanceForRentExemption",
+        json!([len]),
+    )
+    .await
+}
+
+/// Sign `instructions` with `payer` and `signers`, send them as one transaction and wait until
+/// the cluster confirms it. Returns the signature (base58), which is also the transaction id.
+pub async fn send_transaction(
+    rpc_url: &str,
+    proxy: Option<&Proxy>,
+    payer: &SigningKey,
+    signers: &[&SigningKey],
+    instructions: &[Instruction],
+) -> Result<String> {
+    let latest: Blockhash = rpc_call(
+        rpc_url,
+        proxy,
+        "getLatestBlockhash",
+        json!([{ "commitment": "confirmed" }]),
+    )
+    .await?;
+    let blockhash = parse_pubkey("blockhash", &latest.value.blockhash)?;
+    let message = compile_message(&pubkey(payer), instructions, &blockhash);
+    let keys: Vec<&SigningKey> = [payer].into_iter().chain(signers.iter().copied()).collect();
+    let tx = sign_transaction(&message, &keys)?;
+    let encoded = base64::engine::general_purpose::STANDARD.encode(tx);
+    let signature: String = rpc_call(
+        rpc_url,
+        proxy,
+        "sendTransaction",
+        json!([encoded, { "encoding": "base64", "preflightCommitment": "confirmed" }]),
+    )
+    .await?;
+
+    for _ in 0..CONFIRM_POLLS {
+        let statuses: SignatureStatuses =
+            rpc_call(rpc_url, proxy, "getSignatureStatuses", json!([[signature]])).await?;
+        if let Some(Some(status)) = statuses.value.first() {
+            if let Some(err) = &status.err {
+                return Err(Error::RpcError(format!(
+                    "transaction {signature} failed: {err}"
+                )));
+            }
+            if matches!(
+                status.confirmation_status.as_deref(),
+                Some("confirmed" | "finalized")
+            ) {
+                return Ok(signature);
+            }
+        }
+        tokio::time::sleep(CONFIRM_INTERVAL).await;
+    }
+    Err(Error::ProviderUnavailable(format!(
+        "transaction {signature} was not confirmed in time"
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signature, Verifier};
+
+    #[test]
+    fn messages_order_and_merge_accounts_and_carry_every_signature() {
+        let payer = SigningKey::from_bytes(&[1; 32]);
+        let mint = SigningKey::from_bytes(&[2; 32]);
+        let program = [9u8; 32];
+        let readonly = [7u8; 32];
+        let ixs = [
+            Instruction {
+                program_id: SYSTEM_PROGRAM_ID,
+                accounts: vec![
+                    AccountMeta::writable(pubkey(&payer), true),
+                    AccountMeta::writable(pubkey(&mint), true),
+                ],
+                data: vec![0; 200],
+            },
+            Instruction {
+                program_id: program,
+                accounts: vec![
+                    AccountMeta::readonly(readonly, false),
+                    AccountMeta::readonly(pubkey(&mint), false),
+                ],
+                data: vec![1],
+            },
+        ];
+        let blockhash = [5u8; 32];
+        let message = compile_message(&pubkey(&payer), &ixs, &blockhash);
+        assert_eq!(message.signers, vec![pubkey(&payer), pubkey(&mint)]);
+
+        let b = &message.bytes;
+        // Two signers, none read-only; three read-only others (system, readonly, program).
+        assert_eq!(&b[..4], &[2, 0, 3, 5]);
+        let keys: Vec<&[u8]> = b[4..4 + 5 * 32].chunks(32).collect();
+        let want = [
+            pubkey(&payer),
+            pubkey(&mint),
+            SYSTEM_PROGRAM_ID,
+            readonly,
+            program,
+        ];
+        assert_eq!(keys, want.iter().map(|k| &k[..]).collect::<Vec<_>>());
+        let rest = &b[4 + 5 * 32..];
+        assert_eq!(&rest[..32], &blockhash);
+        // Two instructions; the first's 200-byte data length takes two bytes.
+        assert_eq!(&rest[32..39], &[2, 2, 2, 0, 1, 0xc8, 0x01]);
+        assert_eq!(&rest[239..], &[4, 2, 3, 1, 1, 1]);
+
+        assert!(sign_transaction(&message, &[&payer]).is_err());
+        let tx = sign_transaction(&message, &[&mint, &payer]).expect("sign");
+        assert_eq!(tx[0], 2);
+        for (i, key) in [&payer, &mint].iter().enumerate() {
+            let sig = Signature::from_slice(&tx[1 + 64 * i..1 + 64 * (i + 1)]).expect("sig");
+            key.verifying_key().verify(b, &sig).expect("valid");
+        }
+        assert_eq!(&tx[1 + 128..], &b[..]);
+    }
+}
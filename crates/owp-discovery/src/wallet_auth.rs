@@ -0,0 +1,81 @@
+//! `hello.wallet_proof`: the player's wallet signing that it connects to `world_id` now, so a
+//! server can let it go by that wallet. Servers accept each nonce once and only within
+//! `MAX_CLOCK_SKEW_SECS` of their clock, so a captured proof can't be replayed.
+
+use base64::Engine;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use owp_protocol::WalletProof;
+use uuid::Uuid;
+
+/// How far `timestamp` may be from the server's clock.
+pub const MAX_CLOCK_SKEW_SECS: i64 = 300;
+
+pub const MAX_NONCE_LEN: usize = 128;
+
+/// The signed bytes: a version line, then world id, wallet pubkey, timestamp and nonce, one per
+/// line.
+pub fn signed_bytes(world_id: Uuid, wallet_pubkey: &str, timestamp: i64, nonce: &str) -> Vec<u8> {
+    format!("owp-wallet-auth-v1\n{world_id}\n{wallet_pubkey}\n{timestamp}\n{nonce}").into_bytes()
+}
+
+/// A proof for `world_id` with a fresh nonce.
+pub fn sign(key: &SigningKey, world_id: Uuid, timestamp: i64) -> WalletProof {
+    let nonce = Uuid::new_v4().simple().to_string();
+    let pubkey = bs58::encode(key.verifying_key().as_bytes()).into_string();
+    let sig = key.sign(&signed_bytes(world_id, &pubkey, timestamp, &nonce));
+    WalletProof {
+        timestamp,
+        nonce,
+        signature: base64::engine::general_purpose::STANDARD.encode(sig.to_bytes()),
+    }
+}
+
+/// Whether `proof` is a valid signature by `wallet_pubkey` (base58) for `world_id`. Freshness
+/// and single use are up to the server.
+pub fn signature_valid(wallet_pubkey: &str, world_id: Uuid, proof: &WalletProof) -> bool {
+    let Some(key) = bs58::decode(wallet_pubkey)
+        .into_vec()
+        .ok()
+        .and_then(|b| <[u8; 32]>::try_from(b).ok())
+        .and_then(|b| VerifyingKey::from_bytes(&b).ok())
+    else {
+        return false;
+    };
+    let Some(sig) = base64::engine::general_purpose::STANDARD
+        .decode(&proof.signature)
+        .ok()
+        .and_then(|b| <[u8; 64]>::try_from(b).ok())
+    else {
+        return false;
+    };
+    let msg = signed_bytes(world_id, wallet_pubkey, proof.timestamp, &proof.nonce);
+    key.verify(&msg, &Signature::from_bytes(&sig)).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proofs_are_bound_to_wallet_and_world() {
+        let key = SigningKey::from_bytes(&[3u8; 32]);
+        let pubkey = bs58::encode(key.verifying_key().as_bytes()).into_string();
+        let world_id = Uuid::new_v4();
+        let proof = sign(&key, world_id, 1_000);
+        assert!(signature_valid(&pubkey, world_id, &proof));
+        assert!(!signature_valid(&pubkey, Uuid::new_v4(), &proof));
+        let other = bs58::encode(
+            SigningKey::from_bytes(&[4u8; 32])
+                .verifying_key()
+                .as_bytes(),
+        )
+        .into_string();
+        assert!(!signature_valid(&other, world_id, &proof));
+        let later = WalletProof {
+            timestamp: 2_000,
+            ..proof
+        };
+        assert!(!signature_valid(&pubkey, world_id, &later));
+        assert!(!signature_valid("not base58!", world_id, &later));
+    }
+}
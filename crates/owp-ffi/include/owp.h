@@ -0,0 +1,83 @@
+// Generated from crates/owp-ffi/src/lib.rs; do not edit. Regenerate with
+// `OWP_UPDATE_BINDINGS=1 cargo test -p owp-ffi`.
+
+#ifndef OWP_H
+#define OWP_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct OwpConnection OwpConnection;
+typedef struct OwpPeerServer OwpPeerServer;
+
+// Protocol version this library speaks.
+char *owp_protocol_version(void);
+
+// Why the last failed call on this thread failed, or null.
+char *owp_last_error(void);
+
+// Release a string returned by this library. Null is ignored.
+void owp_string_free(char *s);
+
+// Split `owp://host:port?world=<uuid>` into `{"addr": "host:port", "world_id": "..."}`.
+char *owp_parse_connect_string(const char *connect);
+
+// Connect to `addr` (`host:port`) and handshake for `world_id`. `options_json` is null or an
+// object with any of `client_name`, `resume_token`, `party_token`, `wallet_pubkey`,
+// `wallet_proof` (`{timestamp, nonce, signature}` signed by that wallet), `profile_id`,
+// `frame_protection` (default `["crc32c"]`), `wire_format` (e.g. `["msgpack"]`; default JSON),
+// `compression` (default `["zstd"]`; `[]` for uncompressed frames),
+// `world_pubkey` (base58; connect over Noise, refuse
+// a server that doesn't hold the world authority key or attests for another host), `noise`
+// (Noise without checking the server) and `proxy` (`socks5://`, `socks5h://` or `http://` URL;
+// defaults to `OWP_PROXY`, then `ALL_PROXY`). Release the connection with `owp_connection_free`.
+OwpConnection *owp_connect(const char *addr, const char *world_id, const char *options_json);
+
+// The server's `welcome` message as JSON.
+char *owp_welcome(const OwpConnection *conn);
+
+// Send one message, given as its JSON. It is checked against the protocol first, so a
+// malformed message fails here instead of on the server. Returns 0, or -1 on failure.
+int32_t owp_send_message(const OwpConnection *conn, const char *message_json);
+
+// The next message from the server as JSON, waiting up to `timeout_ms` (0 = don't wait).
+// Null when nothing arrived in time; null with `owp_last_error` set once the connection is
+// closed.
+char *owp_poll_message(const OwpConnection *conn, uint32_t timeout_ms);
+
+// Close the connection and release it. Null is ignored.
+void owp_connection_free(OwpConnection *conn);
+
+// Call the admin API: `method` `base_url` + `path` with an optional JSON body and bearer
+// token. Returns the response body and stores the HTTP status in `status`; null when the
+// server couldn't be reached. `timeout_secs` 0 means 30 seconds. Requests go through the
+// `http://` proxy in `OWP_PROXY` (or `ALL_PROXY`) when one is set.
+char *owp_admin_request(const char *base_url, const char *token, const char *method, const char *path, const char *body_json, uint32_t timeout_secs, uint16_t *status);
+
+// Bring `cache_dir` up to date with the world's assets: read `GET /worlds/<world_id>/sync` from
+// the admin API at `base_url` and download each listed blob that isn't cached yet, named by its
+// sha256. An interrupted download is kept as `<sha256>.part` and resumed on the next call.
+// `peers_json` is null or `{"<sha256>": ["host:port", ...]}` from `peer_list` replies; those
+// players are tried first and anything that fails the hash check comes from the host instead.
+// Returns `{cached, downloaded, resumed, from_peers, bytes}` as JSON, or null on failure.
+char *owp_sync_assets(const char *base_url, const char *token, const char *world_id, const char *cache_dir, const char *peers_json);
+
+// Serve the blobs in `cache_dir` to other players on `listen` (`host:port`; port 0 picks one),
+// for worlds with the `peer_assist` capability. Announce them with a `peer_announce` message
+// carrying the port from `owp_peer_server_addr`. Stop with `owp_peer_server_free`.
+OwpPeerServer *owp_peer_serve(const char *cache_dir, const char *listen);
+
+// The `host:port` the cache server listens on.
+char *owp_peer_server_addr(const OwpPeerServer *server);
+
+// Stop serving and release the server. Null is ignored.
+void owp_peer_server_free(OwpPeerServer *server);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,56 @@
+use anyhow::{Context, Result};
+use owp_discovery::proxy::{self, Proxy};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::conn::runtime;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Goes through `OWP_PROXY` (or `ALL_PROXY`) when set, which must then be an `http://` proxy.
+pub fn client() -> Result<&'static reqwest::Client> {
+    static CLIENT: OnceLock<Result<reqwest::Client, String>> = OnceLock::new();
+    CLIENT
+        .get_or_init(|| {
+            Proxy::from_env()
+                .and_then(|p| proxy::http_client(p.as_ref()))
+                .map_err(|e| format!("{e:#}"))
+        })
+        .as_ref()
+        .map_err(|e| anyhow::anyhow!("{e}"))
+}
+
+/// One call to the admin API (`owp-server admin`). Returns the status and the response body;
+/// non-2xx statuses are not errors, so hosts can show the server's message.
+pub fn request(
+    base_url: &str,
+    token: Option<&str>,
+    method: &str,
+    path: &str,
+    body: Option<&str>,
+    timeout: Option<Duration>,
+) -> Result<(u16, String)> {
+    let method = reqwest::Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+        .context("invalid HTTP method")?;
+    if !path.starts_with('/') {
+        anyhow::bail!("path must start with '/'");
+    }
+    let url = format!("{}{path}", base_url.trim_end_matches('/'));
+    let mut req = client()?
+        .request(method, &url)
+        .timeout(timeout.unwrap_or(DEFAULT_TIMEOUT));
+    if let Some(token) = token {
+        req = req.bearer_auth(token);
+    }
+    if let Some(body) = body {
+        req = req
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.to_string());
+    }
+    runtime().block_on(async {
+        let resp = req.send().await.with_context(|| format!("request {url}"))?;
+        let status = resp.status().as_u16();
+        let text = resp.text().await.context("read response")?;
+        Ok((status, text))
+    })
+}
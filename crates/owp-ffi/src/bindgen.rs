@@ -0,0 +1,200 @@
+//! Renders the C header and the C# `DllImport` declarations from the `extern "C"` functions in
+//! `lib.rs`, and checks the checked-in copies are current.
+
+use std::path::Path;
+
+struct Function {
+    doc: Vec<String>,
+    name: String,
+    params: Vec<(String, String)>,
+    ret: String,
+}
+
+/// Exported functions in declaration order, with their doc comments up to `# Safety`.
+fn exported(src: &str) -> Vec<Function> {
+    let mut out = vec![];
+    let mut doc: Vec<String> = vec![];
+    let mut in_safety = false;
+    let mut lines = src.lines();
+    while let Some(line) = lines.next() {
+        let line = line.trim();
+        if let Some(d) = line.strip_prefix("///") {
+            let d = d.trim();
+            in_safety |= d == "# Safety";
+            if !in_safety {
+                doc.push(d.to_string());
+            }
+            continue;
+        }
+        if line.starts_with("#[") {
+            continue;
+        }
+        if !line.contains("extern \"C\" fn ") {
+            doc.clear();
+            in_safety = false;
+            continue;
+        }
+        let mut sig = line.to_string();
+        while !sig.contains('{') {
+            sig.push(' ');
+            sig.push_str(lines.next().expect("signature").trim());
+        }
+        let sig = &sig[sig.find("fn ").expect("fn") + 3..sig.find('{').expect("body")];
+        let (name, rest) = sig.split_once('(').expect("params");
+        let (params, ret) = rest.rsplit_once(')').expect("params");
+        let params = params
+            .split(',')
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .map(|p| {
+                let (n, t) = p.split_once(':').expect("param type");
+                (n.trim().to_string(), t.trim().to_string())
+            })
+            .collect();
+        let ret = ret.trim().trim_start_matches("->").trim().to_string();
+        while doc.last().is_some_and(|d| d.is_empty()) {
+            doc.pop();
+        }
+        out.push(Function {
+            doc: std::mem::take(&mut doc),
+            name: name.trim().to_string(),
+            params,
+            ret,
+        });
+        in_safety = false;
+    }
+    out
+}
+
+fn c_type(rust: &str) -> &'static str {
+    match rust {
+        "" => "void",
+        "*const c_char" => "const char *",
+        "*mut c_char" => "char *",
+        "*const OwpConnection" => "const OwpConnection *",
+        "*mut OwpConnection" => "OwpConnection *",
+        "*const OwpPeerServer" => "const OwpPeerServer *",
+        "*mut OwpPeerServer" => "OwpPeerServer *",
+        "*mut u16" => "uint16_t *",
+        "u32" => "uint32_t",
+        "i32" => "int32_t",
+        other => panic!("no C type for {other}"),
+    }
+}
+
+/// `char *name`, `uint32_t name`.
+fn c_decl(ty: &str, name: &str) -> String {
+    if ty.ends_with('*') {
+        format!("{ty}{name}")
+    } else {
+        format!("{ty} {name}")
+    }
+}
+
+fn cs_param(rust: &str) -> &'static str {
+    match rust {
+        "*const c_char" => "[MarshalAs(UnmanagedType.LPUTF8Str)] string",
+        "*mut c_char"
+        | "*const OwpConnection"
+        | "*mut OwpConnection"
+        | "*const OwpPeerServer"
+        | "*mut OwpPeerServer" => "IntPtr",
+        "*mut u16" => "out ushort",
+        "u32" => "uint",
+        "i32" => "int",
+        other => panic!("no C# type for {other}"),
+    }
+}
+
+fn cs_return(rust: &str) -> &'static str {
+    match rust {
+        "" => "void",
+        "*mut c_char" | "*mut OwpConnection" | "*mut OwpPeerServer" => "IntPtr",
+        "i32" => "int",
+        other => panic!("no C# return type for {other}"),
+    }
+}
+
+const HEADER: &str = "Generated from crates/owp-ffi/src/lib.rs; do not edit. Regenerate with\n\
+                      `OWP_UPDATE_BINDINGS=1 cargo test -p owp-ffi`.";
+
+fn c_header(fns: &[Function]) -> String {
+    let mut s = String::new();
+    for l in HEADER.lines() {
+        s += &format!("// {l}\n");
+    }
+    s += "\n#ifndef OWP_H\n#define OWP_H\n\n#include <stdint.h>\n\n";
+    s += "#ifdef __cplusplus\nextern \"C\" {\n#endif\n\n";
+    s += "typedef struct OwpConnection OwpConnection;\n";
+    s += "typedef struct OwpPeerServer OwpPeerServer;\n";
+    for f in fns {
+        s += "\n";
+        for d in &f.doc {
+            s += &format!("// {d}\n").replace("// \n", "//\n");
+        }
+        let params: Vec<String> = f.params.iter().map(|(n, t)| c_decl(c_type(t), n)).collect();
+        let params = if params.is_empty() {
+            "void".to_string()
+        } else {
+            params.join(", ")
+        };
+        let call = format!("{}({params})", f.name);
+        s += &format!("{};\n", c_decl(c_type(&f.ret), &call));
+    }
+    s += "\n#ifdef __cplusplus\n}\n#endif\n\n#endif\n";
+    s
+}
+
+fn csharp(fns: &[Function]) -> String {
+    let mut s = String::new();
+    for l in HEADER.lines() {
+        s += &format!("// {l}\n");
+    }
+    s += "\nusing System;\nusing System.Runtime.InteropServices;\n\n";
+    s += "internal static class OwpNative\n{\n    private const string Lib = \"owp_ffi\";\n";
+    for f in fns {
+        s += "\n";
+        for d in &f.doc {
+            s += &format!("    // {d}\n").replace("// \n", "//\n");
+        }
+        let params: Vec<String> = f
+            .params
+            .iter()
+            .map(|(n, t)| format!("{} {n}", cs_param(t)))
+            .collect();
+        s += "    [DllImport(Lib, CallingConvention = CallingConvention.Cdecl)]\n";
+        s += &format!(
+            "    internal static extern {} {}({});\n",
+            cs_return(&f.ret),
+            f.name,
+            params.join(", ")
+        );
+    }
+    s += "}\n";
+    s
+}
+
+fn check(path: &Path, want: &str) {
+    if std::env::var_os("OWP_UPDATE_BINDINGS").is_some() {
+        std::fs::write(path, want).expect("write bindings");
+        return;
+    }
+    let have = std::fs::read_to_string(path).unwrap_or_default();
+    assert!(
+        have == want,
+        "{} is stale; run `OWP_UPDATE_BINDINGS=1 cargo test -p owp-ffi`",
+        path.display()
+    );
+}
+
+#[test]
+fn bindings_are_current() {
+    let fns = exported(include_str!("lib.rs"));
+    assert!(fns.iter().any(|f| f.name == "owp_connect"));
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    check(&root.join("include/owp.h"), &c_header(&fns));
+    check(
+        &root.join("../../apps/owp-unity/Assets/Scripts/OwpNative.g.cs"),
+        &csharp(&fns),
+    );
+}
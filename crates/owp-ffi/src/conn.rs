@@ -0,0 +1,250 @@
+use anyhow::{Context, Result};
+use owp_discovery::attestation::{self, AttestationError, Expected};
+use owp_discovery::endpoint;
+use owp_discovery::proxy::{self, Proxy};
+use owp_protocol::noise::server_static_public;
+use owp_protocol::protection::FrameEncoder;
+use owp_protocol::session::{Event, ProtocolStateMachine};
+use owp_protocol::{
+    Compression, FrameProtection, Hello, Message, WalletProof, Welcome, WireFormat,
+    OWP_PROTOCOL_VERSION,
+};
+use serde::Deserialize;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use time::OffsetDateTime;
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::runtime::Runtime;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Runs socket I/O for every connection, so hosts can call in from any thread.
+pub fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("owp-ffi")
+            .enable_all()
+            .build()
+            .expect("tokio runtime")
+    })
+}
+
+/// `Hello` fields the host may set; the rest are filled in here.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HelloOptions {
+    #[serde(default)]
+    pub client_name: Option<String>,
+    #[serde(default)]
+    pub resume_token: Option<String>,
+    #[serde(default)]
+    pub party_token: Option<String>,
+    #[serde(default)]
+    pub wallet_pubkey: Option<String>,
+    /// Proof of `wallet_pubkey`, signed by the host's wallet for this world (see
+    /// `owp_discovery::wallet_auth::signed_bytes`) with a fresh nonce.
+    #[serde(default)]
+    pub wallet_proof: Option<WalletProof>,
+    #[serde(default)]
+    pub profile_id: Option<String>,
+    /// Frame protection to offer, most preferred first.
+    #[serde(default = "default_protection")]
+    pub frame_protection: Vec<FrameProtection>,
+    /// Payload encodings to offer, most preferred first; JSON when empty or refused.
+    #[serde(default)]
+    pub wire_format: Vec<WireFormat>,
+    /// Frame compression to offer, most preferred first; none when empty or refused.
+    #[serde(default = "default_compression")]
+    pub compression: Vec<Compression>,
+    /// World authority pubkey (base58): run a Noise handshake, insist the server holds it and
+    /// check its attestation for `addr`'s host, if it sends one.
+    #[serde(default)]
+    pub world_pubkey: Option<String>,
+    /// Run a Noise handshake even without `world_pubkey` (encrypted, server unchecked).
+    #[serde(default)]
+    pub noise: bool,
+    /// `socks5://`, `socks5h://` or `http://` proxy to tunnel through; `OWP_PROXY`, then
+    /// `ALL_PROXY`, when unset.
+    #[serde(default)]
+    pub proxy: Option<String>,
+}
+
+impl Default for HelloOptions {
+    fn default() -> Self {
+        Self {
+            client_name: None,
+            resume_token: None,
+            party_token: None,
+            wallet_pubkey: None,
+            wallet_proof: None,
+            profile_id: None,
+            frame_protection: default_protection(),
+            wire_format: vec![],
+            compression: default_compression(),
+            world_pubkey: None,
+            noise: false,
+            proxy: None,
+        }
+    }
+}
+
+fn default_protection() -> Vec<FrameProtection> {
+    vec![FrameProtection::Crc32c]
+}
+
+fn default_compression() -> Vec<Compression> {
+    Compression::ALL.to_vec()
+}
+
+/// A handshaken connection to one world. A background task reads frames into `inbox`, answering
+/// the server's keepalive pings itself; the host drains it with `poll`.
+pub struct Connection {
+    welcome: Welcome,
+    /// Shared with the reader task, which writes `Pong`s.
+    writer: Arc<tokio::sync::Mutex<(OwnedWriteHalf, FrameEncoder)>>,
+    /// `Err` is the last item, sent when the connection closes.
+    inbox: Mutex<mpsc::Receiver<Result<Message, String>>>,
+    reader: JoinHandle<()>,
+}
+
+impl Connection {
+    pub fn connect(addr: &str, world_id: Uuid, opts: HelloOptions) -> Result<Self> {
+        runtime().block_on(async {
+            let proxy = match &opts.proxy {
+                Some(url) => Some(url.parse::<Proxy>()?),
+                None => Proxy::from_env()?,
+            };
+            let (host, port) = proxy::split_addr(addr)?;
+            let mut stream = tokio::time::timeout(
+                CONNECT_TIMEOUT,
+                endpoint::connect(proxy.as_ref(), host, port),
+            )
+            .await
+            .context("connect timed out")??;
+            let nonce = Uuid::new_v4().simple().to_string();
+            let server_static = match &opts.world_pubkey {
+                Some(pubkey) => {
+                    let authority =
+                        owp_discovery::decode_world_pubkey(pubkey).context("world_pubkey")?;
+                    Some(server_static_public(&authority)?)
+                }
+                None => None,
+            };
+            let hello = Hello {
+                protocol_version: OWP_PROTOCOL_VERSION.to_string(),
+                request_id: Uuid::new_v4(),
+                world_id: Some(world_id),
+                client_name: opts.client_name.or(Some("owp-ffi".to_string())),
+                resume_token: opts.resume_token,
+                party_token: opts.party_token,
+                wallet_pubkey: opts.wallet_pubkey,
+                wallet_proof: opts.wallet_proof,
+                profile_id: opts.profile_id,
+                frame_protection: opts.frame_protection,
+                wire_format: opts.wire_format,
+                compression: opts.compression,
+                attestation_nonce: Some(nonce.clone()),
+                capabilities: vec![],
+            };
+            let mut proto = if server_static.is_some() || opts.noise {
+                ProtocolStateMachine::noise_client(hello, server_static)?
+            } else {
+                ProtocolStateMachine::client(hello)?
+            };
+            let reply =
+                tokio::time::timeout(CONNECT_TIMEOUT, proto.next_handshake_event(&mut stream))
+                    .await
+                    .context("no welcome")?
+                    .context("handshake")?;
+            let Event::Welcome(welcome) = reply else {
+                anyhow::bail!("unexpected reply to hello: {reply:?}");
+            };
+            if let Some(world_pubkey) = &opts.world_pubkey {
+                let expected = Expected {
+                    world_id,
+                    world_pubkey,
+                    endpoint: attestation::host(addr),
+                    nonce: &nonce,
+                    now: OffsetDateTime::now_utc().unix_timestamp(),
+                };
+                match attestation::verify(welcome.attestation.as_ref(), &expected) {
+                    // Noise already proved the key; the endpoint just isn't attested.
+                    Ok(()) | Err(AttestationError::Missing) => {}
+                    Err(e) => anyhow::bail!("server attestation failed: {e}"),
+                }
+            }
+
+            let (mut read, write) = stream.into_split();
+            let encoder = proto.take_encoder()?;
+            let writer = Arc::new(tokio::sync::Mutex::new((write, encoder)));
+            let (tx, rx) = mpsc::channel();
+            let ponger = writer.clone();
+            let reader = tokio::spawn(async move {
+                loop {
+                    let msg = match proto.next_event(&mut read).await {
+                        Ok(Event::Message(Message::Ping(ping))) => {
+                            let now = OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000;
+                            let pong = Message::Pong(ping.pong(now as i64));
+                            let mut writer = ponger.lock().await;
+                            let (write, encoder) = &mut *writer;
+                            match encoder.write_message(write, &pong).await {
+                                Ok(()) => continue,
+                                Err(e) => Err(e.to_string()),
+                            }
+                        }
+                        Ok(Event::Message(m)) => Ok(m),
+                        Ok(other) => Err(format!("unexpected event: {other:?}")),
+                        Err(e) => Err(e.to_string()),
+                    };
+                    let closed = msg.is_err();
+                    if tx.send(msg).is_err() || closed {
+                        break;
+                    }
+                }
+            });
+            Ok(Self {
+                welcome,
+                writer,
+                inbox: Mutex::new(rx),
+                reader,
+            })
+        })
+    }
+
+    pub fn welcome(&self) -> &Welcome {
+        &self.welcome
+    }
+
+    pub fn send(&self, msg: &Message) -> Result<()> {
+        runtime()
+            .block_on(async {
+                let mut writer = self.writer.lock().await;
+                let (write, encoder) = &mut *writer;
+                encoder.write_message(write, msg).await
+            })
+            .context("send message")
+    }
+
+    /// The next message pushed by the server, waiting up to `timeout`. `None` if nothing
+    /// arrived in time; an error once the connection is closed.
+    pub fn poll(&self, timeout: Duration) -> Result<Option<Message>> {
+        let inbox = self.inbox.lock().unwrap_or_else(|e| e.into_inner());
+        match inbox.recv_timeout(timeout) {
+            Ok(Ok(msg)) => Ok(Some(msg)),
+            Ok(Err(e)) => anyhow::bail!("connection closed: {e}"),
+            Err(mpsc::RecvTimeoutError::Timeout) => Ok(None),
+            Err(mpsc::RecvTimeoutError::Disconnected) => anyhow::bail!("connection closed"),
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
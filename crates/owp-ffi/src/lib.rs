@@ -0,0 +1,420 @@
+//! C ABI over the OWP client protocol, for native hosts such as the Unity client.
+//!
+//! Messages cross the boundary as the JSON of `owp_protocol::Message`; framing, the handshake
+//! and message validation stay in Rust. Strings are NUL-terminated UTF-8. Strings returned by
+//! this library belong to the caller and are released with `owp_string_free`. A call that fails
+//! returns null (or -1) and leaves the reason in `owp_last_error`.
+//!
+//! `include/owp.h` and the Unity client's `OwpNative.g.cs` are generated from the signatures
+//! below; run `OWP_UPDATE_BINDINGS=1 cargo test -p owp-ffi` after changing them.
+
+use anyhow::{Context, Result};
+use owp_protocol::{Message, OWP_PROTOCOL_VERSION};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+use std::time::Duration;
+use uuid::Uuid;
+
+mod admin;
+#[cfg(test)]
+mod bindgen;
+mod conn;
+mod peer;
+mod sync;
+
+/// A connection to one world, from `owp_connect`.
+pub struct OwpConnection(conn::Connection);
+
+/// An asset cache served to other players, from `owp_peer_serve`.
+pub struct OwpPeerServer(peer::PeerServer);
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Run `f`, recording its error (or panic) for `owp_last_error` and returning `fallback`.
+fn ffi<T>(fallback: T, f: impl FnOnce() -> Result<T>) -> T {
+    LAST_ERROR.with(|e| e.borrow_mut().take());
+    let err = match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(v)) => return v,
+        Ok(Err(e)) => format!("{e:#}"),
+        Err(_) => "panic in owp-ffi".to_string(),
+    };
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(err));
+    fallback
+}
+
+unsafe fn opt_str<'a>(p: *const c_char) -> Result<Option<&'a str>> {
+    if p.is_null() {
+        return Ok(None);
+    }
+    let s = CStr::from_ptr(p).to_str().context("string is not UTF-8")?;
+    Ok(Some(s))
+}
+
+unsafe fn str_arg<'a>(p: *const c_char, name: &str) -> Result<&'a str> {
+    opt_str(p)?.with_context(|| format!("{name} is null"))
+}
+
+unsafe fn conn_arg<'a>(p: *const OwpConnection) -> Result<&'a conn::Connection> {
+    p.as_ref().map(|c| &c.0).context("connection is null")
+}
+
+fn owned(s: String) -> Result<*mut c_char> {
+    Ok(CString::new(s).context("string contains NUL")?.into_raw())
+}
+
+/// Protocol version this library speaks.
+#[no_mangle]
+pub extern "C" fn owp_protocol_version() -> *mut c_char {
+    ffi(std::ptr::null_mut(), || {
+        owned(OWP_PROTOCOL_VERSION.to_string())
+    })
+}
+
+/// Why the last failed call on this thread failed, or null.
+#[no_mangle]
+pub extern "C" fn owp_last_error() -> *mut c_char {
+    let err = LAST_ERROR.with(|e| e.borrow().clone());
+    err.and_then(|e| owned(e).ok())
+        .unwrap_or(std::ptr::null_mut())
+}
+
+/// Release a string returned by this library. Null is ignored.
+///
+/// # Safety
+/// `s` must come from this library and not have been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn owp_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Split `owp://host:port?world=<uuid>` into `{"addr": "host:port", "world_id": "..."}`.
+///
+/// # Safety
+/// `connect` must be a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn owp_parse_connect_string(connect: *const c_char) -> *mut c_char {
+    ffi(std::ptr::null_mut(), || {
+        let (addr, world_id) = owp_discovery::parse_connect_string(str_arg(connect, "connect")?)?;
+        owned(serde_json::json!({ "addr": addr, "world_id": world_id }).to_string())
+    })
+}
+
+/// Connect to `addr` (`host:port`) and handshake for `world_id`. `options_json` is null or an
+/// object with any of `client_name`, `resume_token`, `party_token`, `wallet_pubkey`,
+/// `wallet_proof` (`{timestamp, nonce, signature}` signed by that wallet), `profile_id`,
+/// `frame_protection` (default `["crc32c"]`), `wire_format` (e.g. `["msgpack"]`; default JSON),
+/// `compression` (default `["zstd"]`; `[]` for uncompressed frames),
+/// `world_pubkey` (base58; connect over Noise, refuse
+/// a server that doesn't hold the world authority key or attests for another host), `noise`
+/// (Noise without checking the server) and `proxy` (`socks5://`, `socks5h://` or `http://` URL;
+/// defaults to `OWP_PROXY`, then `ALL_PROXY`). Release the connection with `owp_connection_free`.
+///
+/// # Safety
+/// The arguments must be valid C strings (`options_json` may be null).
+#[no_mangle]
+pub unsafe extern "C" fn owp_connect(
+    addr: *const c_char,
+    world_id: *const c_char,
+    options_json: *const c_char,
+) -> *mut OwpConnection {
+    ffi(std::ptr::null_mut(), || {
+        let addr = str_arg(addr, "addr")?;
+        let world_id = Uuid::parse_str(str_arg(world_id, "world_id")?).context("world_id")?;
+        let opts = match opt_str(options_json)? {
+            Some(json) => serde_json::from_str(json).context("options_json")?,
+            None => conn::HelloOptions::default(),
+        };
+        let conn = conn::Connection::connect(addr, world_id, opts)?;
+        Ok(Box::into_raw(Box::new(OwpConnection(conn))))
+    })
+}
+
+/// The server's `welcome` message as JSON.
+///
+/// # Safety
+/// `conn` must come from `owp_connect` and not have been freed.
+#[no_mangle]
+pub unsafe extern "C" fn owp_welcome(conn: *const OwpConnection) -> *mut c_char {
+    ffi(std::ptr::null_mut(), || {
+        let welcome = Message::Welcome(conn_arg(conn)?.welcome().clone());
+        owned(serde_json::to_string(&welcome)?)
+    })
+}
+
+/// Send one message, given as its JSON. It is checked against the protocol first, so a
+/// malformed message fails here instead of on the server. Returns 0, or -1 on failure.
+///
+/// # Safety
+/// `conn` must come from `owp_connect` and not have been freed; `message_json` must be a valid
+/// C string.
+#[no_mangle]
+pub unsafe extern "C" fn owp_send_message(
+    conn: *const OwpConnection,
+    message_json: *const c_char,
+) -> i32 {
+    ffi(-1, || {
+        let conn = conn_arg(conn)?;
+        let msg: Message = serde_json::from_str(str_arg(message_json, "message_json")?)
+            .context("not an OWP message")?;
+        conn.send(&msg)?;
+        Ok(0)
+    })
+}
+
+/// The next message from the server as JSON, waiting up to `timeout_ms` (0 = don't wait).
+/// Null when nothing arrived in time; null with `owp_last_error` set once the connection is
+/// closed.
+///
+/// # Safety
+/// `conn` must come from `owp_connect` and not have been freed.
+#[no_mangle]
+pub unsafe extern "C" fn owp_poll_message(
+    conn: *const OwpConnection,
+    timeout_ms: u32,
+) -> *mut c_char {
+    ffi(std::ptr::null_mut(), || {
+        let conn = conn_arg(conn)?;
+        match conn.poll(Duration::from_millis(timeout_ms.into()))? {
+            Some(msg) => owned(serde_json::to_string(&msg)?),
+            None => Ok(std::ptr::null_mut()),
+        }
+    })
+}
+
+/// Close the connection and release it. Null is ignored.
+///
+/// # Safety
+/// `conn` must come from `owp_connect` and not have been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn owp_connection_free(conn: *mut OwpConnection) {
+    if !conn.is_null() {
+        drop(Box::from_raw(conn));
+    }
+}
+
+/// Call the admin API: `method` `base_url` + `path` with an optional JSON body and bearer
+/// token. Returns the response body and stores the HTTP status in `status`; null when the
+/// server couldn't be reached. `timeout_secs` 0 means 30 seconds. Requests go through the
+/// `http://` proxy in `OWP_PROXY` (or `ALL_PROXY`) when one is set.
+///
+/// # Safety
+/// The string arguments must be valid C strings (`token` and `body_json` may be null);
+/// `status` must be null or point to a writable `uint16_t`.
+#[no_mangle]
+pub unsafe extern "C" fn owp_admin_request(
+    base_url: *const c_char,
+    token: *const c_char,
+    method: *const c_char,
+    path: *const c_char,
+    body_json: *const c_char,
+    timeout_secs: u32,
+    status: *mut u16,
+) -> *mut c_char {
+    ffi(std::ptr::null_mut(), || {
+        let (code, body) = admin::request(
+            str_arg(base_url, "base_url")?,
+            opt_str(token)?,
+            str_arg(method, "method")?,
+            str_arg(path, "path")?,
+            opt_str(body_json)?,
+            (timeout_secs > 0).then(|| Duration::from_secs(timeout_secs.into())),
+        )?;
+        if let Some(status) = status.as_mut() {
+            *status = code;
+        }
+        owned(body)
+    })
+}
+
+/// Bring `cache_dir` up to date with the world's assets: read `GET /worlds/<world_id>/sync` from
+/// the admin API at `base_url` and download each listed blob that isn't cached yet, named by its
+/// sha256. An interrupted download is kept as `<sha256>.part` and resumed on the next call.
+/// `peers_json` is null or `{"<sha256>": ["host:port", ...]}` from `peer_list` replies; those
+/// players are tried first and anything that fails the hash check comes from the host instead.
+/// Returns `{cached, downloaded, resumed, from_peers, bytes}` as JSON, or null on failure.
+///
+/// # Safety
+/// The arguments must be valid C strings (`token` and `peers_json` may be null).
+#[no_mangle]
+pub unsafe extern "C" fn owp_sync_assets(
+    base_url: *const c_char,
+    token: *const c_char,
+    world_id: *const c_char,
+    cache_dir: *const c_char,
+    peers_json: *const c_char,
+) -> *mut c_char {
+    ffi(std::ptr::null_mut(), || {
+        let world_id = Uuid::parse_str(str_arg(world_id, "world_id")?).context("world_id")?;
+        let peers = match opt_str(peers_json)? {
+            Some(json) => serde_json::from_str(json).context("peers_json")?,
+            None => HashMap::new(),
+        };
+        let report = sync::sync(
+            str_arg(base_url, "base_url")?,
+            opt_str(token)?,
+            world_id,
+            Path::new(str_arg(cache_dir, "cache_dir")?),
+            &peers,
+            None,
+        )?;
+        owned(serde_json::to_string(&report)?)
+    })
+}
+
+/// Serve the blobs in `cache_dir` to other players on `listen` (`host:port`; port 0 picks one),
+/// for worlds with the `peer_assist` capability. Announce them with a `peer_announce` message
+/// carrying the port from `owp_peer_server_addr`. Stop with `owp_peer_server_free`.
+///
+/// # Safety
+/// The arguments must be valid C strings.
+#[no_mangle]
+pub unsafe extern "C" fn owp_peer_serve(
+    cache_dir: *const c_char,
+    listen: *const c_char,
+) -> *mut OwpPeerServer {
+    ffi(std::ptr::null_mut(), || {
+        let server = peer::PeerServer::start(
+            Path::new(str_arg(cache_dir, "cache_dir")?),
+            str_arg(listen, "listen")?,
+        )?;
+        Ok(Box::into_raw(Box::new(OwpPeerServer(server))))
+    })
+}
+
+/// The `host:port` the cache server listens on.
+///
+/// # Safety
+/// `server` must come from `owp_peer_serve` and not have been freed.
+#[no_mangle]
+pub unsafe extern "C" fn owp_peer_server_addr(server: *const OwpPeerServer) -> *mut c_char {
+    ffi(std::ptr::null_mut(), || {
+        let server = server.as_ref().context("server is null")?;
+        owned(server.0.addr().to_string())
+    })
+}
+
+/// Stop serving and release the server. Null is ignored.
+///
+/// # Safety
+/// `server` must come from `owp_peer_serve` and not have been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn owp_peer_server_free(server: *mut OwpPeerServer) {
+    if !server.is_null() {
+        drop(Box::from_raw(server));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use owp_protocol::{wire, ChatChannel, ChatSend, Emote, Welcome};
+
+    fn take(s: *mut c_char) -> Option<String> {
+        if s.is_null() {
+            return None;
+        }
+        let out = unsafe { CStr::from_ptr(s) }
+            .to_str()
+            .expect("utf-8")
+            .to_string();
+        unsafe { owp_string_free(s) };
+        Some(out)
+    }
+
+    fn c(s: &str) -> CString {
+        CString::new(s).expect("c string")
+    }
+
+    #[test]
+    fn host_talks_to_a_world_through_the_c_abi() {
+        let world_id = Uuid::new_v4();
+        let listener = conn::runtime()
+            .block_on(tokio::net::TcpListener::bind("127.0.0.1:0"))
+            .expect("bind");
+        let addr = listener.local_addr().expect("addr").to_string();
+        let server = conn::runtime().spawn(async move {
+            let (mut s, _) = listener.accept().await.expect("accept");
+            let Message::Hello(hello) = wire::read_message(&mut s).await.expect("hello") else {
+                panic!("expected hello");
+            };
+            assert_eq!(hello.client_name.as_deref(), Some("owp-unity"));
+            let welcome = Message::Welcome(Welcome {
+                protocol_version: OWP_PROTOCOL_VERSION.to_string(),
+                request_id: hello.request_id,
+                world_id,
+                token_mint: None,
+                motd: Some("hi".to_string()),
+                capabilities: vec![],
+                session_token: None,
+                resumed: false,
+                prefetch: vec![],
+                player_id: None,
+                party: None,
+                player_count: None,
+                frame_protection: None,
+                wire_format: None,
+                compression: None,
+                attestation: None,
+                wallet_verified: None,
+            });
+            wire::write_message(&mut s, &welcome)
+                .await
+                .expect("welcome");
+            let wave = Message::Emote(Emote {
+                id: "wave".to_string(),
+                duration_ms: 1000,
+                player_id: None,
+            });
+            wire::write_message(&mut s, &wave).await.expect("emote");
+            wire::read_message(&mut s).await.expect("chat")
+        });
+
+        let conn = unsafe {
+            owp_connect(
+                c(&addr).as_ptr(),
+                c(&world_id.to_string()).as_ptr(),
+                c(r#"{"client_name":"owp-unity"}"#).as_ptr(),
+            )
+        };
+        assert!(!conn.is_null(), "{:?}", take(owp_last_error()));
+        let welcome = take(unsafe { owp_welcome(conn) }).expect("welcome");
+        assert!(welcome.contains(r#""motd":"hi""#));
+        let pushed = take(unsafe { owp_poll_message(conn, 2000) }).expect("pushed");
+        assert!(pushed.contains(r#""type":"emote""#));
+
+        assert_eq!(
+            unsafe { owp_send_message(conn, c(r#"{"type":"nope"}"#).as_ptr()) },
+            -1
+        );
+        assert!(take(owp_last_error()).is_some_and(|e| e.contains("not an OWP message")));
+        let chat = serde_json::to_string(&Message::ChatSend(ChatSend {
+            channel: ChatChannel::World,
+            text: "hello".to_string(),
+        }))
+        .expect("json");
+        assert_eq!(unsafe { owp_send_message(conn, c(&chat).as_ptr()) }, 0);
+        let got = conn::runtime().block_on(server).expect("server");
+        assert!(matches!(got, Message::ChatSend(m) if m.text == "hello"));
+
+        // The server hung up.
+        assert!(take(unsafe { owp_poll_message(conn, 2000) }).is_none());
+        assert!(take(owp_last_error()).is_some_and(|e| e.contains("closed")));
+        unsafe { owp_connection_free(conn) };
+
+        let parsed = take(unsafe {
+            owp_parse_connect_string(c(&format!("owp://{addr}?world={world_id}")).as_ptr())
+        })
+        .expect("parsed");
+        assert!(parsed.contains(&world_id.to_string()));
+        assert!(
+            unsafe { owp_connect(std::ptr::null(), std::ptr::null(), std::ptr::null()) }.is_null()
+        );
+    }
+}
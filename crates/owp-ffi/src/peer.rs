@@ -0,0 +1,168 @@
+//! Serves an asset cache to other players as `GET /content/<sha256>` (with `Range`), for worlds
+//! that turn on peer assist. Only complete blobs named by their hash are served; downloaders
+//! check every blob against its hash, so a peer can waste their time but not corrupt them.
+
+use anyhow::{Context, Result};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+use crate::conn::runtime;
+
+/// Longest request head accepted.
+const MAX_REQUEST_BYTES: usize = 8 * 1024;
+
+/// Time a peer gets to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A running cache server; stops when dropped.
+pub struct PeerServer {
+    addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl PeerServer {
+    /// Serve `cache_dir` on `listen` (`host:port`; port 0 picks one).
+    pub fn start(cache_dir: &Path, listen: &str) -> Result<Self> {
+        let cache_dir = cache_dir.to_path_buf();
+        runtime().block_on(async {
+            let listener = TcpListener::bind(listen)
+                .await
+                .with_context(|| format!("bind {listen}"))?;
+            let addr = listener.local_addr().context("local addr")?;
+            let task = tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let cache_dir = cache_dir.clone();
+                    tokio::spawn(async move {
+                        // A peer that misbehaves only loses its own request.
+                        let _ =
+                            tokio::time::timeout(REQUEST_TIMEOUT, serve(stream, &cache_dir)).await;
+                    });
+                }
+            });
+            Ok(Self { addr, task })
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for PeerServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// The cached blob a request line asks for, if it names one.
+fn blob_for(cache_dir: &Path, head: &str) -> Option<PathBuf> {
+    let mut words = head.lines().next()?.split_whitespace();
+    if words.next()? != "GET" {
+        return None;
+    }
+    let sha256 = words.next()?.strip_prefix("/content/")?;
+    let ok = sha256.len() == 64 && sha256.chars().all(|c| c.is_ascii_hexdigit());
+    ok.then(|| cache_dir.join(sha256.to_ascii_lowercase()))
+}
+
+/// `Range: bytes=<start>-` from the request head.
+fn range_start(head: &str) -> Option<usize> {
+    head.lines().find_map(|l| {
+        let (name, value) = l.split_once(':')?;
+        if !name.trim().eq_ignore_ascii_case("range") {
+            return None;
+        }
+        value
+            .trim()
+            .strip_prefix("bytes=")?
+            .strip_suffix('-')?
+            .parse()
+            .ok()
+    })
+}
+
+async fn serve(mut stream: TcpStream, cache_dir: &Path) -> Result<()> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 || head.len() + n > MAX_REQUEST_BYTES {
+            return Ok(());
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+    let head = String::from_utf8_lossy(&head);
+    let body = match blob_for(cache_dir, &head) {
+        Some(path) => std::fs::read(&path).ok(),
+        None => None,
+    };
+    let reply = match (body, range_start(&head)) {
+        (None, _) => {
+            b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec()
+        }
+        (Some(body), Some(start)) if start > 0 && start < body.len() => {
+            let head = format!(
+                "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {start}-{}/{}\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len() - 1,
+                body.len(),
+                body.len() - start
+            );
+            [head.as_bytes(), &body[start..]].concat()
+        }
+        (Some(body), _) => {
+            let head = format!(
+                "HTTP/1.1 200 OK\r\nAccept-Ranges: bytes\r\nContent-Length: {}\r\n\
+                 Connection: close\r\n\r\n",
+                body.len()
+            );
+            [head.as_bytes(), &body].concat()
+        }
+    };
+    stream.write_all(&reply).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha256};
+
+    #[test]
+    fn serves_cached_blobs_with_ranges() {
+        let cache = tempfile::tempdir().expect("tempdir");
+        let sha256 = hex::encode(Sha256::digest(b"shared mesh"));
+        std::fs::write(cache.path().join(&sha256), b"shared mesh").expect("write");
+        std::fs::write(cache.path().join("notes.txt"), b"private").expect("write");
+        let server = PeerServer::start(cache.path(), "127.0.0.1:0").expect("start");
+        let base = format!("http://{}", server.addr());
+
+        let client = reqwest::Client::new();
+        let get = |path: String, range: Option<&str>| {
+            let mut req = client.get(format!("{base}{path}"));
+            if let Some(r) = range {
+                req = req.header(reqwest::header::RANGE, r);
+            }
+            runtime().block_on(async {
+                let resp = req.send().await.expect("send");
+                let status = resp.status().as_u16();
+                (status, resp.bytes().await.expect("body").to_vec())
+            })
+        };
+        assert_eq!(
+            get(format!("/content/{sha256}"), None),
+            (200, b"shared mesh".to_vec())
+        );
+        assert_eq!(
+            get(format!("/content/{sha256}"), Some("bytes=7-")),
+            (206, b"mesh".to_vec())
+        );
+        assert_eq!(get("/content/notes.txt".to_string(), None).0, 404);
+        assert_eq!(get("/content/../notes.txt".to_string(), None).0, 404);
+    }
+}
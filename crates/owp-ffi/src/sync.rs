@@ -0,0 +1,299 @@
+//! Keeps a local asset cache in step with a world's sync manifest. Blobs are stored by sha256,
+//! so only missing or changed ones are downloaded, and an interrupted download resumes from the
+//! bytes already on disk.
+
+use anyhow::{Context, Result};
+use owp_protocol::{SyncEntry, SyncManifestV1};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::admin;
+use crate::conn::runtime;
+
+/// What one sync did.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct SyncReport {
+    /// Blobs that were already cached.
+    pub cached: usize,
+    pub downloaded: usize,
+    /// Downloads that continued a partial file.
+    pub resumed: usize,
+    /// Downloads served by another player instead of the host.
+    pub from_peers: usize,
+    /// Bytes transferred.
+    pub bytes: u64,
+}
+
+fn file_sha256(path: &Path) -> Result<String> {
+    let bytes = fs::read(path).with_context(|| format!("read {path:?}"))?;
+    Ok(hex::encode(Sha256::digest(&bytes)))
+}
+
+/// Absolute URIs are used as they are; the rest hang off the admin API.
+fn resolve(base_url: &str, uri: &str) -> String {
+    if uri.starts_with("http://") || uri.starts_with("https://") {
+        uri.to_string()
+    } else {
+        format!(
+            "{}/{}",
+            base_url.trim_end_matches('/'),
+            uri.trim_start_matches('/')
+        )
+    }
+}
+
+/// Download `entry` into `path` by way of `<path>.part`, continuing a partial file when the
+/// server honors `Range`. Returns whether it resumed and how many bytes came over the wire.
+async fn fetch(
+    url: &str,
+    token: Option<&str>,
+    entry: &SyncEntry,
+    path: &Path,
+) -> Result<(bool, u64)> {
+    let part = path.with_extension("part");
+    let mut have = fs::metadata(&part).map_or(0, |m| m.len());
+    if have >= entry.size {
+        if have == entry.size && file_sha256(&part)? == entry.sha256 {
+            fs::rename(&part, path).with_context(|| format!("rename {part:?}"))?;
+            return Ok((true, 0));
+        }
+        fs::remove_file(&part).with_context(|| format!("remove {part:?}"))?;
+        have = 0;
+    }
+
+    let mut req = admin::client()?.get(url);
+    if let Some(token) = token {
+        req = req.bearer_auth(token);
+    }
+    if have > 0 {
+        req = req.header(reqwest::header::RANGE, format!("bytes={have}-"));
+    }
+    let mut resp = req.send().await.with_context(|| format!("GET {url}"))?;
+    let status = resp.status();
+    let resumed = status == reqwest::StatusCode::PARTIAL_CONTENT && have > 0;
+    if !status.is_success() {
+        anyhow::bail!("GET {url}: HTTP {status}");
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resumed)
+        .truncate(!resumed)
+        .open(&part)
+        .with_context(|| format!("open {part:?}"))?;
+    let mut bytes = 0;
+    while let Some(chunk) = resp.chunk().await.with_context(|| format!("GET {url}"))? {
+        file.write_all(&chunk)
+            .with_context(|| format!("write {part:?}"))?;
+        bytes += chunk.len() as u64;
+    }
+    file.sync_all().with_context(|| format!("sync {part:?}"))?;
+    drop(file);
+
+    if file_sha256(&part)? != entry.sha256 {
+        fs::remove_file(&part).with_context(|| format!("remove {part:?}"))?;
+        anyhow::bail!("{url} does not match sha256 {}", entry.sha256);
+    }
+    fs::rename(&part, path).with_context(|| format!("rename {part:?}"))?;
+    Ok((resumed, bytes))
+}
+
+/// Bring `cache_dir` up to date with `GET /worlds/<world_id>/sync`, most urgent entries first.
+/// Blobs are named by sha256; files that aren't in the manifest are left alone, since a cache
+/// may be shared between worlds. `peers` maps a sha256 to `host:port`s from `PeerList`, tried
+/// before the host; anything they send that fails the hash check is fetched again from the host.
+pub fn sync(
+    base_url: &str,
+    token: Option<&str>,
+    world_id: Uuid,
+    cache_dir: &Path,
+    peers: &HashMap<String, Vec<String>>,
+    timeout: Option<Duration>,
+) -> Result<SyncReport> {
+    let path = format!("/worlds/{world_id}/sync");
+    let (status, body) = admin::request(base_url, token, "GET", &path, None, timeout)?;
+    if status != 200 {
+        anyhow::bail!("sync manifest: HTTP {status}: {body}");
+    }
+    let manifest: SyncManifestV1 = serde_json::from_str(&body).context("parse sync manifest")?;
+    fs::create_dir_all(cache_dir).with_context(|| format!("create {cache_dir:?}"))?;
+
+    let mut entries = manifest.entries;
+    entries.sort_by_key(|e| e.priority);
+    let mut report = SyncReport::default();
+    for entry in &entries {
+        if entry.sha256.len() != 64 || !entry.sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+            anyhow::bail!("sync entry {:?} has a malformed sha256", entry.uri);
+        }
+        let path: PathBuf = cache_dir.join(entry.sha256.to_ascii_lowercase());
+        if fs::metadata(&path).is_ok_and(|m| m.len() == entry.size) {
+            report.cached += 1;
+            continue;
+        }
+        let url = resolve(base_url, &entry.uri);
+        // The admin token only goes to the admin API, not to a CDN or a peer.
+        let auth = token.filter(|_| url.starts_with(base_url.trim_end_matches('/')));
+        let from_peers = peers
+            .get(&entry.sha256)
+            .into_iter()
+            .flatten()
+            .map(|p| (format!("http://{p}/content/{}", entry.sha256), None, true));
+        let mut result = Err(anyhow::anyhow!("no source for {}", entry.sha256));
+        for (url, auth, peer) in from_peers.chain([(url, auth, false)]) {
+            result = runtime()
+                .block_on(fetch(&url, auth, entry, &path))
+                .map(|r| (r, peer));
+            if result.is_ok() {
+                break;
+            }
+        }
+        let ((resumed, bytes), peer) = result?;
+        report.from_peers += usize::from(peer);
+        report.downloaded += 1;
+        report.resumed += usize::from(resumed);
+        report.bytes += bytes;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn entry(bytes: &[u8], priority: u8) -> SyncEntry {
+        let sha256 = hex::encode(Sha256::digest(bytes));
+        SyncEntry {
+            uri: format!("/content/{sha256}"),
+            sha256,
+            size: bytes.len() as u64,
+            priority,
+        }
+    }
+
+    #[test]
+    fn downloads_only_what_is_missing_and_resumes_partials() {
+        let world_id = Uuid::new_v4();
+        let blobs: Vec<&[u8]> = vec![b"cached already", b"half way there", b"brand new"];
+        let manifest = SyncManifestV1 {
+            world_id,
+            entries: vec![entry(blobs[2], 1), entry(blobs[0], 0), entry(blobs[1], 0)],
+        };
+        let routes: Vec<(String, Vec<u8>)> = [(
+            format!("/worlds/{world_id}/sync"),
+            serde_json::to_vec(&manifest).expect("json"),
+        )]
+        .into_iter()
+        .chain(
+            manifest
+                .entries
+                .iter()
+                .zip([blobs[2], blobs[0], blobs[1]])
+                .map(|(e, b)| (e.uri.clone(), b.to_vec())),
+        )
+        .collect();
+
+        // Just enough HTTP/1.1 to answer GETs, with `Range: bytes=<start>-`.
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let listener = runtime()
+            .block_on(tokio::net::TcpListener::bind("127.0.0.1:0"))
+            .expect("bind");
+        let base_url = format!("http://{}", listener.local_addr().expect("addr"));
+        let log = seen.clone();
+        runtime().spawn(async move {
+            loop {
+                let Ok((mut s, _)) = listener.accept().await else { return };
+                let mut req = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !req.windows(4).any(|w| w == b"\r\n\r\n") {
+                    let n = s.read(&mut buf).await.expect("read");
+                    if n == 0 {
+                        break;
+                    }
+                    req.extend_from_slice(&buf[..n]);
+                }
+                let req = String::from_utf8_lossy(&req).to_ascii_lowercase();
+                let path = req.split_whitespace().nth(1).unwrap_or("").to_string();
+                let start: usize = req
+                    .lines()
+                    .find_map(|l| l.strip_prefix("range: bytes="))
+                    .and_then(|r| r.trim().trim_end_matches('-').parse().ok())
+                    .unwrap_or(0);
+                log.lock().unwrap().push((path.clone(), start));
+                let reply = match routes.iter().find(|(p, _)| p.to_ascii_lowercase() == path) {
+                    Some((_, body)) if start > 0 => {
+                        let head = format!(
+                            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {start}-{}/{}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                            body.len() - 1,
+                            body.len(),
+                            body.len() - start
+                        );
+                        [head.as_bytes(), &body[start..]].concat()
+                    }
+                    Some((_, body)) => {
+                        let head = format!(
+                            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                            body.len()
+                        );
+                        [head.as_bytes(), body].concat()
+                    }
+                    None => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec(),
+                };
+                s.write_all(&reply).await.expect("write");
+            }
+        });
+
+        let cache = tempfile::tempdir().expect("tempdir");
+        let name = |b: &[u8]| hex::encode(Sha256::digest(b));
+        fs::write(cache.path().join(name(blobs[0])), blobs[0]).expect("cached");
+        fs::write(
+            cache.path().join(format!("{}.part", name(blobs[1]))),
+            &blobs[1][..5],
+        )
+        .expect("partial");
+
+        // A peer that can't be reached just falls back to the host.
+        let peers = HashMap::from([(name(blobs[2]), vec!["127.0.0.1:1".to_string()])]);
+        let report =
+            sync(&base_url, Some("t"), world_id, cache.path(), &peers, None).expect("sync");
+        assert_eq!(
+            report,
+            SyncReport {
+                cached: 1,
+                downloaded: 2,
+                resumed: 1,
+                from_peers: 0,
+                bytes: (blobs[1].len() - 5 + blobs[2].len()) as u64,
+            }
+        );
+        for b in &blobs {
+            assert_eq!(fs::read(cache.path().join(name(b))).expect("blob"), *b);
+        }
+        let seen = seen.lock().unwrap().clone();
+        assert!(seen.contains(&(format!("/content/{}", name(blobs[1])), 5)));
+        // Priority 0 before priority 1.
+        assert_eq!(
+            seen.last().map(|(p, _)| p.clone()),
+            Some(format!("/content/{}", name(blobs[2])))
+        );
+
+        // A second run has nothing to do.
+        let again = sync(
+            &base_url,
+            None,
+            world_id,
+            cache.path(),
+            &HashMap::new(),
+            None,
+        )
+        .expect("sync");
+        assert_eq!((again.cached, again.downloaded), (3, 0));
+    }
+}
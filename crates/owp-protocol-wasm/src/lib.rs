@@ -0,0 +1,99 @@
+//! wasm-bindgen wrapper over the sans-io half of `owp-protocol`, for browser viewers that
+//! receive OWP frames over a WebSocket. Messages cross into JavaScript as the protocol's JSON
+//! strings (`JSON.parse` them); failures are thrown as `Error`s.
+
+use owp_protocol::wire::{self, WireError};
+use owp_protocol::{Message, OWP_PROTOCOL_VERSION};
+use wasm_bindgen::prelude::*;
+
+/// Protocol version this build speaks.
+#[wasm_bindgen(js_name = protocolVersion)]
+pub fn protocol_version() -> String {
+    OWP_PROTOCOL_VERSION.to_string()
+}
+
+/// Reassembles messages from WebSocket payloads, which need not line up with frames.
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct FrameDecoder(wire::FrameDecoder);
+
+#[wasm_bindgen]
+impl FrameDecoder {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> FrameDecoder {
+        FrameDecoder::default()
+    }
+
+    /// Feed received bytes; returns a JSON array of the messages they completed (often `[]`).
+    /// After a throw the stream is out of sync and the decoder should be discarded.
+    pub fn push(&mut self, bytes: &[u8]) -> Result<String, JsError> {
+        Ok(self.push_inner(bytes)?)
+    }
+
+    /// Bytes held back waiting for the rest of a frame.
+    pub fn buffered(&self) -> usize {
+        self.0.buffered()
+    }
+}
+
+impl FrameDecoder {
+    fn push_inner(&mut self, bytes: &[u8]) -> Result<String, WireError> {
+        self.0.push(bytes);
+        let mut out = vec![];
+        while let Some(msg) = self.0.next_message()? {
+            out.push(msg);
+        }
+        Ok(serde_json::to_string(&out)?)
+    }
+}
+
+/// Frame one message, given as its JSON, for sending.
+#[wasm_bindgen(js_name = encodeFrame)]
+pub fn encode_frame(message_json: &str) -> Result<Vec<u8>, JsError> {
+    Ok(encode_inner(message_json)?)
+}
+
+/// The message in one complete frame as JSON, or `undefined` if `bytes` is a partial frame.
+#[wasm_bindgen(js_name = decodeFrame)]
+pub fn decode_frame(bytes: &[u8]) -> Result<Option<String>, JsError> {
+    Ok(decode_inner(bytes)?)
+}
+
+fn encode_inner(message_json: &str) -> Result<Vec<u8>, WireError> {
+    let msg: Message = serde_json::from_str(message_json)?;
+    wire::encode_frame(&msg)
+}
+
+fn decode_inner(bytes: &[u8]) -> Result<Option<String>, WireError> {
+    match wire::decode_frame(bytes)? {
+        Some((msg, _)) => Ok(Some(serde_json::to_string(&msg)?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_round_trip_through_json() {
+        let json = r#"{"type":"emote","id":"wave","duration_ms":1000}"#;
+        let frame = encode_inner(json).expect("encode");
+        assert!(decode_inner(&frame[..5]).expect("partial").is_none());
+        let decoded = decode_inner(&frame).expect("decode").expect("complete");
+        assert!(decoded.contains(r#""id":"wave""#));
+
+        let mut decoder = FrameDecoder::new();
+        let mut bytes = frame.clone();
+        bytes.extend(&frame);
+        assert_eq!(
+            decoder.push_inner(&bytes[..frame.len() + 2]).expect("push"),
+            format!("[{decoded}]")
+        );
+        assert_eq!(decoder.buffered(), 2);
+        let rest = decoder.push_inner(&bytes[frame.len() + 2..]).expect("push");
+        assert_eq!(rest, format!("[{decoded}]"));
+
+        assert!(encode_inner(r#"{"type":"nope"}"#).is_err());
+    }
+}
@@ -0,0 +1,174 @@
+//! Wire codec benchmarks: `cargo xtask bench` (or `cargo bench -p owp-protocol`).
+//!
+//! - `codec`: encode and decode one frame per message, JSON against MessagePack;
+//! - `compression`: a world snapshot through a protected encoder, plain and zstd;
+//! - `broadcast`: one message fanned out to N connections, each with its own outbox and encoder
+//!   the way the game server relays it;
+//! - `chunk`: a chunk's objects to and from JSON, as chunk files and full deltas store them.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use owp_protocol::protection::{Direction, FrameEncoder, FrameSealer};
+use owp_protocol::{
+    wire, ChatBroadcast, ChatChannel, ChunkCoord, ChunkSnapshot, Compression, FrameProtection,
+    Message, PlayerPosition, PlayerSnapshot, WireFormat, WorldObjectV1, WorldSnapshot,
+};
+use std::sync::mpsc;
+use uuid::Uuid;
+
+fn object(i: usize) -> WorldObjectV1 {
+    let f = i as f32;
+    WorldObjectV1 {
+        id: format!("obj_{i:05}"),
+        kind: ["tree_pine", "rock_large", "bush"][i % 3].to_string(),
+        position: [f * 0.7 % 32.0, 0.0, f * 1.3 % 32.0],
+        rotation: [0.0, f * 17.0 % 360.0, 0.0],
+        scale: [1.0, 1.0 + (i % 4) as f32 * 0.1, 1.0],
+    }
+}
+
+fn chunk(objects: usize) -> ChunkSnapshot {
+    ChunkSnapshot {
+        chunk: ChunkCoord { x: 3, z: -2 },
+        version: 42,
+        objects: (0..objects).map(object).collect(),
+    }
+}
+
+fn snapshot() -> Message {
+    Message::WorldSnapshot(WorldSnapshot {
+        world_id: Uuid::new_v4(),
+        name: "Bench".to_string(),
+        chunks: (0..8).map(|_| chunk(64)).collect(),
+        more_chunks: vec![],
+        players: (0..16)
+            .map(|i| PlayerSnapshot {
+                player_id: Uuid::new_v4(),
+                position: Some([i as f32, 0.0, -(i as f32)]),
+                avatar: None,
+            })
+            .collect(),
+    })
+}
+
+fn samples() -> Vec<(&'static str, Message)> {
+    vec![
+        (
+            "position",
+            Message::PlayerPosition(PlayerPosition {
+                position: [12.5, 3.0, -40.25],
+            }),
+        ),
+        (
+            "chat",
+            Message::ChatBroadcast(ChatBroadcast {
+                channel: ChatChannel::World,
+                from: Some(Uuid::new_v4()),
+                text: "anyone up for the northern ridge?".to_string(),
+                sent_at: time::OffsetDateTime::UNIX_EPOCH,
+            }),
+        ),
+        ("snapshot", snapshot()),
+    ]
+}
+
+fn encoder(format: WireFormat, compression: Option<Compression>) -> FrameEncoder {
+    let sealer = FrameSealer::new(FrameProtection::Crc32c, None, Direction::ToClient);
+    FrameEncoder::new(sealer)
+        .with_format(format)
+        .with_compression(compression)
+}
+
+fn codec(c: &mut Criterion) {
+    let mut group = c.benchmark_group("codec");
+    for (name, msg) in samples() {
+        for format in WireFormat::ALL {
+            let frame = wire::encode_frame_as(&msg, format).expect("encode");
+            group.throughput(Throughput::Bytes(frame.len() as u64));
+            group.bench_with_input(
+                BenchmarkId::new(format!("encode/{}", format.as_str()), name),
+                &msg,
+                |b, msg| b.iter(|| wire::encode_frame_as(black_box(msg), format).expect("encode")),
+            );
+            group.bench_with_input(
+                BenchmarkId::new(format!("decode/{}", format.as_str()), name),
+                &frame,
+                |b, frame| {
+                    b.iter(|| wire::decode_frame_as(black_box(frame), format).expect("decode"))
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+fn compression(c: &mut Criterion) {
+    let mut group = c.benchmark_group("compression");
+    let msg = snapshot();
+    for format in WireFormat::ALL {
+        let offers = std::iter::once(None).chain(Compression::ALL.iter().copied().map(Some));
+        for compression in offers {
+            let id = compression.map_or("none", |c| c.as_str());
+            group.bench_function(BenchmarkId::new(format.as_str(), id), |b| {
+                let mut enc = encoder(format, compression);
+                b.iter(|| enc.encode(black_box(&msg)).expect("encode"))
+            });
+        }
+    }
+    group.finish();
+}
+
+fn broadcast(c: &mut Criterion) {
+    let mut group = c.benchmark_group("broadcast");
+    let msg = Message::PlayerPosition(PlayerPosition {
+        position: [12.5, 3.0, -40.25],
+    });
+    for n in [10, 100, 1000] {
+        let mut conns: Vec<_> = (0..n)
+            .map(|_| {
+                let (tx, rx) = mpsc::sync_channel::<Message>(16);
+                (tx, rx, encoder(WireFormat::Msgpack, None))
+            })
+            .collect();
+        group.throughput(Throughput::Elements(n as u64));
+        group.bench_function(BenchmarkId::from_parameter(n), |b| {
+            b.iter(|| {
+                for (tx, _, _) in &conns {
+                    tx.try_send(msg.clone()).expect("outbox");
+                }
+                let mut bytes = 0;
+                for (_, rx, enc) in &mut conns {
+                    while let Ok(m) = rx.try_recv() {
+                        bytes += enc.encode(&m).expect("encode").len();
+                    }
+                }
+                bytes
+            })
+        });
+    }
+    group.finish();
+}
+
+fn chunk_json(c: &mut Criterion) {
+    let mut group = c.benchmark_group("chunk");
+    for objects in [16, 256, 2048] {
+        let chunk = chunk(objects);
+        let json = serde_json::to_vec(&chunk).expect("json");
+        group.throughput(Throughput::Elements(objects as u64));
+        group.bench_with_input(
+            BenchmarkId::new("serialize", objects),
+            &chunk,
+            |b, chunk| b.iter(|| serde_json::to_vec(black_box(chunk)).expect("json")),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("deserialize", objects),
+            &json,
+            |b, json| {
+                b.iter(|| serde_json::from_slice::<ChunkSnapshot>(black_box(json)).expect("json"))
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, codec, compression, broadcast, chunk_json);
+criterion_main!(benches);
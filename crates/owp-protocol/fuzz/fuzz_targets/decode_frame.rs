@@ -0,0 +1,21 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use owp_protocol::wire::{self, FrameDecoder};
+
+// Whatever arrives, decoding must fail cleanly, and anything it accepts must re-encode to a
+// frame that decodes again.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(Some((msg, used))) = wire::decode_frame(data) {
+        assert!(used <= data.len());
+        let frame = wire::encode_frame(&msg).expect("re-encode");
+        assert!(matches!(wire::decode_frame(&frame), Ok(Some(_))));
+    }
+
+    // The same bytes in uneven pieces, as a socket would deliver them.
+    let mut decoder = FrameDecoder::default();
+    for piece in data.chunks(data.first().map_or(1, |b| *b as usize + 1)) {
+        decoder.push(piece);
+        while let Ok(Some(_)) = decoder.next_message() {}
+    }
+});
@@ -0,0 +1,38 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use owp_protocol::session::{Event, ProtocolStateMachine};
+use owp_protocol::{Welcome, OWP_PROTOCOL_VERSION};
+use uuid::Uuid;
+
+// Arbitrary client bytes against the server side: handshake, then an open session.
+fuzz_target!(|data: &[u8]| {
+    let mut server = ProtocolStateMachine::server(Uuid::nil());
+    server.receive(data);
+    while let Ok(Some(event)) = server.poll_event() {
+        if let Event::Hello(hello) = event {
+            server
+                .accept(Welcome {
+                    protocol_version: OWP_PROTOCOL_VERSION.to_string(),
+                    request_id: hello.request_id,
+                    world_id: Uuid::nil(),
+                    token_mint: None,
+                    motd: None,
+                    capabilities: vec![],
+                    session_token: None,
+                    resumed: false,
+                    prefetch: vec![],
+                    player_id: None,
+                    party: None,
+                    player_count: None,
+                    frame_protection: None,
+                    wire_format: None,
+                    compression: None,
+                    attestation: None,
+                    wallet_verified: None,
+                })
+                .expect("accept after hello");
+        }
+    }
+    let _ = server.take_outgoing();
+});
@@ -0,0 +1,324 @@
+//! Hard limits for avatars. Anything persisted or sent to other players goes through
+//! [`validate_avatar`]; clients can run the same check before submitting.
+
+use crate::{AvatarMeshPartV1, AvatarMeshV1, AvatarPartV1, AvatarSpecV1};
+
+pub const AVATAR_VERSION: &str = "v1";
+pub const MAX_NAME_CHARS: usize = 32;
+pub const MAX_TAGS: usize = 16;
+pub const MAX_TAG_CHARS: usize = 32;
+pub const MAX_PARTS: usize = 48;
+pub const MAX_PART_ID_CHARS: usize = 64;
+pub const MIN_HEIGHT: f32 = 0.5;
+pub const MAX_HEIGHT: f32 = 2.0;
+/// Per-axis bound for part scale.
+pub const MIN_PART_SCALE: f32 = 0.01;
+pub const MAX_PART_SCALE: f32 = 10.0;
+/// Parts may sit at most this far (meters, per axis) from their attachment point.
+pub const MAX_PART_OFFSET: f32 = 5.0;
+pub const MAX_EMISSION_STRENGTH: f32 = 10.0;
+pub const MAX_MESH_PARTS: usize = 8;
+pub const MAX_MESH_URI_CHARS: usize = 512;
+/// Ceiling for a single mesh blob (whole avatar or one part).
+pub const MAX_MESH_BYTES: usize = 8 * 1024 * 1024;
+/// Triangle budget for imported meshes, so one avatar can't stall every client rendering it.
+pub const MAX_MESH_TRIANGLES: usize = 100_000;
+
+pub const PART_ATTACH_POINTS: &[&str] = &["body", "head"];
+pub const PART_PRIMITIVES: &[&str] = &["sphere", "capsule", "cube", "cylinder"];
+pub const MESH_FORMATS: &[&str] = &["stl", "gltf", "glb"];
+pub const MESH_MATERIALS: &[&str] = &["primary", "secondary", "emissive"];
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum AvatarError {
+    #[error("unsupported avatar version {0:?}")]
+    Version(String),
+    #[error("name must be 1-{MAX_NAME_CHARS} characters")]
+    Name,
+    #[error("{field} must be a color like \"#RRGGBB\", got {value:?}")]
+    Color { field: String, value: String },
+    #[error("height must be between {MIN_HEIGHT} and {MAX_HEIGHT}")]
+    Height,
+    #[error("at most {MAX_TAGS} tags of up to {MAX_TAG_CHARS} characters")]
+    Tags,
+    #[error("at most {MAX_PARTS} parts")]
+    TooManyParts,
+    #[error("part {index}: {reason}")]
+    Part { index: usize, reason: String },
+    #[error("mesh: {0}")]
+    Mesh(String),
+    #[error("mesh is {size} bytes; the limit is {MAX_MESH_BYTES}")]
+    MeshTooLarge { size: usize },
+}
+
+/// Normalized `#RRGGBB` (uppercase), if `s` is one.
+fn parse_color(s: &str) -> Option<String> {
+    let s = s.trim();
+    let hex = s.strip_prefix('#')?;
+    (hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+        .then(|| format!("#{}", hex.to_ascii_uppercase()))
+}
+
+fn color(field: &str, value: &str) -> Result<String, AvatarError> {
+    parse_color(value).ok_or_else(|| AvatarError::Color {
+        field: field.to_string(),
+        value: value.to_string(),
+    })
+}
+
+fn is_sha256(s: &str) -> bool {
+    s.len() == 64 && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn validate_part(index: usize, p: &AvatarPartV1) -> Result<AvatarPartV1, AvatarError> {
+    let fail = |reason: String| AvatarError::Part { index, reason };
+    let id = p.id.trim();
+    if id.is_empty() || id.chars().count() > MAX_PART_ID_CHARS {
+        return Err(fail(format!("id must be 1-{MAX_PART_ID_CHARS} characters")));
+    }
+    if !PART_ATTACH_POINTS.contains(&p.attach.as_str()) {
+        return Err(fail(format!(
+            "attach must be one of {PART_ATTACH_POINTS:?}"
+        )));
+    }
+    if !PART_PRIMITIVES.contains(&p.primitive.as_str()) {
+        return Err(fail(format!(
+            "primitive must be one of {PART_PRIMITIVES:?}"
+        )));
+    }
+    if !p.position.iter().chain(&p.rotation).all(|v| v.is_finite()) {
+        return Err(fail("position and rotation must be finite".to_string()));
+    }
+    if p.position.iter().any(|v| v.abs() > MAX_PART_OFFSET) {
+        return Err(fail(format!(
+            "position must be within {MAX_PART_OFFSET}m of the attachment point"
+        )));
+    }
+    if !p
+        .scale
+        .iter()
+        .all(|v| (MIN_PART_SCALE..=MAX_PART_SCALE).contains(v))
+    {
+        return Err(fail(format!(
+            "scale must be between {MIN_PART_SCALE} and {MAX_PART_SCALE} on every axis"
+        )));
+    }
+    // An explicit zero strength turns emission off entirely.
+    let emission_off = p.emission_strength == Some(0.0);
+    let emission_strength = match p.emission_strength {
+        Some(s) if !s.is_finite() || s < 0.0 => {
+            return Err(fail("emission_strength must be >= 0".to_string()))
+        }
+        Some(s) if s > MAX_EMISSION_STRENGTH => {
+            return Err(fail(format!(
+                "emission_strength is capped at {MAX_EMISSION_STRENGTH}"
+            )))
+        }
+        _ if emission_off => None,
+        other => other,
+    };
+    let emission_color = match p.emission_color.as_deref().map(str::trim) {
+        None | Some("") => None,
+        Some(_) if emission_off => None,
+        Some(c) => Some(color(&format!("parts[{index}].emission_color"), c)?),
+    };
+    Ok(AvatarPartV1 {
+        id: id.to_string(),
+        attach: p.attach.clone(),
+        primitive: p.primitive.clone(),
+        position: p.position,
+        rotation: p.rotation,
+        scale: p.scale,
+        color: color(&format!("parts[{index}].color"), &p.color)?,
+        emission_color,
+        emission_strength,
+    })
+}
+
+fn validate_mesh_ref(
+    what: &str,
+    uri: &str,
+    sha256: Option<&str>,
+) -> Result<Option<String>, AvatarError> {
+    if uri.trim().is_empty() || uri.len() > MAX_MESH_URI_CHARS {
+        return Err(AvatarError::Mesh(format!(
+            "{what} uri must be 1-{MAX_MESH_URI_CHARS} characters"
+        )));
+    }
+    match sha256 {
+        None => Ok(None),
+        Some(h) if is_sha256(h) => Ok(Some(h.to_ascii_lowercase())),
+        Some(_) => Err(AvatarError::Mesh(format!(
+            "{what} sha256 must be 64 hex characters"
+        ))),
+    }
+}
+
+fn validate_mesh(m: &AvatarMeshV1) -> Result<AvatarMeshV1, AvatarError> {
+    let format = m.format.trim().to_ascii_lowercase();
+    if !MESH_FORMATS.contains(&format.as_str()) {
+        return Err(AvatarError::Mesh(format!(
+            "format must be one of {MESH_FORMATS:?}"
+        )));
+    }
+    let sha256 = validate_mesh_ref("mesh", &m.uri, m.sha256.as_deref())?;
+    if m.parts.len() > MAX_MESH_PARTS {
+        return Err(AvatarError::Mesh(format!("at most {MAX_MESH_PARTS} parts")));
+    }
+    let mut parts = Vec::with_capacity(m.parts.len());
+    for p in &m.parts {
+        let id = p.id.trim();
+        if id.is_empty() || id.chars().count() > MAX_PART_ID_CHARS {
+            return Err(AvatarError::Mesh(format!(
+                "part id must be 1-{MAX_PART_ID_CHARS} characters"
+            )));
+        }
+        let material = match p.material.as_deref() {
+            None => None,
+            Some(mat) if MESH_MATERIALS.contains(&mat) => Some(mat.to_string()),
+            Some(mat) => {
+                return Err(AvatarError::Mesh(format!(
+                    "part {id:?} material {mat:?} is not one of {MESH_MATERIALS:?}"
+                )))
+            }
+        };
+        parts.push(AvatarMeshPartV1 {
+            id: id.to_string(),
+            sha256: validate_mesh_ref(&format!("part {id:?}"), &p.uri, p.sha256.as_deref())?,
+            uri: p.uri.clone(),
+            material,
+        });
+    }
+    Ok(AvatarMeshV1 {
+        format,
+        uri: m.uri.clone(),
+        sha256,
+        parts,
+    })
+}
+
+/// Check an avatar against the hard limits and return its sanitized form: trimmed name, colors
+/// as uppercase `#RRGGBB`, tags deduplicated, zero-strength emission dropped. Unknown fields are
+/// already gone once the spec is deserialized into [`AvatarSpecV1`].
+pub fn validate_avatar(a: &AvatarSpecV1) -> Result<AvatarSpecV1, AvatarError> {
+    if a.version != AVATAR_VERSION {
+        return Err(AvatarError::Version(a.version.clone()));
+    }
+    let name = a.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_CHARS {
+        return Err(AvatarError::Name);
+    }
+    if !(MIN_HEIGHT..=MAX_HEIGHT).contains(&a.height) {
+        return Err(AvatarError::Height);
+    }
+
+    let mut tags: Vec<String> = Vec::new();
+    for t in &a.tags {
+        let t = t.trim();
+        if t.is_empty() || tags.iter().any(|x| x.eq_ignore_ascii_case(t)) {
+            continue;
+        }
+        if t.chars().count() > MAX_TAG_CHARS {
+            return Err(AvatarError::Tags);
+        }
+        tags.push(t.to_string());
+    }
+    if tags.len() > MAX_TAGS {
+        return Err(AvatarError::Tags);
+    }
+
+    if a.parts.len() > MAX_PARTS {
+        return Err(AvatarError::TooManyParts);
+    }
+    let parts = a
+        .parts
+        .iter()
+        .enumerate()
+        .map(|(i, p)| validate_part(i, p))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(AvatarSpecV1 {
+        version: AVATAR_VERSION.to_string(),
+        name: name.to_string(),
+        primary_color: color("primary_color", &a.primary_color)?,
+        secondary_color: color("secondary_color", &a.secondary_color)?,
+        height: a.height,
+        tags,
+        parts,
+        mesh: a.mesh.as_ref().map(validate_mesh).transpose()?,
+    })
+}
+
+/// Size ceiling for mesh blobs, checked wherever mesh bytes are stored.
+pub fn check_mesh_size(size: usize) -> Result<(), AvatarError> {
+    if size > MAX_MESH_BYTES {
+        return Err(AvatarError::MeshTooLarge { size });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn avatar() -> AvatarSpecV1 {
+        serde_json::from_value(serde_json::json!({
+            "version": "v1",
+            "name": "  Rook ",
+            "primary_color": "#00d1ff",
+            "secondary_color": "#FFFFFF",
+            "height": 1.2,
+            "tags": ["knight", "Knight", " "],
+            "parts": [{
+                "id": "visor", "attach": "head", "primitive": "cube",
+                "position": [0.0, 0.1, 0.2], "rotation": [0.0, 0.0, 0.0], "scale": [0.3, 0.1, 0.1],
+                "color": "#222222", "emission_color": "#ff0000", "emission_strength": 0.0
+            }],
+            "unknown_field": "dropped"
+        }))
+        .expect("parse")
+    }
+
+    #[test]
+    fn sanitizes_and_enforces_limits() {
+        let clean = validate_avatar(&avatar()).expect("valid");
+        assert_eq!(clean.name, "Rook");
+        assert_eq!(clean.primary_color, "#00D1FF");
+        assert_eq!(clean.tags, ["knight"]);
+        assert_eq!(clean.parts[0].emission_strength, None);
+        assert_eq!(clean.parts[0].emission_color, None);
+        // Sanitizing is idempotent.
+        assert_eq!(
+            serde_json::to_value(validate_avatar(&clean).expect("valid")).ok(),
+            serde_json::to_value(&clean).ok()
+        );
+
+        let mut big = avatar();
+        big.parts[0].scale = [50.0, 1.0, 1.0];
+        assert!(matches!(
+            validate_avatar(&big),
+            Err(AvatarError::Part { index: 0, .. })
+        ));
+
+        let mut glowing = avatar();
+        glowing.parts[0].emission_strength = Some(100.0);
+        assert!(validate_avatar(&glowing).is_err());
+
+        let mut bad_color = avatar();
+        bad_color.secondary_color = "white".to_string();
+        assert!(matches!(
+            validate_avatar(&bad_color),
+            Err(AvatarError::Color { .. })
+        ));
+
+        let mut crowded = avatar();
+        crowded.parts = vec![crowded.parts[0].clone(); MAX_PARTS + 1];
+        assert_eq!(
+            validate_avatar(&crowded).err(),
+            Some(AvatarError::TooManyParts)
+        );
+
+        assert!(check_mesh_size(MAX_MESH_BYTES).is_ok());
+        assert!(check_mesh_size(MAX_MESH_BYTES + 1).is_err());
+    }
+}
@@ -0,0 +1,236 @@
+//! Capabilities: protocol features beyond the base handshake. A client lists the ones it
+//! understands in `hello.capabilities`; the server answers with the ones it grants in
+//! `welcome.capabilities` (see `negotiate`) and only pushes messages of granted features. On the
+//! wire each is a string such as `chat` or `wire_format:msgpack`; names this build doesn't know
+//! are kept as `Other`, so peers of different versions still understand each other.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+use crate::{Compression, Message, WireFormat};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Capability {
+    Handshake,
+    ChunkDelta,
+    SessionResume,
+    NetReport,
+    AvatarSubmit,
+    AvatarWardrobe,
+    AvatarAnnounce,
+    Emote,
+    Chat,
+    Party,
+    FriendPresence,
+    PathQuery,
+    FrameProtection,
+    WorldSnapshot,
+    WireFormat(WireFormat),
+    Compression(Compression),
+    Noise,
+    Datagrams,
+    Attestation,
+    PeerAssist,
+    AssetToken,
+    ClusterHandoff,
+    /// Several messages packed into one frame (`wire::Frame::Batch`).
+    Batch,
+    /// A capability this build doesn't know.
+    Other(String),
+}
+
+impl Capability {
+    pub fn parse(s: &str) -> Self {
+        match s {
+            "handshake" => Self::Handshake,
+            "chunk_delta" => Self::ChunkDelta,
+            "session_resume" => Self::SessionResume,
+            "net_report" => Self::NetReport,
+            "avatar_submit" => Self::AvatarSubmit,
+            "avatar_wardrobe" => Self::AvatarWardrobe,
+            "avatar_announce" => Self::AvatarAnnounce,
+            "emote" => Self::Emote,
+            "chat" => Self::Chat,
+            "party" => Self::Party,
+            "friend_presence" => Self::FriendPresence,
+            "path_query" => Self::PathQuery,
+            "frame_protection" => Self::FrameProtection,
+            "world_snapshot" => Self::WorldSnapshot,
+            "noise" => Self::Noise,
+            "datagrams" => Self::Datagrams,
+            "attestation" => Self::Attestation,
+            "peer_assist" => Self::PeerAssist,
+            "asset_token" => Self::AssetToken,
+            "cluster_handoff" => Self::ClusterHandoff,
+            "batch" => Self::Batch,
+            other => {
+                let format = other
+                    .strip_prefix("wire_format:")
+                    .and_then(|name| WireFormat::ALL.into_iter().find(|f| f.as_str() == name));
+                if let Some(format) = format {
+                    return Self::WireFormat(format);
+                }
+                let compression = other
+                    .strip_prefix("compression:")
+                    .and_then(|name| [Compression::Zstd].into_iter().find(|c| c.as_str() == name));
+                match compression {
+                    Some(compression) => Self::Compression(compression),
+                    None => Self::Other(other.to_string()),
+                }
+            }
+        }
+    }
+
+    /// Whether a client that sends no `hello.capabilities` is taken to understand this: true for
+    /// everything clients got before they could say what they support. Capabilities added from
+    /// now on are false, so their messages only go to clients that ask for them.
+    pub fn is_legacy(&self) -> bool {
+        !matches!(self, Self::Batch | Self::Other(_))
+    }
+
+    /// The capability a client must have been granted to receive `msg` without asking for it.
+    /// Replies to the client's own requests need none.
+    pub fn pushed_with(msg: &Message) -> Option<Capability> {
+        Some(match msg {
+            Message::WorldSnapshot(_) => Self::WorldSnapshot,
+            Message::AvatarAnnounce(_) => Self::AvatarAnnounce,
+            Message::Emote(_) => Self::Emote,
+            Message::ChatBroadcast(_) => Self::Chat,
+            Message::PartyInvited(_) | Message::PartyUpdate(_) | Message::PartyTravel(_) => {
+                Self::Party
+            }
+            Message::FriendPresence(_) => Self::FriendPresence,
+            Message::Handoff(_) => Self::ClusterHandoff,
+            Message::AssetToken(_) => Self::AssetToken,
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Handshake => "handshake",
+            Self::ChunkDelta => "chunk_delta",
+            Self::SessionResume => "session_resume",
+            Self::NetReport => "net_report",
+            Self::AvatarSubmit => "avatar_submit",
+            Self::AvatarWardrobe => "avatar_wardrobe",
+            Self::AvatarAnnounce => "avatar_announce",
+            Self::Emote => "emote",
+            Self::Chat => "chat",
+            Self::Party => "party",
+            Self::FriendPresence => "friend_presence",
+            Self::PathQuery => "path_query",
+            Self::FrameProtection => "frame_protection",
+            Self::WorldSnapshot => "world_snapshot",
+            Self::WireFormat(format) => return write!(f, "wire_format:{}", format.as_str()),
+            Self::Compression(c) => return write!(f, "compression:{}", c.as_str()),
+            Self::Noise => "noise",
+            Self::Datagrams => "datagrams",
+            Self::Attestation => "attestation",
+            Self::PeerAssist => "peer_assist",
+            Self::AssetToken => "asset_token",
+            Self::ClusterHandoff => "cluster_handoff",
+            Self::Batch => "batch",
+            Self::Other(name) => name,
+        };
+        f.write_str(name)
+    }
+}
+
+impl Serialize for Capability {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Capability {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Ok(Self::parse(&s))
+    }
+}
+
+/// What the server grants out of what it `supports`, given the client's `offered` list: the
+/// legacy set for a client that offered nothing, else what both sides have.
+pub fn negotiate(offered: &[Capability], supports: &[Capability]) -> Vec<Capability> {
+    supports
+        .iter()
+        .filter(|c| {
+            if offered.is_empty() {
+                c.is_legacy()
+            } else {
+                offered.contains(c)
+            }
+        })
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChatBroadcast, ChatChannel, PathResult};
+
+    #[test]
+    fn names_round_trip_and_unknown_ones_survive() {
+        for name in [
+            "chat",
+            "wire_format:msgpack",
+            "compression:zstd",
+            "cluster_handoff",
+            "hologram_calls",
+        ] {
+            let cap: Capability = serde_json::from_value(name.into()).expect("parse");
+            assert_eq!(serde_json::to_value(&cap).expect("json"), name);
+        }
+        assert_eq!(
+            Capability::parse("wire_format:msgpack"),
+            Capability::WireFormat(WireFormat::Msgpack)
+        );
+        assert_eq!(
+            Capability::parse("hologram_calls"),
+            Capability::Other("hologram_calls".to_string())
+        );
+    }
+
+    #[test]
+    fn clients_get_what_they_offered_or_the_legacy_set() {
+        let supports = vec![
+            Capability::Chat,
+            Capability::Party,
+            Capability::Other("hologram_calls".to_string()),
+        ];
+        assert_eq!(
+            negotiate(&[], &supports),
+            vec![Capability::Chat, Capability::Party]
+        );
+        let offered = [
+            Capability::Chat,
+            Capability::Other("hologram_calls".to_string()),
+            Capability::Noise,
+        ];
+        assert_eq!(
+            negotiate(&offered, &supports),
+            vec![
+                Capability::Chat,
+                Capability::Other("hologram_calls".to_string())
+            ]
+        );
+
+        let broadcast = Message::ChatBroadcast(ChatBroadcast {
+            channel: ChatChannel::World,
+            from: None,
+            text: "hi".to_string(),
+            sent_at: time::OffsetDateTime::UNIX_EPOCH,
+        });
+        assert_eq!(Capability::pushed_with(&broadcast), Some(Capability::Chat));
+        let reply = Message::PathResult(PathResult {
+            request_id: uuid::Uuid::nil(),
+            path: vec![],
+            error: None,
+        });
+        assert_eq!(Capability::pushed_with(&reply), None);
+    }
+}
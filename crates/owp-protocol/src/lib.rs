@@ -4,6 +4,27 @@ use uuid::Uuid;
 
 pub const OWP_PROTOCOL_VERSION: &str = "0.1";
 
+/// Whether a peer speaking `version` can talk to this build: same major version and, before
+/// 1.0, same minor version. Anything after `major.minor` is ignored.
+pub fn is_compatible_version(version: &str) -> bool {
+    let parse = |v: &str| -> Option<(u32, u32)> {
+        let mut parts = v.split('.');
+        Some((parts.next()?.parse().ok()?, parts.next()?.parse().ok()?))
+    };
+    match (parse(version), parse(OWP_PROTOCOL_VERSION)) {
+        (Some((major, minor)), Some((ours_major, ours_minor))) => {
+            major == ours_major && (major > 0 || minor == ours_minor)
+        }
+        _ => false,
+    }
+}
+
+pub mod avatar;
+pub mod capability;
+#[cfg(feature = "noise")]
+pub mod noise;
+pub mod protection;
+pub mod session;
 pub mod wire;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,12 +45,239 @@ pub struct WorldManifestV1 {
     pub world_authority_pubkey: Option<String>,
     pub ports: WorldPorts,
     pub token: Option<WorldTokenInfo>,
+    #[serde(default)]
+    pub simulation: WorldSimulationConfig,
+    #[serde(default)]
+    pub assets: WorldAssetsConfig,
+    #[serde(default)]
+    pub emotes: WorldEmotesConfig,
+    #[serde(default)]
+    pub bandwidth: WorldBandwidthConfig,
+    /// Hosting region advertised in directory listings.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    /// Short labels advertised in directory listings, e.g. "pvp" or "creative".
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// SHA-256 of the world's icon image, served from the content store.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon_sha256: Option<String>,
+    /// The world's current prefab bundle, a `PrefabBundleV1` in the content store.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefab_bundle: Option<PrefabBundleRef>,
+    /// What players pay on chain before `welcome`; free to enter when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entry_fee: Option<WorldEntryFee>,
+    /// House rules players must acknowledge (`rules_ack`) before `welcome`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rules: Option<WorldRules>,
+    /// TLS on the game port; plaintext TCP (and Noise) only when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<WorldTls>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrefabBundleRef {
+    /// Bumped whenever a rebuild changes the bundle.
+    pub version: u32,
+    pub sha256: String,
+    pub size: u64,
+}
+
+/// Meshes and material metadata for the object kinds a world uses, so clients can build prefabs
+/// at runtime instead of shipping each one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PrefabBundleV1 {
+    pub version: u32,
+    pub prefabs: Vec<PrefabV1>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PrefabV1 {
+    /// Matches `WorldObjectV1.kind`.
+    pub kind: String,
+    pub mesh: AssetRef,
+    /// Material metadata as the world author wrote it (colors, textures, shader hints).
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
+    pub materials: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorldSimulationConfig {
+    /// Keep ticking (NPCs, timers, economy) while no players are connected, and fast-forward
+    /// over the time the server was offline on startup.
+    #[serde(default)]
+    pub run_while_empty: bool,
+    #[serde(default = "default_tick_hz")]
+    pub tick_hz: u32,
+    /// Upper bound for startup catch-up, so a world left offline for months doesn't spin.
+    #[serde(default = "default_max_catchup_secs")]
+    pub max_catchup_secs: u64,
+}
+
+fn default_tick_hz() -> u32 {
+    10
+}
+
+fn default_max_catchup_secs() -> u64 {
+    24 * 60 * 60
+}
+
+impl Default for WorldSimulationConfig {
+    fn default() -> Self {
+        Self {
+            run_while_empty: false,
+            tick_hz: default_tick_hz(),
+            max_catchup_secs: default_max_catchup_secs(),
+        }
+    }
+}
+
+/// What clients are told to download before spawning (`Welcome.prefetch`).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WorldAssetsConfig {
+    /// Where clients fetch assets from (e.g. a CDN); URIs are relative (`assets/<path>`) without it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+    /// Paths under the world's `assets/` directory to prefetch. Empty means every file there.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub prefetch: Vec<String>,
+    /// Let players serve cached blobs to each other (`PeerAnnounce` / `PeerQuery`).
+    #[serde(default)]
+    pub peer_assist: bool,
+    /// Serve `assets/` only to players holding an `AssetToken` from the game server (or the
+    /// admin), so the files can't be fetched by anyone who learns their URLs.
+    #[serde(default)]
+    pub private: bool,
+}
+
+/// Limits on the bytes a host serves per calendar month (UTC), across game, relay and asset
+/// traffic. No limits by default.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorldBandwidthConfig {
+    /// Past this, new game connections and asset downloads are refused until the month ends.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monthly_cap_bytes: Option<u64>,
+    /// Past this, each connection's outgoing game traffic is paced to `throttle_bytes_per_sec`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub soft_cap_bytes: Option<u64>,
+    #[serde(default = "default_throttle_bytes_per_sec")]
+    pub throttle_bytes_per_sec: u64,
+}
+
+fn default_throttle_bytes_per_sec() -> u64 {
+    32 * 1024
+}
+
+impl Default for WorldBandwidthConfig {
+    fn default() -> Self {
+        Self {
+            monthly_cap_bytes: None,
+            soft_cap_bytes: None,
+            throttle_bytes_per_sec: default_throttle_bytes_per_sec(),
+        }
+    }
+}
+
+/// Price of entering a world, paid to `recipient` (the host's wallet or an escrow account it
+/// controls) and checked on chain before the handshake finishes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorldEntryFee {
+    /// Base58 wallet receiving the fee.
+    pub recipient: String,
+    /// In the token's base units (lamports for SOL).
+    pub amount: u64,
+    /// SPL mint (base58); SOL when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mint: Option<String>,
+    /// Decimals of `mint`, for the amount shown to wallets.
+    #[serde(default = "default_fee_decimals")]
+    pub decimals: u8,
+    /// How long one payment admits the paying wallet (if the player proves it holds it); 0 makes
+    /// every connection pay.
+    #[serde(default = "default_pass_hours")]
+    pub pass_hours: u32,
+}
+
+fn default_fee_decimals() -> u8 {
+    9
+}
+
+fn default_pass_hours() -> u32 {
+    24
+}
+
+/// A world's rules document. Players acknowledge the exact text by its hash, so changing the
+/// document asks everyone again.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorldRules {
+    /// SHA-256 (hex) of the document's bytes.
+    pub sha256: String,
+    /// Where to read it: a `/content/<sha256>` path on the world's admin API or any URL.
+    pub uri: String,
+}
+
+/// TLS on a world's game port. Without `cert_path` and `key_path` the server presents a
+/// self-signed certificate for the world authority key, which clients pin by `world_pubkey`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorldTls {
+    /// PEM certificate chain, absolute or relative to the world directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cert_path: Option<String>,
+    /// PEM private key for the certificate, absolute or relative to the world directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_path: Option<String>,
+    /// Refuse connections that don't start TLS; otherwise plaintext and Noise clients are still
+    /// served on the same port.
+    #[serde(default)]
+    pub required: bool,
+}
+
+/// Emotes players may use in this world; anything else is dropped by the server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorldEmotesConfig {
+    #[serde(default = "default_emote_catalog")]
+    pub catalog: Vec<EmoteDef>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmoteDef {
+    pub id: String,
+    /// Longer requests are shortened to this.
+    pub max_duration_ms: u32,
+}
+
+fn default_emote_catalog() -> Vec<EmoteDef> {
+    [
+        ("wave", 3_000),
+        ("dance", 30_000),
+        ("cheer", 3_000),
+        ("point", 2_000),
+        ("sit", 600_000),
+    ]
+    .into_iter()
+    .map(|(id, max_duration_ms)| EmoteDef {
+        id: id.to_string(),
+        max_duration_ms,
+    })
+    .collect()
+}
+
+impl Default for WorldEmotesConfig {
+    fn default() -> Self {
+        Self {
+            catalog: default_emote_catalog(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WorldPorts {
     pub game_port: u16,
     pub asset_port: Option<u16>,
+    /// UDP port to also serve the game over QUIC on; TCP only when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quic_port: Option<u16>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -43,6 +291,44 @@ pub struct WorldDirectoryEntry {
     pub world_pubkey: Option<String>,
     #[serde(default)]
     pub last_seen: Option<String>,
+    /// Set for entries read from static directory files (see `owp-discovery`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trust: Option<ListingTrust>,
+    /// Free-form hosting region (e.g. `eu-west`), used to group nearby worlds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    /// Round trip of the most recent successful liveness probe, when one was made.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rtt_ms: Option<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon_sha256: Option<String>,
+    /// Occupancy the world last published (on-chain heartbeat), without probing it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub player_count: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_players: Option<u32>,
+    /// Lamports the world locked in the registry to be listed; clients rank staked worlds above
+    /// free entries.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stake_lamports: Option<u64>,
+    /// Rules players must acknowledge to enter, so clients can show them before connecting.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rules: Option<WorldRules>,
+}
+
+/// How far a directory-file listing can be trusted. Clients should warn on anything but
+/// `verified`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ListingTrust {
+    /// Valid signature by a configured publisher key.
+    Verified,
+    /// Valid signature, but not by a key we were told to trust.
+    UnknownPublisher,
+    Unsigned,
+    InvalidSignature,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -119,11 +405,90 @@ pub struct AvatarPartV1 {
     pub emission_strength: Option<f32>,
 }
 
+/// Chunk coordinates on the XZ ground plane.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ChunkCoord {
+    pub x: i32,
+    pub z: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorldObjectV1 {
+    /// Stable identifier, unique within the world.
+    pub id: String,
+    /// Prefab/kind identifier, e.g. "tree_pine" or "rock_large".
+    pub kind: String,
+    /// World-space position (meters, Y-up).
+    pub position: [f32; 3],
+    /// Rotation in degrees (Euler XYZ).
+    #[serde(default)]
+    pub rotation: [f32; 3],
+    #[serde(default = "default_object_scale")]
+    pub scale: [f32; 3],
+}
+
+fn default_object_scale() -> [f32; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ChunkChangeV1 {
+    Upsert { object: WorldObjectV1 },
+    Remove { object_id: String },
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum Message {
     Hello(Hello),
     Welcome(Welcome),
+    WorldSnapshot(WorldSnapshot),
+    ChunkDeltaRequest(ChunkDeltaRequest),
+    ChunkDelta(ChunkDelta),
+    NetReport(NetReport),
+    AvatarSubmit(AvatarSubmit),
+    AvatarResult(AvatarResult),
+    AvatarSwitch(AvatarSwitch),
+    AvatarAnnounce(AvatarAnnounce),
+    AvatarRequest(AvatarRequest),
+    PlayerPosition(PlayerPosition),
+    Emote(Emote),
+    ChatSend(ChatSend),
+    ChatBroadcast(ChatBroadcast),
+    PartyCreate(PartyCreate),
+    PartyInvite(PartyInvite),
+    PartyInvited(PartyInvited),
+    PartyJoin(PartyJoin),
+    PartyLeave(PartyLeave),
+    PartyResult(PartyResult),
+    PartyUpdate(PartyUpdate),
+    PartyTravel(PartyTravel),
+    FriendPresence(FriendPresence),
+    PathQuery(PathQuery),
+    PathResult(PathResult),
+    PeerAnnounce(PeerAnnounce),
+    PeerQuery(PeerQuery),
+    PeerList(PeerList),
+    Handoff(Handoff),
+    WorldMoved(WorldMoved),
+    Error(ErrorMessage),
+    PaymentRequired(PaymentRequired),
+    PaymentSent(PaymentSent),
+    RulesRequired(RulesRequired),
+    RulesAck(RulesAck),
+    Ping(Ping),
+    Pong(Pong),
+    Disconnect(Disconnect),
+    AssetToken(AssetToken),
+}
+
+impl Message {
+    /// Whether the message may travel as a QUIC datagram: losing or reordering it is harmless
+    /// because a newer one supersedes it.
+    pub fn is_unreliable(&self) -> bool {
+        matches!(self, Message::PlayerPosition(_))
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -134,6 +499,110 @@ pub struct Hello {
     pub world_id: Option<Uuid>,
     #[serde(default)]
     pub client_name: Option<String>,
+    /// `session_token` from an earlier `Welcome`, when reconnecting after a drop.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resume_token: Option<String>,
+    /// `token` from an earlier `PartyResult`, to stay in the party when moving to another world
+    /// on the same host.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub party_token: Option<String>,
+    /// Wallet pubkey (base58) the player goes by, so friends can find it. Self-declared unless
+    /// `wallet_proof` is given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wallet_pubkey: Option<String>,
+    /// Signature by `wallet_pubkey` proving the player holds it (see `WalletProof`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wallet_proof: Option<WalletProof>,
+    /// Profile id on this host the player goes by; its friends list decides whose arrivals it
+    /// hears about. Self-declared.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile_id: Option<String>,
+    /// Frame protection the client can use after the handshake, most preferred first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub frame_protection: Vec<FrameProtection>,
+    /// Payload encodings the client can use after the handshake, most preferred first. JSON is
+    /// always understood and needn't be listed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub wire_format: Vec<WireFormat>,
+    /// Frame compression the client can undo after the handshake, most preferred first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub compression: Vec<Compression>,
+    /// Fresh random value the server's `welcome.attestation` must cover, so an old attestation
+    /// can't be replayed by someone else's host.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attestation_nonce: Option<String>,
+    /// Features the client understands. Left empty, it is served the legacy set (see
+    /// `capability::Capability::is_legacy`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub capabilities: Vec<capability::Capability>,
+}
+
+/// The wallet's ed25519 signature over (`world_id`, `wallet_pubkey`, `timestamp`, `nonce`); see
+/// `owp_discovery::wallet_auth` for the signed bytes. Each nonce is accepted once.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WalletProof {
+    /// Unix seconds when it was signed.
+    pub timestamp: i64,
+    /// Fresh random value, at most 128 bytes.
+    pub nonce: String,
+    /// Signature, base64.
+    pub signature: String,
+}
+
+/// Per-frame protection applied after `welcome` (see `protection`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameProtection {
+    /// CRC-32C trailer: detects corruption, not tampering.
+    Crc32c,
+    /// Authenticated encryption under the Noise transport keys; only Noise sessions use it.
+    Chacha20Poly1305,
+}
+
+/// Payload encoding of frames after `welcome` (see `wire`). `hello` and `welcome` are always
+/// JSON.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WireFormat {
+    #[default]
+    Json,
+    /// MessagePack, with structs as maps keyed by field name.
+    Msgpack,
+}
+
+impl WireFormat {
+    /// Every format this build speaks.
+    pub const ALL: [WireFormat; 2] = [WireFormat::Json, WireFormat::Msgpack];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WireFormat::Json => "json",
+            WireFormat::Msgpack => "msgpack",
+        }
+    }
+}
+
+/// Payload compression of frames after `welcome` (see `wire`). Frames below
+/// `wire::COMPRESSION_THRESHOLD` are sent as they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    Zstd,
+}
+
+impl Compression {
+    /// Every compression this build can apply and undo.
+    pub const ALL: &'static [Compression] = if cfg!(feature = "zstd") {
+        &[Compression::Zstd]
+    } else {
+        &[]
+    };
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Compression::Zstd => "zstd",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -145,6 +614,583 @@ pub struct Welcome {
     pub token_mint: Option<String>,
     #[serde(default)]
     pub motd: Option<String>,
+    /// What the server grants out of `hello.capabilities`; see `capability::negotiate`.
+    #[serde(default)]
+    pub capabilities: Vec<capability::Capability>,
+    /// Opaque token the client presents as `resume_token` to pick the session back up.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_token: Option<String>,
+    /// The `resume_token` was accepted and this continues the previous session.
+    #[serde(default)]
+    pub resumed: bool,
+    /// Assets to download (in parallel) before spawning.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub prefetch: Vec<AssetRef>,
+    /// This player's id in the world; kept when a session is resumed. Messages relayed from
+    /// other players carry theirs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub player_id: Option<Uuid>,
+    /// The party this player is in, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub party: Option<PartyInfo>,
+    /// Players connected to the world, this one included.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub player_count: Option<u32>,
+    /// The server's pick from `hello.frame_protection`; unprotected frames if absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frame_protection: Option<FrameProtection>,
+    /// The server's pick from `hello.wire_format`; JSON if absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wire_format: Option<WireFormat>,
+    /// The server's pick from `hello.compression`; frames aren't compressed if absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<Compression>,
+    /// The world authority vouching for this server, when `hello.attestation_nonce` was sent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attestation: Option<ServerAttestation>,
+    /// Whether `hello.wallet_proof` was accepted; absent if none was sent. A refused proof means
+    /// the player goes without its wallet in this session.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wallet_verified: Option<bool>,
+}
+
+/// Sent right after `welcome`: what a client needs to draw the world without asking for each
+/// chunk first. Later changes arrive as usual, through `chunk_delta` from `version`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorldSnapshot {
+    pub world_id: Uuid,
+    pub name: String,
+    /// Chunks holding objects, with their contents.
+    #[serde(default)]
+    pub chunks: Vec<ChunkSnapshot>,
+    /// Chunks that didn't fit in the snapshot; fetch them with `chunk_delta_request`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub more_chunks: Vec<ChunkCoord>,
+    /// Other players connected to the world.
+    #[serde(default)]
+    pub players: Vec<PlayerSnapshot>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkSnapshot {
+    pub chunk: ChunkCoord,
+    pub version: u64,
+    pub objects: Vec<WorldObjectV1>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlayerSnapshot {
+    pub player_id: Uuid,
+    /// Last reported position; absent until the player sends one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<[f32; 3]>,
+    /// What the player last announced, if it shares its avatar.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avatar: Option<AvatarSpecV1>,
+}
+
+/// The world authority's signature over (`world_id`, `endpoint`, `timestamp`,
+/// `hello.attestation_nonce`); see `owp_discovery::attestation` for the signed bytes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerAttestation {
+    /// Host (DNS name or IP) the world is listed under.
+    pub endpoint: String,
+    /// Unix seconds when it was signed.
+    pub timestamp: i64,
+    /// Authority public key, base58.
+    pub public_key: String,
+    /// Ed25519 signature, base64.
+    pub signature: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AssetRef {
+    /// Lowercase hex SHA-256 of the content; lets clients skip what they already cache.
+    pub sha256: String,
+    pub size: u64,
+    pub uri: String,
+}
+
+/// Every asset a world needs, for clients that keep a local cache in step with the server.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncManifestV1 {
+    pub world_id: Uuid,
+    /// Ordered by `priority`, then `uri`.
+    pub entries: Vec<SyncEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncEntry {
+    pub sha256: String,
+    pub size: u64,
+    /// Absolute, or relative to the admin API that served the manifest.
+    pub uri: String,
+    /// 0 is needed before spawning (the prefetch list and prefab bundle), 1 soon after (other
+    /// assets and prefab meshes), 2 whenever (the world icon).
+    pub priority: u8,
+}
+
+/// Keepalive, sent by either side on an open session; the other answers with `Pong`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ping {
+    pub nonce: Uuid,
+    /// Sender's clock, unix milliseconds.
+    pub sent_at_ms: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pong {
+    /// The `Ping`'s nonce.
+    pub nonce: Uuid,
+    /// The `Ping`'s `sent_at_ms`, echoed.
+    pub ping_sent_at_ms: i64,
+    /// Answering side's clock, unix milliseconds.
+    pub sent_at_ms: i64,
+}
+
+impl Ping {
+    pub fn new(now_ms: i64) -> Self {
+        Self {
+            nonce: Uuid::new_v4(),
+            sent_at_ms: now_ms,
+        }
+    }
+
+    pub fn pong(&self, now_ms: i64) -> Pong {
+        Pong {
+            nonce: self.nonce,
+            ping_sent_at_ms: self.sent_at_ms,
+            sent_at_ms: now_ms,
+        }
+    }
+}
+
+/// Periodic connection quality sample sent by the client; the server doesn't reply.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetReport {
+    pub request_id: Uuid,
+    /// Span the sample covers.
+    pub interval_ms: u32,
+    /// Mean request/reply round trip over the interval.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rtt_ms: Option<u32>,
+    /// Mean variation between consecutive round trips.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jitter_ms: Option<u32>,
+    /// Updates the client noticed it missed (e.g. a delta that had to fall back to a full chunk).
     #[serde(default)]
-    pub capabilities: Vec<String>,
+    pub update_gaps: u32,
+}
+
+/// A complete avatar built on the client, with the mesh files it references.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AvatarSubmit {
+    pub request_id: Uuid,
+    pub avatar: AvatarSpecV1,
+    /// Mesh files for `avatar.mesh`; the server stores them and fills in `uri`/`sha256`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub meshes: Vec<AvatarMeshBlob>,
+    /// Also keep the avatar under this name for later `AvatarSwitch`es.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wardrobe_name: Option<String>,
+}
+
+/// Wear an avatar submitted earlier under `name`, without resending it. Answered by
+/// `AvatarResult`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AvatarSwitch {
+    pub request_id: Uuid,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AvatarMeshBlob {
+    /// "body" for `avatar.mesh` itself, otherwise the id of one of `avatar.mesh.parts`.
+    pub id: String,
+    /// File contents in standard base64, in `avatar.mesh.format`.
+    pub data_base64: String,
+}
+
+/// What a player wears. Clients send it (without `player_id`) to show the others; the server
+/// checks it like a submission and relays it to everyone else in the world with `player_id` set.
+/// A successful `AvatarSubmit` or `AvatarSwitch` is announced the same way. Also the answer to
+/// `AvatarRequest`, with its `request_id`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AvatarAnnounce {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub player_id: Option<Uuid>,
+    pub avatar: AvatarSpecV1,
+}
+
+/// Ask for the avatar another player last announced, e.g. one that came into view. Answered by
+/// `AvatarAnnounce`, or by `AvatarResult` with an `error` when there is none to share.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AvatarRequest {
+    pub request_id: Uuid,
+    pub player_id: Uuid,
+}
+
+/// Reply to `AvatarSubmit` and `AvatarSwitch`: the sanitized avatar as stored, or why it was
+/// rejected.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AvatarResult {
+    pub request_id: Uuid,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avatar: Option<AvatarSpecV1>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Where the client's player stands; decides which nearby messages it receives. No reply.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlayerPosition {
+    /// World-space position (meters, Y-up).
+    pub position: [f32; 3],
+}
+
+/// A wave, dance, etc. Sent by a client for its own player and relayed by the server to nearby
+/// players with `player_id` filled in.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Emote {
+    /// Id from the world's emote catalog.
+    pub id: String,
+    pub duration_ms: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub player_id: Option<Uuid>,
+}
+
+/// Who a chat message goes to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ChatChannel {
+    /// Everyone in the world.
+    World,
+    /// Players within speaking distance of the sender.
+    Proximity,
+    /// The sender's party.
+    Party,
+    /// One player, by `player_id`.
+    Whisper { to: Uuid },
+    /// Notices from the server itself, e.g. an undeliverable whisper. Clients can't send on it.
+    System,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatSend {
+    pub channel: ChatChannel,
+    pub text: String,
+}
+
+/// A chat message as delivered, including back to its sender.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatBroadcast {
+    pub channel: ChatChannel,
+    /// Sending player; absent on `System`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<Uuid>,
+    pub text: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub sent_at: OffsetDateTime,
+}
+
+/// A party's roster.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartyInfo {
+    pub party_id: Uuid,
+    pub leader: Uuid,
+    pub members: Vec<PartyMember>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartyMember {
+    pub player_id: Uuid,
+    /// World the member is in.
+    pub world_id: Uuid,
+    /// False while the member is reconnecting or moving between worlds.
+    pub online: bool,
+}
+
+/// Start a party led by the sender. Answered with `PartyResult`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartyCreate {
+    pub request_id: Uuid,
+}
+
+/// Invite a player in the same world to the sender's party. Answered with `PartyResult`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartyInvite {
+    pub request_id: Uuid,
+    pub player_id: Uuid,
+}
+
+/// Sent to an invited player; accept with `PartyJoin`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartyInvited {
+    pub party_id: Uuid,
+    pub from: Uuid,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartyJoin {
+    pub request_id: Uuid,
+    pub party_id: Uuid,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartyLeave {
+    pub request_id: Uuid,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartyResult {
+    pub request_id: Uuid,
+    /// The sender's party after the request; absent after leaving.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub party: Option<PartyInfo>,
+    /// Secret for `Hello.party_token`; set when the sender creates or joins a party.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Pushed to party members whenever the roster changes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartyUpdate {
+    pub party: PartyInfo,
+}
+
+/// The party leader moves the group to another world: clients receiving it (with `from` set)
+/// connect to `connect` and pass their party token in `Hello`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartyTravel {
+    /// Connect string like `owp://host:port?world=<uuid>`.
+    pub connect: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<Uuid>,
+}
+
+/// The player walked into a region another node of a clustered world serves: reconnect to
+/// `addr` (`host:port`, same world) with `session_token` as `Hello.resume_token` to keep the
+/// player id. The server closes the connection after sending it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Handoff {
+    pub addr: String,
+    pub session_token: String,
+}
+
+/// The world moved to another host: reconnect to `connect` (an `owp://` connect string for the
+/// same world) with a fresh session. Sent to every connected client when the move completes, and
+/// right after `Welcome` to anyone connecting to the old host afterwards; the server closes the
+/// connection after sending it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorldMoved {
+    pub connect: String,
+}
+
+/// Goodbye from either side of an open session; the sender closes the connection after it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Disconnect {
+    pub reason: DisconnectReason,
+    /// For humans, e.g. why the player was kicked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisconnectReason {
+    /// The player left.
+    ClientQuit,
+    /// The server process is stopping.
+    ServerShutdown,
+    /// The world is restarting; reconnecting (and resuming) shortly should work.
+    WorldRestart,
+    /// The host removed the player.
+    Kicked,
+    /// The player stopped answering pings.
+    IdleTimeout,
+    /// A reason this build doesn't know.
+    #[serde(other)]
+    Unknown,
+}
+
+/// Credential for the world's private assets (`assets.private`), sent by the server after
+/// `Welcome` and again before it expires. Pass it to the asset routes as the `asset_token` query
+/// parameter or the `X-OWP-Asset-Token` header.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssetToken {
+    pub token: String,
+    /// Unix seconds after which the asset routes refuse it.
+    pub expires_at: i64,
+}
+
+/// Why the server turned a connection down. Sent instead of `welcome` (or on an open session),
+/// after which the server closes the connection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorMessage {
+    pub code: ErrorCode,
+    /// Human-readable detail; not meant to be matched on.
+    pub message: String,
+    /// The `request_id` of the `hello` being answered.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<Uuid>,
+    /// With `version_mismatch`: the protocol versions the server speaks.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub supported_versions: Vec<String>,
+}
+
+/// The world charges an entry fee (see `WorldEntryFee`): pay it with a transfer that lists
+/// `reference` as an extra account (as Solana Pay does), then answer with `payment_sent`. Sent
+/// instead of `welcome`, which follows once the payment is found on chain.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PaymentRequired {
+    /// The `request_id` of the `hello` being answered.
+    pub request_id: Uuid,
+    pub recipient: String,
+    /// In the token's base units (lamports for SOL).
+    pub amount: u64,
+    /// SPL mint; SOL when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mint: Option<String>,
+    /// Fresh base58 key identifying this payment.
+    pub reference: String,
+    /// Unix seconds after which the server stops waiting.
+    pub expires_at: i64,
+    /// The same request as a Solana Pay transfer URL, for wallets and QR codes.
+    pub pay_url: String,
+}
+
+/// The client has sent the payment asked for.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PaymentSent {
+    pub reference: String,
+    /// Transaction signature, if the client knows it; the server finds it by `reference`
+    /// either way.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+/// The world has rules (see `WorldRules`) the player hasn't acknowledged: read them and answer
+/// with `rules_ack`. Sent instead of `welcome`, like `payment_required`, and before it when a
+/// world has both.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RulesRequired {
+    /// The `request_id` of the `hello` being answered.
+    pub request_id: Uuid,
+    pub sha256: String,
+    pub uri: String,
+}
+
+/// The player accepts the rules whose hash is `sha256`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RulesAck {
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// `hello.protocol_version` isn't compatible with the server's.
+    VersionMismatch,
+    /// The server doesn't serve `hello.world_id`.
+    WorldNotFound,
+    /// The host doesn't let this player in.
+    Unauthorized,
+    /// The world can't take players right now (e.g. its bandwidth cap is used up).
+    Unavailable,
+    /// The entry fee wasn't paid, or the payment couldn't be found on chain in time.
+    PaymentRequired,
+    /// The player didn't acknowledge the world's rules.
+    RulesNotAccepted,
+    /// A code this build doesn't know.
+    #[serde(other)]
+    Unknown,
+}
+
+/// A friend entered or left the world the receiving player is in.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FriendPresence {
+    /// The id (wallet pubkey or profile id) the friend is listed by.
+    pub friend: String,
+    pub player_id: Uuid,
+    pub world_id: Uuid,
+    pub online: bool,
+}
+
+/// Ask the server for a walkable route between two points.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PathQuery {
+    pub request_id: Uuid,
+    /// World-space positions (meters, Y-up).
+    pub from: [f32; 3],
+    pub to: [f32; 3],
+}
+
+/// Reply to `PathQuery`: waypoints from `from` to `to`, or an empty path and an `error` when
+/// there is no route.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PathResult {
+    pub request_id: Uuid,
+    #[serde(default)]
+    pub path: Vec<[f32; 3]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The content-store blobs this client serves to other players (`GET /content/:sha256` on
+/// `port`, at the address the server sees it connect from). Replaces any earlier announcement;
+/// an empty list withdraws it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerAnnounce {
+    pub port: u16,
+    #[serde(default)]
+    pub sha256: Vec<String>,
+}
+
+/// Ask which players can serve a blob.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerQuery {
+    pub request_id: Uuid,
+    pub sha256: String,
+}
+
+/// Reply to `PeerQuery`: `host:port` of players announcing the blob. Downloads from them must
+/// be checked against `sha256`; the server doesn't vouch for what peers send.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerList {
+    pub request_id: Uuid,
+    pub sha256: String,
+    #[serde(default)]
+    pub peers: Vec<String>,
+}
+
+/// Ask for the changes to a chunk since a version the client already holds.
+/// `since_version == 0` means "I have nothing".
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkDeltaRequest {
+    pub request_id: Uuid,
+    pub chunk: ChunkCoord,
+    #[serde(default)]
+    pub since_version: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkDelta {
+    pub request_id: Uuid,
+    pub chunk: ChunkCoord,
+    /// Version the changes apply on top of (0 when `full`).
+    pub base_version: u64,
+    /// Current chunk version after applying the changes.
+    pub version: u64,
+    /// When true the server could not serve an incremental delta (journal trimmed or
+    /// unknown base) and `objects` holds the complete chunk contents instead.
+    #[serde(default)]
+    pub full: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub objects: Vec<WorldObjectV1>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub changes: Vec<ChunkChangeV1>,
 }
@@ -0,0 +1,245 @@
+//! Noise_XX sessions whose server static key is the world authority's ed25519 key converted to
+//! X25519, so a client that knows the authority (from the on-chain registry entry, or a signed
+//! directory) can check it reached the real host without any certificates.
+//!
+//! A Noise client opens with [`PREAMBLE`] instead of a frame, then the three XX handshake
+//! messages travel as ordinary length-prefixed frames. Both ends then protect every frame
+//! (`hello` and `welcome` included) with ChaCha20-Poly1305 as in `protection`, keyed by the
+//! handshake's two transport keys.
+
+use curve25519_dalek::montgomery::MontgomeryPoint;
+use snow::params::DHChoice;
+use snow::resolvers::{CryptoResolver, FallbackResolver, RingResolver};
+use snow::types::{Cipher, Dh, Hash, Random};
+
+use crate::protection::SessionKey;
+
+/// Sent by a Noise client before anything else. Read as a frame length it is far over
+/// `MAX_FRAME_LEN`, so it can't be mistaken for a plain `hello`.
+pub const PREAMBLE: [u8; 8] = *b"OWPNXv1\0";
+
+const PARAMS: &str = "Noise_XX_25519_ChaChaPoly_SHA256";
+const PROLOGUE: &[u8] = b"owp-noise-v1";
+/// Largest Noise handshake message.
+const MAX_MESSAGE: usize = 65535;
+
+#[derive(Debug, thiserror::Error)]
+pub enum NoiseError {
+    #[error("noise: {0}")]
+    Snow(#[from] snow::Error),
+    #[error("server key does not match the world authority")]
+    ServerIdentity,
+    #[error("not a valid ed25519 authority key")]
+    AuthorityKey,
+    #[error("this server does not offer noise sessions")]
+    NotOffered,
+}
+
+/// The X25519 public key a world's server uses, from its authority's ed25519 public key.
+pub fn server_static_public(authority_pubkey: &[u8; 32]) -> Result<[u8; 32], NoiseError> {
+    let key = ed25519_dalek::VerifyingKey::from_bytes(authority_pubkey)
+        .map_err(|_| NoiseError::AuthorityKey)?;
+    Ok(key.to_montgomery().to_bytes())
+}
+
+/// The matching X25519 private key, from the authority's ed25519 seed.
+pub fn server_static_secret(authority_seed: &[u8; 32]) -> [u8; 32] {
+    ed25519_dalek::SigningKey::from_bytes(authority_seed).to_scalar_bytes()
+}
+
+#[derive(Default)]
+struct X25519 {
+    private: [u8; 32],
+    public: [u8; 32],
+}
+
+impl Dh for X25519 {
+    fn name(&self) -> &'static str {
+        "25519"
+    }
+
+    fn pub_len(&self) -> usize {
+        32
+    }
+
+    fn priv_len(&self) -> usize {
+        32
+    }
+
+    fn set(&mut self, privkey: &[u8]) {
+        self.private.copy_from_slice(privkey);
+        self.public = MontgomeryPoint::mul_base_clamped(self.private).to_bytes();
+    }
+
+    fn generate(&mut self, rng: &mut dyn Random) {
+        let mut private = [0u8; 32];
+        rng.fill_bytes(&mut private);
+        self.set(&private);
+    }
+
+    fn pubkey(&self) -> &[u8] {
+        &self.public
+    }
+
+    fn privkey(&self) -> &[u8] {
+        &self.private
+    }
+
+    fn dh(&self, pubkey: &[u8], out: &mut [u8]) -> Result<(), snow::Error> {
+        // snow hands over its fixed-size key buffers; the key is the first 32 bytes.
+        let point: [u8; 32] = pubkey
+            .get(..32)
+            .and_then(|p| p.try_into().ok())
+            .ok_or(snow::Error::Dh)?;
+        let shared = MontgomeryPoint(point).mul_clamped(self.private).to_bytes();
+        // A low-order point gives an all-zero secret.
+        if shared == [0u8; 32] {
+            return Err(snow::Error::Dh);
+        }
+        out[..32].copy_from_slice(&shared);
+        Ok(())
+    }
+}
+
+/// ring has no X25519 with static keys; everything else comes from ring.
+struct X25519Resolver;
+
+impl CryptoResolver for X25519Resolver {
+    fn resolve_rng(&self) -> Option<Box<dyn Random>> {
+        None
+    }
+
+    fn resolve_dh(&self, choice: &DHChoice) -> Option<Box<dyn Dh>> {
+        matches!(choice, DHChoice::Curve25519).then(|| Box::new(X25519::default()) as Box<dyn Dh>)
+    }
+
+    fn resolve_hash(&self, _: &snow::params::HashChoice) -> Option<Box<dyn Hash>> {
+        None
+    }
+
+    fn resolve_cipher(&self, _: &snow::params::CipherChoice) -> Option<Box<dyn Cipher>> {
+        None
+    }
+}
+
+fn builder<'a>() -> snow::Builder<'a> {
+    let resolver = FallbackResolver::new(Box::new(RingResolver), Box::new(X25519Resolver));
+    snow::Builder::with_resolver(PARAMS.parse().expect("noise params"), Box::new(resolver))
+        .prologue(PROLOGUE)
+}
+
+/// Transport keys from a finished handshake.
+pub(crate) struct Keys {
+    pub send: SessionKey,
+    pub recv: SessionKey,
+}
+
+/// One side of an XX handshake in progress.
+pub(crate) struct Handshake {
+    state: snow::HandshakeState,
+    /// Client: the server static key to insist on.
+    expect: Option<[u8; 32]>,
+}
+
+impl std::fmt::Debug for Handshake {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Handshake")
+            .field("initiator", &self.state.is_initiator())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Handshake {
+    /// Client side with a fresh static key; returns the first message.
+    pub fn initiator(expect: Option<[u8; 32]>) -> Result<(Self, Vec<u8>), NoiseError> {
+        let b = builder();
+        let keypair = b.generate_keypair()?;
+        let state = b.local_private_key(&keypair.private).build_initiator()?;
+        let mut hs = Self { state, expect };
+        let first = hs.write()?;
+        Ok((hs, first))
+    }
+
+    pub fn responder(static_secret: &[u8; 32]) -> Result<Self, NoiseError> {
+        let state = builder()
+            .local_private_key(static_secret)
+            .build_responder()?;
+        Ok(Self {
+            state,
+            expect: None,
+        })
+    }
+
+    /// Take the peer's next message; returns the reply, if it's our turn.
+    pub fn read(&mut self, message: &[u8]) -> Result<Option<Vec<u8>>, NoiseError> {
+        let mut payload = vec![0u8; MAX_MESSAGE];
+        self.state.read_message(message, &mut payload)?;
+        if let (Some(expect), Some(remote)) = (self.expect, self.state.get_remote_static()) {
+            if remote != expect {
+                return Err(NoiseError::ServerIdentity);
+            }
+        }
+        if self.state.is_handshake_finished() || !self.state.is_my_turn() {
+            return Ok(None);
+        }
+        self.write().map(Some)
+    }
+
+    fn write(&mut self) -> Result<Vec<u8>, NoiseError> {
+        let mut out = vec![0u8; MAX_MESSAGE];
+        let n = self.state.write_message(&[], &mut out)?;
+        out.truncate(n);
+        Ok(out)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state.is_handshake_finished()
+    }
+
+    pub fn into_keys(mut self) -> Keys {
+        // The keys feed `FrameSealer`, which counts its own nonces per direction instead of
+        // using snow's transport state (its 64 KiB message cap is below `MAX_FRAME_LEN`).
+        let (i2r, r2i) = self.state.dangerously_get_raw_split();
+        if self.state.is_initiator() {
+            Keys {
+                send: i2r,
+                recv: r2i,
+            }
+        } else {
+            Keys {
+                send: r2i,
+                recv: i2r,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xx_handshake_pins_the_authority_key() {
+        let seed = [3u8; 32];
+        let authority = ed25519_dalek::SigningKey::from_bytes(&seed).verifying_key();
+        let expect = server_static_public(authority.as_bytes()).expect("pubkey");
+
+        let (mut client, m1) = Handshake::initiator(Some(expect)).expect("initiator");
+        let mut server = Handshake::responder(&server_static_secret(&seed)).expect("responder");
+        let m2 = server.read(&m1).expect("m1").expect("m2");
+        let m3 = client.read(&m2).expect("m2").expect("m3");
+        assert!(server.read(&m3).expect("m3").is_none());
+        assert!(client.is_finished() && server.is_finished());
+
+        let (c, s) = (client.into_keys(), server.into_keys());
+        assert_eq!(c.send, s.recv);
+        assert_eq!(c.recv, s.send);
+        assert_ne!(c.send, c.recv);
+
+        // Someone else's server key is refused.
+        let (mut client, m1) = Handshake::initiator(Some(expect)).expect("initiator");
+        let mut impostor = Handshake::responder(&[5u8; 32]).expect("responder");
+        let m2 = impostor.read(&m1).expect("m1").expect("m2");
+        assert!(matches!(client.read(&m2), Err(NoiseError::ServerIdentity)));
+    }
+}
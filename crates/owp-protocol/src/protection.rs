@@ -0,0 +1,261 @@
+//! Per-frame integrity and encryption, negotiated in the handshake (`hello.frame_protection` /
+//! `welcome.frame_protection`) and applied to every frame after `welcome`. The length prefix then
+//! covers the protected body:
+//!
+//! - `crc32c`: the payload, then its CRC-32C (big-endian `u32`);
+//! - `chacha20_poly1305`: the payload encrypted under a Noise transport key, then the 16-byte
+//!   tag. Only Noise sessions use it (`noise` module); a plain `hello` can't pick it. The
+//!   nonce is a direction byte (0 = client to server, 1 = server to client), three zero bytes and
+//!   the frame's `u64` big-endian sequence number in that direction.
+
+use crate::wire::{self, WireError};
+use crate::{Compression, FrameProtection, Message, WireFormat};
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+const CRC32C: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI);
+
+/// A Noise transport key for one direction, needed for AEAD.
+pub type SessionKey = [u8; 32];
+
+/// Frames sent from the client carry direction 0, frames from the server direction 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    ToServer = 0,
+    ToClient = 1,
+}
+
+/// Protects or checks the frames going one way.
+#[derive(Debug)]
+pub struct FrameSealer {
+    kind: Kind,
+    // Only AEAD nonces use these.
+    #[cfg_attr(not(feature = "aead"), allow(dead_code))]
+    direction: Direction,
+    #[cfg_attr(not(feature = "aead"), allow(dead_code))]
+    sequence: u64,
+}
+
+#[derive(Debug)]
+enum Kind {
+    Crc32c,
+    #[cfg(feature = "aead")]
+    Aead(Box<ring::aead::LessSafeKey>),
+}
+
+impl FrameSealer {
+    /// `None` if `protection` needs a key that isn't there (or this build has no AEAD).
+    pub fn new(
+        protection: FrameProtection,
+        key: Option<&SessionKey>,
+        direction: Direction,
+    ) -> Option<Self> {
+        let kind = match protection {
+            FrameProtection::Crc32c => Kind::Crc32c,
+            #[cfg(feature = "aead")]
+            FrameProtection::Chacha20Poly1305 => {
+                use ring::aead::{LessSafeKey, UnboundKey, CHACHA20_POLY1305};
+                let key = UnboundKey::new(&CHACHA20_POLY1305, key?).ok()?;
+                Kind::Aead(Box::new(LessSafeKey::new(key)))
+            }
+            #[cfg(not(feature = "aead"))]
+            FrameProtection::Chacha20Poly1305 => {
+                let _ = key;
+                return None;
+            }
+        };
+        Some(Self {
+            kind,
+            direction,
+            sequence: 0,
+        })
+    }
+
+    /// Whether this build can use `protection` given the key situation.
+    pub fn supports(protection: FrameProtection, have_key: bool) -> bool {
+        match protection {
+            FrameProtection::Crc32c => true,
+            FrameProtection::Chacha20Poly1305 => cfg!(feature = "aead") && have_key,
+        }
+    }
+
+    pub fn seal(&mut self, mut payload: Vec<u8>) -> Result<Vec<u8>, WireError> {
+        match &self.kind {
+            Kind::Crc32c => {
+                let crc = CRC32C.checksum(&payload);
+                payload.extend_from_slice(&crc.to_be_bytes());
+            }
+            #[cfg(feature = "aead")]
+            Kind::Aead(key) => {
+                let nonce = next_nonce(self.direction, &mut self.sequence)?;
+                key.seal_in_place_append_tag(nonce, ring::aead::Aad::empty(), &mut payload)
+                    .map_err(|_| WireError::Integrity)?;
+            }
+        }
+        Ok(payload)
+    }
+
+    /// The payload of a protected body, or `Integrity` if it was corrupted or tampered with.
+    pub fn open(&mut self, mut body: Vec<u8>) -> Result<Vec<u8>, WireError> {
+        match &self.kind {
+            Kind::Crc32c => {
+                let split = body.len().checked_sub(4).ok_or(WireError::Integrity)?;
+                let (payload, crc) = body.split_at(split);
+                let crc = u32::from_be_bytes(crc.try_into().expect("4 bytes"));
+                if CRC32C.checksum(payload) != crc {
+                    return Err(WireError::Integrity);
+                }
+                body.truncate(split);
+            }
+            #[cfg(feature = "aead")]
+            Kind::Aead(key) => {
+                let nonce = next_nonce(self.direction, &mut self.sequence)?;
+                let len = key
+                    .open_in_place(nonce, ring::aead::Aad::empty(), &mut body)
+                    .map_err(|_| WireError::Integrity)?
+                    .len();
+                body.truncate(len);
+            }
+        }
+        Ok(body)
+    }
+}
+
+#[cfg(feature = "aead")]
+fn next_nonce(direction: Direction, sequence: &mut u64) -> Result<ring::aead::Nonce, WireError> {
+    let mut nonce = [0u8; 12];
+    nonce[0] = direction as u8;
+    nonce[4..].copy_from_slice(&sequence.to_be_bytes());
+    *sequence = sequence.checked_add(1).ok_or(WireError::Integrity)?;
+    Ok(ring::aead::Nonce::assume_unique_for_key(nonce))
+}
+
+/// Frames outgoing messages, protecting them once the handshake has negotiated it.
+#[derive(Debug, Default)]
+pub struct FrameEncoder {
+    sealer: Option<FrameSealer>,
+    format: WireFormat,
+    compression: Option<Compression>,
+}
+
+impl FrameEncoder {
+    pub fn new(sealer: Option<FrameSealer>) -> Self {
+        Self {
+            sealer,
+            format: WireFormat::Json,
+            compression: None,
+        }
+    }
+
+    /// Encode payloads as `format` (the one negotiated in `welcome.wire_format`).
+    pub fn with_format(mut self, format: WireFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Compress payloads with `compression` (the one negotiated in `welcome.compression`).
+    pub fn with_compression(mut self, compression: Option<Compression>) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn format(&self) -> WireFormat {
+        self.format
+    }
+
+    pub fn encode(&mut self, message: &Message) -> Result<Vec<u8>, WireError> {
+        let payload = wire::encode_payload(message, self.format)?;
+        self.finish(payload)
+    }
+
+    /// Pack `messages` into one frame, for a peer granted `Capability::Batch`.
+    pub fn encode_batch(&mut self, messages: &[Message]) -> Result<Vec<u8>, WireError> {
+        let payload = wire::encode_batch_payload(messages, self.format)?;
+        self.finish(payload)
+    }
+
+    fn finish(&mut self, mut payload: Vec<u8>) -> Result<Vec<u8>, WireError> {
+        if let Some(c) = self.compression {
+            payload = wire::compress(payload, c)?;
+        }
+        let body = match &mut self.sealer {
+            Some(s) => s.seal(payload)?,
+            None => payload,
+        };
+        wire::frame(body)
+    }
+
+    #[cfg(feature = "tokio")]
+    pub async fn write_message<W: AsyncWrite + Unpin>(
+        &mut self,
+        writer: &mut W,
+        message: &Message,
+    ) -> Result<(), WireError> {
+        let frame = self.encode(message)?;
+        writer.write_all(&frame).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(protection: FrameProtection, key: Option<&SessionKey>) {
+        let mut tx = FrameSealer::new(protection, key, Direction::ToServer).expect("sealer");
+        let mut rx = FrameSealer::new(protection, key, Direction::ToServer).expect("opener");
+        for text in ["first", "second"] {
+            let body = tx.seal(text.as_bytes().to_vec()).expect("seal");
+            assert_eq!(rx.open(body).expect("open"), text.as_bytes());
+        }
+
+        let mut body = tx.seal(b"{\"type\":\"x\"}".to_vec()).expect("seal");
+        body[3] ^= 1;
+        assert!(matches!(rx.open(body), Err(WireError::Integrity)));
+        assert!(matches!(rx.open(vec![1, 2]), Err(WireError::Integrity)));
+    }
+
+    #[test]
+    fn crc32c_detects_corruption() {
+        // Check value for "123456789" from the CRC-32C spec.
+        assert_eq!(CRC32C.checksum(b"123456789"), 0xE306_9283);
+        round_trip(FrameProtection::Crc32c, None);
+    }
+
+    #[cfg(feature = "aead")]
+    #[test]
+    fn aead_detects_tampering_replay_and_wrong_direction() {
+        let key = [7u8; 32];
+        round_trip(FrameProtection::Chacha20Poly1305, Some(&key));
+        assert!(
+            FrameSealer::new(FrameProtection::Chacha20Poly1305, None, Direction::ToClient)
+                .is_none()
+        );
+
+        let mut tx = FrameSealer::new(
+            FrameProtection::Chacha20Poly1305,
+            Some(&key),
+            Direction::ToClient,
+        )
+        .expect("sealer");
+        let body = tx.seal(b"secret".to_vec()).expect("seal");
+        assert!(!body.windows(6).any(|w| w == b"secret"));
+        let mut wrong_way = FrameSealer::new(
+            FrameProtection::Chacha20Poly1305,
+            Some(&key),
+            Direction::ToServer,
+        )
+        .expect("opener");
+        assert!(wrong_way.open(body.clone()).is_err());
+        let mut rx = FrameSealer::new(
+            FrameProtection::Chacha20Poly1305,
+            Some(&key),
+            Direction::ToClient,
+        )
+        .expect("opener");
+        rx.open(body.clone()).expect("open");
+        // A replayed frame no longer matches the sequence number.
+        assert!(rx.open(body).is_err());
+    }
+}
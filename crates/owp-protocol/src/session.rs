@@ -0,0 +1,1237 @@
+//! Handshake and session sequencing without I/O. Transports feed received bytes into a
+//! [`ProtocolStateMachine`], take the events it decodes, and write out the bytes it queues, so
+//! TCP, WebSocket and FFI hosts (and tests) share one set of rules:
+//!
+//! - the client speaks first with `Hello`; nothing else is accepted from it until then;
+//! - the server answers with exactly one `Welcome`; a `Hello` for another world or with an
+//!   incompatible `protocol_version` (see [`is_compatible_version`]) gets an `Error` instead and
+//!   a closed session, and so does a client that gets an `Error` or an incompatible `Welcome`;
+//! - before `Welcome` the server may ask for an entry fee with `PaymentRequired`; the client
+//!   answers with `PaymentSent` and the server then accepts or rejects as usual;
+//! - likewise it may ask the client to acknowledge the world's rules with `RulesRequired`,
+//!   answered by `RulesAck`;
+//! - after the handshake either side may send anything except the handshake messages;
+//! - the server picks the first `hello.frame_protection` it can use and names it in `welcome`;
+//!   from then on every frame both ways carries it;
+//! - likewise for `hello.wire_format`: frames after `welcome` are encoded in the server's pick,
+//!   JSON if there is none;
+//! - and for `hello.compression`: with one picked, every frame after `welcome` starts with a flag
+//!   byte saying whether the rest is compressed (see [`crate::wire`]);
+//! - a Noise client (see [`crate::noise`]) runs the XX handshake first and sends `Hello` only
+//!   once it is done; every frame after that, `hello` and `welcome` included, is encrypted under
+//!   the Noise keys and `frame_protection` is not negotiated.
+
+#[cfg(feature = "noise")]
+use crate::noise::{self, Handshake, Keys, NoiseError};
+use crate::protection::{Direction, FrameEncoder, FrameSealer};
+use crate::wire::{self, FrameDecoder, WireError};
+use crate::{
+    is_compatible_version, Compression, ErrorCode, ErrorMessage, FrameProtection, Hello, Message,
+    PaymentRequired, PaymentSent, RulesAck, RulesRequired, Welcome, WireFormat,
+    OWP_PROTOCOL_VERSION,
+};
+use std::collections::VecDeque;
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// Server: waiting for the client's `Hello`.
+    AwaitingHello,
+    /// Server: got `Hello`, waiting for the host to `accept` it.
+    AwaitingAccept,
+    /// Server: sent `PaymentRequired`, waiting for the client's `PaymentSent`.
+    AwaitingPayment,
+    /// Server: sent `RulesRequired`, waiting for the client's `RulesAck`.
+    AwaitingRulesAck,
+    /// Client: `Hello` queued, waiting for `Welcome`.
+    AwaitingWelcome,
+    /// Either side: Noise handshake in progress; `Hello` comes after it.
+    Noise,
+    Open,
+    /// Refused or failed; nothing more is sent or decoded.
+    Closed,
+}
+
+#[derive(Debug)]
+pub enum Event {
+    /// Server: the client's `Hello`. Answer with [`ProtocolStateMachine::accept`].
+    Hello(Hello),
+    /// Server: a `Hello` for another world. A `world_not_found` `Error` is queued and the
+    /// session is closed; flush it and hang up.
+    WrongWorld { requested: Uuid },
+    /// Server: a `Hello` speaking an incompatible protocol version. Handled like `WrongWorld`,
+    /// with `version_mismatch`.
+    VersionMismatch { requested: String },
+    /// Client: the server's `Welcome`; the session is open.
+    Welcome(Welcome),
+    /// Client: the world charges an entry fee. Pay it, then call
+    /// [`ProtocolStateMachine::payment_sent`]; `Welcome` (or an `Error`) follows.
+    PaymentRequired(PaymentRequired),
+    /// Server: the client says it paid. Check the chain, then `accept` or `reject`.
+    PaymentSent(PaymentSent),
+    /// Client: the world has rules to accept first. Show them, then call
+    /// [`ProtocolStateMachine::ack_rules`] or hang up.
+    RulesRequired(RulesRequired),
+    /// Server: the client accepted the rules. Check the hash, then carry on with the handshake.
+    RulesAck(RulesAck),
+    /// A message received after the handshake.
+    Message(Message),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProtocolError {
+    #[error(transparent)]
+    Wire(#[from] WireError),
+    #[error("expected {expected}, got {got:?}")]
+    Unexpected {
+        expected: &'static str,
+        got: Box<Message>,
+    },
+    #[error("server serves a different world ({0})")]
+    WrongWorld(Uuid),
+    #[error("server speaks incompatible protocol version {0}")]
+    Version(String),
+    #[error("server refused the connection ({:?}): {}", .0.code, .0.message)]
+    Refused(Box<ErrorMessage>),
+    #[error("not allowed in state {0:?}")]
+    State(State),
+    #[error("server chose frame protection {0:?}, which this client can't use")]
+    Protection(FrameProtection),
+    #[error("server chose wire format {0:?}, which this client didn't offer")]
+    WireFormat(WireFormat),
+    #[error("server chose compression {0:?}, which this client didn't offer")]
+    Compression(Compression),
+    #[error("the encoder was handed to another task; send through it")]
+    EncoderTaken,
+    #[cfg(feature = "noise")]
+    #[error(transparent)]
+    Noise(#[from] NoiseError),
+}
+
+/// One side of one connection. See the module docs for the rules it enforces.
+#[derive(Debug)]
+pub struct ProtocolStateMachine {
+    state: State,
+    client: bool,
+    /// Server: the world served. Client: the world asked for, if any.
+    world_id: Option<Uuid>,
+    request_id: Uuid,
+    /// Client: what `Hello` offered. Server: what it will put in `Welcome`.
+    protection: Vec<FrameProtection>,
+    /// Client: what `Hello` offered. Server: what it speaks, then its pick.
+    wire_formats: Vec<WireFormat>,
+    /// Payload encoding of received frames.
+    wire_format: WireFormat,
+    /// Client: what `Hello` offered. Server: what it can use, then its pick.
+    compressions: Vec<Compression>,
+    /// Compression flag on received frames, once negotiated.
+    compression: Option<Compression>,
+    decoder: FrameDecoder,
+    /// The rest of a received batch, handed out before the next frame.
+    batched: VecDeque<Message>,
+    /// Checks incoming frames once protection is on.
+    opener: Option<FrameSealer>,
+    /// `None` once handed out by `take_encoder`.
+    encoder: Option<FrameEncoder>,
+    outgoing: Vec<u8>,
+    /// Client: a `PaymentRequired` awaits its `PaymentSent`.
+    payment_pending: bool,
+    /// Client: a `RulesRequired` awaits its `RulesAck`.
+    rules_pending: bool,
+    #[cfg(feature = "noise")]
+    noise: Option<Handshake>,
+    /// Server: static secret for Noise clients.
+    #[cfg(feature = "noise")]
+    noise_key: Option<[u8; 32]>,
+    /// Noise client: `Hello`, held until the handshake is done.
+    #[cfg(feature = "noise")]
+    hello: Option<Hello>,
+}
+
+impl ProtocolStateMachine {
+    /// Client side; `hello` is queued for sending right away.
+    pub fn client(hello: Hello) -> Result<Self, ProtocolError> {
+        let mut sm = Self::new(State::AwaitingWelcome, true, hello.world_id);
+        sm.request_id = hello.request_id;
+        sm.protection = hello.frame_protection.clone();
+        sm.wire_formats = hello.wire_format.clone();
+        sm.compressions = hello.compression.clone();
+        sm.queue(&Message::Hello(hello))?;
+        Ok(sm)
+    }
+
+    /// Server side of a connection to `world_id`.
+    pub fn server(world_id: Uuid) -> Self {
+        Self::new(State::AwaitingHello, false, Some(world_id))
+    }
+
+    fn new(state: State, client: bool, world_id: Option<Uuid>) -> Self {
+        Self {
+            state,
+            client,
+            world_id,
+            request_id: Uuid::nil(),
+            protection: vec![],
+            wire_formats: WireFormat::ALL.to_vec(),
+            wire_format: WireFormat::Json,
+            compressions: Compression::ALL.to_vec(),
+            compression: None,
+            decoder: FrameDecoder::default(),
+            batched: VecDeque::new(),
+            opener: None,
+            encoder: Some(FrameEncoder::default()),
+            outgoing: vec![],
+            payment_pending: false,
+            rules_pending: false,
+            #[cfg(feature = "noise")]
+            noise: None,
+            #[cfg(feature = "noise")]
+            noise_key: None,
+            #[cfg(feature = "noise")]
+            hello: None,
+        }
+    }
+
+    /// Server: the wire formats to accept from `hello.wire_format` (default all of them).
+    pub fn with_wire_formats(mut self, formats: Vec<WireFormat>) -> Self {
+        self.wire_formats = formats;
+        self
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Buffer bytes received from the peer; `poll_event` decodes them.
+    pub fn receive(&mut self, bytes: &[u8]) {
+        self.decoder.push(bytes);
+    }
+
+    /// The next event from the received bytes, or `None` until more arrive. While a `Hello`
+    /// waits for `accept`, later frames stay buffered. Errors close the session.
+    pub fn poll_event(&mut self) -> Result<Option<Event>, ProtocolError> {
+        #[cfg(feature = "noise")]
+        if self.state == State::Noise || self.state == State::AwaitingHello {
+            match self.poll_noise() {
+                Ok(true) => {}
+                Ok(false) => return Ok(None),
+                Err(e) => return Err(self.fail(e)),
+            }
+        }
+        let expected = match self.state {
+            State::AwaitingAccept | State::Noise => return Ok(None),
+            State::Closed => return Err(ProtocolError::State(State::Closed)),
+            State::AwaitingHello => "hello",
+            State::AwaitingWelcome => "welcome",
+            State::AwaitingPayment => "payment_sent",
+            State::AwaitingRulesAck => "rules_ack",
+            State::Open => "a session message",
+        };
+        let msg = match self.next_message() {
+            Ok(Some(msg)) => msg,
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(self.fail(e.into())),
+        };
+        let event = match (self.state, msg) {
+            (State::AwaitingHello, Message::Hello(hello)) => {
+                self.request_id = hello.request_id;
+                let speaks = std::mem::take(&mut self.wire_formats);
+                self.wire_formats = hello
+                    .wire_format
+                    .iter()
+                    .copied()
+                    .find(|f| speaks.contains(f))
+                    .into_iter()
+                    .collect();
+                let can_use = std::mem::take(&mut self.compressions);
+                self.compressions = hello
+                    .compression
+                    .iter()
+                    .copied()
+                    .find(|c| can_use.contains(c))
+                    .into_iter()
+                    .collect();
+                match (hello.world_id, self.world_id) {
+                    _ if !is_compatible_version(&hello.protocol_version) => {
+                        let requested = hello.protocol_version;
+                        let message = format!(
+                            "protocol version {requested} is not supported; this server speaks \
+                             {OWP_PROTOCOL_VERSION}"
+                        );
+                        self.reject(ErrorCode::VersionMismatch, message)?;
+                        Event::VersionMismatch { requested }
+                    }
+                    (Some(requested), Some(served)) if requested != served => {
+                        let message = format!("this server serves world {served}");
+                        self.reject(ErrorCode::WorldNotFound, message)?;
+                        Event::WrongWorld { requested }
+                    }
+                    // Already encrypted by Noise.
+                    _ if self.opener.is_some() => {
+                        self.state = State::AwaitingAccept;
+                        Event::Hello(hello)
+                    }
+                    // Only Noise supplies AEAD keys, so a plain session never picks it.
+                    _ => {
+                        self.protection = hello
+                            .frame_protection
+                            .iter()
+                            .copied()
+                            .find(|p| FrameSealer::supports(*p, false))
+                            .into_iter()
+                            .collect();
+                        self.state = State::AwaitingAccept;
+                        Event::Hello(hello)
+                    }
+                }
+            }
+            (State::AwaitingWelcome, Message::Welcome(welcome)) => {
+                if self.world_id.is_some_and(|w| w != welcome.world_id) {
+                    return Err(self.fail(ProtocolError::WrongWorld(welcome.world_id)));
+                }
+                if !is_compatible_version(&welcome.protocol_version) {
+                    return Err(self.fail(ProtocolError::Version(welcome.protocol_version)));
+                }
+                if let Some(p) = welcome.frame_protection {
+                    let keyed = self.opener.is_some();
+                    if keyed || !self.protection.contains(&p) || !self.protect(p) {
+                        return Err(self.fail(ProtocolError::Protection(p)));
+                    }
+                }
+                if let Some(f) = welcome.wire_format {
+                    if f != WireFormat::Json && !self.wire_formats.contains(&f) {
+                        return Err(self.fail(ProtocolError::WireFormat(f)));
+                    }
+                    self.switch_format(f);
+                }
+                if let Some(c) = welcome.compression {
+                    if !self.compressions.contains(&c) {
+                        return Err(self.fail(ProtocolError::Compression(c)));
+                    }
+                    self.switch_compression(c);
+                }
+                self.state = State::Open;
+                Event::Welcome(welcome)
+            }
+            (State::AwaitingWelcome, Message::PaymentRequired(p)) => {
+                self.payment_pending = true;
+                Event::PaymentRequired(p)
+            }
+            (State::AwaitingPayment, Message::PaymentSent(p)) => {
+                self.state = State::AwaitingAccept;
+                Event::PaymentSent(p)
+            }
+            (State::AwaitingWelcome, Message::RulesRequired(r)) => {
+                self.rules_pending = true;
+                Event::RulesRequired(r)
+            }
+            (State::AwaitingRulesAck, Message::RulesAck(a)) => {
+                self.state = State::AwaitingAccept;
+                Event::RulesAck(a)
+            }
+            (State::AwaitingWelcome, Message::Error(e)) => {
+                return Err(self.fail(ProtocolError::Refused(Box::new(e))));
+            }
+            (State::Open, Message::Disconnect(d)) => {
+                // The peer hangs up next; nothing after it counts.
+                self.state = State::Closed;
+                Event::Message(Message::Disconnect(d))
+            }
+            (State::Open, msg) if !is_handshake(&msg) => Event::Message(msg),
+            (_, msg) => {
+                return Err(self.fail(ProtocolError::Unexpected {
+                    expected,
+                    got: Box::new(msg),
+                }));
+            }
+        };
+        Ok(Some(event))
+    }
+
+    /// Server: answer the `Hello` and open the session. `welcome.request_id`,
+    /// `welcome.frame_protection`, `welcome.wire_format` and `welcome.compression` are filled in
+    /// here.
+    pub fn accept(&mut self, mut welcome: Welcome) -> Result<(), ProtocolError> {
+        if self.state != State::AwaitingAccept {
+            return Err(ProtocolError::State(self.state));
+        }
+        welcome.request_id = self.request_id;
+        welcome.frame_protection = match self.opener {
+            // Noise already protects the session.
+            Some(_) => None,
+            None => self.protection.first().copied(),
+        };
+        welcome.wire_format = self.wire_formats.first().copied();
+        welcome.compression = self.compressions.first().copied();
+        self.queue(&Message::Welcome(welcome.clone()))?;
+        if let Some(p) = welcome.frame_protection {
+            // Chosen because `supports` said so.
+            assert!(self.protect(p), "negotiated protection unavailable");
+        }
+        if let Some(f) = welcome.wire_format {
+            self.switch_format(f);
+        }
+        if let Some(c) = welcome.compression {
+            self.switch_compression(c);
+        }
+        self.state = State::Open;
+        Ok(())
+    }
+
+    /// The protection in effect after the handshake, if any.
+    pub fn frame_protection(&self) -> Option<FrameProtection> {
+        let on = self.state == State::Open && self.opener.is_some();
+        on.then(|| self.protection[0])
+    }
+
+    /// The payload encoding in effect after the handshake.
+    pub fn wire_format(&self) -> WireFormat {
+        self.wire_format
+    }
+
+    /// The frame compression in effect after the handshake, if any.
+    pub fn compression(&self) -> Option<Compression> {
+        self.compression
+    }
+
+    /// Queue a message on an open session.
+    pub fn send(&mut self, msg: &Message) -> Result<(), ProtocolError> {
+        if self.state != State::Open {
+            return Err(ProtocolError::State(self.state));
+        }
+        if is_handshake(msg) {
+            return Err(ProtocolError::Unexpected {
+                expected: "a session message",
+                got: Box::new(msg.clone()),
+            });
+        }
+        let encoder = self.encoder.as_mut().ok_or(ProtocolError::EncoderTaken)?;
+        self.outgoing.extend(encoder.encode(msg)?);
+        Ok(())
+    }
+
+    /// Hand the encoder for outgoing frames to whatever writes them (e.g. a writer task), once
+    /// the session is open. `send` fails afterwards.
+    pub fn take_encoder(&mut self) -> Result<FrameEncoder, ProtocolError> {
+        if self.state != State::Open {
+            return Err(ProtocolError::State(self.state));
+        }
+        self.encoder.take().ok_or(ProtocolError::EncoderTaken)
+    }
+
+    /// Bytes to write to the peer, in order.
+    pub fn take_outgoing(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.outgoing)
+    }
+
+    /// Queue a handshake message. Only Noise sessions protect these.
+    fn queue(&mut self, msg: &Message) -> Result<(), ProtocolError> {
+        let encoder = self.encoder.as_mut().ok_or(ProtocolError::EncoderTaken)?;
+        let frame = encoder.encode(msg)?;
+        self.outgoing.extend(frame);
+        Ok(())
+    }
+
+    fn next_message(&mut self) -> Result<Option<Message>, WireError> {
+        if let Some(msg) = self.batched.pop_front() {
+            return Ok(Some(msg));
+        }
+        let Some(body) = self.decoder.next_body()? else {
+            return Ok(None);
+        };
+        let mut payload = match &mut self.opener {
+            Some(o) => o.open(body)?,
+            None => body,
+        };
+        if self.compression.is_some() {
+            payload = wire::decompress(payload)?;
+        }
+        let frame = wire::decode_frame_payload(&payload, self.wire_format)?;
+        self.batched = frame.into_messages().into();
+        Ok(self.batched.pop_front())
+    }
+
+    /// Frame directions as (sent, received).
+    fn directions(&self) -> (Direction, Direction) {
+        if self.client {
+            (Direction::ToServer, Direction::ToClient)
+        } else {
+            (Direction::ToClient, Direction::ToServer)
+        }
+    }
+
+    /// Switch both directions to `p`; false if it can't be used here.
+    fn protect(&mut self, p: FrameProtection) -> bool {
+        let (send, recv) = self.directions();
+        let (Some(sealer), Some(opener)) = (
+            FrameSealer::new(p, None, send),
+            FrameSealer::new(p, None, recv),
+        ) else {
+            return false;
+        };
+        self.encoder = Some(FrameEncoder::new(Some(sealer)));
+        self.opener = Some(opener);
+        self.protection = vec![p];
+        true
+    }
+
+    /// Encode and decode frames as `f` from now on. Goes after `protect`, which replaces the
+    /// encoder.
+    fn switch_format(&mut self, f: WireFormat) {
+        self.wire_format = f;
+        self.encoder = self.encoder.take().map(|e| e.with_format(f));
+    }
+
+    /// Compress and expect compressed frames from now on. Also goes after `protect`.
+    fn switch_compression(&mut self, c: Compression) {
+        self.compression = Some(c);
+        self.encoder = self.encoder.take().map(|e| e.with_compression(Some(c)));
+    }
+
+    fn fail(&mut self, e: ProtocolError) -> ProtocolError {
+        self.state = State::Closed;
+        e
+    }
+
+    /// Server: ask for an entry fee instead of answering the `Hello` yet. `poll_event` then
+    /// yields the client's `PaymentSent`.
+    pub fn require_payment(&mut self, request: PaymentRequired) -> Result<(), ProtocolError> {
+        if self.state != State::AwaitingAccept {
+            return Err(ProtocolError::State(self.state));
+        }
+        self.queue(&Message::PaymentRequired(request))?;
+        self.state = State::AwaitingPayment;
+        Ok(())
+    }
+
+    /// Client: report the payment asked for by `Event::PaymentRequired`.
+    pub fn payment_sent(&mut self, sent: PaymentSent) -> Result<(), ProtocolError> {
+        if self.state != State::AwaitingWelcome || !self.payment_pending {
+            return Err(ProtocolError::State(self.state));
+        }
+        self.payment_pending = false;
+        self.queue(&Message::PaymentSent(sent))
+    }
+
+    /// Server: ask the client to acknowledge the world's rules before answering the `Hello`.
+    /// `poll_event` then yields the client's `RulesAck`.
+    pub fn require_rules_ack(&mut self, request: RulesRequired) -> Result<(), ProtocolError> {
+        if self.state != State::AwaitingAccept {
+            return Err(ProtocolError::State(self.state));
+        }
+        self.queue(&Message::RulesRequired(request))?;
+        self.state = State::AwaitingRulesAck;
+        Ok(())
+    }
+
+    /// Client: accept the rules announced by `Event::RulesRequired`.
+    pub fn ack_rules(&mut self, ack: RulesAck) -> Result<(), ProtocolError> {
+        if self.state != State::AwaitingWelcome || !self.rules_pending {
+            return Err(ProtocolError::State(self.state));
+        }
+        self.rules_pending = false;
+        self.queue(&Message::RulesAck(ack))
+    }
+
+    /// Server: turn the `Hello` down with `code` instead of answering it, and close the
+    /// session. Flush the queued `Error` before hanging up.
+    pub fn reject(&mut self, code: ErrorCode, message: String) -> Result<(), ProtocolError> {
+        let open = matches!(
+            self.state,
+            State::AwaitingHello
+                | State::AwaitingAccept
+                | State::AwaitingPayment
+                | State::AwaitingRulesAck
+        );
+        if !open || self.client {
+            return Err(ProtocolError::State(self.state));
+        }
+        let supported_versions = match code {
+            ErrorCode::VersionMismatch => vec![OWP_PROTOCOL_VERSION.to_string()],
+            _ => vec![],
+        };
+        self.queue(&Message::Error(ErrorMessage {
+            code,
+            message,
+            request_id: Some(self.request_id),
+            supported_versions,
+        }))?;
+        self.state = State::Closed;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "noise")]
+impl ProtocolStateMachine {
+    /// Client side over Noise. `server_static` is the key the server must prove it holds
+    /// ([`noise::server_static_public`] of the world authority); `None` accepts any server but
+    /// still encrypts. `hello` is sent once the Noise handshake is done.
+    pub fn noise_client(
+        hello: Hello,
+        server_static: Option<[u8; 32]>,
+    ) -> Result<Self, ProtocolError> {
+        let (handshake, first) = Handshake::initiator(server_static)?;
+        let mut sm = Self::new(State::Noise, true, hello.world_id);
+        sm.request_id = hello.request_id;
+        sm.noise = Some(handshake);
+        sm.hello = Some(hello);
+        sm.outgoing.extend(noise::PREAMBLE);
+        sm.outgoing.extend(wire::frame(first)?);
+        Ok(sm)
+    }
+
+    /// Server: accept Noise clients, proving possession of `static_secret`
+    /// ([`noise::server_static_secret`] of the world authority seed). Plain clients are still
+    /// served.
+    pub fn with_noise_key(mut self, static_secret: [u8; 32]) -> Self {
+        self.noise_key = Some(static_secret);
+        self
+    }
+
+    /// Runs the Noise handshake over whatever has arrived. False while waiting for more bytes.
+    fn poll_noise(&mut self) -> Result<bool, ProtocolError> {
+        if self.state == State::AwaitingHello {
+            // Only the very first bytes of a connection may be the preamble.
+            if self.opener.is_some() {
+                return Ok(true);
+            }
+            match self.decoder.take_prefix(&noise::PREAMBLE) {
+                None => return Ok(false),
+                Some(false) => return Ok(true),
+                Some(true) => {}
+            }
+            let key = self.noise_key.ok_or(NoiseError::NotOffered)?;
+            self.noise = Some(Handshake::responder(&key)?);
+            self.state = State::Noise;
+        }
+        while self.state == State::Noise {
+            let Some(body) = self.decoder.next_body()? else {
+                return Ok(false);
+            };
+            let handshake = self.noise.as_mut().expect("noise state has a handshake");
+            if let Some(reply) = handshake.read(&body)? {
+                self.outgoing.extend(wire::frame(reply)?);
+            }
+            if handshake.is_finished() {
+                let keys = self.noise.take().expect("handshake").into_keys();
+                self.install_noise_keys(&keys);
+                match self.hello.take() {
+                    Some(hello) => {
+                        self.state = State::AwaitingWelcome;
+                        self.queue(&Message::Hello(hello))?;
+                    }
+                    None => self.state = State::AwaitingHello,
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    fn install_noise_keys(&mut self, keys: &Keys) {
+        let p = FrameProtection::Chacha20Poly1305;
+        let (send, recv) = self.directions();
+        let sealer = FrameSealer::new(p, Some(&keys.send), send);
+        self.encoder = Some(FrameEncoder::new(sealer));
+        self.opener = FrameSealer::new(p, Some(&keys.recv), recv);
+        self.protection = vec![p];
+    }
+}
+
+fn is_handshake(msg: &Message) -> bool {
+    matches!(
+        msg,
+        Message::Hello(_)
+            | Message::Welcome(_)
+            | Message::PaymentRequired(_)
+            | Message::PaymentSent(_)
+            | Message::RulesRequired(_)
+            | Message::RulesAck(_)
+    )
+}
+
+#[cfg(feature = "tokio")]
+impl ProtocolStateMachine {
+    /// Write out everything queued.
+    pub async fn flush<W: AsyncWrite + Unpin>(&mut self, writer: &mut W) -> Result<(), WireError> {
+        let out = self.take_outgoing();
+        if !out.is_empty() {
+            writer.write_all(&out).await?;
+            writer.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Read until the next event. Cancel-safe: bytes already read stay buffered. End of stream
+    /// is `WireError::Io` with `UnexpectedEof`.
+    pub async fn next_event<R: AsyncRead + Unpin>(
+        &mut self,
+        reader: &mut R,
+    ) -> Result<Event, ProtocolError> {
+        let mut buf = [0u8; 8192];
+        loop {
+            if let Some(event) = self.poll_event()? {
+                return Ok(event);
+            }
+            let n = reader.read(&mut buf).await.map_err(WireError::from)?;
+            if n == 0 {
+                let eof = std::io::Error::from(std::io::ErrorKind::UnexpectedEof);
+                return Err(WireError::Io(eof).into());
+            }
+            self.receive(&buf[..n]);
+        }
+    }
+
+    /// `next_event` for the handshake: also writes what the machine queues while waiting (the
+    /// client's `Hello`, Noise handshake replies), so the peer isn't left waiting on us.
+    pub async fn next_handshake_event<S: AsyncRead + AsyncWrite + Unpin>(
+        &mut self,
+        stream: &mut S,
+    ) -> Result<Event, ProtocolError> {
+        let mut buf = [0u8; 8192];
+        loop {
+            if let Some(event) = self.poll_event()? {
+                return Ok(event);
+            }
+            self.flush(stream).await?;
+            let n = stream.read(&mut buf).await.map_err(WireError::from)?;
+            if n == 0 {
+                let eof = std::io::Error::from(std::io::ErrorKind::UnexpectedEof);
+                return Err(WireError::Io(eof).into());
+            }
+            self.receive(&buf[..n]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Disconnect, DisconnectReason, Emote};
+
+    fn hello(world_id: Option<Uuid>) -> Hello {
+        Hello {
+            protocol_version: OWP_PROTOCOL_VERSION.to_string(),
+            request_id: Uuid::new_v4(),
+            world_id,
+            client_name: Some("test".to_string()),
+            resume_token: None,
+            party_token: None,
+            wallet_pubkey: None,
+            wallet_proof: None,
+            profile_id: None,
+            frame_protection: vec![],
+            wire_format: vec![],
+            compression: vec![],
+            attestation_nonce: None,
+            capabilities: vec![],
+        }
+    }
+
+    fn welcome_for(world_id: Uuid) -> Welcome {
+        Welcome {
+            protocol_version: OWP_PROTOCOL_VERSION.to_string(),
+            request_id: Uuid::nil(),
+            world_id,
+            token_mint: None,
+            motd: Some("hi".to_string()),
+            capabilities: vec![],
+            session_token: None,
+            resumed: false,
+            prefetch: vec![],
+            player_id: None,
+            party: None,
+            player_count: None,
+            frame_protection: None,
+            wire_format: None,
+            compression: None,
+            attestation: None,
+            wallet_verified: None,
+        }
+    }
+
+    fn wave() -> Message {
+        Message::Emote(Emote {
+            id: "wave".to_string(),
+            duration_ms: 1000,
+            player_id: None,
+        })
+    }
+
+    /// Move `from`'s queued bytes to `to` a few at a time.
+    fn pipe(from: &mut ProtocolStateMachine, to: &mut ProtocolStateMachine) {
+        for piece in from.take_outgoing().chunks(5) {
+            to.receive(piece);
+        }
+    }
+
+    #[test]
+    fn handshake_then_messages_both_ways() {
+        let world_id = Uuid::new_v4();
+        let mut client = ProtocolStateMachine::client(hello(Some(world_id))).expect("client");
+        let mut server = ProtocolStateMachine::server(world_id);
+        assert!(matches!(
+            client.send(&wave()),
+            Err(ProtocolError::State(State::AwaitingWelcome))
+        ));
+
+        pipe(&mut client, &mut server);
+        let Some(Event::Hello(h)) = server.poll_event().expect("hello") else {
+            panic!("expected hello");
+        };
+        // A message pipelined behind `Hello` waits for `accept`.
+        client.outgoing = wire::encode_frame(&wave()).expect("encode");
+        pipe(&mut client, &mut server);
+        assert!(server.poll_event().expect("held").is_none());
+
+        let welcome = welcome_for(world_id);
+        server.accept(welcome).expect("accept");
+        assert!(matches!(
+            server.poll_event().expect("pipelined"),
+            Some(Event::Message(Message::Emote(_)))
+        ));
+        pipe(&mut server, &mut client);
+        let Some(Event::Welcome(w)) = client.poll_event().expect("welcome") else {
+            panic!("expected welcome");
+        };
+        assert_eq!(w.request_id, h.request_id);
+        assert_eq!(client.state(), State::Open);
+
+        server.send(&wave()).expect("send");
+        pipe(&mut server, &mut client);
+        assert!(matches!(
+            client.poll_event().expect("push"),
+            Some(Event::Message(Message::Emote(_)))
+        ));
+        assert!(server.send(&Message::Hello(hello(None))).is_err());
+
+        // A goodbye closes the receiving side.
+        let bye = Message::Disconnect(Disconnect {
+            reason: DisconnectReason::ClientQuit,
+            message: None,
+        });
+        client.send(&bye).expect("bye");
+        client.send(&wave()).expect("send");
+        pipe(&mut client, &mut server);
+        assert!(matches!(
+            server.poll_event().expect("bye"),
+            Some(Event::Message(Message::Disconnect(_)))
+        ));
+        assert_eq!(server.state(), State::Closed);
+        assert!(server.poll_event().is_err());
+    }
+
+    fn open_pair(offer: Vec<FrameProtection>) -> (ProtocolStateMachine, ProtocolStateMachine) {
+        let world_id = Uuid::new_v4();
+        let mut hello = hello(Some(world_id));
+        hello.frame_protection = offer;
+        let mut client = ProtocolStateMachine::client(hello).expect("client");
+        let mut server = ProtocolStateMachine::server(world_id);
+        pipe(&mut client, &mut server);
+        assert!(matches!(server.poll_event(), Ok(Some(Event::Hello(_)))));
+        let welcome = welcome_for(world_id);
+        server.accept(welcome).expect("accept");
+        pipe(&mut server, &mut client);
+        assert!(matches!(client.poll_event(), Ok(Some(Event::Welcome(_)))));
+        (client, server)
+    }
+
+    #[test]
+    fn negotiated_protection_covers_frames_after_welcome() {
+        let both = vec![FrameProtection::Chacha20Poly1305, FrameProtection::Crc32c];
+        // Outside Noise there is no key: AEAD is skipped for the next offer.
+        let (mut client, mut server) = open_pair(both);
+        assert_eq!(client.frame_protection(), Some(FrameProtection::Crc32c));
+        assert_eq!(server.frame_protection(), Some(FrameProtection::Crc32c));
+        client.send(&wave()).expect("send");
+        let mut frame = client.take_outgoing();
+        let last = frame.len() - 1;
+        frame[last] ^= 0x40;
+        server.receive(&frame);
+        assert!(matches!(
+            server.poll_event(),
+            Err(ProtocolError::Wire(WireError::Integrity))
+        ));
+
+        let (mut client, mut server) = open_pair(vec![FrameProtection::Chacha20Poly1305]);
+        assert_eq!(server.frame_protection(), None);
+        let mut encoder = server.take_encoder().expect("encoder");
+        assert!(matches!(
+            server.send(&wave()),
+            Err(ProtocolError::EncoderTaken)
+        ));
+        client.receive(&encoder.encode(&wave()).expect("encode"));
+        assert!(matches!(
+            client.poll_event(),
+            Ok(Some(Event::Message(Message::Emote(_))))
+        ));
+
+        let (client, _) = open_pair(vec![]);
+        assert_eq!(client.frame_protection(), None);
+    }
+
+    #[test]
+    fn negotiated_wire_format_applies_after_welcome() {
+        let world_id = Uuid::new_v4();
+        let handshake = |speaks: Vec<WireFormat>| {
+            let mut hello = hello(Some(world_id));
+            hello.frame_protection = vec![FrameProtection::Crc32c];
+            hello.wire_format = vec![WireFormat::Msgpack, WireFormat::Json];
+            let mut client = ProtocolStateMachine::client(hello).expect("client");
+            let mut server = ProtocolStateMachine::server(world_id).with_wire_formats(speaks);
+            pipe(&mut client, &mut server);
+            assert!(matches!(server.poll_event(), Ok(Some(Event::Hello(_)))));
+            server.accept(welcome_for(world_id)).expect("accept");
+            pipe(&mut server, &mut client);
+            let Ok(Some(Event::Welcome(w))) = client.poll_event() else {
+                panic!("expected welcome");
+            };
+            (client, server, w)
+        };
+
+        let (mut client, mut server, welcome) = handshake(WireFormat::ALL.to_vec());
+        assert_eq!(welcome.wire_format, Some(WireFormat::Msgpack));
+        assert_eq!(client.wire_format(), WireFormat::Msgpack);
+        client.send(&wave()).expect("send");
+        let frame = client.take_outgoing();
+        // Length prefix, then a MessagePack map (not `{`), then the CRC.
+        assert_ne!(frame[4], b'{');
+        server.receive(&frame);
+        assert!(matches!(
+            server.poll_event(),
+            Ok(Some(Event::Message(Message::Emote(_))))
+        ));
+        server.send(&wave()).expect("send");
+        pipe(&mut server, &mut client);
+        assert!(matches!(
+            client.poll_event(),
+            Ok(Some(Event::Message(Message::Emote(_))))
+        ));
+
+        let (client, _, welcome) = handshake(vec![WireFormat::Json]);
+        assert_eq!(welcome.wire_format, Some(WireFormat::Json));
+        assert_eq!(client.wire_format(), WireFormat::Json);
+
+        // A pick the client never offered closes the session.
+        let mut client = ProtocolStateMachine::client(hello(Some(world_id))).expect("client");
+        let mut welcome = welcome_for(world_id);
+        welcome.wire_format = Some(WireFormat::Msgpack);
+        client.receive(&wire::encode_frame(&Message::Welcome(welcome)).expect("encode"));
+        assert!(matches!(
+            client.poll_event(),
+            Err(ProtocolError::WireFormat(WireFormat::Msgpack))
+        ));
+        assert_eq!(client.state(), State::Closed);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn negotiated_compression_shrinks_large_frames_only() {
+        let world_id = Uuid::new_v4();
+        let handshake = |offer: Vec<Compression>| {
+            let mut hello = hello(Some(world_id));
+            hello.frame_protection = vec![FrameProtection::Crc32c];
+            hello.compression = offer;
+            let mut client = ProtocolStateMachine::client(hello).expect("client");
+            let mut server = ProtocolStateMachine::server(world_id);
+            pipe(&mut client, &mut server);
+            assert!(matches!(server.poll_event(), Ok(Some(Event::Hello(_)))));
+            server.accept(welcome_for(world_id)).expect("accept");
+            pipe(&mut server, &mut client);
+            let Ok(Some(Event::Welcome(w))) = client.poll_event() else {
+                panic!("expected welcome");
+            };
+            (client, server, w)
+        };
+        let long = Message::Emote(Emote {
+            id: "wave ".repeat(1000),
+            duration_ms: 1000,
+            player_id: None,
+        });
+
+        let (mut client, mut server, welcome) = handshake(vec![Compression::Zstd]);
+        assert_eq!(welcome.compression, Some(Compression::Zstd));
+        assert_eq!(client.compression(), Some(Compression::Zstd));
+        for (msg, flag) in [(wave(), 0), (long.clone(), 1)] {
+            server.send(&msg).expect("send");
+            let frame = server.take_outgoing();
+            assert_eq!(frame[4], flag);
+            client.receive(&frame);
+            let Ok(Some(Event::Message(got))) = client.poll_event() else {
+                panic!("expected a message");
+            };
+            assert_eq!(
+                serde_json::to_string(&got).expect("json"),
+                serde_json::to_string(&msg).expect("json")
+            );
+        }
+        client.send(&long).expect("send");
+        let frame = client.take_outgoing();
+        assert!(frame.len() < 1000);
+        server.receive(&frame);
+        assert!(matches!(
+            server.poll_event(),
+            Ok(Some(Event::Message(Message::Emote(_))))
+        ));
+
+        let (client, _, welcome) = handshake(vec![]);
+        assert_eq!(welcome.compression, None);
+        assert_eq!(client.compression(), None);
+
+        // A pick the client never offered closes the session.
+        let mut client = ProtocolStateMachine::client(hello(Some(world_id))).expect("client");
+        let mut welcome = welcome_for(world_id);
+        welcome.compression = Some(Compression::Zstd);
+        client.receive(&wire::encode_frame(&Message::Welcome(welcome)).expect("encode"));
+        assert!(matches!(
+            client.poll_event(),
+            Err(ProtocolError::Compression(Compression::Zstd))
+        ));
+    }
+
+    #[cfg(feature = "noise")]
+    #[test]
+    fn noise_sessions_encrypt_everything_and_check_the_server_key() {
+        let seed = [4u8; 32];
+        let authority = ed25519_dalek::SigningKey::from_bytes(&seed).verifying_key();
+        let server_static = noise::server_static_public(authority.as_bytes()).expect("key");
+        let world_id = Uuid::new_v4();
+        let keyed_server = || {
+            ProtocolStateMachine::server(world_id)
+                .with_noise_key(noise::server_static_secret(&seed))
+        };
+
+        let mut hello = hello(Some(world_id));
+        hello.client_name = Some("secret-name".to_string());
+        let mut client =
+            ProtocolStateMachine::noise_client(hello, Some(server_static)).expect("client");
+        let mut server = keyed_server();
+        // Three handshake messages, then the sealed `Hello`.
+        while client.state() == State::Noise {
+            pipe(&mut client, &mut server);
+            assert!(server.poll_event().expect("noise").is_none());
+            pipe(&mut server, &mut client);
+            assert!(client.poll_event().expect("noise").is_none());
+        }
+        let sent = client.take_outgoing();
+        assert!(!sent.windows(11).any(|w| w == b"secret-name"));
+        server.receive(&sent);
+        assert!(matches!(server.poll_event(), Ok(Some(Event::Hello(_)))));
+        let welcome = welcome_for(world_id);
+        server.accept(welcome).expect("accept");
+        pipe(&mut server, &mut client);
+        let Some(Event::Welcome(w)) = client.poll_event().expect("welcome") else {
+            panic!("expected welcome");
+        };
+        assert_eq!(w.frame_protection, None);
+        assert_eq!(
+            client.frame_protection(),
+            Some(FrameProtection::Chacha20Poly1305)
+        );
+        client.send(&wave()).expect("send");
+        pipe(&mut client, &mut server);
+        assert!(matches!(
+            server.poll_event(),
+            Ok(Some(Event::Message(Message::Emote(_))))
+        ));
+
+        // A server holding some other key is refused.
+        let mut client =
+            ProtocolStateMachine::noise_client(self::hello(None), Some(server_static)).expect("c");
+        let mut impostor = ProtocolStateMachine::server(world_id)
+            .with_noise_key(noise::server_static_secret(&[6; 32]));
+        pipe(&mut client, &mut impostor);
+        assert!(impostor.poll_event().expect("m1").is_none());
+        pipe(&mut impostor, &mut client);
+        assert!(matches!(
+            client.poll_event(),
+            Err(ProtocolError::Noise(NoiseError::ServerIdentity))
+        ));
+
+        // Plain clients still get in; Noise clients of a server without a key don't.
+        let (mut plain, mut server) = (
+            ProtocolStateMachine::client(self::hello(None)).expect("c"),
+            keyed_server(),
+        );
+        pipe(&mut plain, &mut server);
+        assert!(matches!(server.poll_event(), Ok(Some(Event::Hello(_)))));
+        let mut client = ProtocolStateMachine::noise_client(self::hello(None), None).expect("c");
+        let mut keyless = ProtocolStateMachine::server(world_id);
+        pipe(&mut client, &mut keyless);
+        assert!(matches!(
+            keyless.poll_event(),
+            Err(ProtocolError::Noise(NoiseError::NotOffered))
+        ));
+    }
+
+    #[test]
+    fn wrong_world_and_bad_first_message_close_the_session() {
+        let served = Uuid::new_v4();
+        let mut client = ProtocolStateMachine::client(hello(Some(Uuid::new_v4()))).expect("c");
+        let mut server = ProtocolStateMachine::server(served);
+        pipe(&mut client, &mut server);
+        assert!(matches!(
+            server.poll_event().expect("hello"),
+            Some(Event::WrongWorld { .. })
+        ));
+        assert_eq!(server.state(), State::Closed);
+        pipe(&mut server, &mut client);
+        assert!(matches!(
+            client.poll_event(),
+            Err(ProtocolError::Refused(e)) if e.code == ErrorCode::WorldNotFound
+        ));
+        assert_eq!(client.state(), State::Closed);
+
+        let mut old = hello(Some(served));
+        old.protocol_version = "0.2".to_string();
+        let mut client = ProtocolStateMachine::client(old).expect("c");
+        let mut server = ProtocolStateMachine::server(served);
+        pipe(&mut client, &mut server);
+        assert!(matches!(
+            server.poll_event().expect("hello"),
+            Some(Event::VersionMismatch { requested }) if requested == "0.2"
+        ));
+        pipe(&mut server, &mut client);
+        let Err(ProtocolError::Refused(e)) = client.poll_event() else {
+            panic!("expected a refusal");
+        };
+        assert_eq!(e.code, ErrorCode::VersionMismatch);
+        assert_eq!(e.supported_versions, [OWP_PROTOCOL_VERSION]);
+
+        // A client checks the server's version too.
+        let mut client = ProtocolStateMachine::client(hello(Some(served))).expect("c");
+        let mut newer = welcome_for(served);
+        newer.protocol_version = "1.0".to_string();
+        client.receive(&wire::encode_frame(&Message::Welcome(newer)).expect("encode"));
+        assert!(matches!(client.poll_event(), Err(ProtocolError::Version(v)) if v == "1.0"));
+
+        let mut server = ProtocolStateMachine::server(served);
+        server.receive(&wire::encode_frame(&wave()).expect("encode"));
+        assert!(matches!(
+            server.poll_event(),
+            Err(ProtocolError::Unexpected {
+                expected: "hello",
+                ..
+            })
+        ));
+        assert!(server.poll_event().is_err());
+    }
+
+    #[test]
+    fn entry_fees_are_paid_before_welcome() {
+        let world_id = Uuid::new_v4();
+        let mut client = ProtocolStateMachine::client(hello(Some(world_id))).expect("client");
+        let mut server = ProtocolStateMachine::server(world_id);
+        pipe(&mut client, &mut server);
+        let Some(Event::Hello(h)) = server.poll_event().expect("hello") else {
+            panic!("expected hello");
+        };
+        assert!(client
+            .payment_sent(PaymentSent {
+                reference: "ref".to_string(),
+                signature: None,
+            })
+            .is_err());
+        server
+            .require_payment(PaymentRequired {
+                request_id: h.request_id,
+                recipient: "recipient".to_string(),
+                amount: 5,
+                mint: None,
+                reference: "ref".to_string(),
+                expires_at: 0,
+                pay_url: "solana:recipient?amount=0.000000005".to_string(),
+            })
+            .expect("require payment");
+        assert_eq!(server.state(), State::AwaitingPayment);
+        assert!(server.accept(welcome_for(world_id)).is_err());
+
+        pipe(&mut server, &mut client);
+        let Some(Event::PaymentRequired(p)) = client.poll_event().expect("payment request") else {
+            panic!("expected a payment request");
+        };
+        assert_eq!(client.state(), State::AwaitingWelcome);
+        client
+            .payment_sent(PaymentSent {
+                reference: p.reference,
+                signature: Some("sig".to_string()),
+            })
+            .expect("paid");
+        pipe(&mut client, &mut server);
+        let Some(Event::PaymentSent(sent)) = server.poll_event().expect("payment sent") else {
+            panic!("expected payment_sent");
+        };
+        assert_eq!(sent.reference, "ref");
+        server.accept(welcome_for(world_id)).expect("accept");
+        pipe(&mut server, &mut client);
+        assert!(matches!(client.poll_event(), Ok(Some(Event::Welcome(_)))));
+        assert!(client
+            .send(&Message::PaymentSent(PaymentSent {
+                reference: "ref".to_string(),
+                signature: None,
+            }))
+            .is_err());
+    }
+
+    #[test]
+    fn rules_are_acknowledged_before_payment_and_welcome() {
+        let world_id = Uuid::new_v4();
+        let mut client = ProtocolStateMachine::client(hello(Some(world_id))).expect("client");
+        let mut server = ProtocolStateMachine::server(world_id);
+        pipe(&mut client, &mut server);
+        let Some(Event::Hello(h)) = server.poll_event().expect("hello") else {
+            panic!("expected hello");
+        };
+        let ack = || RulesAck {
+            sha256: "abc".to_string(),
+        };
+        assert!(client.ack_rules(ack()).is_err());
+        server
+            .require_rules_ack(RulesRequired {
+                request_id: h.request_id,
+                sha256: "abc".to_string(),
+                uri: "/content/abc".to_string(),
+            })
+            .expect("require rules");
+        assert_eq!(server.state(), State::AwaitingRulesAck);
+        assert!(server.accept(welcome_for(world_id)).is_err());
+
+        pipe(&mut server, &mut client);
+        let Some(Event::RulesRequired(r)) = client.poll_event().expect("rules") else {
+            panic!("expected rules_required");
+        };
+        assert_eq!(r.uri, "/content/abc");
+        client.ack_rules(ack()).expect("ack");
+        assert!(client.ack_rules(ack()).is_err());
+        pipe(&mut client, &mut server);
+        let Some(Event::RulesAck(a)) = server.poll_event().expect("ack") else {
+            panic!("expected rules_ack");
+        };
+        assert_eq!(a.sha256, "abc");
+        // A fee can still be asked for once the rules are settled.
+        server
+            .require_payment(PaymentRequired {
+                request_id: h.request_id,
+                recipient: "recipient".to_string(),
+                amount: 5,
+                mint: None,
+                reference: "ref".to_string(),
+                expires_at: 0,
+                pay_url: "solana:recipient?amount=0.000000005".to_string(),
+            })
+            .expect("require payment");
+        server
+            .reject(ErrorCode::PaymentRequired, "unpaid".to_string())
+            .expect("reject");
+        pipe(&mut server, &mut client);
+        assert!(matches!(
+            client.poll_event(),
+            Ok(Some(Event::PaymentRequired(_)))
+        ));
+        assert!(matches!(
+            client.poll_event(),
+            Err(ProtocolError::Refused(_))
+        ));
+    }
+}
@@ -1,43 +1,342 @@
-use crate::Message;
+use crate::{Compression, Message, WireFormat};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::collections::VecDeque;
+#[cfg(feature = "tokio")]
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 pub const MAX_FRAME_LEN: usize = 4 * 1024 * 1024; // 4 MiB
 
-pub fn encode_frame(message: &Message) -> Result<Vec<u8>, serde_json::Error> {
-    let payload = serde_json::to_vec(message)?;
-    let mut out = Vec::with_capacity(4 + payload.len());
-    let len = u32::try_from(payload.len()).unwrap_or(u32::MAX);
+/// Frame `message` as JSON for sending. Fails with `FrameLength` if its JSON is over
+/// `MAX_FRAME_LEN`, which the receiving side would refuse anyway.
+pub fn encode_frame(message: &Message) -> Result<Vec<u8>, WireError> {
+    encode_frame_as(message, WireFormat::Json)
+}
+
+/// `encode_frame` in the format negotiated in `welcome.wire_format`.
+pub fn encode_frame_as(message: &Message, format: WireFormat) -> Result<Vec<u8>, WireError> {
+    frame(encode_payload(message, format)?)
+}
+
+pub(crate) fn encode_payload(message: &Message, format: WireFormat) -> Result<Vec<u8>, WireError> {
+    match format {
+        WireFormat::Json => Ok(serde_json::to_vec(message)?),
+        WireFormat::Msgpack => {
+            let mut out = vec![];
+            // Human-readable so ids and timestamps are encoded as they are in JSON.
+            let mut ser = rmp_serde::Serializer::new(&mut out)
+                .with_struct_map()
+                .with_human_readable();
+            message.serialize(&mut ser)?;
+            Ok(out)
+        }
+    }
+}
+
+/// What a frame carries: one message, or several packed into one payload so a burst of small
+/// ones (a tick's worth of relayed positions and emotes) costs one length prefix, one seal and one
+/// write. A batch payload is an array of messages where a single one is an object, so decoders
+/// tell them apart by the first byte. Peers only send batches to those granted
+/// `Capability::Batch`.
+#[derive(Debug, Clone)]
+pub enum Frame {
+    Message(Box<Message>),
+    Batch(Vec<Message>),
+}
+
+impl Frame {
+    pub fn into_messages(self) -> Vec<Message> {
+        match self {
+            Frame::Message(msg) => vec![*msg],
+            Frame::Batch(msgs) => msgs,
+        }
+    }
+}
+
+/// Most messages `encode_batch_as` packs into one frame.
+pub const MAX_BATCH_LEN: usize = 256;
+
+/// Frame `messages` as one batch in `format`. Fails like `encode_frame_as` if the batch is over
+/// `MAX_FRAME_LEN`, and with `BatchLength` if it is empty or over `MAX_BATCH_LEN` messages.
+pub fn encode_batch_as(messages: &[Message], format: WireFormat) -> Result<Vec<u8>, WireError> {
+    frame(encode_batch_payload(messages, format)?)
+}
+
+pub(crate) fn encode_batch_payload(
+    messages: &[Message],
+    format: WireFormat,
+) -> Result<Vec<u8>, WireError> {
+    if messages.is_empty() || messages.len() > MAX_BATCH_LEN {
+        return Err(WireError::BatchLength(messages.len()));
+    }
+    match format {
+        WireFormat::Json => Ok(serde_json::to_vec(messages)?),
+        WireFormat::Msgpack => {
+            let mut out = vec![];
+            let mut ser = rmp_serde::Serializer::new(&mut out)
+                .with_struct_map()
+                .with_human_readable();
+            messages.serialize(&mut ser)?;
+            Ok(out)
+        }
+    }
+}
+
+/// Whether `payload` is a batch: a JSON or msgpack array rather than a single message's object.
+fn is_batch(payload: &[u8], format: WireFormat) -> bool {
+    match format {
+        WireFormat::Json => payload
+            .iter()
+            .find(|b| !b.is_ascii_whitespace())
+            .is_some_and(|&b| b == b'['),
+        // fixarray, array 16, array 32
+        WireFormat::Msgpack => payload
+            .first()
+            .is_some_and(|&b| matches!(b, 0x90..=0x9f | 0xdc | 0xdd)),
+    }
+}
+
+/// Decode a payload that may be a batch.
+pub(crate) fn decode_frame_payload(payload: &[u8], format: WireFormat) -> Result<Frame, WireError> {
+    if !is_batch(payload, format) {
+        return decode_payload(payload, format).map(|msg| Frame::Message(Box::new(msg)));
+    }
+    let messages: Vec<Message> = match format {
+        WireFormat::Json => serde_json::from_slice(payload)?,
+        WireFormat::Msgpack => {
+            let mut de = rmp_serde::Deserializer::from_read_ref(payload).with_human_readable();
+            Vec::deserialize(&mut de)?
+        }
+    };
+    if messages.is_empty() || messages.len() > MAX_BATCH_LEN {
+        return Err(WireError::BatchLength(messages.len()));
+    }
+    Ok(Frame::Batch(messages))
+}
+
+/// Compressed frames are only worth it from this payload size on; smaller ones go out as is.
+pub const COMPRESSION_THRESHOLD: usize = 512;
+
+/// First byte of a payload once compression is negotiated: what follows is as is or compressed.
+const PAYLOAD_RAW: u8 = 0;
+#[cfg_attr(not(feature = "zstd"), allow(dead_code))]
+const PAYLOAD_ZSTD: u8 = 1;
+
+#[cfg(feature = "zstd")]
+const ZSTD_LEVEL: i32 = 3;
+
+/// Prefix `payload` with its compression flag, compressing it if it is big enough and that
+/// actually makes it smaller. Goes before protection, so sealed frames cover the flag too.
+pub(crate) fn compress(payload: Vec<u8>, compression: Compression) -> Result<Vec<u8>, WireError> {
+    if payload.len() >= COMPRESSION_THRESHOLD {
+        match compression {
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => {
+                let packed = zstd::bulk::compress(&payload, ZSTD_LEVEL)?;
+                if packed.len() < payload.len() {
+                    let mut out = Vec::with_capacity(1 + packed.len());
+                    out.push(PAYLOAD_ZSTD);
+                    out.extend(packed);
+                    return Ok(out);
+                }
+            }
+            // Never negotiated by builds without it; raw payloads are always valid.
+            #[cfg(not(feature = "zstd"))]
+            Compression::Zstd => {}
+        }
+    }
+    let mut out = Vec::with_capacity(1 + payload.len());
+    out.push(PAYLOAD_RAW);
+    out.extend(payload);
+    Ok(out)
+}
+
+/// Undo `compress`. The result is held to `MAX_FRAME_LEN` like any uncompressed payload.
+pub(crate) fn decompress(mut body: Vec<u8>) -> Result<Vec<u8>, WireError> {
+    match body.first() {
+        Some(&PAYLOAD_RAW) => {
+            body.remove(0);
+            Ok(body)
+        }
+        #[cfg(feature = "zstd")]
+        Some(&PAYLOAD_ZSTD) => {
+            zstd::bulk::decompress(&body[1..], MAX_FRAME_LEN).map_err(|_| WireError::Compression)
+        }
+        _ => Err(WireError::Compression),
+    }
+}
+
+/// Length-prefix a frame body (the encoded payload, with its protection if any).
+pub(crate) fn frame(body: Vec<u8>) -> Result<Vec<u8>, WireError> {
+    if body.len() > MAX_FRAME_LEN {
+        return Err(WireError::FrameLength(body.len()));
+    }
+    let mut out = Vec::with_capacity(4 + body.len());
+    // Fits: MAX_FRAME_LEN is well below u32::MAX.
+    let len = body.len() as u32;
     out.extend_from_slice(&len.to_be_bytes());
-    out.extend_from_slice(&payload);
+    out.extend(body);
     Ok(out)
 }
 
+fn frame_len(header: [u8; 4]) -> Result<usize, WireError> {
+    let len = u32::from_be_bytes(header) as usize;
+    if len == 0 || len > MAX_FRAME_LEN {
+        return Err(WireError::FrameLength(len));
+    }
+    Ok(len)
+}
+
+pub(crate) fn decode_payload(payload: &[u8], format: WireFormat) -> Result<Message, WireError> {
+    match format {
+        WireFormat::Json => {
+            // Validate JSON before decoding to structured types for better errors in logs.
+            let _v: Value = serde_json::from_slice(payload)?;
+            let msg: Message = serde_json::from_slice(payload)?;
+            Ok(msg)
+        }
+        WireFormat::Msgpack => {
+            let mut de = rmp_serde::Deserializer::from_read_ref(payload).with_human_readable();
+            Ok(Message::deserialize(&mut de)?)
+        }
+    }
+}
+
+/// Decode the JSON frame at the start of `buf` without doing any I/O. `Ok(None)` until the
+/// whole frame is there; otherwise the message and how many bytes of `buf` it took.
+pub fn decode_frame(buf: &[u8]) -> Result<Option<(Message, usize)>, WireError> {
+    decode_frame_as(buf, WireFormat::Json)
+}
+
+/// `decode_frame` in the format negotiated in `welcome.wire_format`.
+pub fn decode_frame_as(
+    buf: &[u8],
+    format: WireFormat,
+) -> Result<Option<(Message, usize)>, WireError> {
+    let Some((body, used)) = split_frame(buf)? else {
+        return Ok(None);
+    };
+    Ok(Some((decode_payload(body, format)?, used)))
+}
+
+/// The body of the frame at the start of `buf` and the frame's full length.
+fn split_frame(buf: &[u8]) -> Result<Option<(&[u8], usize)>, WireError> {
+    let Some(header) = buf.first_chunk::<4>() else {
+        return Ok(None);
+    };
+    let len = frame_len(*header)?;
+    Ok(buf.get(4..4 + len).map(|body| (body, 4 + len)))
+}
+
+/// Reassembles messages from bytes arriving in arbitrary pieces (WebSocket messages, FFI
+/// buffers, test input).
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    buf: Vec<u8>,
+    format: WireFormat,
+    /// The rest of a batch `next_message` has started handing out.
+    batched: VecDeque<Message>,
+}
+
+impl FrameDecoder {
+    /// Decode later messages as `format`, e.g. once `welcome.wire_format` has been seen.
+    pub fn set_format(&mut self, format: WireFormat) {
+        self.format = format;
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// The next complete message, if one has arrived. After an error the stream is out of
+    /// sync and the decoder should be dropped.
+    /// Batches are handed out one message at a time.
+    pub fn next_message(&mut self) -> Result<Option<Message>, WireError> {
+        if let Some(msg) = self.batched.pop_front() {
+            return Ok(Some(msg));
+        }
+        let Some((body, used)) = split_frame(&self.buf)? else {
+            return Ok(None);
+        };
+        let frame = decode_frame_payload(body, self.format)?;
+        self.buf.drain(..used);
+        self.batched = frame.into_messages().into();
+        Ok(self.batched.pop_front())
+    }
+
+    /// The next complete frame's body, still protected if the session negotiated that.
+    pub fn next_body(&mut self) -> Result<Option<Vec<u8>>, WireError> {
+        let Some((body, used)) = split_frame(&self.buf)? else {
+            return Ok(None);
+        };
+        let body = body.to_vec();
+        self.buf.drain(..used);
+        Ok(Some(body))
+    }
+
+    /// Consume `prefix` if the buffered bytes start with it. `None` until enough bytes have
+    /// arrived to tell.
+    #[cfg(feature = "noise")]
+    pub(crate) fn take_prefix(&mut self, prefix: &[u8]) -> Option<bool> {
+        let n = self.buf.len().min(prefix.len());
+        if self.buf[..n] != prefix[..n] {
+            return Some(false);
+        }
+        if n < prefix.len() {
+            return None;
+        }
+        self.buf.drain(..n);
+        Some(true)
+    }
+
+    /// Bytes received that don't make up a complete frame yet.
+    pub fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Messages of a batch not handed out yet.
+    pub fn batched(&self) -> usize {
+        self.batched.len()
+    }
+}
+
+#[cfg(feature = "tokio")]
 pub async fn write_message<W: AsyncWrite + Unpin>(
     writer: &mut W,
     message: &Message,
 ) -> Result<(), WireError> {
-    let frame = encode_frame(message)?;
+    write_message_as(writer, message, WireFormat::Json).await
+}
+
+#[cfg(feature = "tokio")]
+pub async fn write_message_as<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    message: &Message,
+    format: WireFormat,
+) -> Result<(), WireError> {
+    let frame = encode_frame_as(message, format)?;
     writer.write_all(&frame).await?;
     writer.flush().await?;
     Ok(())
 }
 
+#[cfg(feature = "tokio")]
 pub async fn read_message<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Message, WireError> {
+    read_message_as(reader, WireFormat::Json).await
+}
+
+#[cfg(feature = "tokio")]
+pub async fn read_message_as<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    format: WireFormat,
+) -> Result<Message, WireError> {
     let mut len_buf = [0u8; 4];
     reader.read_exact(&mut len_buf).await?;
-    let len = u32::from_be_bytes(len_buf) as usize;
-    if len == 0 || len > MAX_FRAME_LEN {
-        return Err(WireError::FrameLength(len));
-    }
+    let len = frame_len(len_buf)?;
 
     let mut payload = vec![0u8; len];
     reader.read_exact(&mut payload).await?;
-
-    // Validate JSON before decoding to structured types for better errors in logs.
-    let _v: Value = serde_json::from_slice(&payload)?;
-    let msg: Message = serde_json::from_slice(&payload)?;
-    Ok(msg)
+    decode_payload(&payload, format)
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -46,6 +345,249 @@ pub enum WireError {
     Io(#[from] std::io::Error),
     #[error("json error: {0}")]
     Json(#[from] serde_json::Error),
+    #[error("msgpack encode error: {0}")]
+    MsgpackEncode(#[from] rmp_serde::encode::Error),
+    #[error("msgpack decode error: {0}")]
+    MsgpackDecode(#[from] rmp_serde::decode::Error),
     #[error("invalid frame length: {0}")]
     FrameLength(usize),
+    #[error("frame failed its integrity check")]
+    Integrity,
+    #[error("compressed payload is malformed or too large")]
+    Compression,
+    #[error("invalid batch length: {0}")]
+    BatchLength(usize),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChatBroadcast, ChatChannel, ChatSend, Emote, PlayerPosition};
+    use proptest::prelude::*;
+
+    fn any_message() -> impl Strategy<Value = Message> {
+        prop_oneof![
+            (any::<String>(), any::<u32>()).prop_map(|(id, duration_ms)| {
+                Message::Emote(Emote {
+                    id,
+                    duration_ms,
+                    player_id: None,
+                })
+            }),
+            any::<String>().prop_map(|text| {
+                Message::ChatSend(ChatSend {
+                    channel: ChatChannel::World,
+                    text,
+                })
+            }),
+            // JSON has no NaN or infinity.
+            prop::array::uniform3(-1e9f32..1e9)
+                .prop_map(|position| { Message::PlayerPosition(PlayerPosition { position }) }),
+        ]
+    }
+
+    fn json(msg: &Message) -> String {
+        serde_json::to_string(msg).expect("json")
+    }
+
+    proptest! {
+        #[test]
+        fn frames_round_trip_however_they_are_split(
+            msgs in prop::collection::vec(any_message(), 1..4),
+            piece in 1usize..64,
+        ) {
+            let mut bytes = vec![];
+            for m in &msgs {
+                bytes.extend(encode_frame(m).expect("encode"));
+            }
+            let mut decoder = FrameDecoder::default();
+            let mut got = vec![];
+            for p in bytes.chunks(piece) {
+                decoder.push(p);
+                while let Some(m) = decoder.next_message().expect("decode") {
+                    got.push(json(&m));
+                }
+            }
+            prop_assert_eq!(got, msgs.iter().map(json).collect::<Vec<_>>());
+            prop_assert_eq!(decoder.buffered(), 0);
+        }
+
+        #[test]
+        fn truncated_frames_wait_for_more(msg in any_message(), cut in any::<prop::sample::Index>()) {
+            let frame = encode_frame(&msg).expect("encode");
+            let cut = cut.index(frame.len());
+            prop_assert!(decode_frame(&frame[..cut]).expect("prefix").is_none());
+        }
+
+        #[test]
+        fn msgpack_frames_round_trip(msg in any_message(), piece in 1usize..64) {
+            let bytes = encode_frame_as(&msg, WireFormat::Msgpack).expect("encode");
+            let mut decoder = FrameDecoder::default();
+            decoder.set_format(WireFormat::Msgpack);
+            let mut got = None;
+            for p in bytes.chunks(piece) {
+                decoder.push(p);
+                if let Some(m) = decoder.next_message().expect("decode") {
+                    got = Some(m);
+                }
+            }
+            prop_assert_eq!(got.map(|m| json(&m)), Some(json(&msg)));
+        }
+
+        #[test]
+        fn arbitrary_bytes_never_panic(bytes in prop::collection::vec(any::<u8>(), 0..256)) {
+            let _ = decode_frame(&bytes);
+            let mut decoder = FrameDecoder::default();
+            decoder.push(&bytes);
+            while let Ok(Some(_)) = decoder.next_message() {}
+        }
+
+        #[test]
+        fn length_prefix_is_checked_before_buffering(len in any::<u32>()) {
+            let res = decode_frame(&len.to_be_bytes());
+            if len == 0 || len as usize > MAX_FRAME_LEN {
+                prop_assert!(matches!(res, Err(WireError::FrameLength(l)) if l == len as usize));
+            } else {
+                prop_assert!(matches!(res, Ok(None)));
+            }
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    proptest! {
+        #[test]
+        fn read_message_agrees_with_decode_frame(
+            msg in any_message(),
+            noise in prop::collection::vec(any::<u8>(), 0..64),
+        ) {
+            let rt = tokio::runtime::Builder::new_current_thread().build().expect("runtime");
+            let mut bytes = encode_frame(&msg).expect("encode");
+            bytes.extend(noise);
+            let mut reader = bytes.as_slice();
+            let read = rt.block_on(read_message(&mut reader)).expect("read");
+            let (decoded, used) = decode_frame(&bytes).expect("decode").expect("complete");
+            prop_assert_eq!(json(&read), json(&decoded));
+            prop_assert_eq!(bytes.len() - reader.len(), used);
+        }
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn compressed_payloads_stay_within_the_frame_limit() {
+        let small = compress(b"{}".to_vec(), Compression::Zstd).expect("compress");
+        assert_eq!(small, b"\0{}");
+        let big = vec![b' '; MAX_FRAME_LEN + 1];
+        let packed = compress(big, Compression::Zstd).expect("compress");
+        assert_eq!(packed[0], PAYLOAD_ZSTD);
+        assert!(packed.len() < 1024);
+        assert!(matches!(decompress(packed), Err(WireError::Compression)));
+        assert!(matches!(
+            decompress(vec![7, 1]),
+            Err(WireError::Compression)
+        ));
+        assert!(matches!(decompress(vec![]), Err(WireError::Compression)));
+    }
+
+    #[test]
+    fn oversized_payloads_are_refused_not_truncated() {
+        let huge = Message::ChatSend(ChatSend {
+            channel: ChatChannel::World,
+            text: "x".repeat(MAX_FRAME_LEN),
+        });
+        assert!(matches!(
+            encode_frame(&huge),
+            Err(WireError::FrameLength(n)) if n > MAX_FRAME_LEN
+        ));
+    }
+
+    #[test]
+    fn decoder_reassembles_split_frames() {
+        let wave = Message::Emote(Emote {
+            id: "wave".to_string(),
+            duration_ms: 1000,
+            player_id: None,
+        });
+        let mut bytes = encode_frame(&wave).expect("encode");
+        bytes.extend(encode_frame(&wave).expect("encode"));
+
+        let mut decoder = FrameDecoder::default();
+        let mut got = vec![];
+        for piece in bytes.chunks(7) {
+            decoder.push(piece);
+            while let Some(msg) = decoder.next_message().expect("decode") {
+                got.push(msg);
+            }
+        }
+        assert_eq!(got.len(), 2);
+        assert!(matches!(&got[0], Message::Emote(e) if e.id == "wave"));
+        assert_eq!(decoder.buffered(), 0);
+
+        assert!(decode_frame(&bytes[..3]).expect("short").is_none());
+        assert!(matches!(
+            decode_frame(&[0, 0, 0, 0]),
+            Err(WireError::FrameLength(0))
+        ));
+    }
+
+    #[test]
+    fn batches_unpack_in_order_between_single_frames() {
+        let emote = |id: &str| {
+            Message::Emote(Emote {
+                id: id.to_string(),
+                duration_ms: 500,
+                player_id: None,
+            })
+        };
+        for format in WireFormat::ALL {
+            let mut bytes = encode_frame_as(&emote("first"), format).expect("encode");
+            let batch = [emote("a"), emote("b"), emote("c")];
+            bytes.extend(encode_batch_as(&batch, format).expect("batch"));
+            bytes.extend(encode_frame_as(&emote("last"), format).expect("encode"));
+
+            let mut decoder = FrameDecoder::default();
+            decoder.set_format(format);
+            let mut got = vec![];
+            for piece in bytes.chunks(5) {
+                decoder.push(piece);
+                while let Some(Message::Emote(e)) = decoder.next_message().expect("decode") {
+                    got.push(e.id);
+                }
+            }
+            assert_eq!(got, ["first", "a", "b", "c", "last"]);
+            assert_eq!((decoder.buffered(), decoder.batched()), (0, 0));
+        }
+        assert!(matches!(
+            encode_batch_as(&[], WireFormat::Json),
+            Err(WireError::BatchLength(0))
+        ));
+        assert!(matches!(
+            decode_frame_payload(b"[]", WireFormat::Json),
+            Err(WireError::BatchLength(0))
+        ));
+    }
+
+    #[test]
+    fn msgpack_keeps_ids_and_timestamps_and_is_smaller() {
+        let chat = Message::ChatBroadcast(ChatBroadcast {
+            channel: ChatChannel::Whisper {
+                to: uuid::Uuid::new_v4(),
+            },
+            from: Some(uuid::Uuid::new_v4()),
+            text: "hi".to_string(),
+            sent_at: time::macros::datetime!(2025-01-02 03:04:05 UTC),
+        });
+        let position = Message::PlayerPosition(PlayerPosition {
+            position: [12.5, 3.0, -40.25],
+        });
+        for msg in [chat, position] {
+            let packed = encode_frame_as(&msg, WireFormat::Msgpack).expect("encode");
+            let (decoded, _) = decode_frame_as(&packed, WireFormat::Msgpack)
+                .expect("decode")
+                .expect("complete");
+            assert_eq!(json(&decoded), json(&msg));
+            assert!(packed.len() < encode_frame(&msg).expect("json").len());
+            // A JSON reader can't mistake it for a message.
+            assert!(decode_frame(&packed).is_err());
+        }
+    }
 }
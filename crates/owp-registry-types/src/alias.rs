@@ -0,0 +1,98 @@
+//! Alias accounts: one PDA per normalized name, pointing at a world, so players can share
+//! `owp://name/<alias>` instead of a UUID.
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use sha2::{Digest, Sha256};
+
+use crate::{read_fixed_string, write_fixed_string, EntryError};
+
+pub const SEED_ALIAS: &[u8] = b"alias";
+
+pub const ALIAS_MAGIC: [u8; 8] = *b"OWPALS01";
+pub const ALIAS_VERSION: u8 = 1;
+
+pub const ALIAS_MIN_LEN: usize = 3;
+pub const ALIAS_LEN: usize = 32;
+
+/// The form an alias is claimed and looked up under: trimmed and lower-cased, 3 to 32 of a-z,
+/// 0-9 and `-`, without a leading, trailing or doubled `-`. `None` if `alias` has no such form,
+/// so look-alikes from other scripts can't be claimed next to the ASCII name.
+pub fn normalize_alias(alias: &str) -> Option<String> {
+    let alias = alias.trim().to_ascii_lowercase();
+    let ok = (ALIAS_MIN_LEN..=ALIAS_LEN).contains(&alias.len())
+        && alias
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+        && !alias.starts_with('-')
+        && !alias.ends_with('-')
+        && !alias.contains("--");
+    ok.then_some(alias)
+}
+
+/// PDA seed of a normalized alias: its SHA-256, so every alias fits the 32-byte seed limit.
+pub fn alias_hash(normalized: &str) -> [u8; 32] {
+    Sha256::digest(normalized.as_bytes()).into()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct AliasEntry {
+    pub magic: [u8; 8],
+    pub version: u8,
+    pub bump: u8,
+
+    /// Normalized alias.
+    pub alias: [u8; ALIAS_LEN],
+    pub world_id: [u8; 16],
+    /// Authority of the world when the alias was claimed; it may release the alias and gets the
+    /// rent back.
+    pub authority: [u8; 32],
+    pub claimed_slot: u64,
+}
+
+impl AliasEntry {
+    pub const LEN: usize = 98;
+
+    /// A new entry for an already normalized alias.
+    pub fn new(
+        normalized: &str,
+        world_id: [u8; 16],
+        authority: [u8; 32],
+        bump: u8,
+        claimed_slot: u64,
+    ) -> Result<Self, EntryError> {
+        let mut alias = [0u8; ALIAS_LEN];
+        write_fixed_string(&mut alias, normalized).map_err(|_| EntryError::StringTooLong {
+            field: "alias",
+            max: ALIAS_LEN,
+        })?;
+        Ok(Self {
+            magic: ALIAS_MAGIC,
+            version: ALIAS_VERSION,
+            bump,
+            alias,
+            world_id,
+            authority,
+            claimed_slot,
+        })
+    }
+
+    /// Read an alias account, checking magic, version and length.
+    pub fn decode(data: &[u8]) -> Result<Self, EntryError> {
+        if !data.starts_with(&ALIAS_MAGIC) {
+            return Err(EntryError::BadMagic);
+        }
+        if data.len() != Self::LEN {
+            return Err(EntryError::BadLength { len: data.len() });
+        }
+        let entry =
+            Self::try_from_slice(data).map_err(|_| EntryError::BadLength { len: data.len() })?;
+        if entry.version != ALIAS_VERSION {
+            return Err(EntryError::UnsupportedVersion(entry.version));
+        }
+        Ok(entry)
+    }
+
+    pub fn alias(&self) -> String {
+        read_fixed_string(&self.alias)
+    }
+}
@@ -0,0 +1,228 @@
+use std::fmt;
+
+use crate::{
+    is_valid_endpoint, write_fixed_string, WorldEntry, ENDPOINT_LEN, METADATA_URI_LEN, NAME_LEN,
+    WORLD_ENTRY_MAGIC, WORLD_ENTRY_VERSION,
+};
+
+/// Why a `WorldEntry` can't be built or read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryError {
+    /// Not a normalized host (see `is_valid_endpoint`).
+    InvalidEndpoint(String),
+    StringTooLong {
+        field: &'static str,
+        max: usize,
+    },
+    /// Port 0 where a real port is required.
+    InvalidPort {
+        field: &'static str,
+    },
+    /// Not a base58 32-byte key.
+    InvalidPubkey {
+        field: &'static str,
+    },
+    /// The account doesn't start with `WORLD_ENTRY_MAGIC`.
+    BadMagic,
+    UnsupportedVersion(u8),
+    /// The account is too short for its version, or a v1/v2 account isn't exactly
+    /// `WorldEntry::account_len(version)`.
+    BadLength {
+        len: usize,
+    },
+}
+
+impl EntryError {
+    /// Short snake_case name, for counting failures by kind.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Self::InvalidEndpoint(_) => "invalid_endpoint",
+            Self::StringTooLong { .. } => "string_too_long",
+            Self::InvalidPort { .. } => "invalid_port",
+            Self::InvalidPubkey { .. } => "invalid_pubkey",
+            Self::BadMagic => "bad_magic",
+            Self::UnsupportedVersion(_) => "unsupported_version",
+            Self::BadLength { .. } => "bad_length",
+        }
+    }
+}
+
+impl fmt::Display for EntryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidEndpoint(e) => write!(f, "endpoint {e:?} is not a normalized host"),
+            Self::StringTooLong { field, max } => write!(f, "{field} is longer than {max} bytes"),
+            Self::InvalidPort { field } => write!(f, "{field} must not be 0"),
+            Self::InvalidPubkey { field } => write!(f, "{field} must be a base58 32-byte key"),
+            Self::BadMagic => write!(f, "not a world entry (bad magic)"),
+            Self::UnsupportedVersion(v) => write!(f, "unsupported world entry version {v}"),
+            Self::BadLength { len } => write!(f, "world entry has the wrong length ({len} bytes)"),
+        }
+    }
+}
+
+impl std::error::Error for EntryError {}
+
+/// Decode a base58 public key.
+pub fn parse_pubkey(field: &'static str, b58: &str) -> Result<[u8; 32], EntryError> {
+    bs58::decode(b58.trim())
+        .into_vec()
+        .ok()
+        .and_then(|b| b.try_into().ok())
+        .ok_or(EntryError::InvalidPubkey { field })
+}
+
+/// The longest prefix of `s` that fits in `max` bytes without splitting a character.
+pub fn truncate_utf8(s: &str, max: usize) -> &str {
+    if s.len() <= max {
+        return s;
+    }
+    let mut end = max;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// Builds a valid `WorldEntry`. Names longer than `NAME_LEN` are cut at a character boundary;
+/// everything else that doesn't fit is an error from `build`, as is the first bad input.
+#[derive(Debug, Clone)]
+pub struct WorldEntryBuilder {
+    entry: WorldEntry,
+    name: String,
+    endpoint: String,
+    metadata_uri: String,
+    error: Option<EntryError>,
+}
+
+impl WorldEntryBuilder {
+    pub fn new(world_id: [u8; 16], authority: [u8; 32]) -> Self {
+        Self {
+            entry: WorldEntry {
+                magic: WORLD_ENTRY_MAGIC,
+                version: WORLD_ENTRY_VERSION,
+                bump: 0,
+                world_id,
+                authority,
+                name: [0u8; NAME_LEN],
+                endpoint: [0u8; ENDPOINT_LEN],
+                game_port: 0,
+                asset_port: 0,
+                token_mint: [0u8; 32],
+                dbc_pool: [0u8; 32],
+                metadata_uri: [0u8; METADATA_URI_LEN],
+                last_update_slot: 0,
+                player_count: 0,
+                max_players: 0,
+                stake_lamports: 0,
+                unlock_slot: 0,
+            },
+            name: String::new(),
+            endpoint: String::new(),
+            metadata_uri: String::new(),
+            error: None,
+        }
+    }
+
+    fn fail(mut self, e: EntryError) -> Self {
+        self.error.get_or_insert(e);
+        self
+    }
+
+    /// Like `new`, with the authority as a base58 key.
+    pub fn with_authority_b58(world_id: [u8; 16], authority: &str) -> Self {
+        match parse_pubkey("authority", authority) {
+            Ok(key) => Self::new(world_id, key),
+            Err(e) => Self::new(world_id, [0u8; 32]).fail(e),
+        }
+    }
+
+    pub fn bump(mut self, bump: u8) -> Self {
+        self.entry.bump = bump;
+        self
+    }
+
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = truncate_utf8(name, NAME_LEN).to_string();
+        self
+    }
+
+    pub fn endpoint(mut self, endpoint: &str) -> Self {
+        self.endpoint = endpoint.to_string();
+        self
+    }
+
+    pub fn game_port(mut self, port: u16) -> Self {
+        self.entry.game_port = port;
+        self
+    }
+
+    pub fn asset_port(mut self, port: Option<u16>) -> Self {
+        if port == Some(0) {
+            return self.fail(EntryError::InvalidPort {
+                field: "asset_port",
+            });
+        }
+        self.entry.asset_port = port.unwrap_or(0);
+        self
+    }
+
+    pub fn token_mint(mut self, mint: Option<[u8; 32]>) -> Self {
+        self.entry.token_mint = mint.unwrap_or([0u8; 32]);
+        self
+    }
+
+    pub fn token_mint_b58(self, mint: Option<&str>) -> Self {
+        match mint.map(|m| parse_pubkey("token_mint", m)).transpose() {
+            Ok(mint) => self.token_mint(mint),
+            Err(e) => self.fail(e),
+        }
+    }
+
+    pub fn dbc_pool(mut self, pool: Option<[u8; 32]>) -> Self {
+        self.entry.dbc_pool = pool.unwrap_or([0u8; 32]);
+        self
+    }
+
+    pub fn dbc_pool_b58(self, pool: Option<&str>) -> Self {
+        match pool.map(|p| parse_pubkey("dbc_pool", p)).transpose() {
+            Ok(pool) => self.dbc_pool(pool),
+            Err(e) => self.fail(e),
+        }
+    }
+
+    pub fn metadata_uri(mut self, uri: &str) -> Self {
+        self.metadata_uri = uri.to_string();
+        self
+    }
+
+    pub fn max_players(mut self, max: Option<u16>) -> Self {
+        self.entry.max_players = max.unwrap_or(0);
+        self
+    }
+
+    pub fn last_update_slot(mut self, slot: u64) -> Self {
+        self.entry.last_update_slot = slot;
+        self
+    }
+
+    pub fn build(self) -> Result<WorldEntry, EntryError> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        let mut entry = self.entry;
+        if !is_valid_endpoint(&self.endpoint) {
+            return Err(EntryError::InvalidEndpoint(self.endpoint));
+        }
+        if entry.game_port == 0 {
+            return Err(EntryError::InvalidPort { field: "game_port" });
+        }
+        let too_long = |field, max| EntryError::StringTooLong { field, max };
+        write_fixed_string(&mut entry.name, &self.name).map_err(|_| too_long("name", NAME_LEN))?;
+        write_fixed_string(&mut entry.endpoint, &self.endpoint)
+            .map_err(|_| too_long("endpoint", ENDPOINT_LEN))?;
+        write_fixed_string(&mut entry.metadata_uri, &self.metadata_uri)
+            .map_err(|_| too_long("metadata_uri", METADATA_URI_LEN))?;
+        Ok(entry)
+    }
+}
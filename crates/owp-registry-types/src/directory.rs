@@ -0,0 +1,35 @@
+use owp_protocol::WorldDirectoryEntry;
+use uuid::Uuid;
+
+use crate::{EntryError, WorldEntry};
+
+impl TryFrom<WorldEntry> for WorldDirectoryEntry {
+    type Error = EntryError;
+
+    /// The listing for a registry account. Fields the account doesn't carry are left empty.
+    fn try_from(entry: WorldEntry) -> Result<Self, EntryError> {
+        entry.check_readable()?;
+        let b58 = |key: [u8; 32]| bs58::encode(key).into_string();
+        Ok(WorldDirectoryEntry {
+            world_id: Uuid::from_bytes(entry.world_id),
+            name: entry.name(),
+            endpoint: entry.endpoint(),
+            port: entry.game_port,
+            token_mint: entry.token_mint().map(b58),
+            dbc_pool: entry.dbc_pool().map(b58),
+            world_pubkey: Some(b58(entry.authority)),
+            last_seen: Some(entry.last_update_slot.to_string()),
+            trust: None,
+            // The registry account has no region field yet.
+            region: None,
+            rtt_ms: None,
+            tags: vec![],
+            icon_sha256: None,
+            player_count: (entry.version >= 2).then_some(entry.player_count.into()),
+            max_players: entry.max_players().map(Into::into),
+            stake_lamports: (entry.stake_lamports > 0).then_some(entry.stake_lamports),
+            // Nor rules; clients learn of them from `rules_required` when they connect.
+            rules: None,
+        })
+    }
+}
@@ -1,15 +1,38 @@
 use borsh::{BorshDeserialize, BorshSerialize};
 
+mod alias;
+mod builder;
+#[cfg(feature = "directory")]
+mod directory;
+
+pub use alias::{
+    alias_hash, normalize_alias, AliasEntry, ALIAS_LEN, ALIAS_MAGIC, ALIAS_MIN_LEN, ALIAS_VERSION,
+    SEED_ALIAS,
+};
+pub use builder::{parse_pubkey, truncate_utf8, EntryError, WorldEntryBuilder};
+
 pub const SEED_WORLD: &[u8] = b"world";
 
 pub const WORLD_ENTRY_MAGIC: [u8; 8] = *b"OWPREG01";
-pub const WORLD_ENTRY_VERSION: u8 = 1;
+/// Version of new entries. v2 appends `player_count` and `max_players` to the v1 layout, v3
+/// `stake_lamports` and `unlock_slot`.
+pub const WORLD_ENTRY_VERSION: u8 = 3;
+
+/// Slots between delisting a staked world and getting its stake back (about two days).
+pub const STAKE_COOLDOWN_SLOTS: u64 = 432_000;
+
+/// Lamports that keep an account of `len` bytes rent-exempt under Solana's default rent (3480
+/// lamports per byte-year, two years, 128 bytes of account overhead): what registering a world
+/// or claiming an alias costs on top of fees, and what a heartbeat upgrade tops up.
+pub fn rent_exempt_lamports(len: usize) -> u64 {
+    (128 + len as u64) * 3480 * 2
+}
 
 pub const NAME_LEN: usize = 32;
 pub const ENDPOINT_LEN: usize = 64;
 pub const METADATA_URI_LEN: usize = 128;
 
-#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
 pub struct WorldEntry {
     pub magic: [u8; 8],
     pub version: u8,
@@ -31,12 +54,159 @@ pub struct WorldEntry {
 
     pub metadata_uri: [u8; METADATA_URI_LEN],
     pub last_update_slot: u64,
+
+    // v2 fields. The derived Borsh impls cover only the v1 layout; `decode` and `write_to` handle
+    // the whole account.
+    /// Players online at the last heartbeat.
+    #[borsh_skip]
+    pub player_count: u16,
+    /// 0 means "unknown".
+    #[borsh_skip]
+    pub max_players: u16,
+
+    // v3 fields.
+    /// Lamports locked in the account on top of its rent, returned on delist.
+    #[borsh_skip]
+    pub stake_lamports: u64,
+    /// 0 while listed. Once delisting, the slot from which the stake can be withdrawn.
+    #[borsh_skip]
+    pub unlock_slot: u64,
 }
 
 impl WorldEntry {
+    /// Size of a v1 account, and of the v1 prefix of every later version.
     pub const LEN: usize = 358;
+    /// Size of a v2 account.
+    pub const LEN_V2: usize = Self::LEN + 4;
+    /// Size of a v3 account.
+    pub const LEN_V3: usize = Self::LEN_V2 + 16;
+
+    /// Account size for entries of `version`.
+    pub fn account_len(version: u8) -> usize {
+        match version {
+            0 | 1 => Self::LEN,
+            2 => Self::LEN_V2,
+            _ => Self::LEN_V3,
+        }
+    }
+
+    /// `BadMagic` or `UnsupportedVersion` unless this is a version the program writes (1 to 3).
+    pub fn check_header(&self) -> Result<(), EntryError> {
+        self.check_readable()?;
+        if self.version > WORLD_ENTRY_VERSION {
+            return Err(EntryError::UnsupportedVersion(self.version));
+        }
+        Ok(())
+    }
+
+    /// Like `check_header`, but also accepts newer versions, which readers see as the prefix
+    /// they know (see `decode`).
+    pub fn check_readable(&self) -> Result<(), EntryError> {
+        if self.magic != WORLD_ENTRY_MAGIC {
+            return Err(EntryError::BadMagic);
+        }
+        if self.version == 0 {
+            return Err(EntryError::UnsupportedVersion(0));
+        }
+        Ok(())
+    }
+
+    /// Read a registry account. Accounts of known versions are exactly `account_len(version)`
+    /// bytes. Every version keeps the previous layout and only appends fields, so newer accounts
+    /// are read by the prefix this version knows and the rest is skipped; the returned entry
+    /// keeps the account's `version`.
+    pub fn decode(data: &[u8]) -> Result<Self, EntryError> {
+        if !data.starts_with(&WORLD_ENTRY_MAGIC) {
+            return Err(EntryError::BadMagic);
+        }
+        let len = data.len();
+        let Some(&version) = data.get(WORLD_ENTRY_MAGIC.len()) else {
+            return Err(EntryError::BadLength { len });
+        };
+        if version == 0 {
+            return Err(EntryError::UnsupportedVersion(0));
+        }
+        let want = Self::account_len(version);
+        if len < want || (version <= WORLD_ENTRY_VERSION && len != want) {
+            return Err(EntryError::BadLength { len });
+        }
+        let mut entry = Self::deserialize(&mut &data[..Self::LEN])
+            .map_err(|_| EntryError::BadLength { len })?;
+        if version >= 2 {
+            let u16_at = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+            entry.player_count = u16_at(Self::LEN);
+            entry.max_players = u16_at(Self::LEN + 2);
+        }
+        if version >= 3 {
+            let u64_at = |at: usize| {
+                let mut b = [0u8; 8];
+                b.copy_from_slice(&data[at..at + 8]);
+                u64::from_le_bytes(b)
+            };
+            entry.stake_lamports = u64_at(Self::LEN_V2);
+            entry.unlock_slot = u64_at(Self::LEN_V2 + 8);
+        }
+        Ok(entry)
+    }
+
+    /// Write the entry in its version's layout to the start of `dst`.
+    pub fn write_to(&self, dst: &mut [u8]) -> Result<(), EntryError> {
+        let len = dst.len();
+        if len < Self::account_len(self.version) {
+            return Err(EntryError::BadLength { len });
+        }
+        self.serialize(&mut &mut dst[..Self::LEN])
+            .map_err(|_| EntryError::BadLength { len })?;
+        if self.version >= 2 {
+            dst[Self::LEN..Self::LEN + 2].copy_from_slice(&self.player_count.to_le_bytes());
+            dst[Self::LEN + 2..Self::LEN_V2].copy_from_slice(&self.max_players.to_le_bytes());
+        }
+        if self.version >= 3 {
+            dst[Self::LEN_V2..Self::LEN_V2 + 8].copy_from_slice(&self.stake_lamports.to_le_bytes());
+            dst[Self::LEN_V2 + 8..Self::LEN_V3].copy_from_slice(&self.unlock_slot.to_le_bytes());
+        }
+        Ok(())
+    }
+
+    pub fn max_players(&self) -> Option<u16> {
+        (self.max_players != 0).then_some(self.max_players)
+    }
+
+    /// Whether the authority has started delisting (the entry stays until the stake unlocks).
+    pub fn is_delisting(&self) -> bool {
+        self.unlock_slot != 0
+    }
+
+    pub fn name(&self) -> String {
+        read_fixed_string(&self.name)
+    }
+
+    pub fn endpoint(&self) -> String {
+        read_fixed_string(&self.endpoint)
+    }
+
+    pub fn metadata_uri(&self) -> String {
+        read_fixed_string(&self.metadata_uri)
+    }
+
+    pub fn asset_port(&self) -> Option<u16> {
+        (self.asset_port != 0).then_some(self.asset_port)
+    }
+
+    pub fn token_mint(&self) -> Option<[u8; 32]> {
+        optional_pubkey(self.token_mint)
+    }
+
+    pub fn dbc_pool(&self) -> Option<[u8; 32]> {
+        optional_pubkey(self.dbc_pool)
+    }
+}
+
+fn optional_pubkey(key: [u8; 32]) -> Option<[u8; 32]> {
+    (key != [0u8; 32]).then_some(key)
 }
 
+#[allow(clippy::result_unit_err)]
 pub fn write_fixed_string<const N: usize>(dst: &mut [u8; N], src: &str) -> Result<(), ()> {
     let bytes = src.as_bytes();
     if bytes.len() > N {
@@ -47,6 +217,26 @@ pub fn write_fixed_string<const N: usize>(dst: &mut [u8; N], src: &str) -> Resul
     Ok(())
 }
 
+/// Whether `endpoint` is a normalized world host: an IP address, or a lower-case DNS name
+/// without a trailing dot. Ports are stored separately and never part of it.
+pub fn is_valid_endpoint(endpoint: &str) -> bool {
+    if endpoint.is_empty() || endpoint.len() > ENDPOINT_LEN {
+        return false;
+    }
+    if endpoint.parse::<std::net::IpAddr>().is_ok() {
+        return true;
+    }
+    endpoint.split('.').all(|label| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+    })
+}
+
 pub fn read_fixed_string(bytes: &[u8]) -> String {
     let mut end = 0usize;
     while end < bytes.len() && bytes[end] != 0 {
@@ -76,8 +266,178 @@ mod tests {
             dbc_pool: [0u8; 32],
             metadata_uri: [0u8; METADATA_URI_LEN],
             last_update_slot: 0,
+            player_count: 3,
+            max_players: 32,
+            stake_lamports: 1_000_000,
+            unlock_slot: 0,
         };
         let data = entry.try_to_vec().expect("serialize");
         assert_eq!(data.len(), WorldEntry::LEN);
+        let mut account = vec![0u8; WorldEntry::LEN_V3];
+        entry.write_to(&mut account).expect("write");
+        assert_eq!(WorldEntry::decode(&account), Ok(entry));
+    }
+
+    /// Account sizes are part of the on-chain format and of what publishing costs; changing
+    /// them strands existing accounts, so they are pinned here.
+    #[test]
+    fn account_sizes_and_rent_are_pinned() {
+        let sizes = [
+            (WorldEntry::account_len(1), 358, 3_382_560),
+            (WorldEntry::account_len(2), 362, 3_410_400),
+            (WorldEntry::account_len(3), 378, 3_521_760),
+            (AliasEntry::LEN, 98, 1_572_960),
+        ];
+        for (len, want, rent) in sizes {
+            assert_eq!(len, want);
+            assert_eq!(rent_exempt_lamports(len), rent, "{len}");
+        }
+        assert_eq!(
+            WorldEntry::account_len(WORLD_ENTRY_VERSION),
+            WorldEntry::LEN_V3
+        );
+        // Growing a v1 account to v3 tops up exactly the difference.
+        assert_eq!(
+            rent_exempt_lamports(WorldEntry::LEN_V3) - rent_exempt_lamports(WorldEntry::LEN),
+            139_200
+        );
+    }
+
+    #[test]
+    fn builder_validates_and_truncates_names() {
+        let authority = bs58::encode([9u8; 32]).into_string();
+        let entry = WorldEntryBuilder::with_authority_b58([7u8; 16], &authority)
+            .name("Ünïcödé wörld with a very long name")
+            .endpoint("play.example.com")
+            .game_port(7777)
+            .token_mint_b58(Some(&bs58::encode([1u8; 32]).into_string()))
+            .build()
+            .expect("build");
+        assert!(entry.name().len() <= NAME_LEN);
+        assert!(entry.name().starts_with("Ünïcödé"));
+        assert_eq!(entry.authority, [9u8; 32]);
+        assert_eq!(entry.token_mint(), Some([1u8; 32]));
+        assert_eq!(entry.dbc_pool(), None);
+        assert_eq!(entry.asset_port(), None);
+        assert_eq!(truncate_utf8("aé", 2), "a");
+
+        let base = || WorldEntryBuilder::new([7u8; 16], [9u8; 32]).endpoint("10.0.0.1");
+        assert_eq!(
+            base().build().unwrap_err(),
+            EntryError::InvalidPort { field: "game_port" }
+        );
+        assert_eq!(
+            base().game_port(1).endpoint("Bad:1").build().unwrap_err(),
+            EntryError::InvalidEndpoint("Bad:1".to_string())
+        );
+        assert_eq!(
+            base()
+                .game_port(1)
+                .dbc_pool_b58(Some("nope"))
+                .build()
+                .unwrap_err(),
+            EntryError::InvalidPubkey { field: "dbc_pool" }
+        );
+        let mut wrong = base().game_port(1).build().expect("build");
+        wrong.version = 9;
+        assert_eq!(wrong.check_header(), Err(EntryError::UnsupportedVersion(9)));
+    }
+
+    #[test]
+    fn decode_checks_header_and_length_and_reads_newer_versions() {
+        let entry = WorldEntryBuilder::new([7u8; 16], [9u8; 32])
+            .endpoint("10.0.0.1")
+            .game_port(7777)
+            .build()
+            .expect("build");
+        let mut data = vec![0u8; WorldEntry::LEN_V3];
+        entry.write_to(&mut data).expect("write");
+        assert_eq!(WorldEntry::decode(&data), Ok(entry.clone()));
+
+        assert_eq!(WorldEntry::decode(b"junk"), Err(EntryError::BadMagic));
+        assert_eq!(
+            WorldEntry::decode(&data[..100]),
+            Err(EntryError::BadLength { len: 100 })
+        );
+        let mut padded = data.clone();
+        padded.push(0);
+        assert_eq!(
+            WorldEntry::decode(&padded).map_err(|e| e.reason()),
+            Err("bad_length")
+        );
+        let mut zero = data.clone();
+        zero[WORLD_ENTRY_MAGIC.len()] = 0;
+        assert_eq!(
+            WorldEntry::decode(&zero),
+            Err(EntryError::UnsupportedVersion(0))
+        );
+
+        // A v1 account has no player counts.
+        let mut v1 = data[..WorldEntry::LEN].to_vec();
+        v1[WORLD_ENTRY_MAGIC.len()] = 1;
+        let read = WorldEntry::decode(&v1).expect("v1");
+        assert_eq!((read.version, read.player_count), (1, 0));
+        assert_eq!(read.check_header(), Ok(()));
+
+        // A v4 account: the v3 layout plus trailing fields this reader doesn't know.
+        let mut v4 = data;
+        v4[WORLD_ENTRY_MAGIC.len()] = 4;
+        v4.extend_from_slice(&[1, 2, 3]);
+        let read = WorldEntry::decode(&v4).expect("v4 prefix");
+        assert_eq!(read.version, 4);
+        assert_eq!(read.endpoint(), "10.0.0.1");
+        assert_eq!(read.check_header(), Err(EntryError::UnsupportedVersion(4)));
+        assert_eq!(read.check_readable(), Ok(()));
+    }
+
+    #[test]
+    fn aliases_normalize_and_round_trip() {
+        assert_eq!(normalize_alias("  My-World "), Some("my-world".to_string()));
+        for bad in [
+            "ab",
+            "-world",
+            "world-",
+            "my--world",
+            "wörld",
+            "my world",
+            &"a".repeat(33),
+        ] {
+            assert_eq!(normalize_alias(bad), None, "{bad}");
+        }
+        assert_eq!(alias_hash("my-world"), alias_hash("my-world"));
+        assert_ne!(alias_hash("my-world"), alias_hash("my-world2"));
+
+        let entry = AliasEntry::new("my-world", [7u8; 16], [9u8; 32], 254, 42).expect("alias");
+        let data = entry.try_to_vec().expect("serialize");
+        assert_eq!(data.len(), AliasEntry::LEN);
+        let read = AliasEntry::decode(&data).expect("decode");
+        assert_eq!(read.alias(), "my-world");
+        assert_eq!(read, entry);
+        assert_eq!(AliasEntry::decode(&data[1..]), Err(EntryError::BadMagic));
+    }
+
+    #[test]
+    fn endpoints_are_hosts_without_ports() {
+        for ok in [
+            "mygame.example.com",
+            "localhost",
+            "10.0.0.1",
+            "::1",
+            "xn--bcher-kva.de",
+        ] {
+            assert!(is_valid_endpoint(ok), "{ok}");
+        }
+        for bad in [
+            "",
+            "MyGame.example.com",
+            "mygame.example.com.",
+            "mygame.example.com:7777",
+            "owp://mygame.example.com",
+            "-bad.example.com",
+            "a..b",
+            "has space.com",
+        ] {
+            assert!(!is_valid_endpoint(bad), "{bad}");
+        }
     }
 }
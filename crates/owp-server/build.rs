@@ -0,0 +1,58 @@
+//! Generates the gRPC admin service stubs. Messages are plain `prost` structs in
+//! `src/grpc.rs`, so no `protoc` is needed; `proto/owp_admin.proto` mirrors them for
+//! tooling in other languages.
+
+use tonic_build::manual::{Builder, Method, Service};
+
+const METHODS: &[(&str, &str, &str, &str)] = &[
+    ("list_worlds", "ListWorlds", "Empty", "WorldList"),
+    (
+        "create_world",
+        "CreateWorld",
+        "CreateWorldRequest",
+        "WorldManifest",
+    ),
+    ("get_manifest", "GetManifest", "WorldRef", "WorldManifest"),
+    (
+        "list_discovery_worlds",
+        "ListDiscoveryWorlds",
+        "DiscoveryQuery",
+        "WorldList",
+    ),
+    (
+        "get_world_summary",
+        "GetWorldSummary",
+        "Empty",
+        "WorldSummaryList",
+    ),
+    (
+        "get_assistant_status",
+        "GetAssistantStatus",
+        "Empty",
+        "AssistantStatus",
+    ),
+    (
+        "assistant_chat",
+        "AssistantChat",
+        "AssistantChatRequest",
+        "AssistantChatReply",
+    ),
+    ("list_jobs", "ListJobs", "Empty", "JobList"),
+];
+
+fn main() {
+    let mut service = Service::builder().name("Admin").package("owp.admin.v1");
+    for (name, route, input, output) in METHODS {
+        service = service.method(
+            Method::builder()
+                .name(name)
+                .route_name(route)
+                .input_type(format!("crate::grpc::pb::{input}"))
+                .output_type(format!("crate::grpc::pb::{output}"))
+                .codec_path("tonic::codec::ProstCodec")
+                .build(),
+        );
+    }
+    Builder::new().compile(&[service.build()]);
+    println!("cargo:rerun-if-changed=build.rs");
+}
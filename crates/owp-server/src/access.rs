@@ -0,0 +1,314 @@
+use anyhow::{Context, Result};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+use crate::config::ServerConfigV1;
+use crate::storage::write_atomic;
+
+/// Which peers a game server accepts connections from. The global filter (`server.json`) and the
+/// world's own (`<world>/access.json`) must both let a peer in.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccessFilter {
+    /// CIDRs or single addresses; when non-empty, peers outside all of them are refused.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+    /// CIDRs or single addresses that are always refused.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<String>,
+    /// ISO 3166-1 alpha-2 codes, looked up in `geoip_db`. When non-empty, peers of other or
+    /// unknown countries are refused.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow_countries: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny_countries: Vec<String>,
+    /// Concurrent connections from one address to a world. The world's value wins over the
+    /// global one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_connections_per_ip: Option<u32>,
+}
+
+fn parse_net(s: &str) -> Option<IpNet> {
+    let s = s.trim();
+    s.parse::<IpNet>()
+        .ok()
+        .or_else(|| s.parse::<IpAddr>().ok().map(IpNet::from))
+}
+
+impl AccessFilter {
+    /// Reject entries that would be ignored, and upper-case country codes.
+    pub fn normalize(&mut self) -> Result<()> {
+        for net in self.allow.iter().chain(&self.deny) {
+            if parse_net(net).is_none() {
+                anyhow::bail!("not an address or CIDR: {net:?}");
+            }
+        }
+        for cc in self
+            .allow_countries
+            .iter_mut()
+            .chain(self.deny_countries.iter_mut())
+        {
+            if cc.len() != 2 || !cc.bytes().all(|b| b.is_ascii_alphabetic()) {
+                anyhow::bail!("not a two-letter country code: {cc:?}");
+            }
+            cc.make_ascii_uppercase();
+        }
+        Ok(())
+    }
+
+    fn uses_countries(&self) -> bool {
+        !self.allow_countries.is_empty() || !self.deny_countries.is_empty()
+    }
+
+    fn check(&self, ip: IpAddr, country: Option<&str>) -> Result<(), Refusal> {
+        let matches = |nets: &[String]| {
+            nets.iter()
+                .filter_map(|n| parse_net(n))
+                .any(|n| n.contains(&ip))
+        };
+        if matches(&self.deny) {
+            return Err(Refusal::Denied);
+        }
+        if !self.allow.is_empty() && !matches(&self.allow) {
+            return Err(Refusal::NotAllowed);
+        }
+        let listed = |codes: &[String]| {
+            country.is_some_and(|c| codes.iter().any(|cc| cc.eq_ignore_ascii_case(c)))
+        };
+        if listed(&self.deny_countries)
+            || (!self.allow_countries.is_empty() && !listed(&self.allow_countries))
+        {
+            return Err(Refusal::Country);
+        }
+        Ok(())
+    }
+}
+
+/// Why a connection was closed right after accept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refusal {
+    Denied,
+    NotAllowed,
+    Country,
+    TooManyConnections,
+    /// The world's `access.json` can't be read; refusing keeps a restricted world closed.
+    Unreadable,
+}
+
+impl Refusal {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Denied => "denied",
+            Self::NotAllowed => "not_allowed",
+            Self::Country => "country",
+            Self::TooManyConnections => "too_many_connections",
+            Self::Unreadable => "unreadable",
+        }
+    }
+}
+
+pub fn access_path(world_dir: &Path) -> PathBuf {
+    world_dir.join("access.json")
+}
+
+pub fn load(world_dir: &Path) -> Result<AccessFilter> {
+    let path = access_path(world_dir);
+    if !path.exists() {
+        return Ok(AccessFilter::default());
+    }
+    let data = fs::read_to_string(&path).with_context(|| format!("read {path:?}"))?;
+    serde_json::from_str(&data).with_context(|| format!("parse {path:?}"))
+}
+
+pub fn save(world_dir: &Path, filter: &AccessFilter) -> Result<()> {
+    let json = serde_json::to_string_pretty(filter).context("serialize access filter")?;
+    write_atomic(&access_path(world_dir), format!("{json}\n").as_bytes())?;
+    Ok(())
+}
+
+/// Address ranges to countries, from a CSV of `first,last,country` rows (the layout of the
+/// free DB-IP "IP to Country Lite" download). Other lines are skipped.
+#[derive(Debug, Default)]
+pub struct GeoIp {
+    ranges: Vec<(IpAddr, IpAddr, String)>,
+}
+
+impl GeoIp {
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = fs::read_to_string(path).with_context(|| format!("read {path:?}"))?;
+        Ok(Self::parse(&data))
+    }
+
+    fn parse(data: &str) -> Self {
+        let mut ranges: Vec<_> = data
+            .lines()
+            .filter_map(|line| {
+                let mut cols = line.split(',').map(|c| c.trim().trim_matches('"'));
+                let first = cols.next()?.parse::<IpAddr>().ok()?;
+                let last = cols.next()?.parse::<IpAddr>().ok()?;
+                let country = cols.next()?;
+                (first.is_ipv4() == last.is_ipv4() && country.len() == 2)
+                    .then(|| (first, last, country.to_ascii_uppercase()))
+            })
+            .collect();
+        ranges.sort();
+        Self { ranges }
+    }
+
+    pub fn country(&self, ip: IpAddr) -> Option<&str> {
+        // `IpAddr` orders every v4 address before every v6 one, so one sorted list serves both.
+        let i = self.ranges.partition_point(|(first, _, _)| *first <= ip);
+        let (_, last, country) = self.ranges.get(i.checked_sub(1)?)?;
+        (ip <= *last).then_some(country.as_str())
+    }
+}
+
+/// The GeoIP path last configured and its database, `None` if it failed to load.
+type CachedGeoIp = Option<(PathBuf, Option<Arc<GeoIp>>)>;
+
+/// Accept filtering for one world's game server.
+#[derive(Clone)]
+pub struct AccessControl {
+    world_dir: PathBuf,
+    per_ip: Arc<Mutex<HashMap<IpAddr, u32>>>,
+    geoip: Arc<Mutex<CachedGeoIp>>,
+}
+
+impl AccessControl {
+    pub fn new(world_dir: PathBuf) -> Self {
+        Self {
+            world_dir,
+            per_ip: Arc::default(),
+            geoip: Arc::default(),
+        }
+    }
+
+    /// Decide on a peer under the global filter in `config` and the world's `access.json`
+    /// (re-read every time, so admin changes apply to the next connection). The slot holds one
+    /// of the address's connections until dropped.
+    pub fn admit(&self, ip: IpAddr, config: &ServerConfigV1) -> Result<ConnectionSlot, Refusal> {
+        let ip = ip.to_canonical();
+        let world = load(&self.world_dir).map_err(|e| {
+            warn!("world access filter: {e:#}");
+            Refusal::Unreadable
+        })?;
+        let global = &config.access;
+        let geoip = match &config.geoip_db {
+            Some(path) if global.uses_countries() || world.uses_countries() => self.geoip(path),
+            _ => None,
+        };
+        let country = geoip.as_ref().and_then(|db| db.country(ip));
+        global.check(ip, country)?;
+        world.check(ip, country)?;
+
+        let cap = world
+            .max_connections_per_ip
+            .or(global.max_connections_per_ip);
+        let mut per_ip = self.per_ip.lock().unwrap_or_else(|e| e.into_inner());
+        let n = per_ip.entry(ip).or_default();
+        if cap.is_some_and(|cap| *n >= cap) {
+            return Err(Refusal::TooManyConnections);
+        }
+        *n += 1;
+        Ok(ConnectionSlot {
+            per_ip: self.per_ip.clone(),
+            ip,
+        })
+    }
+
+    /// Read once per configured path; a file replaced in place is picked up on restart.
+    fn geoip(&self, path: &Path) -> Option<Arc<GeoIp>> {
+        let mut cached = self.geoip.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((p, db)) = cached.as_ref() {
+            if p == path {
+                return db.clone();
+            }
+        }
+        let db = match GeoIp::load(path) {
+            Ok(db) => {
+                info!("loaded {} GeoIP ranges from {path:?}", db.ranges.len());
+                Some(Arc::new(db))
+            }
+            Err(e) => {
+                warn!("GeoIP database unavailable, countries are unknown: {e:#}");
+                None
+            }
+        };
+        *cached = Some((path.to_path_buf(), db.clone()));
+        db
+    }
+}
+
+/// One admitted connection, counted against its address until dropped.
+pub struct ConnectionSlot {
+    per_ip: Arc<Mutex<HashMap<IpAddr, u32>>>,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        let mut per_ip = self.per_ip.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(n) = per_ip.get_mut(&self.ip) {
+            *n -= 1;
+            if *n == 0 {
+                per_ip.remove(&self.ip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_combine_and_slots_are_released() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let db = dir.path().join("geoip.csv");
+        fs::write(
+            &db,
+            "10.0.0.0,10.0.0.255,DE\n\"10.0.1.0\",\"10.0.1.255\",\"us\"\n::1,::1,FR\n",
+        )
+        .expect("write db");
+        let mut config = ServerConfigV1 {
+            geoip_db: Some(db),
+            ..ServerConfigV1::default()
+        };
+        config.access.deny = vec!["10.0.0.66".to_string()];
+        config.access.max_connections_per_ip = Some(1);
+        let mut world = AccessFilter {
+            allow: vec!["10.0.0.0/16".to_string(), "::1".to_string()],
+            allow_countries: vec!["de".to_string(), "fr".to_string()],
+            ..AccessFilter::default()
+        };
+        world.normalize().expect("valid");
+        assert_eq!(world.allow_countries, ["DE", "FR"]);
+        save(dir.path(), &world).expect("save");
+
+        let access = AccessControl::new(dir.path().to_path_buf());
+        let ip = |s: &str| s.parse::<IpAddr>().expect("ip");
+        let admit = |s: &str| access.admit(ip(s), &config).map(|_| ());
+        assert_eq!(admit("10.0.0.66"), Err(Refusal::Denied));
+        assert_eq!(admit("192.168.1.1"), Err(Refusal::NotAllowed));
+        assert_eq!(admit("10.0.1.7"), Err(Refusal::Country));
+        assert_eq!(admit("10.0.2.7"), Err(Refusal::Country));
+        assert_eq!(admit("::ffff:10.0.0.7"), Ok(()));
+        assert_eq!(admit("::1"), Ok(()));
+
+        let slot = access.admit(ip("10.0.0.7"), &config).expect("first");
+        assert_eq!(admit("10.0.0.7"), Err(Refusal::TooManyConnections));
+        drop(slot);
+        assert_eq!(admit("10.0.0.7"), Ok(()));
+
+        let mut bad = AccessFilter {
+            deny: vec!["10.0.0.0/33".to_string()],
+            ..AccessFilter::default()
+        };
+        assert!(bad.normalize().is_err());
+    }
+}
@@ -0,0 +1,347 @@
+use anyhow::{Context, Result};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+
+use crate::friends::check_profile_id;
+use crate::storage::{write_atomic, WorldStore};
+
+/// Lifetime of a login session.
+pub const SESSION_TTL: Duration = Duration::from_secs(60 * 60);
+
+pub const MIN_PASSWORD_CHARS: usize = 8;
+
+const MAX_USERNAME_CHARS: usize = 32;
+
+/// Wrong passwords for one account before logins to it pause for `LOCKOUT`.
+const MAX_FAILED_LOGINS: u32 = 5;
+
+const LOCKOUT: Duration = Duration::from_secs(60);
+
+/// Serializes read-modify-write of `accounts.json` within this process.
+static ACCOUNTS_LOCK: Mutex<()> = Mutex::new(());
+
+/// A named local account, for households sharing one host. Each maps to one profile, whose
+/// avatar, wardrobe, friends and companion history it alone can use while logged in.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountV1 {
+    pub username: String,
+    pub profile_id: String,
+    /// May use admin-only endpoints and other accounts' profiles.
+    #[serde(default)]
+    pub admin: bool,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredAccount {
+    #[serde(flatten)]
+    account: AccountV1,
+    /// Argon2id PHC string.
+    password_hash: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct AccountsFileV1 {
+    #[serde(default)]
+    accounts: Vec<StoredAccount>,
+}
+
+pub fn accounts_path(store: &WorldStore) -> PathBuf {
+    store.root_dir().join("accounts.json")
+}
+
+fn load_file(store: &WorldStore) -> Result<AccountsFileV1> {
+    let path = accounts_path(store);
+    if !path.exists() {
+        return Ok(AccountsFileV1::default());
+    }
+    let data = fs::read_to_string(&path).with_context(|| format!("read {path:?}"))?;
+    serde_json::from_str(&data).with_context(|| format!("parse {path:?}"))
+}
+
+fn save_file(store: &WorldStore, file: &AccountsFileV1) -> Result<()> {
+    let json = serde_json::to_string_pretty(file).context("serialize accounts")?;
+    write_atomic(&accounts_path(store), format!("{json}\n").as_bytes())?;
+    Ok(())
+}
+
+pub fn check_username(username: &str) -> Result<()> {
+    let ok = !username.is_empty()
+        && username.len() <= MAX_USERNAME_CHARS
+        && username
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-');
+    if !ok {
+        anyhow::bail!("usernames are 1-{MAX_USERNAME_CHARS} characters of a-z, 0-9, '_' and '-'");
+    }
+    Ok(())
+}
+
+fn hash_password(password: &str) -> Result<String> {
+    if password.chars().count() < MIN_PASSWORD_CHARS {
+        anyhow::bail!("passwords need at least {MIN_PASSWORD_CHARS} characters");
+    }
+    let mut salt = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let salt = SaltString::encode_b64(&salt).map_err(|e| anyhow::anyhow!("salt: {e}"))?;
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|h| h.to_string())
+        .map_err(|e| anyhow::anyhow!("hash password: {e}"))
+}
+
+pub fn list(store: &WorldStore) -> Result<Vec<AccountV1>> {
+    Ok(load_file(store)?
+        .accounts
+        .into_iter()
+        .map(|a| a.account)
+        .collect())
+}
+
+/// Create an account; its profile defaults to the username.
+pub fn add(
+    store: &WorldStore,
+    username: &str,
+    password: &str,
+    profile_id: Option<&str>,
+    admin: bool,
+) -> Result<AccountV1> {
+    check_username(username)?;
+    let profile_id = profile_id.unwrap_or(username);
+    check_profile_id(profile_id)?;
+    let password_hash = hash_password(password)?;
+    let _guard = ACCOUNTS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut file = load_file(store)?;
+    if file.accounts.iter().any(|a| a.account.username == username) {
+        anyhow::bail!("account {username:?} already exists");
+    }
+    let account = AccountV1 {
+        username: username.to_string(),
+        profile_id: profile_id.to_string(),
+        admin,
+        created_at: OffsetDateTime::now_utc(),
+    };
+    file.accounts.push(StoredAccount {
+        account: account.clone(),
+        password_hash,
+    });
+    save_file(store, &file)?;
+    Ok(account)
+}
+
+/// `false` if there is no such account.
+pub fn remove(store: &WorldStore, username: &str) -> Result<bool> {
+    let _guard = ACCOUNTS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut file = load_file(store)?;
+    let before = file.accounts.len();
+    file.accounts.retain(|a| a.account.username != username);
+    if file.accounts.len() == before {
+        return Ok(false);
+    }
+    save_file(store, &file)?;
+    Ok(true)
+}
+
+/// `false` if there is no such account.
+pub fn set_password(store: &WorldStore, username: &str, password: &str) -> Result<bool> {
+    let password_hash = hash_password(password)?;
+    let _guard = ACCOUNTS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut file = load_file(store)?;
+    let Some(stored) = file
+        .accounts
+        .iter_mut()
+        .find(|a| a.account.username == username)
+    else {
+        return Ok(false);
+    };
+    stored.password_hash = password_hash;
+    save_file(store, &file)?;
+    Ok(true)
+}
+
+/// The account, if `password` is right. Reads the file every time, so accounts added with the
+/// CLI can log in without a restart.
+pub fn verify(store: &WorldStore, username: &str, password: &str) -> Result<Option<AccountV1>> {
+    let file = load_file(store)?;
+    let Some(stored) = file
+        .accounts
+        .into_iter()
+        .find(|a| a.account.username == username)
+    else {
+        return Ok(None);
+    };
+    let hash = PasswordHash::new(&stored.password_hash)
+        .map_err(|e| anyhow::anyhow!("stored hash of {username:?}: {e}"))?;
+    Ok(Argon2::default()
+        .verify_password(password.as_bytes(), &hash)
+        .is_ok()
+        .then_some(stored.account))
+}
+
+/// A logged-in account, as resolved from its session token.
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub username: String,
+    pub profile_id: String,
+    pub admin: bool,
+    expires: Instant,
+}
+
+/// Returned by `POST /auth/login`.
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionToken {
+    pub token: String,
+    pub expires_in_secs: u64,
+    pub username: String,
+    pub profile_id: String,
+}
+
+#[derive(Default)]
+struct SessionState {
+    /// By SHA-256 of the token.
+    sessions: HashMap<String, Session>,
+    /// Failed logins per username and when the last one happened.
+    failures: HashMap<String, (u32, Instant)>,
+}
+
+/// Login sessions. In memory only: a restart logs everyone out.
+#[derive(Clone, Default)]
+pub struct Sessions {
+    state: Arc<Mutex<SessionState>>,
+}
+
+fn token_hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+impl Sessions {
+    fn state(&self) -> std::sync::MutexGuard<'_, SessionState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Whether `username` is paused after too many wrong passwords.
+    pub fn locked_out(&self, username: &str) -> bool {
+        self.state()
+            .failures
+            .get(username)
+            .is_some_and(|(n, at)| *n >= MAX_FAILED_LOGINS && at.elapsed() < LOCKOUT)
+    }
+
+    pub fn record_failure(&self, username: &str) {
+        let mut state = self.state();
+        let entry = state
+            .failures
+            .entry(username.to_string())
+            .or_insert((0, Instant::now()));
+        if entry.1.elapsed() >= LOCKOUT {
+            entry.0 = 0;
+        }
+        *entry = (entry.0 + 1, Instant::now());
+    }
+
+    pub fn issue(&self, account: &AccountV1) -> SessionToken {
+        let token: String = rand::thread_rng()
+            .sample_iter(&rand::distributions::Alphanumeric)
+            .take(40)
+            .map(char::from)
+            .collect();
+        let token = format!("owps_{token}");
+        let now = Instant::now();
+        let mut state = self.state();
+        state.failures.remove(&account.username);
+        state.sessions.retain(|_, s| s.expires > now);
+        state.sessions.insert(
+            token_hash(&token),
+            Session {
+                username: account.username.clone(),
+                profile_id: account.profile_id.clone(),
+                admin: account.admin,
+                expires: now + SESSION_TTL,
+            },
+        );
+        SessionToken {
+            token,
+            expires_in_secs: SESSION_TTL.as_secs(),
+            username: account.username.clone(),
+            profile_id: account.profile_id.clone(),
+        }
+    }
+
+    pub fn lookup(&self, token: &str) -> Option<Session> {
+        let state = self.state();
+        state
+            .sessions
+            .get(&token_hash(token))
+            .filter(|s| s.expires > Instant::now())
+            .cloned()
+    }
+
+    pub fn end(&self, token: &str) -> bool {
+        self.state().sessions.remove(&token_hash(token)).is_some()
+    }
+
+    /// Log out every session of `username`, e.g. after its password changed.
+    pub fn end_all(&self, username: &str) {
+        self.state().sessions.retain(|_, s| s.username != username);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accounts_log_in_and_lock_out() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let store = WorldStore::with_root(root.path().to_path_buf()).expect("store");
+        let kid = add(&store, "kid", "correct horse", None, false).expect("add");
+        assert_eq!(kid.profile_id, "kid");
+        assert!(add(&store, "kid", "another one", None, false).is_err());
+        assert!(add(&store, "Parent", "long enough", None, true).is_err());
+        assert!(add(&store, "parent", "short", None, true).is_err());
+        assert!(!fs::read_to_string(accounts_path(&store))
+            .expect("file")
+            .contains("correct horse"));
+
+        assert_eq!(
+            verify(&store, "kid", "correct horse").expect("verify"),
+            Some(kid.clone())
+        );
+        assert_eq!(verify(&store, "kid", "wrong horse").expect("verify"), None);
+        assert_eq!(
+            verify(&store, "nobody", "correct horse").expect("verify"),
+            None
+        );
+
+        let sessions = Sessions::default();
+        let token = sessions.issue(&kid);
+        let session = sessions.lookup(&token.token).expect("session");
+        assert_eq!((session.profile_id.as_str(), session.admin), ("kid", false));
+        sessions.end_all("kid");
+        assert!(sessions.lookup(&token.token).is_none());
+
+        for _ in 0..MAX_FAILED_LOGINS {
+            assert!(!sessions.locked_out("kid"));
+            sessions.record_failure("kid");
+        }
+        assert!(sessions.locked_out("kid"));
+
+        assert!(set_password(&store, "kid", "battery staple").expect("set"));
+        assert_eq!(
+            verify(&store, "kid", "correct horse").expect("verify"),
+            None
+        );
+        assert!(remove(&store, "kid").expect("remove"));
+        assert_eq!(list(&store).expect("list"), vec![]);
+    }
+}
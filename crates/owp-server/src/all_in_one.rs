@@ -0,0 +1,182 @@
+use anyhow::{Context, Result};
+use owp_protocol::DisconnectReason;
+use std::future::Future;
+use std::path::PathBuf;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::config::LiveConfig;
+use crate::crash;
+use crate::health::{HealthRegistry, ServiceState};
+use crate::party::Parties;
+use crate::presence::Roster;
+use crate::quota::Quotas;
+use crate::storage::WorldStore;
+use crate::tcp_game;
+use crate::wal;
+use crate::web_admin;
+
+/// Everything `owp-server all-in-one` needs; every field can come from the environment.
+#[derive(Debug, Clone)]
+pub struct AllInOneConfig {
+    pub data_dir: PathBuf,
+    pub admin_listen: String,
+    pub admin_token: Option<String>,
+    pub no_auth: bool,
+    /// Worlds to serve; empty = every world in the data dir.
+    pub worlds: Vec<String>,
+    /// Bind host for game listeners. Each world keeps its manifest `game_port`.
+    pub game_host: String,
+    /// Create a world with this name when the data dir has none (first container start).
+    pub create_world: Option<String>,
+    pub discovery: web_admin::DiscoveryConfig,
+    /// Also serve the admin API over gRPC on this address.
+    pub grpc_listen: Option<String>,
+}
+
+pub async fn run(cfg: AllInOneConfig) -> Result<()> {
+    let store = WorldStore::with_root(cfg.data_dir.clone())?;
+    crash::install(&store);
+    info!("data dir: {}", store.root_dir().display());
+
+    let mut worlds = store.list_worlds()?;
+    if worlds.is_empty() {
+        if let Some(name) = cfg.create_world.as_deref().filter(|n| !n.trim().is_empty()) {
+            let m = store.create_world(name, 7777)?;
+            info!("created world {} ({name})", m.world_id);
+            worlds.push(m);
+        }
+    }
+
+    let world_ids: Vec<Uuid> = if cfg.worlds.is_empty() {
+        worlds.iter().map(|m| m.world_id).collect()
+    } else {
+        cfg.worlds
+            .iter()
+            .filter(|w| !w.trim().is_empty())
+            .map(|w| Uuid::parse_str(w.trim()).with_context(|| format!("invalid world id {w:?}")))
+            .collect::<Result<_>>()?
+    };
+
+    let auth = if cfg.no_auth {
+        web_admin::AuthMode::Disabled
+    } else {
+        let token = match cfg.admin_token.filter(|t| !t.trim().is_empty()) {
+            Some(t) => t,
+            None => store
+                .load_or_create_admin_token()
+                .context("create/load admin token")?,
+        };
+        web_admin::AuthMode::BearerToken(token)
+    };
+
+    let health = HealthRegistry::default();
+    let config = LiveConfig::load(&store)?;
+    config.spawn_watchers();
+    // One party registry for all worlds, so parties can move between them.
+    let parties = Parties::default();
+    let roster = Roster::default();
+    // Shared with the admin API, so its `/metrics` covers every world's budget.
+    let quotas = Quotas::default();
+    for world_id in world_ids {
+        let manifest = store
+            .read_manifest(&store.world_dir(world_id))
+            .with_context(|| format!("world {world_id}"))?;
+        let listen = format!("{}:{}", cfg.game_host, manifest.ports.game_port);
+        let store = store.clone();
+        let health = health.clone();
+        let config = config.clone();
+        let parties = parties.clone();
+        let roster = roster.clone();
+        let quotas = quotas.clone();
+        tokio::spawn(async move {
+            // A failed world shows up in /health/services instead of taking the process down.
+            if let Err(e) = tcp_game::serve(
+                store,
+                world_id,
+                Some(listen),
+                health.clone(),
+                config,
+                parties,
+                roster,
+                quotas,
+            )
+            .await
+            {
+                error!("game server for {world_id} stopped: {e:#}");
+                health.set(
+                    tcp_game::health_key(world_id),
+                    ServiceState::Failed,
+                    Some(format!("{e:#}")),
+                );
+            }
+        });
+    }
+
+    tokio::select! {
+        r = web_admin::serve(
+            cfg.admin_listen,
+            store.clone(),
+            auth,
+            cfg.discovery,
+            health,
+            config,
+            roster.clone(),
+            cfg.grpc_listen,
+            false,
+            quotas,
+        ) => r,
+        _ = shutdown_signal() => {
+            info!("shutting down");
+            tcp_game::disconnect_all(&roster, DisconnectReason::ServerShutdown, SHUTDOWN_MESSAGE)
+                .await;
+            for m in store.list_worlds()? {
+                if let Err(e) = wal::checkpoint(&store.world_dir(m.world_id)) {
+                    error!("wal checkpoint failed for {}: {e:#}", m.world_id);
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+const SHUTDOWN_MESSAGE: &str = "the server is shutting down";
+
+/// Run one game server until Ctrl-C/SIGTERM, then say goodbye to its players.
+pub async fn serve_until_shutdown(
+    serve: impl Future<Output = Result<()>>,
+    roster: &Roster,
+) -> Result<()> {
+    tokio::select! {
+        r = serve => r,
+        _ = shutdown_signal() => {
+            info!("shutting down");
+            tcp_game::disconnect_all(roster, DisconnectReason::ServerShutdown, SHUTDOWN_MESSAGE)
+                .await;
+            Ok(())
+        }
+    }
+}
+
+/// Ctrl-C, or SIGTERM on unix (what `docker stop` sends; PID 1 has no default handler).
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = term.recv() => {}
+                }
+            }
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
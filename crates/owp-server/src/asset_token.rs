@@ -0,0 +1,155 @@
+//! Short-lived tokens for private world assets (`assets.private`). The game server hands one to
+//! each admitted player (`asset_token` messages) and the asset routes check it, so a private
+//! world's files can't be fetched by someone who merely learned their URLs.
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use ring::hmac;
+use std::path::PathBuf;
+use std::time::Duration;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::storage::{read_or_create_secret, WorldStore};
+
+/// How long a token is good for. The game server sends a fresh one halfway through.
+pub const TOKEN_TTL: Duration = Duration::from_secs(30 * 60);
+
+const DOMAIN: &str = "owp-asset-v1";
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum AssetTokenError {
+    #[error("malformed asset token")]
+    Malformed,
+    #[error("asset token is for another world")]
+    WrongWorld,
+    #[error("asset token expired")]
+    Expired,
+    #[error("asset token signature does not match")]
+    BadSignature,
+}
+
+/// The server secret tokens are signed with, shared by every process using the data dir.
+#[derive(Clone)]
+pub struct AssetTokenKey(hmac::Key);
+
+pub fn key_path(store: &WorldStore) -> PathBuf {
+    store.root_dir().join("asset-token-key")
+}
+
+impl AssetTokenKey {
+    pub fn from_secret(secret: &[u8; 32]) -> Self {
+        Self(hmac::Key::new(hmac::HMAC_SHA256, secret))
+    }
+
+    /// Load the secret, creating one on first use.
+    pub fn load_or_create(store: &WorldStore) -> Result<Self> {
+        let path = key_path(store);
+        let (hex_key, _) = read_or_create_secret(&path, || {
+            format!("{}\n", hex::encode(rand::random::<[u8; 32]>()))
+        })?;
+        let secret: [u8; 32] = hex::decode(hex_key.trim())
+            .ok()
+            .and_then(|b| b.try_into().ok())
+            .with_context(|| format!("{path:?} is not a 32-byte hex key"))?;
+        Ok(Self::from_secret(&secret))
+    }
+
+    /// `v1.<world_id>.<player_id>.<expires_at>.<signature>`, good until `expires_at` (unix
+    /// seconds).
+    pub fn issue(&self, world_id: Uuid, player_id: Uuid, expires_at: i64) -> String {
+        let claims = format!("{world_id}.{player_id}.{expires_at}");
+        let sig = self.sign(&claims);
+        format!("v1.{claims}.{sig}")
+    }
+
+    /// The player a token was issued to, if it is genuine, for `world_id` and not expired.
+    pub fn check(&self, token: &str, world_id: Uuid, now: i64) -> Result<Uuid, AssetTokenError> {
+        let rest = token
+            .strip_prefix("v1.")
+            .ok_or(AssetTokenError::Malformed)?;
+        let (claims, sig) = rest.rsplit_once('.').ok_or(AssetTokenError::Malformed)?;
+        let sig = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(sig)
+            .map_err(|_| AssetTokenError::Malformed)?;
+        let message = format!("{DOMAIN}\n{claims}");
+        hmac::verify(&self.0, message.as_bytes(), &sig)
+            .map_err(|_| AssetTokenError::BadSignature)?;
+        let mut parts = claims.split('.');
+        let (Some(world), Some(player), Some(expires_at), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(AssetTokenError::Malformed);
+        };
+        let world = Uuid::parse_str(world).map_err(|_| AssetTokenError::Malformed)?;
+        let player = Uuid::parse_str(player).map_err(|_| AssetTokenError::Malformed)?;
+        let expires_at: i64 = expires_at.parse().map_err(|_| AssetTokenError::Malformed)?;
+        if world != world_id {
+            return Err(AssetTokenError::WrongWorld);
+        }
+        if now >= expires_at {
+            return Err(AssetTokenError::Expired);
+        }
+        Ok(player)
+    }
+
+    fn sign(&self, claims: &str) -> String {
+        let message = format!("{DOMAIN}\n{claims}");
+        let tag = hmac::sign(&self.0, message.as_bytes());
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(tag.as_ref())
+    }
+}
+
+/// Whether `given` equals the secret `expected`, in time that doesn't depend on where they
+/// differ: both go through HMAC under a throwaway key and `hmac::verify` compares the tags.
+pub fn secret_eq(expected: &str, given: &str) -> bool {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, &rand::random::<[u8; 32]>());
+    let tag = hmac::sign(&key, expected.as_bytes());
+    hmac::verify(&key, given.as_bytes(), tag.as_ref()).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_match_only_themselves() {
+        assert!(secret_eq("s3cret", "s3cret"));
+        assert!(!secret_eq("s3cret", "s3creT"));
+        assert!(!secret_eq("s3cret", "s3cret-and-more"));
+        assert!(!secret_eq("s3cret", ""));
+    }
+
+    #[test]
+    fn tokens_hold_only_for_their_world_until_they_expire() {
+        let key = AssetTokenKey::from_secret(&[7; 32]);
+        let (world, player) = (Uuid::new_v4(), Uuid::new_v4());
+        let token = key.issue(world, player, 1_000);
+        assert_eq!(key.check(&token, world, 999), Ok(player));
+        assert_eq!(
+            key.check(&token, world, 1_000),
+            Err(AssetTokenError::Expired)
+        );
+        assert_eq!(
+            key.check(&token, Uuid::new_v4(), 999),
+            Err(AssetTokenError::WrongWorld)
+        );
+
+        let other = AssetTokenKey::from_secret(&[8; 32]);
+        assert_eq!(
+            other.check(&token, world, 999),
+            Err(AssetTokenError::BadSignature)
+        );
+        // Stretching the expiry breaks the signature.
+        let forged = token.replace(".1000.", ".9000.");
+        assert_eq!(
+            key.check(&forged, world, 999),
+            Err(AssetTokenError::BadSignature)
+        );
+        assert_eq!(
+            key.check("v1.nonsense", world, 0),
+            Err(AssetTokenError::Malformed)
+        );
+        assert_eq!(key.check("", world, 0), Err(AssetTokenError::Malformed));
+    }
+}
@@ -0,0 +1,311 @@
+use anyhow::{Context, Result};
+use owp_protocol::{AssetRef, SyncEntry, SyncManifestV1, WorldAssetsConfig};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tracing::warn;
+
+use crate::content;
+use crate::prefabs;
+use crate::storage::WorldStore;
+
+/// Cap on `Welcome.prefetch`, so a cluttered `assets/` can't blow up the handshake.
+pub const PREFETCH_MAX_ENTRIES: usize = 256;
+
+/// Cap on sync manifest entries.
+pub const SYNC_MAX_ENTRIES: usize = 10_000;
+
+pub fn assets_dir(world_dir: &Path) -> PathBuf {
+    world_dir.join("assets")
+}
+
+/// Files under `dir`, relative to it, in sorted order. Leftover `*.tmp` files are skipped.
+fn walk(dir: &Path, rel: &Path, out: &mut Vec<PathBuf>) -> Result<()> {
+    let mut entries: Vec<_> = fs::read_dir(dir)
+        .with_context(|| format!("read {dir:?}"))?
+        .flatten()
+        .collect();
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let path = entry.path();
+        let rel = rel.join(entry.file_name());
+        if path.is_dir() {
+            walk(&path, &rel, out)?;
+        } else if path.extension().and_then(|e| e.to_str()) != Some("tmp") {
+            out.push(rel);
+        }
+    }
+    Ok(())
+}
+
+/// A configured prefetch path, if it stays inside `assets/`.
+pub fn safe_relative(path: &str) -> Option<PathBuf> {
+    let p = Path::new(path);
+    p.components()
+        .all(|c| matches!(c, Component::Normal(_)))
+        .then(|| p.to_path_buf())
+}
+
+fn uri_for(rel: &Path, base_url: Option<&str>) -> String {
+    let rel = rel
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    match base_url {
+        Some(base) => format!("{}/{rel}", base.trim_end_matches('/')),
+        None => format!("assets/{rel}"),
+    }
+}
+
+struct CachedHash {
+    size: u64,
+    mtime: SystemTime,
+    sha256: String,
+}
+
+/// Content hashes of a world's assets, recomputed only when a file's size or mtime changes.
+#[derive(Clone, Default)]
+pub struct AssetIndex {
+    hashes: Arc<Mutex<HashMap<PathBuf, CachedHash>>>,
+}
+
+impl AssetIndex {
+    fn hash(&self, path: &Path) -> Result<(u64, String)> {
+        let meta = fs::metadata(path).with_context(|| format!("stat {path:?}"))?;
+        let size = meta.len();
+        let mtime = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+        let mut hashes = self.hashes.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(c) = hashes.get(path) {
+            if c.size == size && c.mtime == mtime {
+                return Ok((size, c.sha256.clone()));
+            }
+        }
+        let bytes = fs::read(path).with_context(|| format!("read {path:?}"))?;
+        let sha256 = hex::encode(Sha256::digest(&bytes));
+        hashes.insert(
+            path.to_path_buf(),
+            CachedHash {
+                size,
+                mtime,
+                sha256: sha256.clone(),
+            },
+        );
+        Ok((size, sha256))
+    }
+
+    /// The `Welcome.prefetch` list for a world: the configured paths, or every file in
+    /// `assets/` outside `prefabs/` (those ship in the prefab bundle) when none are configured.
+    /// Missing or unsafe paths are skipped with a warning.
+    pub fn prefetch_list(
+        &self,
+        world_dir: &Path,
+        cfg: &WorldAssetsConfig,
+    ) -> Result<Vec<AssetRef>> {
+        let dir = assets_dir(world_dir);
+        let mut paths = Vec::new();
+        if cfg.prefetch.is_empty() {
+            if dir.exists() {
+                walk(&dir, Path::new(""), &mut paths)?;
+                paths.retain(|p| !p.starts_with("prefabs"));
+            }
+        } else {
+            for p in &cfg.prefetch {
+                match safe_relative(p) {
+                    Some(rel) => paths.push(rel),
+                    None => warn!("prefetch path {p:?} leaves assets/; skipped"),
+                }
+            }
+        }
+        if paths.len() > PREFETCH_MAX_ENTRIES {
+            warn!(
+                "{} prefetch assets; only the first {PREFETCH_MAX_ENTRIES} are announced",
+                paths.len()
+            );
+            paths.truncate(PREFETCH_MAX_ENTRIES);
+        }
+
+        let mut out = Vec::with_capacity(paths.len());
+        for rel in paths {
+            match self.hash(&dir.join(&rel)) {
+                Ok((size, sha256)) => out.push(AssetRef {
+                    sha256,
+                    size,
+                    uri: uri_for(&rel, cfg.base_url.as_deref()),
+                }),
+                Err(e) => warn!("prefetch asset skipped: {e:#}"),
+            }
+        }
+        Ok(out)
+    }
+
+    /// Everything a client should cache for a world: each file in `assets/` outside `prefabs/`,
+    /// the prefab bundle and its meshes, and the icon. Without a `base_url`, `assets/` files
+    /// point at `GET /worlds/:world_id/assets/*path`. Content shared by several paths is listed
+    /// once, at its most urgent priority. Content-store URIs carry `?world_id=` so the download
+    /// counts against this world's bandwidth.
+    pub fn sync_manifest(&self, store: &WorldStore, world_dir: &Path) -> Result<SyncManifestV1> {
+        let manifest = store.read_manifest(world_dir)?;
+        let cfg = &manifest.assets;
+        let dir = assets_dir(world_dir);
+        let mut paths = Vec::new();
+        if dir.exists() {
+            walk(&dir, Path::new(""), &mut paths)?;
+            paths.retain(|p| !p.starts_with("prefabs"));
+        }
+        if paths.len() > SYNC_MAX_ENTRIES {
+            anyhow::bail!(
+                "{} assets; sync lists at most {SYNC_MAX_ENTRIES}",
+                paths.len()
+            );
+        }
+        let prefetch: HashSet<String> = self
+            .prefetch_list(world_dir, cfg)?
+            .into_iter()
+            .map(|a| a.uri)
+            .collect();
+
+        let mut entries = Vec::new();
+        for rel in paths {
+            let uri = uri_for(&rel, cfg.base_url.as_deref());
+            let (size, sha256) = match self.hash(&dir.join(&rel)) {
+                Ok(h) => h,
+                Err(e) => {
+                    warn!("sync asset skipped: {e:#}");
+                    continue;
+                }
+            };
+            entries.push(SyncEntry {
+                priority: if prefetch.contains(&uri) { 0 } else { 1 },
+                uri: match cfg.base_url {
+                    Some(_) => uri,
+                    None => format!("/worlds/{}/{uri}", manifest.world_id),
+                },
+                sha256,
+                size,
+            });
+        }
+        if let Some(r) = &manifest.prefab_bundle {
+            let AssetRef { sha256, size, uri } = prefabs::prefetch_ref(r);
+            entries.push(SyncEntry {
+                sha256,
+                size,
+                uri,
+                priority: 0,
+            });
+            for p in prefabs::current(store, world_dir)?.map_or(vec![], |b| b.prefabs) {
+                entries.push(SyncEntry {
+                    sha256: p.mesh.sha256,
+                    size: p.mesh.size,
+                    uri: p.mesh.uri,
+                    priority: 1,
+                });
+            }
+        }
+        if let Some(icon) = &manifest.icon_sha256 {
+            match fs::metadata(content::content_path(store, icon)) {
+                Ok(meta) => entries.push(SyncEntry {
+                    sha256: icon.clone(),
+                    size: meta.len(),
+                    uri: content::content_uri(icon),
+                    priority: 2,
+                }),
+                Err(e) => warn!("world icon {icon} missing from the content store: {e}"),
+            }
+        }
+
+        entries.sort_by(|a, b| (a.priority, &a.uri).cmp(&(b.priority, &b.uri)));
+        let mut seen = HashSet::new();
+        entries.retain(|e| seen.insert(e.sha256.clone()));
+        for e in &mut entries {
+            if e.uri.starts_with("/content/") {
+                e.uri = format!("{}?world_id={}", e.uri, manifest.world_id);
+            }
+        }
+        Ok(SyncManifestV1 {
+            world_id: manifest.world_id,
+            entries,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_hashes_and_respects_config() {
+        let world = tempfile::tempdir().expect("tempdir");
+        let dir = assets_dir(world.path());
+        fs::create_dir_all(dir.join("npc")).expect("mkdir");
+        fs::write(dir.join("terrain.png"), b"height").expect("write");
+        fs::write(dir.join("npc").join("guard.glb"), b"mesh").expect("write");
+        fs::write(dir.join("half.tmp"), b"x").expect("write");
+
+        let index = AssetIndex::default();
+        let all = index
+            .prefetch_list(world.path(), &WorldAssetsConfig::default())
+            .expect("list");
+        let uris: Vec<&str> = all.iter().map(|a| a.uri.as_str()).collect();
+        assert_eq!(uris, ["assets/npc/guard.glb", "assets/terrain.png"]);
+        assert_eq!(all[1].size, 6);
+        assert_eq!(all[1].sha256, hex::encode(Sha256::digest(b"height")));
+
+        let cfg = WorldAssetsConfig {
+            base_url: Some("https://cdn.example.com/w/".to_string()),
+            prefetch: vec![
+                "terrain.png".to_string(),
+                "../manifest/world.manifest.json".to_string(),
+                "missing.bin".to_string(),
+            ],
+            ..WorldAssetsConfig::default()
+        };
+        let picked = index.prefetch_list(world.path(), &cfg).expect("list");
+        assert_eq!(picked.len(), 1);
+        assert_eq!(picked[0].uri, "https://cdn.example.com/w/terrain.png");
+    }
+
+    #[test]
+    fn sync_manifest_lists_assets_bundle_and_icon() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let store = WorldStore::with_root(root.path().to_path_buf()).expect("store");
+        let mut manifest = store.create_world("sync", 7777).expect("create");
+        let world_dir = store.world_dir(manifest.world_id);
+        let dir = assets_dir(&world_dir);
+        fs::create_dir_all(dir.join("prefabs")).expect("mkdir");
+        fs::write(dir.join("terrain.png"), b"height").expect("write");
+        fs::write(dir.join("music.ogg"), b"tune").expect("write");
+        fs::write(dir.join("copy.ogg"), b"tune").expect("write");
+        fs::write(dir.join("prefabs").join("rock.glb"), b"rock").expect("write");
+        manifest.assets.prefetch = vec!["terrain.png".to_string()];
+        manifest.icon_sha256 = Some(content::put(&store, b"icon").expect("icon"));
+        store
+            .write_manifest(&world_dir, &manifest)
+            .expect("manifest");
+        let bundle = prefabs::build(&store, &world_dir).expect("bundle");
+
+        let sync = AssetIndex::default()
+            .sync_manifest(&store, &world_dir)
+            .expect("sync");
+        let listed: Vec<(u8, String)> = sync
+            .entries
+            .into_iter()
+            .map(|e| (e.priority, e.uri))
+            .collect();
+        let id = manifest.world_id;
+        let metered = |sha256: &str| format!("{}?world_id={id}", content::content_uri(sha256));
+        // music.ogg has the same bytes as copy.ogg, so only the first is listed.
+        let mut want = vec![
+            (0, metered(&bundle.sha256)),
+            (0, format!("/worlds/{id}/assets/terrain.png")),
+            (1, format!("/worlds/{id}/assets/copy.ogg")),
+            (1, metered(&content::hash(b"rock"))),
+            (2, metered(&content::hash(b"icon"))),
+        ];
+        want.sort();
+        assert_eq!(listed, want);
+    }
+}
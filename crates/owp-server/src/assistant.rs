@@ -3,15 +3,40 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::path::Path;
 use std::path::PathBuf;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::io::AsyncWriteExt;
 use tokio::process::Command;
 use tokio::time::timeout;
+use tracing::{info, warn};
 
 use owp_protocol::AvatarSpecV1;
 
 use crate::avatar as avatar_mod;
-use crate::storage::WorldStore;
+use crate::avatar_history::RevisionSource;
+use crate::generation_cache;
+use crate::privacy;
+use crate::provider_health;
+use crate::storage::{StorageError, WorldStore};
+use crate::versioned::{self, Versioned};
+
+/// Why an assistant job failed, so the admin API can tell "install or fix the provider" from
+/// "the provider answered with something unusable".
+#[derive(Debug, thiserror::Error)]
+pub enum AssistantError {
+    /// No provider is configured, or its CLI is missing, timed out or exited with an error.
+    #[error("{0}")]
+    ProviderUnavailable(String),
+    /// The provider ran but didn't return the JSON we asked for.
+    #[error("{0}")]
+    Corrupt(String),
+    /// The composed prompt is over `max_prompt_tokens`, even with the history left out.
+    #[error("{0}")]
+    PromptTooLarge(String),
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
@@ -45,12 +70,27 @@ pub struct AssistantConfig {
     /// When enabled, generate an OpenSCAD→STL avatar mesh on each chat update (host-only).
     #[serde(default = "default_avatar_mesh_enabled")]
     pub avatar_mesh_enabled: bool,
+    /// How long generation outputs are reused for identical requests; 0 turns caching off.
+    #[serde(default = "default_generation_cache_ttl_secs")]
+    pub generation_cache_ttl_secs: u64,
+    /// Estimated tokens a prompt may take (see `estimate_tokens`); companion history is cut to
+    /// fit and larger prompts are refused. 0 means no limit.
+    #[serde(default = "default_max_prompt_tokens")]
+    pub max_prompt_tokens: usize,
 }
 
 fn default_avatar_mesh_enabled() -> bool {
     true
 }
 
+fn default_generation_cache_ttl_secs() -> u64 {
+    24 * 60 * 60
+}
+
+fn default_max_prompt_tokens() -> usize {
+    24_000
+}
+
 impl Default for AssistantConfig {
     fn default() -> Self {
         Self {
@@ -59,16 +99,45 @@ impl Default for AssistantConfig {
             codex_reasoning_effort: None,
             claude_model: None,
             avatar_mesh_enabled: true,
+            generation_cache_ttl_secs: default_generation_cache_ttl_secs(),
+            max_prompt_tokens: default_max_prompt_tokens(),
         }
     }
 }
 
+impl Versioned for AssistantConfig {
+    const VERSION: u32 = 1;
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProviderStatus {
     pub id: String,
     pub installed: bool,
     #[serde(default)]
     pub note: Option<String>,
+    /// False after repeated failures; requests fail fast until a probe succeeds.
+    pub healthy: bool,
+    pub consecutive_failures: u32,
+    /// Why the last run failed, while it keeps failing.
+    #[serde(default)]
+    pub failure: Option<String>,
+    #[serde(default)]
+    pub last_latency_ms: Option<u64>,
+}
+
+impl ProviderStatus {
+    fn new(provider: AssistantProviderId, installed: bool) -> Self {
+        let health = provider_health::report(provider);
+        Self {
+            id: provider.as_str().to_string(),
+            installed,
+            note: None,
+            healthy: health.healthy,
+            consecutive_failures: health.consecutive_failures,
+            failure: health.failure,
+            last_latency_ms: health.last_latency_ms,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -78,27 +147,15 @@ pub struct AssistantStatus {
     pub providers: Vec<ProviderStatus>,
 }
 
-pub fn load_config(store: &WorldStore) -> Result<AssistantConfig> {
-    let path = store.config_path();
-    if !path.exists() {
-        return Ok(AssistantConfig::default());
-    }
-    let data = std::fs::read_to_string(&path).with_context(|| format!("read {path:?}"))?;
-    let cfg: AssistantConfig = serde_json::from_str(&data).context("parse assistant config")?;
-    Ok(cfg)
+pub fn load_config(store: &WorldStore) -> Result<AssistantConfig, StorageError> {
+    Ok(versioned::load(&store.config_path())?.unwrap_or_default())
 }
 
-pub fn save_config(store: &WorldStore, cfg: &AssistantConfig) -> Result<()> {
-    let path = store.config_path();
-    if let Some(parent) = path.parent() {
-        std::fs::create_dir_all(parent).with_context(|| format!("create {parent:?}"))?;
-    }
-    let json = serde_json::to_string_pretty(cfg).context("serialize assistant config")?;
-    std::fs::write(&path, format!("{json}\n")).with_context(|| format!("write {path:?}"))?;
-    Ok(())
+pub fn save_config(store: &WorldStore, cfg: &AssistantConfig) -> Result<(), StorageError> {
+    versioned::save(&store.config_path(), cfg)
 }
 
-pub async fn status(store: &WorldStore) -> Result<AssistantStatus> {
+pub async fn status(store: &WorldStore) -> Result<AssistantStatus, StorageError> {
     let cfg = load_config(store)?;
     let provider = cfg.provider.map(|p| p.as_str().to_string());
 
@@ -108,16 +165,8 @@ pub async fn status(store: &WorldStore) -> Result<AssistantStatus> {
     Ok(AssistantStatus {
         provider,
         providers: vec![
-            ProviderStatus {
-                id: "codex".to_string(),
-                installed: codex,
-                note: None,
-            },
-            ProviderStatus {
-                id: "claude".to_string(),
-                installed: claude,
-                note: None,
-            },
+            ProviderStatus::new(AssistantProviderId::Codex, codex),
+            ProviderStatus::new(AssistantProviderId::Claude, claude),
         ],
     })
 }
@@ -135,6 +184,68 @@ async fn program_exists(program: &str) -> bool {
         .is_some()
 }
 
+/// Rough token count of `text`, at about four characters a token as for English and JSON. Close
+/// enough to keep prompts under a limit, not to bill by.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Whether `prompt` fits `cfg.max_prompt_tokens`.
+fn fits(cfg: &AssistantConfig, prompt: &str) -> bool {
+    cfg.max_prompt_tokens == 0 || estimate_tokens(prompt) <= cfg.max_prompt_tokens
+}
+
+/// The prompt `compose` builds from as many of the latest `turns` as `cfg.max_prompt_tokens`
+/// leaves room for, dropping the oldest first; `compose` also gets how many it dropped.
+pub(crate) fn fit_turns<T>(
+    cfg: &AssistantConfig,
+    turns: &[T],
+    compose: impl Fn(&[T], usize) -> String,
+) -> String {
+    let mut skip = 0;
+    loop {
+        let prompt = compose(&turns[skip..], skip);
+        if skip == turns.len() || fits(cfg, &prompt) {
+            if skip > 0 {
+                info!("left {skip} turns of history out of the prompt to fit the limit");
+            }
+            return prompt;
+        }
+        skip += 1;
+    }
+}
+
+/// Refuse a `what` prompt over `cfg.max_prompt_tokens` before a provider spends anything on it,
+/// and warn when it comes close.
+fn preflight(cfg: &AssistantConfig, what: &str, prompt: &str) -> Result<(), AssistantError> {
+    let limit = cfg.max_prompt_tokens;
+    let tokens = estimate_tokens(prompt);
+    if limit == 0 {
+        return Ok(());
+    }
+    if tokens > limit {
+        return Err(AssistantError::PromptTooLarge(format!(
+            "the {what} prompt is about {tokens} tokens, over the limit of {limit}"
+        )));
+    }
+    if tokens > limit / 10 * 8 {
+        warn!("the {what} prompt is about {tokens} tokens, close to the limit of {limit}");
+    }
+    Ok(())
+}
+
+/// Run a provider's CLI, failing fast while it is unhealthy (see `provider_health`).
+async fn checked<T>(
+    provider: AssistantProviderId,
+    run: impl std::future::Future<Output = Result<T, AssistantError>>,
+) -> Result<T, AssistantError> {
+    provider_health::admit(provider)?;
+    let started = Instant::now();
+    let out = run.await;
+    provider_health::record(provider, started.elapsed(), out.as_ref().err());
+    out
+}
+
 pub async fn run_codex_structured(
     prompt: &str,
     schema_path: &Path,
@@ -142,7 +253,26 @@ pub async fn run_codex_structured(
     cwd: Option<&Path>,
     model: Option<&str>,
     reasoning_effort: Option<&str>,
-) -> Result<()> {
+) -> Result<(), AssistantError> {
+    let run = codex_structured(
+        prompt,
+        schema_path,
+        output_path,
+        cwd,
+        model,
+        reasoning_effort,
+    );
+    checked(AssistantProviderId::Codex, run).await
+}
+
+async fn codex_structured(
+    prompt: &str,
+    schema_path: &Path,
+    output_path: &Path,
+    cwd: Option<&Path>,
+    model: Option<&str>,
+    reasoning_effort: Option<&str>,
+) -> Result<(), AssistantError> {
     let mut cmd = Command::new("codex");
     cmd.arg("exec");
     if let Some(model) = model {
@@ -179,22 +309,27 @@ pub async fn run_codex_structured(
     cmd.stdout(std::process::Stdio::null());
     cmd.stderr(std::process::Stdio::piped());
 
-    let mut child = cmd.spawn().context("spawn codex")?;
+    let unavailable = |what: &str, e: &dyn std::fmt::Display| {
+        AssistantError::ProviderUnavailable(format!("{what}: {e}"))
+    };
+    let mut child = cmd.spawn().map_err(|e| unavailable("spawn codex", &e))?;
     if let Some(mut stdin) = child.stdin.take() {
         stdin
             .write_all(prompt.as_bytes())
             .await
-            .context("write codex stdin")?;
+            .map_err(|e| unavailable("write codex stdin", &e))?;
     }
 
     let status = timeout(Duration::from_secs(120), child.wait_with_output())
         .await
-        .context("codex timeout")?
-        .context("wait codex")?;
+        .map_err(|e| unavailable("codex timeout", &e))?
+        .map_err(|e| unavailable("wait codex", &e))?;
 
     if !status.status.success() {
         let err = String::from_utf8_lossy(&status.stderr);
-        anyhow::bail!("codex failed: {err}");
+        return Err(AssistantError::ProviderUnavailable(format!(
+            "codex failed: {err}"
+        )));
     }
     Ok(())
 }
@@ -203,7 +338,19 @@ pub async fn run_claude_structured(
     prompt: &str,
     schema: &str,
     model: Option<&str>,
-) -> Result<String> {
+) -> Result<String, AssistantError> {
+    checked(
+        AssistantProviderId::Claude,
+        claude_structured(prompt, schema, model),
+    )
+    .await
+}
+
+async fn claude_structured(
+    prompt: &str,
+    schema: &str,
+    model: Option<&str>,
+) -> Result<String, AssistantError> {
     let mut cmd = Command::new("claude");
     cmd.arg("--print");
     cmd.arg("--output-format").arg("json");
@@ -220,16 +367,105 @@ pub async fn run_claude_structured(
 
     let out = timeout(Duration::from_secs(120), cmd.output())
         .await
-        .context("claude timeout")?
-        .context("run claude")?;
+        .map_err(|e| AssistantError::ProviderUnavailable(format!("claude timeout: {e}")))?
+        .map_err(|e| AssistantError::ProviderUnavailable(format!("run claude: {e}")))?;
 
     if !out.status.success() {
         let err = String::from_utf8_lossy(&out.stderr);
-        anyhow::bail!("claude failed: {err}");
+        return Err(AssistantError::ProviderUnavailable(format!(
+            "claude failed: {err}"
+        )));
     }
     Ok(String::from_utf8_lossy(&out.stdout).to_string())
 }
 
+/// The provider's JSON output for `prompt`, reused from the generation cache when an identical
+/// request was answered within the configured TTL, unless `force`. `template` names the prompt
+/// template and its version, e.g. `avatar/v1`.
+pub async fn generate_structured(
+    store: &WorldStore,
+    cfg: &AssistantConfig,
+    template: &str,
+    prompt: &str,
+    schema: &str,
+    force: bool,
+) -> Result<String, AssistantError> {
+    let Some(provider) = cfg.provider else {
+        return Err(AssistantError::ProviderUnavailable(
+            "no provider configured".to_string(),
+        ));
+    };
+    let key = generation_cache::key(cfg, provider, template, prompt, schema);
+    if !force {
+        if let Some(output) = generation_cache::get(store, &key, cfg.generation_cache_ttl_secs) {
+            info!("reusing the cached {template} output");
+            return Ok(output);
+        }
+    }
+    let output = run_structured(store, cfg, template, prompt, schema).await?;
+    // Only keep what parses, so a garbled answer isn't handed out again.
+    if cfg.generation_cache_ttl_secs > 0 && serde_json::from_str::<Value>(&output).is_ok() {
+        if let Err(e) = generation_cache::put(store, &key, &output) {
+            warn!("caching the {template} output failed: {e:#}");
+        }
+    }
+    Ok(output)
+}
+
+/// Run the configured provider on `prompt` once `preflight` lets it through, returning the JSON
+/// it produced for `schema`. `what` names the request in logs and errors.
+pub async fn run_structured(
+    store: &WorldStore,
+    cfg: &AssistantConfig,
+    what: &str,
+    prompt: &str,
+    schema: &str,
+) -> Result<String, AssistantError> {
+    let Some(provider) = cfg.provider else {
+        return Err(AssistantError::ProviderUnavailable(
+            "no provider configured".to_string(),
+        ));
+    };
+    preflight(cfg, what, prompt)?;
+    Ok(match provider {
+        AssistantProviderId::Codex => {
+            let schema_file = tempfile::NamedTempFile::new().context("create schema tempfile")?;
+            std::fs::write(schema_file.path(), schema).context("write schema tempfile")?;
+            let output_file = tempfile::NamedTempFile::new().context("create output tempfile")?;
+            run_codex_structured(
+                prompt,
+                schema_file.path(),
+                output_file.path(),
+                Some(store.root_dir()),
+                cfg.codex_model.as_deref(),
+                cfg.codex_reasoning_effort.as_deref(),
+            )
+            .await?;
+            std::fs::read_to_string(output_file.path()).context("read codex output")?
+        }
+        AssistantProviderId::Claude => {
+            let raw = run_claude_structured(prompt, schema, cfg.claude_model.as_deref()).await?;
+            claude_output_json(&raw)?
+        }
+    })
+}
+
+/// The JSON object in a `claude --output-format json` result: `structured_output` when the
+/// schema was honored, else the first object in the free-text `result`.
+pub fn claude_output_json(raw: &str) -> Result<String, AssistantError> {
+    let corrupt = |what: &str| AssistantError::Corrupt(format!("claude {what}"));
+    let v: Value = serde_json::from_str(raw).map_err(|_| corrupt("result wrapper isn't json"))?;
+    if let Some(so) = v.get("structured_output") {
+        Ok(so.to_string())
+    } else if let Some(result) = v.get("result").and_then(|r| r.as_str()) {
+        extract_json_object(result)
+            .map(str::to_string)
+            .ok_or_else(|| corrupt("result has no json object"))
+    } else {
+        Err(corrupt("did not return structured_output or result"))
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 struct CompanionTurn {
@@ -237,6 +473,10 @@ struct CompanionTurn {
     content: String,
 }
 
+impl Versioned for Vec<CompanionTurn> {
+    const VERSION: u32 = 1;
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CompanionChatResponse {
     pub reply: String,
@@ -253,13 +493,7 @@ fn companion_history_path(store: &WorldStore, profile_id: &str) -> PathBuf {
 
 fn load_companion_history(store: &WorldStore, profile_id: &str) -> Result<Vec<CompanionTurn>> {
     let path = companion_history_path(store, profile_id);
-    if !path.exists() {
-        return Ok(Vec::new());
-    }
-    let data = std::fs::read_to_string(&path).with_context(|| format!("read {path:?}"))?;
-    let turns: Vec<CompanionTurn> =
-        serde_json::from_str(&data).context("parse companion history")?;
-    Ok(turns)
+    Ok(versioned::load(&path)?.unwrap_or_default())
 }
 
 fn save_companion_history(
@@ -268,18 +502,54 @@ fn save_companion_history(
     turns: &[CompanionTurn],
 ) -> Result<()> {
     let path = companion_history_path(store, profile_id);
-    if let Some(parent) = path.parent() {
-        std::fs::create_dir_all(parent).with_context(|| format!("create {parent:?}"))?;
-    }
-    let json = serde_json::to_string_pretty(turns).context("serialize companion history")?;
-    std::fs::write(&path, format!("{json}\n")).with_context(|| format!("write {path:?}"))?;
+    versioned::save(&path, &turns.to_vec())?;
     Ok(())
 }
 
-fn extract_json_object(text: &str) -> Result<String> {
-    let start = text
-        .find('{')
-        .ok_or_else(|| anyhow::anyhow!("no '{{' found in text"))?;
+/// Append an exchange to the companion history, unless the profile opted out of keeping it.
+fn remember_turns(
+    store: &WorldStore,
+    profile_id: &str,
+    mut history: Vec<CompanionTurn>,
+    message: &str,
+    reply: &str,
+) {
+    if !privacy::load_or_default(store, profile_id).retain_companion_history {
+        return;
+    }
+    // The history was read as empty if it didn't parse; don't save over it.
+    if let Err(e) = load_companion_history(store, profile_id) {
+        warn!("companion history of {profile_id:?} unreadable, not saving: {e:#}");
+        return;
+    }
+    history.push(CompanionTurn {
+        role: "user".to_string(),
+        content: message.trim().to_string(),
+    });
+    history.push(CompanionTurn {
+        role: "assistant".to_string(),
+        content: reply.to_string(),
+    });
+    if history.len() > 80 {
+        history = history.split_off(history.len().saturating_sub(80));
+    }
+    save_companion_history(store, profile_id, &history).ok();
+}
+
+/// Delete a profile's companion history, e.g. when it stops allowing retention.
+pub fn forget_companion_history(store: &WorldStore, profile_id: &str) -> Result<()> {
+    let path = companion_history_path(store, profile_id);
+    match std::fs::remove_file(&path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            Err(e).with_context(|| format!("remove {path:?}"))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The first balanced `{...}` in `text`, if any.
+fn extract_json_object(text: &str) -> Option<&str> {
+    let start = text.find('{')?;
 
     let mut depth = 0usize;
     let mut in_string = false;
@@ -306,15 +576,14 @@ fn extract_json_object(text: &str) -> Result<String> {
             '}' => {
                 depth = depth.saturating_sub(1);
                 if depth == 0 {
-                    let end = start + i + 1;
-                    return Ok(text[start..end].to_string());
+                    return Some(&text[start..start + i + 1]);
                 }
             }
             _ => {}
         }
     }
 
-    anyhow::bail!("unterminated json object");
+    None
 }
 
 fn companion_schema_json() -> String {
@@ -374,28 +643,18 @@ pub async fn companion_chat(
     cfg: &AssistantConfig,
     profile_id: &str,
     message: &str,
-) -> Result<CompanionChatResponse> {
+) -> Result<CompanionChatResponse, AssistantError> {
     if cfg.avatar_mesh_enabled {
-        match crate::avatar_mesh::generate_avatar_mesh(store, cfg, profile_id, message).await {
+        match crate::avatar_mesh::generate_avatar_mesh(store, cfg, profile_id, message, false).await
+        {
             Ok(avatar) => {
                 let reply = format!(
                     "Updated—your avatar mesh is now **{}**. Tell me what to change next.",
                     avatar.name
                 );
 
-                let mut history = load_companion_history(store, profile_id).unwrap_or_default();
-                history.push(CompanionTurn {
-                    role: "user".to_string(),
-                    content: message.trim().to_string(),
-                });
-                history.push(CompanionTurn {
-                    role: "assistant".to_string(),
-                    content: reply.clone(),
-                });
-                if history.len() > 80 {
-                    history = history.split_off(history.len().saturating_sub(80));
-                }
-                save_companion_history(store, profile_id, &history).ok();
+                let history = load_companion_history(store, profile_id).unwrap_or_default();
+                remember_turns(store, profile_id, history, message, &reply);
 
                 return Ok(CompanionChatResponse {
                     reply,
@@ -418,37 +677,29 @@ pub async fn companion_chat(
     companion_chat_primitives(store, cfg, profile_id, message).await
 }
 
-async fn companion_chat_primitives(
-    store: &WorldStore,
+/// Turns of companion history put in a prompt, at most.
+const PROMPT_TURNS: usize = 16;
+
+/// The companion prompt with as much recent history as `cfg.max_prompt_tokens` leaves room for;
+/// the oldest turns go first.
+fn companion_prompt(
     cfg: &AssistantConfig,
-    profile_id: &str,
+    avatar_json: &str,
+    history: &[CompanionTurn],
     message: &str,
-) -> Result<CompanionChatResponse> {
-    let Some(provider) = cfg.provider else {
-        anyhow::bail!("no provider configured");
-    };
-
-    let mut history = load_companion_history(store, profile_id).unwrap_or_default();
-    // keep history bounded
-    if history.len() > 50 {
-        history = history.split_off(history.len().saturating_sub(50));
-    }
-
-    let current_avatar = avatar_mod::load_avatar(store, profile_id)
-        .context("load current avatar")?
-        .unwrap_or(AvatarSpecV1 {
-            version: "v1".to_string(),
-            name: "Traveler".to_string(),
-            primary_color: "#00D1FF".to_string(),
-            secondary_color: "#FFFFFF".to_string(),
-            height: 1.0,
-            tags: vec!["default".to_string()],
-            parts: Vec::new(),
-            mesh: None,
-        });
-    let current_avatar_json =
-        serde_json::to_string_pretty(&current_avatar).context("serialize current avatar")?;
+) -> String {
+    let recent = &history[history.len().saturating_sub(PROMPT_TURNS)..];
+    fit_turns(cfg, recent, |turns, omitted| {
+        compose_companion_prompt(avatar_json, turns, omitted, message)
+    })
+}
 
+fn compose_companion_prompt(
+    avatar_json: &str,
+    turns: &[CompanionTurn],
+    omitted: usize,
+    message: &str,
+) -> String {
     let mut prompt = String::new();
     prompt.push_str("You are the OWP Companion inside a Unity game.\n");
     prompt.push_str("You chat with the user and MAY update their avatar.\n");
@@ -464,9 +715,12 @@ async fn companion_chat_primitives(
     prompt.push_str("- Only claim details that are explicitly encoded in `avatar.tags` and/or `avatar.parts`.\n");
     prompt.push_str("- If the user asks for something you can't literally model, approximate it with primitives (horns/stripes/gear) and be honest.\n");
     prompt.push_str("\nCurrent avatar JSON:\n");
-    prompt.push_str(&current_avatar_json);
+    prompt.push_str(avatar_json);
     prompt.push_str("\n\nConversation:\n");
-    for t in history.iter().rev().take(16).rev() {
+    if omitted > 0 {
+        prompt.push_str(&format!("({omitted} earlier messages left out)\n"));
+    }
+    for t in turns {
         let who = if t.role == "assistant" {
             "Assistant"
         } else {
@@ -480,39 +734,42 @@ async fn companion_chat_primitives(
     prompt.push_str("User: ");
     prompt.push_str(message.trim());
     prompt.push('\n');
+    prompt
+}
 
+async fn companion_chat_primitives(
+    store: &WorldStore,
+    cfg: &AssistantConfig,
+    profile_id: &str,
+    message: &str,
+) -> Result<CompanionChatResponse, AssistantError> {
+    let mut history = load_companion_history(store, profile_id).unwrap_or_default();
+    // keep history bounded
+    if history.len() > 50 {
+        history = history.split_off(history.len().saturating_sub(50));
+    }
+
+    let current_avatar = avatar_mod::load_avatar(store, profile_id)
+        .context("load current avatar")?
+        .unwrap_or(AvatarSpecV1 {
+            version: "v1".to_string(),
+            name: "Traveler".to_string(),
+            primary_color: "#00D1FF".to_string(),
+            secondary_color: "#FFFFFF".to_string(),
+            height: 1.0,
+            tags: vec!["default".to_string()],
+            parts: Vec::new(),
+            mesh: None,
+        });
+    let current_avatar_json =
+        serde_json::to_string_pretty(&current_avatar).context("serialize current avatar")?;
+
+    let prompt = companion_prompt(cfg, &current_avatar_json, &history, message);
     let schema = companion_schema_json();
-    let raw_json = match provider {
-        AssistantProviderId::Codex => {
-            let schema_file = tempfile::NamedTempFile::new().context("create schema tempfile")?;
-            std::fs::write(schema_file.path(), &schema).context("write schema tempfile")?;
-            let output_file = tempfile::NamedTempFile::new().context("create output tempfile")?;
-            run_codex_structured(
-                &prompt,
-                schema_file.path(),
-                output_file.path(),
-                Some(store.root_dir()),
-                cfg.codex_model.as_deref(),
-                cfg.codex_reasoning_effort.as_deref(),
-            )
-            .await?;
-            std::fs::read_to_string(output_file.path()).context("read codex output")?
-        }
-        AssistantProviderId::Claude => {
-            let raw = run_claude_structured(&prompt, &schema, cfg.claude_model.as_deref()).await?;
-            let v: Value = serde_json::from_str(&raw).context("parse claude result wrapper")?;
-            if let Some(so) = v.get("structured_output") {
-                serde_json::to_string(so).context("serialize structured_output")?
-            } else if let Some(result) = v.get("result").and_then(|r| r.as_str()) {
-                extract_json_object(result).context("extract json from claude result")?
-            } else {
-                anyhow::bail!("claude did not return structured_output or result");
-            }
-        }
-    };
+    let raw_json = run_structured(store, cfg, "companion", &prompt, &schema).await?;
 
-    let mut out: CompanionChatResponse =
-        serde_json::from_str(&raw_json).context("parse companion output")?;
+    let mut out: CompanionChatResponse = serde_json::from_str(&raw_json)
+        .map_err(|e| AssistantError::Corrupt(format!("parse companion output: {e}")))?;
     out.reply = out.reply.trim().to_string();
 
     // Update avatar if provided
@@ -520,23 +777,12 @@ async fn companion_chat_primitives(
         a.version = "v1".to_string();
         avatar_mod::normalize_avatar(a);
         ensure_parts_for_prompt(a, message);
-        avatar_mod::save_avatar(store, profile_id, a).context("save avatar")?;
+        *a = avatar_mod::save_avatar(store, profile_id, a, RevisionSource::Assistant)
+            .context("save avatar")?;
         out.reply = enforce_honest_reply(&out.reply, a, message);
     }
 
-    // Append to history and persist
-    history.push(CompanionTurn {
-        role: "user".to_string(),
-        content: message.trim().to_string(),
-    });
-    history.push(CompanionTurn {
-        role: "assistant".to_string(),
-        content: out.reply.clone(),
-    });
-    if history.len() > 80 {
-        history = history.split_off(history.len().saturating_sub(80));
-    }
-    save_companion_history(store, profile_id, &history).ok();
+    remember_turns(store, profile_id, history, message, &out.reply);
 
     Ok(out)
 }
@@ -937,6 +1183,7 @@ fn ensure_parts_for_prompt(avatar: &mut AvatarSpecV1, message: &str) {
     avatar.parts = parts;
 }
 
+#[allow(clippy::too_many_arguments)]
 fn make_part(
     id: &str,
     attach: &str,
@@ -1084,3 +1331,51 @@ fn summarize_parts(parts: &[owp_protocol::AvatarPartV1]) -> String {
     }
     out.join(", ")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn turn(role: &str, content: &str) -> CompanionTurn {
+        CompanionTurn {
+            role: role.to_string(),
+            content: content.to_string(),
+        }
+    }
+
+    #[test]
+    fn long_history_is_cut_to_fit_and_oversized_prompts_are_refused() {
+        let history: Vec<CompanionTurn> = (0..20)
+            .map(|i| {
+                turn(
+                    if i % 2 == 0 { "user" } else { "assistant" },
+                    &"x".repeat(400),
+                )
+            })
+            .collect();
+        let unlimited = AssistantConfig {
+            max_prompt_tokens: 0,
+            ..AssistantConfig::default()
+        };
+        let full = companion_prompt(&unlimited, "{}", &history, "hi");
+        assert_eq!(full.matches(&"x".repeat(400)).count(), PROMPT_TURNS);
+        assert!(!full.contains("left out"));
+
+        let tight = AssistantConfig {
+            max_prompt_tokens: estimate_tokens(&full) - 250,
+            ..AssistantConfig::default()
+        };
+        let cut = companion_prompt(&tight, "{}", &history, "hi");
+        assert!(estimate_tokens(&cut) <= tight.max_prompt_tokens);
+        assert!(cut.contains("(3 earlier messages left out)"), "{cut}");
+        assert!(cut.ends_with("User: hi\n"));
+        assert!(preflight(&tight, "companion", &cut).is_ok());
+
+        let tiny = AssistantConfig {
+            max_prompt_tokens: 10,
+            ..AssistantConfig::default()
+        };
+        let err = preflight(&tiny, "avatar/v1", &cut).expect_err("too large");
+        assert!(matches!(err, AssistantError::PromptTooLarge(_)), "{err}");
+    }
+}
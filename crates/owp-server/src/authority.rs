@@ -0,0 +1,92 @@
+use anyhow::{Context, Result};
+use ed25519_dalek::SigningKey;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::storage::{write_atomic, WorldStore};
+
+pub fn authority_key_path(world_dir: &Path) -> PathBuf {
+    world_dir.join("authority-key")
+}
+
+/// The world authority key (hex ed25519 seed). Created on first use for worlds that don't name
+/// an authority yet, and recorded as the manifest's `world_authority_pubkey`; the registry entry
+/// should be created with the same key so clients can pin it.
+pub fn load_or_create(store: &WorldStore, world_id: Uuid) -> Result<Option<SigningKey>> {
+    let world_dir = store.world_dir(world_id);
+    let path = authority_key_path(&world_dir);
+    let mut manifest = store.read_manifest(&world_dir)?;
+    let key = if path.exists() {
+        let hex_key = fs::read_to_string(&path).with_context(|| format!("read {path:?}"))?;
+        let bytes: [u8; 32] = hex::decode(hex_key.trim())
+            .ok()
+            .and_then(|b| b.try_into().ok())
+            .with_context(|| format!("{path:?} is not a 32-byte hex key"))?;
+        SigningKey::from_bytes(&bytes)
+    } else if manifest.world_authority_pubkey.is_some() {
+        info!("no {path:?} for the world's authority; noise sessions are off");
+        return Ok(None);
+    } else {
+        let key = SigningKey::generate(&mut rand::rngs::OsRng);
+        write_atomic(
+            &path,
+            format!("{}\n", hex::encode(key.to_bytes())).as_bytes(),
+        )?;
+        key
+    };
+
+    let pubkey = bs58::encode(key.verifying_key().as_bytes()).into_string();
+    match &manifest.world_authority_pubkey {
+        Some(named) if *named != pubkey => {
+            warn!("{path:?} is not the manifest's authority {named}; noise sessions are off");
+            return Ok(None);
+        }
+        Some(_) => {}
+        None => {
+            manifest.world_authority_pubkey = Some(pubkey);
+            store.write_manifest(&world_dir, &manifest)?;
+        }
+    }
+    Ok(Some(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_is_created_once_and_must_match_the_manifest() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let store = WorldStore::with_root(root.path().to_path_buf()).expect("store");
+        let world = store.create_world("w", 7777).expect("world");
+
+        let key = load_or_create(&store, world.world_id)
+            .expect("load")
+            .expect("created");
+        let manifest = store
+            .read_manifest(&store.world_dir(world.world_id))
+            .expect("manifest");
+        let pubkey = bs58::encode(key.verifying_key().as_bytes()).into_string();
+        assert_eq!(manifest.world_authority_pubkey, Some(pubkey));
+        let again = load_or_create(&store, world.world_id)
+            .expect("load")
+            .expect("kept");
+        assert_eq!(again.to_bytes(), key.to_bytes());
+
+        // Someone else's authority: the key can't prove it.
+        let mut manifest = manifest;
+        manifest.world_authority_pubkey = Some(bs58::encode([9u8; 32]).into_string());
+        let dir = store.world_dir(world.world_id);
+        store.write_manifest(&dir, &manifest).expect("write");
+        assert!(load_or_create(&store, world.world_id)
+            .expect("load")
+            .is_none());
+        fs::remove_file(authority_key_path(&dir)).expect("remove");
+        assert!(load_or_create(&store, world.world_id)
+            .expect("load")
+            .is_none());
+        assert!(!authority_key_path(&dir).exists());
+    }
+}
@@ -1,13 +1,18 @@
 use anyhow::{Context, Result};
-use owp_protocol::{AvatarPartV1, AvatarSpecV1};
+use base64::Engine;
+use owp_protocol::avatar::{
+    check_mesh_size, validate_avatar, MAX_MESH_PARTS, MAX_NAME_CHARS, MAX_PARTS, MAX_PART_OFFSET,
+    MAX_PART_SCALE, MAX_TAGS, MIN_PART_SCALE,
+};
+use owp_protocol::{AvatarMeshBlob, AvatarPartV1, AvatarSpecV1};
 use serde_json::Value;
 use std::path::PathBuf;
-use tempfile::NamedTempFile;
 
-use crate::assistant::{
-    run_claude_structured, run_codex_structured, AssistantConfig, AssistantProviderId,
-};
+use crate::assistant::{generate_structured, AssistantConfig, AssistantError};
+use crate::avatar_history::{self, RevisionSource};
+use crate::content;
 use crate::storage::WorldStore;
+use crate::versioned::{self, Versioned};
 
 pub const AVATAR_SCHEMA_JSON: &str = r#"{
   "$schema": "https://json-schema.org/draft/2020-12/schema",
@@ -44,38 +49,94 @@ pub const AVATAR_SCHEMA_JSON: &str = r#"{
   }
 }"#;
 
+impl Versioned for AvatarSpecV1 {
+    const VERSION: u32 = 1;
+}
+
 pub fn avatar_path(store: &WorldStore, profile_id: &str) -> PathBuf {
     store.profiles_root().join(profile_id).join("avatar.json")
 }
 
 pub fn load_avatar(store: &WorldStore, profile_id: &str) -> Result<Option<AvatarSpecV1>> {
-    let path = avatar_path(store, profile_id);
-    if !path.exists() {
-        return Ok(None);
+    Ok(versioned::load(&avatar_path(store, profile_id))?)
+}
+
+/// Validate (see `owp_protocol::avatar`) and persist; returns the sanitized spec that was saved.
+pub fn save_avatar(
+    store: &WorldStore,
+    profile_id: &str,
+    avatar: &AvatarSpecV1,
+    source: RevisionSource,
+) -> Result<AvatarSpecV1> {
+    let avatar = validate_avatar(avatar).context("avatar rejected")?;
+    versioned::save(&avatar_path(store, profile_id), &avatar)?;
+    avatar_history::record(store, profile_id, &avatar, source)?;
+    Ok(avatar)
+}
+
+/// A client-built avatar checked against the limits, with its mesh files decoded.
+pub struct AvatarSubmission {
+    pub avatar: AvatarSpecV1,
+    blobs: Vec<Vec<u8>>,
+}
+
+/// Decode the mesh files of a client submission, point `avatar.mesh` at their content hashes
+/// and validate the result. Every error is a reason to reject the submission.
+pub fn prepare_submission(
+    avatar: &AvatarSpecV1,
+    meshes: &[AvatarMeshBlob],
+) -> Result<AvatarSubmission> {
+    let mut avatar = avatar.clone();
+    if meshes.len() > MAX_MESH_PARTS + 1 {
+        anyhow::bail!("at most {} mesh files", MAX_MESH_PARTS + 1);
     }
-    let data = std::fs::read_to_string(&path).with_context(|| format!("read {path:?}"))?;
-    let avatar: AvatarSpecV1 = serde_json::from_str(&data).context("parse avatar")?;
-    Ok(Some(avatar))
+    let mut blobs = Vec::with_capacity(meshes.len());
+    for blob in meshes {
+        let Some(mesh) = avatar.mesh.as_mut() else {
+            anyhow::bail!("mesh file {:?} sent without avatar.mesh", blob.id);
+        };
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(blob.data_base64.trim())
+            .with_context(|| format!("mesh file {:?} is not valid base64", blob.id))?;
+        check_mesh_size(bytes.len())?;
+        let sha256 = content::hash(&bytes);
+        if blob.id == "body" {
+            mesh.uri = content::content_uri(&sha256);
+            mesh.sha256 = Some(sha256);
+        } else {
+            let Some(part) = mesh.parts.iter_mut().find(|p| p.id == blob.id) else {
+                anyhow::bail!("mesh file {:?} matches no avatar.mesh part", blob.id);
+            };
+            part.uri = content::content_uri(&sha256);
+            part.sha256 = Some(sha256);
+        }
+        blobs.push(bytes);
+    }
+    let avatar = validate_avatar(&avatar)?;
+    Ok(AvatarSubmission { avatar, blobs })
 }
 
-pub fn save_avatar(store: &WorldStore, profile_id: &str, avatar: &AvatarSpecV1) -> Result<()> {
-    let path = avatar_path(store, profile_id);
-    if let Some(parent) = path.parent() {
-        std::fs::create_dir_all(parent).with_context(|| format!("create {parent:?}"))?;
+impl AvatarSubmission {
+    /// Write the mesh files to the content store.
+    pub fn store_meshes(&self, store: &WorldStore) -> Result<()> {
+        for bytes in &self.blobs {
+            content::put(store, bytes)?;
+        }
+        Ok(())
     }
-    let json = serde_json::to_string_pretty(avatar).context("serialize avatar")?;
-    std::fs::write(&path, format!("{json}\n")).with_context(|| format!("write {path:?}"))?;
-    Ok(())
 }
 
+/// Generate an avatar for `user_prompt`; an identical earlier request is answered from the
+/// generation cache unless `force`.
 pub async fn generate_avatar(
     store: &WorldStore,
     cfg: &AssistantConfig,
     user_prompt: &str,
+    force: bool,
 ) -> Result<AvatarSpecV1> {
-    let Some(provider) = cfg.provider else {
+    if cfg.provider.is_none() {
         anyhow::bail!("no provider configured");
-    };
+    }
 
     let system_prompt = format!(
         "You are the OWP avatar generator.\n\
@@ -95,43 +156,18 @@ Constraints:\n\
 - parts.primitive must be one of sphere/capsule/cube/cylinder\n"
     );
 
-    let avatar_json = match provider {
-        AssistantProviderId::Codex => {
-            let schema_file = NamedTempFile::new().context("create schema tempfile")?;
-            std::fs::write(schema_file.path(), AVATAR_SCHEMA_JSON)
-                .context("write schema tempfile")?;
-
-            let output_file = NamedTempFile::new().context("create output tempfile")?;
-            run_codex_structured(
-                &system_prompt,
-                schema_file.path(),
-                output_file.path(),
-                Some(store.root_dir()),
-                cfg.codex_model.as_deref(),
-                cfg.codex_reasoning_effort.as_deref(),
-            )
-            .await?;
-            std::fs::read_to_string(output_file.path()).context("read codex output")?
-        }
-        AssistantProviderId::Claude => {
-            let raw = run_claude_structured(
-                &system_prompt,
-                AVATAR_SCHEMA_JSON,
-                cfg.claude_model.as_deref(),
-            )
-            .await?;
-            let v: Value = serde_json::from_str(&raw).context("parse claude result wrapper")?;
-            if let Some(so) = v.get("structured_output") {
-                serde_json::to_string(so).context("serialize structured_output")?
-            } else if let Some(result) = v.get("result").and_then(|r| r.as_str()) {
-                extract_json_object(result).context("extract json from claude result")?
-            } else {
-                anyhow::bail!("claude did not return structured_output or result");
-            }
-        }
-    };
+    let avatar_json = generate_structured(
+        store,
+        cfg,
+        "avatar/v1",
+        &system_prompt,
+        AVATAR_SCHEMA_JSON,
+        force,
+    )
+    .await?;
 
-    let avatar_value: Value = serde_json::from_str(&avatar_json).context("parse avatar json")?;
+    let avatar_value: Value = serde_json::from_str(&avatar_json)
+        .map_err(|e| AssistantError::Corrupt(format!("parse avatar json: {e}")))?;
     let mut avatar = value_to_avatar(&avatar_value).context("normalize avatar json")?;
     avatar.version = "v1".to_string();
     normalize_avatar(&mut avatar);
@@ -223,17 +259,17 @@ fn value_to_part(v: &Value) -> Option<AvatarPartV1> {
         attach,
         primitive,
         position: [
-            position.get(0)?.as_f64()? as f32,
+            position.first()?.as_f64()? as f32,
             position.get(1)?.as_f64()? as f32,
             position.get(2)?.as_f64()? as f32,
         ],
         rotation: [
-            rotation.get(0)?.as_f64()? as f32,
+            rotation.first()?.as_f64()? as f32,
             rotation.get(1)?.as_f64()? as f32,
             rotation.get(2)?.as_f64()? as f32,
         ],
         scale: [
-            scale.get(0)?.as_f64()? as f32,
+            scale.first()?.as_f64()? as f32,
             scale.get(1)?.as_f64()? as f32,
             scale.get(2)?.as_f64()? as f32,
         ],
@@ -249,52 +285,18 @@ fn value_to_part(v: &Value) -> Option<AvatarPartV1> {
     })
 }
 
-fn extract_json_object(text: &str) -> Result<String> {
-    let start = text
-        .find('{')
-        .ok_or_else(|| anyhow::anyhow!("no '{{' found in text"))?;
-
-    let mut depth = 0usize;
-    let mut in_string = false;
-    let mut escape = false;
-
-    for (i, ch) in text[start..].char_indices() {
-        let c = ch;
-        if in_string {
-            if escape {
-                escape = false;
-                continue;
-            }
-            match c {
-                '\\' => escape = true,
-                '"' => in_string = false,
-                _ => {}
-            }
-            continue;
-        }
-
-        match c {
-            '"' => in_string = true,
-            '{' => depth += 1,
-            '}' => {
-                depth = depth.saturating_sub(1);
-                if depth == 0 {
-                    let end = start + i + 1;
-                    return Ok(text[start..end].to_string());
-                }
-            }
-            _ => {}
-        }
-    }
-
-    anyhow::bail!("unterminated json object");
+fn is_hex_color(s: &str) -> bool {
+    s.trim()
+        .strip_prefix('#')
+        .is_some_and(|h| h.len() == 6 && h.chars().all(|c| c.is_ascii_hexdigit()))
 }
 
+/// Best-effort repair of model output so it passes `validate_avatar`.
 pub(crate) fn normalize_avatar(a: &mut AvatarSpecV1) {
-    if a.primary_color.is_empty() {
+    if !is_hex_color(&a.primary_color) {
         a.primary_color = "#00D1FF".to_string();
     }
-    if a.secondary_color.is_empty() {
+    if !is_hex_color(&a.secondary_color) {
         a.secondary_color = "#FFFFFF".to_string();
     }
     if !(0.5..=2.0).contains(&a.height) {
@@ -303,9 +305,13 @@ pub(crate) fn normalize_avatar(a: &mut AvatarSpecV1) {
     if a.name.trim().is_empty() {
         a.name = "Traveler".to_string();
     }
+    if a.name.trim().chars().count() > MAX_NAME_CHARS {
+        a.name = a.name.trim().chars().take(MAX_NAME_CHARS).collect();
+    }
+    a.tags.truncate(MAX_TAGS);
 
-    if a.parts.len() > 48 {
-        a.parts.truncate(48);
+    if a.parts.len() > MAX_PARTS {
+        a.parts.truncate(MAX_PARTS);
     }
     for p in &mut a.parts {
         if p.id.trim().is_empty() {
@@ -330,7 +336,13 @@ pub(crate) fn normalize_avatar(a: &mut AvatarSpecV1) {
             if *s == 0.0 {
                 *s = 0.1;
             }
-            *s = s.clamp(0.01, 10.0);
+            *s = s.clamp(MIN_PART_SCALE, MAX_PART_SCALE);
+        }
+        for x in p.position.iter_mut() {
+            *x = x.clamp(-MAX_PART_OFFSET, MAX_PART_OFFSET);
+        }
+        if !is_hex_color(&p.color) {
+            p.color = a.secondary_color.clone();
         }
         if let Some(strength) = p.emission_strength {
             if !strength.is_finite() || strength <= 0.0 {
@@ -340,9 +352,71 @@ pub(crate) fn normalize_avatar(a: &mut AvatarSpecV1) {
             }
         }
         if let Some(ref c) = p.emission_color {
-            if c.trim().is_empty() {
+            if !is_hex_color(c) {
                 p.emission_color = None;
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use owp_protocol::{AvatarMeshPartV1, AvatarMeshV1};
+
+    fn blob(id: &str, bytes: &[u8]) -> AvatarMeshBlob {
+        AvatarMeshBlob {
+            id: id.to_string(),
+            data_base64: base64::engine::general_purpose::STANDARD.encode(bytes),
+        }
+    }
+
+    #[test]
+    fn submission_points_meshes_at_content_store() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let store = WorldStore::with_root(root.path().to_path_buf()).expect("store");
+        let avatar = AvatarSpecV1 {
+            version: "v1".to_string(),
+            name: " Pilot ".to_string(),
+            primary_color: "#00d1ff".to_string(),
+            secondary_color: "#ffffff".to_string(),
+            height: 1.0,
+            tags: vec![],
+            parts: vec![],
+            mesh: Some(AvatarMeshV1 {
+                format: "glb".to_string(),
+                uri: String::new(),
+                sha256: None,
+                parts: vec![AvatarMeshPartV1 {
+                    id: "hat".to_string(),
+                    uri: String::new(),
+                    sha256: None,
+                    material: Some("secondary".to_string()),
+                }],
+            }),
+        };
+
+        let sub = prepare_submission(&avatar, &[blob("body", b"glTF body"), blob("hat", b"hat")])
+            .expect("accepted");
+        sub.store_meshes(&store).expect("store");
+        let saved =
+            save_avatar(&store, "local", &sub.avatar, RevisionSource::Upload).expect("save");
+        assert_eq!(saved.name, "Pilot");
+        let mesh = saved.mesh.expect("mesh");
+        let body = mesh.sha256.expect("hash");
+        assert_eq!(mesh.uri, content::content_uri(&body));
+        assert_eq!(
+            content::get(&store, &body).expect("get").as_deref(),
+            Some(&b"glTF body"[..])
+        );
+        assert!(mesh.parts[0].sha256.is_some());
+
+        assert!(prepare_submission(&avatar, &[blob("wing", b"x")]).is_err());
+        assert!(
+            prepare_submission(&avatar, &[]).is_err(),
+            "mesh without uri"
+        );
+        let huge = vec![0u8; owp_protocol::avatar::MAX_MESH_BYTES + 1];
+        assert!(prepare_submission(&avatar, &[blob("body", &huge)]).is_err());
+    }
+}
@@ -0,0 +1,457 @@
+use anyhow::{Context, Result};
+use owp_protocol::AvatarSpecV1;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use time::OffsetDateTime;
+use tracing::warn;
+
+use crate::avatar as avatar_mod;
+use crate::diff::{self, FieldChange, ListDiff};
+use crate::storage::{write_atomic, WorldStore};
+use crate::wardrobe;
+
+/// Serializes read-modify-write of history files within this process.
+static HISTORY_LOCK: Mutex<()> = Mutex::new(());
+
+/// Revisions kept per profile; older ones are dropped.
+pub const MAX_REVISIONS: usize = 50;
+
+/// Assistant changes that can be undone per profile.
+pub const MAX_UNDO: usize = 20;
+
+/// What saved a revision.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RevisionSource {
+    /// `PUT /avatar` or a client upload.
+    Upload,
+    /// `POST /avatar/generate`.
+    Generate,
+    /// The companion changed the avatar during a chat.
+    Assistant,
+    /// `POST /avatar/mesh/generate`.
+    Mesh,
+    /// `POST /avatar/mesh/import`.
+    Import,
+    /// Switched to a wardrobe outfit.
+    Wardrobe,
+    /// `POST /assistant/undo`.
+    Undo,
+    /// `POST /assistant/redo`.
+    Redo,
+}
+
+impl RevisionSource {
+    /// Saves the assistant made, which `POST /assistant/undo` can revert.
+    pub fn is_assistant(self) -> bool {
+        matches!(self, Self::Assistant | Self::Generate | Self::Mesh)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AvatarRevisionV1 {
+    /// Increases by one per save and is never reused, even after old revisions are dropped.
+    pub revision: u64,
+    #[serde(with = "time::serde::rfc3339")]
+    pub saved_at: OffsetDateTime,
+    pub source: RevisionSource,
+    pub avatar: AvatarSpecV1,
+}
+
+/// `AvatarRevisionV1` without the spec, for listings.
+#[derive(Debug, Clone, Serialize)]
+pub struct RevisionSummary {
+    pub revision: u64,
+    #[serde(with = "time::serde::rfc3339")]
+    pub saved_at: OffsetDateTime,
+    pub source: RevisionSource,
+    pub name: String,
+}
+
+/// An assistant change: the revision it replaced and the one it saved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct UndoStep {
+    before: u64,
+    after: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct AvatarHistoryV1 {
+    #[serde(default)]
+    revisions: Vec<AvatarRevisionV1>,
+    /// Newest last.
+    #[serde(default)]
+    undo: Vec<UndoStep>,
+    #[serde(default)]
+    redo: Vec<UndoStep>,
+}
+
+pub fn history_path(store: &WorldStore, profile_id: &str) -> PathBuf {
+    store
+        .profiles_root()
+        .join(profile_id)
+        .join("avatar_history.json")
+}
+
+fn load(store: &WorldStore, profile_id: &str) -> Result<AvatarHistoryV1> {
+    let path = history_path(store, profile_id);
+    if !path.exists() {
+        return Ok(AvatarHistoryV1::default());
+    }
+    let data = std::fs::read_to_string(&path).with_context(|| format!("read {path:?}"))?;
+    serde_json::from_str(&data).with_context(|| format!("parse {path:?}"))
+}
+
+fn save(store: &WorldStore, profile_id: &str, h: &AvatarHistoryV1) -> Result<()> {
+    let path = history_path(store, profile_id);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).with_context(|| format!("create {parent:?}"))?;
+    }
+    let json = serde_json::to_string_pretty(h).context("serialize avatar history")?;
+    write_atomic(&path, format!("{json}\n").as_bytes())?;
+    Ok(())
+}
+
+/// Append a saved avatar; returns its revision number. Generated meshes are copied into the
+/// content store first, so a revision still has its meshes after the next generation replaces
+/// the profile's files.
+///
+/// An assistant save can be undone; any other save except an undo or redo clears both stacks,
+/// so undo never throws away an edit made by hand.
+pub fn record(
+    store: &WorldStore,
+    profile_id: &str,
+    avatar: &AvatarSpecV1,
+    source: RevisionSource,
+) -> Result<u64> {
+    let mut avatar = avatar.clone();
+    if let Err(e) = wardrobe::pin_meshes(store, profile_id, &mut avatar) {
+        warn!("avatar revision of {profile_id} keeps unpinned meshes: {e:#}");
+    }
+    let _guard = HISTORY_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut h = load(store, profile_id)?;
+    let previous = h.revisions.last().map(|r| r.revision);
+    let revision = previous.map_or(1, |r| r + 1);
+    h.revisions.push(AvatarRevisionV1 {
+        revision,
+        saved_at: OffsetDateTime::now_utc(),
+        source,
+        avatar,
+    });
+    let excess = h.revisions.len().saturating_sub(MAX_REVISIONS);
+    h.revisions.drain(..excess);
+
+    match source {
+        RevisionSource::Undo | RevisionSource::Redo => {}
+        s if s.is_assistant() => {
+            h.redo.clear();
+            if let Some(before) = previous {
+                h.undo.push(UndoStep {
+                    before,
+                    after: revision,
+                });
+            }
+        }
+        _ => {
+            h.undo.clear();
+            h.redo.clear();
+        }
+    }
+    let oldest = h.revisions.first().map_or(0, |r| r.revision);
+    h.undo.retain(|s| s.before >= oldest);
+    h.redo.retain(|s| s.before >= oldest);
+    let excess = h.undo.len().saturating_sub(MAX_UNDO);
+    h.undo.drain(..excess);
+
+    save(store, profile_id, &h)?;
+    Ok(revision)
+}
+
+/// Put back the avatar from before the latest assistant change; `None` if there is nothing to
+/// undo.
+pub fn undo(store: &WorldStore, profile_id: &str) -> Result<Option<AvatarSpecV1>> {
+    step(store, profile_id, RevisionSource::Undo)
+}
+
+/// Re-apply the change the latest undo reverted; `None` if there is nothing to redo.
+pub fn redo(store: &WorldStore, profile_id: &str) -> Result<Option<AvatarSpecV1>> {
+    step(store, profile_id, RevisionSource::Redo)
+}
+
+fn step(
+    store: &WorldStore,
+    profile_id: &str,
+    direction: RevisionSource,
+) -> Result<Option<AvatarSpecV1>> {
+    let target = {
+        let _guard = HISTORY_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut h = load(store, profile_id)?;
+        let (from, to) = match direction {
+            RevisionSource::Undo => (&mut h.undo, &mut h.redo),
+            _ => (&mut h.redo, &mut h.undo),
+        };
+        let Some(s) = from.pop() else {
+            return Ok(None);
+        };
+        to.push(s);
+        let wanted = if direction == RevisionSource::Undo {
+            s.before
+        } else {
+            s.after
+        };
+        let target = h
+            .revisions
+            .iter()
+            .find(|r| r.revision == wanted)
+            .map(|r| r.avatar.clone())
+            .with_context(|| format!("revision {wanted} is no longer in the history"))?;
+        save(store, profile_id, &h)?;
+        target
+    };
+    avatar_mod::save_avatar(store, profile_id, &target, direction).map(Some)
+}
+
+/// Oldest first.
+pub fn list(store: &WorldStore, profile_id: &str) -> Result<Vec<RevisionSummary>> {
+    Ok(load(store, profile_id)?
+        .revisions
+        .into_iter()
+        .map(|r| RevisionSummary {
+            revision: r.revision,
+            saved_at: r.saved_at,
+            source: r.source,
+            name: r.avatar.name,
+        })
+        .collect())
+}
+
+pub fn get(
+    store: &WorldStore,
+    profile_id: &str,
+    revision: u64,
+) -> Result<Option<AvatarRevisionV1>> {
+    Ok(load(store, profile_id)?
+        .revisions
+        .into_iter()
+        .find(|r| r.revision == revision))
+}
+
+/// The newest revision, if any.
+pub fn latest(store: &WorldStore, profile_id: &str) -> Result<Option<AvatarRevisionV1>> {
+    Ok(load(store, profile_id)?.revisions.pop())
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AvatarDiff {
+    pub from: u64,
+    pub to: u64,
+    /// Top-level fields: name, colors, height, tags.
+    pub fields: Vec<FieldChange>,
+    /// Primitive parts, matched by `id`.
+    pub parts: ListDiff,
+    /// `mesh.*` fields; mesh parts are listed whole.
+    pub mesh: Vec<FieldChange>,
+    /// One sentence per change, for showing as is.
+    pub summary: Vec<String>,
+}
+
+/// What changed from `from` to `to`.
+pub fn diff(from: &AvatarRevisionV1, to: &AvatarRevisionV1) -> Result<AvatarDiff> {
+    let a = serde_json::to_value(&from.avatar).context("serialize avatar")?;
+    let b = serde_json::to_value(&to.avatar).context("serialize avatar")?;
+    let pick = |v: &Value, key: &str| v.get(key).cloned().unwrap_or(Value::Null);
+    let list = |v: &Value| pick(v, "parts").as_array().cloned().unwrap_or_default();
+
+    let mut fields = Vec::new();
+    for key in ["name", "primary_color", "secondary_color", "height", "tags"] {
+        fields.extend(
+            diff::diff_fields(&pick(&a, key), &pick(&b, key))
+                .into_iter()
+                .map(|c| FieldChange {
+                    field: key.to_string(),
+                    ..c
+                }),
+        );
+    }
+    let parts = diff::diff_by_id(&list(&a), &list(&b));
+    let mut mesh = diff::diff_fields(&pick(&a, "mesh"), &pick(&b, "mesh"));
+    for c in &mut mesh {
+        c.field = if c.field.is_empty() {
+            "mesh".to_string()
+        } else {
+            format!("mesh.{}", c.field)
+        };
+    }
+
+    let mut summary = Vec::new();
+    for c in &fields {
+        summary.push(format!(
+            "{} {} -> {}",
+            c.field.replace('_', " "),
+            diff::show(&c.from),
+            diff::show(&c.to)
+        ));
+    }
+    let describe = |p: &Value| {
+        format!(
+            "{} ({} on {})",
+            diff::show(&pick(p, "id")),
+            diff::show(&pick(p, "primitive")),
+            diff::show(&pick(p, "attach"))
+        )
+    };
+    for p in &parts.added {
+        summary.push(format!("added part {}", describe(p)));
+    }
+    for p in &parts.removed {
+        summary.push(format!("removed part {}", describe(p)));
+    }
+    for c in &parts.changed {
+        let what: Vec<String> = c
+            .fields
+            .iter()
+            .map(|f| {
+                format!(
+                    "{} {} -> {}",
+                    f.field.replace('_', " "),
+                    diff::show(&f.from),
+                    diff::show(&f.to)
+                )
+            })
+            .collect();
+        summary.push(format!("changed part {}: {}", c.id, what.join(", ")));
+    }
+    match (&from.avatar.mesh, &to.avatar.mesh) {
+        (None, Some(_)) => summary.push("added a generated mesh".to_string()),
+        (Some(_), None) => summary.push("removed the generated mesh".to_string()),
+        _ if !mesh.is_empty() => summary.push("regenerated the mesh".to_string()),
+        _ => {}
+    }
+
+    Ok(AvatarDiff {
+        from: from.revision,
+        to: to.revision,
+        fields,
+        parts,
+        mesh,
+        summary,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use owp_protocol::AvatarPartV1;
+
+    #[test]
+    fn revisions_are_bounded_and_diffed() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let store = WorldStore::with_root(root.path().to_path_buf()).expect("store");
+        let part = |id: &str, color: &str| AvatarPartV1 {
+            id: id.to_string(),
+            attach: "head".to_string(),
+            primitive: "cube".to_string(),
+            position: [0.0, 0.1, 0.0],
+            rotation: [0.0; 3],
+            scale: [0.2; 3],
+            color: color.to_string(),
+            emission_color: None,
+            emission_strength: None,
+        };
+        let mut avatar = AvatarSpecV1 {
+            version: "v1".to_string(),
+            name: "Wizard".to_string(),
+            primary_color: "#112233".to_string(),
+            secondary_color: "#445566".to_string(),
+            height: 1.0,
+            tags: vec![],
+            parts: vec![part("hat", "#000000"), part("beard", "#FFFFFF")],
+            mesh: None,
+        };
+        record(&store, "local", &avatar, RevisionSource::Upload).expect("record");
+        avatar.height = 1.25;
+        avatar.parts = vec![part("hat", "#FF0000"), part("staff", "#884400")];
+        let rev = record(&store, "local", &avatar, RevisionSource::Assistant).expect("record");
+        assert_eq!(rev, 2);
+
+        let d = diff(
+            &get(&store, "local", 1).expect("get").expect("rev 1"),
+            &latest(&store, "local").expect("latest").expect("rev 2"),
+        )
+        .expect("diff");
+        assert_eq!(d.fields.len(), 1);
+        assert_eq!(d.parts.added.len(), 1);
+        assert_eq!(d.parts.removed.len(), 1);
+        assert_eq!(
+            d.summary,
+            [
+                "height 1 -> 1.25",
+                "added part staff (cube on head)",
+                "removed part beard (cube on head)",
+                "changed part hat: color #000000 -> #FF0000",
+            ]
+        );
+
+        for _ in 0..MAX_REVISIONS {
+            record(&store, "local", &avatar, RevisionSource::Generate).expect("record");
+        }
+        let all = list(&store, "local").expect("list");
+        assert_eq!(all.len(), MAX_REVISIONS);
+        assert_eq!(
+            all.last().map(|r| r.revision),
+            Some(MAX_REVISIONS as u64 + 2)
+        );
+    }
+
+    #[test]
+    fn assistant_changes_undo_and_redo() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let store = WorldStore::with_root(root.path().to_path_buf()).expect("store");
+        let mut avatar = AvatarSpecV1 {
+            version: "v1".to_string(),
+            name: "Mine".to_string(),
+            primary_color: "#112233".to_string(),
+            secondary_color: "#445566".to_string(),
+            height: 1.0,
+            tags: vec![],
+            parts: vec![],
+            mesh: None,
+        };
+        let save = |a: &AvatarSpecV1, source| {
+            avatar_mod::save_avatar(&store, "local", a, source).expect("save")
+        };
+        save(&avatar, RevisionSource::Upload);
+        assert!(undo(&store, "local").expect("undo").is_none());
+
+        avatar.name = "Robot".to_string();
+        save(&avatar, RevisionSource::Generate);
+        avatar.height = 1.5;
+        save(&avatar, RevisionSource::Assistant);
+
+        let name_and_height = |a: Option<AvatarSpecV1>| a.map(|a| (a.name, a.height));
+        assert_eq!(
+            name_and_height(undo(&store, "local").expect("undo")),
+            Some(("Robot".to_string(), 1.0))
+        );
+        assert_eq!(
+            name_and_height(undo(&store, "local").expect("undo")),
+            Some(("Mine".to_string(), 1.0))
+        );
+        assert!(undo(&store, "local").expect("undo").is_none());
+        assert_eq!(
+            name_and_height(redo(&store, "local").expect("redo")),
+            Some(("Robot".to_string(), 1.0))
+        );
+        assert_eq!(
+            name_and_height(avatar_mod::load_avatar(&store, "local").expect("load")),
+            Some(("Robot".to_string(), 1.0))
+        );
+
+        // A hand edit ends both stacks.
+        save(&avatar, RevisionSource::Upload);
+        assert!(redo(&store, "local").expect("redo").is_none());
+        assert!(undo(&store, "local").expect("undo").is_none());
+    }
+}
@@ -0,0 +1,345 @@
+use anyhow::{Context, Result};
+use base64::Engine;
+use owp_protocol::avatar::{check_mesh_size, MAX_HEIGHT, MAX_MESH_TRIANGLES, MIN_HEIGHT};
+use owp_protocol::{AvatarMeshV1, AvatarSpecV1};
+use sha2::{Digest, Sha256};
+use std::io::Cursor;
+
+use crate::avatar as avatar_mod;
+use crate::avatar_history::RevisionSource;
+use crate::avatar_mesh::{avatar_mesh_dir, avatar_mesh_parts_dir, avatar_mesh_stl_path};
+use crate::storage::{write_atomic, WorldStore};
+
+/// Ceiling for an uploaded model before conversion; text formats are much larger than the STL.
+pub const MAX_IMPORT_BYTES: usize = 32 * 1024 * 1024;
+
+type Triangle = [[f32; 3]; 3];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    Obj,
+    Gltf,
+    Glb,
+}
+
+impl ImportFormat {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "obj" => Some(Self::Obj),
+            "gltf" => Some(Self::Gltf),
+            "glb" => Some(Self::Glb),
+            _ => None,
+        }
+    }
+
+    /// Guess from the contents: GLB has a magic number, glTF is JSON, anything else is OBJ.
+    pub fn sniff(bytes: &[u8]) -> Self {
+        if bytes.starts_with(b"glTF") {
+            Self::Glb
+        } else if bytes.trim_ascii_start().starts_with(b"{") {
+            Self::Gltf
+        } else {
+            Self::Obj
+        }
+    }
+}
+
+/// An uploaded model converted to the serving format (binary STL, Z-up, feet at z=0).
+pub struct ConvertedMesh {
+    pub stl: Vec<u8>,
+    pub triangles: usize,
+    /// Model height in meters.
+    pub height: f32,
+}
+
+fn check_triangles(n: usize) -> Result<()> {
+    if n > MAX_MESH_TRIANGLES {
+        anyhow::bail!("mesh has {n} triangles; the limit is {MAX_MESH_TRIANGLES}");
+    }
+    Ok(())
+}
+
+fn obj_triangles(bytes: &[u8]) -> Result<Vec<Triangle>> {
+    let opts = tobj::LoadOptions {
+        triangulate: true,
+        single_index: true,
+        ..Default::default()
+    };
+    // Materials live in separate .mtl files, which an upload can't reference.
+    let (models, _) = tobj::load_obj_buf(&mut Cursor::new(bytes), &opts, |_| {
+        Err(tobj::LoadError::OpenFileFailed)
+    })
+    .context("parse obj")?;
+    let mut out = Vec::new();
+    for m in &models {
+        let pos = &m.mesh.positions;
+        let vertex = |i: u32| -> Result<[f32; 3]> {
+            let i = i as usize * 3;
+            pos.get(i..i + 3)
+                .map(|p| [p[0], p[1], p[2]])
+                .context("obj face references a missing vertex")
+        };
+        check_triangles(out.len() + m.mesh.indices.len() / 3)?;
+        for f in m.mesh.indices.chunks_exact(3) {
+            out.push([vertex(f[0])?, vertex(f[1])?, vertex(f[2])?]);
+        }
+    }
+    Ok(out)
+}
+
+fn mat_mul(a: &[[f32; 4]; 4], b: &[[f32; 4]; 4]) -> [[f32; 4]; 4] {
+    // Column-major, as glTF stores them.
+    let mut out = [[0.0; 4]; 4];
+    for (c, col) in out.iter_mut().enumerate() {
+        for (r, v) in col.iter_mut().enumerate() {
+            *v = (0..4).map(|k| a[k][r] * b[c][k]).sum();
+        }
+    }
+    out
+}
+
+fn transform(m: &[[f32; 4]; 4], p: [f32; 3]) -> [f32; 3] {
+    let mut out = [0.0; 3];
+    for (r, v) in out.iter_mut().enumerate() {
+        *v = m[0][r] * p[0] + m[1][r] * p[1] + m[2][r] * p[2] + m[3][r];
+    }
+    out
+}
+
+fn gltf_buffers(gltf: &gltf::Gltf) -> Result<Vec<Vec<u8>>> {
+    let mut out = Vec::new();
+    for buffer in gltf.buffers() {
+        let data = match buffer.source() {
+            gltf::buffer::Source::Bin => gltf.blob.clone().context("glb has no binary chunk")?,
+            gltf::buffer::Source::Uri(uri) => {
+                // External files can't come along with a single upload; only embedded data.
+                let (_, b64) = uri
+                    .strip_prefix("data:")
+                    .and_then(|rest| rest.split_once(";base64,"))
+                    .context("gltf buffers must be embedded (data: URIs or glb)")?;
+                base64::engine::general_purpose::STANDARD
+                    .decode(b64)
+                    .context("decode gltf buffer")?
+            }
+        };
+        if data.len() < buffer.length() {
+            anyhow::bail!("gltf buffer {} is truncated", buffer.index());
+        }
+        out.push(data);
+    }
+    Ok(out)
+}
+
+fn gltf_node(
+    node: gltf::Node,
+    parent: &[[f32; 4]; 4],
+    buffers: &[Vec<u8>],
+    out: &mut Vec<Triangle>,
+) -> Result<()> {
+    let world = mat_mul(parent, &node.transform().matrix());
+    if let Some(mesh) = node.mesh() {
+        for prim in mesh.primitives() {
+            if prim.mode() != gltf::mesh::Mode::Triangles {
+                anyhow::bail!("only triangle meshes are supported");
+            }
+            let reader = prim.reader(|b| buffers.get(b.index()).map(Vec::as_slice));
+            let positions: Vec<[f32; 3]> = reader
+                .read_positions()
+                .context("primitive without positions")?
+                .map(|p| transform(&world, p))
+                .collect();
+            let indices: Vec<u32> = match reader.read_indices() {
+                Some(i) => i.into_u32().collect(),
+                None => (0..positions.len() as u32).collect(),
+            };
+            check_triangles(out.len() + indices.len() / 3)?;
+            for f in indices.chunks_exact(3) {
+                let v = |i: u32| {
+                    positions
+                        .get(i as usize)
+                        .copied()
+                        .context("gltf index out of range")
+                };
+                out.push([v(f[0])?, v(f[1])?, v(f[2])?]);
+            }
+        }
+    }
+    for child in node.children() {
+        gltf_node(child, &world, buffers, out)?;
+    }
+    Ok(())
+}
+
+fn gltf_triangles(bytes: &[u8]) -> Result<Vec<Triangle>> {
+    let gltf = gltf::Gltf::from_slice(bytes).context("parse gltf")?;
+    let buffers = gltf_buffers(&gltf)?;
+    let scene = gltf
+        .default_scene()
+        .or_else(|| gltf.scenes().next())
+        .context("gltf has no scene")?;
+    let identity = [
+        [1.0, 0.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ];
+    let mut out = Vec::new();
+    for node in scene.nodes() {
+        gltf_node(node, &identity, &buffers, &mut out)?;
+    }
+    Ok(out)
+}
+
+fn binary_stl(tris: &[Triangle]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(84 + tris.len() * 50);
+    let mut header = [0u8; 80];
+    let title = b"owp avatar import";
+    header[..title.len()].copy_from_slice(title);
+    out.extend_from_slice(&header);
+    out.extend_from_slice(&(tris.len() as u32).to_le_bytes());
+    for t in tris {
+        let u = [t[1][0] - t[0][0], t[1][1] - t[0][1], t[1][2] - t[0][2]];
+        let v = [t[2][0] - t[0][0], t[2][1] - t[0][1], t[2][2] - t[0][2]];
+        let n = [
+            u[1] * v[2] - u[2] * v[1],
+            u[2] * v[0] - u[0] * v[2],
+            u[0] * v[1] - u[1] * v[0],
+        ];
+        let len = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+        let n = if len > 0.0 {
+            n.map(|x| x / len)
+        } else {
+            [0.0; 3]
+        };
+        for x in n.iter().chain(t.iter().flatten()) {
+            out.extend_from_slice(&x.to_le_bytes());
+        }
+        out.extend_from_slice(&0u16.to_le_bytes());
+    }
+    out
+}
+
+/// Parse an upload, enforce the limits and convert it to STL. glTF and OBJ are Y-up, the
+/// serving format is Z-up like the OpenSCAD pipeline, so axes are swapped and the model is
+/// centered with its feet at z=0. Every error is a reason to reject the upload.
+pub fn convert(bytes: &[u8], format: ImportFormat) -> Result<ConvertedMesh> {
+    if bytes.len() > MAX_IMPORT_BYTES {
+        anyhow::bail!(
+            "upload is {} bytes; the limit is {MAX_IMPORT_BYTES}",
+            bytes.len()
+        );
+    }
+    let mut tris = match format {
+        ImportFormat::Obj => obj_triangles(bytes)?,
+        ImportFormat::Gltf | ImportFormat::Glb => gltf_triangles(bytes)?,
+    };
+    if tris.is_empty() {
+        anyhow::bail!("model has no triangles");
+    }
+    if tris.iter().flatten().flatten().any(|x| !x.is_finite()) {
+        anyhow::bail!("model has non-finite vertex positions");
+    }
+
+    let mut min = [f32::MAX; 3];
+    let mut max = [f32::MIN; 3];
+    for t in tris.iter_mut() {
+        // Y-up -> Z-up; swapping two axes mirrors the model, so flip the winding back.
+        t.swap(1, 2);
+        for p in t.iter_mut() {
+            *p = [p[0], p[2], p[1]];
+            for k in 0..3 {
+                min[k] = min[k].min(p[k]);
+                max[k] = max[k].max(p[k]);
+            }
+        }
+    }
+    let offset = [(min[0] + max[0]) / 2.0, (min[1] + max[1]) / 2.0, min[2]];
+    for p in tris.iter_mut().flatten() {
+        for k in 0..3 {
+            p[k] -= offset[k];
+        }
+    }
+
+    let stl = binary_stl(&tris);
+    check_mesh_size(stl.len())?;
+    Ok(ConvertedMesh {
+        stl,
+        triangles: tris.len(),
+        height: max[2] - min[2],
+    })
+}
+
+/// Install a converted mesh as the profile's avatar mesh, replacing any generated one.
+pub fn install(store: &WorldStore, profile_id: &str, mesh: &ConvertedMesh) -> Result<AvatarSpecV1> {
+    let dir = avatar_mesh_dir(store, profile_id);
+    std::fs::create_dir_all(&dir).with_context(|| format!("create {dir:?}"))?;
+    // Parts from an earlier OpenSCAD render don't belong to this model.
+    let parts_dir = avatar_mesh_parts_dir(store, profile_id);
+    if parts_dir.exists() {
+        std::fs::remove_dir_all(&parts_dir).with_context(|| format!("remove {parts_dir:?}"))?;
+    }
+    write_atomic(&avatar_mesh_stl_path(store, profile_id), &mesh.stl)?;
+    let hash = hex::encode(Sha256::digest(&mesh.stl));
+
+    let mut avatar = avatar_mod::load_avatar(store, profile_id)
+        .context("load avatar")?
+        .unwrap_or(AvatarSpecV1 {
+            version: "v1".to_string(),
+            name: "Traveler".to_string(),
+            primary_color: "#00D1FF".to_string(),
+            secondary_color: "#FFFFFF".to_string(),
+            height: 1.0,
+            tags: Vec::new(),
+            parts: Vec::new(),
+            mesh: None,
+        });
+    avatar.height = mesh.height.clamp(MIN_HEIGHT, MAX_HEIGHT);
+    if !avatar.tags.iter().any(|t| t == "mesh") {
+        avatar.tags.insert(0, "mesh".to_string());
+    }
+    // Mesh supersedes primitive parts.
+    avatar.parts.clear();
+    avatar.mesh = Some(AvatarMeshV1 {
+        format: "stl".to_string(),
+        uri: format!("/avatar/mesh?profile_id={profile_id}"),
+        sha256: Some(hash),
+        parts: Vec::new(),
+    });
+    avatar_mod::save_avatar(store, profile_id, &avatar, RevisionSource::Import)
+        .context("save avatar")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CUBE_OBJ: &str = "\
+v 0 0 0\nv 1 0 0\nv 1 2 0\nv 0 2 0\nv 0 0 1\nv 1 0 1\nv 1 2 1\nv 0 2 1\n\
+f 1 2 3 4\nf 5 6 7 8\nf 1 2 6 5\nf 2 3 7 6\nf 3 4 8 7\nf 4 1 5 8\n";
+
+    #[test]
+    fn obj_converts_to_z_up_stl() {
+        assert_eq!(ImportFormat::sniff(CUBE_OBJ.as_bytes()), ImportFormat::Obj);
+        assert_eq!(ImportFormat::sniff(b"glTF\x02\0\0\0"), ImportFormat::Glb);
+        assert_eq!(ImportFormat::sniff(b" {\"asset\":{}}"), ImportFormat::Gltf);
+
+        let mesh = convert(CUBE_OBJ.as_bytes(), ImportFormat::Obj).expect("convert");
+        assert_eq!(mesh.triangles, 12);
+        assert_eq!(mesh.stl.len(), 84 + 12 * 50);
+        // The 2m Y extent becomes the height.
+        assert!((mesh.height - 2.0).abs() < 1e-6);
+
+        let root = tempfile::tempdir().expect("tempdir");
+        let store = WorldStore::with_root(root.path().to_path_buf()).expect("store");
+        let avatar = install(&store, "local", &mesh).expect("install");
+        assert_eq!(avatar.height, 2.0);
+        assert_eq!(avatar.mesh.expect("mesh").format, "stl");
+        assert_eq!(
+            std::fs::read(avatar_mesh_stl_path(&store, "local")).expect("stl"),
+            mesh.stl
+        );
+
+        assert!(convert(b"v 0 0 0\n", ImportFormat::Obj).is_err());
+        assert!(convert(b"{}", ImportFormat::Gltf).is_err());
+    }
+}
@@ -1,19 +1,18 @@
 use anyhow::{Context, Result};
+use owp_protocol::avatar::check_mesh_size;
 use owp_protocol::{AvatarMeshPartV1, AvatarMeshV1, AvatarSpecV1};
 use serde::Deserialize;
-use serde_json::Value;
 use sha2::{Digest, Sha256};
 use std::path::PathBuf;
-use std::time::Duration;
-use tempfile::NamedTempFile;
+use std::time::{Duration, Instant};
 use tokio::process::Command;
 use tokio::time::timeout;
 
-use crate::assistant::{
-    run_claude_structured, run_codex_structured, AssistantConfig, AssistantProviderId,
-};
+use crate::assistant::{generate_structured, AssistantConfig, AssistantError};
 use crate::avatar as avatar_mod;
+use crate::avatar_history::RevisionSource;
 use crate::storage::WorldStore;
+use crate::telemetry;
 
 const AVATAR_SCAD_SCHEMA_JSON: &str = r#"{
   "$schema": "https://json-schema.org/draft/2020-12/schema",
@@ -106,15 +105,18 @@ async fn program_exists(program: &str) -> bool {
         .is_some()
 }
 
+/// Generate a mesh avatar for `user_prompt`; the OpenSCAD code for an identical earlier request
+/// comes from the generation cache unless `force`.
 pub async fn generate_avatar_mesh(
     store: &WorldStore,
     cfg: &AssistantConfig,
     profile_id: &str,
     user_prompt: &str,
+    force: bool,
 ) -> Result<AvatarSpecV1> {
-    let Some(provider) = cfg.provider else {
+    if cfg.provider.is_none() {
         anyhow::bail!("no provider configured");
-    };
+    }
 
     if !program_exists("openscad").await {
         anyhow::bail!("openscad not found on PATH");
@@ -179,43 +181,18 @@ Output requirements:\n\
 User request: {user_prompt}\n"
     );
 
-    let raw_json = match provider {
-        AssistantProviderId::Codex => {
-            let schema_file = NamedTempFile::new().context("create schema tempfile")?;
-            std::fs::write(schema_file.path(), AVATAR_SCAD_SCHEMA_JSON)
-                .context("write schema tempfile")?;
-
-            let output_file = NamedTempFile::new().context("create output tempfile")?;
-            run_codex_structured(
-                &scad_prompt,
-                schema_file.path(),
-                output_file.path(),
-                Some(store.root_dir()),
-                cfg.codex_model.as_deref(),
-                cfg.codex_reasoning_effort.as_deref(),
-            )
-            .await?;
-            std::fs::read_to_string(output_file.path()).context("read codex output")?
-        }
-        AssistantProviderId::Claude => {
-            let raw = run_claude_structured(
-                &scad_prompt,
-                AVATAR_SCAD_SCHEMA_JSON,
-                cfg.claude_model.as_deref(),
-            )
-            .await?;
-            let v: Value = serde_json::from_str(&raw).context("parse claude result wrapper")?;
-            if let Some(so) = v.get("structured_output") {
-                serde_json::to_string(so).context("serialize structured_output")?
-            } else if let Some(result) = v.get("result").and_then(|r| r.as_str()) {
-                extract_json_object(result).context("extract json from claude result")?
-            } else {
-                anyhow::bail!("claude did not return structured_output or result");
-            }
-        }
-    };
+    let raw_json = generate_structured(
+        store,
+        cfg,
+        "avatar_mesh/v1",
+        &scad_prompt,
+        AVATAR_SCAD_SCHEMA_JSON,
+        force,
+    )
+    .await?;
 
-    let scad: ScadResult = serde_json::from_str(&raw_json).context("parse scad json")?;
+    let scad: ScadResult = serde_json::from_str(&raw_json)
+        .map_err(|e| AssistantError::Corrupt(format!("parse scad json: {e}")))?;
 
     let dir = avatar_mesh_dir(store, profile_id);
     std::fs::create_dir_all(&dir).with_context(|| format!("create {dir:?}"))?;
@@ -237,10 +214,14 @@ User request: {user_prompt}\n"
     cmd.stdout(std::process::Stdio::null());
     cmd.stderr(std::process::Stdio::piped());
 
+    let started = Instant::now();
     let out = timeout(Duration::from_secs(60), cmd.output())
         .await
         .context("openscad timeout")?
         .context("run openscad")?;
+    if out.status.success() {
+        telemetry::record(store, telemetry::Event::Render(started.elapsed()));
+    }
 
     if !out.status.success() {
         let err = String::from_utf8_lossy(&out.stderr);
@@ -250,6 +231,7 @@ User request: {user_prompt}\n"
     }
 
     let stl_bytes = std::fs::read(&stl_path).with_context(|| format!("read {stl_path:?}"))?;
+    check_mesh_size(stl_bytes.len())?;
     let hash = hex::encode(Sha256::digest(&stl_bytes));
 
     // Render optional accessory parts to separate STL files (for multi-material looks in Unity).
@@ -291,6 +273,9 @@ User request: {user_prompt}\n"
         }
 
         if let Ok(bytes) = std::fs::read(&out_path) {
+            if check_mesh_size(bytes.len()).is_err() {
+                continue;
+            }
             let phash = hex::encode(Sha256::digest(&bytes));
             if phash == hash {
                 // Likely ignored render_part and exported the full mesh; don't duplicate.
@@ -345,8 +330,7 @@ User request: {user_prompt}\n"
         parts: mesh_parts,
     });
 
-    avatar_mod::save_avatar(store, profile_id, &avatar).context("save avatar")?;
-    Ok(avatar)
+    avatar_mod::save_avatar(store, profile_id, &avatar, RevisionSource::Mesh).context("save avatar")
 }
 
 pub fn read_mesh_bytes(
@@ -362,44 +346,3 @@ pub fn read_mesh_bytes(
     let bytes = std::fs::read(&p).with_context(|| format!("read {p:?}"))?;
     Ok(bytes)
 }
-
-fn extract_json_object(text: &str) -> Result<String> {
-    let start = text
-        .find('{')
-        .ok_or_else(|| anyhow::anyhow!("no '{{' found in text"))?;
-
-    let mut depth = 0usize;
-    let mut in_string = false;
-    let mut escape = false;
-
-    for (i, ch) in text[start..].char_indices() {
-        let c = ch;
-        if in_string {
-            if escape {
-                escape = false;
-                continue;
-            }
-            match c {
-                '\\' => escape = true,
-                '"' => in_string = false,
-                _ => {}
-            }
-            continue;
-        }
-
-        match c {
-            '"' => in_string = true,
-            '{' => depth += 1,
-            '}' => {
-                depth = depth.saturating_sub(1);
-                if depth == 0 {
-                    let end = start + i + 1;
-                    return Ok(text[start..end].to_string());
-                }
-            }
-            _ => {}
-        }
-    }
-
-    anyhow::bail!("unterminated json object");
-}
@@ -0,0 +1,310 @@
+//! Bytes served per world and calendar month (UTC), split by channel, and the caps in
+//! `WorldBandwidthConfig`. Each process writes only the channels it serves to
+//! `logs/bandwidth/<channel>.json` and reads the others back, so the game server and the admin
+//! API can run apart.
+
+use anyhow::{Context, Result};
+use owp_protocol::{Message, WorldBandwidthConfig};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use time::OffsetDateTime;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::storage::write_atomic;
+
+/// How often a running game server writes its counters and rereads the admin API's.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Serializes read-modify-write of the asset counter within this process.
+static ASSET_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+    /// Replies and world state sent to game connections.
+    Game,
+    /// Other players' positions, emotes and chat fanned out to game connections.
+    Relay,
+    /// Asset and content downloads from the admin API.
+    Asset,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 3] = [Channel::Game, Channel::Relay, Channel::Asset];
+
+    fn name(self) -> &'static str {
+        match self {
+            Channel::Game => "game",
+            Channel::Relay => "relay",
+            Channel::Asset => "asset",
+        }
+    }
+
+    /// Which channel an outgoing game message counts against.
+    pub fn of(msg: &Message) -> Self {
+        match msg {
+            Message::PlayerPosition(_)
+            | Message::Emote(_)
+            | Message::ChatBroadcast(_)
+            | Message::AvatarAnnounce(_) => Channel::Relay,
+            _ => Channel::Game,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CapState {
+    Ok,
+    /// Past `soft_cap_bytes`: game traffic is paced.
+    Throttled,
+    /// Past `monthly_cap_bytes`: new connections and downloads are refused.
+    Capped,
+}
+
+pub fn cap_state(cfg: &WorldBandwidthConfig, total: u64) -> CapState {
+    if cfg.monthly_cap_bytes.is_some_and(|cap| total >= cap) {
+        CapState::Capped
+    } else if cfg.soft_cap_bytes.is_some_and(|cap| total >= cap) {
+        CapState::Throttled
+    } else {
+        CapState::Ok
+    }
+}
+
+/// `2026-10`: counters from another month count as zero.
+pub fn current_month() -> String {
+    let now = OffsetDateTime::now_utc();
+    format!("{}-{:02}", now.year(), u8::from(now.month()))
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CounterV1 {
+    month: String,
+    bytes: u64,
+}
+
+pub fn counter_path(world_dir: &Path, channel: Channel) -> PathBuf {
+    world_dir
+        .join("logs")
+        .join("bandwidth")
+        .join(format!("{}.json", channel.name()))
+}
+
+fn load_counter(world_dir: &Path, channel: Channel, month: &str) -> Result<u64> {
+    let path = counter_path(world_dir, channel);
+    if !path.exists() {
+        return Ok(0);
+    }
+    let data = fs::read_to_string(&path).with_context(|| format!("read {path:?}"))?;
+    let c: CounterV1 = serde_json::from_str(&data).with_context(|| format!("parse {path:?}"))?;
+    Ok(if c.month == month { c.bytes } else { 0 })
+}
+
+fn save_counter(world_dir: &Path, channel: Channel, month: &str, bytes: u64) -> Result<()> {
+    let path = counter_path(world_dir, channel);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("create {parent:?}"))?;
+    }
+    let c = CounterV1 {
+        month: month.to_string(),
+        bytes,
+    };
+    let json = serde_json::to_string(&c).context("serialize bandwidth counter")?;
+    write_atomic(&path, format!("{json}\n").as_bytes())?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BandwidthUsageV1 {
+    pub month: String,
+    pub game_bytes: u64,
+    pub relay_bytes: u64,
+    pub asset_bytes: u64,
+    pub total_bytes: u64,
+    pub state: CapState,
+}
+
+/// This month's usage as last flushed, for the admin API.
+pub fn usage(world_dir: &Path, cfg: &WorldBandwidthConfig) -> Result<BandwidthUsageV1> {
+    let month = current_month();
+    let [game_bytes, relay_bytes, asset_bytes] =
+        Channel::ALL.map(|c| load_counter(world_dir, c, &month));
+    let (game_bytes, relay_bytes, asset_bytes) = (game_bytes?, relay_bytes?, asset_bytes?);
+    let total_bytes = game_bytes + relay_bytes + asset_bytes;
+    Ok(BandwidthUsageV1 {
+        month,
+        game_bytes,
+        relay_bytes,
+        asset_bytes,
+        total_bytes,
+        state: cap_state(cfg, total_bytes),
+    })
+}
+
+/// Count an asset download served by the admin API.
+pub fn record_asset(world_dir: &Path, bytes: u64) -> Result<()> {
+    let _guard = ASSET_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let month = current_month();
+    let before = load_counter(world_dir, Channel::Asset, &month)?;
+    save_counter(world_dir, Channel::Asset, &month, before + bytes)
+}
+
+/// Prometheus text exposition for `GET /metrics`.
+pub fn render_metrics(worlds: &[(Uuid, BandwidthUsageV1)]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "# HELP owp_bandwidth_month_bytes Bytes served this calendar month, by channel."
+    );
+    let _ = writeln!(out, "# TYPE owp_bandwidth_month_bytes gauge");
+    for (world_id, u) in worlds {
+        for (channel, bytes) in [
+            (Channel::Game, u.game_bytes),
+            (Channel::Relay, u.relay_bytes),
+            (Channel::Asset, u.asset_bytes),
+        ] {
+            let _ = writeln!(
+                out,
+                "owp_bandwidth_month_bytes{{world_id=\"{world_id}\",channel=\"{}\"}} {bytes}",
+                channel.name()
+            );
+        }
+    }
+    out
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    month: String,
+    game: u64,
+    relay: u64,
+    /// The admin API's count, as of the last flush.
+    asset: u64,
+}
+
+/// Game and relay bytes of a running game server, written out every few seconds.
+#[derive(Clone, Default)]
+pub struct GameMeter {
+    inner: Arc<Mutex<Counts>>,
+    dirty: Arc<AtomicBool>,
+}
+
+impl GameMeter {
+    /// Continue this month's counts from the files.
+    pub fn load(world_dir: &Path) -> Self {
+        let month = current_month();
+        let read = |c| {
+            load_counter(world_dir, c, &month).unwrap_or_else(|e| {
+                warn!("bandwidth counter reset: {e:#}");
+                0
+            })
+        };
+        let counts = Counts {
+            game: read(Channel::Game),
+            relay: read(Channel::Relay),
+            asset: read(Channel::Asset),
+            month,
+        };
+        Self {
+            inner: Arc::new(Mutex::new(counts)),
+            dirty: Arc::default(),
+        }
+    }
+
+    pub fn record(&self, channel: Channel, bytes: u64) {
+        let mut c = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        match channel {
+            Channel::Game => c.game += bytes,
+            Channel::Relay => c.relay += bytes,
+            Channel::Asset => c.asset += bytes,
+        }
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Bytes served this month on every channel.
+    pub fn total(&self) -> u64 {
+        let c = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        c.game + c.relay + c.asset
+    }
+
+    /// Write the game and relay counters, pick up the asset counter, and start over when the
+    /// month turns.
+    fn flush(&self, world_dir: &Path) -> Result<()> {
+        let month = current_month();
+        let asset = load_counter(world_dir, Channel::Asset, &month)?;
+        let mut c = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        if c.month != month {
+            *c = Counts {
+                month: month.clone(),
+                ..Counts::default()
+            };
+        }
+        c.asset = asset;
+        if self.dirty.swap(false, Ordering::Relaxed) {
+            save_counter(world_dir, Channel::Game, &month, c.game)?;
+            save_counter(world_dir, Channel::Relay, &month, c.relay)?;
+        }
+        Ok(())
+    }
+
+    pub fn spawn_flush(&self, world_dir: PathBuf) {
+        let this = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = this.flush(&world_dir) {
+                    warn!("writing bandwidth counters failed: {e:#}");
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn game_and_asset_counts_add_up_against_caps() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let cfg = WorldBandwidthConfig {
+            monthly_cap_bytes: Some(1000),
+            soft_cap_bytes: Some(500),
+            ..WorldBandwidthConfig::default()
+        };
+        let meter = GameMeter::load(dir.path());
+        meter.record(Channel::Game, 300);
+        meter.record(Channel::Relay, 100);
+        meter.flush(dir.path()).expect("flush");
+        record_asset(dir.path(), 150).expect("asset");
+
+        let u = usage(dir.path(), &cfg).expect("usage");
+        assert_eq!(
+            (u.game_bytes, u.relay_bytes, u.asset_bytes, u.total_bytes),
+            (300, 100, 150, 550)
+        );
+        assert_eq!(u.state, CapState::Throttled);
+        // The game server sees the admin API's bytes after its next flush.
+        assert_eq!(meter.total(), 400);
+        meter.flush(dir.path()).expect("flush");
+        assert_eq!(meter.total(), 550);
+
+        // A restart picks up where the files left off; last month's files count as zero.
+        assert_eq!(GameMeter::load(dir.path()).total(), 550);
+        save_counter(dir.path(), Channel::Game, "1999-01", 5000).expect("old month");
+        assert_eq!(usage(dir.path(), &cfg).expect("usage").total_bytes, 250);
+        assert_eq!(cap_state(&cfg, 1000), CapState::Capped);
+        assert_eq!(
+            cap_state(&WorldBandwidthConfig::default(), u64::MAX),
+            CapState::Ok
+        );
+    }
+}
@@ -0,0 +1,370 @@
+//! `owp-server bench-host`: measure what this machine can do and turn it into starting
+//! settings. The estimates are deliberately rough: they assume the default tick rate, players
+//! spread over a few nearby groups, and one OpenSCAD render per generated avatar mesh.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::process::Command;
+use tokio::time::timeout;
+
+use owp_protocol::{wire, Message, PlayerPosition};
+
+use crate::assistant::{self, AssistantProviderId};
+use crate::storage::WorldStore;
+
+/// Written and read back for the throughput test.
+const DISK_BYTES: usize = 64 * 1024 * 1024;
+
+/// Small appends, each fsynced like a WAL record.
+const FSYNC_WRITES: usize = 200;
+
+/// Pushed through a loopback connection.
+const NET_BYTES: usize = 256 * 1024 * 1024;
+
+/// Game traffic one player causes: its own replies plus positions, emotes and chat relayed
+/// from about 20 nearby players at 10 Hz.
+const PLAYER_BYTES_PER_SEC: f64 = 16.0 * 1024.0;
+
+/// Frames the server encodes for one player per second, mostly relayed from nearby players.
+const PLAYER_FRAMES_PER_SEC: f64 = 200.0;
+
+/// Frames encoded and decoded for the CPU test.
+const CPU_FRAMES: usize = 200_000;
+
+/// WAL commits one player causes per second (edits, ledger changes, exploration).
+const PLAYER_FSYNCS_PER_SEC: f64 = 0.5;
+
+/// Share of a measured capacity the estimate plans to use.
+const HEADROOM: f64 = 0.5;
+
+/// Stands in for a generated avatar: a few dozen primitives, like the assistant's output.
+const REFERENCE_SCAD: &str = r#"$fn = 48;
+module limb(h, r) { cylinder(h = h, r1 = r, r2 = r * 0.8); }
+union() {
+  translate([0, 0, 1.45]) sphere(r = 0.13);
+  translate([0, 0, 0.85]) scale([1, 0.6, 1.4]) sphere(r = 0.25);
+  for (s = [-1, 1]) {
+    translate([s * 0.28, 0, 1.15]) rotate([0, s * 160, 0]) limb(0.55, 0.05);
+    translate([s * 0.1, 0, 0.55]) rotate([0, 180, 0]) limb(0.55, 0.07);
+    translate([s * 0.05, 0.12, 1.48]) sphere(r = 0.025);
+  }
+  translate([0, 0, 1.6]) minkowski() { cube([0.18, 0.18, 0.04], center = true); sphere(r = 0.02); }
+}
+"#;
+
+#[derive(Debug, Default, Serialize)]
+pub struct BenchReport {
+    pub cpus: usize,
+    /// Frames encoded and decoded per second on one core.
+    pub frames_per_sec: f64,
+    pub disk_write_mb_s: f64,
+    pub disk_read_mb_s: f64,
+    pub fsyncs_per_sec: f64,
+    pub loopback_mb_s: f64,
+    /// `None` when OpenSCAD isn't installed.
+    pub openscad_render_ms: Option<u64>,
+    /// `None` when no provider is configured or `--no-llm` was given.
+    pub llm_latency_ms: Option<u64>,
+    pub estimate: Estimate,
+    /// Why a measurement was skipped.
+    pub notes: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize, PartialEq)]
+pub struct Estimate {
+    pub players: u64,
+    /// What limits `players`: `cpu`, `network`, `uplink` or `disk`.
+    pub players_bound_by: &'static str,
+    /// Avatar generations to run at once; 0 when OpenSCAD is missing.
+    pub generation_concurrency: u64,
+    pub generations_per_hour: u64,
+}
+
+/// Turn measurements into settings. `uplink_mbps` is the host's internet upload, which
+/// loopback can't measure.
+pub fn estimate(
+    cpus: usize,
+    frames_per_sec: f64,
+    loopback_mb_s: f64,
+    fsyncs_per_sec: f64,
+    uplink_mbps: Option<f64>,
+    openscad_render_ms: Option<u64>,
+    llm_latency_ms: Option<u64>,
+) -> Estimate {
+    let mut bounds = vec![
+        ("cpu", frames_per_sec * cpus as f64 / PLAYER_FRAMES_PER_SEC),
+        (
+            "network",
+            loopback_mb_s * 1024.0 * 1024.0 / PLAYER_BYTES_PER_SEC,
+        ),
+        ("disk", fsyncs_per_sec / PLAYER_FSYNCS_PER_SEC),
+    ];
+    if let Some(mbps) = uplink_mbps {
+        bounds.push(("uplink", mbps * 1_000_000.0 / 8.0 / PLAYER_BYTES_PER_SEC));
+    }
+    let (players_bound_by, players) = bounds
+        .into_iter()
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap_or(("network", 0.0));
+
+    // Renders are single-threaded; keep one core for the game servers.
+    let (generation_concurrency, generations_per_hour) = match openscad_render_ms {
+        Some(render_ms) => {
+            let concurrency = cpus.saturating_sub(1).max(1) as u64;
+            let per_job_ms = render_ms + llm_latency_ms.unwrap_or(0);
+            let per_hour = concurrency * 3_600_000 / per_job_ms.max(1);
+            (concurrency, per_hour)
+        }
+        None => (0, 0),
+    };
+    Estimate {
+        players: (players * HEADROOM) as u64,
+        players_bound_by,
+        generation_concurrency,
+        generations_per_hour,
+    }
+}
+
+fn mb_per_sec(bytes: usize, took: Duration) -> f64 {
+    bytes as f64 / 1024.0 / 1024.0 / took.as_secs_f64().max(1e-9)
+}
+
+/// Round-trip position frames through the wire format, as the game server does per message.
+fn bench_cpu() -> Result<f64> {
+    let msg = Message::PlayerPosition(PlayerPosition {
+        position: [12.5, 3.0, -40.25],
+    });
+    let started = Instant::now();
+    for _ in 0..CPU_FRAMES {
+        let frame = wire::encode_frame(&msg)?;
+        wire::decode_frame(&frame)?.context("decode frame")?;
+    }
+    Ok(CPU_FRAMES as f64 / started.elapsed().as_secs_f64().max(1e-9))
+}
+
+/// Sequential write (fsynced at the end) and read of `DISK_BYTES`, then fsynced small appends.
+fn bench_disk(dir: &Path) -> Result<(f64, f64, f64)> {
+    let path = dir.join("bench-host.tmp");
+    let block = vec![0x5au8; 1024 * 1024];
+    let result = (|| {
+        let started = Instant::now();
+        let mut f = File::create(&path).with_context(|| format!("create {path:?}"))?;
+        for _ in 0..DISK_BYTES / block.len() {
+            f.write_all(&block)?;
+        }
+        f.sync_all()?;
+        let write = mb_per_sec(DISK_BYTES, started.elapsed());
+
+        // Mostly served from the page cache, as the server's own reads are.
+        let started = Instant::now();
+        let mut buf = vec![0u8; block.len()];
+        let mut f = File::open(&path)?;
+        while f.read(&mut buf)? > 0 {}
+        let read = mb_per_sec(DISK_BYTES, started.elapsed());
+
+        let mut f = OpenOptions::new().write(true).truncate(true).open(&path)?;
+        let started = Instant::now();
+        for _ in 0..FSYNC_WRITES {
+            f.write_all(&block[..200])?;
+            f.sync_data()?;
+        }
+        let fsyncs = FSYNC_WRITES as f64 / started.elapsed().as_secs_f64().max(1e-9);
+        anyhow::Ok((write, read, fsyncs))
+    })();
+    let _ = fs::remove_file(&path);
+    result.context("disk benchmark")
+}
+
+async fn bench_loopback() -> Result<f64> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let reader = tokio::spawn(async move {
+        let (mut s, _) = listener.accept().await?;
+        let mut buf = vec![0u8; 64 * 1024];
+        let mut total = 0;
+        while total < NET_BYTES {
+            match s.read(&mut buf).await? {
+                0 => break,
+                n => total += n,
+            }
+        }
+        anyhow::Ok(total)
+    });
+    let mut s = TcpStream::connect(addr).await?;
+    let chunk = vec![0x5au8; 64 * 1024];
+    let started = Instant::now();
+    for _ in 0..NET_BYTES / chunk.len() {
+        s.write_all(&chunk).await?;
+    }
+    s.shutdown().await?;
+    let received = reader.await??;
+    Ok(mb_per_sec(received, started.elapsed()))
+}
+
+/// Render `REFERENCE_SCAD` the way avatar meshes are rendered. `Ok(None)` without OpenSCAD.
+async fn bench_openscad() -> Result<Option<Duration>> {
+    let dir = tempfile::tempdir().context("create temp dir")?;
+    let scad = dir.path().join("reference.scad");
+    fs::write(&scad, REFERENCE_SCAD).with_context(|| format!("write {scad:?}"))?;
+    let mut cmd = Command::new("openscad");
+    cmd.arg("--render")
+        .arg("-o")
+        .arg(dir.path().join("reference.stl"))
+        .arg(&scad)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::piped());
+    let started = Instant::now();
+    let out = match timeout(Duration::from_secs(120), cmd.output()).await {
+        Ok(Ok(out)) => out,
+        Ok(Err(e)) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Ok(Err(e)) => return Err(e).context("run openscad"),
+        Err(_) => anyhow::bail!("openscad took over 120s"),
+    };
+    if !out.status.success() {
+        anyhow::bail!(
+            "openscad failed: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+    }
+    Ok(Some(started.elapsed()))
+}
+
+/// One minimal structured request to the configured provider. `Ok(None)` without one.
+async fn bench_llm(store: &WorldStore) -> Result<Option<Duration>> {
+    let cfg = assistant::load_config(store)?;
+    let Some(provider) = cfg.provider else {
+        return Ok(None);
+    };
+    let schema = r#"{"type":"object","properties":{"ok":{"type":"boolean"}},"required":["ok"],"additionalProperties":false}"#;
+    let prompt = "Reply with {\"ok\": true}.";
+    let started = Instant::now();
+    match provider {
+        AssistantProviderId::Codex => {
+            let schema_file = tempfile::NamedTempFile::new().context("create schema tempfile")?;
+            fs::write(schema_file.path(), schema).context("write schema tempfile")?;
+            let output_file = tempfile::NamedTempFile::new().context("create output tempfile")?;
+            assistant::run_codex_structured(
+                prompt,
+                schema_file.path(),
+                output_file.path(),
+                Some(store.root_dir()),
+                cfg.codex_model.as_deref(),
+                cfg.codex_reasoning_effort.as_deref(),
+            )
+            .await?;
+        }
+        AssistantProviderId::Claude => {
+            assistant::run_claude_structured(prompt, schema, cfg.claude_model.as_deref()).await?;
+        }
+    }
+    Ok(Some(started.elapsed()))
+}
+
+pub async fn run(store: &WorldStore, uplink_mbps: Option<f64>, llm: bool) -> Result<BenchReport> {
+    let mut report = BenchReport {
+        cpus: std::thread::available_parallelism().map_or(1, |n| n.get()),
+        ..BenchReport::default()
+    };
+    report.frames_per_sec = tokio::task::spawn_blocking(bench_cpu)
+        .await?
+        .context("cpu benchmark")?;
+    let root = store.root_dir().to_path_buf();
+    let (write, read, fsyncs) = tokio::task::spawn_blocking(move || bench_disk(&root)).await??;
+    report.disk_write_mb_s = write;
+    report.disk_read_mb_s = read;
+    report.fsyncs_per_sec = fsyncs;
+    report.loopback_mb_s = bench_loopback().await.context("loopback benchmark")?;
+
+    match bench_openscad().await {
+        Ok(Some(took)) => report.openscad_render_ms = Some(took.as_millis() as u64),
+        Ok(None) => report
+            .notes
+            .push("openscad not found on PATH; avatar meshes can't be generated".to_string()),
+        Err(e) => report.notes.push(format!("openscad: {e:#}")),
+    }
+    if llm {
+        match bench_llm(store).await {
+            Ok(Some(took)) => report.llm_latency_ms = Some(took.as_millis() as u64),
+            Ok(None) => report
+                .notes
+                .push("no assistant provider configured".to_string()),
+            Err(e) => report.notes.push(format!("assistant provider: {e:#}")),
+        }
+    }
+    if uplink_mbps.is_none() {
+        report.notes.push(
+            "internet upload not measured; pass --uplink-mbps to include it in the estimate"
+                .to_string(),
+        );
+    }
+    report.estimate = estimate(
+        report.cpus,
+        report.frames_per_sec,
+        report.loopback_mb_s,
+        report.fsyncs_per_sec,
+        uplink_mbps,
+        report.openscad_render_ms,
+        report.llm_latency_ms,
+    );
+    Ok(report)
+}
+
+pub fn print(report: &BenchReport) {
+    let ms = |v: Option<u64>| v.map_or_else(|| "skipped".to_string(), |v| format!("{v} ms"));
+    println!(
+        "cpu                  {} cores, {:.0} frames/s per core",
+        report.cpus, report.frames_per_sec
+    );
+    println!(
+        "disk                 write {:.0} MB/s, read {:.0} MB/s, {:.0} fsyncs/s",
+        report.disk_write_mb_s, report.disk_read_mb_s, report.fsyncs_per_sec
+    );
+    println!("loopback network     {:.0} MB/s", report.loopback_mb_s);
+    println!("openscad render      {}", ms(report.openscad_render_ms));
+    println!("assistant latency    {}", ms(report.llm_latency_ms));
+    println!();
+    let e = &report.estimate;
+    println!(
+        "players              about {} (bound by {})",
+        e.players, e.players_bound_by
+    );
+    println!(
+        "generation           {} at once, about {} per hour",
+        e.generation_concurrency, e.generations_per_hour
+    );
+    for note in &report.notes {
+        println!("note: {note}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimate_takes_the_tightest_bound() {
+        // 50 MB/s loopback allows 3200 players, 1000 fsyncs/s 2000: disk wins, halved.
+        let e = estimate(8, 1e6, 50.0, 1000.0, None, Some(2_000), Some(8_000));
+        assert_eq!(e.players_bound_by, "disk");
+        assert_eq!(e.players, 1000);
+        assert_eq!(e.generation_concurrency, 7);
+        assert_eq!(e.generations_per_hour, 7 * 360);
+
+        // 10 Mbit/s upload: 76 players, halved.
+        let e = estimate(1, 1e6, 50.0, 1000.0, Some(10.0), None, None);
+        assert_eq!(e.players_bound_by, "uplink");
+        assert_eq!(e.players, 38);
+        assert_eq!((e.generation_concurrency, e.generations_per_hour), (0, 0));
+
+        // 100k frames/s on 2 cores: 1000 players, halved.
+        let e = estimate(2, 1e5, 50.0, 1000.0, None, None, None);
+        assert_eq!((e.players_bound_by, e.players), ("cpu", 500));
+    }
+}
@@ -0,0 +1,245 @@
+use anyhow::{Context, Result};
+use owp_protocol::{ChatBroadcast, ChatChannel, ChatSend, Message};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::party::Parties;
+use crate::presence::Presence;
+
+pub const MAX_CHAT_CHARS: usize = 500;
+
+/// Proximity chat reaches this far (meters).
+pub const PROXIMITY_RADIUS_M: f32 = 30.0;
+
+/// The chat log rolls over to `chat.jsonl.1` past this size.
+pub const CHAT_LOG_MAX_BYTES: u64 = 1024 * 1024;
+
+/// Rolled-over chat logs kept besides the current one.
+pub const CHAT_LOG_KEEP: usize = 3;
+
+/// A server notice for one player.
+pub fn system(text: impl Into<String>) -> Message {
+    Message::ChatBroadcast(ChatBroadcast {
+        channel: ChatChannel::System,
+        from: None,
+        text: text.into(),
+        sent_at: OffsetDateTime::now_utc(),
+    })
+}
+
+/// Route a chat message from `from` and echo it back to the sender as confirmation. Errors are
+/// for the sender only (e.g. whispering to someone who left) and are meant to be shown to them.
+pub fn deliver(
+    presence: &Presence,
+    parties: &Parties,
+    from: Uuid,
+    send: ChatSend,
+) -> Result<ChatBroadcast> {
+    let text = send.text.trim();
+    if text.is_empty() {
+        anyhow::bail!("empty message");
+    }
+    if text.chars().count() > MAX_CHAT_CHARS {
+        anyhow::bail!("messages are limited to {MAX_CHAT_CHARS} characters");
+    }
+    let broadcast = ChatBroadcast {
+        channel: send.channel,
+        from: Some(from),
+        text: text.to_string(),
+        sent_at: OffsetDateTime::now_utc(),
+    };
+    let msg = Message::ChatBroadcast(broadcast.clone());
+    match send.channel {
+        ChatChannel::World => {
+            presence.send_all(Some(from), &msg);
+        }
+        ChatChannel::Proximity => {
+            presence.send_within(from, Some(PROXIMITY_RADIUS_M), &msg);
+        }
+        ChatChannel::Whisper { to } => {
+            if to == from {
+                anyhow::bail!("you can't whisper to yourself");
+            }
+            if !presence.send_to(to, &msg) {
+                anyhow::bail!("player {to} is not here");
+            }
+        }
+        ChatChannel::Party => {
+            // Members may be in other worlds; the echo is part of the party send.
+            parties.send(from, &msg)?;
+            return Ok(broadcast);
+        }
+        ChatChannel::System => anyhow::bail!("clients can't send system messages"),
+    }
+    presence.send_to(from, &msg);
+    Ok(broadcast)
+}
+
+pub fn chat_log_path(world_dir: &Path) -> PathBuf {
+    world_dir.join("logs").join("chat.jsonl")
+}
+
+/// The world's rolling log of public chat (`World` and `Proximity`), one `ChatBroadcast` per
+/// line. Whispers and party chat are never written down.
+#[derive(Clone)]
+pub struct ChatLog {
+    path: PathBuf,
+    lock: Arc<Mutex<()>>,
+}
+
+impl ChatLog {
+    pub fn new(world_dir: &Path) -> Self {
+        Self {
+            path: chat_log_path(world_dir),
+            lock: Arc::default(),
+        }
+    }
+
+    pub fn record(&self, broadcast: &ChatBroadcast) -> Result<()> {
+        if !matches!(
+            broadcast.channel,
+            ChatChannel::World | ChatChannel::Proximity
+        ) {
+            return Ok(());
+        }
+        let line = serde_json::to_string(broadcast).context("serialize chat line")?;
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let path = &self.path;
+        if fs::metadata(path).is_ok_and(|m| m.len() >= CHAT_LOG_MAX_BYTES) {
+            self.roll()?;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("create {parent:?}"))?;
+        }
+        let mut f = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("open {path:?}"))?;
+        writeln!(f, "{line}").with_context(|| format!("append {path:?}"))
+    }
+
+    /// `chat.jsonl` becomes `chat.jsonl.1`, `.1` becomes `.2`, and the oldest is dropped.
+    fn roll(&self) -> Result<()> {
+        let rolled = |n: usize| PathBuf::from(format!("{}.{n}", self.path.display()));
+        for n in (1..CHAT_LOG_KEEP).rev() {
+            let from = rolled(n);
+            if from.exists() {
+                fs::rename(&from, rolled(n + 1)).with_context(|| format!("roll {from:?}"))?;
+            }
+        }
+        fs::rename(&self.path, rolled(1)).with_context(|| format!("roll {:?}", self.path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presence::OUTBOX_CAPACITY;
+    use tokio::sync::mpsc;
+
+    fn text_of(rx: &mut mpsc::Receiver<Message>) -> Option<(ChatChannel, String)> {
+        match rx.try_recv().ok()? {
+            Message::ChatBroadcast(b) => Some((b.channel, b.text)),
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+
+    #[test]
+    fn channels_reach_the_right_players() {
+        let presence = Presence::default();
+        let parties = Parties::default();
+        let ids: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        let mut rx: Vec<_> = ids
+            .iter()
+            .map(|id| {
+                let (tx, rx) = mpsc::channel(OUTBOX_CAPACITY);
+                presence.join(*id, tx);
+                rx
+            })
+            .collect();
+        presence.set_position(ids[0], [0.0, 0.0, 0.0]);
+        presence.set_position(ids[1], [10.0, 0.0, 10.0]);
+        presence.set_position(ids[2], [50.0, 0.0, 0.0]); // same interest area, out of earshot
+
+        let say = |channel, text: &str| ChatSend {
+            channel,
+            text: text.to_string(),
+        };
+        deliver(
+            &presence,
+            &parties,
+            ids[0],
+            say(ChatChannel::Proximity, " hi "),
+        )
+        .expect("proximity");
+        assert_eq!(
+            text_of(&mut rx[0]),
+            Some((ChatChannel::Proximity, "hi".to_string()))
+        );
+        assert!(text_of(&mut rx[1]).is_some());
+        assert!(text_of(&mut rx[2]).is_none());
+
+        deliver(&presence, &parties, ids[0], say(ChatChannel::World, "all")).expect("world");
+        assert!(rx.iter_mut().all(|r| text_of(r).is_some()));
+
+        let whisper = ChatChannel::Whisper { to: ids[2] };
+        deliver(&presence, &parties, ids[1], say(whisper, "psst")).expect("whisper");
+        assert!(text_of(&mut rx[0]).is_none());
+        assert!(text_of(&mut rx[1]).is_some());
+        assert!(text_of(&mut rx[2]).is_some());
+
+        assert!(deliver(&presence, &parties, ids[1], say(ChatChannel::Party, "x")).is_err());
+        let (tx, _) = mpsc::channel(OUTBOX_CAPACITY);
+        parties.create(ids[1], Uuid::nil(), tx).expect("party");
+        deliver(&presence, &parties, ids[1], say(ChatChannel::Party, "team")).expect("party");
+        assert!(rx.iter_mut().all(|r| text_of(r).is_none()));
+
+        let gone = ChatChannel::Whisper { to: Uuid::new_v4() };
+        assert!(deliver(&presence, &parties, ids[1], say(gone, "hello?")).is_err());
+        assert!(deliver(&presence, &parties, ids[1], say(ChatChannel::System, "x")).is_err());
+        assert!(deliver(&presence, &parties, ids[1], say(ChatChannel::World, " ")).is_err());
+    }
+
+    #[test]
+    fn public_chat_is_logged_and_rolled_over() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let log = ChatLog::new(dir.path());
+        let line = |channel, text: &str| ChatBroadcast {
+            channel,
+            from: Some(Uuid::nil()),
+            text: text.to_string(),
+            sent_at: OffsetDateTime::UNIX_EPOCH,
+        };
+        log.record(&line(ChatChannel::World, "hello"))
+            .expect("world");
+        log.record(&line(ChatChannel::Proximity, "near"))
+            .expect("proximity");
+        let whisper = ChatChannel::Whisper { to: Uuid::nil() };
+        log.record(&line(whisper, "secret")).expect("whisper");
+        log.record(&line(ChatChannel::Party, "team"))
+            .expect("party");
+        let path = chat_log_path(dir.path());
+        let text = fs::read_to_string(&path).expect("log");
+        assert_eq!(text.lines().count(), 2);
+        assert!(!text.contains("secret") && !text.contains("team"));
+
+        let big = "x".repeat(MAX_CHAT_CHARS);
+        let per_line = serde_json::to_string(&line(ChatChannel::World, &big))
+            .expect("json")
+            .len() as u64
+            + 1;
+        let lines = CHAT_LOG_MAX_BYTES.div_ceil(per_line) as usize;
+        for _ in 0..lines * (CHAT_LOG_KEEP + 2) {
+            log.record(&line(ChatChannel::World, &big)).expect("record");
+        }
+        let rolled = |n: usize| PathBuf::from(format!("{}.{n}", path.display()));
+        assert!((1..=CHAT_LOG_KEEP).all(|n| rolled(n).exists()));
+        assert!(!rolled(CHAT_LOG_KEEP + 1).exists());
+        assert!(fs::metadata(&path).expect("current").len() <= CHAT_LOG_MAX_BYTES + per_line);
+    }
+}
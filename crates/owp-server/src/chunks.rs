@@ -0,0 +1,202 @@
+use anyhow::{Context, Result};
+use owp_protocol::{ChunkChangeV1, ChunkCoord, ChunkDelta, WorldObjectV1};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::storage::write_atomic;
+
+/// Chunk edge length in meters (XZ plane).
+pub const CHUNK_SIZE_M: f32 = 32.0;
+
+/// How many changes we keep per chunk for incremental sync. Clients further behind get a
+/// full chunk instead.
+pub const CHUNK_JOURNAL_LEN: usize = 256;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// Chunk version produced by applying `change`.
+    pub version: u64,
+    pub change: ChunkChangeV1,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkFileV1 {
+    pub chunk: ChunkCoord,
+    pub version: u64,
+    #[serde(default)]
+    pub objects: Vec<WorldObjectV1>,
+    #[serde(default)]
+    pub journal: VecDeque<JournalEntry>,
+    /// Last world WAL sequence number reflected in this file (see `wal.rs`).
+    #[serde(default)]
+    pub applied_seq: u64,
+}
+
+impl ChunkFileV1 {
+    fn empty(chunk: ChunkCoord) -> Self {
+        Self {
+            chunk,
+            version: 0,
+            objects: Vec::new(),
+            journal: VecDeque::new(),
+            applied_seq: 0,
+        }
+    }
+
+    /// Apply a change, bump the version, and record it in the bounded journal.
+    pub fn apply(&mut self, change: ChunkChangeV1) -> u64 {
+        match &change {
+            ChunkChangeV1::Upsert { object } => {
+                match self.objects.iter_mut().find(|o| o.id == object.id) {
+                    Some(existing) => *existing = object.clone(),
+                    None => self.objects.push(object.clone()),
+                }
+            }
+            ChunkChangeV1::Remove { object_id } => {
+                self.objects.retain(|o| &o.id != object_id);
+            }
+        }
+        self.version += 1;
+        self.journal.push_back(JournalEntry {
+            version: self.version,
+            change,
+        });
+        while self.journal.len() > CHUNK_JOURNAL_LEN {
+            self.journal.pop_front();
+        }
+        self.version
+    }
+
+    /// Build the reply for "changes since `since`". Falls back to a full chunk when the
+    /// journal no longer covers the requested range.
+    pub fn delta_since(&self, request_id: Uuid, since: u64) -> ChunkDelta {
+        let oldest_base = self
+            .journal
+            .front()
+            .map(|e| e.version - 1)
+            .unwrap_or(self.version);
+        let incremental = since > 0 && since <= self.version && since >= oldest_base;
+
+        if incremental {
+            ChunkDelta {
+                request_id,
+                chunk: self.chunk,
+                base_version: since,
+                version: self.version,
+                full: false,
+                objects: Vec::new(),
+                changes: self
+                    .journal
+                    .iter()
+                    .filter(|e| e.version > since)
+                    .map(|e| e.change.clone())
+                    .collect(),
+            }
+        } else {
+            ChunkDelta {
+                request_id,
+                chunk: self.chunk,
+                base_version: 0,
+                version: self.version,
+                full: true,
+                objects: self.objects.clone(),
+                changes: Vec::new(),
+            }
+        }
+    }
+}
+
+pub fn chunk_for_position(position: [f32; 3]) -> ChunkCoord {
+    ChunkCoord {
+        x: (position[0] / CHUNK_SIZE_M).floor() as i32,
+        z: (position[2] / CHUNK_SIZE_M).floor() as i32,
+    }
+}
+
+pub fn chunk_path(world_dir: &Path, chunk: ChunkCoord) -> PathBuf {
+    world_dir
+        .join("chunks")
+        .join(format!("{}_{}.json", chunk.x, chunk.z))
+}
+
+pub fn load_chunk(world_dir: &Path, chunk: ChunkCoord) -> Result<ChunkFileV1> {
+    let path = chunk_path(world_dir, chunk);
+    if !path.exists() {
+        return Ok(ChunkFileV1::empty(chunk));
+    }
+    let data = fs::read_to_string(&path).with_context(|| format!("read {path:?}"))?;
+    let file: ChunkFileV1 =
+        serde_json::from_str(&data).with_context(|| format!("parse {path:?}"))?;
+    Ok(file)
+}
+
+/// Every chunk file of the world, in no particular order.
+pub fn list_chunks(world_dir: &Path) -> Result<Vec<ChunkFileV1>> {
+    let mut chunks = Vec::new();
+    let dir = world_dir.join("chunks");
+    if dir.exists() {
+        for entry in fs::read_dir(&dir).with_context(|| format!("read {dir:?}"))? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|e| e != "json") {
+                continue;
+            }
+            let data = fs::read_to_string(&path).with_context(|| format!("read {path:?}"))?;
+            chunks.push(serde_json::from_str(&data).with_context(|| format!("parse {path:?}"))?);
+        }
+    }
+    Ok(chunks)
+}
+
+pub fn save_chunk(world_dir: &Path, file: &ChunkFileV1) -> Result<()> {
+    let path = chunk_path(world_dir, file.chunk);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("create {parent:?}"))?;
+    }
+    let json = serde_json::to_string_pretty(file).context("serialize chunk")?;
+    write_atomic(&path, format!("{json}\n").as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn obj(id: &str) -> WorldObjectV1 {
+        WorldObjectV1 {
+            id: id.to_string(),
+            kind: "rock".to_string(),
+            position: [0.0, 0.0, 0.0],
+            rotation: [0.0, 0.0, 0.0],
+            scale: [1.0, 1.0, 1.0],
+        }
+    }
+
+    #[test]
+    fn delta_is_incremental_within_journal_and_full_outside() {
+        let mut file = ChunkFileV1::empty(ChunkCoord { x: 0, z: 0 });
+        for i in 0..(CHUNK_JOURNAL_LEN + 10) {
+            file.apply(ChunkChangeV1::Upsert {
+                object: obj(&format!("o{}", i % 4)),
+            });
+        }
+        let v = file.version;
+
+        let d = file.delta_since(Uuid::nil(), v - 3);
+        assert!(!d.full);
+        assert_eq!(d.changes.len(), 3);
+
+        let d = file.delta_since(Uuid::nil(), v);
+        assert!(!d.full);
+        assert!(d.changes.is_empty());
+
+        let d = file.delta_since(Uuid::nil(), 1);
+        assert!(d.full);
+        assert_eq!(d.objects.len(), 4);
+
+        let d = file.delta_since(Uuid::nil(), v + 5);
+        assert!(d.full);
+    }
+}
@@ -0,0 +1,279 @@
+//! Experimental region sharding: several nodes serve one world, each owning a rectangle of its
+//! chunks. A player walking out of a node's region is handed off: the node tells the owner of
+//! the new region about the session over the internal listener, then sends the client a
+//! `Handoff` to resume there. `owp-server cluster-router` is the one address clients are given;
+//! it forwards new connections to the node owning the spawn chunk.
+//!
+//! Not shared between nodes yet: chunk files (each node serves its own copy), presence across a
+//! border, parties and chat. See README "Clustering".
+
+use anyhow::{Context, Result};
+use axum::{extract::State, http::HeaderMap, http::StatusCode, routing::post, Json, Router};
+use owp_protocol::ChunkCoord;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::asset_token;
+
+/// How long a node waits for another to accept a handoff.
+const HANDOFF_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Inclusive rectangle of chunk coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ChunkRegion {
+    pub min: [i32; 2],
+    pub max: [i32; 2],
+}
+
+impl ChunkRegion {
+    pub fn contains(&self, chunk: ChunkCoord) -> bool {
+        (self.min[0]..=self.max[0]).contains(&chunk.x)
+            && (self.min[1]..=self.max[1]).contains(&chunk.z)
+    }
+
+    fn overlaps(&self, other: &ChunkRegion) -> bool {
+        self.min[0] <= other.max[0]
+            && other.min[0] <= self.max[0]
+            && self.min[1] <= other.max[1]
+            && other.min[1] <= self.max[1]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClusterNode {
+    pub id: String,
+    /// `host:port` clients reach this node's game server at.
+    pub game: String,
+    /// `host:port` of this node's internal listener, reachable from the other nodes only.
+    pub internal: String,
+    pub region: ChunkRegion,
+}
+
+/// The `cluster` section of `server.json`; every node lists the same `nodes`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClusterConfig {
+    pub world_id: Uuid,
+    /// Which of `nodes` this data dir is.
+    pub node_id: String,
+    pub nodes: Vec<ClusterNode>,
+    /// Shared by the nodes to authenticate handoffs. Falls back to `OWP_CLUSTER_SECRET`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+impl ClusterConfig {
+    /// Node ids are unique, this node is listed and regions are well-formed and disjoint.
+    pub fn check(&self) -> Result<()> {
+        let mut ids = HashSet::new();
+        for (i, node) in self.nodes.iter().enumerate() {
+            if !ids.insert(node.id.as_str()) {
+                anyhow::bail!("cluster node {:?} is listed twice", node.id);
+            }
+            let r = node.region;
+            if r.min[0] > r.max[0] || r.min[1] > r.max[1] {
+                anyhow::bail!("cluster node {:?} has an empty region", node.id);
+            }
+            if let Some(other) = self.nodes[..i].iter().find(|o| o.region.overlaps(&r)) {
+                anyhow::bail!("cluster nodes {:?} and {:?} overlap", other.id, node.id);
+            }
+        }
+        self.this_node()?;
+        self.secret()?;
+        Ok(())
+    }
+
+    pub fn this_node(&self) -> Result<&ClusterNode> {
+        self.nodes
+            .iter()
+            .find(|n| n.id == self.node_id)
+            .with_context(|| format!("cluster node_id {:?} is not in nodes", self.node_id))
+    }
+
+    /// The node serving `chunk`; `None` outside every region.
+    pub fn owner(&self, chunk: ChunkCoord) -> Option<&ClusterNode> {
+        self.nodes.iter().find(|n| n.region.contains(chunk))
+    }
+
+    /// Where the router sends new connections: the owner of chunk (0, 0), else the first node.
+    pub fn entry(&self) -> Option<&ClusterNode> {
+        self.owner(ChunkCoord { x: 0, z: 0 })
+            .or_else(|| self.nodes.first())
+    }
+
+    fn secret(&self) -> Result<String> {
+        self.secret
+            .clone()
+            .or_else(|| std::env::var("OWP_CLUSTER_SECRET").ok())
+            .filter(|s| !s.is_empty())
+            .context("cluster needs a secret (or OWP_CLUSTER_SECRET)")
+    }
+}
+
+/// A player moving to another node, sent to that node's internal listener.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HandoffTicket {
+    pub world_id: Uuid,
+    pub player_id: Uuid,
+    pub session_token: String,
+    /// Node handing the player off.
+    pub from: String,
+}
+
+/// Tell `to` to expect `ticket`'s session.
+pub async fn send_handoff(
+    cfg: &ClusterConfig,
+    to: &ClusterNode,
+    ticket: &HandoffTicket,
+) -> Result<()> {
+    let resp = reqwest::Client::new()
+        .post(format!("http://{}/handoff", to.internal))
+        .bearer_auth(cfg.secret()?)
+        .timeout(HANDOFF_TIMEOUT)
+        .json(ticket)
+        .send()
+        .await
+        .with_context(|| format!("handoff to node {:?}", to.id))?;
+    if !resp.status().is_success() {
+        anyhow::bail!(
+            "node {:?} refused the handoff: HTTP {}",
+            to.id,
+            resp.status()
+        );
+    }
+    Ok(())
+}
+
+type Adopt = Arc<dyn Fn(&HandoffTicket) + Send + Sync>;
+
+#[derive(Clone)]
+struct InternalState {
+    world_id: Uuid,
+    secret: String,
+    adopt: Adopt,
+}
+
+async fn accept_handoff(
+    State(st): State<InternalState>,
+    headers: HeaderMap,
+    Json(ticket): Json<HandoffTicket>,
+) -> StatusCode {
+    let token = headers
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    if !token.is_some_and(|t| asset_token::secret_eq(&st.secret, t)) {
+        return StatusCode::UNAUTHORIZED;
+    }
+    if ticket.world_id != st.world_id {
+        return StatusCode::NOT_FOUND;
+    }
+    info!(
+        "node {:?} hands off player {}",
+        ticket.from, ticket.player_id
+    );
+    (st.adopt)(&ticket);
+    StatusCode::NO_CONTENT
+}
+
+/// Serve this node's internal listener; `adopt` makes a handed-off session resumable here.
+pub fn spawn_internal(
+    cfg: &ClusterConfig,
+    adopt: impl Fn(&HandoffTicket) + Send + Sync + 'static,
+) -> Result<()> {
+    let addr = cfg.this_node()?.internal.clone();
+    let state = InternalState {
+        world_id: cfg.world_id,
+        secret: cfg.secret()?,
+        adopt: Arc::new(adopt),
+    };
+    let app = Router::new()
+        .route("/handoff", post(accept_handoff))
+        .with_state(state);
+    tokio::spawn(async move {
+        let listener = match TcpListener::bind(&addr).await {
+            Ok(l) => l,
+            Err(e) => {
+                warn!("cluster internal listener on {addr} failed: {e}");
+                return;
+            }
+        };
+        info!("cluster internal listener on http://{addr}");
+        if let Err(e) = axum::serve(listener, app).await {
+            warn!("cluster internal listener stopped: {e}");
+        }
+    });
+    Ok(())
+}
+
+/// `owp-server cluster-router`: forward every connection to the entry node unchanged.
+pub async fn route(cfg: ClusterConfig, listen: &str) -> Result<()> {
+    cfg.check()?;
+    let entry = cfg.entry().context("cluster has no nodes")?.clone();
+    let listener = TcpListener::bind(listen)
+        .await
+        .with_context(|| format!("bind {listen}"))?;
+    info!(
+        "cluster router on tcp://{listen} -> node {:?} ({})",
+        entry.id, entry.game
+    );
+    loop {
+        let (mut client, peer) = listener.accept().await.context("accept")?;
+        let target = entry.game.clone();
+        tokio::spawn(async move {
+            let mut node = match TcpStream::connect(&target).await {
+                Ok(s) => s,
+                Err(e) => {
+                    warn!("routing {peer} to {target} failed: {e}");
+                    return;
+                }
+            };
+            if let Err(e) = tokio::io::copy_bidirectional(&mut client, &mut node).await {
+                debug!("routed connection from {peer} ended: {e}");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(id: &str, min: [i32; 2], max: [i32; 2]) -> ClusterNode {
+        ClusterNode {
+            id: id.to_string(),
+            game: format!("{id}:7777"),
+            internal: format!("{id}:7800"),
+            region: ChunkRegion { min, max },
+        }
+    }
+
+    #[test]
+    fn regions_pick_owners_and_must_not_overlap() {
+        let mut cfg = ClusterConfig {
+            world_id: Uuid::new_v4(),
+            node_id: "east".to_string(),
+            nodes: vec![
+                node("west", [-64, -64], [-1, 63]),
+                node("east", [0, -64], [63, 63]),
+            ],
+            secret: Some("s3cret".to_string()),
+        };
+        cfg.check().expect("valid");
+        let at = |x, z| cfg.owner(ChunkCoord { x, z }).map(|n| n.id.as_str());
+        assert_eq!(at(-1, 0), Some("west"));
+        assert_eq!(at(0, 63), Some("east"));
+        assert_eq!(at(64, 0), None);
+        assert_eq!(cfg.entry().expect("entry").id, "east");
+
+        cfg.nodes.push(node("north", [60, 60], [80, 80]));
+        assert!(cfg.check().is_err());
+        cfg.nodes.pop();
+        cfg.node_id = "south".to_string();
+        assert!(cfg.check().is_err());
+    }
+}
@@ -0,0 +1,326 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use time::OffsetDateTime;
+use tracing::{info, warn};
+
+use crate::access::AccessFilter;
+use crate::backup::BackupConfig;
+use crate::cluster::ClusterConfig;
+use crate::quota::QuotaConfig;
+use crate::storage::{write_atomic, WorldStore};
+use crate::telemetry::TelemetryConfig;
+use crate::token_launch::TokenLaunchConfig;
+
+/// How often the config file's mtime is polled (works where SIGHUP doesn't, e.g. Windows).
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    /// Sustained game messages per second per connection.
+    #[serde(default = "default_messages_per_sec")]
+    pub messages_per_sec: u32,
+    /// Messages a connection may send in a burst above the sustained rate.
+    #[serde(default = "default_burst")]
+    pub burst: u32,
+}
+
+fn default_messages_per_sec() -> u32 {
+    50
+}
+
+fn default_burst() -> u32 {
+    100
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            messages_per_sec: default_messages_per_sec(),
+            burst: default_burst(),
+        }
+    }
+}
+
+/// Pings on open game connections.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeepaliveConfig {
+    /// Seconds between pings (0 = never ping, never drop idle connections).
+    #[serde(default = "default_ping_interval_secs")]
+    pub interval_secs: u64,
+    /// Drop a connection once this many pings in a row went unanswered.
+    #[serde(default = "default_max_missed")]
+    pub max_missed: u32,
+}
+
+fn default_ping_interval_secs() -> u64 {
+    15
+}
+
+fn default_max_missed() -> u32 {
+    3
+}
+
+impl Default for KeepaliveConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: default_ping_interval_secs(),
+            max_missed: default_max_missed(),
+        }
+    }
+}
+
+/// Settings that can change without restarting (no listen addresses here).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServerConfigV1 {
+    #[serde(default = "default_motd")]
+    pub motd: String,
+    #[serde(default)]
+    pub rate_limits: RateLimitConfig,
+    #[serde(default)]
+    pub keepalive: KeepaliveConfig,
+    /// Host clients reach the worlds at, as listed in the registry. Worlds with an authority
+    /// key sign it into `welcome.attestation`; unset, nothing is attested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_endpoint: Option<String>,
+    /// Accept filter for every world; each world can narrow it further.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub access: AccessFilter,
+    /// `first,last,country` CSV that country filters look peers up in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub geoip_db: Option<PathBuf>,
+    /// S3-compatible target for scheduled encrypted backups; unset, nothing is backed up.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup: Option<BackupConfig>,
+    /// Experimental: this node's share of a world split across several nodes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cluster: Option<ClusterConfig>,
+    /// Per-world budgets when one process hosts several worlds.
+    #[serde(default, skip_serializing_if = "QuotaConfig::is_empty")]
+    pub quotas: QuotaConfig,
+    /// Solana RPC that game servers check entry fees with; `OWP_SOLANA_RPC_URL` when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub solana_rpc_url: Option<String>,
+    /// Network and programs for `POST /worlds/:id/token/create`.
+    #[serde(default, skip_serializing_if = "TokenLaunchConfig::is_default")]
+    pub token_launch: TokenLaunchConfig,
+    /// Opt-in usage stats; see `telemetry.rs`.
+    #[serde(default, skip_serializing_if = "TelemetryConfig::is_default")]
+    pub telemetry: TelemetryConfig,
+    /// URL that assistant providers turning unhealthy or recovering are posted to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub assistant_health_webhook: Option<String>,
+}
+
+fn is_default(filter: &AccessFilter) -> bool {
+    *filter == AccessFilter::default()
+}
+
+fn default_motd() -> String {
+    "Welcome to OWP".to_string()
+}
+
+impl Default for ServerConfigV1 {
+    fn default() -> Self {
+        Self {
+            motd: default_motd(),
+            rate_limits: RateLimitConfig::default(),
+            keepalive: KeepaliveConfig::default(),
+            public_endpoint: None,
+            access: AccessFilter::default(),
+            geoip_db: None,
+            backup: None,
+            cluster: None,
+            quotas: QuotaConfig::default(),
+            solana_rpc_url: None,
+            token_launch: TokenLaunchConfig::default(),
+            telemetry: TelemetryConfig::default(),
+            assistant_health_webhook: None,
+        }
+    }
+}
+
+pub fn server_config_path(store: &WorldStore) -> PathBuf {
+    store.root_dir().join("server.json")
+}
+
+fn read_config(path: &Path) -> Result<ServerConfigV1> {
+    if !path.exists() {
+        return Ok(ServerConfigV1::default());
+    }
+    let data = fs::read_to_string(path).with_context(|| format!("read {path:?}"))?;
+    serde_json::from_str(&data).with_context(|| format!("parse {path:?}"))
+}
+
+#[derive(Debug)]
+struct LiveState {
+    active: ServerConfigV1,
+    loaded_at: OffsetDateTime,
+    mtime: Option<SystemTime>,
+    last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigStatus {
+    pub path: String,
+    pub active: ServerConfigV1,
+    /// `None` when the file on disk can't be parsed (see `last_error`).
+    pub on_disk: Option<ServerConfigV1>,
+    pub in_sync: bool,
+    #[serde(with = "time::serde::rfc3339")]
+    pub loaded_at: OffsetDateTime,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+/// Server config that is re-read on SIGHUP or when the file changes.
+#[derive(Debug, Clone)]
+pub struct LiveConfig {
+    path: PathBuf,
+    inner: Arc<RwLock<LiveState>>,
+}
+
+fn mtime(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl LiveConfig {
+    pub fn load(store: &WorldStore) -> Result<Self> {
+        let path = server_config_path(store);
+        let active = read_config(&path)?;
+        Ok(Self {
+            inner: Arc::new(RwLock::new(LiveState {
+                active,
+                loaded_at: OffsetDateTime::now_utc(),
+                mtime: mtime(&path),
+                last_error: None,
+            })),
+            path,
+        })
+    }
+
+    pub fn current(&self) -> ServerConfigV1 {
+        self.inner
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .active
+            .clone()
+    }
+
+    /// Re-read the file. A bad file keeps the previous config active. Returns true if the
+    /// active config changed.
+    pub fn reload(&self) -> Result<bool> {
+        let mtime = mtime(&self.path);
+        let mut st = self.inner.write().unwrap_or_else(|e| e.into_inner());
+        st.mtime = mtime;
+        match read_config(&self.path) {
+            Ok(cfg) => {
+                let changed = cfg != st.active;
+                st.active = cfg;
+                st.loaded_at = OffsetDateTime::now_utc();
+                st.last_error = None;
+                Ok(changed)
+            }
+            Err(e) => {
+                st.last_error = Some(format!("{e:#}"));
+                Err(e)
+            }
+        }
+    }
+
+    /// Change the file on disk with `f` and make the result active.
+    pub fn update(&self, f: impl FnOnce(&mut ServerConfigV1)) -> Result<ServerConfigV1> {
+        let mut cfg = read_config(&self.path)?;
+        f(&mut cfg);
+        let json = serde_json::to_string_pretty(&cfg).context("serialize server config")?;
+        write_atomic(&self.path, format!("{json}\n").as_bytes())?;
+        self.reload()?;
+        Ok(cfg)
+    }
+
+    pub fn status(&self) -> ConfigStatus {
+        let on_disk = read_config(&self.path).ok();
+        let st = self.inner.read().unwrap_or_else(|e| e.into_inner());
+        ConfigStatus {
+            path: self.path.display().to_string(),
+            in_sync: on_disk.as_ref() == Some(&st.active),
+            active: st.active.clone(),
+            on_disk,
+            loaded_at: st.loaded_at,
+            last_error: st.last_error.clone(),
+        }
+    }
+
+    fn file_changed(&self) -> bool {
+        let st = self.inner.read().unwrap_or_else(|e| e.into_inner());
+        mtime(&self.path) != st.mtime
+    }
+
+    fn reload_logged(&self, why: &str) {
+        match self.reload() {
+            Ok(true) => info!("reloaded {:?} ({why})", self.path),
+            Ok(false) => {}
+            Err(e) => warn!("config reload failed, keeping previous config: {e:#}"),
+        }
+    }
+
+    /// Reload on SIGHUP (unix) and whenever the file's mtime changes.
+    pub fn spawn_watchers(&self) {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+            let cfg = self.clone();
+            match signal(SignalKind::hangup()) {
+                Ok(mut hup) => {
+                    tokio::spawn(async move {
+                        while hup.recv().await.is_some() {
+                            cfg.reload_logged("SIGHUP");
+                        }
+                    });
+                }
+                Err(e) => warn!("cannot install SIGHUP handler: {e}"),
+            }
+        }
+
+        let cfg = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(POLL_INTERVAL);
+            loop {
+                interval.tick().await;
+                if cfg.file_changed() {
+                    cfg.reload_logged("file changed");
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bad_file_keeps_previous_config() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let store = WorldStore::with_root(root.path().to_path_buf()).expect("store");
+        let live = LiveConfig::load(&store).expect("load");
+        assert_eq!(live.current(), ServerConfigV1::default());
+
+        let path = server_config_path(&store);
+        fs::write(&path, r#"{"motd":"hi"}"#).expect("write");
+        assert!(live.reload().expect("reload"));
+        assert_eq!(live.current().motd, "hi");
+        assert_eq!(live.current().rate_limits, RateLimitConfig::default());
+
+        fs::write(&path, "{oops").expect("write");
+        assert!(live.reload().is_err());
+        let status = live.status();
+        assert_eq!(status.active.motd, "hi");
+        assert!(status.on_disk.is_none());
+        assert!(!status.in_sync);
+        assert!(status.last_error.is_some());
+    }
+}
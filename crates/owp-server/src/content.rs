@@ -0,0 +1,48 @@
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::storage::{write_atomic, WorldStore};
+
+pub fn is_sha256(s: &str) -> bool {
+    s.len() == 64 && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+pub fn content_path(store: &WorldStore, sha256: &str) -> PathBuf {
+    store.content_root().join(sha256.to_ascii_lowercase())
+}
+
+/// URI clients use to fetch a blob from the admin API.
+pub fn content_uri(sha256: &str) -> String {
+    format!("/content/{sha256}")
+}
+
+pub fn hash(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+/// Store a blob under its hash and return the hash. Storing the same bytes twice is a no-op.
+pub fn put(store: &WorldStore, bytes: &[u8]) -> Result<String> {
+    let sha256 = hash(bytes);
+    let path = content_path(store, &sha256);
+    if path.exists() {
+        return Ok(sha256);
+    }
+    let root = store.content_root();
+    fs::create_dir_all(&root).with_context(|| format!("create {root:?}"))?;
+    write_atomic(&path, bytes)?;
+    Ok(sha256)
+}
+
+pub fn get(store: &WorldStore, sha256: &str) -> Result<Option<Vec<u8>>> {
+    if !is_sha256(sha256) {
+        return Ok(None);
+    }
+    let path = content_path(store, sha256);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let bytes = fs::read(&path).with_context(|| format!("read {path:?}"))?;
+    Ok(Some(bytes))
+}
@@ -0,0 +1,233 @@
+//! Crash reports: a panic hook that writes what it can about the failure (message, location,
+//! backtrace, the worlds being served, the latest log lines) to `<root>/crashes/` before the
+//! default hook runs. `GET /crashes` lists them.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::backtrace::Backtrace;
+use std::collections::BTreeSet;
+use std::fs;
+use std::panic::PanicHookInfo;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, Once};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::logging::{self, LogLine, RecentQuery};
+use crate::storage::{write_atomic, WorldStore};
+
+static INSTALL: Once = Once::new();
+
+/// How many of the latest log lines go into a report.
+const RECENT_LOGS: usize = 500;
+
+/// Worlds with a running game server in this process.
+static ACTIVE_WORLDS: Mutex<BTreeSet<Uuid>> = Mutex::new(BTreeSet::new());
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CrashReportV1 {
+    #[serde(with = "time::serde::rfc3339")]
+    pub at: OffsetDateTime,
+    pub owp_version: String,
+    pub pid: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thread: Option<String>,
+    pub message: String,
+    /// `file:line:column` of the panic.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    pub backtrace: String,
+    pub worlds: Vec<Uuid>,
+    pub recent_logs: Vec<LogLine>,
+}
+
+/// One entry of `GET /crashes`.
+#[derive(Debug, Clone, Serialize)]
+pub struct CrashSummary {
+    pub name: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub at: OffsetDateTime,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+}
+
+pub fn crashes_dir(store: &WorldStore) -> PathBuf {
+    store.root_dir().join("crashes")
+}
+
+/// Marks a world as served until dropped.
+pub struct ActiveWorld(Uuid);
+
+impl Drop for ActiveWorld {
+    fn drop(&mut self) {
+        ACTIVE_WORLDS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.0);
+    }
+}
+
+pub fn world_started(world_id: Uuid) -> ActiveWorld {
+    ACTIVE_WORLDS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(world_id);
+    ActiveWorld(world_id)
+}
+
+fn active_worlds() -> Vec<Uuid> {
+    // `try_lock`: the panic may have happened while this thread held the lock.
+    match ACTIVE_WORLDS.try_lock() {
+        Ok(worlds) => worlds.iter().copied().collect(),
+        Err(_) => vec![],
+    }
+}
+
+fn payload_message(info: &PanicHookInfo) -> String {
+    let payload = info.payload();
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "(non-string panic payload)".to_string()
+    }
+}
+
+fn report(info: &PanicHookInfo) -> CrashReportV1 {
+    CrashReportV1 {
+        at: OffsetDateTime::now_utc(),
+        owp_version: env!("CARGO_PKG_VERSION").to_string(),
+        pid: std::process::id(),
+        thread: std::thread::current().name().map(String::from),
+        message: payload_message(info),
+        location: info
+            .location()
+            .map(|l| format!("{}:{}:{}", l.file(), l.line(), l.column())),
+        backtrace: Backtrace::force_capture().to_string(),
+        worlds: active_worlds(),
+        recent_logs: logging::recent(&RecentQuery {
+            limit: Some(RECENT_LOGS),
+            ..RecentQuery::default()
+        })
+        .unwrap_or_default(),
+    }
+}
+
+fn file_name(report: &CrashReportV1) -> String {
+    format!(
+        "{}-{}.json",
+        report.at.unix_timestamp_nanos() / 1_000_000,
+        report.pid
+    )
+}
+
+pub fn write_report(dir: &Path, report: &CrashReportV1) -> Result<PathBuf> {
+    fs::create_dir_all(dir).with_context(|| format!("create {dir:?}"))?;
+    let path = dir.join(file_name(report));
+    let json = serde_json::to_string_pretty(report).context("serialize crash report")?;
+    write_atomic(&path, format!("{json}\n").as_bytes())?;
+    Ok(path)
+}
+
+/// Write a crash report on every panic, then hand over to the previous hook. Only the first call
+/// in a process installs anything.
+pub fn install(store: &WorldStore) {
+    let dir = crashes_dir(store);
+    INSTALL.call_once(move || {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            match write_report(&dir, &report(info)) {
+                Ok(path) => eprintln!("crash report written to {}", path.display()),
+                Err(e) => eprintln!("writing crash report failed: {e:#}"),
+            }
+            previous(info);
+        }));
+    });
+}
+
+pub fn check_name(name: &str) -> Result<()> {
+    let ok = name.strip_suffix(".json").is_some_and(|stem| {
+        !stem.is_empty() && stem.chars().all(|c| c.is_ascii_digit() || c == '-')
+    });
+    if !ok {
+        anyhow::bail!("invalid crash report name {name:?}");
+    }
+    Ok(())
+}
+
+/// Every report, newest first.
+pub fn list(store: &WorldStore) -> Result<Vec<CrashSummary>> {
+    let dir = crashes_dir(store);
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+    let mut out = vec![];
+    for entry in fs::read_dir(&dir).with_context(|| format!("read {dir:?}"))? {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        if check_name(&name).is_err() {
+            continue;
+        }
+        let report = load(store, &name)?;
+        out.push(CrashSummary {
+            name,
+            at: report.at,
+            message: report.message,
+            location: report.location,
+        });
+    }
+    out.sort_by_key(|c| std::cmp::Reverse(c.at));
+    Ok(out)
+}
+
+/// One report, by the name `list` gave it.
+pub fn load(store: &WorldStore, name: &str) -> Result<CrashReportV1> {
+    check_name(name)?;
+    let path = crashes_dir(store).join(name);
+    let data = fs::read_to_string(&path).with_context(|| format!("read {path:?}"))?;
+    serde_json::from_str(&data).with_context(|| format!("parse {path:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_are_listed_newest_first() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let store = WorldStore::with_root(root.path().to_path_buf()).expect("store");
+        assert!(list(&store).expect("empty").is_empty());
+
+        let world_id = Uuid::new_v4();
+        let guard = world_started(world_id);
+        assert!(active_worlds().contains(&world_id));
+        let older = CrashReportV1 {
+            at: OffsetDateTime::from_unix_timestamp(1_700_000_000).expect("time"),
+            owp_version: "0.1.0".into(),
+            pid: 42,
+            thread: Some("main".into()),
+            message: "index out of bounds".into(),
+            location: Some("src/sim.rs:10:5".into()),
+            backtrace: String::new(),
+            worlds: active_worlds(),
+            recent_logs: vec![],
+        };
+        drop(guard);
+        assert!(!active_worlds().contains(&world_id));
+        let newer = CrashReportV1 {
+            at: older.at + time::Duration::seconds(5),
+            message: "called `Option::unwrap()` on a `None` value".into(),
+            ..older.clone()
+        };
+        write_report(&crashes_dir(&store), &older).expect("write");
+        let path = write_report(&crashes_dir(&store), &newer).expect("write");
+        assert_eq!(path.file_name().unwrap(), "1700000005000-42.json");
+
+        let listed = list(&store).expect("list");
+        let messages: Vec<&str> = listed.iter().map(|c| c.message.as_str()).collect();
+        assert_eq!(messages, [newer.message.as_str(), older.message.as_str()]);
+        assert_eq!(load(&store, &listed[1].name).expect("load"), older);
+        assert!(load(&store, "../server.json").is_err());
+    }
+}
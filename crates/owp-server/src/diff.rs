@@ -0,0 +1,166 @@
+//! Structured diffs between two revisions of a JSON document, for "what changed?" views. Objects
+//! are compared field by field; lists of objects with an `id` are matched by it, so a moved or
+//! edited entry reads as changed rather than removed and re-added.
+
+use serde::Serialize;
+use serde_json::Value;
+
+/// One field that differs. `field` is a dotted path like `mesh.sha256`; a missing side is `null`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub from: Value,
+    pub to: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChangedEntry {
+    pub id: String,
+    pub fields: Vec<FieldChange>,
+}
+
+/// Differences between two lists of objects keyed by `id`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ListDiff {
+    pub added: Vec<Value>,
+    pub removed: Vec<Value>,
+    pub changed: Vec<ChangedEntry>,
+}
+
+/// Field-level differences between two values. Nested objects recurse; anything else (arrays,
+/// or an object on one side only) is compared whole.
+pub fn diff_fields(from: &Value, to: &Value) -> Vec<FieldChange> {
+    let mut out = Vec::new();
+    diff_into("", from, to, &mut out);
+    out
+}
+
+fn diff_into(path: &str, from: &Value, to: &Value, out: &mut Vec<FieldChange>) {
+    match (from, to) {
+        (Value::Object(a), Value::Object(b)) => {
+            let mut keys: Vec<&String> = a.keys().chain(b.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let field = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{path}.{key}")
+                };
+                let x = a.get(key).unwrap_or(&Value::Null);
+                let y = b.get(key).unwrap_or(&Value::Null);
+                diff_into(&field, x, y, out);
+            }
+        }
+        _ if from == to => {}
+        _ => out.push(FieldChange {
+            field: path.to_string(),
+            from: from.clone(),
+            to: to.clone(),
+        }),
+    }
+}
+
+/// Match entries by their `id` field; entries without one are compared by position.
+pub fn diff_by_id(from: &[Value], to: &[Value]) -> ListDiff {
+    let key = |i: usize, v: &Value| {
+        v.get("id")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("#{i}"))
+    };
+    let before: Vec<(String, &Value)> = from
+        .iter()
+        .enumerate()
+        .map(|(i, v)| (key(i, v), v))
+        .collect();
+    let after: Vec<(String, &Value)> = to.iter().enumerate().map(|(i, v)| (key(i, v), v)).collect();
+    let mut out = ListDiff::default();
+    for (id, a) in &before {
+        match after.iter().find(|(k, _)| k == id) {
+            None => out.removed.push((*a).clone()),
+            Some((_, b)) => {
+                let fields = diff_fields(a, b);
+                if !fields.is_empty() {
+                    out.changed.push(ChangedEntry {
+                        id: id.clone(),
+                        fields,
+                    });
+                }
+            }
+        }
+    }
+    for (id, b) in &after {
+        if !before.iter().any(|(k, _)| k == id) {
+            out.added.push((*b).clone());
+        }
+    }
+    out
+}
+
+/// Short rendering of a value for summaries: strings unquoted, arrays of numbers compact.
+pub fn show(v: &Value) -> String {
+    match v {
+        Value::Null => "none".to_string(),
+        Value::String(s) => s.clone(),
+        Value::Number(n) => n
+            .as_f64()
+            .map(|f| format!("{}", (f * 1000.0).round() / 1000.0))
+            .unwrap_or_else(|| n.to_string()),
+        Value::Array(items) => format!(
+            "[{}]",
+            items.iter().map(show).collect::<Vec<_>>().join(", ")
+        ),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn fields_and_lists_diff_by_id() {
+        let changes = diff_fields(
+            &json!({"name": "a", "mesh": {"uri": "/x", "sha256": "1"}, "height": 1.0}),
+            &json!({"name": "a", "mesh": {"uri": "/x", "sha256": "2"}, "tags": ["t"]}),
+        );
+        assert_eq!(
+            changes,
+            vec![
+                FieldChange {
+                    field: "height".into(),
+                    from: json!(1.0),
+                    to: Value::Null
+                },
+                FieldChange {
+                    field: "mesh.sha256".into(),
+                    from: json!("1"),
+                    to: json!("2")
+                },
+                FieldChange {
+                    field: "tags".into(),
+                    from: Value::Null,
+                    to: json!(["t"])
+                },
+            ]
+        );
+
+        let d = diff_by_id(
+            &[
+                json!({"id": "hat", "color": "#000000"}),
+                json!({"id": "cape"}),
+            ],
+            &[
+                json!({"id": "horn"}),
+                json!({"id": "hat", "color": "#FF0000"}),
+            ],
+        );
+        assert_eq!(d.added, vec![json!({"id": "horn"})]);
+        assert_eq!(d.removed, vec![json!({"id": "cape"})]);
+        assert_eq!(d.changed.len(), 1);
+        assert_eq!(d.changed[0].fields[0].field, "color");
+        assert_eq!(show(&json!([0.1f32, 2.0])), "[0.1, 2]");
+    }
+}
@@ -0,0 +1,120 @@
+use anyhow::{Context, Result};
+use ed25519_dalek::SigningKey;
+use owp_discovery::directory::{DirectoryFileV1, DIRECTORY_SCHEMA_VERSION};
+use owp_discovery::endpoint;
+use owp_discovery::proxy::Proxy;
+use std::fs;
+use std::path::{Path, PathBuf};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::storage::{directory_entry, WorldStore};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    Json,
+    Toml,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportSource {
+    /// Manifests in this data dir
+    Local,
+    /// Entries from the on-chain registry
+    Chain,
+}
+
+pub fn directory_key_path(store: &WorldStore) -> PathBuf {
+    store.root_dir().join("directory-key")
+}
+
+/// Load the publisher key used to sign exported directories, creating one on first use.
+pub fn load_or_create_directory_key(store: &WorldStore) -> Result<SigningKey> {
+    let path = directory_key_path(store);
+    if path.exists() {
+        let hex_key = fs::read_to_string(&path).with_context(|| format!("read {path:?}"))?;
+        let bytes: [u8; 32] = hex::decode(hex_key.trim())
+            .ok()
+            .and_then(|b| b.try_into().ok())
+            .with_context(|| format!("{path:?} is not a 32-byte hex key"))?;
+        return Ok(SigningKey::from_bytes(&bytes));
+    }
+    let key = SigningKey::generate(&mut rand::rngs::OsRng);
+    fs::write(&path, format!("{}\n", hex::encode(key.to_bytes())))
+        .with_context(|| format!("write {path:?}"))?;
+    Ok(key)
+}
+
+pub struct ExportOptions<'a> {
+    pub source: ExportSource,
+    /// Public host advertised for local worlds.
+    pub endpoint: &'a str,
+    pub sign: bool,
+    pub solana_rpc_url: Option<&'a str>,
+    pub registry_program_id: Option<&'a str>,
+    pub proxy: Option<&'a Proxy>,
+}
+
+pub async fn build_directory(
+    store: &WorldStore,
+    opts: &ExportOptions<'_>,
+) -> Result<DirectoryFileV1> {
+    let worlds = match opts.source {
+        ExportSource::Local => {
+            let endpoint = endpoint::normalize(opts.endpoint)?;
+            store
+                .list_worlds()?
+                .iter()
+                .map(|m| directory_entry(m, &endpoint))
+                .collect()
+        }
+        ExportSource::Chain => {
+            let rpc = opts
+                .solana_rpc_url
+                .context("--source chain needs --solana-rpc-url (or OWP_SOLANA_RPC_URL)")?;
+            let program = opts.registry_program_id.context(
+                "--source chain needs --registry-program-id (or OWP_REGISTRY_PROGRAM_ID)",
+            )?;
+            owp_discovery::fetch_worlds_from_rpc(rpc, program, opts.proxy).await?
+        }
+    };
+    let mut file = DirectoryFileV1 {
+        schema_version: DIRECTORY_SCHEMA_VERSION,
+        generated_at: OffsetDateTime::now_utc()
+            .format(&Rfc3339)
+            .context("format timestamp")?,
+        source: match opts.source {
+            ExportSource::Local => "local",
+            ExportSource::Chain => "chain",
+        }
+        .to_string(),
+        worlds,
+        signature: None,
+    };
+    if opts.sign {
+        file.sign(&load_or_create_directory_key(store)?)?;
+    }
+    Ok(file)
+}
+
+pub fn render(file: &DirectoryFileV1, format: ExportFormat) -> Result<String> {
+    Ok(match format {
+        ExportFormat::Json => {
+            format!(
+                "{}\n",
+                serde_json::to_string_pretty(file).context("serialize directory")?
+            )
+        }
+        ExportFormat::Toml => toml::to_string_pretty(file).context("serialize directory")?,
+    })
+}
+
+pub fn write_output(out: Option<&Path>, body: &str) -> Result<()> {
+    match out {
+        Some(path) => fs::write(path, body).with_context(|| format!("write {path:?}")),
+        None => {
+            print!("{body}");
+            Ok(())
+        }
+    }
+}
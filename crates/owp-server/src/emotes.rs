@@ -0,0 +1,41 @@
+use anyhow::Result;
+use owp_protocol::{Emote, WorldEmotesConfig};
+use uuid::Uuid;
+
+/// Check an emote against the world's catalog and stamp it with the sender, shortening it to
+/// the emote's maximum duration.
+pub fn check(cfg: &WorldEmotesConfig, emote: &Emote, player_id: Uuid) -> Result<Emote> {
+    let Some(def) = cfg.catalog.iter().find(|d| d.id == emote.id) else {
+        anyhow::bail!("emote {:?} is not in this world's catalog", emote.id);
+    };
+    Ok(Emote {
+        id: def.id.clone(),
+        duration_ms: emote.duration_ms.min(def.max_duration_ms),
+        player_id: Some(player_id),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn catalog_gates_and_caps_emotes() {
+        let cfg = WorldEmotesConfig::default();
+        let me = Uuid::new_v4();
+        let spoofed = Emote {
+            id: "wave".to_string(),
+            duration_ms: 60_000,
+            player_id: Some(Uuid::new_v4()),
+        };
+        let ok = check(&cfg, &spoofed, me).expect("wave");
+        assert_eq!(ok.player_id, Some(me));
+        assert_eq!(ok.duration_ms, 3_000);
+
+        let unknown = Emote {
+            id: "moonwalk".to_string(),
+            ..spoofed
+        };
+        assert!(check(&cfg, &unknown, me).is_err());
+    }
+}
@@ -0,0 +1,291 @@
+//! Entry fees for premium worlds (`manifest.entry_fee`): the `PaymentRequired` sent instead of
+//! `Welcome`, the on-chain check of the payment, and the receipts kept in `receipts.jsonl`.
+
+use anyhow::{Context, Result};
+use owp_discovery::payment::{self, Payment};
+use owp_protocol::session::{Event, ProtocolStateMachine};
+use owp_protocol::{ErrorCode, Hello, PaymentRequired, WorldEntryFee};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use time::OffsetDateTime;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tracing::{info, warn};
+use url::Url;
+
+/// How long a client has to pay and answer with `payment_sent`.
+const PAYMENT_WINDOW: Duration = Duration::from_secs(600);
+
+/// After `payment_sent`, how long the server looks for the transaction before giving up.
+const VERIFY_POLLS: u32 = 15;
+const VERIFY_INTERVAL: Duration = Duration::from_secs(2);
+
+/// One entry fee paid, as found on chain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Receipt {
+    #[serde(with = "time::serde::rfc3339")]
+    pub at: OffsetDateTime,
+    /// Wallet that paid (the transaction's fee payer).
+    pub payer: String,
+    pub amount: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mint: Option<String>,
+    pub reference: String,
+    pub signature: String,
+}
+
+pub fn receipts_path(world_dir: &Path) -> PathBuf {
+    world_dir.join("receipts.jsonl")
+}
+
+pub fn load_receipts(world_dir: &Path) -> Result<Vec<Receipt>> {
+    let path = receipts_path(world_dir);
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    let data = fs::read_to_string(&path).with_context(|| format!("read {path:?}"))?;
+    data.lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| serde_json::from_str(l).with_context(|| format!("parse {path:?}")))
+        .collect()
+}
+
+pub fn record_receipt(world_dir: &Path, receipt: &Receipt) -> Result<()> {
+    let path = receipts_path(world_dir);
+    let line = serde_json::to_string(receipt).context("serialize receipt")?;
+    let mut f = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("open {path:?}"))?;
+    writeln!(f, "{line}").with_context(|| format!("append {path:?}"))?;
+    f.sync_data().with_context(|| format!("sync {path:?}"))
+}
+
+/// Whether `wallet` paid `fee` within the last `fee.pass_hours`.
+pub fn has_pass(
+    receipts: &[Receipt],
+    fee: &WorldEntryFee,
+    wallet: &str,
+    now: OffsetDateTime,
+) -> bool {
+    let pass = time::Duration::hours(fee.pass_hours as i64);
+    receipts.iter().any(|r| {
+        r.payer == wallet && r.mint == fee.mint && r.amount >= fee.amount && now < r.at + pass
+    })
+}
+
+/// `amount` base units as a decimal with `decimals` places, trailing zeros dropped.
+pub fn format_amount(amount: u64, decimals: u8) -> String {
+    let scale = 10u128.pow(decimals as u32);
+    let (whole, frac) = (amount as u128 / scale, amount as u128 % scale);
+    if frac == 0 {
+        return whole.to_string();
+    }
+    let frac = format!("{frac:0width$}", width = decimals as usize);
+    format!("{whole}.{}", frac.trim_end_matches('0'))
+}
+
+/// A fresh payment request answering `hello`, with a new random reference key.
+pub fn payment_request(
+    fee: &WorldEntryFee,
+    hello: &Hello,
+    label: &str,
+    now: OffsetDateTime,
+) -> PaymentRequired {
+    let reference = bs58::encode(rand::random::<[u8; 32]>()).into_string();
+    PaymentRequired {
+        request_id: hello.request_id,
+        recipient: fee.recipient.clone(),
+        amount: fee.amount,
+        mint: fee.mint.clone(),
+        pay_url: pay_url(fee, &reference, label),
+        reference,
+        expires_at: (now + PAYMENT_WINDOW).unix_timestamp(),
+    }
+}
+
+/// The Solana Pay transfer URL for paying `fee` with `reference`.
+fn pay_url(fee: &WorldEntryFee, reference: &str, label: &str) -> String {
+    let mut url = Url::parse(&format!("solana:{}", fee.recipient)).expect("solana: url");
+    {
+        let mut query = url.query_pairs_mut();
+        query.append_pair("amount", &format_amount(fee.amount, fee.decimals));
+        if let Some(mint) = &fee.mint {
+            query.append_pair("spl-token", mint);
+        }
+        query.append_pair("reference", reference);
+        query.append_pair("label", label);
+    }
+    url.to_string()
+}
+
+/// Charge the entry fee before `Welcome`, unless the player proved a wallet that holds a pass.
+/// `Ok(false)` means the player was turned away (and told why); the caller should hang up.
+#[allow(clippy::too_many_arguments)]
+pub async fn admit<S: AsyncRead + AsyncWrite + Unpin>(
+    proto: &mut ProtocolStateMachine,
+    stream: &mut S,
+    world_dir: &Path,
+    fee: &WorldEntryFee,
+    hello: &Hello,
+    label: &str,
+    rpc_url: Option<&str>,
+    peer: SocketAddr,
+) -> Result<bool> {
+    let now = OffsetDateTime::now_utc();
+    // `hello.wallet_pubkey` was cleared unless its proof checked out.
+    if let Some(wallet) = hello
+        .wallet_proof
+        .as_ref()
+        .and(hello.wallet_pubkey.as_deref())
+    {
+        if has_pass(&load_receipts(world_dir)?, fee, wallet, now) {
+            return Ok(true);
+        }
+    }
+    let Some(rpc_url) = rpc_url else {
+        warn!("refused {peer}: the world charges an entry fee but no Solana RPC is configured");
+        let message = "entry fees can't be checked right now".to_string();
+        proto.reject(ErrorCode::Unavailable, message)?;
+        proto.flush(stream).await?;
+        return Ok(false);
+    };
+
+    let request = payment_request(fee, hello, label, now);
+    proto.require_payment(request.clone())?;
+    proto.flush(stream).await?;
+    let sent = match tokio::time::timeout(PAYMENT_WINDOW, proto.next_handshake_event(stream)).await
+    {
+        Ok(Ok(Event::PaymentSent(sent))) => sent,
+        Ok(Ok(other)) => anyhow::bail!("unexpected event awaiting payment: {other:?}"),
+        Ok(Err(e)) => return Err(e).context("read payment_sent"),
+        Err(_) => {
+            info!("{peer} didn't pay the entry fee in time");
+            return refuse(proto, stream, "no payment arrived in time").await;
+        }
+    };
+    if sent.reference != request.reference {
+        return refuse(proto, stream, "payment_sent names another reference").await;
+    }
+
+    match find(rpc_url, &request).await {
+        Ok(Some(payment)) => {
+            info!("{peer} paid the entry fee in {}", payment.signature);
+            record_receipt(
+                world_dir,
+                &Receipt {
+                    at: OffsetDateTime::now_utc(),
+                    payer: payment.payer,
+                    amount: request.amount,
+                    mint: request.mint,
+                    reference: request.reference,
+                    signature: payment.signature,
+                },
+            )?;
+            Ok(true)
+        }
+        Ok(None) => {
+            info!("no entry fee from {peer} found for {}", request.reference);
+            refuse(proto, stream, "the payment wasn't found on chain").await
+        }
+        Err(e) => {
+            warn!("checking the entry fee from {peer} failed: {e:#}");
+            let message = "the payment couldn't be checked".to_string();
+            proto.reject(ErrorCode::Unavailable, message)?;
+            proto.flush(stream).await?;
+            Ok(false)
+        }
+    }
+}
+
+/// Give the cluster a little time to confirm a payment the client says it made.
+async fn find(rpc_url: &str, request: &PaymentRequired) -> owp_discovery::Result<Option<Payment>> {
+    for _ in 0..VERIFY_POLLS {
+        if let Some(payment) = payment::find_payment(rpc_url, None, request).await? {
+            return Ok(Some(payment));
+        }
+        tokio::time::sleep(VERIFY_INTERVAL).await;
+    }
+    Ok(None)
+}
+
+async fn refuse<S: AsyncWrite + Unpin>(
+    proto: &mut ProtocolStateMachine,
+    stream: &mut S,
+    message: &str,
+) -> Result<bool> {
+    proto.reject(ErrorCode::PaymentRequired, message.to_string())?;
+    proto.flush(stream).await?;
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fee(mint: Option<&str>) -> WorldEntryFee {
+        WorldEntryFee {
+            recipient: "9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin".into(),
+            amount: 1_500_000,
+            mint: mint.map(String::from),
+            decimals: 6,
+            pass_hours: 24,
+        }
+    }
+
+    #[test]
+    fn requests_carry_a_pay_url_and_receipts_grant_a_pass() {
+        assert_eq!(format_amount(1_500_000, 6), "1.5");
+        assert_eq!(format_amount(2_000_000_000, 9), "2");
+        assert_eq!(format_amount(1, 9), "0.000000001");
+        assert_eq!(format_amount(7, 0), "7");
+
+        let url = pay_url(&fee(Some("Mint")), "Ref", "Lobby & Co");
+        assert_eq!(
+            url,
+            "solana:9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin?amount=1.5&spl-token=Mint\
+             &reference=Ref&label=Lobby+%26+Co"
+        );
+
+        let dir = tempfile::tempdir().expect("tempdir");
+        assert!(load_receipts(dir.path()).expect("empty").is_empty());
+        let now = OffsetDateTime::now_utc();
+        let receipt = Receipt {
+            at: now - time::Duration::hours(2),
+            payer: "Payer".into(),
+            amount: 1_500_000,
+            mint: Some("Mint".into()),
+            reference: "Ref".into(),
+            signature: "Sig".into(),
+        };
+        record_receipt(dir.path(), &receipt).expect("record");
+        let receipts = load_receipts(dir.path()).expect("load");
+        assert_eq!(receipts, vec![receipt]);
+
+        let fee = fee(Some("Mint"));
+        assert!(has_pass(&receipts, &fee, "Payer", now));
+        assert!(!has_pass(&receipts, &fee, "Someone", now));
+        assert!(!has_pass(
+            &receipts,
+            &fee,
+            "Payer",
+            now + time::Duration::hours(23)
+        ));
+        let sol = self::fee(None);
+        assert!(!has_pass(&receipts, &sol, "Payer", now));
+        let pricier = WorldEntryFee {
+            amount: 2_000_000,
+            ..fee.clone()
+        };
+        assert!(!has_pass(&receipts, &pricier, "Payer", now));
+        let no_pass = WorldEntryFee {
+            pass_hours: 0,
+            ..fee
+        };
+        assert!(!has_pass(&receipts, &no_pass, "Payer", now));
+    }
+}
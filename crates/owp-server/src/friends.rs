@@ -0,0 +1,242 @@
+use anyhow::{Context, Result};
+use owp_protocol::{FriendPresence, Message};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::presence::Presence;
+use crate::storage::WorldStore;
+use crate::versioned::{self, Versioned};
+
+/// Serializes read-modify-write of friends files within this process.
+static FRIENDS_LOCK: Mutex<()> = Mutex::new(());
+
+pub const MAX_FRIENDS: usize = 500;
+pub const MAX_PROFILE_ID_CHARS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FriendKind {
+    /// Solana wallet pubkey (base58).
+    Wallet,
+    /// Profile id, as used by the admin API's `profile_id`.
+    Profile,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FriendV1 {
+    pub id: String,
+    pub kind: FriendKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nickname: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub added_at: OffsetDateTime,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FriendsV1 {
+    #[serde(default)]
+    pub friends: Vec<FriendV1>,
+}
+
+impl Versioned for FriendsV1 {
+    const VERSION: u32 = 1;
+}
+
+impl FriendsV1 {
+    pub fn ids(&self) -> HashSet<String> {
+        self.friends.iter().map(|f| f.id.clone()).collect()
+    }
+}
+
+pub fn friends_path(store: &WorldStore, profile_id: &str) -> PathBuf {
+    store.profiles_root().join(profile_id).join("friends.json")
+}
+
+fn is_wallet_pubkey(id: &str) -> bool {
+    (32..=44).contains(&id.len())
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() && !matches!(c, '0' | 'O' | 'I' | 'l'))
+}
+
+/// Profile ids double as directory names, so they are kept to `[A-Za-z0-9_-]`.
+pub fn check_profile_id(id: &str) -> Result<()> {
+    let ok = !id.is_empty()
+        && id.len() <= MAX_PROFILE_ID_CHARS
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if !ok {
+        anyhow::bail!(
+            "profile ids are 1-{MAX_PROFILE_ID_CHARS} characters of A-Z, a-z, 0-9, '_' and '-'"
+        );
+    }
+    Ok(())
+}
+
+/// Classify a friend id: wallet pubkeys are 32-44 base58 characters, anything else must be a
+/// valid profile id.
+pub fn kind_of(id: &str) -> Result<FriendKind> {
+    if is_wallet_pubkey(id) {
+        return Ok(FriendKind::Wallet);
+    }
+    check_profile_id(id).context("not a wallet pubkey or profile id")?;
+    Ok(FriendKind::Profile)
+}
+
+pub fn load(store: &WorldStore, profile_id: &str) -> Result<FriendsV1> {
+    let path = friends_path(store, profile_id);
+    Ok(versioned::load(&path)?.unwrap_or_default())
+}
+
+fn save(store: &WorldStore, profile_id: &str, f: &FriendsV1) -> Result<()> {
+    let path = friends_path(store, profile_id);
+    versioned::save(&path, f)?;
+    Ok(())
+}
+
+/// Add a friend, or update the nickname of an existing one.
+pub fn add(
+    store: &WorldStore,
+    profile_id: &str,
+    id: &str,
+    nickname: Option<String>,
+) -> Result<FriendV1> {
+    let kind = kind_of(id)?;
+    let _guard = FRIENDS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut f = load(store, profile_id)?;
+    if let Some(existing) = f.friends.iter_mut().find(|x| x.id == id) {
+        existing.nickname = nickname;
+        let friend = existing.clone();
+        save(store, profile_id, &f)?;
+        return Ok(friend);
+    }
+    if f.friends.len() >= MAX_FRIENDS {
+        anyhow::bail!("friends list is full ({MAX_FRIENDS})");
+    }
+    let friend = FriendV1 {
+        id: id.to_string(),
+        kind,
+        nickname,
+        added_at: OffsetDateTime::now_utc(),
+    };
+    f.friends.push(friend.clone());
+    save(store, profile_id, &f)?;
+    Ok(friend)
+}
+
+pub fn remove(store: &WorldStore, profile_id: &str, id: &str) -> Result<bool> {
+    let _guard = FRIENDS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut f = load(store, profile_id)?;
+    let before = f.friends.len();
+    f.friends.retain(|x| x.id != id);
+    if f.friends.len() == before {
+        return Ok(false);
+    }
+    save(store, profile_id, &f)?;
+    Ok(true)
+}
+
+/// Tell players in the world that have `player_id` as a friend that it came or went. When it
+/// came, also tell it which of its friends are already here.
+pub fn announce(presence: &Presence, world_id: Uuid, player_id: Uuid, online: bool) {
+    for (watcher, friend) in presence.watchers(player_id) {
+        let msg = Message::FriendPresence(FriendPresence {
+            friend,
+            player_id,
+            world_id,
+            online,
+        });
+        presence.send_to(watcher, &msg);
+    }
+    if !online {
+        return;
+    }
+    for (other, friend) in presence.friends_here(player_id) {
+        let msg = Message::FriendPresence(FriendPresence {
+            friend,
+            player_id: other,
+            world_id,
+            online: true,
+        });
+        presence.send_to(player_id, &msg);
+    }
+}
+
+/// Announces a player's departure to its friends when its connection goes away.
+pub struct FriendsGuard {
+    pub presence: Presence,
+    pub world_id: Uuid,
+    pub player_id: Uuid,
+}
+
+impl Drop for FriendsGuard {
+    fn drop(&mut self) {
+        announce(&self.presence, self.world_id, self.player_id, false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presence::OUTBOX_CAPACITY;
+    use tokio::sync::mpsc;
+
+    const WALLET: &str = "9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin";
+
+    #[test]
+    fn friends_hear_when_you_come_and_go() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let store = WorldStore::with_root(root.path().to_path_buf()).expect("store");
+        add(&store, "alice", WALLET, Some("Bob".to_string())).expect("add");
+        add(&store, "alice", "carol", None).expect("add");
+        assert!(add(&store, "alice", "../etc", None).is_err());
+        let list = load(&store, "alice").expect("load");
+        assert_eq!(list.friends[0].kind, FriendKind::Wallet);
+        assert_eq!(list.friends[1].kind, FriendKind::Profile);
+
+        let presence = Presence::default();
+        let world_id = Uuid::new_v4();
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        let (alice_tx, mut alice_rx) = mpsc::channel(OUTBOX_CAPACITY);
+        let (bob_tx, mut bob_rx) = mpsc::channel(OUTBOX_CAPACITY);
+        presence.join(alice, alice_tx);
+        presence.identify(alice, vec!["alice".to_string()], list.ids(), true);
+        presence.join(bob, bob_tx);
+        presence.identify(bob, vec![WALLET.to_string()], HashSet::new(), true);
+
+        announce(&presence, world_id, bob, true);
+        match alice_rx.try_recv() {
+            Ok(Message::FriendPresence(p)) => {
+                assert_eq!(
+                    (p.friend.as_str(), p.player_id, p.online),
+                    (WALLET, bob, true)
+                )
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+        // Bob has no friends list, so Bob isn't told about Alice.
+        assert!(bob_rx.try_recv().is_err());
+
+        drop(FriendsGuard {
+            presence: presence.clone(),
+            world_id,
+            player_id: bob,
+        });
+        assert!(matches!(alice_rx.try_recv(), Ok(Message::FriendPresence(p)) if !p.online));
+
+        // Once Bob stops appearing in rosters, Alice isn't told when Bob comes.
+        presence.set_listed(WALLET, false);
+        announce(&presence, world_id, bob, true);
+        assert!(alice_rx.try_recv().is_err());
+        assert!(presence.find_listed(WALLET).is_empty());
+        assert_eq!(presence.find(WALLET), vec![bob]);
+
+        assert!(remove(&store, "alice", WALLET).expect("remove"));
+        assert!(!remove(&store, "alice", WALLET).expect("remove"));
+    }
+}
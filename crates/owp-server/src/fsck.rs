@@ -0,0 +1,510 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::assets;
+use crate::chunks;
+use crate::ledger;
+use crate::prefabs;
+use crate::sim;
+use crate::storage::WorldStore;
+use crate::wal;
+
+/// Subdirectories every world workspace is expected to have.
+const WORLD_SUBDIRS: &[&str] = &["manifest", "chunks", "assets", "snapshots", "logs"];
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FsckIssueKind {
+    ManifestUnreadable,
+    WorldIdMismatch,
+    MissingDir,
+    ChunkUnreadable,
+    ChunkCoordMismatch,
+    ChunkJournalInconsistent,
+    ChunkContentMismatch,
+    OrphanedAsset,
+    StrayFile,
+    WalTruncated,
+    WalPending,
+    SimStateUnreadable,
+    LedgerUnreadable,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FsckIssue {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub world_id: Option<Uuid>,
+    pub kind: FsckIssueKind,
+    pub path: String,
+    pub detail: String,
+    pub repaired: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FsckReport {
+    pub worlds_checked: usize,
+    pub issues: Vec<FsckIssue>,
+}
+
+impl FsckReport {
+    pub fn unrepaired(&self) -> usize {
+        self.issues.iter().filter(|i| !i.repaired).count()
+    }
+}
+
+struct Checker {
+    world_id: Option<Uuid>,
+    repair: bool,
+    issues: Vec<FsckIssue>,
+}
+
+impl Checker {
+    fn report(&mut self, kind: FsckIssueKind, path: &Path, detail: String, repaired: bool) {
+        self.issues.push(FsckIssue {
+            world_id: self.world_id,
+            kind,
+            path: path.display().to_string(),
+            detail,
+            repaired,
+        });
+    }
+}
+
+/// Check every world under the store (or just `only`). With `repair`, fixable issues are
+/// repaired in place and pending WAL records are folded in (compacting the log).
+pub fn check_store(store: &WorldStore, only: Option<Uuid>, repair: bool) -> Result<FsckReport> {
+    let mut report = FsckReport::default();
+    let root = store.worlds_root();
+    for entry in fs::read_dir(&root).with_context(|| format!("read {root:?}"))? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let dir = entry.path();
+        let dir_id = entry
+            .file_name()
+            .to_str()
+            .and_then(|n| Uuid::parse_str(n).ok());
+        if only.is_some() && dir_id != only {
+            continue;
+        }
+        report.worlds_checked += 1;
+        report
+            .issues
+            .extend(check_world(store, &dir, dir_id, repair));
+    }
+    Ok(report)
+}
+
+pub fn check_world(
+    store: &WorldStore,
+    world_dir: &Path,
+    dir_id: Option<Uuid>,
+    repair: bool,
+) -> Vec<FsckIssue> {
+    let mut c = Checker {
+        world_id: dir_id,
+        repair,
+        issues: Vec::new(),
+    };
+
+    match store.read_manifest(world_dir) {
+        Ok(m) => {
+            if Some(m.world_id) != dir_id {
+                c.report(
+                    FsckIssueKind::WorldIdMismatch,
+                    &WorldStore::manifest_path(world_dir),
+                    format!("manifest world_id {} does not match directory", m.world_id),
+                    false,
+                );
+            }
+        }
+        Err(e) => c.report(
+            FsckIssueKind::ManifestUnreadable,
+            &WorldStore::manifest_path(world_dir),
+            format!("{e:#}"),
+            false,
+        ),
+    }
+
+    for sub in WORLD_SUBDIRS {
+        let p = world_dir.join(sub);
+        if !p.is_dir() {
+            let repaired = c.repair && fs::create_dir_all(&p).is_ok();
+            c.report(
+                FsckIssueKind::MissingDir,
+                &p,
+                "missing directory".to_string(),
+                repaired,
+            );
+        }
+    }
+
+    check_wal(&mut c, world_dir);
+    check_chunks(&mut c, world_dir);
+    check_prefab_files(&mut c, world_dir);
+
+    let sim_path = sim::sim_state_path(world_dir);
+    if let Err(e) = sim::load_state(world_dir) {
+        let repaired = c.repair && quarantine(&sim_path).is_ok();
+        c.report(
+            FsckIssueKind::SimStateUnreadable,
+            &sim_path,
+            format!("{e:#}"),
+            repaired,
+        );
+    }
+    // The ledger is never quarantined automatically: it holds balances.
+    if let Err(e) = ledger::load_ledger(world_dir) {
+        c.report(
+            FsckIssueKind::LedgerUnreadable,
+            &ledger::ledger_path(world_dir),
+            format!("{e:#}"),
+            false,
+        );
+    }
+
+    for sub in ["chunks", "snapshots", "wal", "manifest", "assets"] {
+        check_stray_tmp(&mut c, &world_dir.join(sub));
+    }
+
+    c.issues
+}
+
+fn check_wal(c: &mut Checker, world_dir: &Path) {
+    let path = wal::wal_path(world_dir);
+    let scan = match wal::scan(world_dir) {
+        Ok(s) => s,
+        Err(e) => {
+            c.report(FsckIssueKind::WalTruncated, &path, format!("{e:#}"), false);
+            return;
+        }
+    };
+    if !scan.truncated && scan.records.is_empty() {
+        return;
+    }
+    // Recovery replays valid records, drops a torn tail and checkpoints (compacts) the log.
+    let repaired = c.repair && wal::recover(world_dir).is_ok();
+    if scan.truncated {
+        c.report(
+            FsckIssueKind::WalTruncated,
+            &path,
+            format!("damaged record after byte {}", scan.valid_len),
+            repaired,
+        );
+    }
+    if !scan.records.is_empty() {
+        c.report(
+            FsckIssueKind::WalPending,
+            &path,
+            format!("{} record(s) not yet checkpointed", scan.records.len()),
+            repaired,
+        );
+    }
+}
+
+fn check_chunks(c: &mut Checker, world_dir: &Path) {
+    let dir = world_dir.join("chunks");
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        let Some(stem) = name.strip_suffix(".json") else {
+            continue;
+        };
+        let coord = stem
+            .split_once('_')
+            .and_then(|(x, z)| Some((x.parse::<i32>().ok()?, z.parse::<i32>().ok()?)));
+        let Some((x, z)) = coord else {
+            c.report(
+                FsckIssueKind::StrayFile,
+                &path,
+                "not a chunk file name".to_string(),
+                false,
+            );
+            continue;
+        };
+        let coord = owp_protocol::ChunkCoord { x, z };
+
+        let mut file = match chunks::load_chunk(world_dir, coord) {
+            Ok(f) => f,
+            Err(e) => {
+                let repaired = c.repair && quarantine(&path).is_ok();
+                c.report(
+                    FsckIssueKind::ChunkUnreadable,
+                    &path,
+                    format!("{e:#}"),
+                    repaired,
+                );
+                continue;
+            }
+        };
+
+        let mut dirty = false;
+        if file.chunk != coord {
+            c.report(
+                FsckIssueKind::ChunkCoordMismatch,
+                &path,
+                format!("file says ({}, {})", file.chunk.x, file.chunk.z),
+                c.repair,
+            );
+            file.chunk = coord;
+            dirty = true;
+        }
+
+        let journal_ok = file.journal.len() <= chunks::CHUNK_JOURNAL_LEN
+            && file
+                .journal
+                .back()
+                .map(|e| e.version == file.version)
+                .unwrap_or(true)
+            && file
+                .journal
+                .iter()
+                .zip(file.journal.iter().skip(1))
+                .all(|(a, b)| b.version == a.version + 1);
+        if !journal_ok {
+            c.report(
+                FsckIssueKind::ChunkJournalInconsistent,
+                &path,
+                "journal is oversized or not contiguous up to the chunk version".to_string(),
+                c.repair,
+            );
+            // Dropping the journal forces clients onto a full chunk sync.
+            file.journal.clear();
+            dirty = true;
+        } else {
+            let expected = journal_objects(&file);
+            if serde_json::to_value(&expected).ok() != serde_json::to_value(&file.objects).ok() {
+                c.report(
+                    FsckIssueKind::ChunkContentMismatch,
+                    &path,
+                    "objects differ from what the journal recorded".to_string(),
+                    c.repair,
+                );
+                file.objects = expected;
+                dirty = true;
+            }
+        }
+
+        if dirty && c.repair {
+            if let Err(e) = chunks::save_chunk(world_dir, &file) {
+                if let Some(last) = c.issues.last_mut() {
+                    last.repaired = false;
+                    last.detail = format!("{}; repair failed: {e:#}", last.detail);
+                }
+            }
+        }
+    }
+}
+
+/// The objects the chunk's journal says it holds: replayed from nothing when the journal goes
+/// back to version 1, else its last change to each object it mentions applied over the file.
+fn journal_objects(file: &chunks::ChunkFileV1) -> Vec<owp_protocol::WorldObjectV1> {
+    let mut replay = file.clone();
+    if file.journal.front().is_some_and(|e| e.version == 1) {
+        replay.objects.clear();
+    }
+    for entry in &file.journal {
+        replay.apply(entry.change.clone());
+    }
+    replay.objects
+}
+
+/// Files under `assets/prefabs/` that no bundle build picks up: neither a mesh nor the
+/// materials of one. Sync skips `prefabs/`, so nothing serves them. Moved out of `assets/` on
+/// repair.
+fn check_prefab_files(c: &mut Checker, world_dir: &Path) {
+    let dir = prefabs::prefabs_dir(world_dir);
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let used = match (
+            path.file_stem().and_then(|s| s.to_str()),
+            path.extension().and_then(|e| e.to_str()),
+        ) {
+            (Some(_), Some(ext)) if prefabs::MESH_EXTENSIONS.contains(&ext) => true,
+            (Some(stem), Some("json")) => prefabs::MESH_EXTENSIONS
+                .iter()
+                .any(|ext| dir.join(format!("{stem}.{ext}")).exists()),
+            (_, Some("tmp")) => continue,
+            _ => false,
+        };
+        if used || !path.is_file() {
+            continue;
+        }
+        let repaired = c.repair && set_aside(world_dir, &path).is_ok();
+        c.report(
+            FsckIssueKind::OrphanedAsset,
+            &path,
+            "no prefab mesh uses this file".to_string(),
+            repaired,
+        );
+    }
+}
+
+/// Move an orphaned asset to the same place under `<world>/orphaned/`, out of `assets/`.
+fn set_aside(world_dir: &Path, path: &Path) -> Result<PathBuf> {
+    let rel = path
+        .strip_prefix(assets::assets_dir(world_dir))
+        .context("asset outside assets/")?;
+    let dst = world_dir.join("orphaned").join(rel);
+    if let Some(parent) = dst.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("create {parent:?}"))?;
+    }
+    fs::rename(path, &dst).with_context(|| format!("rename {path:?}"))?;
+    Ok(dst)
+}
+
+fn check_stray_tmp(c: &mut Checker, dir: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("tmp") {
+            continue;
+        }
+        let repaired = c.repair && fs::remove_file(&path).is_ok();
+        c.report(
+            FsckIssueKind::StrayFile,
+            &path,
+            "leftover temp file from an interrupted write".to_string(),
+            repaired,
+        );
+    }
+}
+
+/// Log a report at startup without touching anything.
+pub fn warn_issues(report: &FsckReport) {
+    for i in &report.issues {
+        warn!(
+            "fsck: {:?} at {}: {} (run `owp-server fsck --repair`)",
+            i.kind, i.path, i.detail
+        );
+    }
+}
+
+/// Move a damaged file aside (`<name>.corrupt`) so the server starts from defaults.
+fn quarantine(path: &Path) -> Result<PathBuf> {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".corrupt");
+    let dst = path.with_file_name(name);
+    fs::rename(path, &dst).with_context(|| format!("rename {path:?}"))?;
+    Ok(dst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use owp_protocol::{ChunkChangeV1, ChunkCoord, WorldObjectV1};
+
+    fn kinds(issues: &[FsckIssue]) -> Vec<FsckIssueKind> {
+        issues.iter().map(|i| i.kind).collect()
+    }
+
+    #[test]
+    fn detects_and_repairs_chunk_and_wal_damage() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let store = WorldStore::with_root(root.path().to_path_buf()).expect("store");
+        let m = store.create_world("fsck", 7777).expect("create world");
+        let world = store.world_dir(m.world_id);
+
+        let coord = ChunkCoord { x: 1, z: 2 };
+        let mut file = chunks::load_chunk(&world, coord).expect("chunk");
+        file.apply(ChunkChangeV1::Upsert {
+            object: WorldObjectV1 {
+                id: "a".to_string(),
+                kind: "rock".to_string(),
+                position: [40.0, 0.0, 70.0],
+                rotation: [0.0, 0.0, 0.0],
+                scale: [1.0, 1.0, 1.0],
+            },
+        });
+        file.version += 3;
+        chunks::save_chunk(&world, &file).expect("save");
+        fs::write(world.join("chunks").join("0_0.json"), "{not json").expect("corrupt");
+        fs::create_dir_all(wal::wal_dir(&world)).expect("wal dir");
+        fs::write(wal::wal_path(&world), "1\tdeadbeef\t{").expect("torn wal");
+
+        let issues = check_world(&store, &world, Some(m.world_id), false);
+        let k = kinds(&issues);
+        assert!(k.contains(&FsckIssueKind::ChunkJournalInconsistent));
+        assert!(k.contains(&FsckIssueKind::ChunkUnreadable));
+        assert!(k.contains(&FsckIssueKind::WalTruncated));
+        assert!(issues.iter().all(|i| !i.repaired));
+
+        let issues = check_world(&store, &world, Some(m.world_id), true);
+        assert!(issues.iter().all(|i| i.repaired), "{issues:?}");
+        assert!(check_world(&store, &world, Some(m.world_id), false).is_empty());
+        assert!(world.join("chunks").join("0_0.json.corrupt").exists());
+    }
+
+    #[test]
+    fn chunk_objects_are_checked_against_the_journal() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let store = WorldStore::with_root(root.path().to_path_buf()).expect("store");
+        let m = store.create_world("fsck", 7777).expect("create world");
+        let world = store.world_dir(m.world_id);
+
+        let rock = |id: &str| WorldObjectV1 {
+            id: id.to_string(),
+            kind: "rock".to_string(),
+            position: [1.0, 0.0, 1.0],
+            rotation: [0.0, 0.0, 0.0],
+            scale: [1.0, 1.0, 1.0],
+        };
+        let coord = ChunkCoord { x: 0, z: 0 };
+        let mut file = chunks::load_chunk(&world, coord).expect("chunk");
+        file.apply(ChunkChangeV1::Upsert { object: rock("a") });
+        file.apply(ChunkChangeV1::Upsert { object: rock("b") });
+        file.apply(ChunkChangeV1::Remove {
+            object_id: "a".to_string(),
+        });
+        // An object the journal never placed, and one it removed, are both wrong.
+        file.objects = vec![rock("a"), rock("b"), rock("ghost")];
+        chunks::save_chunk(&world, &file).expect("save");
+
+        let issues = check_world(&store, &world, Some(m.world_id), false);
+        assert_eq!(kinds(&issues), [FsckIssueKind::ChunkContentMismatch]);
+        let issues = check_world(&store, &world, Some(m.world_id), true);
+        assert!(issues.iter().all(|i| i.repaired), "{issues:?}");
+        let file = chunks::load_chunk(&world, coord).expect("chunk");
+        let ids: Vec<&str> = file.objects.iter().map(|o| o.id.as_str()).collect();
+        assert_eq!(ids, ["b"]);
+        assert!(check_world(&store, &world, Some(m.world_id), false).is_empty());
+    }
+
+    #[test]
+    fn orphaned_prefab_files_are_moved_aside() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let store = WorldStore::with_root(root.path().to_path_buf()).expect("store");
+        let m = store.create_world("fsck", 7777).expect("create world");
+        let world = store.world_dir(m.world_id);
+        let dir = prefabs::prefabs_dir(&world);
+        fs::create_dir_all(&dir).expect("prefabs dir");
+        fs::write(dir.join("rock.glb"), b"rock").expect("write");
+        fs::write(dir.join("rock.json"), b"{}").expect("write");
+        fs::write(dir.join("tree.json"), b"{}").expect("write");
+
+        let issues = check_world(&store, &world, Some(m.world_id), false);
+        assert_eq!(kinds(&issues), [FsckIssueKind::OrphanedAsset]);
+        assert!(issues[0].path.ends_with("tree.json"));
+        let issues = check_world(&store, &world, Some(m.world_id), true);
+        assert!(issues.iter().all(|i| i.repaired), "{issues:?}");
+        assert!(!dir.join("tree.json").exists());
+        assert!(world.join("orphaned/prefabs/tree.json").exists());
+        assert!(dir.join("rock.json").exists());
+        assert!(check_world(&store, &world, Some(m.world_id), false).is_empty());
+    }
+}
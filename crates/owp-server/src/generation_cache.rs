@@ -0,0 +1,164 @@
+//! Structured outputs of avatar generation, kept under `cache/generation/` so an identical request
+//! (a UI retry, a demo run twice) doesn't run the provider again. Entries are keyed by a hash of
+//! the provider and its model settings, the prompt template and the full prompt, and expire after
+//! `generation_cache_ttl_secs` (assistant config).
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use time::OffsetDateTime;
+use tracing::warn;
+
+use crate::assistant::{AssistantConfig, AssistantProviderId};
+use crate::storage::{write_atomic, WorldStore};
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    #[serde(with = "time::serde::rfc3339")]
+    created_at: OffsetDateTime,
+    output: String,
+}
+
+fn cache_dir(store: &WorldStore) -> PathBuf {
+    store.root_dir().join("cache").join("generation")
+}
+
+/// Hash of everything that decides what a request produces. `template` names the prompt
+/// template and its version, e.g. `avatar/v1`.
+pub fn key(
+    cfg: &AssistantConfig,
+    provider: AssistantProviderId,
+    template: &str,
+    prompt: &str,
+    schema: &str,
+) -> String {
+    let model = match provider {
+        AssistantProviderId::Codex => [
+            cfg.codex_model.as_deref(),
+            cfg.codex_reasoning_effort.as_deref(),
+        ],
+        AssistantProviderId::Claude => [cfg.claude_model.as_deref(), None],
+    };
+    let mut h = Sha256::new();
+    for part in [
+        Some(provider.as_str()),
+        model[0],
+        model[1],
+        Some(template),
+        Some(prompt),
+        Some(schema),
+    ] {
+        // Length-prefixed, so fields can't run into each other.
+        let part = part.unwrap_or("").trim();
+        h.update((part.len() as u64).to_le_bytes());
+        h.update(part.as_bytes());
+    }
+    hex::encode(h.finalize())
+}
+
+/// The output cached under `key`, unless it is older than `ttl_secs`.
+pub fn get(store: &WorldStore, key: &str, ttl_secs: u64) -> Option<String> {
+    if ttl_secs == 0 {
+        return None;
+    }
+    let path = cache_dir(store).join(format!("{key}.json"));
+    let text = std::fs::read_to_string(&path).ok()?;
+    let entry: Entry = match serde_json::from_str(&text) {
+        Ok(entry) => entry,
+        Err(e) => {
+            warn!("dropping unreadable cache entry {path:?}: {e}");
+            let _ = std::fs::remove_file(&path);
+            return None;
+        }
+    };
+    let age = OffsetDateTime::now_utc() - entry.created_at;
+    if age.whole_seconds() >= ttl_secs.try_into().unwrap_or(i64::MAX) {
+        let _ = std::fs::remove_file(&path);
+        return None;
+    }
+    Some(entry.output)
+}
+
+pub fn put(store: &WorldStore, key: &str, output: &str) -> Result<()> {
+    let dir = cache_dir(store);
+    std::fs::create_dir_all(&dir).with_context(|| format!("create {dir:?}"))?;
+    let entry = Entry {
+        created_at: OffsetDateTime::now_utc(),
+        output: output.to_string(),
+    };
+    let json = serde_json::to_string(&entry).context("serialize cache entry")?;
+    write_atomic(&dir.join(format!("{key}.json")), json.as_bytes())?;
+    Ok(())
+}
+
+/// Remove every cached output; returns how many there were.
+pub fn clear(store: &WorldStore) -> Result<usize> {
+    let dir = cache_dir(store);
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e).with_context(|| format!("read {dir:?}")),
+    };
+    let mut removed = 0;
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|e| e == "json") {
+            std::fs::remove_file(&path).with_context(|| format!("remove {path:?}"))?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outputs_are_reused_per_request_until_they_expire() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let store = WorldStore::with_root(root.path().to_path_buf()).expect("store");
+        let cfg = AssistantConfig::default();
+        let codex = AssistantProviderId::Codex;
+        let k = key(&cfg, codex, "avatar/v1", "a wizard", "{}");
+        assert_ne!(k, key(&cfg, codex, "avatar/v1", "a knight", "{}"));
+        assert_ne!(k, key(&cfg, codex, "avatar/v2", "a wizard", "{}"));
+        assert_ne!(
+            k,
+            key(
+                &cfg,
+                AssistantProviderId::Claude,
+                "avatar/v1",
+                "a wizard",
+                "{}"
+            )
+        );
+        let other_model = AssistantConfig {
+            codex_model: Some("other".to_string()),
+            ..AssistantConfig::default()
+        };
+        assert_ne!(k, key(&other_model, codex, "avatar/v1", "a wizard", "{}"));
+
+        assert_eq!(get(&store, &k, 3600), None);
+        put(&store, &k, r#"{"name":"Wizard"}"#).expect("put");
+        assert_eq!(
+            get(&store, &k, 3600).as_deref(),
+            Some(r#"{"name":"Wizard"}"#)
+        );
+        assert_eq!(get(&store, &k, 0), None, "a zero ttl turns caching off");
+
+        let stale = Entry {
+            created_at: OffsetDateTime::now_utc() - time::Duration::hours(2),
+            output: "old".to_string(),
+        };
+        let path = cache_dir(&store).join(format!("{k}.json"));
+        std::fs::write(&path, serde_json::to_string(&stale).expect("json")).expect("write");
+        assert_eq!(get(&store, &k, 3600), None);
+        assert!(!path.exists(), "expired entries are removed");
+
+        put(&store, &k, "again").expect("put");
+        assert_eq!(clear(&store).expect("clear"), 1);
+        assert_eq!(get(&store, &k, 3600), None);
+    }
+}
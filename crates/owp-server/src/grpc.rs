@@ -0,0 +1,762 @@
+// tonic handlers return `Status` by value, so helpers feeding them do too.
+#![allow(clippy::result_large_err)]
+
+use anyhow::{Context, Result};
+use axum::body::Body;
+use axum::http::{self, header, HeaderMap, StatusCode};
+use axum::Router;
+use owp_protocol::{ListingTrust, WorldDirectoryEntry, WorldManifestV1};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use tonic::metadata::MetadataMap;
+use tonic::{Request, Response, Status};
+use tower::ServiceExt;
+use tracing::info;
+
+use crate::assistant::AssistantStatus;
+use crate::health::{HealthRegistry, ServiceHealth, ServiceState};
+use crate::world_summary::WorldSummary;
+
+/// Largest REST response the facade will relay.
+const MAX_RESPONSE_BYTES: usize = 16 * 1024 * 1024;
+
+/// Messages of `owp.admin.v1`; `proto/owp_admin.proto` is the same schema for other languages.
+pub mod pb {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum ListingTrust {
+        Unspecified = 0,
+        Verified = 1,
+        UnknownPublisher = 2,
+        Unsigned = 3,
+        InvalidSignature = 4,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum JobState {
+        Unspecified = 0,
+        Starting = 1,
+        Running = 2,
+        Failed = 3,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Empty {}
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct WorldRef {
+        #[prost(string, tag = "1")]
+        pub world_id: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct CreateWorldRequest {
+        #[prost(string, tag = "1")]
+        pub name: String,
+        #[prost(uint32, tag = "2")]
+        pub game_port: u32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct World {
+        #[prost(string, tag = "1")]
+        pub world_id: String,
+        #[prost(string, tag = "2")]
+        pub name: String,
+        #[prost(string, tag = "3")]
+        pub endpoint: String,
+        #[prost(uint32, tag = "4")]
+        pub port: u32,
+        #[prost(string, optional, tag = "5")]
+        pub token_mint: Option<String>,
+        #[prost(string, optional, tag = "6")]
+        pub dbc_pool: Option<String>,
+        #[prost(string, optional, tag = "7")]
+        pub world_pubkey: Option<String>,
+        #[prost(string, optional, tag = "8")]
+        pub last_seen: Option<String>,
+        #[prost(enumeration = "ListingTrust", tag = "9")]
+        pub trust: i32,
+        #[prost(string, optional, tag = "10")]
+        pub region: Option<String>,
+        #[prost(uint32, optional, tag = "11")]
+        pub rtt_ms: Option<u32>,
+        #[prost(string, repeated, tag = "12")]
+        pub tags: Vec<String>,
+        #[prost(string, optional, tag = "13")]
+        pub icon_sha256: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct WorldList {
+        #[prost(message, repeated, tag = "1")]
+        pub worlds: Vec<World>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct WorldToken {
+        #[prost(string, tag = "1")]
+        pub network: String,
+        #[prost(string, tag = "2")]
+        pub mint: String,
+        #[prost(string, optional, tag = "3")]
+        pub dbc_pool: Option<String>,
+        #[prost(string, repeated, tag = "4")]
+        pub tx_signatures: Vec<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct WorldSimulation {
+        #[prost(bool, tag = "1")]
+        pub run_while_empty: bool,
+        #[prost(uint32, tag = "2")]
+        pub tick_hz: u32,
+        #[prost(uint64, tag = "3")]
+        pub max_catchup_secs: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct WorldManifest {
+        #[prost(string, tag = "1")]
+        pub protocol_version: String,
+        #[prost(string, tag = "2")]
+        pub world_id: String,
+        #[prost(string, tag = "3")]
+        pub name: String,
+        #[prost(string, tag = "4")]
+        pub created_at: String,
+        #[prost(string, optional, tag = "5")]
+        pub world_authority_pubkey: Option<String>,
+        #[prost(uint32, tag = "6")]
+        pub game_port: u32,
+        #[prost(uint32, optional, tag = "7")]
+        pub asset_port: Option<u32>,
+        #[prost(message, optional, tag = "8")]
+        pub token: Option<WorldToken>,
+        #[prost(message, optional, tag = "9")]
+        pub simulation: Option<WorldSimulation>,
+        #[prost(string, optional, tag = "10")]
+        pub region: Option<String>,
+        #[prost(string, repeated, tag = "11")]
+        pub tags: Vec<String>,
+        #[prost(string, optional, tag = "12")]
+        pub icon_sha256: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct DiscoveryQuery {
+        #[prost(float, optional, tag = "1")]
+        pub min_score: Option<f32>,
+        #[prost(uint32, optional, tag = "2")]
+        pub max_reports: Option<u32>,
+        #[prost(string, tag = "3")]
+        pub sort: String,
+        #[prost(string, tag = "4")]
+        pub group: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct WorldSummary {
+        #[prost(string, tag = "1")]
+        pub world_id: String,
+        #[prost(string, tag = "2")]
+        pub name: String,
+        #[prost(string, tag = "3")]
+        pub connect: String,
+        #[prost(string, optional, tag = "4")]
+        pub region: Option<String>,
+        #[prost(string, repeated, tag = "5")]
+        pub tags: Vec<String>,
+        #[prost(string, optional, tag = "6")]
+        pub icon_sha256: Option<String>,
+        #[prost(bool, optional, tag = "7")]
+        pub online: Option<bool>,
+        #[prost(uint32, optional, tag = "8")]
+        pub player_count: Option<u32>,
+        #[prost(uint32, optional, tag = "9")]
+        pub rtt_ms: Option<u32>,
+        #[prost(float, tag = "10")]
+        pub score: f32,
+        #[prost(string, optional, tag = "11")]
+        pub token_mint: Option<String>,
+        #[prost(enumeration = "ListingTrust", tag = "12")]
+        pub trust: i32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct WorldSummaryList {
+        #[prost(message, repeated, tag = "1")]
+        pub worlds: Vec<WorldSummary>,
+        #[prost(string, tag = "2")]
+        pub etag: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct AssistantProvider {
+        #[prost(string, tag = "1")]
+        pub id: String,
+        #[prost(bool, tag = "2")]
+        pub installed: bool,
+        #[prost(string, optional, tag = "3")]
+        pub note: Option<String>,
+        #[prost(bool, tag = "4")]
+        pub healthy: bool,
+        #[prost(uint32, tag = "5")]
+        pub consecutive_failures: u32,
+        #[prost(string, optional, tag = "6")]
+        pub failure: Option<String>,
+        #[prost(uint64, optional, tag = "7")]
+        pub last_latency_ms: Option<u64>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct AssistantStatus {
+        #[prost(string, optional, tag = "1")]
+        pub provider: Option<String>,
+        #[prost(message, repeated, tag = "2")]
+        pub providers: Vec<AssistantProvider>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct AssistantChatRequest {
+        #[prost(string, tag = "1")]
+        pub message: String,
+        #[prost(string, optional, tag = "2")]
+        pub profile_id: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct AssistantChatReply {
+        #[prost(string, tag = "1")]
+        pub reply: String,
+        #[prost(string, optional, tag = "2")]
+        pub avatar_json: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Job {
+        #[prost(string, tag = "1")]
+        pub name: String,
+        #[prost(enumeration = "JobState", tag = "2")]
+        pub state: i32,
+        #[prost(string, optional, tag = "3")]
+        pub detail: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct JobList {
+        #[prost(bool, tag = "1")]
+        pub ok: bool,
+        #[prost(message, repeated, tag = "2")]
+        pub jobs: Vec<Job>,
+    }
+
+    include!(concat!(env!("OUT_DIR"), "/owp.admin.v1.Admin.rs"));
+}
+
+fn trust(t: Option<ListingTrust>) -> i32 {
+    let t = match t {
+        None => pb::ListingTrust::Unspecified,
+        Some(ListingTrust::Verified) => pb::ListingTrust::Verified,
+        Some(ListingTrust::UnknownPublisher) => pb::ListingTrust::UnknownPublisher,
+        Some(ListingTrust::Unsigned) => pb::ListingTrust::Unsigned,
+        Some(ListingTrust::InvalidSignature) => pb::ListingTrust::InvalidSignature,
+    };
+    t as i32
+}
+
+impl From<WorldDirectoryEntry> for pb::World {
+    fn from(w: WorldDirectoryEntry) -> Self {
+        Self {
+            world_id: w.world_id.to_string(),
+            name: w.name,
+            endpoint: w.endpoint,
+            port: w.port.into(),
+            token_mint: w.token_mint,
+            dbc_pool: w.dbc_pool,
+            world_pubkey: w.world_pubkey,
+            last_seen: w.last_seen,
+            trust: trust(w.trust),
+            region: w.region,
+            rtt_ms: w.rtt_ms,
+            tags: w.tags,
+            icon_sha256: w.icon_sha256,
+        }
+    }
+}
+
+impl From<WorldManifestV1> for pb::WorldManifest {
+    fn from(m: WorldManifestV1) -> Self {
+        Self {
+            protocol_version: m.protocol_version,
+            world_id: m.world_id.to_string(),
+            name: m.name,
+            created_at: m
+                .created_at
+                .format(&time::format_description::well_known::Rfc3339)
+                .unwrap_or_default(),
+            world_authority_pubkey: m.world_authority_pubkey,
+            game_port: m.ports.game_port.into(),
+            asset_port: m.ports.asset_port.map(u32::from),
+            token: m.token.map(|t| pb::WorldToken {
+                network: t.network,
+                mint: t.mint,
+                dbc_pool: t.dbc_pool,
+                tx_signatures: t.tx_signatures,
+            }),
+            simulation: Some(pb::WorldSimulation {
+                run_while_empty: m.simulation.run_while_empty,
+                tick_hz: m.simulation.tick_hz,
+                max_catchup_secs: m.simulation.max_catchup_secs,
+            }),
+            region: m.region,
+            tags: m.tags,
+            icon_sha256: m.icon_sha256,
+        }
+    }
+}
+
+impl From<WorldSummary> for pb::WorldSummary {
+    fn from(s: WorldSummary) -> Self {
+        Self {
+            world_id: s.world_id.to_string(),
+            name: s.name,
+            connect: s.connect,
+            region: s.region,
+            tags: s.tags,
+            icon_sha256: s.icon_sha256,
+            online: s.online,
+            player_count: s.player_count,
+            rtt_ms: s.rtt_ms,
+            score: s.score,
+            token_mint: s.token_mint,
+            trust: trust(s.trust),
+        }
+    }
+}
+
+impl From<AssistantStatus> for pb::AssistantStatus {
+    fn from(s: AssistantStatus) -> Self {
+        Self {
+            provider: s.provider,
+            providers: s
+                .providers
+                .into_iter()
+                .map(|p| pb::AssistantProvider {
+                    id: p.id,
+                    installed: p.installed,
+                    note: p.note,
+                    healthy: p.healthy,
+                    consecutive_failures: p.consecutive_failures,
+                    failure: p.failure,
+                    last_latency_ms: p.last_latency_ms,
+                })
+                .collect(),
+        }
+    }
+}
+
+fn job(name: String, h: ServiceHealth) -> pb::Job {
+    let state = match h.state {
+        ServiceState::Starting => pb::JobState::Starting,
+        ServiceState::Running => pb::JobState::Running,
+        ServiceState::Failed => pb::JobState::Failed,
+    };
+    pb::Job {
+        name,
+        state: state as i32,
+        detail: h.detail,
+    }
+}
+
+fn status_for(code: StatusCode) -> Status {
+    let msg = format!("admin API answered {code}");
+    match code {
+        StatusCode::BAD_REQUEST => Status::invalid_argument(msg),
+        StatusCode::UNAUTHORIZED => Status::unauthenticated(msg),
+        StatusCode::FORBIDDEN => Status::permission_denied(msg),
+        StatusCode::NOT_FOUND => Status::not_found(msg),
+        StatusCode::CONFLICT => Status::already_exists(msg),
+        StatusCode::PRECONDITION_FAILED => Status::failed_precondition(msg),
+        StatusCode::PAYLOAD_TOO_LARGE => Status::resource_exhausted(msg),
+        StatusCode::SERVICE_UNAVAILABLE => Status::unavailable(msg),
+        _ => Status::internal(msg),
+    }
+}
+
+/// Query values are passed through to the REST query string as-is.
+fn query_word(name: &str, v: &str) -> Result<(), Status> {
+    if v.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Ok(());
+    }
+    Err(Status::invalid_argument(format!("invalid {name}")))
+}
+
+struct Reply {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: axum::body::Bytes,
+}
+
+impl Reply {
+    fn json<T: DeserializeOwned>(&self) -> Result<T, Status> {
+        if !self.status.is_success() {
+            return Err(status_for(self.status));
+        }
+        serde_json::from_slice(&self.body)
+            .map_err(|e| Status::internal(format!("unexpected admin API response: {e}")))
+    }
+}
+
+/// Serves `owp.admin.v1.Admin` by running each call through the admin REST router, so both
+/// APIs share handlers, validation and auth.
+#[derive(Clone)]
+pub struct AdminFacade {
+    router: Router,
+}
+
+impl AdminFacade {
+    pub fn new(router: Router) -> Self {
+        Self { router }
+    }
+
+    async fn call(
+        &self,
+        metadata: &MetadataMap,
+        method: http::Method,
+        uri: &str,
+        body: Option<serde_json::Value>,
+    ) -> Result<Reply, Status> {
+        let mut req = http::Request::builder().method(method).uri(uri);
+        if let Some(auth) = metadata
+            .get(header::AUTHORIZATION.as_str())
+            .and_then(|v| v.to_str().ok())
+        {
+            req = req.header(header::AUTHORIZATION, auth);
+        }
+        let body = match body {
+            Some(v) => {
+                req = req.header(header::CONTENT_TYPE, "application/json");
+                Body::from(v.to_string())
+            }
+            None => Body::empty(),
+        };
+        let req = req
+            .body(body)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let res = self
+            .router
+            .clone()
+            .oneshot(req)
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+        let (parts, body) = res.into_parts();
+        let body = axum::body::to_bytes(body, MAX_RESPONSE_BYTES)
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+        Ok(Reply {
+            status: parts.status,
+            headers: parts.headers,
+            body,
+        })
+    }
+
+    async fn get(&self, metadata: &MetadataMap, uri: &str) -> Result<Reply, Status> {
+        self.call(metadata, http::Method::GET, uri, None).await
+    }
+
+    async fn post(
+        &self,
+        metadata: &MetadataMap,
+        uri: &str,
+        body: serde_json::Value,
+    ) -> Result<Reply, Status> {
+        self.call(metadata, http::Method::POST, uri, Some(body))
+            .await
+    }
+}
+
+#[tonic::async_trait]
+impl pb::admin_server::Admin for AdminFacade {
+    async fn list_worlds(
+        &self,
+        req: Request<pb::Empty>,
+    ) -> Result<Response<pb::WorldList>, Status> {
+        let worlds: Vec<WorldDirectoryEntry> = self.get(req.metadata(), "/worlds").await?.json()?;
+        Ok(Response::new(pb::WorldList {
+            worlds: worlds.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    async fn create_world(
+        &self,
+        req: Request<pb::CreateWorldRequest>,
+    ) -> Result<Response<pb::WorldManifest>, Status> {
+        let game_port = match req.get_ref().game_port {
+            0 => 7777,
+            p => {
+                u16::try_from(p).map_err(|_| Status::invalid_argument("game_port out of range"))?
+            }
+        };
+        let body = serde_json::json!({ "name": req.get_ref().name, "game_port": game_port });
+        let manifest: WorldManifestV1 = self.post(req.metadata(), "/worlds", body).await?.json()?;
+        Ok(Response::new(manifest.into()))
+    }
+
+    async fn get_manifest(
+        &self,
+        req: Request<pb::WorldRef>,
+    ) -> Result<Response<pb::WorldManifest>, Status> {
+        let world_id = uuid::Uuid::parse_str(&req.get_ref().world_id)
+            .map_err(|_| Status::invalid_argument("invalid world_id"))?;
+        let uri = format!("/worlds/{world_id}/manifest");
+        let manifest: WorldManifestV1 = self.get(req.metadata(), &uri).await?.json()?;
+        Ok(Response::new(manifest.into()))
+    }
+
+    async fn list_discovery_worlds(
+        &self,
+        req: Request<pb::DiscoveryQuery>,
+    ) -> Result<Response<pb::WorldList>, Status> {
+        let q = req.get_ref();
+        query_word("sort", &q.sort)?;
+        query_word("group", &q.group)?;
+        let mut params = vec![];
+        if let Some(v) = q.min_score {
+            params.push(format!("min_score={v}"));
+        }
+        if let Some(v) = q.max_reports {
+            params.push(format!("max_reports={v}"));
+        }
+        if !q.sort.is_empty() {
+            params.push(format!("sort={}", q.sort));
+        }
+        if !q.group.is_empty() {
+            params.push(format!("group={}", q.group));
+        }
+        let uri = format!("/discovery/worlds?{}", params.join("&"));
+        let worlds: Vec<WorldDirectoryEntry> = self.get(req.metadata(), &uri).await?.json()?;
+        Ok(Response::new(pb::WorldList {
+            worlds: worlds.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    async fn get_world_summary(
+        &self,
+        req: Request<pb::Empty>,
+    ) -> Result<Response<pb::WorldSummaryList>, Status> {
+        let reply = self
+            .get(req.metadata(), "/discovery/worlds/summary")
+            .await?;
+        let worlds: Vec<WorldSummary> = reply.json()?;
+        let etag = reply
+            .headers
+            .get(header::ETAG)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        Ok(Response::new(pb::WorldSummaryList {
+            worlds: worlds.into_iter().map(Into::into).collect(),
+            etag,
+        }))
+    }
+
+    async fn get_assistant_status(
+        &self,
+        req: Request<pb::Empty>,
+    ) -> Result<Response<pb::AssistantStatus>, Status> {
+        let status: AssistantStatus = self
+            .get(req.metadata(), "/assistant/status")
+            .await?
+            .json()?;
+        Ok(Response::new(status.into()))
+    }
+
+    async fn assistant_chat(
+        &self,
+        req: Request<pb::AssistantChatRequest>,
+    ) -> Result<Response<pb::AssistantChatReply>, Status> {
+        #[derive(Deserialize)]
+        struct ChatReply {
+            reply: String,
+            #[serde(default)]
+            avatar: Option<serde_json::Value>,
+        }
+        let body = serde_json::json!({
+            "message": req.get_ref().message,
+            "profile_id": req.get_ref().profile_id,
+        });
+        let out: ChatReply = self
+            .post(req.metadata(), "/assistant/chat", body)
+            .await?
+            .json()?;
+        Ok(Response::new(pb::AssistantChatReply {
+            reply: out.reply,
+            avatar_json: out.avatar.map(|a| a.to_string()),
+        }))
+    }
+
+    async fn list_jobs(&self, req: Request<pb::Empty>) -> Result<Response<pb::JobList>, Status> {
+        #[derive(Deserialize)]
+        struct Services {
+            ok: bool,
+            services: BTreeMap<String, ServiceHealth>,
+        }
+        let mut reply = self.get(req.metadata(), "/health/services").await?;
+        // 503 only means some job isn't running; the body is the same.
+        if reply.status == StatusCode::SERVICE_UNAVAILABLE {
+            reply.status = StatusCode::OK;
+        }
+        let s: Services = reply.json()?;
+        Ok(Response::new(pb::JobList {
+            ok: s.ok,
+            jobs: s.services.into_iter().map(|(n, h)| job(n, h)).collect(),
+        }))
+    }
+}
+
+/// Serve the gRPC facade of `router` on `listen` until the process exits.
+pub async fn serve(listen: String, router: Router, services: HealthRegistry) -> Result<()> {
+    services.set("grpc", ServiceState::Starting, None);
+    let addr: SocketAddr = listen.parse().context("parse grpc listen addr")?;
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    let incoming = tonic::transport::server::TcpIncoming::from_listener(listener, true, None)
+        .map_err(|e| anyhow::anyhow!("grpc listener: {e}"))?;
+    info!("OWP admin gRPC listening on {addr}");
+    services.set(
+        "grpc",
+        ServiceState::Running,
+        Some(format!("grpc://{addr}")),
+    );
+    tonic::transport::Server::builder()
+        .add_service(pb::admin_server::AdminServer::new(AdminFacade::new(router)))
+        .serve_with_incoming(incoming)
+        .await
+        .context("grpc server")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::pb::admin_server::Admin;
+    use super::*;
+    use crate::config::LiveConfig;
+    use crate::pairing::Pairing;
+    use crate::presence::Roster;
+    use crate::storage::WorldStore;
+    use crate::web_admin::{self, AuthMode, DiscoveryConfig};
+
+    fn authed<T>(msg: T) -> Request<T> {
+        let mut req = Request::new(msg);
+        req.metadata_mut()
+            .insert("authorization", "Bearer secret".parse().expect("metadata"));
+        req
+    }
+
+    #[tokio::test]
+    async fn facade_mirrors_the_rest_api() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let store = WorldStore::with_root(root.path().to_path_buf()).expect("store");
+        let health = HealthRegistry::default();
+        health.set("scheduler", ServiceState::Running, None);
+        let router = web_admin::router(
+            store.clone(),
+            AuthMode::BearerToken("secret".to_string()),
+            DiscoveryConfig {
+                solana_rpc_url: None,
+                registry_program_id: None,
+                directory_urls: vec![],
+                trusted_directory_keys: vec![],
+                probe_liveness: false,
+                proxy: None,
+            },
+            health,
+            LiveConfig::load(&store).expect("config"),
+            Roster::default(),
+            Pairing::load(&store).expect("pairing"),
+            crate::asset_token::AssetTokenKey::load_or_create(&store).expect("asset key"),
+            crate::quota::Quotas::default(),
+        );
+        let facade = AdminFacade::new(router);
+
+        let err = facade
+            .list_worlds(Request::new(pb::Empty {}))
+            .await
+            .expect_err("no token");
+        assert_eq!(err.code(), tonic::Code::Unauthenticated);
+
+        let created = facade
+            .create_world(authed(pb::CreateWorldRequest {
+                name: "Harbor".to_string(),
+                game_port: 0,
+            }))
+            .await
+            .expect("create")
+            .into_inner();
+        assert_eq!(created.game_port, 7777);
+        let listed = facade
+            .list_worlds(authed(pb::Empty {}))
+            .await
+            .expect("list")
+            .into_inner();
+        assert_eq!(listed.worlds.len(), 1);
+        assert_eq!(listed.worlds[0].world_id, created.world_id);
+        let manifest = facade
+            .get_manifest(authed(pb::WorldRef {
+                world_id: created.world_id.clone(),
+            }))
+            .await
+            .expect("manifest")
+            .into_inner();
+        assert_eq!(manifest.name, "Harbor");
+
+        let err = facade
+            .get_manifest(authed(pb::WorldRef {
+                world_id: uuid::Uuid::new_v4().to_string(),
+            }))
+            .await
+            .expect_err("unknown world");
+        assert_eq!(err.code(), tonic::Code::NotFound);
+        let err = facade
+            .list_discovery_worlds(authed(pb::DiscoveryQuery {
+                sort: "score&x=1".to_string(),
+                ..Default::default()
+            }))
+            .await
+            .expect_err("bad sort");
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+        let err = facade
+            .get_world_summary(authed(pb::Empty {}))
+            .await
+            .expect_err("no discovery sources");
+        assert_eq!(err.code(), tonic::Code::FailedPrecondition);
+
+        let jobs = facade
+            .list_jobs(authed(pb::Empty {}))
+            .await
+            .expect("jobs")
+            .into_inner();
+        assert!(jobs.ok);
+        assert_eq!(jobs.jobs[0].state, pb::JobState::Running as i32);
+    }
+
+    #[test]
+    fn proto_file_declares_every_rpc() {
+        let proto = include_str!("../proto/owp_admin.proto");
+        for rpc in [
+            "ListWorlds",
+            "CreateWorld",
+            "GetManifest",
+            "ListDiscoveryWorlds",
+            "GetWorldSummary",
+            "GetAssistantStatus",
+            "AssistantChat",
+            "ListJobs",
+        ] {
+            assert!(proto.contains(&format!("rpc {rpc}(")), "{rpc} missing");
+        }
+    }
+}
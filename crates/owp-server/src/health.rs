@@ -0,0 +1,44 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ServiceState {
+    Starting,
+    Running,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServiceHealth {
+    pub state: ServiceState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// Shared view of every sub-service running in this process (admin API, game servers,
+/// background jobs), reported by `GET /health/services`.
+#[derive(Debug, Clone, Default)]
+pub struct HealthRegistry {
+    inner: Arc<RwLock<BTreeMap<String, ServiceHealth>>>,
+}
+
+impl HealthRegistry {
+    pub fn set(&self, name: impl Into<String>, state: ServiceState, detail: Option<String>) {
+        let mut map = self.inner.write().unwrap_or_else(|e| e.into_inner());
+        map.insert(name.into(), ServiceHealth { state, detail });
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, ServiceHealth> {
+        self.inner.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn all_running(&self) -> bool {
+        self.inner
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+            .all(|s| s.state == ServiceState::Running)
+    }
+}
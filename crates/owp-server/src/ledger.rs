@@ -0,0 +1,112 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::storage::write_atomic;
+
+/// Per-profile balance + inventory inside one world.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LedgerAccount {
+    #[serde(default)]
+    pub balance: i64,
+    #[serde(default)]
+    pub inventory: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LedgerV1 {
+    /// Last world WAL sequence number reflected in this file (see `wal.rs`).
+    #[serde(default)]
+    pub applied_seq: u64,
+    #[serde(default)]
+    pub accounts: BTreeMap<String, LedgerAccount>,
+}
+
+/// A change refused because it would take a balance or item count below zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Insufficient {
+    Balance,
+    Items,
+}
+
+impl fmt::Display for Insufficient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Insufficient::Balance => f.write_str("insufficient balance"),
+            Insufficient::Items => f.write_str("insufficient items"),
+        }
+    }
+}
+
+impl std::error::Error for Insufficient {}
+
+impl LedgerV1 {
+    pub fn check_currency(&self, profile_id: &str, delta: i64) -> Result<()> {
+        let balance = self
+            .accounts
+            .get(profile_id)
+            .map(|a| a.balance)
+            .unwrap_or(0);
+        match balance.checked_add(delta) {
+            Some(v) if v >= 0 => Ok(()),
+            _ => Err(Insufficient::Balance.into()),
+        }
+    }
+
+    pub fn check_inventory(&self, profile_id: &str, item: &str, delta: i64) -> Result<()> {
+        let count = self
+            .accounts
+            .get(profile_id)
+            .and_then(|a| a.inventory.get(item))
+            .copied()
+            .unwrap_or(0);
+        match (count as i64).checked_add(delta) {
+            Some(v) if v >= 0 => Ok(()),
+            _ => Err(Insufficient::Items.into()),
+        }
+    }
+
+    pub fn apply_currency(&mut self, profile_id: &str, delta: i64) {
+        let acc = self.accounts.entry(profile_id.to_string()).or_default();
+        acc.balance = acc.balance.saturating_add(delta).max(0);
+    }
+
+    pub fn apply_inventory(&mut self, profile_id: &str, item: &str, delta: i64) {
+        let acc = self.accounts.entry(profile_id.to_string()).or_default();
+        let count = acc.inventory.get(item).copied().unwrap_or(0) as i64;
+        let next = count.saturating_add(delta).max(0) as u64;
+        if next == 0 {
+            acc.inventory.remove(item);
+        } else {
+            acc.inventory.insert(item.to_string(), next);
+        }
+    }
+}
+
+pub fn ledger_path(world_dir: &Path) -> PathBuf {
+    world_dir.join("snapshots").join("ledger.json")
+}
+
+pub fn load_ledger(world_dir: &Path) -> Result<LedgerV1> {
+    let path = ledger_path(world_dir);
+    if !path.exists() {
+        return Ok(LedgerV1::default());
+    }
+    let data = fs::read_to_string(&path).with_context(|| format!("read {path:?}"))?;
+    let ledger: LedgerV1 =
+        serde_json::from_str(&data).with_context(|| format!("parse {path:?}"))?;
+    Ok(ledger)
+}
+
+pub fn save_ledger(world_dir: &Path, ledger: &LedgerV1) -> Result<()> {
+    let path = ledger_path(world_dir);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("create {parent:?}"))?;
+    }
+    let json = serde_json::to_string_pretty(ledger).context("serialize ledger")?;
+    write_atomic(&path, format!("{json}\n").as_bytes())?;
+    Ok(())
+}
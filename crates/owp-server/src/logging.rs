@@ -0,0 +1,280 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use time::OffsetDateTime;
+use tracing::field::{Field, Visit};
+use tracing::{info, Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context as LayerContext, Layer, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+struct LogControl {
+    handle: reload::Handle<EnvFilter, Registry>,
+    /// Directive string of the filter currently applied.
+    current: Mutex<String>,
+    /// Bumped on every change so a stale revert timer doesn't undo a newer setting.
+    generation: AtomicU64,
+}
+
+static LOG_CONTROL: OnceLock<LogControl> = OnceLock::new();
+
+/// How many of the latest log lines are kept in memory, for `GET /logs/recent` and crash
+/// reports.
+const RECENT_CAPACITY: usize = 5000;
+
+static RECENT: Mutex<VecDeque<LogLine>> = Mutex::new(VecDeque::new());
+
+/// One event that passed the active filter.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogLine {
+    #[serde(with = "time::serde::rfc3339")]
+    pub at: OffsetDateTime,
+    pub level: String,
+    pub target: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogLevelStatus {
+    pub filter: String,
+}
+
+fn startup_filter() -> EnvFilter {
+    EnvFilter::from_default_env().add_directive("info".parse().unwrap())
+}
+
+/// Install the global subscriber with a reloadable filter.
+pub fn init() {
+    let filter = startup_filter();
+    let current = filter.to_string();
+    let (layer, handle) = reload::Layer::new(filter);
+    tracing_subscriber::registry()
+        .with(layer)
+        .with(tracing_subscriber::fmt::layer())
+        .with(RecentLayer)
+        .init();
+    let _ = LOG_CONTROL.set(LogControl {
+        handle,
+        current: Mutex::new(current),
+        generation: AtomicU64::new(0),
+    });
+}
+
+/// Renders an event's fields the way the fmt layer does: the message, then `key=value` pairs.
+#[derive(Default)]
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        let sep = if self.0.is_empty() { "" } else { " " };
+        if field.name() == "message" {
+            let _ = write!(self.0, "{sep}{value:?}");
+        } else {
+            let _ = write!(self.0, "{sep}{}={value:?}", field.name());
+        }
+    }
+}
+
+/// Keeps the latest `RECENT_CAPACITY` events in `RECENT`.
+struct RecentLayer;
+
+impl<S: Subscriber> Layer<S> for RecentLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: LayerContext<'_, S>) {
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        let meta = event.metadata();
+        remember(LogLine {
+            at: OffsetDateTime::now_utc(),
+            level: meta.level().to_string(),
+            target: meta.target().to_string(),
+            message: visitor.0,
+        });
+    }
+}
+
+fn remember(line: LogLine) {
+    let mut recent = RECENT.lock().unwrap_or_else(|e| e.into_inner());
+    if recent.len() == RECENT_CAPACITY {
+        recent.pop_front();
+    }
+    recent.push_back(line);
+}
+
+/// Which of the kept log lines to return.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RecentQuery {
+    /// This level and more severe ones (`warn` also returns `error`).
+    #[serde(default)]
+    pub level: Option<String>,
+    /// Module the event came from: a full target (`owp_server::tcp_game`) or one of its path
+    /// segments (`tcp_game`).
+    #[serde(default)]
+    pub module: Option<String>,
+    /// Only the latest this many matching lines.
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+fn from_module(target: &str, module: &str) -> bool {
+    target == module
+        || target
+            .strip_prefix(module)
+            .is_some_and(|rest| rest.starts_with("::"))
+        || target.split("::").any(|segment| segment == module)
+}
+
+fn select(lines: &VecDeque<LogLine>, q: &RecentQuery) -> Result<Vec<LogLine>> {
+    let level = match q.level.as_deref() {
+        Some(l) => Some(
+            l.parse::<Level>()
+                .map_err(|_| anyhow::anyhow!("invalid level {l:?}"))?,
+        ),
+        None => None,
+    };
+    let mut out: Vec<LogLine> = lines
+        .iter()
+        .filter(|line| {
+            let severe =
+                level.is_none_or(|want| line.level.parse::<Level>().is_ok_and(|l| l <= want));
+            severe
+                && q.module
+                    .as_deref()
+                    .is_none_or(|m| from_module(&line.target, m))
+        })
+        .cloned()
+        .collect();
+    if let Some(limit) = q.limit {
+        out.drain(..out.len().saturating_sub(limit));
+    }
+    Ok(out)
+}
+
+/// The kept log lines `q` asks for, oldest first.
+pub fn recent(q: &RecentQuery) -> Result<Vec<LogLine>> {
+    let recent = RECENT.lock().unwrap_or_else(|e| e.into_inner());
+    select(&recent, q)
+}
+
+fn control() -> Result<&'static LogControl> {
+    LOG_CONTROL.get().context("logging not initialized")
+}
+
+pub fn status() -> Result<LogLevelStatus> {
+    let c = control()?;
+    let filter = c.current.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    Ok(LogLevelStatus { filter })
+}
+
+fn apply(c: &LogControl, filter: &str) -> Result<()> {
+    let parsed =
+        EnvFilter::try_new(filter).with_context(|| format!("invalid filter {filter:?}"))?;
+    c.handle.reload(parsed).context("reload log filter")?;
+    *c.current.lock().unwrap_or_else(|e| e.into_inner()) = filter.to_string();
+    Ok(())
+}
+
+/// Replace the active `EnvFilter` (e.g. `info,owp_server::tcp_game=trace`). With `ttl`, the
+/// previous filter comes back after that long unless something else changed it meanwhile.
+pub fn set_filter(filter: &str, ttl: Option<Duration>) -> Result<LogLevelStatus> {
+    let c = control()?;
+    let previous = status()?.filter;
+    apply(c, filter)?;
+    let generation = c.generation.fetch_add(1, Ordering::SeqCst) + 1;
+    info!("log filter set to {filter:?}");
+
+    if let Some(ttl) = ttl {
+        tokio::spawn(async move {
+            tokio::time::sleep(ttl).await;
+            if c.generation.load(Ordering::SeqCst) == generation && apply(c, &previous).is_ok() {
+                info!("log filter reverted to {previous:?}");
+            }
+        });
+    }
+    status()
+}
+
+/// Client side of `owp-server log-level`: talk to a running admin API.
+pub async fn remote(
+    admin_url: &str,
+    token: Option<&str>,
+    filter: Option<&str>,
+    ttl_secs: Option<u64>,
+) -> Result<LogLevelStatus> {
+    let url = format!("{}/admin/log-level", admin_url.trim_end_matches('/'));
+    let client = reqwest::Client::new();
+    let mut req = match filter {
+        Some(f) => client
+            .put(&url)
+            .json(&serde_json::json!({ "filter": f, "ttl_secs": ttl_secs })),
+        None => client.get(&url),
+    };
+    if let Some(t) = token {
+        req = req.bearer_auth(t);
+    }
+    let resp = req.send().await.with_context(|| format!("request {url}"))?;
+    let status = resp.status();
+    let body = resp.text().await.context("read response")?;
+    if !status.is_success() {
+        anyhow::bail!("{url} returned {status}: {}", body.trim());
+    }
+    serde_json::from_str(&body).context("parse response")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recent_lines_filter_by_level_and_module() {
+        let line = |level: &str, target: &str| LogLine {
+            at: OffsetDateTime::now_utc(),
+            level: level.to_string(),
+            target: target.to_string(),
+            message: String::new(),
+        };
+        let lines: VecDeque<LogLine> = [
+            line("INFO", "owp_server::tcp_game"),
+            line("WARN", "owp_server::tcp_game"),
+            line("ERROR", "owp_server::web_admin"),
+            line("WARN", "owp_server::tcp_gameplay"),
+            line("ERROR", "owp_server::tcp_game::relay"),
+        ]
+        .into();
+        let pick = |level: Option<&str>, module: Option<&str>, limit| {
+            let q = RecentQuery {
+                level: level.map(String::from),
+                module: module.map(String::from),
+                limit,
+            };
+            let got = select(&lines, &q).expect("select");
+            got.iter()
+                .map(|l| format!("{} {}", l.level, l.target))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(pick(None, None, None).len(), 5);
+        assert_eq!(
+            pick(Some("warn"), Some("tcp_game"), None),
+            [
+                "WARN owp_server::tcp_game",
+                "ERROR owp_server::tcp_game::relay"
+            ]
+        );
+        assert_eq!(
+            pick(None, Some("owp_server::tcp_game"), Some(1)),
+            ["ERROR owp_server::tcp_game::relay"]
+        );
+        assert_eq!(pick(Some("error"), None, None).len(), 2);
+        assert!(select(
+            &lines,
+            &RecentQuery {
+                level: Some("loud".into()),
+                ..RecentQuery::default()
+            }
+        )
+        .is_err());
+    }
+}
@@ -1,13 +1,74 @@
 use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
-use tracing_subscriber::EnvFilter;
+use owp_discovery::proxy::Proxy;
 
+mod access;
+mod accounts;
+mod all_in_one;
+mod asset_token;
+mod assets;
 mod assistant;
+mod authority;
 mod avatar;
+mod avatar_history;
+mod avatar_import;
 mod avatar_mesh;
+mod backup;
+mod bandwidth;
+mod bench;
+mod chat;
+mod chunks;
+mod cluster;
+mod config;
+mod content;
+mod crash;
+mod diff;
+mod directory_export;
+mod emotes;
+mod entry_fee;
+mod friends;
+mod fsck;
+mod generation_cache;
+mod grpc;
+mod health;
+mod ledger;
+mod logging;
+mod migration;
+mod minimap;
+mod net_quality;
+mod pairing;
+mod party;
+mod pathfind;
+mod peers;
+mod prefabs;
+mod presence;
+mod privacy;
+mod profile_data;
+mod provider_health;
+mod qr;
+mod quic;
+mod quota;
+mod replica;
+mod reputation;
+mod rules;
+mod scatter;
+mod service;
+mod sim;
+mod soak;
 mod storage;
+mod tap;
 mod tcp_game;
+mod telemetry;
+mod tls;
+mod token_launch;
+mod versioned;
+mod wal;
+mod wallet_auth;
+mod wardrobe;
 mod web_admin;
+mod world_chat;
+mod world_snapshot;
+mod world_summary;
 
 #[derive(Debug, Parser)]
 #[command(
@@ -52,9 +113,29 @@ enum Command {
         /// Can also be provided via `OWP_REGISTRY_PROGRAM_ID`.
         #[arg(long)]
         registry_program_id: Option<String>,
+
+        /// Static directory file to include in discovery (URL or path; repeatable)
+        #[arg(long, env = "OWP_DIRECTORY_URLS", value_delimiter = ',')]
+        directory_url: Vec<String>,
+
+        /// Publisher key (base58) trusted to sign directory files (repeatable)
+        #[arg(long, env = "OWP_DIRECTORY_TRUSTED_KEYS", value_delimiter = ',')]
+        directory_key: Vec<String>,
+
+        /// Handshake with listed worlds on discovery requests and keep the history
+        #[arg(long, env = "OWP_DISCOVERY_PROBE", default_value_t = false)]
+        probe_liveness: bool,
+
+        /// Proxy for discovery requests and probes (`socks5://`, `socks5h://` or `http://`)
+        #[arg(long, env = "OWP_PROXY")]
+        proxy: Option<Proxy>,
+
+        /// Also serve the admin API over gRPC (`owp.admin.v1.Admin`) on this address
+        #[arg(long, env = "OWP_GRPC_LISTEN")]
+        grpc_listen: Option<String>,
     },
 
-    /// Run the game server TCP listener (handshake only, for now)
+    /// Run the game server TCP listener
     Run {
         /// World id to serve
         #[arg(long)]
@@ -64,19 +145,369 @@ enum Command {
         #[arg(long)]
         listen: Option<String>,
     },
+
+    /// Follow a world on a leader's admin API and serve read-only copies of it (see README
+    /// "Read replicas")
+    Replica {
+        /// World id to follow
+        #[arg(long)]
+        world_id: String,
+
+        /// Admin API of the leader, e.g. `http://10.0.0.1:9333`
+        #[arg(long, env = "OWP_LEADER_URL")]
+        leader: String,
+
+        /// Admin-scoped bearer token for the leader
+        #[arg(long, env = "OWP_LEADER_TOKEN")]
+        leader_token: Option<String>,
+
+        /// Game listen address for observers (defaults to 0.0.0.0:<world game_port>)
+        #[arg(long)]
+        listen: Option<String>,
+
+        /// Also serve a read-only admin API (assets, chunks, sync) on this address
+        #[arg(long)]
+        admin_listen: Option<String>,
+    },
+
+    /// Run the admin API, game servers and background jobs in one process (for containers).
+    /// Every option can be set via its environment variable; nothing defaults to `~`.
+    AllInOne {
+        #[arg(long, env = "OWP_DATA_DIR", default_value = "./owp-data")]
+        data_dir: std::path::PathBuf,
+
+        #[arg(long, env = "OWP_ADMIN_LISTEN", default_value = "0.0.0.0:9333")]
+        admin_listen: String,
+
+        /// Bearer token for the admin API. If omitted, one is generated in the data dir.
+        #[arg(long, env = "OWP_ADMIN_TOKEN")]
+        admin_token: Option<String>,
+
+        #[arg(long, env = "OWP_ADMIN_NO_AUTH", default_value_t = false)]
+        no_auth: bool,
+
+        /// Comma-separated world ids to serve (defaults to every world in the data dir)
+        #[arg(long, env = "OWP_WORLDS", value_delimiter = ',')]
+        worlds: Vec<String>,
+
+        /// Bind host for game listeners; each world uses its manifest game_port
+        #[arg(long, env = "OWP_GAME_HOST", default_value = "0.0.0.0")]
+        game_host: String,
+
+        /// Create a world with this name on first start if the data dir has none
+        #[arg(long, env = "OWP_CREATE_WORLD")]
+        create_world: Option<String>,
+
+        #[arg(long, env = "OWP_SOLANA_RPC_URL")]
+        solana_rpc_url: Option<String>,
+
+        #[arg(long, env = "OWP_REGISTRY_PROGRAM_ID")]
+        registry_program_id: Option<String>,
+
+        #[arg(long, env = "OWP_DIRECTORY_URLS", value_delimiter = ',')]
+        directory_url: Vec<String>,
+
+        #[arg(long, env = "OWP_DIRECTORY_TRUSTED_KEYS", value_delimiter = ',')]
+        directory_key: Vec<String>,
+
+        #[arg(long, env = "OWP_DISCOVERY_PROBE", default_value_t = false)]
+        probe_liveness: bool,
+
+        #[arg(long, env = "OWP_PROXY")]
+        proxy: Option<Proxy>,
+
+        /// Also serve the admin API over gRPC on this address
+        #[arg(long, env = "OWP_GRPC_LISTEN")]
+        grpc_listen: Option<String>,
+    },
+
+    /// Write a signed, statically hostable directory of worlds
+    ExportDirectory {
+        #[arg(long, value_enum, default_value = "json")]
+        format: directory_export::ExportFormat,
+
+        #[arg(long, value_enum, default_value = "local")]
+        source: directory_export::ExportSource,
+
+        /// Output file (stdout if omitted)
+        #[arg(long)]
+        out: Option<std::path::PathBuf>,
+
+        /// Public host advertised for local worlds
+        #[arg(long, default_value = "127.0.0.1")]
+        endpoint: String,
+
+        /// Skip signing with the publisher key (~/.owp/directory-key)
+        #[arg(long, default_value_t = false)]
+        unsigned: bool,
+
+        #[arg(long, env = "OWP_SOLANA_RPC_URL")]
+        solana_rpc_url: Option<String>,
+
+        #[arg(long, env = "OWP_REGISTRY_PROGRAM_ID")]
+        registry_program_id: Option<String>,
+
+        /// Proxy for the Solana RPC request (`http://`)
+        #[arg(long, env = "OWP_PROXY")]
+        proxy: Option<Proxy>,
+    },
+
+    /// Measure disk, network, OpenSCAD and assistant latency and estimate what this host can serve
+    BenchHost {
+        /// Internet upload in Mbit/s, which can't be measured locally; included in the estimate
+        #[arg(long)]
+        uplink_mbps: Option<f64>,
+
+        /// Skip the assistant provider request (it may be billed)
+        #[arg(long, default_value_t = false)]
+        no_llm: bool,
+
+        /// Print the report as JSON
+        #[arg(long, default_value_t = false)]
+        json: bool,
+    },
+
+    /// Serve a world in-process to synthetic players for a while and report on memory growth
+    Soak {
+        #[arg(long)]
+        world_id: String,
+
+        /// Bots to connect (all from 127.0.0.1, so mind `max_connections_per_ip`)
+        #[arg(long, default_value_t = 50)]
+        bots: usize,
+
+        /// How long to run
+        #[arg(long, default_value_t = 3600)]
+        duration_secs: u64,
+
+        /// Position updates per bot per second
+        #[arg(long, default_value_t = 5.0)]
+        moves_per_sec: f64,
+
+        /// Chat messages per bot per minute
+        #[arg(long, default_value_t = 1.0)]
+        chats_per_min: f64,
+
+        /// Objects placed per bot per minute
+        #[arg(long, default_value_t = 0.5)]
+        places_per_min: f64,
+
+        /// How often to sample memory
+        #[arg(long, default_value_t = 10)]
+        sample_every_secs: u64,
+
+        /// Print the report as JSON
+        #[arg(long, default_value_t = false)]
+        json: bool,
+    },
+
+    /// Check world directories for damage (manifests, chunks, WAL, leftover temp files)
+    Fsck {
+        /// Only check this world
+        #[arg(long)]
+        world_id: Option<String>,
+
+        /// Repair what can be repaired (quarantine unreadable files, replay/compact the WAL)
+        #[arg(long, default_value_t = false)]
+        repair: bool,
+    },
+
+    /// Back up the data dir to the `backup` target in server.json now (see README "Backups")
+    Backup {
+        /// List the backups in the bucket instead, newest first
+        #[arg(long, default_value_t = false)]
+        list: bool,
+    },
+
+    /// Restore the data dir from an encrypted backup; stop running servers first
+    Restore {
+        /// Backup to restore: an object key, a name under the prefix, or `latest`
+        #[arg(long, value_name = "KEY")]
+        from_backup: String,
+
+        /// Restore even though the data dir already has worlds (their files are overwritten)
+        #[arg(long, default_value_t = false)]
+        force: bool,
+    },
+
+    /// Print the opt-in usage report (see README "Telemetry"), or post it to `telemetry.endpoint`
+    Telemetry {
+        /// Post the report and reset the counters
+        #[arg(long, default_value_t = false)]
+        send: bool,
+    },
+
+    /// Experimental: forward game connections to the entry node of the `cluster` in server.json
+    ClusterRouter {
+        #[arg(long, default_value = "0.0.0.0:7777")]
+        listen: String,
+    },
+
+    /// Show or change the log filter of a running admin / all-in-one server
+    LogLevel {
+        /// New `EnvFilter` directives, e.g. `info,owp_server::tcp_game=trace`. Omit to show.
+        filter: Option<String>,
+
+        /// Revert to the previous filter after this many seconds
+        #[arg(long)]
+        ttl_secs: Option<u64>,
+
+        #[arg(long, env = "OWP_ADMIN_URL", default_value = "http://127.0.0.1:9333")]
+        admin_url: String,
+
+        /// Admin bearer token (defaults to the saved admin-token, if any)
+        #[arg(long, env = "OWP_ADMIN_TOKEN")]
+        token: Option<String>,
+    },
+
+    /// Start pairing a game client with a running admin server and print the one-time code
+    Pair {
+        /// Device name the paired key is listed under
+        #[arg(long, default_value = "Unity client")]
+        name: String,
+
+        #[arg(long, value_enum, default_value = "client")]
+        scope: pairing::Scope,
+
+        #[arg(long, env = "OWP_ADMIN_URL", default_value = "http://127.0.0.1:9333")]
+        admin_url: String,
+
+        /// Admin bearer token (defaults to the saved admin-token, if any)
+        #[arg(long, env = "OWP_ADMIN_TOKEN")]
+        token: Option<String>,
+    },
+
+    /// Print a QR code of a world's connect string, or of the admin pairing info for the Unity client
+    Qr {
+        /// World whose connect string to encode
+        #[arg(long, required_unless_present = "admin", conflicts_with = "admin")]
+        world_id: Option<String>,
+
+        /// Encode the admin API address and a new pairing code instead (needs a running server)
+        #[arg(long, default_value_t = false)]
+        admin: bool,
+
+        /// Host in the connect string (defaults to `public_endpoint`, else 127.0.0.1)
+        #[arg(long)]
+        endpoint: Option<String>,
+
+        #[arg(long, value_enum, default_value = "text")]
+        format: qr::QrFormat,
+
+        /// Output file (stdout if omitted)
+        #[arg(long)]
+        out: Option<std::path::PathBuf>,
+
+        /// Admin API address to pair with; use a LAN address when scanning from another device
+        #[arg(long, env = "OWP_ADMIN_URL", default_value = "http://127.0.0.1:9333")]
+        admin_url: String,
+
+        /// Admin bearer token (defaults to the saved admin-token, if any)
+        #[arg(long, env = "OWP_ADMIN_TOKEN")]
+        token: Option<String>,
+    },
+
+    /// Manage local accounts (`~/.owp/accounts.json`)
+    Account {
+        #[command(subcommand)]
+        action: AccountAction,
+    },
+
+    /// Install, remove or inspect OS services (systemd / launchd / Windows scheduled task)
+    Service {
+        #[command(subcommand)]
+        action: ServiceAction,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum AccountAction {
+    /// Create an account; the password is read from OWP_ACCOUNT_PASSWORD or stdin
+    Add {
+        username: String,
+
+        /// Profile the account uses (defaults to the username)
+        #[arg(long)]
+        profile_id: Option<String>,
+
+        /// Allow admin-only endpoints and other profiles
+        #[arg(long, default_value_t = false)]
+        admin: bool,
+    },
+
+    /// Delete an account (its profile data is kept)
+    Remove { username: String },
+
+    /// Change a password, read like `add`'s
+    Passwd { username: String },
+
+    /// List accounts
+    List,
+}
+
+/// `OWP_ACCOUNT_PASSWORD`, else the first line of stdin.
+fn read_password() -> Result<String> {
+    if let Ok(p) = std::env::var("OWP_ACCOUNT_PASSWORD") {
+        return Ok(p);
+    }
+    eprint!("password: ");
+    let mut line = String::new();
+    std::io::stdin()
+        .read_line(&mut line)
+        .context("read password")?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+#[derive(Debug, Subcommand)]
+enum ServiceAction {
+    /// Install and start the admin API plus one game server per world
+    Install {
+        /// Worlds to run (defaults to every world in the data dir)
+        #[arg(long)]
+        world_id: Vec<String>,
+
+        #[arg(long, default_value = "127.0.0.1:9333")]
+        admin_listen: String,
+
+        /// Install system-wide instead of for the current user
+        #[arg(long, default_value_t = false)]
+        system: bool,
+
+        /// Print the generated files instead of installing them
+        #[arg(long, default_value_t = false)]
+        dry_run: bool,
+    },
+
+    /// Stop and remove every installed OWP service
+    Uninstall {
+        #[arg(long, default_value_t = false)]
+        system: bool,
+    },
+
+    /// Show installed OWP services and their state
+    Status {
+        #[arg(long, default_value_t = false)]
+        system: bool,
+    },
+}
+
+/// Load `server.json` and keep it fresh (SIGHUP / file changes).
+fn live_config(store: &storage::WorldStore) -> Result<config::LiveConfig> {
+    let cfg = config::LiveConfig::load(store)?;
+    cfg.spawn_watchers();
+    Ok(cfg)
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_default_env().add_directive("info".parse().unwrap()))
-        .init();
+    logging::init();
 
     let cli = Cli::parse();
     match cli.cmd {
         Command::CreateWorld { name, game_port } => {
             let store = storage::WorldStore::new()?;
             let manifest = store.create_world(&name, game_port)?;
+            telemetry::record(&store, telemetry::Event::WorldCreated);
             println!("{}", serde_json::to_string_pretty(&manifest)?);
             Ok(())
         }
@@ -86,8 +517,14 @@ async fn main() -> Result<()> {
             no_auth,
             solana_rpc_url,
             registry_program_id,
+            directory_url,
+            directory_key,
+            probe_liveness,
+            proxy,
+            grpc_listen,
         } => {
             let store = storage::WorldStore::new()?;
+            crash::install(&store);
             let auth = if no_auth {
                 web_admin::AuthMode::Disabled
             } else {
@@ -106,6 +543,7 @@ async fn main() -> Result<()> {
             let registry_program_id = registry_program_id
                 .or_else(|| std::env::var("OWP_REGISTRY_PROGRAM_ID").ok())
                 .filter(|v| !v.trim().is_empty());
+            let config = live_config(&store)?;
 
             web_admin::serve(
                 listen,
@@ -114,14 +552,444 @@ async fn main() -> Result<()> {
                 web_admin::DiscoveryConfig {
                     solana_rpc_url,
                     registry_program_id,
+                    directory_urls: directory_url,
+                    trusted_directory_keys: directory_key,
+                    probe_liveness,
+                    proxy,
                 },
+                health::HealthRegistry::default(),
+                config,
+                presence::Roster::default(),
+                grpc_listen,
+                false,
+                quota::Quotas::default(),
             )
             .await
         }
         Command::Run { world_id, listen } => {
             let store = storage::WorldStore::new()?;
+            crash::install(&store);
             let world_id = uuid::Uuid::parse_str(&world_id).context("invalid --world-id")?;
-            tcp_game::serve(store, world_id, listen).await
+            let config = live_config(&store)?;
+            let roster = presence::Roster::default();
+            let serve = tcp_game::serve(
+                store,
+                world_id,
+                listen,
+                health::HealthRegistry::default(),
+                config,
+                party::Parties::default(),
+                roster.clone(),
+                quota::Quotas::default(),
+            );
+            all_in_one::serve_until_shutdown(serve, &roster).await
+        }
+        Command::Replica {
+            world_id,
+            leader,
+            leader_token,
+            listen,
+            admin_listen,
+        } => {
+            let store = storage::WorldStore::new()?;
+            crash::install(&store);
+            let world_id = uuid::Uuid::parse_str(&world_id).context("invalid --world-id")?;
+            let health = health::HealthRegistry::default();
+            let mut follower =
+                replica::Follower::new(store.clone(), &leader, leader_token, world_id)?;
+            // The game server needs the world on disk before it starts.
+            follower
+                .poll()
+                .await
+                .context("first sync from the leader")?;
+            follower.mirror_assets().await?;
+            tokio::spawn(follower.run(health.clone()));
+            let config = live_config(&store)?;
+            let roster = presence::Roster::default();
+            let quotas = quota::Quotas::default();
+            if let Some(admin_listen) = admin_listen {
+                let token = store
+                    .load_or_create_admin_token()
+                    .context("create/load admin token")?;
+                let discovery = web_admin::DiscoveryConfig {
+                    solana_rpc_url: None,
+                    registry_program_id: None,
+                    directory_urls: vec![],
+                    trusted_directory_keys: vec![],
+                    probe_liveness: false,
+                    proxy: None,
+                };
+                let admin = web_admin::serve(
+                    admin_listen,
+                    store.clone(),
+                    web_admin::AuthMode::BearerToken(token),
+                    discovery,
+                    health.clone(),
+                    config.clone(),
+                    roster.clone(),
+                    None,
+                    true,
+                    quotas.clone(),
+                );
+                tokio::spawn(async move {
+                    if let Err(e) = admin.await {
+                        tracing::error!("read-only admin API stopped: {e:#}");
+                    }
+                });
+            }
+            let serve = tcp_game::serve(
+                store,
+                world_id,
+                listen,
+                health,
+                config,
+                party::Parties::default(),
+                roster.clone(),
+                quotas,
+            );
+            all_in_one::serve_until_shutdown(serve, &roster).await
+        }
+        Command::AllInOne {
+            data_dir,
+            admin_listen,
+            admin_token,
+            no_auth,
+            worlds,
+            game_host,
+            create_world,
+            solana_rpc_url,
+            registry_program_id,
+            directory_url,
+            directory_key,
+            probe_liveness,
+            proxy,
+            grpc_listen,
+        } => {
+            all_in_one::run(all_in_one::AllInOneConfig {
+                data_dir,
+                admin_listen,
+                admin_token,
+                no_auth,
+                worlds,
+                game_host,
+                create_world,
+                discovery: web_admin::DiscoveryConfig {
+                    solana_rpc_url: solana_rpc_url.filter(|v| !v.trim().is_empty()),
+                    registry_program_id: registry_program_id.filter(|v| !v.trim().is_empty()),
+                    directory_urls: directory_url,
+                    trusted_directory_keys: directory_key,
+                    probe_liveness,
+                    proxy,
+                },
+                grpc_listen: grpc_listen.filter(|v| !v.trim().is_empty()),
+            })
+            .await
+        }
+        Command::ExportDirectory {
+            format,
+            source,
+            out,
+            endpoint,
+            unsigned,
+            solana_rpc_url,
+            registry_program_id,
+            proxy,
+        } => {
+            let store = storage::WorldStore::new()?;
+            let file = directory_export::build_directory(
+                &store,
+                &directory_export::ExportOptions {
+                    source,
+                    endpoint: &endpoint,
+                    sign: !unsigned,
+                    solana_rpc_url: solana_rpc_url.as_deref().filter(|v| !v.trim().is_empty()),
+                    registry_program_id: registry_program_id
+                        .as_deref()
+                        .filter(|v| !v.trim().is_empty()),
+                    proxy: proxy.as_ref(),
+                },
+            )
+            .await?;
+            if let Some(sig) = &file.signature {
+                eprintln!("signed by publisher key {}", sig.public_key);
+            }
+            let body = directory_export::render(&file, format)?;
+            directory_export::write_output(out.as_deref(), &body)
+        }
+        Command::Backup { list } => {
+            let store = storage::WorldStore::new()?;
+            let cfg = config::LiveConfig::load(&store)?
+                .current()
+                .backup
+                .context("no `backup` section in server.json")?;
+            if list {
+                for key in backup::list(&cfg).await? {
+                    println!("{key}");
+                }
+                return Ok(());
+            }
+            let report = backup::run(&store, &cfg).await?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            Ok(())
+        }
+        Command::Restore { from_backup, force } => {
+            let store = storage::WorldStore::new()?;
+            let cfg = config::LiveConfig::load(&store)?
+                .current()
+                .backup
+                .context("no `backup` section in server.json")?;
+            let name = backup::restore(&store, &cfg, &from_backup, force).await?;
+            eprintln!("restored {name} into {:?}", store.root_dir());
+            Ok(())
+        }
+        Command::Telemetry { send } => {
+            let store = storage::WorldStore::new()?;
+            let report = if send {
+                let cfg = config::LiveConfig::load(&store)?.current().telemetry;
+                telemetry::send(&store, &cfg).await?
+            } else {
+                telemetry::preview(&store)?
+            };
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            Ok(())
+        }
+        Command::ClusterRouter { listen } => {
+            let store = storage::WorldStore::new()?;
+            let cfg = config::LiveConfig::load(&store)?
+                .current()
+                .cluster
+                .context("no `cluster` section in server.json")?;
+            cluster::route(cfg, &listen).await
+        }
+        Command::BenchHost {
+            uplink_mbps,
+            no_llm,
+            json,
+        } => {
+            let store = storage::WorldStore::new()?;
+            let report = bench::run(&store, uplink_mbps, !no_llm).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                bench::print(&report);
+            }
+            Ok(())
+        }
+        Command::Soak {
+            world_id,
+            bots,
+            duration_secs,
+            moves_per_sec,
+            chats_per_min,
+            places_per_min,
+            sample_every_secs,
+            json,
+        } => {
+            let store = storage::WorldStore::new()?;
+            let options = soak::SoakOptions {
+                world_id: uuid::Uuid::parse_str(&world_id).context("invalid --world-id")?,
+                bots,
+                duration: std::time::Duration::from_secs(duration_secs),
+                moves_per_sec,
+                chats_per_min,
+                places_per_min,
+                sample_every: std::time::Duration::from_secs(sample_every_secs),
+            };
+            let report = soak::run(store, options).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                soak::print(&report);
+            }
+            if report.memory.leak_suspected {
+                anyhow::bail!("memory kept growing during the soak");
+            }
+            if report.failed > 0 {
+                anyhow::bail!("{} bot(s) failed", report.failed);
+            }
+            Ok(())
+        }
+        Command::Fsck { world_id, repair } => {
+            let store = storage::WorldStore::new()?;
+            let world_id = world_id
+                .map(|id| uuid::Uuid::parse_str(&id).context("invalid --world-id"))
+                .transpose()?;
+            let report = fsck::check_store(&store, world_id, repair)?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            let left = report.unrepaired();
+            if left > 0 {
+                anyhow::bail!("{left} unrepaired issue(s)");
+            }
+            Ok(())
+        }
+        Command::LogLevel {
+            filter,
+            ttl_secs,
+            admin_url,
+            token,
+        } => {
+            let token = match token {
+                Some(t) => Some(t),
+                None => {
+                    let path = storage::WorldStore::new()?.admin_token_path();
+                    std::fs::read_to_string(path)
+                        .ok()
+                        .map(|t| t.trim().to_string())
+                }
+            };
+            let status =
+                logging::remote(&admin_url, token.as_deref(), filter.as_deref(), ttl_secs).await?;
+            println!("{}", status.filter);
+            Ok(())
+        }
+        Command::Pair {
+            name,
+            scope,
+            admin_url,
+            token,
+        } => {
+            let token = match token {
+                Some(t) => Some(t),
+                None => {
+                    let path = storage::WorldStore::new()?.admin_token_path();
+                    std::fs::read_to_string(path)
+                        .ok()
+                        .map(|t| t.trim().to_string())
+                }
+            };
+            let code = pairing::remote_start(&admin_url, token.as_deref(), &name, scope).await?;
+            println!("{}", code.code);
+            eprintln!(
+                "enter this code in the client within {} minutes",
+                code.expires_in_secs / 60
+            );
+            Ok(())
+        }
+        Command::Qr {
+            world_id,
+            admin: _,
+            endpoint,
+            format,
+            out,
+            admin_url,
+            token,
+        } => {
+            let store = storage::WorldStore::new()?;
+            let data = match world_id {
+                Some(id) => {
+                    let id = uuid::Uuid::parse_str(&id).context("invalid --world-id")?;
+                    let dir = store.world_dir(id);
+                    if !dir.exists() {
+                        anyhow::bail!("no world {id}");
+                    }
+                    let manifest = store.read_manifest(&dir)?;
+                    let endpoint = match endpoint {
+                        Some(e) => e,
+                        None => config::LiveConfig::load(&store)?
+                            .current()
+                            .public_endpoint
+                            .unwrap_or_else(|| "127.0.0.1".to_string()),
+                    };
+                    let endpoint = owp_discovery::endpoint::normalize(&endpoint)?;
+                    storage::connect_string(&manifest, &endpoint)
+                }
+                None => {
+                    let token = token.or_else(|| {
+                        std::fs::read_to_string(store.admin_token_path())
+                            .ok()
+                            .map(|t| t.trim().to_string())
+                    });
+                    let code = pairing::remote_start(
+                        &admin_url,
+                        token.as_deref(),
+                        "Scanned device",
+                        pairing::Scope::Client,
+                    )
+                    .await?;
+                    qr::pairing_uri(&admin_url, Some(&code.code))?
+                }
+            };
+            let code = qr::QrCode::encode(data.as_bytes())?;
+            eprintln!("{data}");
+            let body = qr::render(&code, format);
+            match out {
+                Some(path) => {
+                    std::fs::write(&path, body).with_context(|| format!("write {path:?}"))
+                }
+                None => {
+                    use std::io::Write;
+                    std::io::stdout().write_all(&body).context("write stdout")
+                }
+            }
+        }
+        Command::Account { action } => {
+            let store = storage::WorldStore::new()?;
+            match action {
+                AccountAction::Add {
+                    username,
+                    profile_id,
+                    admin,
+                } => {
+                    let password = read_password()?;
+                    let account =
+                        accounts::add(&store, &username, &password, profile_id.as_deref(), admin)?;
+                    println!("{}", serde_json::to_string_pretty(&account)?);
+                    Ok(())
+                }
+                AccountAction::Remove { username } => {
+                    if !accounts::remove(&store, &username)? {
+                        anyhow::bail!("no account {username:?}");
+                    }
+                    Ok(())
+                }
+                AccountAction::Passwd { username } => {
+                    let password = read_password()?;
+                    if !accounts::set_password(&store, &username, &password)? {
+                        anyhow::bail!("no account {username:?}");
+                    }
+                    Ok(())
+                }
+                AccountAction::List => {
+                    println!(
+                        "{}",
+                        serde_json::to_string_pretty(&accounts::list(&store)?)?
+                    );
+                    Ok(())
+                }
+            }
+        }
+        Command::Service { action } => {
+            let store = storage::WorldStore::new()?;
+            let data_dir = store.root_dir().to_path_buf();
+            match action {
+                ServiceAction::Install {
+                    world_id,
+                    admin_listen,
+                    system,
+                    dry_run,
+                } => {
+                    let world_ids = if world_id.is_empty() {
+                        store
+                            .list_worlds()?
+                            .into_iter()
+                            .map(|m| m.world_id)
+                            .collect()
+                    } else {
+                        world_id
+                            .iter()
+                            .map(|id| uuid::Uuid::parse_str(id).context("invalid --world-id"))
+                            .collect::<Result<Vec<_>>>()?
+                    };
+                    let opts = service::InstallOptions::detect(data_dir, system)?;
+                    service::install(&service::specs(&admin_listen, &world_ids), &opts, dry_run)
+                }
+                ServiceAction::Uninstall { system } => {
+                    service::uninstall(&service::InstallOptions::detect(data_dir, system)?)
+                }
+                ServiceAction::Status { system } => {
+                    service::status(&service::InstallOptions::detect(data_dir, system)?)
+                }
+            }
         }
     }
 }
@@ -0,0 +1,349 @@
+//! Moving a world to another host. The source freezes the world (WAL writes are refused),
+//! checkpoints it and exports the world dir plus the content-store blobs it references; the
+//! target imports that archive as a new world. The source then marks the world moved, and its
+//! game server sends every connected client a `WorldMoved` pointing at the new host.
+//!
+//! The registry entry is signed by the host wallet, so it isn't written here: the target
+//! returns the directory entry to submit as `UpdateWorld` (see docs/REGISTRY_ONCHAIN.md).
+
+use anyhow::{Context, Result};
+use owp_protocol::{WorldDirectoryEntry, WorldManifestV1};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use time::OffsetDateTime;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::assets::AssetIndex;
+use crate::backup;
+use crate::content;
+use crate::storage::{self, write_atomic, WorldStore};
+use crate::wal;
+
+/// Largest archive `POST /worlds/import` accepts.
+pub const MAX_ARCHIVE_BYTES: usize = 512 * 1024 * 1024;
+
+/// How long the source waits for the target to import the archive.
+const IMPORT_TIMEOUT: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MigrationPhase {
+    /// Writes are refused while the world is exported.
+    Frozen,
+    /// The target imported it; clients are sent to `connect`.
+    Moved,
+}
+
+/// `migration.json` in the world dir; absent when no migration is under way.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MigrationStateV1 {
+    pub phase: MigrationPhase,
+    #[serde(with = "time::serde::rfc3339")]
+    pub since: OffsetDateTime,
+    /// Where the world went, once `Moved`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect: Option<String>,
+    /// What the host wallet should submit as the registry's `UpdateWorld`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registry_update: Option<WorldDirectoryEntry>,
+}
+
+/// A write refused because the world is frozen for a migration.
+#[derive(Debug, Clone, Copy)]
+pub struct Frozen;
+
+impl fmt::Display for Frozen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("world is being migrated; writes are frozen")
+    }
+}
+
+impl std::error::Error for Frozen {}
+
+/// An import refused because the archive's world is already on this host.
+#[derive(Debug, Clone, Copy)]
+pub struct AlreadyExists(pub Uuid);
+
+impl fmt::Display for AlreadyExists {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "world {} already exists on this host", self.0)
+    }
+}
+
+impl std::error::Error for AlreadyExists {}
+
+pub fn state_path(world_dir: &Path) -> PathBuf {
+    world_dir.join("migration.json")
+}
+
+pub fn load(world_dir: &Path) -> Result<Option<MigrationStateV1>> {
+    let path = state_path(world_dir);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let data = fs::read_to_string(&path).with_context(|| format!("read {path:?}"))?;
+    serde_json::from_str(&data)
+        .map(Some)
+        .with_context(|| format!("parse {path:?}"))
+}
+
+fn save(world_dir: &Path, state: &MigrationStateV1) -> Result<()> {
+    let json = serde_json::to_string_pretty(state).context("serialize migration state")?;
+    write_atomic(&state_path(world_dir), format!("{json}\n").as_bytes())?;
+    Ok(())
+}
+
+/// Lift the freeze (or forget a finished move) so the world accepts writes again.
+pub fn clear(world_dir: &Path) -> Result<bool> {
+    let path = state_path(world_dir);
+    match fs::remove_file(&path) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e).with_context(|| format!("remove {path:?}")),
+    }
+}
+
+/// `Err(Frozen)` while a migration is under way. Called by `wal::mutate_batch` under the WAL
+/// lock, so no write lands after `export` checkpoints.
+pub fn check_writable(world_dir: &Path) -> Result<()> {
+    if state_path(world_dir).exists() {
+        return Err(Frozen.into());
+    }
+    Ok(())
+}
+
+/// Freeze the world and archive it: `world/` holds the world dir, `content/<sha256>` the
+/// content-store blobs its assets reference. The freeze stays until `clear`.
+pub fn export(store: &WorldStore, world_dir: &Path) -> Result<Vec<u8>> {
+    if load(world_dir)?.is_none_or(|s| s.phase != MigrationPhase::Frozen) {
+        save(
+            world_dir,
+            &MigrationStateV1 {
+                phase: MigrationPhase::Frozen,
+                since: OffsetDateTime::now_utc(),
+                connect: None,
+                registry_update: None,
+            },
+        )?;
+    }
+    wal::checkpoint(world_dir).context("checkpoint before export")?;
+
+    let mut tar = tar::Builder::new(Vec::new());
+    let files = backup::append_tree(&mut tar, world_dir, Path::new("world"))?;
+    let sync = AssetIndex::default().sync_manifest(store, world_dir)?;
+    let mut blobs = 0;
+    for entry in sync.entries {
+        if !entry.uri.starts_with("/content/") || !content::is_sha256(&entry.sha256) {
+            continue;
+        }
+        let path = content::content_path(store, &entry.sha256);
+        if path.exists() {
+            tar.append_path_with_name(&path, Path::new("content").join(&entry.sha256))
+                .with_context(|| format!("archive {path:?}"))?;
+            blobs += 1;
+        }
+    }
+    let bytes = tar.into_inner().context("finish archive")?;
+    info!(
+        "exported {world_dir:?}: {files} files, {blobs} content blobs, {} bytes",
+        bytes.len()
+    );
+    Ok(bytes)
+}
+
+/// Install an `export` archive as a new world. Refuses a world id this host already has.
+pub fn import(store: &WorldStore, archive: &[u8]) -> Result<WorldManifestV1> {
+    let root = store.worlds_root();
+    fs::create_dir_all(&root).with_context(|| format!("create {root:?}"))?;
+    // No manifest at its top, so `list_worlds` skips it while it's being unpacked.
+    let staging = root.join(format!(".import-{}", Uuid::new_v4()));
+    let result = install(store, &staging, archive);
+    if let Err(e) = fs::remove_dir_all(&staging) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            warn!("removing {staging:?} failed: {e}");
+        }
+    }
+    result
+}
+
+fn install(store: &WorldStore, staging: &Path, archive: &[u8]) -> Result<WorldManifestV1> {
+    backup::unpack(staging, archive)?;
+    let unpacked = staging.join("world");
+    let manifest = store
+        .read_manifest(&unpacked)
+        .context("archive has no world manifest")?;
+    let target = store.world_dir(manifest.world_id);
+    if target.exists() {
+        return Err(AlreadyExists(manifest.world_id).into());
+    }
+
+    let blobs = staging.join("content");
+    if blobs.exists() {
+        for entry in fs::read_dir(&blobs).with_context(|| format!("read {blobs:?}"))? {
+            let path = entry?.path();
+            let bytes = fs::read(&path).with_context(|| format!("read {path:?}"))?;
+            let name = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or_default();
+            if content::hash(&bytes) != name {
+                anyhow::bail!("content blob {name:?} does not match its hash");
+            }
+            content::put(store, &bytes)?;
+        }
+    }
+    // The source's freeze travels with the archive; the copy here starts writable.
+    clear(&unpacked)?;
+    wal::recover(&unpacked).context("wal recovery")?;
+    fs::rename(&unpacked, &target).with_context(|| format!("move world into {target:?}"))?;
+    info!("imported world {} ({})", manifest.world_id, manifest.name);
+    Ok(manifest)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MigrateRequest {
+    /// Admin API of the target host, e.g. `http://new-host:8080`.
+    pub target_url: String,
+    pub target_token: String,
+    /// Connect string to redirect clients to; the target's own is used when absent.
+    #[serde(default)]
+    pub connect: Option<String>,
+}
+
+/// What the target answers `POST /worlds/import` with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportReport {
+    pub manifest: WorldManifestV1,
+    /// Set when the target has a `public_endpoint`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registry_update: Option<WorldDirectoryEntry>,
+}
+
+/// The target's side of `import`.
+pub fn import_report(
+    store: &WorldStore,
+    archive: &[u8],
+    public_endpoint: Option<&str>,
+) -> Result<ImportReport> {
+    let manifest = import(store, archive)?;
+    Ok(ImportReport {
+        connect: public_endpoint.map(|e| storage::connect_string(&manifest, e)),
+        registry_update: public_endpoint.map(|e| storage::directory_entry(&manifest, e)),
+        manifest,
+    })
+}
+
+/// Export the world, have the target import it, then mark it moved. Any failure lifts the
+/// freeze again.
+pub async fn migrate(
+    store: &WorldStore,
+    world_id: Uuid,
+    req: &MigrateRequest,
+) -> Result<MigrationStateV1> {
+    let world_dir = store.world_dir(world_id);
+    let result = async {
+        let (s, dir) = (store.clone(), world_dir.clone());
+        let archive = tokio::task::spawn_blocking(move || export(&s, &dir)).await??;
+        let resp = reqwest::Client::new()
+            .post(format!(
+                "{}/worlds/import",
+                req.target_url.trim_end_matches('/')
+            ))
+            .bearer_auth(&req.target_token)
+            .header(reqwest::header::CONTENT_TYPE, "application/x-tar")
+            .timeout(IMPORT_TIMEOUT)
+            .body(archive)
+            .send()
+            .await
+            .with_context(|| format!("send the world to {}", req.target_url))?;
+        let status = resp.status();
+        if !status.is_success() {
+            let body = resp.text().await.unwrap_or_default();
+            anyhow::bail!("target refused the import: HTTP {status}: {body}");
+        }
+        let report: ImportReport = resp.json().await.context("parse import report")?;
+        let connect = req
+            .connect
+            .clone()
+            .or(report.connect)
+            .context("target has no public_endpoint; pass `connect`")?;
+        let state = MigrationStateV1 {
+            phase: MigrationPhase::Moved,
+            since: OffsetDateTime::now_utc(),
+            connect: Some(connect),
+            registry_update: report.registry_update,
+        };
+        save(&world_dir, &state)?;
+        anyhow::Ok(state)
+    }
+    .await;
+    if result.is_err() {
+        if let Err(e) = clear(&world_dir) {
+            warn!("lifting the migration freeze of {world_id} failed: {e:#}");
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunks;
+    use owp_protocol::{ChunkChangeV1, ChunkCoord, WorldObjectV1};
+
+    fn place(world_dir: &Path, id: &str) -> Result<Option<u64>> {
+        let op = wal::WalOp::ChunkChange {
+            chunk: ChunkCoord { x: 0, z: 0 },
+            change: ChunkChangeV1::Upsert {
+                object: WorldObjectV1 {
+                    id: id.to_string(),
+                    kind: "rock".to_string(),
+                    position: [1.0, 0.0, 1.0],
+                    rotation: [0.0; 3],
+                    scale: [1.0; 3],
+                },
+            },
+        };
+        wal::mutate(world_dir, op)
+    }
+
+    #[test]
+    fn export_freezes_and_import_recreates_the_world() {
+        let source_dir = tempfile::tempdir().expect("tempdir");
+        let source = WorldStore::with_root(source_dir.path().to_path_buf()).expect("store");
+        let world_id = source.create_world("moving", 7777).expect("world").world_id;
+        let sw = source.world_dir(world_id);
+        place(&sw, "a").expect("mutate");
+
+        let archive = export(&source, &sw).expect("export");
+        assert!(place(&sw, "b").expect_err("frozen").is::<Frozen>());
+
+        let target_dir = tempfile::tempdir().expect("tempdir");
+        let target = WorldStore::with_root(target_dir.path().to_path_buf()).expect("store");
+        assert_eq!(
+            import(&target, &archive).expect("import").world_id,
+            world_id
+        );
+        let tw = target.world_dir(world_id);
+        assert!(load(&tw).expect("state").is_none());
+        assert_eq!(
+            chunks::load_chunk(&tw, ChunkCoord { x: 0, z: 0 })
+                .expect("chunk")
+                .objects
+                .len(),
+            1
+        );
+        place(&tw, "b").expect("the copy is writable");
+        assert!(import(&target, &archive).is_err(), "already there");
+
+        // Aborting lifts the freeze on the source.
+        assert!(clear(&sw).expect("clear"));
+        place(&sw, "b").expect("writable again");
+    }
+}
@@ -0,0 +1,258 @@
+//! Compact map data for in-game minimaps: a quantized height grid and object markers over a
+//! square of chunks, plus the chunks a profile has explored.
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use owp_protocol::ChunkCoord;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::chunks::{self, CHUNK_SIZE_M};
+use crate::storage::write_atomic;
+
+/// Largest `radius` (in chunks) one request may cover.
+pub const MAX_RADIUS_CHUNKS: i32 = 8;
+
+/// Height steps in the grid, in meters.
+pub const HEIGHT_STEP_M: f32 = 0.5;
+
+/// Serializes read-modify-write of discovery files within this process.
+static DISCOVERY_LOCK: Mutex<()> = Mutex::new(());
+
+/// Which square to render.
+#[derive(Debug, Clone, Copy)]
+pub struct MinimapArea {
+    /// Center chunk.
+    pub x: i32,
+    pub z: i32,
+    /// Chunks on each side of the center.
+    pub radius: i32,
+    /// Grid cell edge in meters; must divide the chunk size.
+    pub cell_m: u32,
+}
+
+impl Default for MinimapArea {
+    fn default() -> Self {
+        Self {
+            x: 0,
+            z: 0,
+            radius: 4,
+            cell_m: 4,
+        }
+    }
+}
+
+/// One object kind seen in a cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct MinimapMarker {
+    /// Cell column (x) and row (z) from `origin`.
+    pub col: u16,
+    pub row: u16,
+    /// Index into `kinds`.
+    pub kind: u16,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MinimapV1 {
+    /// `[x, z]` of the grid's low corner, in meters.
+    pub origin: [f32; 2],
+    pub cell_m: u32,
+    /// Cells along x and z.
+    pub width: u32,
+    pub height: u32,
+    /// Base64 of one byte per cell, rows along x from `origin`: the top of the tallest object in
+    /// `HEIGHT_STEP_M` steps above y = 0, clamped to 0-255. Empty ground is 0.
+    pub heights: String,
+    pub kinds: Vec<String>,
+    pub markers: Vec<MinimapMarker>,
+    /// Explored chunks inside the area, when the request names a profile.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub discovered: Option<Vec<ChunkCoord>>,
+}
+
+pub fn check_area(area: &MinimapArea) -> Result<()> {
+    if !(0..=MAX_RADIUS_CHUNKS).contains(&area.radius) {
+        anyhow::bail!("radius must be 0-{MAX_RADIUS_CHUNKS} chunks");
+    }
+    if area.cell_m == 0 || !(CHUNK_SIZE_M as u32).is_multiple_of(area.cell_m) {
+        anyhow::bail!("cell_m must divide the {CHUNK_SIZE_M} m chunk size");
+    }
+    Ok(())
+}
+
+/// Render the area from the world's chunk files.
+pub fn render(world_dir: &Path, area: &MinimapArea, profile_id: Option<&str>) -> Result<MinimapV1> {
+    check_area(area)?;
+    let per_chunk = CHUNK_SIZE_M as u32 / area.cell_m;
+    let side_chunks = (2 * area.radius + 1) as u32;
+    let (width, height) = (side_chunks * per_chunk, side_chunks * per_chunk);
+    let lo = ChunkCoord {
+        x: area.x - area.radius,
+        z: area.z - area.radius,
+    };
+    let origin = [lo.x as f32 * CHUNK_SIZE_M, lo.z as f32 * CHUNK_SIZE_M];
+    let cell = area.cell_m as f32;
+
+    let mut heights = vec![0u8; (width * height) as usize];
+    let mut kinds: Vec<String> = Vec::new();
+    let mut markers = BTreeSet::new();
+    for z in lo.z..lo.z + side_chunks as i32 {
+        for x in lo.x..lo.x + side_chunks as i32 {
+            for o in chunks::load_chunk(world_dir, ChunkCoord { x, z })?.objects {
+                let col = ((o.position[0] - origin[0]) / cell).floor();
+                let row = ((o.position[2] - origin[1]) / cell).floor();
+                if !(0.0..width as f32).contains(&col) || !(0.0..height as f32).contains(&row) {
+                    continue;
+                }
+                let (col, row) = (col as u32, row as u32);
+                let top = o.position[1] + o.scale[1].abs();
+                let h = &mut heights[(row * width + col) as usize];
+                *h = (*h).max((top / HEIGHT_STEP_M).round().clamp(0.0, 255.0) as u8);
+                let kind = match kinds.iter().position(|k| *k == o.kind) {
+                    Some(i) => i,
+                    None => {
+                        kinds.push(o.kind);
+                        kinds.len() - 1
+                    }
+                };
+                markers.insert(MinimapMarker {
+                    col: col as u16,
+                    row: row as u16,
+                    kind: kind as u16,
+                });
+            }
+        }
+    }
+
+    let discovered = match profile_id {
+        Some(p) => Some(
+            load_discovered(world_dir, p)?
+                .into_iter()
+                .filter(|(x, z)| {
+                    (x - area.x).abs() <= area.radius && (z - area.z).abs() <= area.radius
+                })
+                .map(|(x, z)| ChunkCoord { x, z })
+                .collect(),
+        ),
+        None => None,
+    };
+    Ok(MinimapV1 {
+        origin,
+        cell_m: area.cell_m,
+        width,
+        height,
+        heights: base64::engine::general_purpose::STANDARD.encode(&heights),
+        kinds,
+        markers: markers.into_iter().collect(),
+        discovered,
+    })
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct DiscoveredV1 {
+    #[serde(default)]
+    chunks: BTreeSet<(i32, i32)>,
+}
+
+pub fn discovered_path(world_dir: &Path, profile_id: &str) -> PathBuf {
+    world_dir
+        .join("discovered")
+        .join(format!("{profile_id}.json"))
+}
+
+fn load_discovered(world_dir: &Path, profile_id: &str) -> Result<BTreeSet<(i32, i32)>> {
+    let path = discovered_path(world_dir, profile_id);
+    if !path.exists() {
+        return Ok(BTreeSet::new());
+    }
+    let data = fs::read_to_string(&path).with_context(|| format!("read {path:?}"))?;
+    let file: DiscoveredV1 =
+        serde_json::from_str(&data).with_context(|| format!("parse {path:?}"))?;
+    Ok(file.chunks)
+}
+
+/// Mark `chunk` explored by `profile_id`; `false` if it already was.
+pub fn discover(world_dir: &Path, profile_id: &str, chunk: ChunkCoord) -> Result<bool> {
+    let _guard = DISCOVERY_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut chunks = load_discovered(world_dir, profile_id)?;
+    if !chunks.insert((chunk.x, chunk.z)) {
+        return Ok(false);
+    }
+    let path = discovered_path(world_dir, profile_id);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("create {parent:?}"))?;
+    }
+    let json = serde_json::to_string(&DiscoveredV1 { chunks }).context("serialize discovered")?;
+    write_atomic(&path, format!("{json}\n").as_bytes())?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wal::{self, WalOp};
+    use owp_protocol::{ChunkChangeV1, WorldObjectV1};
+
+    #[test]
+    fn minimap_quantizes_objects_and_filters_discovery() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        for (id, kind, position, tall) in [
+            ("a", "tree_pine", [1.0, 0.0, 1.0], 6.0),
+            ("b", "rock_large", [2.0, 0.0, 3.0], 1.0),
+            ("c", "tree_pine", [-30.0, 0.0, 10.0], 5.0),
+        ] {
+            let object = WorldObjectV1 {
+                id: id.to_string(),
+                kind: kind.to_string(),
+                position,
+                rotation: [0.0; 3],
+                scale: [1.0, tall, 1.0],
+            };
+            wal::mutate(
+                dir.path(),
+                WalOp::ChunkChange {
+                    chunk: chunks::chunk_for_position(position),
+                    change: ChunkChangeV1::Upsert { object },
+                },
+            )
+            .expect("place");
+        }
+        assert!(discover(dir.path(), "kid", ChunkCoord { x: -1, z: 0 }).expect("discover"));
+        assert!(!discover(dir.path(), "kid", ChunkCoord { x: -1, z: 0 }).expect("again"));
+        discover(dir.path(), "kid", ChunkCoord { x: 5, z: 5 }).expect("far away");
+
+        let area = MinimapArea {
+            radius: 1,
+            cell_m: 8,
+            ..MinimapArea::default()
+        };
+        let map = render(dir.path(), &area, Some("kid")).expect("render");
+        assert_eq!(
+            (map.width, map.height, map.origin),
+            (12, 12, [-32.0, -32.0])
+        );
+        let heights = base64::engine::general_purpose::STANDARD
+            .decode(&map.heights)
+            .expect("base64");
+        // Objects a and b share the cell at the world origin; the taller one wins.
+        assert_eq!(heights[4 * 12 + 4], 12);
+        assert_eq!(heights[5 * 12], 10);
+        assert_eq!(map.kinds.len(), 2);
+        assert_eq!(map.markers.len(), 3);
+        assert_eq!(map.discovered, Some(vec![ChunkCoord { x: -1, z: 0 }]));
+
+        let too_far = MinimapArea {
+            radius: MAX_RADIUS_CHUNKS + 1,
+            ..MinimapArea::default()
+        };
+        assert!(check_area(&too_far).is_err());
+        assert!(check_area(&MinimapArea {
+            cell_m: 5,
+            ..MinimapArea::default()
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,232 @@
+use anyhow::{Context, Result};
+use owp_protocol::NetReport;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use time::OffsetDateTime;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::storage::write_atomic;
+
+/// Weight of the newest report in the running averages.
+const EWMA_ALPHA: f64 = 0.2;
+
+/// How often a running game server writes its aggregate for the admin API.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Connection quality of one world, aggregated from client `NetReport`s.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NetQualityV1 {
+    #[serde(default)]
+    pub reports: u64,
+    /// Running averages (EWMA); absent until a report carried the value.
+    #[serde(default)]
+    pub rtt_ms: Option<f64>,
+    #[serde(default)]
+    pub jitter_ms: Option<f64>,
+    /// Gaps per report, running average.
+    #[serde(default)]
+    pub gap_rate: f64,
+    #[serde(default)]
+    pub update_gaps_total: u64,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub updated_at: Option<OffsetDateTime>,
+}
+
+fn ewma(prev: Option<f64>, sample: f64) -> f64 {
+    match prev {
+        Some(p) => p + EWMA_ALPHA * (sample - p),
+        None => sample,
+    }
+}
+
+impl NetQualityV1 {
+    pub fn record(&mut self, r: &NetReport) {
+        if let Some(rtt) = r.rtt_ms {
+            self.rtt_ms = Some(ewma(self.rtt_ms, rtt as f64));
+        }
+        if let Some(jitter) = r.jitter_ms {
+            self.jitter_ms = Some(ewma(self.jitter_ms, jitter as f64));
+        }
+        self.gap_rate = ewma(
+            (self.reports > 0).then_some(self.gap_rate),
+            r.update_gaps as f64,
+        );
+        self.reports += 1;
+        self.update_gaps_total += r.update_gaps as u64;
+        self.updated_at = Some(OffsetDateTime::now_utc());
+    }
+
+    /// Multiplier (0.25 ..= 1.0) for how often updates should be pushed to this world's
+    /// clients: backs off as round trips, jitter and missed updates grow.
+    pub fn update_rate_scale(&self) -> f64 {
+        let rtt = ((self.rtt_ms.unwrap_or(0.0) - 100.0) / 400.0).clamp(0.0, 0.5);
+        let jitter = ((self.jitter_ms.unwrap_or(0.0) - 20.0) / 100.0).clamp(0.0, 0.25);
+        let gaps = (self.gap_rate / 4.0).clamp(0.0, 0.25);
+        (1.0 - rtt - jitter - gaps).max(0.25)
+    }
+}
+
+pub fn net_quality_path(world_dir: &Path) -> PathBuf {
+    world_dir.join("logs").join("net_quality.json")
+}
+
+pub fn load(world_dir: &Path) -> Result<NetQualityV1> {
+    let path = net_quality_path(world_dir);
+    if !path.exists() {
+        return Ok(NetQualityV1::default());
+    }
+    let data = fs::read_to_string(&path).with_context(|| format!("read {path:?}"))?;
+    serde_json::from_str(&data).with_context(|| format!("parse {path:?}"))
+}
+
+fn save(world_dir: &Path, q: &NetQualityV1) -> Result<()> {
+    let path = net_quality_path(world_dir);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("create {parent:?}"))?;
+    }
+    let json = serde_json::to_string_pretty(q).context("serialize net quality")?;
+    write_atomic(&path, format!("{json}\n").as_bytes())?;
+    Ok(())
+}
+
+/// Live aggregate shared by a game server's connections.
+#[derive(Clone, Default)]
+pub struct NetQuality {
+    inner: Arc<Mutex<NetQualityV1>>,
+    dirty: Arc<AtomicBool>,
+}
+
+impl NetQuality {
+    /// Continue from the last flushed aggregate so restarts don't reset the averages.
+    pub fn load(world_dir: &Path) -> Self {
+        let q = load(world_dir).unwrap_or_else(|e| {
+            warn!("net quality reset: {e:#}");
+            NetQualityV1::default()
+        });
+        Self {
+            inner: Arc::new(Mutex::new(q)),
+            dirty: Arc::default(),
+        }
+    }
+
+    pub fn record(&self, r: &NetReport) {
+        self.inner
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .record(r);
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> NetQualityV1 {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Periodically write the aggregate to `net_quality_path` when it changed.
+    pub fn spawn_flush(&self, world_dir: PathBuf) {
+        let this = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+            loop {
+                interval.tick().await;
+                if this.dirty.swap(false, Ordering::Relaxed) {
+                    if let Err(e) = save(&world_dir, &this.snapshot()) {
+                        warn!("writing net quality failed: {e:#}");
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// Prometheus text exposition for `GET /metrics`.
+pub fn render_metrics(worlds: &[(Uuid, NetQualityV1)]) -> String {
+    type Metric = (
+        &'static str,
+        &'static str,
+        &'static str,
+        fn(&NetQualityV1) -> Option<f64>,
+    );
+    let metrics: [Metric; 5] = [
+        (
+            "owp_net_reports_total",
+            "counter",
+            "NetReport messages received.",
+            |q| Some(q.reports as f64),
+        ),
+        (
+            "owp_net_rtt_ms",
+            "gauge",
+            "Client-reported round trip, running average.",
+            |q| q.rtt_ms,
+        ),
+        (
+            "owp_net_jitter_ms",
+            "gauge",
+            "Client-reported jitter, running average.",
+            |q| q.jitter_ms,
+        ),
+        (
+            "owp_net_update_gaps_total",
+            "counter",
+            "Missed updates reported by clients.",
+            |q| Some(q.update_gaps_total as f64),
+        ),
+        (
+            "owp_net_update_rate_scale",
+            "gauge",
+            "Suggested update rate multiplier derived from connection quality.",
+            |q| Some(q.update_rate_scale()),
+        ),
+    ];
+    let mut out = String::new();
+    for (name, kind, help, value) in metrics {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} {kind}");
+        for (world_id, q) in worlds {
+            if let Some(v) = value(q) {
+                let _ = writeln!(out, "{name}{{world_id=\"{world_id}\"}} {v}");
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(rtt: u32, jitter: u32, gaps: u32) -> NetReport {
+        NetReport {
+            request_id: Uuid::nil(),
+            interval_ms: 5000,
+            rtt_ms: Some(rtt),
+            jitter_ms: Some(jitter),
+            update_gaps: gaps,
+        }
+    }
+
+    #[test]
+    fn bad_connections_lower_the_update_rate() {
+        let mut good = NetQualityV1::default();
+        good.record(&report(30, 5, 0));
+        assert_eq!(good.update_rate_scale(), 1.0);
+
+        let mut bad = NetQualityV1::default();
+        for _ in 0..10 {
+            bad.record(&report(600, 150, 8));
+        }
+        assert_eq!(bad.update_gaps_total, 80);
+        assert_eq!(bad.update_rate_scale(), 0.25);
+
+        let text = render_metrics(&[(Uuid::nil(), bad)]);
+        assert!(text.contains(
+            "owp_net_reports_total{world_id=\"00000000-0000-0000-0000-000000000000\"} 10"
+        ));
+    }
+}
@@ -0,0 +1,304 @@
+use anyhow::{Context, Result};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::storage::{write_atomic, WorldStore};
+
+/// How long a pairing code can be redeemed.
+pub const CODE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Wrong codes tolerated before every pending code is dropped; with 8 characters from a
+/// 31-letter alphabet that leaves no realistic chance of guessing one.
+const MAX_FAILED_ATTEMPTS: u32 = 5;
+
+/// No 0/O, 1/I/L: codes are read off a screen and typed in.
+const CODE_ALPHABET: &[u8] = b"23456789ABCDEFGHJKMNPQRSTUVWXYZ";
+
+/// What a paired key may do.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    /// Everything the game client uses: worlds, discovery, avatars, friends, the assistant.
+    /// Not server settings, access filters, log levels or key management.
+    #[default]
+    Client,
+    /// Same as the admin token.
+    Admin,
+}
+
+/// A paired API key, as listed by the admin API.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiKeyV1 {
+    pub id: Uuid,
+    /// Device name given when pairing started.
+    pub name: String,
+    pub scope: Scope,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+}
+
+/// Only the SHA-256 of the secret is kept.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredKey {
+    #[serde(flatten)]
+    key: ApiKeyV1,
+    sha256: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ApiKeysFileV1 {
+    #[serde(default)]
+    keys: Vec<StoredKey>,
+}
+
+pub fn api_keys_path(store: &WorldStore) -> PathBuf {
+    store.root_dir().join("api-keys.json")
+}
+
+struct PendingCode {
+    code: String,
+    name: String,
+    scope: Scope,
+    expires: Instant,
+}
+
+#[derive(Default)]
+struct PairingState {
+    keys: Vec<StoredKey>,
+    pending: Vec<PendingCode>,
+    failed: u32,
+}
+
+/// A code shown on the host, to be typed into the client.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PairingCode {
+    pub code: String,
+    pub expires_in_secs: u64,
+}
+
+/// Returned once to the client that redeemed a code; the secret can't be recovered later.
+#[derive(Debug, Clone, Serialize)]
+pub struct PairedKey {
+    #[serde(flatten)]
+    pub key: ApiKeyV1,
+    pub api_key: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RedeemError {
+    /// Unknown, expired or already used.
+    InvalidCode,
+    /// Too many wrong codes; start pairing again on the host.
+    Locked,
+}
+
+/// One-time pairing codes and the API keys they were exchanged for (`api-keys.json`).
+#[derive(Clone)]
+pub struct Pairing {
+    path: PathBuf,
+    state: Arc<Mutex<PairingState>>,
+}
+
+impl Pairing {
+    pub fn load(store: &WorldStore) -> Result<Self> {
+        let path = api_keys_path(store);
+        let keys = if path.exists() {
+            let data = fs::read_to_string(&path).with_context(|| format!("read {path:?}"))?;
+            let file: ApiKeysFileV1 =
+                serde_json::from_str(&data).with_context(|| format!("parse {path:?}"))?;
+            file.keys
+        } else {
+            vec![]
+        };
+        Ok(Self {
+            path,
+            state: Arc::new(Mutex::new(PairingState {
+                keys,
+                ..PairingState::default()
+            })),
+        })
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, PairingState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn save(&self, keys: Vec<StoredKey>) -> Result<Vec<StoredKey>> {
+        let file = ApiKeysFileV1 { keys };
+        let json = serde_json::to_string_pretty(&file).context("serialize api keys")?;
+        write_atomic(&self.path, format!("{json}\n").as_bytes())?;
+        Ok(file.keys)
+    }
+
+    /// Issue a code for a device called `name`. Starting again also clears a lockout.
+    pub fn start(&self, name: &str, scope: Scope) -> PairingCode {
+        let mut rng = rand::thread_rng();
+        let raw: String = (0..8)
+            .map(|_| char::from(CODE_ALPHABET[rng.gen_range(0..CODE_ALPHABET.len())]))
+            .collect();
+        let mut state = self.state();
+        let now = Instant::now();
+        state.pending.retain(|p| p.expires > now);
+        state.failed = 0;
+        state.pending.push(PendingCode {
+            code: raw.clone(),
+            name: name.to_string(),
+            scope,
+            expires: now + CODE_TTL,
+        });
+        PairingCode {
+            code: format!("{}-{}", &raw[..4], &raw[4..]),
+            expires_in_secs: CODE_TTL.as_secs(),
+        }
+    }
+
+    /// Exchange a code for a new API key. Case, spaces and dashes in `code` don't matter.
+    pub fn redeem(&self, code: &str) -> Result<Result<PairedKey, RedeemError>> {
+        let code: String = code
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .map(|c| c.to_ascii_uppercase())
+            .collect();
+        let mut state = self.state();
+        if state.failed >= MAX_FAILED_ATTEMPTS {
+            return Ok(Err(RedeemError::Locked));
+        }
+        let now = Instant::now();
+        state.pending.retain(|p| p.expires > now);
+        let Some(i) = state.pending.iter().position(|p| p.code == code) else {
+            state.failed += 1;
+            if state.failed >= MAX_FAILED_ATTEMPTS {
+                state.pending.clear();
+            }
+            return Ok(Err(RedeemError::InvalidCode));
+        };
+        let pending = state.pending.remove(i);
+
+        let secret: String = rand::thread_rng()
+            .sample_iter(&rand::distributions::Alphanumeric)
+            .take(40)
+            .map(char::from)
+            .collect();
+        let api_key = format!("owpk_{secret}");
+        let key = ApiKeyV1 {
+            id: Uuid::new_v4(),
+            name: pending.name,
+            scope: pending.scope,
+            created_at: OffsetDateTime::now_utc(),
+        };
+        let mut keys = state.keys.clone();
+        keys.push(StoredKey {
+            key: key.clone(),
+            sha256: hex::encode(Sha256::digest(api_key.as_bytes())),
+        });
+        state.keys = self.save(keys)?;
+        Ok(Ok(PairedKey { key, api_key }))
+    }
+
+    /// Scope of the key `token`, if it is one of ours.
+    pub fn scope_of(&self, token: &str) -> Option<Scope> {
+        let sha256 = hex::encode(Sha256::digest(token.as_bytes()));
+        self.state()
+            .keys
+            .iter()
+            .find(|k| k.sha256 == sha256)
+            .map(|k| k.key.scope)
+    }
+
+    pub fn list(&self) -> Vec<ApiKeyV1> {
+        self.state().keys.iter().map(|k| k.key.clone()).collect()
+    }
+
+    /// Revoke a key; `false` if there was none with that id.
+    pub fn revoke(&self, id: Uuid) -> Result<bool> {
+        let mut state = self.state();
+        let keys: Vec<_> = state
+            .keys
+            .iter()
+            .filter(|k| k.key.id != id)
+            .cloned()
+            .collect();
+        if keys.len() == state.keys.len() {
+            return Ok(false);
+        }
+        state.keys = self.save(keys)?;
+        Ok(true)
+    }
+}
+
+/// `POST /auth/pair/start` on a running admin server, for a host without a UI.
+pub async fn remote_start(
+    admin_url: &str,
+    token: Option<&str>,
+    name: &str,
+    scope: Scope,
+) -> Result<PairingCode> {
+    let url = format!("{}/auth/pair/start", admin_url.trim_end_matches('/'));
+    let mut req = reqwest::Client::new()
+        .post(&url)
+        .json(&serde_json::json!({ "name": name, "scope": scope }));
+    if let Some(t) = token {
+        req = req.bearer_auth(t);
+    }
+    let resp = req.send().await.with_context(|| format!("request {url}"))?;
+    let status = resp.status();
+    let body = resp.text().await.context("read response")?;
+    if !status.is_success() {
+        anyhow::bail!("{url} returned {status}: {}", body.trim());
+    }
+    serde_json::from_str(&body).context("parse response")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_are_single_use_and_guessing_locks_out() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let store = WorldStore::with_root(root.path().to_path_buf()).expect("store");
+        let pairing = Pairing::load(&store).expect("load");
+
+        let code = pairing.start("Living room PC", Scope::Client);
+        assert_eq!(code.code.len(), 9);
+        let paired = pairing
+            .redeem(&code.code.to_lowercase().replace('-', " "))
+            .expect("io")
+            .expect("redeem");
+        assert_eq!(pairing.scope_of(&paired.api_key), Some(Scope::Client));
+        assert_eq!(pairing.scope_of("owpk_nope"), None);
+        assert_eq!(
+            pairing.redeem(&code.code).expect("io").map(|_| ()),
+            Err(RedeemError::InvalidCode)
+        );
+
+        // Keys survive a restart; the hash never leaves the file.
+        let reloaded = Pairing::load(&store).expect("reload");
+        assert_eq!(reloaded.scope_of(&paired.api_key), Some(Scope::Client));
+        let listed = serde_json::to_string(&reloaded.list()).expect("json");
+        assert!(listed.contains("Living room PC") && !listed.contains("sha256"));
+
+        let code = pairing.start("Laptop", Scope::Admin);
+        for _ in 0..MAX_FAILED_ATTEMPTS {
+            assert_eq!(
+                pairing.redeem("AAAA-AAAA").expect("io").map(|_| ()),
+                Err(RedeemError::InvalidCode)
+            );
+        }
+        assert_eq!(
+            pairing.redeem(&code.code).expect("io").map(|_| ()),
+            Err(RedeemError::Locked)
+        );
+
+        assert!(pairing.revoke(paired.key.id).expect("revoke"));
+        assert_eq!(pairing.scope_of(&paired.api_key), None);
+        assert!(!pairing.revoke(paired.key.id).expect("revoke again"));
+    }
+}
@@ -0,0 +1,379 @@
+use anyhow::{Context, Result};
+use owp_protocol::{Message, PartyInfo, PartyMember, PartyTravel, PartyUpdate};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+pub const MAX_PARTY_SIZE: usize = 8;
+
+/// Members offline for longer than this (dropped and never came back, or never arrived in the
+/// world they travelled to) leave the party.
+pub const OFFLINE_GRACE: Duration = Duration::from_secs(120);
+
+struct Member {
+    player_id: Uuid,
+    world_id: Uuid,
+    /// Lets the member's next connection, possibly to another world, take its place.
+    token: String,
+    outbox: Option<mpsc::Sender<Message>>,
+    offline_since: Option<Instant>,
+}
+
+struct Party {
+    leader: Uuid,
+    members: Vec<Member>,
+    invited: HashSet<Uuid>,
+}
+
+impl Party {
+    fn info(&self, party_id: Uuid) -> PartyInfo {
+        PartyInfo {
+            party_id,
+            leader: self.leader,
+            members: self
+                .members
+                .iter()
+                .map(|m| PartyMember {
+                    player_id: m.player_id,
+                    world_id: m.world_id,
+                    online: m.outbox.is_some(),
+                })
+                .collect(),
+        }
+    }
+
+    fn member_mut(&mut self, player_id: Uuid) -> Option<&mut Member> {
+        self.members.iter_mut().find(|m| m.player_id == player_id)
+    }
+
+    /// Send to every online member except `except`; returns how many got it.
+    fn send(&self, except: Option<Uuid>, msg: &Message) -> usize {
+        self.members
+            .iter()
+            .filter(|m| Some(m.player_id) != except)
+            .filter_map(|m| m.outbox.as_ref())
+            .filter(|tx| tx.try_send(msg.clone()).is_ok())
+            .count()
+    }
+
+    fn announce(&self, party_id: Uuid) {
+        let party = self.info(party_id);
+        self.send(None, &Message::PartyUpdate(PartyUpdate { party }));
+    }
+}
+
+#[derive(Default)]
+struct State {
+    parties: HashMap<Uuid, Party>,
+    /// Party of each member, by player id.
+    by_player: HashMap<Uuid, Uuid>,
+}
+
+impl State {
+    fn party_of(&mut self, player_id: Uuid) -> Result<(Uuid, &mut Party)> {
+        let party_id = *self
+            .by_player
+            .get(&player_id)
+            .context("you are not in a party")?;
+        let party = self.parties.get_mut(&party_id).context("party is gone")?;
+        Ok((party_id, party))
+    }
+
+    fn remove_member(&mut self, player_id: Uuid) -> Option<Uuid> {
+        let party_id = self.by_player.remove(&player_id)?;
+        let party = self.parties.get_mut(&party_id)?;
+        party.members.retain(|m| m.player_id != player_id);
+        if party.members.is_empty() {
+            self.parties.remove(&party_id);
+            return None;
+        }
+        if party.leader == player_id {
+            party.leader = party.members[0].player_id;
+        }
+        party.announce(party_id);
+        Some(party_id)
+    }
+
+    fn prune(&mut self) {
+        let expired: Vec<Uuid> = self
+            .parties
+            .values()
+            .flat_map(|p| p.members.iter())
+            .filter(|m| {
+                m.offline_since
+                    .is_some_and(|t| t.elapsed() >= OFFLINE_GRACE)
+            })
+            .map(|m| m.player_id)
+            .collect();
+        for player_id in expired {
+            self.remove_member(player_id);
+        }
+    }
+
+    fn add_member(
+        &mut self,
+        party_id: Uuid,
+        player_id: Uuid,
+        world_id: Uuid,
+        outbox: mpsc::Sender<Message>,
+    ) -> Result<(PartyInfo, String)> {
+        let party = self.parties.get_mut(&party_id).context("no such party")?;
+        let token = Uuid::new_v4().simple().to_string();
+        party.members.push(Member {
+            player_id,
+            world_id,
+            token: token.clone(),
+            outbox: Some(outbox),
+            offline_since: None,
+        });
+        party.announce(party_id);
+        let info = party.info(party_id);
+        self.by_player.insert(player_id, party_id);
+        Ok((info, token))
+    }
+}
+
+/// Parties on this host. Every world served by the process shares one registry, so members
+/// can move between those worlds together.
+#[derive(Clone, Default)]
+pub struct Parties(Arc<Mutex<State>>);
+
+impl Parties {
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        let mut state = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        state.prune();
+        state
+    }
+
+    /// A player connected to `world_id`. Puts it back in its party if it resumed a session or
+    /// presented a member's `party_token`, and returns that party.
+    pub fn connect(
+        &self,
+        player_id: Uuid,
+        world_id: Uuid,
+        outbox: mpsc::Sender<Message>,
+        token: Option<&str>,
+    ) -> Option<PartyInfo> {
+        let mut state = self.state();
+        let party_id = match state.by_player.get(&player_id) {
+            Some(id) => *id,
+            None => {
+                let token = token?;
+                let (party_id, old_id) = state.parties.iter().find_map(|(id, p)| {
+                    let m = p.members.iter().find(|m| m.token == token)?;
+                    Some((*id, m.player_id))
+                })?;
+                state.by_player.remove(&old_id);
+                state.by_player.insert(player_id, party_id);
+                let party = state.parties.get_mut(&party_id)?;
+                if party.leader == old_id {
+                    party.leader = player_id;
+                }
+                party.member_mut(old_id)?.player_id = player_id;
+                party_id
+            }
+        };
+        let party = state.parties.get_mut(&party_id)?;
+        let member = party.member_mut(player_id)?;
+        member.world_id = world_id;
+        member.outbox = Some(outbox);
+        member.offline_since = None;
+        party.announce(party_id);
+        Some(party.info(party_id))
+    }
+
+    /// The player's connection went away; it stays in the party for `OFFLINE_GRACE`.
+    pub fn disconnect(&self, player_id: Uuid) {
+        let mut state = self.state();
+        let Ok((party_id, party)) = state.party_of(player_id) else {
+            return;
+        };
+        if let Some(m) = party.member_mut(player_id) {
+            m.outbox = None;
+            m.offline_since = Some(Instant::now());
+        }
+        party.announce(party_id);
+    }
+
+    /// Start a party with the player as leader. Returns the roster and the player's token.
+    pub fn create(
+        &self,
+        player_id: Uuid,
+        world_id: Uuid,
+        outbox: mpsc::Sender<Message>,
+    ) -> Result<(PartyInfo, String)> {
+        let mut state = self.state();
+        if state.by_player.contains_key(&player_id) {
+            anyhow::bail!("you are already in a party");
+        }
+        let party_id = Uuid::new_v4();
+        state.parties.insert(
+            party_id,
+            Party {
+                leader: player_id,
+                members: vec![],
+                invited: HashSet::new(),
+            },
+        );
+        state.add_member(party_id, player_id, world_id, outbox)
+    }
+
+    /// Let `to` join the party of `from`. Returns the party of `from`.
+    pub fn invite(&self, from: Uuid, to: Uuid) -> Result<PartyInfo> {
+        let mut state = self.state();
+        if state.by_player.contains_key(&to) {
+            anyhow::bail!("player {to} is already in a party");
+        }
+        let (party_id, party) = state.party_of(from)?;
+        if party.members.len() >= MAX_PARTY_SIZE {
+            anyhow::bail!("party is full ({MAX_PARTY_SIZE} players)");
+        }
+        party.invited.insert(to);
+        Ok(party.info(party_id))
+    }
+
+    /// Accept an invitation.
+    pub fn join(
+        &self,
+        player_id: Uuid,
+        world_id: Uuid,
+        party_id: Uuid,
+        outbox: mpsc::Sender<Message>,
+    ) -> Result<(PartyInfo, String)> {
+        let mut state = self.state();
+        if state.by_player.contains_key(&player_id) {
+            anyhow::bail!("you are already in a party");
+        }
+        let party = state
+            .parties
+            .get_mut(&party_id)
+            .filter(|p| p.invited.contains(&player_id))
+            .context("no invitation to that party")?;
+        if party.members.len() >= MAX_PARTY_SIZE {
+            anyhow::bail!("party is full ({MAX_PARTY_SIZE} players)");
+        }
+        party.invited.remove(&player_id);
+        state.add_member(party_id, player_id, world_id, outbox)
+    }
+
+    pub fn leave(&self, player_id: Uuid) -> Result<()> {
+        let mut state = self.state();
+        if !state.by_player.contains_key(&player_id) {
+            anyhow::bail!("you are not in a party");
+        }
+        state.remove_member(player_id);
+        Ok(())
+    }
+
+    /// Send to every online member of the player's party, the player included.
+    pub fn send(&self, player_id: Uuid, msg: &Message) -> Result<usize> {
+        let mut state = self.state();
+        let (_, party) = state.party_of(player_id)?;
+        Ok(party.send(None, msg))
+    }
+
+    /// The leader takes the party to another world: other online members are told where to
+    /// connect. Returns how many were told.
+    pub fn travel(&self, player_id: Uuid, connect: &str) -> Result<usize> {
+        owp_discovery::parse_connect_string(connect)?;
+        let mut state = self.state();
+        let (_, party) = state.party_of(player_id)?;
+        if party.leader != player_id {
+            anyhow::bail!("only the party leader can move the party");
+        }
+        let msg = Message::PartyTravel(PartyTravel {
+            connect: connect.to_string(),
+            from: Some(player_id),
+        });
+        Ok(party.send(Some(player_id), &msg))
+    }
+}
+
+/// Marks a player offline in its party when its connection goes away.
+pub struct PartyGuard {
+    pub parties: Parties,
+    pub player_id: Uuid,
+}
+
+impl Drop for PartyGuard {
+    fn drop(&mut self) {
+        self.parties.disconnect(self.player_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presence::OUTBOX_CAPACITY;
+
+    fn drain(rx: &mut mpsc::Receiver<Message>) -> Vec<Message> {
+        std::iter::from_fn(|| rx.try_recv().ok()).collect()
+    }
+
+    #[test]
+    fn party_follows_its_members_to_another_world() {
+        let parties = Parties::default();
+        let (lobby, arena) = (Uuid::new_v4(), Uuid::new_v4());
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        let (alice_tx, mut alice_rx) = mpsc::channel(OUTBOX_CAPACITY);
+        let (bob_tx, mut bob_rx) = mpsc::channel(OUTBOX_CAPACITY);
+
+        let (party, _) = parties.create(alice, lobby, alice_tx).expect("create");
+        assert!(parties
+            .join(bob, lobby, party.party_id, bob_tx.clone())
+            .is_err());
+        parties.invite(alice, bob).expect("invite");
+        let (party, bob_token) = parties
+            .join(bob, lobby, party.party_id, bob_tx)
+            .expect("join");
+        assert_eq!(party.members.len(), 2);
+        drain(&mut alice_rx);
+        drain(&mut bob_rx);
+
+        let there = "owp://127.0.0.1:7001?world=00000000-0000-0000-0000-000000000001";
+        assert!(parties.travel(bob, there).is_err());
+        assert_eq!(parties.travel(alice, there).expect("travel"), 1);
+        assert!(matches!(&drain(&mut bob_rx)[..], [Message::PartyTravel(t)] if t.connect == there));
+
+        // Bob drops out of the lobby and shows up in the arena under a new player id.
+        parties.disconnect(bob);
+        let bob_again = Uuid::new_v4();
+        let (bob_tx, mut bob_rx) = mpsc::channel(OUTBOX_CAPACITY);
+        assert!(parties
+            .connect(bob_again, arena, bob_tx.clone(), Some("wrong"))
+            .is_none());
+        let party = parties
+            .connect(bob_again, arena, bob_tx, Some(&bob_token))
+            .expect("rejoined");
+        let bob_member = party
+            .members
+            .iter()
+            .find(|m| m.player_id == bob_again)
+            .expect("member");
+        assert_eq!(bob_member.world_id, arena);
+        assert!(bob_member.online);
+
+        parties
+            .send(
+                bob_again,
+                &Message::PartyLeave(owp_protocol::PartyLeave {
+                    request_id: Uuid::nil(),
+                }),
+            )
+            .expect("send");
+        assert!(drain(&mut alice_rx)
+            .iter()
+            .any(|m| matches!(m, Message::PartyLeave(_))));
+
+        // The leader leaving hands the party over.
+        parties.leave(alice).expect("leave");
+        let updates = drain(&mut bob_rx);
+        assert!(
+            matches!(updates.last(), Some(Message::PartyUpdate(u)) if u.party.leader == bob_again)
+        );
+        parties.leave(bob_again).expect("leave");
+        assert!(parties.leave(bob_again).is_err());
+    }
+}
@@ -0,0 +1,300 @@
+//! Walkability grid and A* paths for `PathQuery`. The grid is baked per chunk from object
+//! footprints and re-baked whenever the chunk file changes, so paths follow edits made through
+//! the admin API or the WAL without any notification.
+
+use anyhow::Result;
+use owp_protocol::{ChunkCoord, WorldObjectV1};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::chunks::{self, CHUNK_SIZE_M};
+
+/// Grid cell edge in meters.
+pub const NAV_CELL_M: f32 = 1.0;
+
+/// Kept between walkers and object footprints.
+pub const AGENT_RADIUS_M: f32 = 0.3;
+
+/// Objects lower than this can be stepped over.
+pub const STEP_HEIGHT_M: f32 = 0.4;
+
+/// Longest straight-line distance a query may span.
+pub const MAX_PATH_DISTANCE_M: f32 = 256.0;
+
+/// Cells A* may expand before giving up.
+const MAX_EXPANSIONS: usize = 40_000;
+
+const CELLS_PER_CHUNK: i32 = (CHUNK_SIZE_M / NAV_CELL_M) as i32;
+
+type Cell = (i32, i32);
+
+/// A chunk's blocked cells (which may lie in neighbouring chunks) and the file state they
+/// were baked from.
+struct Baked {
+    stamp: Option<(SystemTime, u64)>,
+    blocked: Arc<HashSet<Cell>>,
+}
+
+/// Per-world walkability cache.
+#[derive(Clone)]
+pub struct NavGrid {
+    world_dir: PathBuf,
+    baked: Arc<Mutex<HashMap<Cell, Baked>>>,
+}
+
+fn cell_of(x: f32, z: f32) -> Cell {
+    (
+        (x / NAV_CELL_M).floor() as i32,
+        (z / NAV_CELL_M).floor() as i32,
+    )
+}
+
+fn cell_center((x, z): Cell) -> (f32, f32) {
+    ((x as f32 + 0.5) * NAV_CELL_M, (z as f32 + 0.5) * NAV_CELL_M)
+}
+
+/// Cells whose centers fall inside the object's yawed footprint, grown by the agent radius.
+/// Footprints reaching more than a chunk past their own chunk are cut off there.
+fn rasterize(o: &WorldObjectV1, out: &mut HashSet<Cell>) {
+    if o.scale[1].abs() < STEP_HEIGHT_M || !o.position.iter().all(|v| v.is_finite()) {
+        return;
+    }
+    let half_x = o.scale[0].abs() / 2.0 + AGENT_RADIUS_M;
+    let half_z = o.scale[2].abs() / 2.0 + AGENT_RADIUS_M;
+    let reach = half_x.hypot(half_z).min(CHUNK_SIZE_M);
+    let (sin, cos) = o.rotation[1].to_radians().sin_cos();
+    let (lo, hi) = (
+        cell_of(o.position[0] - reach, o.position[2] - reach),
+        cell_of(o.position[0] + reach, o.position[2] + reach),
+    );
+    for z in lo.1..=hi.1 {
+        for x in lo.0..=hi.0 {
+            let (cx, cz) = cell_center((x, z));
+            let (dx, dz) = (cx - o.position[0], cz - o.position[2]);
+            // Into the object's frame (yaw about +Y).
+            let lx = dx * cos - dz * sin;
+            let lz = dx * sin + dz * cos;
+            if lx.abs() <= half_x && lz.abs() <= half_z {
+                out.insert((x, z));
+            }
+        }
+    }
+}
+
+impl NavGrid {
+    pub fn new(world_dir: PathBuf) -> Self {
+        Self {
+            world_dir,
+            baked: Arc::default(),
+        }
+    }
+
+    /// Blocked cells contributed by one chunk's objects, re-baked if its file changed.
+    fn chunk_blocked(&self, chunk: Cell) -> Result<Arc<HashSet<Cell>>> {
+        let coord = ChunkCoord {
+            x: chunk.0,
+            z: chunk.1,
+        };
+        let stamp = fs::metadata(chunks::chunk_path(&self.world_dir, coord))
+            .ok()
+            .and_then(|m| Some((m.modified().ok()?, m.len())));
+        let mut baked = self.baked.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(b) = baked.get(&chunk).filter(|b| b.stamp == stamp) {
+            return Ok(b.blocked.clone());
+        }
+        let mut blocked = HashSet::new();
+        if stamp.is_some() {
+            for o in chunks::load_chunk(&self.world_dir, coord)?.objects {
+                rasterize(&o, &mut blocked);
+            }
+        }
+        let blocked = Arc::new(blocked);
+        baked.insert(
+            chunk,
+            Baked {
+                stamp,
+                blocked: blocked.clone(),
+            },
+        );
+        Ok(blocked)
+    }
+
+    /// Waypoints from `from` to `to` around objects: cell centers where the route turns, with
+    /// the exact endpoints first and last. Heights follow `from` (there is no terrain yet).
+    pub fn find_path(&self, from: [f32; 3], to: [f32; 3]) -> Result<Vec<[f32; 3]>> {
+        if !from.iter().chain(&to).all(|v| v.is_finite()) {
+            anyhow::bail!("positions must be finite");
+        }
+        if (to[0] - from[0]).hypot(to[2] - from[2]) > MAX_PATH_DISTANCE_M {
+            anyhow::bail!("paths span at most {MAX_PATH_DISTANCE_M} m");
+        }
+        let start = cell_of(from[0], from[2]);
+        let goal = cell_of(to[0], to[2]);
+
+        // Chunk results for this query, so each chunk file is checked once.
+        let mut seen: HashMap<Cell, Arc<HashSet<Cell>>> = HashMap::new();
+        let mut blocked = |c: Cell| -> Result<bool> {
+            let home = (
+                c.0.div_euclid(CELLS_PER_CHUNK),
+                c.1.div_euclid(CELLS_PER_CHUNK),
+            );
+            for dz in -1..=1 {
+                for dx in -1..=1 {
+                    let chunk = (home.0 + dx, home.1 + dz);
+                    let set = match seen.get(&chunk) {
+                        Some(s) => s.clone(),
+                        None => {
+                            let s = self.chunk_blocked(chunk)?;
+                            seen.insert(chunk, s.clone());
+                            s
+                        }
+                    };
+                    if set.contains(&c) {
+                        return Ok(true);
+                    }
+                }
+            }
+            Ok(false)
+        };
+        if blocked(goal)? {
+            anyhow::bail!("the destination is inside an object");
+        }
+
+        // Costs are 10 per straight step and 14 per diagonal one.
+        let heuristic = |c: Cell| {
+            let (dx, dz) = ((c.0 - goal.0).abs(), (c.1 - goal.1).abs());
+            (10 * dx.max(dz) + 4 * dx.min(dz)) as u32
+        };
+        let mut open = BinaryHeap::new();
+        let mut cost: HashMap<Cell, u32> = HashMap::from([(start, 0)]);
+        let mut came_from: HashMap<Cell, Cell> = HashMap::new();
+        open.push(Reverse((heuristic(start), start)));
+        let mut expansions = 0;
+        while let Some(Reverse((_, c))) = open.pop() {
+            if c == goal {
+                return Ok(waypoints(&came_from, goal, from, to));
+            }
+            expansions += 1;
+            if expansions > MAX_EXPANSIONS {
+                anyhow::bail!("no path found within the search limit");
+            }
+            let here = cost[&c];
+            for (dx, dz) in [
+                (1, 0),
+                (-1, 0),
+                (0, 1),
+                (0, -1),
+                (1, 1),
+                (1, -1),
+                (-1, 1),
+                (-1, -1),
+            ] {
+                let next = (c.0 + dx, c.1 + dz);
+                if blocked(next)? {
+                    continue;
+                }
+                let diagonal = dx != 0 && dz != 0;
+                // No squeezing between two blocked corners.
+                if diagonal && (blocked((c.0 + dx, c.1))? || blocked((c.0, c.1 + dz))?) {
+                    continue;
+                }
+                let step = if diagonal { 14 } else { 10 };
+                if cost.get(&next).is_none_or(|&known| here + step < known) {
+                    cost.insert(next, here + step);
+                    came_from.insert(next, c);
+                    open.push(Reverse((here + step + heuristic(next), next)));
+                }
+            }
+        }
+        anyhow::bail!("no path to the destination")
+    }
+}
+
+/// Walk `came_from` back from `goal` and keep only the cells where the direction changes.
+fn waypoints(
+    came_from: &HashMap<Cell, Cell>,
+    goal: Cell,
+    from: [f32; 3],
+    to: [f32; 3],
+) -> Vec<[f32; 3]> {
+    let mut cells = vec![goal];
+    while let Some(prev) = came_from.get(cells.last().expect("non-empty")) {
+        cells.push(*prev);
+    }
+    cells.reverse();
+    let mut out = vec![from];
+    for w in cells.windows(3) {
+        let d1 = (w[1].0 - w[0].0, w[1].1 - w[0].1);
+        let d2 = (w[2].0 - w[1].0, w[2].1 - w[1].1);
+        if d1 != d2 {
+            let (x, z) = cell_center(w[1]);
+            out.push([x, from[1], z]);
+        }
+    }
+    out.push(to);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wal::{self, WalOp};
+    use owp_protocol::ChunkChangeV1;
+    use std::path::Path;
+
+    fn place(dir: &Path, object: WorldObjectV1) {
+        wal::mutate(
+            dir,
+            WalOp::ChunkChange {
+                chunk: chunks::chunk_for_position(object.position),
+                change: ChunkChangeV1::Upsert { object },
+            },
+        )
+        .expect("place");
+    }
+
+    #[test]
+    fn paths_go_around_walls_and_follow_edits() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let nav = NavGrid::new(dir.path().to_path_buf());
+        let (from, to) = ([0.5, 0.0, 10.5], [20.5, 0.0, 10.5]);
+        assert_eq!(nav.find_path(from, to).expect("open field"), vec![from, to]);
+
+        // A wall across the straight line, spanning the chunk border at z = 0.
+        place(
+            dir.path(),
+            WorldObjectV1 {
+                id: "wall".to_string(),
+                kind: "wall".to_string(),
+                position: [10.0, 0.0, 6.0],
+                rotation: [0.0; 3],
+                scale: [1.0, 3.0, 20.0],
+            },
+        );
+        let path = nav.find_path(from, to).expect("around the wall");
+        assert!(path.len() > 2);
+        assert!(path.iter().any(|p| p[2] > 16.0 || p[2] < -4.0));
+        assert!(path
+            .iter()
+            .all(|p| !((9.2..=10.8).contains(&p[0]) && (-4.3..=16.3).contains(&p[2]))));
+
+        // A flat rug doesn't block; a destination inside an object does.
+        place(
+            dir.path(),
+            WorldObjectV1 {
+                id: "rug".to_string(),
+                kind: "rug".to_string(),
+                position: [25.0, 0.0, 10.0],
+                rotation: [0.0, 45.0, 0.0],
+                scale: [4.0, 0.05, 4.0],
+            },
+        );
+        assert!(nav.find_path(to, [25.0, 0.0, 10.0]).is_ok());
+        assert!(nav.find_path(from, [10.0, 0.0, 6.0]).is_err());
+        assert!(nav.find_path(from, [500.0, 0.0, 0.0]).is_err());
+    }
+}
@@ -0,0 +1,142 @@
+//! Peer-assisted asset distribution: players announce the content-store blobs they cache and
+//! the port they serve them on, and others ask who has a blob before falling back to the host.
+//! Peers are only ever pointed at the address a player connected from.
+
+use rand::seq::SliceRandom;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+use crate::content;
+
+/// Blobs one player may announce.
+pub const MAX_ANNOUNCED: usize = 4096;
+
+/// Peers named in one `PeerList`.
+pub const MAX_PEERS_PER_REPLY: usize = 8;
+
+#[derive(Default)]
+struct Inner {
+    /// Blob -> players serving it.
+    holders: HashMap<String, HashSet<Uuid>>,
+    /// Player -> where it serves, and what.
+    players: HashMap<Uuid, (SocketAddr, Vec<String>)>,
+}
+
+impl Inner {
+    fn withdraw(&mut self, player_id: Uuid) {
+        let Some((_, blobs)) = self.players.remove(&player_id) else {
+            return;
+        };
+        for sha256 in blobs {
+            if let Some(h) = self.holders.get_mut(&sha256) {
+                h.remove(&player_id);
+                if h.is_empty() {
+                    self.holders.remove(&sha256);
+                }
+            }
+        }
+    }
+}
+
+/// Who serves which blobs, for one world.
+#[derive(Clone, Default)]
+pub struct PeerRegistry {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl PeerRegistry {
+    /// Replace what `player_id` serves at `addr`. Malformed hashes are dropped and the list is
+    /// cut to `MAX_ANNOUNCED`; an empty list (or port 0) withdraws the player.
+    pub fn announce(&self, player_id: Uuid, addr: SocketAddr, sha256: Vec<String>) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner.withdraw(player_id);
+        let mut blobs: Vec<String> = sha256
+            .into_iter()
+            .filter(|s| content::is_sha256(s))
+            .map(|s| s.to_ascii_lowercase())
+            .collect();
+        blobs.sort();
+        blobs.dedup();
+        blobs.truncate(MAX_ANNOUNCED);
+        if blobs.is_empty() || addr.port() == 0 {
+            return;
+        }
+        for sha256 in &blobs {
+            inner
+                .holders
+                .entry(sha256.clone())
+                .or_default()
+                .insert(player_id);
+        }
+        inner.players.insert(player_id, (addr, blobs));
+    }
+
+    pub fn withdraw(&self, player_id: Uuid) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner.withdraw(player_id);
+    }
+
+    /// Up to `MAX_PEERS_PER_REPLY` players other than `asker` serving `sha256`, as
+    /// `host:port`, shuffled so joins spread over everyone who has it.
+    pub fn peers_for(&self, sha256: &str, asker: Uuid) -> Vec<String> {
+        let inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(holders) = inner.holders.get(&sha256.to_ascii_lowercase()) else {
+            return vec![];
+        };
+        let mut peers: Vec<String> = holders
+            .iter()
+            .filter(|p| **p != asker)
+            .filter_map(|p| inner.players.get(p))
+            .map(|(addr, _)| addr.to_string())
+            .collect();
+        peers.shuffle(&mut rand::thread_rng());
+        peers.truncate(MAX_PEERS_PER_REPLY);
+        peers
+    }
+}
+
+/// Withdraws a player's announcement when its connection goes away.
+pub struct PeerGuard {
+    pub peers: PeerRegistry,
+    pub player_id: Uuid,
+}
+
+impl Drop for PeerGuard {
+    fn drop(&mut self) {
+        self.peers.withdraw(self.player_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn announcements_replace_and_withdraw() {
+        let peers = PeerRegistry::default();
+        let (a, b, asker) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let (x, y) = (content::hash(b"x"), content::hash(b"y"));
+        let addr = |port| SocketAddr::from(([10, 0, 0, 1], port));
+
+        peers.announce(a, addr(7000), vec![x.clone(), "not a hash".to_string()]);
+        peers.announce(b, addr(7001), vec![x.to_ascii_uppercase(), y.clone()]);
+        let mut got = peers.peers_for(&x, asker);
+        got.sort();
+        assert_eq!(got, ["10.0.0.1:7000", "10.0.0.1:7001"]);
+        assert_eq!(peers.peers_for(&x, a), ["10.0.0.1:7001"]);
+
+        // A new announcement replaces the old one.
+        peers.announce(b, addr(7001), vec![y.clone()]);
+        assert_eq!(peers.peers_for(&x, asker), ["10.0.0.1:7000"]);
+
+        drop(PeerGuard {
+            peers: peers.clone(),
+            player_id: a,
+        });
+        assert!(peers.peers_for(&x, asker).is_empty());
+        peers.announce(b, addr(0), vec![y.clone()]);
+        assert!(peers.peers_for(&y, asker).is_empty());
+    }
+}
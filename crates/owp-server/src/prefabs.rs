@@ -0,0 +1,169 @@
+//! Prefab bundles: the meshes and material metadata under a world's `assets/prefabs/`, packed
+//! into a versioned `PrefabBundleV1` in the content store and referenced from the manifest.
+
+use anyhow::{Context, Result};
+use owp_protocol::{AssetRef, PrefabBundleRef, PrefabBundleV1, PrefabV1};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::assets::assets_dir;
+use crate::content;
+use crate::scatter::check_kind;
+use crate::storage::WorldStore;
+
+/// Mesh formats a prefab may use, by file extension.
+pub const MESH_EXTENSIONS: [&str; 2] = ["glb", "stl"];
+
+/// Most prefabs one bundle may carry.
+pub const MAX_PREFABS: usize = 512;
+
+/// Serializes bundle builds so two can't both bump the same version.
+static BUNDLE_LOCK: Mutex<()> = Mutex::new(());
+
+pub fn prefabs_dir(world_dir: &Path) -> PathBuf {
+    assets_dir(world_dir).join("prefabs")
+}
+
+/// One prefab per `<kind>.<mesh extension>` file, with `<kind>.json` as its materials.
+fn collect(store: &WorldStore, dir: &Path) -> Result<Vec<PrefabV1>> {
+    let mut meshes = BTreeMap::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("read {dir:?}"))? {
+        let path = entry?.path();
+        let (Some(stem), Some(ext)) = (
+            path.file_stem().and_then(|s| s.to_str()),
+            path.extension().and_then(|e| e.to_str()),
+        ) else {
+            continue;
+        };
+        if !MESH_EXTENSIONS.contains(&ext) {
+            continue;
+        }
+        check_kind(stem).with_context(|| format!("prefab file {path:?}"))?;
+        if meshes.insert(stem.to_string(), path.clone()).is_some() {
+            anyhow::bail!("prefab {stem:?} has more than one mesh");
+        }
+    }
+    if meshes.len() > MAX_PREFABS {
+        anyhow::bail!(
+            "{} prefabs; a bundle holds at most {MAX_PREFABS}",
+            meshes.len()
+        );
+    }
+
+    let mut prefabs = Vec::with_capacity(meshes.len());
+    for (kind, path) in meshes {
+        let bytes = fs::read(&path).with_context(|| format!("read {path:?}"))?;
+        let sha256 = content::put(store, &bytes)?;
+        let materials_path = dir.join(format!("{kind}.json"));
+        let materials = if materials_path.exists() {
+            let data = fs::read_to_string(&materials_path)
+                .with_context(|| format!("read {materials_path:?}"))?;
+            serde_json::from_str(&data).with_context(|| format!("parse {materials_path:?}"))?
+        } else {
+            serde_json::Value::Null
+        };
+        prefabs.push(PrefabV1 {
+            kind,
+            mesh: AssetRef {
+                uri: content::content_uri(&sha256),
+                sha256,
+                size: bytes.len() as u64,
+            },
+            materials,
+        });
+    }
+    Ok(prefabs)
+}
+
+/// The bundle the manifest currently points at, if it is still in the content store.
+pub fn current(store: &WorldStore, world_dir: &Path) -> Result<Option<PrefabBundleV1>> {
+    let manifest = store.read_manifest(world_dir)?;
+    let Some(r) = manifest.prefab_bundle else {
+        return Ok(None);
+    };
+    let Some(bytes) = content::get(store, &r.sha256)? else {
+        return Ok(None);
+    };
+    let bundle = serde_json::from_slice(&bytes).context("parse prefab bundle")?;
+    Ok(Some(bundle))
+}
+
+/// Pack `assets/prefabs/` into a bundle and point the manifest at it. The version only moves
+/// when a mesh or its materials changed since the last build.
+pub fn build(store: &WorldStore, world_dir: &Path) -> Result<PrefabBundleRef> {
+    let _guard = BUNDLE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = prefabs_dir(world_dir);
+    let prefabs = if dir.exists() {
+        collect(store, &dir)?
+    } else {
+        vec![]
+    };
+    let mut manifest = store.read_manifest(world_dir)?;
+    if let Some(r) = &manifest.prefab_bundle {
+        if current(store, world_dir)?.is_some_and(|b| b.prefabs == prefabs) {
+            return Ok(r.clone());
+        }
+    }
+    let bundle = PrefabBundleV1 {
+        version: manifest.prefab_bundle.as_ref().map_or(1, |r| r.version + 1),
+        prefabs,
+    };
+    let bytes = serde_json::to_vec(&bundle).context("serialize prefab bundle")?;
+    let r = PrefabBundleRef {
+        version: bundle.version,
+        sha256: content::put(store, &bytes)?,
+        size: bytes.len() as u64,
+    };
+    manifest.prefab_bundle = Some(r.clone());
+    store.write_manifest(world_dir, &manifest)?;
+    Ok(r)
+}
+
+/// The bundle as a `Welcome.prefetch` entry.
+pub fn prefetch_ref(r: &PrefabBundleRef) -> AssetRef {
+    AssetRef {
+        sha256: r.sha256.clone(),
+        size: r.size,
+        uri: content::content_uri(&r.sha256),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundle_versions_follow_prefab_changes() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let store = WorldStore::with_root(root.path().to_path_buf()).expect("store");
+        let manifest = store.create_world("prefabs", 7777).expect("create");
+        let world_dir = store.world_dir(manifest.world_id);
+        let dir = prefabs_dir(&world_dir);
+        fs::create_dir_all(&dir).expect("mkdir");
+        fs::write(dir.join("tree_pine.glb"), b"pine").expect("write");
+        fs::write(dir.join("tree_pine.json"), br##"{"albedo":"#2f6b2f"}"##).expect("write");
+        fs::write(dir.join("rock_large.stl"), b"rock").expect("write");
+        fs::write(dir.join("notes.txt"), b"ignored").expect("write");
+
+        let first = build(&store, &world_dir).expect("build");
+        assert_eq!(first.version, 1);
+        let bundle = current(&store, &world_dir).expect("load").expect("bundle");
+        let kinds: Vec<_> = bundle.prefabs.iter().map(|p| p.kind.as_str()).collect();
+        assert_eq!(kinds, ["rock_large", "tree_pine"]);
+        assert_eq!(bundle.prefabs[1].materials["albedo"], "#2f6b2f");
+        let mesh = content::get(&store, &bundle.prefabs[0].mesh.sha256).expect("get");
+        assert_eq!(mesh.as_deref(), Some(&b"rock"[..]));
+
+        // Unchanged sources keep the version; an edit bumps it.
+        assert_eq!(build(&store, &world_dir).expect("rebuild"), first);
+        fs::write(dir.join("rock_large.stl"), b"rock v2").expect("write");
+        let second = build(&store, &world_dir).expect("rebuild");
+        assert_eq!(second.version, 2);
+        assert_ne!(second.sha256, first.sha256);
+
+        fs::write(dir.join("Bad Name.glb"), b"x").expect("write");
+        assert!(build(&store, &world_dir).is_err());
+    }
+}
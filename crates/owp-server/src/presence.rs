@@ -0,0 +1,352 @@
+use owp_protocol::{AvatarSpecV1, ChunkCoord, Message, PlayerSnapshot};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::chunks::chunk_for_position;
+
+/// Players within this many chunks (per axis) of each other are "nearby".
+pub const INTEREST_RADIUS_CHUNKS: i32 = 2;
+
+/// Outgoing messages buffered per connection; relayed messages beyond this are dropped for
+/// that player rather than stalling the sender.
+pub const OUTBOX_CAPACITY: usize = 256;
+
+struct Player {
+    position: Option<[f32; 3]>,
+    chunk: Option<ChunkCoord>,
+    outbox: mpsc::Sender<Message>,
+    /// Wallet pubkey and/or profile id the player goes by.
+    ids: Vec<String>,
+    /// Ids on the player's friends list.
+    friends: HashSet<String>,
+    /// Whether friends hear about the player (its profile's `appear_in_rosters`).
+    listed: bool,
+    /// Last avatar announced, kept only if the profile shares it (`share_avatar`).
+    avatar: Option<AvatarSpecV1>,
+}
+
+fn near(a: ChunkCoord, b: ChunkCoord) -> bool {
+    (a.x - b.x).abs() <= INTEREST_RADIUS_CHUNKS && (a.z - b.z).abs() <= INTEREST_RADIUS_CHUNKS
+}
+
+/// Players connected to one world, where they stand, and how to reach them.
+#[derive(Clone, Default)]
+pub struct Presence {
+    players: Arc<Mutex<HashMap<Uuid, Player>>>,
+}
+
+impl Presence {
+    pub fn join(&self, player_id: Uuid, outbox: mpsc::Sender<Message>) {
+        let mut players = self.players.lock().unwrap_or_else(|e| e.into_inner());
+        players.insert(
+            player_id,
+            Player {
+                position: None,
+                chunk: None,
+                outbox,
+                ids: vec![],
+                friends: HashSet::new(),
+                listed: true,
+                avatar: None,
+            },
+        );
+    }
+
+    /// Everyone connected but `except`, with their last position.
+    pub fn players(&self, except: Uuid) -> Vec<PlayerSnapshot> {
+        let players = self.players.lock().unwrap_or_else(|e| e.into_inner());
+        players
+            .iter()
+            .filter(|(id, _)| **id != except)
+            .map(|(id, p)| PlayerSnapshot {
+                player_id: *id,
+                position: p.position,
+                avatar: p.avatar.clone(),
+            })
+            .collect()
+    }
+
+    pub fn set_avatar(&self, player_id: Uuid, avatar: AvatarSpecV1) {
+        let mut players = self.players.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(p) = players.get_mut(&player_id) {
+            p.avatar = Some(avatar);
+        }
+    }
+
+    pub fn position(&self, player_id: Uuid) -> Option<[f32; 3]> {
+        let players = self.players.lock().unwrap_or_else(|e| e.into_inner());
+        players.get(&player_id).and_then(|p| p.position)
+    }
+
+    pub fn avatar(&self, player_id: Uuid) -> Option<AvatarSpecV1> {
+        let players = self.players.lock().unwrap_or_else(|e| e.into_inner());
+        players.get(&player_id).and_then(|p| p.avatar.clone())
+    }
+
+    pub fn leave(&self, player_id: Uuid) {
+        let mut players = self.players.lock().unwrap_or_else(|e| e.into_inner());
+        players.remove(&player_id);
+    }
+
+    pub fn set_position(&self, player_id: Uuid, position: [f32; 3]) {
+        let mut players = self.players.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(p) = players.get_mut(&player_id) {
+            p.position = Some(position);
+            p.chunk = Some(chunk_for_position(position));
+        }
+    }
+
+    pub fn identify(
+        &self,
+        player_id: Uuid,
+        ids: Vec<String>,
+        friends: HashSet<String>,
+        listed: bool,
+    ) {
+        let mut players = self.players.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(p) = players.get_mut(&player_id) {
+            p.ids = ids;
+            p.friends = friends;
+            p.listed = listed;
+        }
+    }
+
+    /// Players going by `id`.
+    pub fn find(&self, id: &str) -> Vec<Uuid> {
+        self.find_where(id, |_| true)
+    }
+
+    /// Players going by `id` that appear in rosters.
+    pub fn find_listed(&self, id: &str) -> Vec<Uuid> {
+        self.find_where(id, |p| p.listed)
+    }
+
+    fn find_where(&self, id: &str, keep: impl Fn(&Player) -> bool) -> Vec<Uuid> {
+        let players = self.players.lock().unwrap_or_else(|e| e.into_inner());
+        players
+            .iter()
+            .filter(|(_, p)| p.ids.iter().any(|i| i == id) && keep(p))
+            .map(|(pid, _)| *pid)
+            .collect()
+    }
+
+    /// Change whether players going by `id` appear in rosters, e.g. after a privacy change.
+    /// Friends aren't told; it shows from the next arrival or departure on.
+    pub fn set_listed(&self, id: &str, listed: bool) {
+        let mut players = self.players.lock().unwrap_or_else(|e| e.into_inner());
+        for p in players
+            .values_mut()
+            .filter(|p| p.ids.iter().any(|i| i == id))
+        {
+            p.listed = listed;
+        }
+    }
+
+    /// Other players that have `player_id` on their friends list, with the id they know it by.
+    /// Nobody, when `player_id` doesn't appear in rosters.
+    pub fn watchers(&self, player_id: Uuid) -> Vec<(Uuid, String)> {
+        let players = self.players.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(me) = players.get(&player_id).filter(|me| me.listed) else {
+            return vec![];
+        };
+        players
+            .iter()
+            .filter(|(other, _)| **other != player_id)
+            .filter_map(|(other, p)| {
+                let id = me.ids.iter().find(|i| p.friends.contains(*i))?;
+                Some((*other, id.clone()))
+            })
+            .collect()
+    }
+
+    /// Other players that are on `player_id`'s friends list, with the id they're listed by.
+    /// Players that don't appear in rosters are left out.
+    pub fn friends_here(&self, player_id: Uuid) -> Vec<(Uuid, String)> {
+        let players = self.players.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(me) = players.get(&player_id) else {
+            return vec![];
+        };
+        players
+            .iter()
+            .filter(|(other, p)| **other != player_id && p.listed)
+            .filter_map(|(other, p)| {
+                let id = p.ids.iter().find(|i| me.friends.contains(*i))?;
+                Some((*other, id.clone()))
+            })
+            .collect()
+    }
+
+    /// Send to every other player in the interest area around `from`. Players that haven't
+    /// reported a position yet are nowhere, so they neither send nor receive. Returns how many
+    /// players got the message.
+    pub fn send_nearby(&self, from: Uuid, msg: &Message) -> usize {
+        self.send_within(from, None, msg)
+    }
+
+    /// Like `send_nearby`, limited to players at most `radius_m` meters from `from`. The
+    /// radius is cut off at the interest area.
+    pub fn send_within(&self, from: Uuid, radius_m: Option<f32>, msg: &Message) -> usize {
+        let players = self.players.lock().unwrap_or_else(|e| e.into_inner());
+        let Some((origin, at)) = players
+            .get(&from)
+            .and_then(|p| Some((p.chunk?, p.position?)))
+        else {
+            return 0;
+        };
+        let mut sent = 0;
+        for (id, p) in players.iter() {
+            if *id == from || !p.chunk.is_some_and(|c| near(origin, c)) {
+                continue;
+            }
+            if let (Some(r), Some(pos)) = (radius_m, p.position) {
+                let d2: f32 = (0..3).map(|k| (pos[k] - at[k]).powi(2)).sum();
+                if d2 > r * r {
+                    continue;
+                }
+            }
+            if p.outbox.try_send(msg.clone()).is_ok() {
+                sent += 1;
+            }
+        }
+        sent
+    }
+
+    /// Send to one player; `false` if it isn't connected or its outbox is full.
+    pub fn send_to(&self, player_id: Uuid, msg: &Message) -> bool {
+        let players = self.players.lock().unwrap_or_else(|e| e.into_inner());
+        players
+            .get(&player_id)
+            .is_some_and(|p| p.outbox.try_send(msg.clone()).is_ok())
+    }
+
+    /// Send to every player in the world except `except`.
+    pub fn send_all(&self, except: Option<Uuid>, msg: &Message) -> usize {
+        let players = self.players.lock().unwrap_or_else(|e| e.into_inner());
+        players
+            .iter()
+            .filter(|(id, _)| Some(**id) != except)
+            .filter(|(_, p)| p.outbox.try_send(msg.clone()).is_ok())
+            .count()
+    }
+}
+
+/// Presence of every world served by this process, for lookups across worlds.
+#[derive(Clone, Default)]
+pub struct Roster(Arc<Mutex<HashMap<Uuid, Presence>>>);
+
+impl Roster {
+    /// The presence hub of `world_id`, created on first use.
+    pub fn world(&self, world_id: Uuid) -> Presence {
+        let mut worlds = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        worlds.entry(world_id).or_default().clone()
+    }
+
+    /// Send to every player in every world; returns how many it reached.
+    pub fn send_all(&self, msg: &Message) -> usize {
+        let worlds = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        worlds.values().map(|p| p.send_all(None, msg)).sum()
+    }
+
+    /// Worlds and player ids of everyone going by `id`.
+    pub fn find(&self, id: &str) -> Vec<(Uuid, Uuid)> {
+        let worlds = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        worlds
+            .iter()
+            .flat_map(|(world_id, p)| p.find(id).into_iter().map(|pid| (*world_id, pid)))
+            .collect()
+    }
+
+    /// Like `find`, leaving out players that don't appear in rosters.
+    pub fn find_listed(&self, id: &str) -> Vec<(Uuid, Uuid)> {
+        let worlds = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        worlds
+            .iter()
+            .flat_map(|(world_id, p)| p.find_listed(id).into_iter().map(|pid| (*world_id, pid)))
+            .collect()
+    }
+
+    /// `Presence::set_listed` in every world.
+    pub fn set_listed(&self, id: &str, listed: bool) {
+        let worlds = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        for p in worlds.values() {
+            p.set_listed(id, listed);
+        }
+    }
+}
+
+/// Removes a player from the world when its connection goes away.
+pub struct PresenceGuard {
+    pub presence: Presence,
+    pub player_id: Uuid,
+}
+
+impl Drop for PresenceGuard {
+    fn drop(&mut self) {
+        self.presence.leave(self.player_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use owp_protocol::Emote;
+
+    #[test]
+    fn only_nearby_players_hear_it() {
+        let presence = Presence::default();
+        let mut rx = HashMap::new();
+        let ids: Vec<Uuid> = (0..4).map(|_| Uuid::new_v4()).collect();
+        for id in &ids {
+            let (tx, r) = mpsc::channel(OUTBOX_CAPACITY);
+            presence.join(*id, tx);
+            rx.insert(*id, r);
+        }
+        presence.set_position(ids[0], [0.0, 0.0, 0.0]);
+        presence.set_position(ids[1], [60.0, 0.0, -40.0]); // two chunks away
+        presence.set_position(ids[2], [200.0, 0.0, 0.0]); // too far
+                                                          // ids[3] never reported a position.
+
+        let wave = Message::Emote(Emote {
+            id: "wave".to_string(),
+            duration_ms: 1000,
+            player_id: Some(ids[0]),
+        });
+        assert_eq!(presence.send_nearby(ids[0], &wave), 1);
+        assert!(rx.get_mut(&ids[1]).expect("rx").try_recv().is_ok());
+        for id in [ids[0], ids[2], ids[3]] {
+            assert!(rx.get_mut(&id).expect("rx").try_recv().is_err());
+        }
+
+        presence.leave(ids[1]);
+        assert_eq!(presence.send_nearby(ids[0], &wave), 0);
+    }
+
+    #[test]
+    fn snapshots_carry_announced_avatars() {
+        let presence = Presence::default();
+        let (me, other) = (Uuid::new_v4(), Uuid::new_v4());
+        for id in [me, other] {
+            let (tx, _rx) = mpsc::channel(OUTBOX_CAPACITY);
+            presence.join(id, tx);
+        }
+        let avatar: AvatarSpecV1 = serde_json::from_value(serde_json::json!({
+            "version": "v1",
+            "name": "Scout",
+            "primary_color": "#00D1FF",
+            "secondary_color": "#FFFFFF",
+            "height": 1.0,
+        }))
+        .expect("avatar");
+        presence.set_avatar(other, avatar);
+        assert!(presence.avatar(me).is_none());
+        let players = presence.players(me);
+        assert_eq!(players.len(), 1);
+        assert_eq!(players[0].player_id, other);
+        assert_eq!(
+            players[0].avatar.as_ref().map(|a| a.name.as_str()),
+            Some("Scout")
+        );
+    }
+}
@@ -0,0 +1,128 @@
+//! Per-profile privacy settings (`profiles/<id>/privacy.json`). Everything is shared by default,
+//! matching how profiles behaved before the settings existed.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tracing::warn;
+
+use crate::friends::check_profile_id;
+use crate::storage::WorldStore;
+use crate::versioned::{self, Versioned};
+
+/// Serializes read-modify-write of privacy files within this process.
+static PRIVACY_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrivacyV1 {
+    /// Let other accounts and players see the profile's avatar; otherwise only the profile
+    /// itself and admins can.
+    #[serde(default = "default_true")]
+    pub share_avatar: bool,
+    /// Show up in friends' presence: `friend_presence` messages and `/friends/presence`.
+    #[serde(default = "default_true")]
+    pub appear_in_rosters: bool,
+    /// Keep companion chat history between requests. Turning it off deletes what was kept.
+    #[serde(default = "default_true")]
+    pub retain_companion_history: bool,
+}
+
+impl Versioned for PrivacyV1 {
+    const VERSION: u32 = 1;
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for PrivacyV1 {
+    fn default() -> Self {
+        Self {
+            share_avatar: true,
+            appear_in_rosters: true,
+            retain_companion_history: true,
+        }
+    }
+}
+
+/// A partial update; unset fields keep their value.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PrivacyPatch {
+    #[serde(default)]
+    pub share_avatar: Option<bool>,
+    #[serde(default)]
+    pub appear_in_rosters: Option<bool>,
+    #[serde(default)]
+    pub retain_companion_history: Option<bool>,
+}
+
+impl PrivacyPatch {
+    pub fn apply(&self, p: &mut PrivacyV1) {
+        if let Some(v) = self.share_avatar {
+            p.share_avatar = v;
+        }
+        if let Some(v) = self.appear_in_rosters {
+            p.appear_in_rosters = v;
+        }
+        if let Some(v) = self.retain_companion_history {
+            p.retain_companion_history = v;
+        }
+    }
+}
+
+pub fn privacy_path(store: &WorldStore, profile_id: &str) -> PathBuf {
+    store.profiles_root().join(profile_id).join("privacy.json")
+}
+
+pub fn load(store: &WorldStore, profile_id: &str) -> Result<PrivacyV1> {
+    check_profile_id(profile_id)?;
+    let path = privacy_path(store, profile_id);
+    Ok(versioned::load(&path)?.unwrap_or_default())
+}
+
+/// Settings for `profile_id`, or the defaults when they can't be read. For checks on paths
+/// where failing outright would be worse than the default.
+pub fn load_or_default(store: &WorldStore, profile_id: &str) -> PrivacyV1 {
+    load(store, profile_id).unwrap_or_else(|e| {
+        warn!("privacy settings of {profile_id:?} unreadable: {e:#}");
+        PrivacyV1::default()
+    })
+}
+
+pub fn update(store: &WorldStore, profile_id: &str, patch: &PrivacyPatch) -> Result<PrivacyV1> {
+    let _guard = PRIVACY_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut p = load(store, profile_id)?;
+    patch.apply(&mut p);
+    let path = privacy_path(store, profile_id);
+    versioned::save(&path, &p)?;
+    Ok(p)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patches_change_only_what_they_name() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let store = WorldStore::with_root(root.path().to_path_buf()).expect("store");
+        assert_eq!(load(&store, "alice").expect("load"), PrivacyV1::default());
+        assert!(load(&store, "../etc").is_err());
+
+        let patch = PrivacyPatch {
+            appear_in_rosters: Some(false),
+            ..PrivacyPatch::default()
+        };
+        let p = update(&store, "alice", &patch).expect("update");
+        assert!(!p.appear_in_rosters);
+        assert!(p.share_avatar && p.retain_companion_history);
+        assert_eq!(load(&store, "alice").expect("load"), p);
+        assert_eq!(load(&store, "bob").expect("load"), PrivacyV1::default());
+
+        let patch: PrivacyPatch =
+            serde_json::from_str(r#"{"share_avatar": false}"#).expect("patch");
+        let p = update(&store, "alice", &patch).expect("update");
+        assert!(!p.share_avatar && !p.appear_in_rosters);
+    }
+}
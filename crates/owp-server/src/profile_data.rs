@@ -0,0 +1,296 @@
+//! Everything the host keeps about one profile, for handing it over (`export`) or deleting it
+//! (`erase`): the profile directory (avatar and its revisions, meshes, wardrobe, companion
+//! history, friends, privacy), its ledger account and explored chunks in each world, and the
+//! content-store blobs its avatars reference.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::assets::AssetIndex;
+use crate::backup;
+use crate::content;
+use crate::friends::check_profile_id;
+use crate::ledger;
+use crate::migration;
+use crate::minimap;
+use crate::storage::WorldStore;
+use crate::wal::{self, WalOp};
+
+#[derive(Debug, Default, Serialize)]
+pub struct EraseReport {
+    /// Files removed from the profile directory.
+    pub profile_files: usize,
+    /// Worlds whose ledger or exploration record held the profile.
+    pub worlds: usize,
+    /// Content-store blobs removed; blobs something else still uses are kept.
+    pub blobs: usize,
+}
+
+fn profile_dir(store: &WorldStore, profile_id: &str) -> PathBuf {
+    store.profiles_root().join(profile_id)
+}
+
+/// Content hashes in the `/content/<sha256>` URIs `text` mentions.
+fn content_refs(text: &str, into: &mut BTreeSet<String>) {
+    for (i, _) in text.match_indices("/content/") {
+        let rest = &text[i + "/content/".len()..];
+        if let Some(sha256) = rest.get(..64).filter(|h| content::is_sha256(h)) {
+            into.insert(sha256.to_ascii_lowercase());
+        }
+    }
+}
+
+/// Blobs referenced from the JSON files directly in `dir`.
+fn refs_in_dir(dir: &Path, into: &mut BTreeSet<String>) -> Result<()> {
+    if !dir.exists() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir).with_context(|| format!("read {dir:?}"))? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|e| e == "json") {
+            let text = fs::read_to_string(&path).with_context(|| format!("read {path:?}"))?;
+            content_refs(&text, into);
+        }
+    }
+    Ok(())
+}
+
+/// Blobs the profile's avatars, revisions and outfits reference.
+fn profile_blobs(store: &WorldStore, profile_id: &str) -> Result<BTreeSet<String>> {
+    let mut refs = BTreeSet::new();
+    refs_in_dir(&profile_dir(store, profile_id), &mut refs)?;
+    Ok(refs)
+}
+
+/// Blobs still needed by other profiles or by any world's assets.
+fn blobs_in_use(store: &WorldStore, except: &str) -> Result<BTreeSet<String>> {
+    let mut refs = BTreeSet::new();
+    let root = store.profiles_root();
+    if root.exists() {
+        for entry in fs::read_dir(&root).with_context(|| format!("read {root:?}"))? {
+            let entry = entry?;
+            if entry.file_name() != except && entry.file_type()?.is_dir() {
+                refs_in_dir(&entry.path(), &mut refs)?;
+            }
+        }
+    }
+    for m in store.list_worlds()? {
+        let sync = AssetIndex::default().sync_manifest(store, &store.world_dir(m.world_id))?;
+        refs.extend(
+            sync.entries
+                .into_iter()
+                .map(|e| e.sha256.to_ascii_lowercase()),
+        );
+    }
+    Ok(refs)
+}
+
+fn append_json<T: Serialize>(
+    tar: &mut tar::Builder<Vec<u8>>,
+    name: &Path,
+    value: &T,
+) -> Result<()> {
+    let json = serde_json::to_vec_pretty(value).context("serialize export entry")?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(json.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    tar.append_data(&mut header, name, json.as_slice())
+        .with_context(|| format!("archive {name:?}"))
+}
+
+/// Tar of everything kept about `profile_id`: `profile/` holds its directory,
+/// `worlds/<world_id>/` its `ledger.json` account and `discovered.json` exploration, and
+/// `content/<sha256>` the blobs its avatars reference.
+pub fn export(store: &WorldStore, profile_id: &str) -> Result<Vec<u8>> {
+    check_profile_id(profile_id)?;
+    let mut tar = tar::Builder::new(Vec::new());
+    let dir = profile_dir(store, profile_id);
+    let mut files = 0;
+    if dir.exists() {
+        files += backup::append_tree(&mut tar, &dir, Path::new("profile"))?;
+    }
+    for m in store.list_worlds()? {
+        let world_dir = store.world_dir(m.world_id);
+        let name = Path::new("worlds").join(m.world_id.to_string());
+        if let Some(account) = ledger::load_ledger(&world_dir)?.accounts.get(profile_id) {
+            append_json(&mut tar, &name.join("ledger.json"), account)?;
+            files += 1;
+        }
+        let discovered = minimap::discovered_path(&world_dir, profile_id);
+        if discovered.exists() {
+            tar.append_path_with_name(&discovered, name.join("discovered.json"))
+                .with_context(|| format!("archive {discovered:?}"))?;
+            files += 1;
+        }
+    }
+    for sha256 in profile_blobs(store, profile_id)? {
+        let path = content::content_path(store, &sha256);
+        if path.exists() {
+            tar.append_path_with_name(&path, Path::new("content").join(&sha256))
+                .with_context(|| format!("archive {path:?}"))?;
+            files += 1;
+        }
+    }
+    let bytes = tar.into_inner().context("finish archive")?;
+    info!(
+        "exported profile {profile_id:?}: {files} files, {} bytes",
+        bytes.len()
+    );
+    Ok(bytes)
+}
+
+/// Delete everything `export` would hand over. Refuses, before touching anything, while a world
+/// holding the profile's ledger account is frozen for a migration.
+pub fn erase(store: &WorldStore, profile_id: &str) -> Result<EraseReport> {
+    check_profile_id(profile_id)?;
+    let mut report = EraseReport::default();
+    let mut ledgers = vec![];
+    let mut explored = vec![];
+    for m in store.list_worlds()? {
+        let world_dir = store.world_dir(m.world_id);
+        let has_account = ledger::load_ledger(&world_dir)?
+            .accounts
+            .contains_key(profile_id);
+        let discovered = minimap::discovered_path(&world_dir, profile_id);
+        let has_explored = discovered.exists();
+        if has_account {
+            migration::check_writable(&world_dir)?;
+            ledgers.push(world_dir);
+        }
+        if has_explored {
+            explored.push(discovered);
+        }
+        if has_account || has_explored {
+            report.worlds += 1;
+        }
+    }
+
+    let blobs = profile_blobs(store, profile_id)?;
+    for world_dir in &ledgers {
+        let op = WalOp::EraseAccount {
+            profile_id: profile_id.to_string(),
+        };
+        wal::mutate(world_dir, op)?;
+        // The log still holds the account's earlier changes until it is checkpointed.
+        wal::checkpoint(world_dir)?;
+    }
+    for path in &explored {
+        fs::remove_file(path).with_context(|| format!("remove {path:?}"))?;
+    }
+    let dir = profile_dir(store, profile_id);
+    if dir.exists() {
+        report.profile_files = count_files(&dir)?;
+        fs::remove_dir_all(&dir).with_context(|| format!("remove {dir:?}"))?;
+    }
+    let in_use = blobs_in_use(store, profile_id)?;
+    for sha256 in blobs.difference(&in_use) {
+        let path = content::content_path(store, sha256);
+        if path.exists() {
+            fs::remove_file(&path).with_context(|| format!("remove {path:?}"))?;
+            report.blobs += 1;
+        }
+    }
+    info!("erased profile {profile_id:?}: {report:?}");
+    Ok(report)
+}
+
+fn count_files(dir: &Path) -> Result<usize> {
+    let mut n = 0;
+    for entry in fs::read_dir(dir).with_context(|| format!("read {dir:?}"))? {
+        let entry = entry?;
+        n += if entry.file_type()?.is_dir() {
+            count_files(&entry.path())?
+        } else {
+            1
+        };
+    }
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use owp_protocol::ChunkCoord;
+
+    #[test]
+    fn erase_removes_what_export_hands_over() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let store = WorldStore::with_root(root.path().to_path_buf()).expect("store");
+        let world = store.create_world("Erasable", 7777).expect("world");
+        let world_dir = store.world_dir(world.world_id);
+
+        let own = content::put(&store, b"alice mesh").expect("put");
+        let shared = content::put(&store, b"shared mesh").expect("put");
+        let avatar = |shas: &[&str]| {
+            let uris: Vec<String> = shas.iter().map(|s| content::content_uri(s)).collect();
+            serde_json::to_string(&uris).expect("json")
+        };
+        for (profile, shas) in [("alice", vec![&own, &shared]), ("bob", vec![&shared])] {
+            let dir = store.profiles_root().join(profile);
+            fs::create_dir_all(&dir).expect("mkdir");
+            let shas: Vec<&str> = shas.iter().map(|s| s.as_str()).collect();
+            fs::write(dir.join("avatar.json"), avatar(&shas)).expect("write");
+        }
+        fs::write(
+            store.profiles_root().join("alice/companion_history.json"),
+            "[]",
+        )
+        .expect("write");
+        wal::mutate(
+            &world_dir,
+            WalOp::Inventory {
+                profile_id: "alice".into(),
+                item: "sword".into(),
+                delta: 1,
+            },
+        )
+        .expect("grant");
+        minimap::discover(&world_dir, "alice", ChunkCoord { x: 0, z: 0 }).expect("explore");
+
+        let archive = export(&store, "alice").expect("export");
+        let mut names: Vec<String> = tar::Archive::new(archive.as_slice())
+            .entries()
+            .expect("entries")
+            .map(|e| {
+                e.expect("entry")
+                    .path()
+                    .expect("path")
+                    .display()
+                    .to_string()
+            })
+            .collect();
+        names.sort();
+        let w = world.world_id;
+        let mut expected = vec![
+            format!("content/{own}"),
+            format!("content/{shared}"),
+            "profile/avatar.json".to_string(),
+            "profile/companion_history.json".to_string(),
+            format!("worlds/{w}/discovered.json"),
+            format!("worlds/{w}/ledger.json"),
+        ];
+        expected.sort();
+        assert_eq!(names, expected);
+
+        let report = erase(&store, "alice").expect("erase");
+        assert_eq!(
+            (report.profile_files, report.worlds, report.blobs),
+            (2, 1, 1)
+        );
+        assert!(!store.profiles_root().join("alice").exists());
+        assert!(!content::content_path(&store, &own).exists());
+        // Bob's avatar still uses it.
+        assert!(content::content_path(&store, &shared).exists());
+        assert!(!minimap::discovered_path(&world_dir, "alice").exists());
+        let ledger = ledger::load_ledger(&world_dir).expect("ledger");
+        assert!(!ledger.accounts.contains_key("alice"));
+        // Nothing left in the log could bring the account back.
+        assert_eq!(wal::recover(&world_dir).expect("recover"), 0);
+        assert!(erase(&store, "../etc").is_err());
+    }
+}
@@ -0,0 +1,219 @@
+//! Health of the assistant providers. Every run of a provider's CLI is recorded; after
+//! `UNHEALTHY_AFTER` failures in a row the provider is unhealthy and requests to it fail at once
+//! instead of each waiting out the CLI. One request per `PROBE_INTERVAL` still goes through as a
+//! probe, and the provider is healthy again once one succeeds.
+//!
+//! Changes are logged and broadcast; the admin server posts them to `assistant_health_webhook`
+//! (server.json).
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+use crate::assistant::{AssistantError, AssistantProviderId};
+use crate::config::LiveConfig;
+
+/// Failures in a row that make a provider unhealthy.
+const UNHEALTHY_AFTER: u32 = 3;
+
+/// How often an unhealthy provider gets a request through to find out whether it recovered.
+const PROBE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Longest failure reason kept; CLI stderr can be long.
+const MAX_REASON_CHARS: usize = 500;
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+static HEALTH: Mutex<BTreeMap<&'static str, Health>> = Mutex::new(BTreeMap::new());
+
+static CHANGES: OnceLock<broadcast::Sender<HealthChange>> = OnceLock::new();
+
+#[derive(Debug, Clone, Default)]
+struct Health {
+    consecutive_failures: u32,
+    last_error: Option<String>,
+    last_latency: Option<Duration>,
+    /// Set while unhealthy.
+    unhealthy_since: Option<OffsetDateTime>,
+    /// When the last request was let through as a probe.
+    last_probe: Option<Instant>,
+}
+
+impl Health {
+    /// Whether a request may run now. While unhealthy, only one per `PROBE_INTERVAL` does.
+    fn admit(&mut self, now: Instant) -> bool {
+        if self.unhealthy_since.is_none() {
+            return true;
+        }
+        if self
+            .last_probe
+            .is_some_and(|at| now.duration_since(at) < PROBE_INTERVAL)
+        {
+            return false;
+        }
+        self.last_probe = Some(now);
+        true
+    }
+
+    /// Record a finished run; `Some(healthy)` when that changed the provider's health.
+    fn record(&mut self, latency: Duration, error: Option<String>) -> Option<bool> {
+        self.last_latency = Some(latency);
+        let Some(error) = error else {
+            self.consecutive_failures = 0;
+            self.last_error = None;
+            self.last_probe = None;
+            return self.unhealthy_since.take().map(|_| true);
+        };
+        self.consecutive_failures += 1;
+        self.last_error = Some(error.chars().take(MAX_REASON_CHARS).collect());
+        if self.unhealthy_since.is_none() && self.consecutive_failures >= UNHEALTHY_AFTER {
+            self.unhealthy_since = Some(OffsetDateTime::now_utc());
+            return Some(false);
+        }
+        None
+    }
+}
+
+/// A provider's health as `/assistant/status` shows it.
+#[derive(Debug, Clone, Default)]
+pub struct HealthReport {
+    pub healthy: bool,
+    pub consecutive_failures: u32,
+    /// Why the last run failed, while the provider keeps failing.
+    pub failure: Option<String>,
+    pub last_latency_ms: Option<u64>,
+}
+
+/// A provider turning unhealthy or recovering.
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthChange {
+    pub provider: &'static str,
+    pub healthy: bool,
+    /// The failure that made it unhealthy.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub at: OffsetDateTime,
+}
+
+fn changes() -> &'static broadcast::Sender<HealthChange> {
+    CHANGES.get_or_init(|| broadcast::channel(64).0)
+}
+
+/// Fail fast while `provider` is unhealthy, unless this request is its probe.
+pub fn admit(provider: AssistantProviderId) -> Result<(), AssistantError> {
+    let mut health = HEALTH.lock().unwrap_or_else(|e| e.into_inner());
+    let h = health.entry(provider.as_str()).or_default();
+    if h.admit(Instant::now()) {
+        return Ok(());
+    }
+    Err(AssistantError::ProviderUnavailable(format!(
+        "{} is unhealthy after {} failures in a row (retried every {}s): {}",
+        provider.as_str(),
+        h.consecutive_failures,
+        PROBE_INTERVAL.as_secs(),
+        h.last_error.as_deref().unwrap_or("unknown error"),
+    )))
+}
+
+/// Record how a run of `provider` went.
+pub fn record(provider: AssistantProviderId, latency: Duration, error: Option<&AssistantError>) {
+    let id = provider.as_str();
+    let changed = {
+        let mut health = HEALTH.lock().unwrap_or_else(|e| e.into_inner());
+        let h = health.entry(id).or_default();
+        h.record(latency, error.map(|e| e.to_string()))
+            .map(|healthy| HealthChange {
+                provider: id,
+                healthy,
+                reason: h.last_error.clone().filter(|_| !healthy),
+                at: OffsetDateTime::now_utc(),
+            })
+    };
+    let Some(change) = changed else {
+        return;
+    };
+    match &change.reason {
+        Some(reason) => warn!("assistant provider {id} is unhealthy: {reason}"),
+        None => info!("assistant provider {id} is healthy again"),
+    }
+    // Nobody listening is fine.
+    let _ = changes().send(change);
+}
+
+pub fn report(provider: AssistantProviderId) -> HealthReport {
+    let health = HEALTH.lock().unwrap_or_else(|e| e.into_inner());
+    let h = health.get(provider.as_str()).cloned().unwrap_or_default();
+    HealthReport {
+        healthy: h.unhealthy_since.is_none(),
+        consecutive_failures: h.consecutive_failures,
+        failure: h.last_error,
+        last_latency_ms: h.last_latency.map(|d| d.as_millis() as u64),
+    }
+}
+
+/// Post every health change to `assistant_health_webhook`, as configured at the time.
+pub fn spawn_webhook(config: LiveConfig) {
+    let mut rx = changes().subscribe();
+    tokio::spawn(async move {
+        let client = reqwest::Client::new();
+        loop {
+            let change = match rx.recv().await {
+                Ok(change) => change,
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("dropped {n} provider health changes");
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            };
+            let Some(url) = config.current().assistant_health_webhook else {
+                continue;
+            };
+            let sent = client
+                .post(&url)
+                .timeout(WEBHOOK_TIMEOUT)
+                .json(&change)
+                .send()
+                .await
+                .and_then(|r| r.error_for_status());
+            if let Err(e) = sent {
+                warn!("posting provider health to {url} failed: {e}");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failing_providers_are_probed_until_one_run_succeeds() {
+        let mut h = Health::default();
+        let t0 = Instant::now();
+        let ms = Duration::from_millis;
+        assert!(h.admit(t0));
+        assert_eq!(h.record(ms(5), Some("spawn codex: not found".into())), None);
+        assert_eq!(h.record(ms(5), Some("spawn codex: not found".into())), None);
+        assert_eq!(
+            h.record(ms(5), Some("spawn codex: not found".into())),
+            Some(false)
+        );
+        assert_eq!(h.last_error.as_deref(), Some("spawn codex: not found"));
+
+        // One probe goes through, then nothing until the interval passes.
+        assert!(h.admit(t0));
+        assert!(!h.admit(t0 + ms(10)));
+        assert_eq!(h.record(ms(5), Some("still broken".into())), None);
+        assert!(!h.admit(t0 + PROBE_INTERVAL - ms(1)));
+        assert!(h.admit(t0 + PROBE_INTERVAL));
+        assert_eq!(h.record(ms(900), None), Some(true));
+        assert_eq!(h.consecutive_failures, 0);
+        assert!(h.admit(t0 + PROBE_INTERVAL));
+        assert!(h.admit(t0 + PROBE_INTERVAL));
+    }
+}
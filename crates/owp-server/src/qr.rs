@@ -0,0 +1,567 @@
+//! QR codes for connect strings and admin pairing, so another device (a phone, the couch PC) can
+//! join by scanning. Byte mode, error correction level M, versions 1-13 (up to 331 bytes); the
+//! encoder follows the layout of ISO/IEC 18004 as implemented by Nayuki's reference library.
+
+use anyhow::{Context, Result};
+use std::fmt::Write as _;
+
+/// (total codewords, EC codewords per block, blocks) at level M, per version.
+const VERSIONS_M: [(usize, usize, usize); 13] = [
+    (26, 10, 1),
+    (44, 16, 1),
+    (70, 26, 1),
+    (100, 18, 2),
+    (134, 24, 2),
+    (172, 16, 4),
+    (196, 18, 4),
+    (242, 22, 4),
+    (292, 22, 5),
+    (346, 26, 5),
+    (404, 30, 5),
+    (466, 22, 8),
+    (532, 22, 9),
+];
+
+/// Light modules around the code that scanners need.
+const QUIET_ZONE: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum QrFormat {
+    Svg,
+    Png,
+    /// Unicode half blocks, for terminals.
+    Text,
+}
+
+impl QrFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Svg => "image/svg+xml",
+            Self::Png => "image/png",
+            Self::Text => "text/plain; charset=utf-8",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct QrCode {
+    size: usize,
+    /// Row-major, `true` = dark.
+    modules: Vec<bool>,
+    /// Finder, timing, alignment, format and version modules.
+    function: Vec<bool>,
+}
+
+impl QrCode {
+    pub fn encode(data: &[u8]) -> Result<Self> {
+        let (version, &(total, ec_len, blocks)) = VERSIONS_M
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (i + 1, v))
+            .find(|(version, (total, ec_len, blocks))| {
+                let count_bits = if *version < 10 { 8 } else { 16 };
+                4 + count_bits + data.len() * 8 <= (total - ec_len * blocks) * 8
+            })
+            .with_context(|| format!("{} bytes is too long for a QR code", data.len()))?;
+        let data_len = total - ec_len * blocks;
+
+        let mut bits = BitBuffer::default();
+        bits.push(0b0100, 4);
+        bits.push(data.len() as u32, if version < 10 { 8 } else { 16 });
+        for &b in data {
+            bits.push(b.into(), 8);
+        }
+        let capacity = data_len * 8;
+        bits.push(0, (capacity - bits.len).min(4));
+        bits.push(0, (8 - bits.len % 8) % 8);
+        let mut codewords = bits.bytes;
+        for pad in [0xec, 0x11].into_iter().cycle() {
+            if codewords.len() >= data_len {
+                break;
+            }
+            codewords.push(pad);
+        }
+
+        let size = version * 4 + 17;
+        let mut qr = Self {
+            size,
+            modules: vec![false; size * size],
+            function: vec![false; size * size],
+        };
+        qr.draw_function_patterns(version);
+        qr.draw_codewords(&interleave(&codewords, total, ec_len, blocks));
+
+        let mask = (0..8)
+            .min_by_key(|&mask| {
+                let mut candidate = qr.clone();
+                candidate.apply_mask(mask);
+                candidate.draw_format_bits(mask);
+                candidate.penalty()
+            })
+            .unwrap_or(0);
+        qr.apply_mask(mask);
+        qr.draw_format_bits(mask);
+        Ok(qr)
+    }
+
+    pub fn dark(&self, x: usize, y: usize) -> bool {
+        x < self.size && y < self.size && self.modules[y * self.size + x]
+    }
+
+    fn set_function(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y * self.size + x] = dark;
+        self.function[y * self.size + x] = true;
+    }
+
+    fn draw_function_patterns(&mut self, version: usize) {
+        let size = self.size;
+        for i in 0..size {
+            self.set_function(6, i, i % 2 == 0);
+            self.set_function(i, 6, i % 2 == 0);
+        }
+        for (x, y) in [(3, 3), (size - 4, 3), (3, size - 4)] {
+            for dy in -4i32..=4 {
+                for dx in -4i32..=4 {
+                    let (xx, yy) = (x as i32 + dx, y as i32 + dy);
+                    if (0..size as i32).contains(&xx) && (0..size as i32).contains(&yy) {
+                        let dist = dx.abs().max(dy.abs());
+                        self.set_function(xx as usize, yy as usize, dist != 2 && dist != 4);
+                    }
+                }
+            }
+        }
+        let align = alignment_positions(version);
+        let last = align.len().saturating_sub(1);
+        for (i, &ay) in align.iter().enumerate() {
+            for (j, &ax) in align.iter().enumerate() {
+                // The three corners hold finder patterns.
+                if (i == 0 && (j == 0 || j == last)) || (i == last && j == 0) {
+                    continue;
+                }
+                for dy in -2i32..=2 {
+                    for dx in -2i32..=2 {
+                        let dark = dx.abs().max(dy.abs()) != 1;
+                        self.set_function(
+                            (ax as i32 + dx) as usize,
+                            (ay as i32 + dy) as usize,
+                            dark,
+                        );
+                    }
+                }
+            }
+        }
+        // Reserve the format areas; the real bits go in once the mask is chosen.
+        self.draw_format_bits(0);
+        if version >= 7 {
+            let bits = version_bits(version);
+            for i in 0..18 {
+                let dark = (bits >> i) & 1 == 1;
+                let (a, b) = (size - 11 + i % 3, i / 3);
+                self.set_function(a, b, dark);
+                self.set_function(b, a, dark);
+            }
+        }
+    }
+
+    fn draw_format_bits(&mut self, mask: u8) {
+        let bits = format_bits(mask);
+        let bit = |i: usize| (bits >> i) & 1 == 1;
+        let size = self.size;
+        for i in 0..6 {
+            self.set_function(8, i, bit(i));
+        }
+        self.set_function(8, 7, bit(6));
+        self.set_function(8, 8, bit(7));
+        self.set_function(7, 8, bit(8));
+        for i in 9..15 {
+            self.set_function(14 - i, 8, bit(i));
+        }
+        for i in 0..8 {
+            self.set_function(size - 1 - i, 8, bit(i));
+        }
+        for i in 8..15 {
+            self.set_function(8, size - 15 + i, bit(i));
+        }
+        self.set_function(8, size - 8, true);
+    }
+
+    fn draw_codewords(&mut self, data: &[u8]) {
+        let size = self.size;
+        let mut i = 0;
+        let mut right = size - 1;
+        loop {
+            if right == 6 {
+                right = 5;
+            }
+            for vert in 0..size {
+                for j in 0..2 {
+                    let x = right - j;
+                    let upward = (right + 1) & 2 == 0;
+                    let y = if upward { size - 1 - vert } else { vert };
+                    if !self.function[y * size + x] && i < data.len() * 8 {
+                        self.modules[y * size + x] = (data[i >> 3] >> (7 - (i & 7))) & 1 == 1;
+                        i += 1;
+                    }
+                }
+            }
+            if right < 2 {
+                break;
+            }
+            right -= 2;
+        }
+    }
+
+    fn apply_mask(&mut self, mask: u8) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let invert = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+                let i = y * self.size + x;
+                if invert && !self.function[i] {
+                    self.modules[i] = !self.modules[i];
+                }
+            }
+        }
+    }
+
+    /// The four penalty rules scanners are most sensitive to; lower is easier to read.
+    fn penalty(&self) -> usize {
+        let size = self.size;
+        let mut score = 0;
+        const FINDER: [bool; 11] = [
+            true, false, true, true, true, false, true, false, false, false, false,
+        ];
+        for horizontal in [true, false] {
+            for a in 0..size {
+                let line: Vec<bool> = (0..size)
+                    .map(|b| {
+                        if horizontal {
+                            self.dark(b, a)
+                        } else {
+                            self.dark(a, b)
+                        }
+                    })
+                    .collect();
+                let mut run = 1;
+                for b in 1..=size {
+                    if b < size && line[b] == line[b - 1] {
+                        run += 1;
+                        continue;
+                    }
+                    if run >= 5 {
+                        score += run - 2;
+                    }
+                    run = 1;
+                }
+                for w in line.windows(FINDER.len()) {
+                    if w == FINDER || w.iter().rev().eq(FINDER.iter()) {
+                        score += 40;
+                    }
+                }
+            }
+        }
+        for y in 0..size - 1 {
+            for x in 0..size - 1 {
+                let c = self.dark(x, y);
+                if c == self.dark(x + 1, y)
+                    && c == self.dark(x, y + 1)
+                    && c == self.dark(x + 1, y + 1)
+                {
+                    score += 3;
+                }
+            }
+        }
+        let dark = self.modules.iter().filter(|m| **m).count();
+        let percent = dark * 100 / self.modules.len();
+        score + percent.abs_diff(50) / 5 * 10
+    }
+
+    pub fn to_svg(&self) -> String {
+        let dim = self.size + QUIET_ZONE * 2;
+        let mut path = String::new();
+        for y in 0..self.size {
+            for x in 0..self.size {
+                if self.dark(x, y) {
+                    let _ = write!(path, "M{},{}h1v1h-1z", x + QUIET_ZONE, y + QUIET_ZONE);
+                }
+            }
+        }
+        format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {dim} {dim}\" \
+             shape-rendering=\"crispEdges\"><rect width=\"100%\" height=\"100%\" fill=\"#fff\"/>\
+             <path d=\"{path}\" fill=\"#000\"/></svg>\n"
+        )
+    }
+
+    /// Two rows per line with `▀`/`▄`/`█`, dark on a light terminal background.
+    pub fn to_text(&self) -> String {
+        let dim = self.size + QUIET_ZONE * 2;
+        let dark = |x: usize, y: usize| {
+            x >= QUIET_ZONE && y >= QUIET_ZONE && self.dark(x - QUIET_ZONE, y - QUIET_ZONE)
+        };
+        let mut out = String::new();
+        for y in (0..dim).step_by(2) {
+            for x in 0..dim {
+                out.push(match (dark(x, y), dark(x, y + 1)) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                });
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// 1-bit grayscale PNG with `scale` pixels per module.
+    pub fn to_png(&self, scale: usize) -> Vec<u8> {
+        let dim = (self.size + QUIET_ZONE * 2) * scale;
+        let row_bytes = dim.div_ceil(8);
+        let mut raw = Vec::with_capacity((row_bytes + 1) * dim);
+        for py in 0..dim {
+            raw.push(0); // filter: none
+            let mut row = vec![0xffu8; row_bytes];
+            for px in 0..dim {
+                let (x, y) = (px / scale, py / scale);
+                if x >= QUIET_ZONE && y >= QUIET_ZONE && self.dark(x - QUIET_ZONE, y - QUIET_ZONE) {
+                    row[px / 8] &= !(0x80 >> (px % 8));
+                }
+            }
+            raw.extend_from_slice(&row);
+        }
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        let mut ihdr = Vec::with_capacity(13);
+        ihdr.extend_from_slice(&(dim as u32).to_be_bytes());
+        ihdr.extend_from_slice(&(dim as u32).to_be_bytes());
+        ihdr.extend_from_slice(&[1, 0, 0, 0, 0]);
+        png_chunk(&mut png, b"IHDR", &ihdr);
+        png_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+        png_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+}
+
+pub fn render(code: &QrCode, format: QrFormat) -> Vec<u8> {
+    match format {
+        QrFormat::Svg => code.to_svg().into_bytes(),
+        QrFormat::Png => code.to_png(8),
+        QrFormat::Text => code.to_text().into_bytes(),
+    }
+}
+
+#[derive(Default)]
+struct BitBuffer {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl BitBuffer {
+    fn push(&mut self, value: u32, bits: usize) {
+        for i in (0..bits).rev() {
+            if self.len.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            if (value >> i) & 1 == 1 {
+                *self.bytes.last_mut().expect("byte") |= 0x80 >> (self.len % 8);
+            }
+            self.len += 1;
+        }
+    }
+}
+
+fn alignment_positions(version: usize) -> Vec<usize> {
+    if version == 1 {
+        return vec![];
+    }
+    let count = version / 7 + 2;
+    let size = version * 4 + 17;
+    let step = (version * 8 + count * 3 + 5) / (count * 4 - 4) * 2;
+    let mut out: Vec<usize> = (0..count - 1).map(|i| size - 7 - i * step).collect();
+    out.push(6);
+    out.reverse();
+    out
+}
+
+fn format_bits(mask: u8) -> u32 {
+    // Level M is 0b00, so the data is just the mask.
+    let data = u32::from(mask);
+    let mut rem = data;
+    for _ in 0..10 {
+        rem = (rem << 1) ^ ((rem >> 9) * 0x537);
+    }
+    ((data << 10) | rem) ^ 0x5412
+}
+
+fn version_bits(version: usize) -> u32 {
+    let data = version as u32;
+    let mut rem = data;
+    for _ in 0..12 {
+        rem = (rem << 1) ^ ((rem >> 11) * 0x1f25);
+    }
+    (data << 12) | rem
+}
+
+fn gf_mul(x: u8, y: u8) -> u8 {
+    let mut z: u32 = 0;
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x11d);
+        z ^= u32::from((y >> i) & 1) * u32::from(x);
+    }
+    z as u8
+}
+
+fn rs_divisor(degree: usize) -> Vec<u8> {
+    let mut result = vec![0u8; degree];
+    result[degree - 1] = 1;
+    let mut root = 1u8;
+    for _ in 0..degree {
+        for j in 0..degree {
+            result[j] = gf_mul(result[j], root);
+            if j + 1 < degree {
+                result[j] ^= result[j + 1];
+            }
+        }
+        root = gf_mul(root, 0x02);
+    }
+    result
+}
+
+fn rs_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut result = vec![0u8; divisor.len()];
+    for &b in data {
+        let factor = b ^ result.remove(0);
+        result.push(0);
+        for (r, &d) in result.iter_mut().zip(divisor) {
+            *r ^= gf_mul(d, factor);
+        }
+    }
+    result
+}
+
+/// Split into blocks (the short ones first), add each block's EC codewords and interleave.
+fn interleave(data: &[u8], total: usize, ec_len: usize, blocks: usize) -> Vec<u8> {
+    let short_blocks = blocks - total % blocks;
+    let short_len = total / blocks;
+    let divisor = rs_divisor(ec_len);
+    let mut split = Vec::with_capacity(blocks);
+    let mut at = 0;
+    for i in 0..blocks {
+        let len = short_len - ec_len + usize::from(i >= short_blocks);
+        let block = &data[at..at + len];
+        at += len;
+        split.push((block.to_vec(), rs_remainder(block, &divisor)));
+    }
+    let mut out = Vec::with_capacity(total);
+    for i in 0..=short_len - ec_len {
+        for (block, _) in &split {
+            if let Some(&b) = block.get(i) {
+                out.push(b);
+            }
+        }
+    }
+    for i in 0..ec_len {
+        for (_, ec) in &split {
+            out.push(ec[i]);
+        }
+    }
+    out
+}
+
+fn png_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(kind);
+    crc.update(data);
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    out.extend_from_slice(&crc.finalize().to_be_bytes());
+}
+
+/// zlib stream of uncompressed deflate blocks; QR images are small enough not to need more.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut chunks = data.chunks(u16::MAX as usize).peekable();
+    if chunks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(chunk) = chunks.next() {
+        out.push(u8::from(chunks.peek().is_none()));
+        let len = chunk.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(chunk);
+    }
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + u32::from(byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    out.extend_from_slice(&((b << 16) | a).to_be_bytes());
+    out
+}
+
+/// What the Unity client scans to reach this admin API: `owp-admin://<host:port>?code=...`,
+/// standing for `http://<host:port>` and a pairing code to redeem there.
+pub fn pairing_uri(admin_url: &str, code: Option<&str>) -> Result<String> {
+    let url =
+        url::Url::parse(admin_url).with_context(|| format!("invalid admin URL {admin_url:?}"))?;
+    let host = url.host_str().context("admin URL has no host")?;
+    let port = url
+        .port_or_known_default()
+        .context("admin URL has no port")?;
+    let mut uri = format!("owp-admin://{host}:{port}");
+    if let Some(code) = code {
+        uri.push_str("?code=");
+        uri.extend(url::form_urlencoded::byte_serialize(code.as_bytes()));
+    }
+    Ok(uri)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_known_vectors() {
+        // "HELLO WORLD" at 1-M, from the QR code tutorial at thonky.com.
+        let data = [
+            32, 91, 11, 120, 209, 114, 220, 77, 67, 64, 236, 17, 236, 17, 236, 17,
+        ];
+        assert_eq!(
+            rs_remainder(&data, &rs_divisor(10)),
+            [196, 35, 39, 119, 235, 215, 231, 226, 93, 23]
+        );
+        assert_eq!(format_bits(0), 0b101010000010010);
+        assert_eq!(version_bits(7), 0b000111110010010100);
+        assert_eq!(alignment_positions(7), [6, 22, 38]);
+        assert_eq!(alignment_positions(13), [6, 34, 62]);
+
+        let connect = "owp://mygame.example.com:7777?world=0b9f6c52-5d35-4b1e-9a6e-2c7f0c4f1d2a";
+        let code = QrCode::encode(connect.as_bytes()).expect("encode");
+        assert_eq!(code.size, 4 * 5 + 17);
+        // Finder pattern corners and the always-dark module.
+        assert!(code.dark(0, 0) && code.dark(6, 6) && !code.dark(7, 7));
+        assert!(code.dark(code.size - 1, 0) && code.dark(0, code.size - 1));
+        assert!(code.dark(8, code.size - 8));
+        assert!(QrCode::encode(&[b'x'; 332]).is_err());
+
+        let png = code.to_png(2);
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+        assert!(code.to_svg().starts_with("<svg"));
+        assert_eq!(
+            pairing_uri("http://192.168.1.5:9333/", Some("ABCD EFGH")).expect("uri"),
+            "owp-admin://192.168.1.5:9333?code=ABCD+EFGH"
+        );
+    }
+}
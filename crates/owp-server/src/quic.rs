@@ -0,0 +1,237 @@
+//! The game over QUIC (`ports.quic_port` in the manifest). A client opens one bidirectional
+//! stream and speaks exactly what it would over TCP on it: handshake, chat, everything that must
+//! arrive. Messages a newer one supersedes (`Message::is_unreliable`, i.e. player positions) may
+//! travel as datagrams instead, so a lost position never holds up the rest.
+//!
+//! TLS uses a self-signed certificate for the world authority key: a client that knows the
+//! world's `pubkey` checks the certificate carries it, as it would check a Noise server.
+
+use crate::tls;
+use anyhow::{Context, Result};
+use ed25519_dalek::SigningKey;
+use owp_protocol::{wire, Message, WireFormat};
+use quinn::crypto::rustls::QuicServerConfig;
+use quinn::{rustls, Connection, Endpoint};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tracing::debug;
+
+/// ALPN protocol id of the game over QUIC.
+pub const ALPN: &[u8] = b"owp/0.1";
+
+/// Datagrams waiting for the connection loop; older positions are dropped past this.
+const DATAGRAM_BACKLOG: usize = 32;
+
+/// Listen on `addr`. Without the world authority key (a world whose authority lives elsewhere)
+/// the certificate is for a throwaway key, and clients can't pin it.
+pub fn endpoint(addr: SocketAddr, authority: Option<&SigningKey>) -> Result<Endpoint> {
+    let key = match authority {
+        Some(key) => key.clone(),
+        None => SigningKey::generate(&mut rand::rngs::OsRng),
+    };
+    let (cert, key) = tls::self_signed(&key)?;
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut tls = rustls::ServerConfig::builder_with_provider(provider)
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .context("tls versions")?
+        .with_no_client_auth()
+        .with_single_cert(vec![cert], key)
+        .context("tls certificate")?;
+    tls.alpn_protocols = vec![ALPN.to_vec()];
+    let crypto = QuicServerConfig::try_from(tls).context("quic tls config")?;
+    let config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+    Endpoint::server(config, addr).with_context(|| format!("bind quic {addr}"))
+}
+
+/// One frame per datagram, in the session's wire format. Never protected or compressed: QUIC
+/// already encrypts, and AEAD sequence numbers can't survive lost datagrams.
+pub fn encode_datagram(msg: &Message, format: WireFormat) -> Result<Vec<u8>> {
+    Ok(wire::encode_frame_as(msg, format)?)
+}
+
+pub fn decode_datagram(bytes: &[u8], format: WireFormat) -> Result<Message> {
+    let (msg, used) = wire::decode_frame_as(bytes, format)?.context("truncated datagram")?;
+    anyhow::ensure!(used == bytes.len(), "trailing bytes in datagram");
+    Ok(msg)
+}
+
+/// Send `msg` as a datagram if the peer takes them and it fits; `None` means send it on the
+/// stream instead.
+pub fn send_datagram(conn: &Connection, msg: &Message, format: WireFormat) -> Option<usize> {
+    let frame = encode_datagram(msg, format).ok()?;
+    if conn.max_datagram_size().is_none_or(|max| frame.len() > max) {
+        return None;
+    }
+    let len = frame.len();
+    conn.send_datagram(frame.into()).ok()?;
+    Some(len)
+}
+
+/// Decode the peer's datagrams until the connection ends. Anything but an unreliable message is
+/// dropped: reliable ones belong on the stream.
+pub fn spawn_datagram_reader(conn: Connection, format: WireFormat) -> mpsc::Receiver<Message> {
+    let (tx, rx) = mpsc::channel(DATAGRAM_BACKLOG);
+    tokio::spawn(async move {
+        while let Ok(bytes) = conn.read_datagram().await {
+            match decode_datagram(&bytes, format) {
+                Ok(msg) if msg.is_unreliable() => {
+                    // A full backlog means the loop is behind; newer positions will follow.
+                    let _ = tx.try_send(msg);
+                }
+                Ok(msg) => debug!("dropped a reliable message sent as a datagram: {msg:?}"),
+                Err(e) => debug!("dropped a malformed datagram: {e:#}"),
+            }
+            if tx.is_closed() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use owp_protocol::{ChatChannel, ChatSend, PlayerPosition};
+    use quinn::crypto::rustls::QuicClientConfig;
+    use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+    use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+    use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+    use rustls::{DigitallySignedStruct, SignatureScheme};
+
+    /// Accepts the server's self-signed certificate only if it carries the expected key.
+    #[derive(Debug)]
+    struct PinnedKey {
+        key: [u8; 32],
+        provider: Arc<CryptoProvider>,
+    }
+
+    impl ServerCertVerifier for PinnedKey {
+        fn verify_server_cert(
+            &self,
+            cert: &CertificateDer<'_>,
+            _intermediates: &[CertificateDer<'_>],
+            _name: &ServerName<'_>,
+            _ocsp: &[u8],
+            _now: UnixTime,
+        ) -> Result<ServerCertVerified, rustls::Error> {
+            // The ed25519 OID, then the 32-byte key as a BIT STRING.
+            let spki = [
+                &[0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00][..],
+                &self.key,
+            ]
+            .concat();
+            if cert.windows(spki.len()).any(|w| w == spki) {
+                Ok(ServerCertVerified::assertion())
+            } else {
+                Err(rustls::Error::General("not the world authority".into()))
+            }
+        }
+
+        fn verify_tls12_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            let algs = &self.provider.signature_verification_algorithms;
+            verify_tls12_signature(message, cert, dss, algs)
+        }
+
+        fn verify_tls13_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            let algs = &self.provider.signature_verification_algorithms;
+            verify_tls13_signature(message, cert, dss, algs)
+        }
+
+        fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+            let algs = &self.provider.signature_verification_algorithms;
+            algs.supported_schemes()
+        }
+    }
+
+    fn client(pinned: [u8; 32]) -> Endpoint {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let verifier = PinnedKey {
+            key: pinned,
+            provider: provider.clone(),
+        };
+        let mut tls = rustls::ClientConfig::builder_with_provider(provider)
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .expect("versions")
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+            .with_no_client_auth();
+        tls.alpn_protocols = vec![ALPN.to_vec()];
+        let crypto = QuicClientConfig::try_from(tls).expect("quic client config");
+        let mut endpoint = Endpoint::client("127.0.0.1:0".parse().unwrap()).expect("client");
+        endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(crypto)));
+        endpoint
+    }
+
+    #[tokio::test]
+    async fn streams_and_datagrams_reach_a_pinned_server() {
+        let authority = SigningKey::generate(&mut rand::rngs::OsRng);
+        let server = endpoint("127.0.0.1:0".parse().unwrap(), Some(&authority)).expect("server");
+        let addr = server.local_addr().expect("addr");
+        let format = WireFormat::Msgpack;
+
+        let accepted = tokio::spawn(async move {
+            let conn = server
+                .accept()
+                .await
+                .expect("incoming")
+                .await
+                .expect("conn");
+            let (_send, mut recv) = conn.accept_bi().await.expect("stream");
+            let msg = wire::read_message_as(&mut recv, format)
+                .await
+                .expect("read");
+            let mut datagrams = spawn_datagram_reader(conn.clone(), format);
+            let position = datagrams.recv().await.expect("datagram");
+            (msg, position)
+        });
+
+        let pinned = *authority.verifying_key().as_bytes();
+        let conn = client(pinned)
+            .connect(addr, "owp")
+            .expect("connect")
+            .await
+            .expect("handshake");
+        let (mut send, _recv) = conn.open_bi().await.expect("stream");
+        let chat = Message::ChatSend(ChatSend {
+            channel: ChatChannel::World,
+            text: "hi".to_string(),
+        });
+        wire::write_message_as(&mut send, &chat, format)
+            .await
+            .expect("write");
+        // Reliable messages sent as datagrams are dropped.
+        assert!(send_datagram(&conn, &chat, format).is_some());
+        let position = Message::PlayerPosition(PlayerPosition {
+            position: [1.0, 2.0, 3.0],
+        });
+        assert!(send_datagram(&conn, &position, format).is_some());
+
+        let (msg, position) = accepted.await.expect("server task");
+        assert!(matches!(msg, Message::ChatSend(c) if c.text == "hi"));
+        assert!(matches!(position, Message::PlayerPosition(p) if p.position == [1.0, 2.0, 3.0]));
+
+        // Another key in the certificate fails the handshake.
+        let other = SigningKey::generate(&mut rand::rngs::OsRng);
+        let server = endpoint("127.0.0.1:0".parse().unwrap(), Some(&other)).expect("server");
+        let addr = server.local_addr().expect("addr");
+        tokio::spawn(async move {
+            if let Some(incoming) = server.accept().await {
+                let _ = incoming.await;
+            }
+        });
+        let refused = client(pinned).connect(addr, "owp").expect("connect").await;
+        assert!(refused.is_err());
+    }
+}
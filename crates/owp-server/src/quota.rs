@@ -0,0 +1,410 @@
+//! Per-world budgets for a process hosting many worlds: open connections, simulation CPU time,
+//! assistant jobs and storage. Limits come from the `quotas` section of `server.json` and apply
+//! from the next check after a reload. Usage is kept in memory by the process enforcing it, so
+//! only `all-in-one`, which runs the game servers and the admin API together, sees all of it;
+//! storage is measured on disk.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::{self, Write as _};
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::config::LiveConfig;
+
+/// Window the tick CPU budget is counted over.
+const TICK_WINDOW: Duration = Duration::from_secs(1);
+
+/// Window the assistant job budget is counted over.
+const JOB_WINDOW: Duration = Duration::from_secs(3600);
+
+/// How long a measured world dir size is trusted.
+const STORAGE_MEASURE_EVERY: Duration = Duration::from_secs(60);
+
+/// Limits for one world; unset means unlimited.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WorldQuota {
+    /// Open game connections.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_connections: Option<u32>,
+    /// Simulation CPU time per second; ticks past it are skipped until the next second.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tick_cpu_ms_per_sec: Option<u32>,
+    /// Assistant jobs (chat, avatar and mesh generation) by players in the world, per hour.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub assistant_jobs_per_hour: Option<u32>,
+    /// Size of the world dir; past it, edits through the admin API are refused.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage_bytes: Option<u64>,
+}
+
+impl WorldQuota {
+    /// `self`, with the limits it leaves unset taken from `fallback`.
+    fn or(&self, fallback: &WorldQuota) -> WorldQuota {
+        WorldQuota {
+            max_connections: self.max_connections.or(fallback.max_connections),
+            tick_cpu_ms_per_sec: self.tick_cpu_ms_per_sec.or(fallback.tick_cpu_ms_per_sec),
+            assistant_jobs_per_hour: self
+                .assistant_jobs_per_hour
+                .or(fallback.assistant_jobs_per_hour),
+            storage_bytes: self.storage_bytes.or(fallback.storage_bytes),
+        }
+    }
+}
+
+/// The `quotas` section of `server.json`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct QuotaConfig {
+    /// Limits for every world.
+    #[serde(default)]
+    pub default: WorldQuota,
+    /// Overrides for single worlds, field by field.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub worlds: BTreeMap<Uuid, WorldQuota>,
+}
+
+impl QuotaConfig {
+    pub fn is_empty(&self) -> bool {
+        *self == QuotaConfig::default()
+    }
+
+    pub fn for_world(&self, world_id: Uuid) -> WorldQuota {
+        match self.worlds.get(&world_id) {
+            Some(q) => q.or(&self.default),
+            None => self.default.clone(),
+        }
+    }
+}
+
+/// The budget a request ran into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exceeded {
+    Connections,
+    AssistantJobs,
+    Storage,
+}
+
+impl fmt::Display for Exceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Exceeded::Connections => "the world is at its connection limit",
+            Exceeded::AssistantJobs => "the world used up its assistant jobs for this hour",
+            Exceeded::Storage => "the world is over its storage quota",
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    connections: u32,
+    connections_refused: u64,
+    tick_cpu: Duration,
+    window: Option<(Instant, Duration)>,
+    ticks_skipped: u64,
+    jobs: VecDeque<Instant>,
+    jobs_refused: u64,
+    storage: Option<(Instant, u64)>,
+    writes_refused: u64,
+}
+
+/// What a world has used, for `/metrics` and `GET /worlds/:world_id/quota`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct WorldUsageV1 {
+    pub connections: u32,
+    pub connections_refused: u64,
+    pub tick_cpu_ms: u64,
+    pub ticks_skipped: u64,
+    pub assistant_jobs_last_hour: u32,
+    pub assistant_jobs_refused: u64,
+    /// As of the last measurement; `None` until a check needed it.
+    pub storage_bytes: Option<u64>,
+    pub writes_refused: u64,
+}
+
+/// Usage of every world in this process.
+#[derive(Clone, Default)]
+pub struct Quotas(Arc<Mutex<HashMap<Uuid, Arc<Mutex<Counters>>>>>);
+
+impl Quotas {
+    /// The budget of `world_id`, checked against the current `config` on every call.
+    pub fn world(&self, world_id: Uuid, config: &LiveConfig) -> WorldBudget {
+        let mut worlds = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        WorldBudget {
+            world_id,
+            config: config.clone(),
+            counters: worlds.entry(world_id).or_default().clone(),
+        }
+    }
+
+    /// Usage of every world that has been checked so far.
+    pub fn usage(&self) -> Vec<(Uuid, WorldUsageV1)> {
+        let worlds = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let mut out: Vec<_> = worlds
+            .iter()
+            .map(|(id, c)| (*id, usage_of(&c.lock().unwrap_or_else(|e| e.into_inner()))))
+            .collect();
+        out.sort_by_key(|(id, _)| *id);
+        out
+    }
+}
+
+fn usage_of(c: &Counters) -> WorldUsageV1 {
+    WorldUsageV1 {
+        connections: c.connections,
+        connections_refused: c.connections_refused,
+        tick_cpu_ms: c.tick_cpu.as_millis() as u64,
+        ticks_skipped: c.ticks_skipped,
+        assistant_jobs_last_hour: c.jobs.iter().filter(|t| t.elapsed() < JOB_WINDOW).count() as u32,
+        assistant_jobs_refused: c.jobs_refused,
+        storage_bytes: c.storage.map(|(_, bytes)| bytes),
+        writes_refused: c.writes_refused,
+    }
+}
+
+#[derive(Clone)]
+pub struct WorldBudget {
+    world_id: Uuid,
+    config: LiveConfig,
+    counters: Arc<Mutex<Counters>>,
+}
+
+impl WorldBudget {
+    pub fn limits(&self) -> WorldQuota {
+        self.config.current().quotas.for_world(self.world_id)
+    }
+
+    fn counters(&self) -> std::sync::MutexGuard<'_, Counters> {
+        self.counters.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn usage(&self) -> WorldUsageV1 {
+        usage_of(&self.counters())
+    }
+
+    /// Take one of the world's connection slots until the permit is dropped.
+    pub fn connect(&self) -> Result<ConnectionPermit, Exceeded> {
+        let limit = self.limits().max_connections;
+        let mut c = self.counters();
+        if limit.is_some_and(|max| c.connections >= max) {
+            c.connections_refused += 1;
+            return Err(Exceeded::Connections);
+        }
+        c.connections += 1;
+        Ok(ConnectionPermit {
+            counters: self.counters.clone(),
+        })
+    }
+
+    /// Whether the simulation may run a tick now; counts the ones it may not.
+    pub fn tick_allowed(&self) -> bool {
+        let Some(ms) = self.limits().tick_cpu_ms_per_sec else {
+            return true;
+        };
+        let mut c = self.counters();
+        match c.window {
+            Some((start, spent))
+                if start.elapsed() < TICK_WINDOW && spent >= Duration::from_millis(ms.into()) =>
+            {
+                c.ticks_skipped += 1;
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Record CPU time a tick took.
+    pub fn charge_tick(&self, spent: Duration) {
+        let mut c = self.counters();
+        c.tick_cpu += spent;
+        c.window = match c.window {
+            Some((start, used)) if start.elapsed() < TICK_WINDOW => Some((start, used + spent)),
+            _ => Some((Instant::now(), spent)),
+        };
+    }
+
+    /// Count an assistant job, unless the world has used up its hourly budget.
+    pub fn assistant_job(&self) -> Result<(), Exceeded> {
+        let limit = self.limits().assistant_jobs_per_hour;
+        let mut c = self.counters();
+        while c.jobs.front().is_some_and(|t| t.elapsed() >= JOB_WINDOW) {
+            c.jobs.pop_front();
+        }
+        if limit.is_some_and(|max| c.jobs.len() >= max as usize) {
+            c.jobs_refused += 1;
+            return Err(Exceeded::AssistantJobs);
+        }
+        c.jobs.push_back(Instant::now());
+        Ok(())
+    }
+
+    /// Refuse a write when the world dir is over its storage quota. The size is measured at
+    /// most once a minute.
+    pub fn check_storage(&self, world_dir: &Path) -> Result<(), Exceeded> {
+        let Some(limit) = self.limits().storage_bytes else {
+            return Ok(());
+        };
+        let cached = self
+            .counters()
+            .storage
+            .filter(|(at, _)| at.elapsed() < STORAGE_MEASURE_EVERY);
+        let bytes = match cached {
+            Some((_, bytes)) => bytes,
+            None => {
+                let bytes = dir_size(world_dir).unwrap_or_else(|e| {
+                    tracing::warn!("measuring {world_dir:?} failed: {e:#}");
+                    0
+                });
+                self.counters().storage = Some((Instant::now(), bytes));
+                bytes
+            }
+        };
+        if bytes >= limit {
+            self.counters().writes_refused += 1;
+            return Err(Exceeded::Storage);
+        }
+        Ok(())
+    }
+}
+
+/// One open connection of a world; frees the slot when dropped.
+pub struct ConnectionPermit {
+    counters: Arc<Mutex<Counters>>,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        let mut c = self.counters.lock().unwrap_or_else(|e| e.into_inner());
+        c.connections = c.connections.saturating_sub(1);
+    }
+}
+
+fn dir_size(dir: &Path) -> Result<u64> {
+    let mut total = 0;
+    for entry in fs::read_dir(dir).with_context(|| format!("read {dir:?}"))? {
+        let entry = entry?;
+        let ty = entry.file_type()?;
+        if ty.is_dir() {
+            total += dir_size(&entry.path())?;
+        } else if ty.is_file() {
+            total += entry.metadata()?.len();
+        }
+    }
+    Ok(total)
+}
+
+/// Metric name, help text and the value it reports (none to skip the world).
+type Series = (&'static str, &'static str, fn(&WorldUsageV1) -> Option<u64>);
+
+pub fn render_metrics(worlds: &[(Uuid, WorldUsageV1)]) -> String {
+    let mut out = String::new();
+    let series: [Series; 8] = [
+        ("owp_world_connections", "Open game connections.", |u| {
+            Some(u.connections.into())
+        }),
+        (
+            "owp_world_connections_refused_total",
+            "Connections refused by the connection quota.",
+            |u| Some(u.connections_refused),
+        ),
+        (
+            "owp_world_tick_cpu_ms_total",
+            "CPU time spent in simulation ticks.",
+            |u| Some(u.tick_cpu_ms),
+        ),
+        (
+            "owp_world_ticks_skipped_total",
+            "Simulation ticks skipped by the tick CPU quota.",
+            |u| Some(u.ticks_skipped),
+        ),
+        (
+            "owp_world_assistant_jobs_hour",
+            "Assistant jobs in the last hour.",
+            |u| Some(u.assistant_jobs_last_hour.into()),
+        ),
+        (
+            "owp_world_assistant_jobs_refused_total",
+            "Assistant jobs refused by the hourly quota.",
+            |u| Some(u.assistant_jobs_refused),
+        ),
+        (
+            "owp_world_storage_bytes",
+            "Size of the world dir, as last measured.",
+            |u| u.storage_bytes,
+        ),
+        (
+            "owp_world_writes_refused_total",
+            "Admin API edits refused by the storage quota.",
+            |u| Some(u.writes_refused),
+        ),
+    ];
+    for (name, help, value) in series {
+        let kind = if name.ends_with("_total") {
+            "counter"
+        } else {
+            "gauge"
+        };
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} {kind}");
+        for (world_id, u) in worlds {
+            if let Some(v) = value(u) {
+                let _ = writeln!(out, "{name}{{world_id=\"{world_id}\"}} {v}");
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::WorldStore;
+
+    #[test]
+    fn budgets_follow_the_live_config() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let store = WorldStore::with_root(dir.path().to_path_buf()).expect("store");
+        let config = LiveConfig::load(&store).expect("config");
+        let world_id = Uuid::new_v4();
+        let quotas = Quotas::default();
+        let budget = quotas.world(world_id, &config);
+
+        // Nothing configured: nothing is limited.
+        let _a = budget.connect().expect("unlimited");
+        assert!(budget.assistant_job().is_ok());
+
+        config
+            .update(|cfg| {
+                cfg.quotas.default.max_connections = Some(5);
+                cfg.quotas.worlds.insert(
+                    world_id,
+                    WorldQuota {
+                        max_connections: Some(2),
+                        assistant_jobs_per_hour: Some(1),
+                        ..WorldQuota::default()
+                    },
+                );
+            })
+            .expect("update");
+        assert_eq!(budget.limits().max_connections, Some(2));
+
+        let b = budget.connect().expect("second slot");
+        assert_eq!(budget.connect().err(), Some(Exceeded::Connections));
+        drop(b);
+        let _b = budget.connect().expect("freed slot");
+        assert_eq!(budget.assistant_job(), Err(Exceeded::AssistantJobs));
+
+        let usage = quotas.usage();
+        assert_eq!(usage.len(), 1);
+        assert_eq!(usage[0].1.connections, 2);
+        assert_eq!(usage[0].1.connections_refused, 1);
+        assert_eq!(usage[0].1.assistant_jobs_refused, 1);
+        // Other worlds only get the defaults.
+        let other = config.current().quotas.for_world(Uuid::new_v4());
+        assert_eq!(other.max_connections, Some(5));
+        assert_eq!(other.assistant_jobs_per_hour, None);
+    }
+}
@@ -0,0 +1,359 @@
+//! Read replicas: a follower process tails a leader's world over the admin API
+//! (`GET /worlds/:world_id/replication`) and keeps a local copy of its chunks, ledger, manifest
+//! and assets, so it can serve observers and asset downloads, and be promoted by hand when the
+//! leader is gone.
+//!
+//! The follower writes the leader's WAL records into its own log with the leader's sequence
+//! numbers, so a promoted replica carries on where the leader stopped. When the leader has
+//! checkpointed past the follower, it sends a snapshot instead.
+
+use anyhow::{Context, Result};
+use owp_protocol::{SyncManifestV1, WorldManifestV1};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::assets;
+use crate::chunks::{self, ChunkFileV1};
+use crate::content;
+use crate::health::{HealthRegistry, ServiceState};
+use crate::ledger::{self, LedgerV1};
+use crate::storage::WorldStore;
+use crate::wal::{self, WalRecord};
+
+/// How often the follower asks the leader for new records.
+const POLL: Duration = Duration::from_secs(1);
+
+/// How often assets are compared with the leader's sync manifest.
+const ASSET_POLL: Duration = Duration::from_secs(60);
+
+/// Wait after a failed poll, doubling up to `MAX_BACKOFF`.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Health registry key of the follower loop.
+pub const HEALTH_KEY: &str = "replica";
+
+/// Everything the world's WAL covers, as of `last_seq`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorldSnapshotV1 {
+    pub manifest: WorldManifestV1,
+    #[serde(default)]
+    pub chunks: Vec<ChunkFileV1>,
+    #[serde(default)]
+    pub ledger: LedgerV1,
+}
+
+/// Reply to `GET /worlds/:world_id/replication?since=<seq>`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplicationBatchV1 {
+    pub last_seq: u64,
+    /// Records after `since`, oldest first. Empty when `snapshot` is set.
+    #[serde(default)]
+    pub records: Vec<WalRecord>,
+    /// Sent instead of records when the log no longer reaches back to `since`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<WorldSnapshotV1>,
+}
+
+/// Leader side: what a follower at `since` (`None`: nothing yet) needs next.
+pub fn batch(
+    store: &WorldStore,
+    world_dir: &Path,
+    since: Option<u64>,
+) -> Result<ReplicationBatchV1> {
+    let (last_seq, records, snapshot) =
+        wal::tail(world_dir, since, |_| snapshot(store, world_dir))?;
+    Ok(ReplicationBatchV1 {
+        last_seq,
+        records,
+        snapshot,
+    })
+}
+
+fn snapshot(store: &WorldStore, world_dir: &Path) -> Result<WorldSnapshotV1> {
+    Ok(WorldSnapshotV1 {
+        manifest: store.read_manifest(world_dir)?,
+        chunks: chunks::list_chunks(world_dir)?,
+        ledger: ledger::load_ledger(world_dir)?,
+    })
+}
+
+/// Replace the local world with `snap`, then restart the log at `last_seq`.
+fn install(
+    store: &WorldStore,
+    world_dir: &Path,
+    snap: &WorldSnapshotV1,
+    last_seq: u64,
+) -> Result<()> {
+    for sub in ["manifest", "chunks", "assets", "snapshots", "logs"] {
+        let dir = world_dir.join(sub);
+        fs::create_dir_all(&dir).with_context(|| format!("create {dir:?}"))?;
+    }
+    store.write_manifest(world_dir, &snap.manifest)?;
+    let keep: Vec<_> = snap
+        .chunks
+        .iter()
+        .map(|c| chunks::chunk_path(world_dir, c.chunk))
+        .collect();
+    for entry in fs::read_dir(world_dir.join("chunks"))? {
+        let path = entry?.path();
+        if !keep.contains(&path) {
+            fs::remove_file(&path).with_context(|| format!("remove {path:?}"))?;
+        }
+    }
+    for chunk in &snap.chunks {
+        chunks::save_chunk(world_dir, chunk)?;
+    }
+    ledger::save_ledger(world_dir, &snap.ledger)?;
+    wal::reset(world_dir, last_seq)
+}
+
+/// Follower side: keeps `world_id` in the local data dir in step with the leader.
+pub struct Follower {
+    client: reqwest::Client,
+    leader: url::Url,
+    token: Option<String>,
+    world_id: Uuid,
+    store: WorldStore,
+    /// Leader sequence the local copy reflects; `None` until the first snapshot.
+    seq: Option<u64>,
+}
+
+impl Follower {
+    pub fn new(
+        store: WorldStore,
+        leader: &str,
+        token: Option<String>,
+        world_id: Uuid,
+    ) -> Result<Self> {
+        let leader =
+            url::Url::parse(leader).with_context(|| format!("parse leader URL {leader:?}"))?;
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(30))
+            .build()
+            .context("build http client")?;
+        Ok(Self {
+            client,
+            leader,
+            token,
+            world_id,
+            store,
+            seq: None,
+        })
+    }
+
+    async fn get(&self, path: &str) -> Result<reqwest::Response> {
+        let url = self
+            .leader
+            .join(path)
+            .with_context(|| format!("leader URL for {path}"))?;
+        let mut req = self.client.get(url);
+        if let Some(t) = &self.token {
+            req = req.bearer_auth(t);
+        }
+        let resp = req.send().await.with_context(|| format!("GET {path}"))?;
+        let status = resp.status();
+        if !status.is_success() {
+            anyhow::bail!("GET {path}: HTTP {status}");
+        }
+        Ok(resp)
+    }
+
+    /// Fetch and apply one batch. Returns whether it was a snapshot.
+    pub async fn poll(&mut self) -> Result<bool> {
+        let mut path = format!("worlds/{}/replication", self.world_id);
+        if let Some(seq) = self.seq {
+            path.push_str(&format!("?since={seq}"));
+        }
+        let batch: ReplicationBatchV1 = self
+            .get(&path)
+            .await?
+            .json()
+            .await
+            .context("parse replication batch")?;
+        let world_dir = self.store.world_dir(self.world_id);
+        let store = self.store.clone();
+        let is_snapshot = batch.snapshot.is_some();
+        let seq = tokio::task::spawn_blocking(move || -> Result<u64> {
+            if let Some(snap) = &batch.snapshot {
+                install(&store, &world_dir, snap, batch.last_seq)?;
+                return Ok(batch.last_seq);
+            }
+            wal::append_replicated(&world_dir, &batch.records)
+        })
+        .await
+        .context("replication task")??;
+        if is_snapshot {
+            info!("installed snapshot of {} at seq {seq}", self.world_id);
+        }
+        self.seq = Some(seq);
+        Ok(is_snapshot)
+    }
+
+    /// Download assets and content-store blobs from the leader's sync manifest that the local
+    /// copy lacks or has stale. Returns how many were fetched.
+    pub async fn mirror_assets(&self) -> Result<usize> {
+        let sync: SyncManifestV1 = self
+            .get(&format!("worlds/{}/sync", self.world_id))
+            .await?
+            .json()
+            .await
+            .context("parse sync manifest")?;
+        let world_dir = self.store.world_dir(self.world_id);
+        let assets_prefix = format!("/worlds/{}/assets/", self.world_id);
+        let mut fetched = 0;
+        for entry in sync.entries {
+            let uri = entry.uri.split('?').next().unwrap_or_default();
+            let target = if let Some(rel) = uri.strip_prefix(&assets_prefix) {
+                let Some(rel) = assets::safe_relative(rel) else {
+                    continue;
+                };
+                assets::assets_dir(&world_dir).join(rel)
+            } else if uri.starts_with("/content/") && content::is_sha256(&entry.sha256) {
+                content::content_path(&self.store, &entry.sha256)
+            } else {
+                // Served from a CDN (`assets.base_url`), not by the host.
+                continue;
+            };
+            if fs::read(&target).is_ok_and(|b| content::hash(&b) == entry.sha256) {
+                continue;
+            }
+            let bytes = self.get(uri.trim_start_matches('/')).await?.bytes().await?;
+            if content::hash(&bytes) != entry.sha256 {
+                warn!("{uri} from the leader does not match its hash; skipped");
+                continue;
+            }
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent).with_context(|| format!("create {parent:?}"))?;
+            }
+            crate::storage::write_atomic(&target, &bytes)?;
+            fetched += 1;
+        }
+        Ok(fetched)
+    }
+
+    /// Poll forever, reporting progress in `health` under `HEALTH_KEY`.
+    pub async fn run(mut self, health: HealthRegistry) {
+        let mut backoff = MIN_BACKOFF;
+        let mut assets_due = tokio::time::Instant::now();
+        loop {
+            let result = async {
+                let snapshot = self.poll().await?;
+                if snapshot || tokio::time::Instant::now() >= assets_due {
+                    let n = self.mirror_assets().await?;
+                    if n > 0 {
+                        info!("mirrored {n} asset(s) from the leader");
+                    }
+                    assets_due = tokio::time::Instant::now() + ASSET_POLL;
+                }
+                anyhow::Ok(())
+            }
+            .await;
+            match result {
+                Ok(()) => {
+                    backoff = MIN_BACKOFF;
+                    health.set(
+                        HEALTH_KEY,
+                        ServiceState::Running,
+                        Some(format!(
+                            "seq {} from {}",
+                            self.seq.unwrap_or(0),
+                            self.leader
+                        )),
+                    );
+                    tokio::time::sleep(POLL).await;
+                }
+                Err(e) => {
+                    warn!("replication from {} failed: {e:#}", self.leader);
+                    health.set(HEALTH_KEY, ServiceState::Failed, Some(format!("{e:#}")));
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use owp_protocol::{ChunkChangeV1, ChunkCoord, WorldObjectV1};
+
+    fn place(world_dir: &Path, id: &str) {
+        let op = wal::WalOp::ChunkChange {
+            chunk: ChunkCoord { x: 0, z: 0 },
+            change: ChunkChangeV1::Upsert {
+                object: WorldObjectV1 {
+                    id: id.to_string(),
+                    kind: "rock".to_string(),
+                    position: [1.0, 0.0, 1.0],
+                    rotation: [0.0; 3],
+                    scale: [1.0; 3],
+                },
+            },
+        };
+        wal::mutate(world_dir, op).expect("mutate");
+    }
+
+    #[test]
+    fn follower_catches_up_by_records_then_snapshot() {
+        let leader_dir = tempfile::tempdir().expect("tempdir");
+        let leader = WorldStore::with_root(leader_dir.path().to_path_buf()).expect("store");
+        let world_id = leader
+            .create_world("replicated", 7777)
+            .expect("world")
+            .world_id;
+        let lw = leader.world_dir(world_id);
+        place(&lw, "a");
+
+        let follower_dir = tempfile::tempdir().expect("tempdir");
+        let follower = WorldStore::with_root(follower_dir.path().to_path_buf()).expect("store");
+        let fw = follower.world_dir(world_id);
+
+        // First contact is always a snapshot.
+        let b = batch(&leader, &lw, None).expect("batch");
+        install(
+            &follower,
+            &fw,
+            b.snapshot.as_ref().expect("snapshot"),
+            b.last_seq,
+        )
+        .expect("install");
+        assert_eq!(b.last_seq, 1);
+
+        // Within the log: records, applied with the leader's numbering.
+        place(&lw, "b");
+        let b = batch(&leader, &lw, Some(1)).expect("batch");
+        assert!(b.snapshot.is_none());
+        assert_eq!(wal::append_replicated(&fw, &b.records).expect("append"), 2);
+        assert_eq!(
+            chunks::load_chunk(&fw, ChunkCoord { x: 0, z: 0 })
+                .expect("chunk")
+                .objects
+                .len(),
+            2
+        );
+
+        // Checkpointed past the follower: snapshot again.
+        place(&lw, "c");
+        wal::checkpoint(&lw).expect("checkpoint");
+        let b = batch(&leader, &lw, Some(2)).expect("batch");
+        let snap = b.snapshot.as_ref().expect("snapshot");
+        install(&follower, &fw, snap, b.last_seq).expect("install");
+        assert_eq!(
+            chunks::load_chunk(&fw, ChunkCoord { x: 0, z: 0 })
+                .expect("chunk")
+                .objects
+                .len(),
+            3
+        );
+
+        // A promoted follower continues the leader's sequence.
+        place(&fw, "d");
+        assert_eq!(wal::scan(&fw).expect("scan").records[0].seq, 4);
+    }
+}
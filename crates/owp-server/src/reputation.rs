@@ -0,0 +1,229 @@
+use anyhow::{Context, Result};
+use owp_discovery::probe::ProbeResult;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::storage::{write_atomic, WorldStore};
+
+/// Probe samples kept per world.
+const PROBE_HISTORY_LEN: usize = 20;
+
+/// Each report knocks this much off the score (capped at losing everything).
+const REPORT_PENALTY: f32 = 0.2;
+
+/// Serializes read-modify-write of the store within this process.
+static REPUTATION_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportReason {
+    Spam,
+    Offensive,
+    Scam,
+    Impersonation,
+    Broken,
+    Other,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AbuseReport {
+    pub reason: ReportReason,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reporter: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub at: OffsetDateTime,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProbeSample {
+    #[serde(with = "time::serde::rfc3339")]
+    pub at: OffsetDateTime,
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rtt_ms: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WorldReputation {
+    /// Stars (1-5) keyed by rater, so re-rating replaces the old value.
+    #[serde(default)]
+    pub ratings: BTreeMap<String, u8>,
+    #[serde(default)]
+    pub reports: Vec<AbuseReport>,
+    #[serde(default)]
+    pub probes: VecDeque<ProbeSample>,
+}
+
+impl WorldReputation {
+    /// Share of recent probes that succeeded; `None` without probe history.
+    pub fn uptime(&self) -> Option<f32> {
+        if self.probes.is_empty() {
+            return None;
+        }
+        let ok = self.probes.iter().filter(|p| p.ok).count();
+        Some(ok as f32 / self.probes.len() as f32)
+    }
+
+    pub fn average_rating(&self) -> Option<f32> {
+        if self.ratings.is_empty() {
+            return None;
+        }
+        let sum: u32 = self.ratings.values().map(|v| *v as u32).sum();
+        Some(sum as f32 / self.ratings.len() as f32)
+    }
+
+    /// 0.0 (sink) ..= 1.0. Unknown liveness or ratings count as neutral (0.5); every report
+    /// scales the result down.
+    pub fn score(&self) -> f32 {
+        let liveness = self.uptime().unwrap_or(0.5);
+        let rating = self
+            .average_rating()
+            .map(|r| (r - 1.0) / 4.0)
+            .unwrap_or(0.5);
+        let penalty = (self.reports.len() as f32 * REPORT_PENALTY).min(1.0);
+        (0.5 * liveness + 0.5 * rating) * (1.0 - penalty)
+    }
+
+    /// Round trip of the latest probe; `None` if it failed or there is none.
+    pub fn last_rtt_ms(&self) -> Option<u32> {
+        self.probes.back().and_then(|p| p.rtt_ms)
+    }
+
+    pub fn record_probe(&mut self, r: &ProbeResult) {
+        self.probes.push_back(ProbeSample {
+            at: OffsetDateTime::now_utc(),
+            ok: r.ok,
+            rtt_ms: r.rtt_ms,
+        });
+        while self.probes.len() > PROBE_HISTORY_LEN {
+            self.probes.pop_front();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReputationStoreV1 {
+    #[serde(default)]
+    pub worlds: BTreeMap<Uuid, WorldReputation>,
+}
+
+impl ReputationStoreV1 {
+    pub fn get(&self, world_id: Uuid) -> WorldReputation {
+        self.worlds.get(&world_id).cloned().unwrap_or_default()
+    }
+}
+
+pub fn reputation_path(store: &WorldStore) -> PathBuf {
+    store.root_dir().join("reputation.json")
+}
+
+pub fn load(store: &WorldStore) -> Result<ReputationStoreV1> {
+    let path = reputation_path(store);
+    if !path.exists() {
+        return Ok(ReputationStoreV1::default());
+    }
+    let data = fs::read_to_string(&path).with_context(|| format!("read {path:?}"))?;
+    serde_json::from_str(&data).with_context(|| format!("parse {path:?}"))
+}
+
+/// Load, modify and save the store under the process-wide lock.
+pub fn update<T>(
+    store: &WorldStore,
+    f: impl FnOnce(&mut ReputationStoreV1) -> Result<T>,
+) -> Result<T> {
+    let _guard = REPUTATION_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut rep = load(store)?;
+    let out = f(&mut rep)?;
+    let json = serde_json::to_string_pretty(&rep).context("serialize reputation")?;
+    write_atomic(&reputation_path(store), format!("{json}\n").as_bytes())?;
+    Ok(out)
+}
+
+pub fn rate(store: &WorldStore, world_id: Uuid, rater: &str, stars: u8) -> Result<WorldReputation> {
+    if !(1..=5).contains(&stars) {
+        anyhow::bail!("stars must be 1-5");
+    }
+    update(store, |rep| {
+        let w = rep.worlds.entry(world_id).or_default();
+        w.ratings.insert(rater.to_string(), stars);
+        Ok(w.clone())
+    })
+}
+
+pub fn report(store: &WorldStore, world_id: Uuid, report: AbuseReport) -> Result<WorldReputation> {
+    update(store, |rep| {
+        let w = rep.worlds.entry(world_id).or_default();
+        // One report per reporter and reason.
+        let dup = report.reporter.is_some()
+            && w.reports
+                .iter()
+                .any(|r| r.reporter == report.reporter && r.reason == report.reason);
+        if !dup {
+            w.reports.push(report);
+        }
+        Ok(w.clone())
+    })
+}
+
+pub fn record_probes(store: &WorldStore, results: &[ProbeResult]) -> Result<()> {
+    update(store, |rep| {
+        for r in results {
+            rep.worlds.entry(r.world_id).or_default().record_probe(r);
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn probe(ok: bool) -> ProbeResult {
+        ProbeResult {
+            world_id: Uuid::nil(),
+            ok,
+            rtt_ms: ok.then_some(20),
+            player_count: None,
+            attested: None,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn dead_or_reported_worlds_sink() {
+        let neutral = WorldReputation::default();
+        assert!((neutral.score() - 0.5).abs() < 1e-6);
+
+        let mut alive = WorldReputation::default();
+        for _ in 0..5 {
+            alive.record_probe(&probe(true));
+        }
+        alive.ratings.insert("a".to_string(), 5);
+        assert!((alive.score() - 1.0).abs() < 1e-6);
+
+        let mut dead = WorldReputation::default();
+        for _ in 0..(PROBE_HISTORY_LEN + 5) {
+            dead.record_probe(&probe(false));
+        }
+        assert_eq!(dead.probes.len(), PROBE_HISTORY_LEN);
+        assert!(dead.score() < neutral.score());
+
+        let mut reported = alive.clone();
+        for _ in 0..5 {
+            reported.reports.push(AbuseReport {
+                reason: ReportReason::Scam,
+                reporter: None,
+                note: None,
+                at: OffsetDateTime::now_utc(),
+            });
+        }
+        assert_eq!(reported.score(), 0.0);
+    }
+}
@@ -0,0 +1,170 @@
+//! World rules (`manifest.rules`): the `RulesRequired` sent before `Welcome`, and the
+//! acknowledgments kept in `rules_acks.jsonl` so hosts can show who agreed to what.
+
+use anyhow::{Context, Result};
+use owp_protocol::session::{Event, ProtocolStateMachine};
+use owp_protocol::{ErrorCode, Hello, RulesRequired, WorldRules};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use time::OffsetDateTime;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tracing::info;
+
+/// How long a client has to answer `rules_required`.
+const ACK_WINDOW: Duration = Duration::from_secs(300);
+
+/// One player accepting one version of the rules.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RulesAckRecord {
+    #[serde(with = "time::serde::rfc3339")]
+    pub at: OffsetDateTime,
+    /// Verified wallet of the player; absent for players who didn't prove one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wallet: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile_id: Option<String>,
+    pub sha256: String,
+}
+
+pub fn acks_path(world_dir: &Path) -> PathBuf {
+    world_dir.join("rules_acks.jsonl")
+}
+
+pub fn load_acks(world_dir: &Path) -> Result<Vec<RulesAckRecord>> {
+    let path = acks_path(world_dir);
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    let data = fs::read_to_string(&path).with_context(|| format!("read {path:?}"))?;
+    data.lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| serde_json::from_str(l).with_context(|| format!("parse {path:?}")))
+        .collect()
+}
+
+pub fn record_ack(world_dir: &Path, ack: &RulesAckRecord) -> Result<()> {
+    let path = acks_path(world_dir);
+    let line = serde_json::to_string(ack).context("serialize rules ack")?;
+    let mut f = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("open {path:?}"))?;
+    writeln!(f, "{line}").with_context(|| format!("append {path:?}"))?;
+    f.sync_data().with_context(|| format!("sync {path:?}"))
+}
+
+/// Whether `wallet` already accepted this version of the rules.
+pub fn has_acked(acks: &[RulesAckRecord], rules: &WorldRules, wallet: &str) -> bool {
+    acks.iter()
+        .any(|a| a.wallet.as_deref() == Some(wallet) && a.sha256 == rules.sha256)
+}
+
+/// Have the player acknowledge the rules before `Welcome`, unless a wallet it proved already did.
+/// `Ok(false)` means the player was turned away (and told why); the caller should hang up.
+pub async fn admit<S: AsyncRead + AsyncWrite + Unpin>(
+    proto: &mut ProtocolStateMachine,
+    stream: &mut S,
+    world_dir: &Path,
+    rules: &WorldRules,
+    hello: &Hello,
+    peer: SocketAddr,
+) -> Result<bool> {
+    // `hello.wallet_pubkey` was cleared unless its proof checked out.
+    let wallet = hello
+        .wallet_proof
+        .as_ref()
+        .and(hello.wallet_pubkey.as_deref());
+    if let Some(wallet) = wallet {
+        if has_acked(&load_acks(world_dir)?, rules, wallet) {
+            return Ok(true);
+        }
+    }
+
+    proto.require_rules_ack(RulesRequired {
+        request_id: hello.request_id,
+        sha256: rules.sha256.clone(),
+        uri: rules.uri.clone(),
+    })?;
+    proto.flush(stream).await?;
+    let ack = match tokio::time::timeout(ACK_WINDOW, proto.next_handshake_event(stream)).await {
+        Ok(Ok(Event::RulesAck(ack))) => ack,
+        Ok(Ok(other)) => anyhow::bail!("unexpected event awaiting rules_ack: {other:?}"),
+        Ok(Err(e)) => return Err(e).context("read rules_ack"),
+        Err(_) => {
+            info!("{peer} didn't accept the rules in time");
+            return refuse(proto, stream, "the rules weren't accepted in time").await;
+        }
+    };
+    if !ack.sha256.eq_ignore_ascii_case(&rules.sha256) {
+        return refuse(
+            proto,
+            stream,
+            "rules_ack names another version of the rules",
+        )
+        .await;
+    }
+    info!("{peer} accepted the rules {}", rules.sha256);
+    record_ack(
+        world_dir,
+        &RulesAckRecord {
+            at: OffsetDateTime::now_utc(),
+            wallet: wallet.map(String::from),
+            profile_id: hello.profile_id.clone(),
+            sha256: rules.sha256.clone(),
+        },
+    )?;
+    Ok(true)
+}
+
+async fn refuse<S: AsyncWrite + Unpin>(
+    proto: &mut ProtocolStateMachine,
+    stream: &mut S,
+    message: &str,
+) -> Result<bool> {
+    proto.reject(ErrorCode::RulesNotAccepted, message.to_string())?;
+    proto.flush(stream).await?;
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn acks_are_kept_per_wallet_and_version() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        assert!(load_acks(dir.path()).expect("empty").is_empty());
+        let ack = RulesAckRecord {
+            at: OffsetDateTime::now_utc(),
+            wallet: Some("Wallet".into()),
+            profile_id: None,
+            sha256: "aa".into(),
+        };
+        record_ack(dir.path(), &ack).expect("record");
+        let anonymous = RulesAckRecord {
+            wallet: None,
+            profile_id: Some("bob".into()),
+            ..ack.clone()
+        };
+        record_ack(dir.path(), &anonymous).expect("record");
+        let acks = load_acks(dir.path()).expect("load");
+        assert_eq!(acks, vec![ack, anonymous]);
+
+        let rules = WorldRules {
+            sha256: "aa".into(),
+            uri: "/content/aa".into(),
+        };
+        assert!(has_acked(&acks, &rules, "Wallet"));
+        assert!(!has_acked(&acks, &rules, "Someone"));
+        let revised = WorldRules {
+            sha256: "bb".into(),
+            ..rules
+        };
+        assert!(!has_acked(&acks, &revised, "Wallet"));
+    }
+}
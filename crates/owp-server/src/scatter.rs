@@ -0,0 +1,353 @@
+//! Procedural scatter brush: fill a rectangle of the ground with trees, rocks or any other
+//! prefab mix from a few parameters, instead of placing every object by hand.
+
+use anyhow::Result;
+use owp_protocol::{ChunkChangeV1, ChunkCoord, WorldObjectV1};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use crate::chunks::{self, CHUNK_SIZE_M};
+use crate::wal::{self, WalOp};
+
+/// Objects one scatter may place.
+pub const MAX_SCATTER_OBJECTS: usize = 1000;
+
+/// Longest side of a scatter region, in meters.
+pub const MAX_REGION_SIDE_M: f32 = 512.0;
+
+const MAX_PREFABS: usize = 16;
+
+const MAX_KIND_CHARS: usize = 64;
+
+/// Axis-aligned rectangle on the XZ ground plane.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ScatterRegion {
+    /// `[x, z]` of the low corner.
+    pub min: [f32; 2],
+    /// `[x, z]` of the high corner.
+    pub max: [f32; 2],
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScatterPrefab {
+    pub kind: String,
+    /// Relative share of placements.
+    #[serde(default = "one")]
+    pub weight: f32,
+    /// Uniform scale is picked between these.
+    #[serde(default = "one")]
+    pub scale_min: f32,
+    #[serde(default = "one")]
+    pub scale_max: f32,
+}
+
+fn one() -> f32 {
+    1.0
+}
+
+fn default_jitter() -> f32 {
+    0.8
+}
+
+fn default_clearance() -> f32 {
+    1.0
+}
+
+/// Body of `POST /worlds/:world_id/scatter`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScatterRequest {
+    pub region: ScatterRegion,
+    /// Objects per 100 m².
+    pub density: f32,
+    /// How far (0-1 of the grid spacing) points stray from a regular grid.
+    #[serde(default = "default_jitter")]
+    pub jitter: f32,
+    pub prefabs: Vec<ScatterPrefab>,
+    /// Same seed, same placements; ids derive from it, so re-running a seed replaces its
+    /// objects instead of adding more.
+    #[serde(default)]
+    pub seed: u64,
+    /// Meters kept free around objects already in the world.
+    #[serde(default = "default_clearance")]
+    pub clearance: f32,
+    /// Ground height for placed objects.
+    #[serde(default)]
+    pub y: f32,
+    /// Return the placements without writing them.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScatterResult {
+    pub placed: usize,
+    /// Points dropped for being too close to existing objects.
+    pub skipped: usize,
+    pub chunks: Vec<ChunkCoord>,
+    pub objects: Vec<WorldObjectV1>,
+}
+
+pub fn check_kind(kind: &str) -> Result<()> {
+    let ok = !kind.is_empty()
+        && kind.len() <= MAX_KIND_CHARS
+        && kind
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-');
+    if !ok {
+        anyhow::bail!("prefab kinds are 1-{MAX_KIND_CHARS} characters of a-z, 0-9, '_' and '-'");
+    }
+    Ok(())
+}
+
+/// Grid spacing and cell counts along x and z.
+fn grid(req: &ScatterRequest) -> (f32, usize, usize) {
+    let spacing = (100.0 / req.density).sqrt();
+    let cells = |lo: f32, hi: f32| (((hi - lo) / spacing).floor() as usize).max(1);
+    (
+        spacing,
+        cells(req.region.min[0], req.region.max[0]),
+        cells(req.region.min[1], req.region.max[1]),
+    )
+}
+
+pub fn validate(req: &ScatterRequest) -> Result<()> {
+    let ScatterRegion { min, max } = req.region;
+    if !min.iter().chain(&max).all(|v| v.is_finite()) || min[0] >= max[0] || min[1] >= max[1] {
+        anyhow::bail!("region.min must be below region.max on both axes");
+    }
+    if max[0] - min[0] > MAX_REGION_SIDE_M || max[1] - min[1] > MAX_REGION_SIDE_M {
+        anyhow::bail!("regions are at most {MAX_REGION_SIDE_M} m on a side");
+    }
+    if !(req.density.is_finite() && req.density > 0.0) {
+        anyhow::bail!("density must be above 0");
+    }
+    if !(0.0..=1.0).contains(&req.jitter) {
+        anyhow::bail!("jitter must be between 0 and 1");
+    }
+    if !(req.clearance.is_finite() && req.clearance >= 0.0 && req.y.is_finite()) {
+        anyhow::bail!("clearance and y must be finite, clearance not negative");
+    }
+    if req.prefabs.is_empty() || req.prefabs.len() > MAX_PREFABS {
+        anyhow::bail!("give 1-{MAX_PREFABS} prefabs");
+    }
+    for p in &req.prefabs {
+        check_kind(&p.kind)?;
+        if !(p.weight.is_finite() && p.weight > 0.0) {
+            anyhow::bail!("prefab {:?} needs a weight above 0", p.kind);
+        }
+        if !(p.scale_min > 0.0 && p.scale_min <= p.scale_max && p.scale_max <= 100.0) {
+            anyhow::bail!(
+                "prefab {:?} needs 0 < scale_min <= scale_max <= 100",
+                p.kind
+            );
+        }
+    }
+    let (_, nx, nz) = grid(req);
+    if nx.saturating_mul(nz) > MAX_SCATTER_OBJECTS {
+        anyhow::bail!(
+            "that density would place {} objects; the limit is {MAX_SCATTER_OBJECTS}",
+            nx.saturating_mul(nz)
+        );
+    }
+    Ok(())
+}
+
+fn id_prefix(seed: u64) -> String {
+    format!("scatter-{seed:x}-")
+}
+
+/// Placements for a validated request, skipping points that land on `existing` objects.
+/// Returns the placements and how many points were skipped.
+pub fn place(req: &ScatterRequest, existing: &[WorldObjectV1]) -> (Vec<WorldObjectV1>, usize) {
+    let prefix = id_prefix(req.seed);
+    let obstacles: Vec<([f32; 2], f32)> = existing
+        .iter()
+        .filter(|o| !o.id.starts_with(&prefix))
+        .map(|o| {
+            let radius = o.scale[0].abs().max(o.scale[2].abs()) / 2.0;
+            ([o.position[0], o.position[2]], radius + req.clearance)
+        })
+        .collect();
+    let total_weight: f32 = req.prefabs.iter().map(|p| p.weight).sum();
+    let (spacing, nx, nz) = grid(req);
+    let ScatterRegion { min, max } = req.region;
+
+    let mut rng = StdRng::seed_from_u64(req.seed);
+    let mut out = Vec::new();
+    let mut skipped = 0;
+    for i in 0..nx * nz {
+        // Draw every value up front so one skipped point doesn't shift the rest.
+        let jx = rng.gen_range(-0.5..=0.5) * req.jitter * spacing;
+        let jz = rng.gen_range(-0.5..=0.5) * req.jitter * spacing;
+        let mut pick = rng.gen_range(0.0..total_weight);
+        let yaw = rng.gen_range(0.0..360.0);
+        let t: f32 = rng.gen_range(0.0..=1.0);
+
+        let x = (min[0] + ((i % nx) as f32 + 0.5) * spacing + jx).clamp(min[0], max[0]);
+        let z = (min[1] + ((i / nx) as f32 + 0.5) * spacing + jz).clamp(min[1], max[1]);
+        if obstacles.iter().any(|([ox, oz], r)| {
+            let (dx, dz) = (x - ox, z - oz);
+            dx * dx + dz * dz < r * r
+        }) {
+            skipped += 1;
+            continue;
+        }
+        let prefab = req
+            .prefabs
+            .iter()
+            .find(|p| {
+                pick -= p.weight;
+                pick < 0.0
+            })
+            .unwrap_or(&req.prefabs[req.prefabs.len() - 1]);
+        let s = prefab.scale_min + (prefab.scale_max - prefab.scale_min) * t;
+        out.push(WorldObjectV1 {
+            id: format!("{prefix}{i}"),
+            kind: prefab.kind.clone(),
+            position: [x, req.y, z],
+            rotation: [0.0, yaw, 0.0],
+            scale: [s, s, s],
+        });
+    }
+    (out, skipped)
+}
+
+/// Chunks touched by `region` grown by `margin` meters.
+fn chunks_under(region: ScatterRegion, margin: f32) -> Vec<ChunkCoord> {
+    let lo = chunks::chunk_for_position([region.min[0] - margin, 0.0, region.min[1] - margin]);
+    let hi = chunks::chunk_for_position([region.max[0] + margin, 0.0, region.max[1] + margin]);
+    (lo.z..=hi.z)
+        .flat_map(|z| (lo.x..=hi.x).map(move |x| ChunkCoord { x, z }))
+        .collect()
+}
+
+/// Validate, place against the world's current objects and, unless `dry_run`, write the
+/// placements through the WAL in one batch.
+pub fn scatter(world_dir: &Path, req: &ScatterRequest) -> Result<ScatterResult> {
+    validate(req)?;
+    // Existing objects can reach into the region from neighbouring chunks.
+    let mut existing = Vec::new();
+    for chunk in chunks_under(req.region, req.clearance + CHUNK_SIZE_M / 2.0) {
+        existing.extend(chunks::load_chunk(world_dir, chunk)?.objects);
+    }
+    let (objects, skipped) = place(req, &existing);
+    let touched: BTreeSet<(i32, i32)> = objects
+        .iter()
+        .map(|o| chunks::chunk_for_position(o.position))
+        .map(|c| (c.x, c.z))
+        .collect();
+
+    if !req.dry_run {
+        let ops = objects
+            .iter()
+            .map(|o| WalOp::ChunkChange {
+                chunk: chunks::chunk_for_position(o.position),
+                change: ChunkChangeV1::Upsert { object: o.clone() },
+            })
+            .collect();
+        wal::mutate_batch(world_dir, ops)?;
+    }
+    Ok(ScatterResult {
+        placed: objects.len(),
+        skipped,
+        chunks: touched
+            .into_iter()
+            .map(|(x, z)| ChunkCoord { x, z })
+            .collect(),
+        objects,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(seed: u64) -> ScatterRequest {
+        ScatterRequest {
+            region: ScatterRegion {
+                min: [-20.0, -20.0],
+                max: [20.0, 20.0],
+            },
+            density: 4.0,
+            jitter: 0.8,
+            prefabs: vec![
+                ScatterPrefab {
+                    kind: "tree_pine".to_string(),
+                    weight: 3.0,
+                    scale_min: 0.8,
+                    scale_max: 1.4,
+                },
+                ScatterPrefab {
+                    kind: "rock_large".to_string(),
+                    weight: 1.0,
+                    scale_min: 1.0,
+                    scale_max: 1.0,
+                },
+            ],
+            seed,
+            clearance: 1.0,
+            y: 0.0,
+            dry_run: false,
+        }
+    }
+
+    #[test]
+    fn scatter_is_seeded_bounded_and_avoids_existing_objects() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let house = WorldObjectV1 {
+            id: "house".to_string(),
+            kind: "house".to_string(),
+            position: [0.0, 0.0, 0.0],
+            rotation: [0.0; 3],
+            scale: [8.0, 4.0, 8.0],
+        };
+        wal::mutate(
+            dir.path(),
+            WalOp::ChunkChange {
+                chunk: chunks::chunk_for_position(house.position),
+                change: ChunkChangeV1::Upsert { object: house },
+            },
+        )
+        .expect("place house");
+
+        let first = scatter(dir.path(), &request(7)).expect("scatter");
+        assert_eq!(first.placed + first.skipped, 64);
+        assert!(first.skipped > 0);
+        assert_eq!(first.chunks.len(), 4);
+        assert!(first.objects.iter().all(|o| {
+            (-20.0..=20.0).contains(&o.position[0])
+                && (-20.0..=20.0).contains(&o.position[2])
+                && o.position[0].hypot(o.position[2]) >= 5.0
+        }));
+
+        // The same seed replaces its own objects rather than dodging them.
+        let again = scatter(dir.path(), &request(7)).expect("scatter again");
+        let positions = |r: &ScatterResult| {
+            r.objects
+                .iter()
+                .map(|o| (o.id.clone(), o.position))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(positions(&again), positions(&first));
+        let stored: usize = first
+            .chunks
+            .iter()
+            .map(|c| {
+                chunks::load_chunk(dir.path(), *c)
+                    .expect("chunk")
+                    .objects
+                    .len()
+            })
+            .sum();
+        assert_eq!(stored, first.placed + 1);
+
+        let mut dense = request(7);
+        dense.density = 100.0;
+        assert!(validate(&dense).is_err());
+        let mut bad = request(7);
+        bad.prefabs[0].kind = "Tree Pine".to_string();
+        assert!(validate(&bad).is_err());
+    }
+}
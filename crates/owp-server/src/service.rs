@@ -0,0 +1,509 @@
+use anyhow::{Context, Result};
+use directories::UserDirs;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use uuid::Uuid;
+
+/// Prefix for every unit / plist / task we create, so uninstall can find them again.
+const SERVICE_PREFIX: &str = "owp-";
+
+/// Written into every file we generate; uninstall and status only touch files carrying it.
+const SERVICE_MARKER: &str =
+    "Generated by `owp-server service install`; removed by `service uninstall`.";
+
+/// Env vars forwarded from the installing shell into the service environment.
+const FORWARDED_ENV: &[&str] = &["RUST_LOG", "OWP_SOLANA_RPC_URL", "OWP_REGISTRY_PROGRAM_ID"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServicePlatform {
+    Systemd,
+    Launchd,
+    /// Windows has no way to run a plain console binary as a real service, so we register a
+    /// `.cmd` wrapper as an at-startup scheduled task instead.
+    WindowsTask,
+}
+
+impl ServicePlatform {
+    pub fn current() -> Result<Self> {
+        if cfg!(target_os = "linux") {
+            Ok(Self::Systemd)
+        } else if cfg!(target_os = "macos") {
+            Ok(Self::Launchd)
+        } else if cfg!(windows) {
+            Ok(Self::WindowsTask)
+        } else {
+            anyhow::bail!("service install is not supported on this platform")
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ServiceSpec {
+    /// e.g. `owp-admin`, `owp-world-<uuid>`
+    pub name: String,
+    pub description: String,
+    /// Arguments passed to the owp-server binary.
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct InstallOptions {
+    pub exe: PathBuf,
+    pub data_dir: PathBuf,
+    pub env: Vec<(String, String)>,
+    /// System-wide units (root) instead of per-user ones. Ignored on Windows.
+    pub system: bool,
+}
+
+impl InstallOptions {
+    /// Use the running binary, the given data dir, and forward known OWP env vars.
+    pub fn detect(data_dir: PathBuf, system: bool) -> Result<Self> {
+        let exe = std::env::current_exe().context("resolve current executable")?;
+        let mut env = vec![("OWP_DATA_DIR".to_string(), data_dir.display().to_string())];
+        for key in FORWARDED_ENV {
+            if let Ok(v) = std::env::var(key) {
+                if !v.trim().is_empty() {
+                    env.push((key.to_string(), v));
+                }
+            }
+        }
+        Ok(Self {
+            exe,
+            data_dir,
+            env,
+            system,
+        })
+    }
+}
+
+/// One admin unit plus one `run` unit per world.
+pub fn specs(admin_listen: &str, world_ids: &[Uuid]) -> Vec<ServiceSpec> {
+    let mut out = vec![ServiceSpec {
+        name: format!("{SERVICE_PREFIX}admin"),
+        description: "OWP admin API".to_string(),
+        args: vec![
+            "admin".to_string(),
+            "--listen".to_string(),
+            admin_listen.to_string(),
+        ],
+    }];
+    for id in world_ids {
+        out.push(ServiceSpec {
+            name: format!("{SERVICE_PREFIX}world-{id}"),
+            description: format!("OWP game server for world {id}"),
+            args: vec!["run".to_string(), "--world-id".to_string(), id.to_string()],
+        });
+    }
+    out
+}
+
+/// Quote for a unit file value; `%` is doubled so systemd doesn't expand it as a specifier.
+fn systemd_quote(s: &str) -> String {
+    let s = s.replace('%', "%%");
+    if s.chars()
+        .any(|c| c.is_whitespace() || c == '"' || c == '\\')
+    {
+        format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        s
+    }
+}
+
+pub fn render_systemd(spec: &ServiceSpec, opts: &InstallOptions) -> String {
+    let mut exec = vec![systemd_quote(&opts.exe.display().to_string())];
+    exec.extend(spec.args.iter().map(|a| systemd_quote(a)));
+    let mut out = format!(
+        "# {SERVICE_MARKER}\n[Unit]\nDescription={}\nAfter=network-online.target\nWants=network-online.target\n\n[Service]\nType=simple\nExecStart={}\nWorkingDirectory={}\nRestart=on-failure\nRestartSec=5\n",
+        spec.description.replace('%', "%%"),
+        exec.join(" "),
+        systemd_quote(&opts.data_dir.display().to_string()),
+    );
+    for (k, v) in &opts.env {
+        out.push_str(&format!(
+            "Environment={}\n",
+            systemd_quote(&format!("{k}={v}"))
+        ));
+    }
+    let target = if opts.system {
+        "multi-user.target"
+    } else {
+        "default.target"
+    };
+    out.push_str(&format!("\n[Install]\nWantedBy={target}\n"));
+    out
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn launchd_label(name: &str) -> String {
+    format!("dev.owp.{}", name.trim_start_matches(SERVICE_PREFIX))
+}
+
+pub fn render_launchd(spec: &ServiceSpec, opts: &InstallOptions) -> String {
+    let mut args = format!(
+        "    <string>{}</string>\n",
+        xml_escape(&opts.exe.display().to_string())
+    );
+    for a in &spec.args {
+        args.push_str(&format!("    <string>{}</string>\n", xml_escape(a)));
+    }
+    let mut env = String::new();
+    for (k, v) in &opts.env {
+        env.push_str(&format!(
+            "    <key>{}</key>\n    <string>{}</string>\n",
+            xml_escape(k),
+            xml_escape(v)
+        ));
+    }
+    let logs = opts.data_dir.join("logs");
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!-- {marker} -->
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+  <key>Label</key>
+  <string>{label}</string>
+  <key>ProgramArguments</key>
+  <array>
+{args}  </array>
+  <key>EnvironmentVariables</key>
+  <dict>
+{env}  </dict>
+  <key>WorkingDirectory</key>
+  <string>{workdir}</string>
+  <key>RunAtLoad</key>
+  <true/>
+  <key>KeepAlive</key>
+  <true/>
+  <key>StandardOutPath</key>
+  <string>{stdout}</string>
+  <key>StandardErrorPath</key>
+  <string>{stderr}</string>
+</dict>
+</plist>
+"#,
+        marker = SERVICE_MARKER,
+        label = xml_escape(&launchd_label(&spec.name)),
+        workdir = xml_escape(&opts.data_dir.display().to_string()),
+        stdout = xml_escape(
+            &logs
+                .join(format!("{}.out.log", spec.name))
+                .display()
+                .to_string()
+        ),
+        stderr = xml_escape(
+            &logs
+                .join(format!("{}.err.log", spec.name))
+                .display()
+                .to_string()
+        ),
+    )
+}
+
+/// `%` doubled so cmd doesn't expand it as a variable.
+fn cmd_escape(s: &str) -> String {
+    s.replace('%', "%%")
+}
+
+pub fn render_windows_cmd(spec: &ServiceSpec, opts: &InstallOptions) -> String {
+    let mut out = format!("@echo off\r\nrem {SERVICE_MARKER}\r\n");
+    for (k, v) in &opts.env {
+        out.push_str(&format!("set \"{k}={}\"\r\n", cmd_escape(v)));
+    }
+    let data_dir = cmd_escape(&opts.data_dir.display().to_string());
+    out.push_str(&format!("cd /d \"{data_dir}\"\r\n"));
+    out.push_str(&format!(
+        "\"{}\"",
+        cmd_escape(&opts.exe.display().to_string())
+    ));
+    for a in &spec.args {
+        out.push_str(&format!(" \"{}\"", cmd_escape(a)));
+    }
+    out.push_str("\r\n");
+    out
+}
+
+fn windows_task_name(spec_name: &str) -> String {
+    format!("OWP\\{spec_name}")
+}
+
+fn unit_dir(platform: ServicePlatform, opts: &InstallOptions) -> Result<PathBuf> {
+    let home = || -> Result<PathBuf> {
+        Ok(UserDirs::new()
+            .context("resolve user dirs")?
+            .home_dir()
+            .to_path_buf())
+    };
+    Ok(match (platform, opts.system) {
+        (ServicePlatform::Systemd, true) => PathBuf::from("/etc/systemd/system"),
+        (ServicePlatform::Systemd, false) => home()?.join(".config/systemd/user"),
+        (ServicePlatform::Launchd, true) => PathBuf::from("/Library/LaunchDaemons"),
+        (ServicePlatform::Launchd, false) => home()?.join("Library/LaunchAgents"),
+        (ServicePlatform::WindowsTask, _) => opts.data_dir.join("service"),
+    })
+}
+
+fn unit_file_name(platform: ServicePlatform, spec: &ServiceSpec) -> String {
+    match platform {
+        ServicePlatform::Systemd => format!("{}.service", spec.name),
+        ServicePlatform::Launchd => format!("{}.plist", launchd_label(&spec.name)),
+        ServicePlatform::WindowsTask => format!("{}.cmd", spec.name),
+    }
+}
+
+fn run(cmd: &mut Command) -> Result<String> {
+    let out = cmd.output().with_context(|| format!("spawn {cmd:?}"))?;
+    let stdout = String::from_utf8_lossy(&out.stdout).trim().to_string();
+    if !out.status.success() {
+        anyhow::bail!(
+            "{cmd:?} failed ({}): {}",
+            out.status,
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+    }
+    Ok(stdout)
+}
+
+fn systemctl(system: bool) -> Command {
+    let mut c = Command::new("systemctl");
+    if !system {
+        c.arg("--user");
+    }
+    c
+}
+
+/// Write unit files and enable/start them. With `dry_run`, only print what would be written.
+pub fn install(specs: &[ServiceSpec], opts: &InstallOptions, dry_run: bool) -> Result<()> {
+    let platform = ServicePlatform::current()?;
+    let dir = unit_dir(platform, opts)?;
+    for spec in specs {
+        let path = dir.join(unit_file_name(platform, spec));
+        let body = match platform {
+            ServicePlatform::Systemd => render_systemd(spec, opts),
+            ServicePlatform::Launchd => render_launchd(spec, opts),
+            ServicePlatform::WindowsTask => render_windows_cmd(spec, opts),
+        };
+        if dry_run {
+            println!("# {}\n{body}", path.display());
+            continue;
+        }
+        fs::create_dir_all(&dir).with_context(|| format!("create {dir:?}"))?;
+        fs::write(&path, body).with_context(|| format!("write {path:?}"))?;
+        println!("wrote {}", path.display());
+    }
+    if dry_run {
+        return Ok(());
+    }
+    fs::create_dir_all(opts.data_dir.join("logs")).context("create logs dir")?;
+
+    match platform {
+        ServicePlatform::Systemd => {
+            run(systemctl(opts.system).arg("daemon-reload"))?;
+            for spec in specs {
+                run(systemctl(opts.system).args(["enable", "--now", &spec.name]))?;
+            }
+            if !opts.system {
+                println!(
+                    "note: user services only start at boot with lingering enabled: loginctl enable-linger $USER"
+                );
+            }
+        }
+        ServicePlatform::Launchd => {
+            for spec in specs {
+                let path = dir.join(unit_file_name(platform, spec));
+                run(Command::new("launchctl").arg("load").arg("-w").arg(&path))?;
+            }
+        }
+        ServicePlatform::WindowsTask => {
+            for spec in specs {
+                let path = dir.join(unit_file_name(platform, spec));
+                run(Command::new("schtasks")
+                    .args(["/Create", "/F", "/SC", "ONSTART", "/RU", "SYSTEM", "/TN"])
+                    .arg(windows_task_name(&spec.name))
+                    .arg("/TR")
+                    .arg(format!("\"{}\"", path.display())))?;
+                run(Command::new("schtasks")
+                    .args(["/Run", "/TN"])
+                    .arg(windows_task_name(&spec.name)))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Names of every OWP service installed in the unit dir: files with our naming that carry
+/// `SERVICE_MARKER`, so a hand-written `owp-*` unit is left alone.
+fn installed(platform: ServicePlatform, dir: &Path) -> Vec<(String, PathBuf)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut out = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Some(file) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        let name = match platform {
+            ServicePlatform::Systemd => file.strip_suffix(".service").map(str::to_string),
+            ServicePlatform::Launchd => file
+                .strip_prefix("dev.owp.")
+                .and_then(|f| f.strip_suffix(".plist"))
+                .map(|n| format!("{SERVICE_PREFIX}{n}")),
+            ServicePlatform::WindowsTask => file.strip_suffix(".cmd").map(str::to_string),
+        };
+        let Some(name) = name.filter(|n| n.starts_with(SERVICE_PREFIX)) else {
+            continue;
+        };
+        if fs::read_to_string(&path).is_ok_and(|body| body.contains(SERVICE_MARKER)) {
+            out.push((name, path));
+        }
+    }
+    out.sort();
+    out
+}
+
+/// Stop, disable and remove every OWP service we installed.
+pub fn uninstall(opts: &InstallOptions) -> Result<()> {
+    let platform = ServicePlatform::current()?;
+    let dir = unit_dir(platform, opts)?;
+    let found = installed(platform, &dir);
+    if found.is_empty() {
+        println!("no OWP services installed in {}", dir.display());
+        return Ok(());
+    }
+    for (name, path) in &found {
+        // Stopping may fail if the service is already gone; removal is what matters.
+        let stopped = match platform {
+            ServicePlatform::Systemd => {
+                run(systemctl(opts.system).args(["disable", "--now", name.as_str()]))
+            }
+            ServicePlatform::Launchd => {
+                run(Command::new("launchctl").arg("unload").arg("-w").arg(path))
+            }
+            ServicePlatform::WindowsTask => run(Command::new("schtasks")
+                .args(["/Delete", "/F", "/TN"])
+                .arg(windows_task_name(name))),
+        };
+        if let Err(e) = stopped {
+            eprintln!("warning: {e:#}");
+        }
+        fs::remove_file(path).with_context(|| format!("remove {path:?}"))?;
+        println!("removed {name}");
+    }
+    if platform == ServicePlatform::Systemd {
+        run(systemctl(opts.system).arg("daemon-reload"))?;
+    }
+    Ok(())
+}
+
+/// Print each installed OWP service and what the service manager says about it.
+pub fn status(opts: &InstallOptions) -> Result<()> {
+    let platform = ServicePlatform::current()?;
+    let dir = unit_dir(platform, opts)?;
+    let found = installed(platform, &dir);
+    if found.is_empty() {
+        println!("no OWP services installed in {}", dir.display());
+        return Ok(());
+    }
+    for (name, path) in &found {
+        let state = match platform {
+            ServicePlatform::Systemd => {
+                // `is-active` exits non-zero for inactive units; we only want the word.
+                systemctl(opts.system)
+                    .args(["is-active", name.as_str()])
+                    .output()
+                    .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+                    .unwrap_or_else(|e| format!("unknown ({e})"))
+            }
+            ServicePlatform::Launchd => {
+                match run(Command::new("launchctl").args(["list", &launchd_label(name)])) {
+                    Ok(_) => "loaded".to_string(),
+                    Err(_) => "not loaded".to_string(),
+                }
+            }
+            ServicePlatform::WindowsTask => {
+                match run(Command::new("schtasks")
+                    .args(["/Query", "/TN"])
+                    .arg(windows_task_name(name)))
+                {
+                    Ok(_) => "registered".to_string(),
+                    Err(_) => "not registered".to_string(),
+                }
+            }
+        };
+        println!("{name}\t{state}\t{}", path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn systemd_unit_quotes_paths_and_env() {
+        let opts = InstallOptions {
+            exe: PathBuf::from("/opt/owp bin/owp-server"),
+            data_dir: PathBuf::from("/var/lib/owp"),
+            env: vec![("OWP_DATA_DIR".to_string(), "/var/lib/owp".to_string())],
+            system: true,
+        };
+        let id = Uuid::nil();
+        let specs = specs("127.0.0.1:9333", &[id]);
+        assert_eq!(specs.len(), 2);
+        let unit = render_systemd(&specs[1], &opts);
+        assert!(unit.contains(&format!(
+            "ExecStart=\"/opt/owp bin/owp-server\" run --world-id {id}\n"
+        )));
+        assert!(unit.contains("Environment=OWP_DATA_DIR=/var/lib/owp\n"));
+        assert!(unit.contains("WantedBy=multi-user.target\n"));
+    }
+
+    #[test]
+    fn percent_signs_are_escaped_for_systemd_and_cmd() {
+        let opts = InstallOptions {
+            exe: PathBuf::from("/opt/owp/owp-server"),
+            data_dir: PathBuf::from("/srv/100%owp"),
+            env: vec![("RUST_LOG".to_string(), "%i".to_string())],
+            system: false,
+        };
+        let spec = &specs("127.0.0.1:9333", &[])[0];
+        let unit = render_systemd(spec, &opts);
+        assert!(unit.contains("WorkingDirectory=/srv/100%%owp\n"));
+        assert!(unit.contains("Environment=RUST_LOG=%%i\n"));
+        let cmd = render_windows_cmd(spec, &opts);
+        assert!(cmd.contains("set \"RUST_LOG=%%i\"\r\n"));
+        assert!(cmd.contains("cd /d \"/srv/100%%owp\"\r\n"));
+    }
+
+    #[test]
+    fn only_generated_files_count_as_installed() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let opts = InstallOptions {
+            exe: PathBuf::from("/opt/owp/owp-server"),
+            data_dir: dir.path().to_path_buf(),
+            env: vec![],
+            system: false,
+        };
+        let spec = &specs("127.0.0.1:9333", &[])[0];
+        let ours = dir.path().join("owp-admin.service");
+        fs::write(&ours, render_systemd(spec, &opts)).expect("write");
+        fs::write(dir.path().join("owp-custom.service"), "[Unit]\n").expect("write");
+        let found = installed(ServicePlatform::Systemd, dir.path());
+        assert_eq!(found, [("owp-admin".to_string(), ours)]);
+
+        let plist = dir.path().join("dev.owp.admin.plist");
+        fs::write(&plist, render_launchd(spec, &opts)).expect("write");
+        assert_eq!(installed(ServicePlatform::Launchd, dir.path()).len(), 1);
+        let cmd = dir.path().join("owp-admin.cmd");
+        fs::write(&cmd, render_windows_cmd(spec, &opts)).expect("write");
+        assert_eq!(installed(ServicePlatform::WindowsTask, dir.path()).len(), 1);
+    }
+}
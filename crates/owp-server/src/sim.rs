@@ -0,0 +1,288 @@
+use anyhow::{Context, Result};
+use owp_protocol::WorldSimulationConfig;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::quota::WorldBudget;
+
+/// Step size used when fast-forwarding over offline time.
+const CATCHUP_STEP_SECS: f64 = 1.0;
+
+/// How often the live tick loop persists state.
+const SAVE_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NpcState {
+    pub id: String,
+    pub position: [f32; 3],
+    /// NPCs wander around this point.
+    pub home: [f32; 3],
+    #[serde(default = "default_wander_radius")]
+    pub wander_radius: f32,
+    /// Meters per second.
+    #[serde(default = "default_npc_speed")]
+    pub speed: f32,
+}
+
+fn default_wander_radius() -> f32 {
+    8.0
+}
+
+fn default_npc_speed() -> f32 {
+    1.2
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SimTimer {
+    pub id: String,
+    pub remaining_secs: f64,
+    /// Re-arm with this period after firing; `None` = one-shot.
+    #[serde(default)]
+    pub repeat_secs: Option<f64>,
+    #[serde(default)]
+    pub fired: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EconomyState {
+    /// Currency accrued into the world treasury per in-game hour.
+    #[serde(default)]
+    pub accrual_per_hour: f64,
+    #[serde(default)]
+    pub treasury: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SimStateV1 {
+    pub tick: u64,
+    /// Total simulated seconds.
+    #[serde(default)]
+    pub sim_time_secs: f64,
+    #[serde(with = "time::serde::rfc3339")]
+    pub last_tick_at: OffsetDateTime,
+    #[serde(default)]
+    pub npcs: Vec<NpcState>,
+    #[serde(default)]
+    pub timers: Vec<SimTimer>,
+    #[serde(default)]
+    pub economy: EconomyState,
+}
+
+impl SimStateV1 {
+    fn new() -> Self {
+        Self {
+            tick: 0,
+            sim_time_secs: 0.0,
+            last_tick_at: OffsetDateTime::now_utc(),
+            npcs: Vec::new(),
+            timers: Vec::new(),
+            economy: EconomyState::default(),
+        }
+    }
+
+    /// Advance the simulation by `dt` seconds.
+    pub fn step(&mut self, dt: f64) {
+        self.tick += 1;
+        self.sim_time_secs += dt;
+
+        let mut rng = StdRng::seed_from_u64(self.tick);
+        for npc in &mut self.npcs {
+            let max_step = npc.speed * dt as f32;
+            let dx = rng.gen_range(-1.0f32..=1.0) * max_step;
+            let dz = rng.gen_range(-1.0f32..=1.0) * max_step;
+            let nx = npc.position[0] + dx;
+            let nz = npc.position[2] + dz;
+            let off_x = nx - npc.home[0];
+            let off_z = nz - npc.home[2];
+            if (off_x * off_x + off_z * off_z).sqrt() <= npc.wander_radius {
+                npc.position[0] = nx;
+                npc.position[2] = nz;
+            } else {
+                // Drift back towards home instead of leaving the wander area.
+                npc.position[0] -= off_x.signum() * max_step.min(off_x.abs());
+                npc.position[2] -= off_z.signum() * max_step.min(off_z.abs());
+            }
+        }
+
+        for t in &mut self.timers {
+            t.remaining_secs -= dt;
+            if t.remaining_secs <= 0.0 {
+                t.fired += 1;
+                if let Some(period) = t.repeat_secs.filter(|p| *p > 0.0) {
+                    t.remaining_secs += period;
+                }
+            }
+        }
+        self.timers
+            .retain(|t| t.remaining_secs > 0.0 || t.repeat_secs.is_some_and(|p| p > 0.0));
+
+        self.economy.treasury += self.economy.accrual_per_hour * dt / 3600.0;
+    }
+
+    /// Fast-forward over the wall time elapsed since the last persisted tick.
+    /// Returns the number of seconds simulated.
+    pub fn catch_up(&mut self, now: OffsetDateTime, max_secs: u64) -> f64 {
+        let elapsed = (now - self.last_tick_at).as_seconds_f64();
+        if elapsed <= 0.0 {
+            return 0.0;
+        }
+        let total = elapsed.min(max_secs as f64);
+        let mut left = total;
+        while left > 0.0 {
+            let dt = left.min(CATCHUP_STEP_SECS);
+            self.step(dt);
+            left -= dt;
+        }
+        self.last_tick_at = now;
+        total
+    }
+}
+
+pub fn sim_state_path(world_dir: &Path) -> PathBuf {
+    world_dir.join("snapshots").join("sim_state.json")
+}
+
+pub fn load_state(world_dir: &Path) -> Result<SimStateV1> {
+    let path = sim_state_path(world_dir);
+    if !path.exists() {
+        return Ok(SimStateV1::new());
+    }
+    let data = fs::read_to_string(&path).with_context(|| format!("read {path:?}"))?;
+    let state: SimStateV1 =
+        serde_json::from_str(&data).with_context(|| format!("parse {path:?}"))?;
+    Ok(state)
+}
+
+pub fn save_state(world_dir: &Path, state: &SimStateV1) -> Result<()> {
+    let path = sim_state_path(world_dir);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("create {parent:?}"))?;
+    }
+    let json = serde_json::to_string_pretty(state).context("serialize sim state")?;
+    fs::write(&path, format!("{json}\n")).with_context(|| format!("write {path:?}"))?;
+    Ok(())
+}
+
+/// Load sim state and apply offline catch-up (only when the world keeps running while empty;
+/// otherwise the world is frozen while the server is down).
+pub fn load_for_startup(world_dir: &Path, cfg: &WorldSimulationConfig) -> Result<SimStateV1> {
+    let mut state = load_state(world_dir)?;
+    let now = OffsetDateTime::now_utc();
+    if cfg.run_while_empty {
+        let secs = state.catch_up(now, cfg.max_catchup_secs);
+        if secs > 0.0 {
+            info!(
+                "simulation caught up {secs:.0}s of offline time (tick={})",
+                state.tick
+            );
+        }
+    } else {
+        state.last_tick_at = now;
+    }
+    save_state(world_dir, &state)?;
+    Ok(state)
+}
+
+/// Drive the world tick loop. When `run_while_empty` is off the loop idles while
+/// `online == 0` and does not accumulate the paused time. Ticks over the world's CPU quota
+/// are skipped, so the simulation slows down instead of starving other worlds.
+pub async fn run_tick_loop(
+    world_dir: PathBuf,
+    cfg: WorldSimulationConfig,
+    state: Arc<Mutex<SimStateV1>>,
+    online: Arc<AtomicUsize>,
+    budget: WorldBudget,
+) {
+    let hz = cfg.tick_hz.clamp(1, 60);
+    let dt = 1.0 / hz as f64;
+    let mut interval = tokio::time::interval(Duration::from_secs_f64(dt));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut last_save = tokio::time::Instant::now();
+
+    loop {
+        interval.tick().await;
+        let active = cfg.run_while_empty || online.load(Ordering::Relaxed) > 0;
+        let mut st = state.lock().await;
+        if active && budget.tick_allowed() {
+            let started = Instant::now();
+            st.step(dt);
+            budget.charge_tick(started.elapsed());
+        }
+        st.last_tick_at = OffsetDateTime::now_utc();
+
+        if last_save.elapsed() >= SAVE_INTERVAL {
+            last_save = tokio::time::Instant::now();
+            if let Err(e) = save_state(&world_dir, &st) {
+                warn!("saving sim state failed: {e:#}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn world() -> SimStateV1 {
+        let mut state = SimStateV1::new();
+        state.npcs.push(NpcState {
+            id: "n".to_string(),
+            position: [0.0, 0.0, 0.0],
+            home: [0.0, 0.0, 0.0],
+            wander_radius: default_wander_radius(),
+            speed: default_npc_speed(),
+        });
+        state.timers.push(SimTimer {
+            id: "t".to_string(),
+            remaining_secs: 4.0,
+            repeat_secs: Some(3.0),
+            fired: 0,
+        });
+        state.economy.accrual_per_hour = 3600.0;
+        state
+    }
+
+    fn json(state: &SimStateV1) -> serde_json::Value {
+        serde_json::to_value(state).expect("serialize")
+    }
+
+    #[test]
+    fn catch_up_steps_deterministically_up_to_the_cap() {
+        let start = world();
+        let now = start.last_tick_at + time::Duration::milliseconds(10_500);
+
+        let mut caught_up = start.clone();
+        assert_eq!(caught_up.catch_up(now, 100), 10.5);
+        let mut by_hand = start.clone();
+        for _ in 0..10 {
+            by_hand.step(1.0);
+        }
+        by_hand.step(0.5);
+        by_hand.last_tick_at = now;
+        assert_eq!(json(&caught_up), json(&by_hand));
+        assert_eq!(caught_up.tick, 11);
+        assert_eq!(caught_up.timers[0].fired, 3);
+
+        // Offline time past the cap is dropped, not simulated.
+        let mut capped = start.clone();
+        let later = start.last_tick_at + time::Duration::hours(1);
+        assert_eq!(capped.catch_up(later, 30), 30.0);
+        assert_eq!(capped.tick, 30);
+        assert_eq!(capped.sim_time_secs, 30.0);
+        assert_eq!(capped.last_tick_at, later);
+
+        // A clock that went backwards simulates nothing.
+        let mut behind = start.clone();
+        let earlier = start.last_tick_at - time::Duration::seconds(5);
+        assert_eq!(behind.catch_up(earlier, 30), 0.0);
+        assert_eq!(json(&behind), json(&start));
+    }
+}
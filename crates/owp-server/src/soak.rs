@@ -0,0 +1,600 @@
+//! `owp-server soak`: serve a world in-process and connect synthetic players to it for as long as
+//! asked. Bots wander (`player_position`), chat, and place and remove objects, while the process's
+//! resident memory is sampled; a steady climb after warm-up is reported as a suspected leak.
+//!
+//! The protocol has no message for players to edit the world yet, so bots place objects through
+//! the WAL the way the admin API does, then ask the server for the chunk like a client would.
+//! Each bot keeps a few objects at a time and removes them all before it leaves.
+
+use anyhow::{Context, Result};
+use owp_protocol::session::{Event, ProtocolStateMachine};
+use owp_protocol::{
+    ChatChannel, ChatSend, ChunkChangeV1, ChunkCoord, ChunkDeltaRequest, Compression, Disconnect,
+    DisconnectReason, FrameProtection, Hello, Message, PlayerPosition, WireFormat, WorldObjectV1,
+    OWP_PROTOCOL_VERSION,
+};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::net::{SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+use tokio::net::TcpStream;
+use tokio::time::MissedTickBehavior;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::chunks;
+use crate::config::LiveConfig;
+use crate::health::{HealthRegistry, ServiceState};
+use crate::party::Parties;
+use crate::presence::Roster;
+use crate::quota::Quotas;
+use crate::storage::WorldStore;
+use crate::tcp_game;
+use crate::wal::{self, WalOp};
+
+/// Objects a bot keeps placed; placing another removes its oldest.
+const OBJECTS_PER_BOT: usize = 8;
+
+/// Bots connect spread over this long (or a tenth of the run, if shorter).
+const RAMP_UP: Duration = Duration::from_secs(10);
+
+/// How far a bot strays from the origin, in meters.
+const WANDER_RADIUS: f32 = 96.0;
+
+/// Share of the run ignored by leak detection while caches and buffers fill.
+const WARM_UP_SHARE: f64 = 0.2;
+
+/// Samples needed after warm-up to judge memory growth.
+const MIN_LEAK_SAMPLES: usize = 5;
+
+/// Growth per hour that counts as a leak: this much, or `LEAK_SHARE` of the warm baseline if
+/// that's more.
+const LEAK_BYTES_PER_HOUR: f64 = 16.0 * 1024.0 * 1024.0;
+const LEAK_SHARE: f64 = 0.05;
+
+/// How often the WAL is folded into chunk files, as the admin server does.
+const CHECKPOINT_EVERY: Duration = Duration::from_secs(30);
+
+/// Bot failures kept in the report.
+const MAX_ERRORS: usize = 20;
+
+#[derive(Debug, Clone)]
+pub struct SoakOptions {
+    pub world_id: Uuid,
+    pub bots: usize,
+    pub duration: Duration,
+    pub moves_per_sec: f64,
+    pub chats_per_min: f64,
+    pub places_per_min: f64,
+    pub sample_every: Duration,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct SoakReport {
+    pub world_id: Uuid,
+    pub bots: usize,
+    pub duration_secs: u64,
+    /// Bots that got a `welcome`.
+    pub connected: u64,
+    pub sent: SentCounts,
+    /// Messages the bots received (chat, chunk deltas, pings, snapshots...).
+    pub received: u64,
+    /// Bots that stopped before the end, and why.
+    pub failed: u64,
+    pub errors: Vec<String>,
+    pub memory: MemoryReport,
+    pub notes: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct SentCounts {
+    pub positions: u64,
+    pub chats: u64,
+    pub objects_placed: u64,
+    pub objects_removed: u64,
+    pub chunk_requests: u64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct MemoryReport {
+    /// Resident set size over the run; empty where it can't be read (not Linux).
+    pub samples: Vec<RssSample>,
+    pub start_bytes: Option<u64>,
+    pub end_bytes: Option<u64>,
+    pub peak_bytes: Option<u64>,
+    /// Least-squares growth after warm-up; `None` if the run was too short to tell.
+    pub growth_bytes_per_hour: Option<f64>,
+    pub leak_suspected: bool,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct RssSample {
+    pub at_secs: f64,
+    pub bytes: u64,
+}
+
+#[derive(Default)]
+struct Stats {
+    connected: AtomicU64,
+    positions: AtomicU64,
+    chats: AtomicU64,
+    placed: AtomicU64,
+    removed: AtomicU64,
+    chunk_requests: AtomicU64,
+    received: AtomicU64,
+}
+
+fn bump(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+/// This process's resident set size, from `/proc/self/status`.
+fn rss_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+/// Growth in bytes per hour over the samples after warm-up, and whether it looks like a leak.
+pub fn memory_growth(samples: &[RssSample], duration: Duration) -> Option<(f64, bool)> {
+    let warm_after = duration.as_secs_f64() * WARM_UP_SHARE;
+    let warm: Vec<&RssSample> = samples.iter().filter(|s| s.at_secs >= warm_after).collect();
+    if warm.len() < MIN_LEAK_SAMPLES {
+        return None;
+    }
+    let n = warm.len() as f64;
+    let mean_t = warm.iter().map(|s| s.at_secs).sum::<f64>() / n;
+    let mean_b = warm.iter().map(|s| s.bytes as f64).sum::<f64>() / n;
+    let (mut cov, mut var) = (0.0, 0.0);
+    for s in &warm {
+        cov += (s.at_secs - mean_t) * (s.bytes as f64 - mean_b);
+        var += (s.at_secs - mean_t).powi(2);
+    }
+    if var == 0.0 {
+        return None;
+    }
+    let per_hour = cov / var * 3600.0;
+    let threshold = LEAK_BYTES_PER_HOUR.max(warm[0].bytes as f64 * LEAK_SHARE);
+    Some((per_hour, per_hour > threshold))
+}
+
+fn now_ms() -> i64 {
+    (OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000) as i64
+}
+
+/// An interval ticking `per_sec` times a second, first after a random share of the period so
+/// bots don't act in lockstep. `None` for a zero rate.
+fn every(per_sec: f64, rng: &mut StdRng) -> Option<tokio::time::Interval> {
+    if per_sec <= 0.0 || !per_sec.is_finite() {
+        return None;
+    }
+    let period = Duration::from_secs_f64(1.0 / per_sec);
+    let first = tokio::time::Instant::now() + period.mul_f64(rng.gen::<f64>());
+    let mut interval = tokio::time::interval_at(first, period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    Some(interval)
+}
+
+async fn next_tick(interval: &mut Option<tokio::time::Interval>) {
+    match interval {
+        Some(i) => {
+            i.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+async fn change_chunk(world_dir: &Path, chunk: ChunkCoord, change: ChunkChangeV1) -> Result<()> {
+    let world_dir = world_dir.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        wal::mutate(&world_dir, WalOp::ChunkChange { chunk, change })
+    })
+    .await??;
+    Ok(())
+}
+
+struct Bot {
+    index: usize,
+    addr: SocketAddr,
+    world_id: Uuid,
+    world_dir: PathBuf,
+    options: SoakOptions,
+    stats: Arc<Stats>,
+    until: tokio::time::Instant,
+}
+
+impl Bot {
+    async fn run(self) -> Result<()> {
+        let mut stream = TcpStream::connect(self.addr).await.context("connect")?;
+        let hello = Hello {
+            protocol_version: OWP_PROTOCOL_VERSION.to_string(),
+            request_id: Uuid::new_v4(),
+            world_id: Some(self.world_id),
+            client_name: Some(format!("owp-soak-{}", self.index)),
+            resume_token: None,
+            party_token: None,
+            wallet_pubkey: None,
+            wallet_proof: None,
+            profile_id: None,
+            frame_protection: vec![FrameProtection::Crc32c],
+            wire_format: vec![WireFormat::Msgpack],
+            compression: Compression::ALL.to_vec(),
+            attestation_nonce: None,
+            capabilities: vec![],
+        };
+        let mut proto = ProtocolStateMachine::client(hello)?;
+        match proto
+            .next_handshake_event(&mut stream)
+            .await
+            .context("handshake")?
+        {
+            Event::Welcome(_) => bump(&self.stats.connected),
+            // Rules and entry fees need a human (and a wallet); soak a world without them.
+            other => anyhow::bail!("not admitted: {other:?}"),
+        }
+        let (mut reader, mut writer) = stream.into_split();
+
+        let mut rng = StdRng::seed_from_u64(self.index as u64);
+        let mut position = [
+            rng.gen_range(-WANDER_RADIUS..WANDER_RADIUS),
+            0.0,
+            rng.gen_range(-WANDER_RADIUS..WANDER_RADIUS),
+        ];
+        let mut heading: f32 = rng.gen_range(0.0..std::f32::consts::TAU);
+        let o = &self.options;
+        let mut moves = every(o.moves_per_sec, &mut rng);
+        let mut chats = every(o.chats_per_min / 60.0, &mut rng);
+        let mut places = every(o.places_per_min / 60.0, &mut rng);
+        let mut placed: VecDeque<(ChunkCoord, String)> = VecDeque::new();
+        let mut next_object = 0u64;
+
+        let result = loop {
+            let msg = tokio::select! {
+                read = proto.next_event(&mut reader) => match read {
+                    Ok(Event::Message(Message::Ping(ping))) => {
+                        bump(&self.stats.received);
+                        Message::Pong(ping.pong(now_ms()))
+                    }
+                    Ok(Event::Message(Message::Disconnect(bye))) => {
+                        break Err(anyhow::anyhow!("disconnected by the server: {:?}", bye.reason));
+                    }
+                    Ok(_) => {
+                        bump(&self.stats.received);
+                        continue;
+                    }
+                    Err(e) => break Err(e).context("read"),
+                },
+                () = next_tick(&mut moves) => {
+                    heading += rng.gen_range(-0.3..0.3);
+                    let step = 4.0 / o.moves_per_sec as f32;
+                    position[0] = (position[0] + heading.cos() * step).clamp(-WANDER_RADIUS, WANDER_RADIUS);
+                    position[2] = (position[2] + heading.sin() * step).clamp(-WANDER_RADIUS, WANDER_RADIUS);
+                    bump(&self.stats.positions);
+                    Message::PlayerPosition(PlayerPosition { position })
+                }
+                () = next_tick(&mut chats) => {
+                    bump(&self.stats.chats);
+                    Message::ChatSend(ChatSend {
+                        channel: ChatChannel::World,
+                        text: format!("soak bot {} at {:.0},{:.0}", self.index, position[0], position[2]),
+                    })
+                }
+                () = next_tick(&mut places) => {
+                    let chunk = chunks::chunk_for_position(position);
+                    let id = format!("soak_{}_{next_object}", self.index);
+                    next_object += 1;
+                    let object = WorldObjectV1 {
+                        id: id.clone(),
+                        kind: "rock_small".to_string(),
+                        position,
+                        rotation: [0.0, rng.gen_range(0.0..360.0), 0.0],
+                        scale: [1.0, 1.0, 1.0],
+                    };
+                    if let Err(e) = change_chunk(&self.world_dir, chunk, ChunkChangeV1::Upsert { object }).await {
+                        break Err(e).context("place object");
+                    }
+                    bump(&self.stats.placed);
+                    placed.push_back((chunk, id));
+                    if placed.len() > OBJECTS_PER_BOT {
+                        let (chunk, object_id) = placed.pop_front().expect("non-empty");
+                        if let Err(e) = change_chunk(&self.world_dir, chunk, ChunkChangeV1::Remove { object_id }).await {
+                            break Err(e).context("remove object");
+                        }
+                        bump(&self.stats.removed);
+                    }
+                    bump(&self.stats.chunk_requests);
+                    Message::ChunkDeltaRequest(ChunkDeltaRequest {
+                        request_id: Uuid::new_v4(),
+                        chunk,
+                        since_version: 0,
+                    })
+                }
+                () = tokio::time::sleep_until(self.until) => break Ok(()),
+            };
+            proto.send(&msg)?;
+            if let Err(e) = proto.flush(&mut writer).await {
+                break Err(e).context("write");
+            }
+        };
+
+        // Leave the world as it was, even after a failure.
+        for (chunk, object_id) in placed {
+            change_chunk(&self.world_dir, chunk, ChunkChangeV1::Remove { object_id }).await?;
+            bump(&self.stats.removed);
+        }
+        if result.is_ok() {
+            proto.send(&Message::Disconnect(Disconnect {
+                reason: DisconnectReason::ClientQuit,
+                message: None,
+            }))?;
+            proto.flush(&mut writer).await?;
+        }
+        result
+    }
+}
+
+async fn checkpoint(world_dir: &Path) {
+    let dir = world_dir.to_path_buf();
+    match tokio::task::spawn_blocking(move || wal::checkpoint(&dir)).await {
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) => warn!("wal checkpoint failed: {e:#}"),
+        Err(e) => warn!("wal checkpoint failed: {e}"),
+    }
+}
+
+fn free_local_port() -> Result<SocketAddr> {
+    let listener = TcpListener::bind("127.0.0.1:0").context("find a free port")?;
+    Ok(listener.local_addr()?)
+}
+
+pub async fn run(store: WorldStore, options: SoakOptions) -> Result<SoakReport> {
+    let world_id = options.world_id;
+    let world_dir = store.world_dir(world_id);
+    if !world_dir.exists() {
+        anyhow::bail!("world not found: {world_id}");
+    }
+    let addr = free_local_port()?;
+    let health = HealthRegistry::default();
+    let config = LiveConfig::load(&store)?;
+    let mut server = tokio::spawn(tcp_game::serve(
+        store.clone(),
+        world_id,
+        Some(addr.to_string()),
+        health.clone(),
+        config,
+        Parties::default(),
+        Roster::default(),
+        Quotas::default(),
+    ));
+    let key = tcp_game::health_key(world_id);
+    while health.snapshot().get(&key).map(|h| h.state) != Some(ServiceState::Running) {
+        if server.is_finished() {
+            server.await??;
+            anyhow::bail!("the game server stopped before it started listening");
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    info!(
+        "soaking world {world_id} with {} bots for {:?}",
+        options.bots, options.duration
+    );
+
+    let started = Instant::now();
+    let until = tokio::time::Instant::now() + options.duration;
+    let ramp = RAMP_UP.min(options.duration / 10);
+    let stats = Arc::new(Stats::default());
+    let mut bots = tokio::task::JoinSet::new();
+    for index in 0..options.bots {
+        let bot = Bot {
+            index,
+            addr,
+            world_id,
+            world_dir: world_dir.clone(),
+            options: options.clone(),
+            stats: stats.clone(),
+            until,
+        };
+        let delay = ramp.mul_f64(index as f64 / options.bots.max(1) as f64);
+        bots.spawn(async move {
+            tokio::time::sleep(delay).await;
+            bot.run().await.with_context(|| format!("bot {index}"))
+        });
+    }
+
+    let mut memory = MemoryReport::default();
+    let mut sampling = tokio::time::interval(options.sample_every.max(Duration::from_millis(100)));
+    sampling.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut checkpoints = tokio::time::interval_at(
+        tokio::time::Instant::now() + CHECKPOINT_EVERY,
+        CHECKPOINT_EVERY,
+    );
+    loop {
+        tokio::select! {
+            _ = sampling.tick() => {
+                if let Some(bytes) = rss_bytes() {
+                    let at_secs = started.elapsed().as_secs_f64();
+                    memory.samples.push(RssSample { at_secs, bytes });
+                }
+            }
+            _ = checkpoints.tick() => checkpoint(&world_dir).await,
+            () = tokio::time::sleep_until(until) => break,
+            done = &mut server => {
+                done??;
+                anyhow::bail!("the game server stopped during the soak");
+            }
+        }
+    }
+
+    let mut report = SoakReport {
+        world_id,
+        bots: options.bots,
+        duration_secs: options.duration.as_secs(),
+        ..SoakReport::default()
+    };
+    while let Some(joined) = bots.join_next().await {
+        if let Err(e) = joined.context("bot task")? {
+            warn!("{e:#}");
+            report.failed += 1;
+            if report.errors.len() < MAX_ERRORS {
+                report.errors.push(format!("{e:#}"));
+            }
+        }
+    }
+    server.abort();
+    checkpoint(&world_dir).await;
+
+    if let Some(bytes) = rss_bytes() {
+        let at_secs = started.elapsed().as_secs_f64();
+        memory.samples.push(RssSample { at_secs, bytes });
+    }
+    memory.start_bytes = memory.samples.first().map(|s| s.bytes);
+    memory.end_bytes = memory.samples.last().map(|s| s.bytes);
+    memory.peak_bytes = memory.samples.iter().map(|s| s.bytes).max();
+    match memory_growth(&memory.samples, options.duration) {
+        Some((per_hour, leak)) => {
+            memory.growth_bytes_per_hour = Some(per_hour);
+            memory.leak_suspected = leak;
+        }
+        None if memory.samples.is_empty() => report
+            .notes
+            .push("memory can't be sampled on this platform".to_string()),
+        None => report.notes.push(format!(
+            "too few memory samples to judge growth; run longer or lower --sample-every-secs \
+             (need {MIN_LEAK_SAMPLES} after the first {:.0}% of the run)",
+            WARM_UP_SHARE * 100.0
+        )),
+    }
+    report.memory = memory;
+    report.connected = stats.connected.load(Ordering::Relaxed);
+    report.received = stats.received.load(Ordering::Relaxed);
+    report.sent = SentCounts {
+        positions: stats.positions.load(Ordering::Relaxed),
+        chats: stats.chats.load(Ordering::Relaxed),
+        objects_placed: stats.placed.load(Ordering::Relaxed),
+        objects_removed: stats.removed.load(Ordering::Relaxed),
+        chunk_requests: stats.chunk_requests.load(Ordering::Relaxed),
+    };
+    Ok(report)
+}
+
+pub fn print(report: &SoakReport) {
+    let mb = |v: Option<u64>| {
+        v.map_or_else(
+            || "unknown".to_string(),
+            |v| format!("{:.1} MB", v as f64 / 1024.0 / 1024.0),
+        )
+    };
+    let s = &report.sent;
+    println!(
+        "bots                 {} connected of {}, {} failed",
+        report.connected, report.bots, report.failed
+    );
+    println!("duration             {}s", report.duration_secs);
+    println!(
+        "sent                 {} positions, {} chats, {} chunk requests",
+        s.positions, s.chats, s.chunk_requests
+    );
+    println!(
+        "objects              {} placed, {} removed",
+        s.objects_placed, s.objects_removed
+    );
+    println!("received             {} messages", report.received);
+    let m = &report.memory;
+    println!(
+        "memory               start {}, end {}, peak {}",
+        mb(m.start_bytes),
+        mb(m.end_bytes),
+        mb(m.peak_bytes)
+    );
+    if let Some(per_hour) = m.growth_bytes_per_hour {
+        println!(
+            "memory growth        {:.1} MB/hour{}",
+            per_hour / 1024.0 / 1024.0,
+            if m.leak_suspected {
+                " (LEAK SUSPECTED)"
+            } else {
+                ""
+            }
+        );
+    }
+    for e in &report.errors {
+        println!("error: {e}");
+    }
+    for note in &report.notes {
+        println!("note: {note}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(per_sec: f64) -> Vec<RssSample> {
+        (0..20)
+            .map(|i| RssSample {
+                at_secs: i as f64 * 60.0,
+                bytes: (100_000_000.0 + per_sec * i as f64 * 60.0) as u64,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn steady_growth_after_warm_up_is_a_leak() {
+        let run = Duration::from_secs(20 * 60);
+        // 1 MB a minute: 60 MB/hour.
+        let (per_hour, leak) = memory_growth(&samples(1_000_000.0 / 60.0), run).expect("judged");
+        assert!((per_hour - 60_000_000.0).abs() < 1.0);
+        assert!(leak);
+
+        let (per_hour, leak) = memory_growth(&samples(0.0), run).expect("judged");
+        assert_eq!(per_hour, 0.0);
+        assert!(!leak);
+
+        // Growth during warm-up alone doesn't count.
+        let mut warming = samples(0.0);
+        warming[0].bytes = 10_000_000;
+        warming[1].bytes = 50_000_000;
+        assert!(!memory_growth(&warming, run).expect("judged").1);
+
+        assert!(memory_growth(&samples(0.0)[..4], run).is_none());
+    }
+
+    #[tokio::test]
+    async fn bots_play_and_clean_up() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let store = WorldStore::with_root(root.path().to_path_buf()).expect("store");
+        let manifest = store.create_world("Soak", 0).expect("world");
+        let report = run(
+            store.clone(),
+            SoakOptions {
+                world_id: manifest.world_id,
+                bots: 3,
+                duration: Duration::from_secs(2),
+                moves_per_sec: 10.0,
+                chats_per_min: 120.0,
+                places_per_min: 240.0,
+                sample_every: Duration::from_millis(200),
+            },
+        )
+        .await
+        .expect("soak");
+        assert_eq!(report.connected, 3, "{:?}", report.errors);
+        assert_eq!(report.failed, 0, "{:?}", report.errors);
+        assert!(report.sent.positions > 0 && report.sent.chats > 0);
+        assert!(report.sent.objects_placed > 0);
+        assert_eq!(report.sent.objects_placed, report.sent.objects_removed);
+        assert!(report.received > 0);
+
+        let world_dir = store.world_dir(manifest.world_id);
+        for chunk in chunks::list_chunks(&world_dir).expect("chunks") {
+            assert!(chunk.objects.is_empty());
+        }
+    }
+}
@@ -1,24 +1,68 @@
-use anyhow::{Context, Result};
 use directories::UserDirs;
-use owp_protocol::{WorldManifestV1, WorldPorts, WorldTokenInfo, OWP_PROTOCOL_VERSION};
+use owp_protocol::{
+    WorldAssetsConfig, WorldBandwidthConfig, WorldDirectoryEntry, WorldEmotesConfig,
+    WorldManifestV1, WorldPorts, WorldSimulationConfig, WorldTokenInfo, OWP_PROTOCOL_VERSION,
+};
 use rand::{distributions::Alphanumeric, Rng};
 use std::fs;
+use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use time::OffsetDateTime;
 use uuid::Uuid;
 
+pub type Result<T, E = StorageError> = std::result::Result<T, E>;
+
+/// Why a store operation failed, so callers can tell a missing world from a damaged one.
+#[derive(Debug, thiserror::Error)]
+pub enum StorageError {
+    #[error("{0} not found")]
+    NotFound(String),
+    /// A file exists but doesn't parse.
+    #[error("parse {path:?}: {source}")]
+    Corrupt {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("{context}: {source}")]
+    Io {
+        context: String,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+impl StorageError {
+    /// `map_err` adapter that wraps an io error with `context`.
+    pub(crate) fn io(context: impl std::fmt::Display) -> impl FnOnce(std::io::Error) -> Self {
+        let context = context.to_string();
+        move |source| Self::Io { context, source }
+    }
+}
+
 #[derive(Clone)]
 pub struct WorldStore {
     root: PathBuf,
 }
 
 impl WorldStore {
+    /// Open the store at `$OWP_DATA_DIR`, falling back to `~/.owp`.
     pub fn new() -> Result<Self> {
-        let user_dirs = UserDirs::new().context("resolve user dirs")?;
+        if let Some(dir) = std::env::var_os("OWP_DATA_DIR").filter(|v| !v.is_empty()) {
+            return Self::with_root(PathBuf::from(dir));
+        }
+        let user_dirs =
+            UserDirs::new().ok_or_else(|| StorageError::NotFound("home directory".to_string()))?;
         let home = user_dirs.home_dir();
-        let root = home.join(".owp");
-        fs::create_dir_all(&root).context("create ~/.owp")?;
-        fs::create_dir_all(root.join("worlds")).context("create ~/.owp/worlds")?;
+        Self::with_root(home.join(".owp"))
+    }
+
+    /// Open a store rooted somewhere other than `~/.owp`.
+    pub fn with_root(root: PathBuf) -> Result<Self> {
+        fs::create_dir_all(&root).map_err(StorageError::io(format!("create {root:?}")))?;
+        let worlds = root.join("worlds");
+        fs::create_dir_all(&worlds).map_err(StorageError::io(format!("create {worlds:?}")))?;
         Ok(Self { root })
     }
 
@@ -38,6 +82,11 @@ impl WorldStore {
         self.root.join("profiles")
     }
 
+    /// Content-addressed blobs (avatar meshes), named by sha256.
+    pub fn content_root(&self) -> PathBuf {
+        self.root.join("content")
+    }
+
     pub fn admin_token_path(&self) -> PathBuf {
         self.root.join("admin-token")
     }
@@ -45,7 +94,7 @@ impl WorldStore {
     pub fn load_or_create_admin_token(&self) -> Result<String> {
         let path = self.admin_token_path();
         if path.exists() {
-            let t = fs::read_to_string(&path).context("read admin-token")?;
+            let t = fs::read_to_string(&path).map_err(StorageError::io("read admin-token"))?;
             return Ok(t.trim().to_string());
         }
 
@@ -54,7 +103,7 @@ impl WorldStore {
             .take(48)
             .map(char::from)
             .collect();
-        fs::write(&path, format!("{token}\n")).context("write admin-token")?;
+        fs::write(&path, format!("{token}\n")).map_err(StorageError::io("write admin-token"))?;
         Ok(token)
     }
 
@@ -69,11 +118,10 @@ impl WorldStore {
     pub fn create_world(&self, name: &str, game_port: u16) -> Result<WorldManifestV1> {
         let world_id = Uuid::new_v4();
         let dir = self.world_dir(world_id);
-        fs::create_dir_all(dir.join("manifest")).context("create manifest dir")?;
-        fs::create_dir_all(dir.join("chunks")).context("create chunks dir")?;
-        fs::create_dir_all(dir.join("assets")).context("create assets dir")?;
-        fs::create_dir_all(dir.join("snapshots")).context("create snapshots dir")?;
-        fs::create_dir_all(dir.join("logs")).context("create logs dir")?;
+        for sub in ["manifest", "chunks", "assets", "snapshots", "logs"] {
+            fs::create_dir_all(dir.join(sub))
+                .map_err(StorageError::io(format!("create {sub} dir")))?;
+        }
 
         let manifest = WorldManifestV1 {
             protocol_version: OWP_PROTOCOL_VERSION.to_string(),
@@ -84,8 +132,20 @@ impl WorldStore {
             ports: WorldPorts {
                 game_port,
                 asset_port: None,
+                quic_port: None,
             },
             token: None,
+            simulation: WorldSimulationConfig::default(),
+            assets: WorldAssetsConfig::default(),
+            emotes: WorldEmotesConfig::default(),
+            bandwidth: WorldBandwidthConfig::default(),
+            region: None,
+            tags: vec![],
+            icon_sha256: None,
+            prefab_bundle: None,
+            entry_fee: None,
+            rules: None,
+            tls: None,
         };
 
         self.write_manifest(&dir, &manifest)?;
@@ -94,9 +154,11 @@ impl WorldStore {
 
     pub fn list_worlds(&self) -> Result<Vec<WorldManifestV1>> {
         let mut out = Vec::new();
-        for entry in fs::read_dir(self.worlds_root()).context("read worlds dir")? {
-            let entry = entry?;
-            if !entry.file_type()?.is_dir() {
+        let entries =
+            fs::read_dir(self.worlds_root()).map_err(StorageError::io("read worlds dir"))?;
+        for entry in entries {
+            let entry = entry.map_err(StorageError::io("read worlds dir"))?;
+            if !entry.path().is_dir() {
                 continue;
             }
             let world_dir = entry.path();
@@ -113,17 +175,27 @@ impl WorldStore {
 
     pub fn read_manifest(&self, world_dir: &Path) -> Result<WorldManifestV1> {
         let path = Self::manifest_path(world_dir);
-        let data = fs::read_to_string(&path).with_context(|| format!("read {path:?}"))?;
-        let manifest: WorldManifestV1 =
-            serde_json::from_str(&data).with_context(|| format!("parse {path:?}"))?;
-        Ok(manifest)
+        let data = fs::read_to_string(&path).map_err(|source| {
+            if source.kind() == std::io::ErrorKind::NotFound {
+                StorageError::NotFound(format!("{path:?}"))
+            } else {
+                StorageError::Io {
+                    context: format!("read {path:?}"),
+                    source,
+                }
+            }
+        })?;
+        serde_json::from_str(&data).map_err(|source| StorageError::Corrupt { path, source })
     }
 
     pub fn write_manifest(&self, world_dir: &Path, manifest: &WorldManifestV1) -> Result<()> {
         let path = Self::manifest_path(world_dir);
-        let json = serde_json::to_string_pretty(manifest).context("serialize manifest")?;
-        fs::write(&path, format!("{json}\n")).with_context(|| format!("write {path:?}"))?;
-        Ok(())
+        let json =
+            serde_json::to_string_pretty(manifest).map_err(|source| StorageError::Corrupt {
+                path: path.clone(),
+                source,
+            })?;
+        fs::write(&path, format!("{json}\n")).map_err(StorageError::io(format!("write {path:?}")))
     }
 
     pub fn set_token_info(
@@ -136,7 +208,7 @@ impl WorldStore {
     ) -> Result<WorldManifestV1> {
         let dir = self.world_dir(world_id);
         if !dir.exists() {
-            anyhow::bail!("world not found");
+            return Err(StorageError::NotFound(format!("world {world_id}")));
         }
 
         let mut manifest = self.read_manifest(&dir)?;
@@ -150,3 +222,157 @@ impl WorldStore {
         Ok(manifest)
     }
 }
+
+/// Directory listing for a local world, advertised at `endpoint`.
+pub fn directory_entry(m: &WorldManifestV1, endpoint: &str) -> WorldDirectoryEntry {
+    WorldDirectoryEntry {
+        world_id: m.world_id,
+        name: m.name.clone(),
+        endpoint: endpoint.to_string(),
+        port: m.ports.game_port,
+        token_mint: m.token.as_ref().map(|t| t.mint.clone()),
+        dbc_pool: m.token.as_ref().and_then(|t| t.dbc_pool.clone()),
+        world_pubkey: m.world_authority_pubkey.clone(),
+        last_seen: None,
+        trust: None,
+        region: m.region.clone(),
+        rtt_ms: None,
+        tags: m.tags.clone(),
+        icon_sha256: m.icon_sha256.clone(),
+        player_count: None,
+        max_players: None,
+        stake_lamports: None,
+        rules: m.rules.clone(),
+    }
+}
+
+/// `owp://` connect string for a local world reached at `endpoint`, with the token mint and
+/// authority key when the world has them.
+pub fn connect_string(m: &WorldManifestV1, endpoint: &str) -> String {
+    let host = if endpoint.contains(':') {
+        format!("[{endpoint}]")
+    } else {
+        endpoint.to_string()
+    };
+    let mut out = format!("owp://{host}:{}?world={}", m.ports.game_port, m.world_id);
+    if let Some(token) = &m.token {
+        out.push_str(&format!("&mint={}", token.mint));
+    }
+    if let Some(pubkey) = &m.world_authority_pubkey {
+        out.push_str(&format!("&pubkey={pubkey}"));
+    }
+    out
+}
+
+/// Tells apart concurrent temp files for the same target within this process.
+static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Write `bytes` to `path` via a temp file + rename so readers never observe a torn file.
+/// The temp file is unique to this write and synced before the rename, and the directory after
+/// it, so a crash leaves either the old file or the whole new one.
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> Result<()> {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy())
+        .unwrap_or_default();
+    let n = TMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let tmp = path.with_file_name(format!("{name}.{}.{n}.tmp", std::process::id()));
+    let written = fs::File::create(&tmp)
+        .and_then(|mut f| f.write_all(bytes).and_then(|()| f.sync_all()))
+        .map_err(StorageError::io(format!("write {tmp:?}")));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    if let Err(e) = fs::rename(&tmp, path) {
+        let _ = fs::remove_file(&tmp);
+        return Err(StorageError::io(format!("rename {tmp:?} -> {path:?}"))(e));
+    }
+    // Not supported on every platform (e.g. Windows); best effort.
+    if let Some(dir) = path.parent().and_then(|d| fs::File::open(d).ok()) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+/// Read the secret in `path`, or create it from `make()` if there is none yet; the flag says
+/// whether it was created. Creation is exclusive and owner-only (0600 on Unix), so processes
+/// starting together on a fresh data dir all end up with the one secret that won.
+pub fn read_or_create_secret(path: &Path, make: impl FnOnce() -> String) -> Result<(String, bool)> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    match options.open(path) {
+        Ok(mut file) => {
+            let secret = make();
+            file.write_all(secret.as_bytes())
+                .and_then(|()| file.sync_all())
+                .map_err(StorageError::io(format!("write {path:?}")))?;
+            Ok((secret, true))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            // Whoever created it may not have written it yet.
+            for _ in 0..SECRET_READ_ATTEMPTS {
+                let secret =
+                    fs::read_to_string(path).map_err(StorageError::io(format!("read {path:?}")))?;
+                if !secret.trim().is_empty() {
+                    return Ok((secret, false));
+                }
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+            Err(StorageError::io(format!("read {path:?}"))(
+                std::io::Error::other("the file stays empty"),
+            ))
+        }
+        Err(e) => Err(StorageError::io(format!("create {path:?}"))(e)),
+    }
+}
+
+const SECRET_READ_ATTEMPTS: usize = 100;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_and_damaged_worlds_are_told_apart() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let store = WorldStore::with_root(root.path().to_path_buf()).expect("store");
+        let set = |world_id| {
+            store.set_token_info(
+                world_id,
+                "devnet".to_string(),
+                "mint".to_string(),
+                None,
+                vec![],
+            )
+        };
+        assert!(matches!(
+            set(Uuid::new_v4()),
+            Err(StorageError::NotFound(_))
+        ));
+
+        let m = store.create_world("Test", 7777).expect("create");
+        fs::write(WorldStore::manifest_path(&store.world_dir(m.world_id)), "{").expect("damage");
+        assert!(matches!(set(m.world_id), Err(StorageError::Corrupt { .. })));
+    }
+
+    #[test]
+    fn secrets_are_created_once_and_kept_private() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let path = root.path().join("secret");
+        let (first, created) =
+            read_or_create_secret(&path, || "abc\n".to_string()).expect("create");
+        assert!(created);
+        let (again, created) =
+            read_or_create_secret(&path, || unreachable!("already there")).expect("read");
+        assert_eq!((again.as_str(), created), (first.as_str(), false));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).expect("metadata").permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+}
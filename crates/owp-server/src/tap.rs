@@ -0,0 +1,349 @@
+//! Live copies of protocol messages for debugging ("taps"). An admin opens one with a filter
+//! (world, player, message types), over `GET /admin/tap` (server-sent events) or into a JSON
+//! lines file under `logs/tap/`; game servers in the same process mirror every session message
+//! that matches, in both directions, until it closes. With no tap open, a message costs one
+//! atomic load.
+
+use anyhow::{Context, Result};
+use owp_protocol::Message;
+use serde::{Deserialize, Serialize};
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::task::{Context as TaskContext, Poll};
+use std::time::Duration;
+use time::OffsetDateTime;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Events waiting for a slow tap reader; past this, events are dropped (and `seq` skips).
+const TAP_BACKLOG: usize = 1024;
+
+/// How long a file tap runs unless told otherwise, so a forgotten one doesn't fill the disk.
+const DEFAULT_FILE_TTL: Duration = Duration::from_secs(600);
+
+/// Open taps, checked before anything else so sessions skip the registry when it's empty.
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+static TAPS: Mutex<Vec<Tap>> = Mutex::new(Vec::new());
+static FILE_TAP: Mutex<Option<FileTap>> = Mutex::new(None);
+
+/// Which messages a tap copies; every field narrows it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TapFilter {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub world_id: Option<Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub player_id: Option<Uuid>,
+    /// Comma-separated message `type`s, e.g. `chat_send,chat_broadcast`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub types: Option<String>,
+    /// Replace the text of chat messages with its length.
+    #[serde(default)]
+    pub redact_chat: bool,
+}
+
+impl TapFilter {
+    fn wants_session(&self, world_id: Uuid, player_id: Uuid) -> bool {
+        self.world_id.is_none_or(|w| w == world_id) && self.player_id.is_none_or(|p| p == player_id)
+    }
+
+    fn wants_type(&self, kind: &str) -> bool {
+        self.types
+            .as_deref()
+            .is_none_or(|types| types.split(',').any(|t| t.trim() == kind))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// Client to server.
+    In,
+    /// Server to client.
+    Out,
+}
+
+/// One copied message.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TapEvent {
+    /// Counts every message the tap matched, so a gap means events were dropped.
+    pub seq: u64,
+    #[serde(with = "time::serde::rfc3339")]
+    pub at: OffsetDateTime,
+    pub world_id: Uuid,
+    pub player_id: Uuid,
+    pub direction: Direction,
+    /// The message as JSON, whatever wire format the session uses.
+    pub message: serde_json::Value,
+}
+
+struct Tap {
+    id: u64,
+    filter: TapFilter,
+    seq: u64,
+    tx: mpsc::Sender<TapEvent>,
+}
+
+/// An open tap; it closes when dropped.
+pub struct Subscription {
+    id: u64,
+    rx: mpsc::Receiver<TapEvent>,
+}
+
+pub fn subscribe(filter: TapFilter) -> Subscription {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let (tx, rx) = mpsc::channel(TAP_BACKLOG);
+    let mut taps = TAPS.lock().unwrap_or_else(|e| e.into_inner());
+    taps.push(Tap {
+        id,
+        filter,
+        seq: 0,
+        tx,
+    });
+    ACTIVE.store(taps.len(), Ordering::Relaxed);
+    Subscription { id, rx }
+}
+
+impl Subscription {
+    pub async fn recv(&mut self) -> Option<TapEvent> {
+        self.rx.recv().await
+    }
+}
+
+impl tokio_stream::Stream for Subscription {
+    type Item = TapEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<TapEvent>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let mut taps = TAPS.lock().unwrap_or_else(|e| e.into_inner());
+        taps.retain(|t| t.id != self.id);
+        ACTIVE.store(taps.len(), Ordering::Relaxed);
+    }
+}
+
+fn redact(message: &mut serde_json::Value) {
+    if let Some(text) = message.get_mut("text") {
+        let chars = text.as_str().map_or(0, |t| t.chars().count());
+        *text = serde_json::Value::String(format!("[{chars} chars redacted]"));
+    }
+}
+
+/// Copy `msg` to every open tap that wants it.
+pub fn record(world_id: Uuid, player_id: Uuid, direction: Direction, msg: &Message) {
+    if ACTIVE.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    let mut taps = TAPS.lock().unwrap_or_else(|e| e.into_inner());
+    let mut json: Option<serde_json::Value> = None;
+    for tap in taps
+        .iter_mut()
+        .filter(|t| t.filter.wants_session(world_id, player_id))
+    {
+        let message = match &json {
+            Some(v) => v,
+            None => match serde_json::to_value(msg) {
+                Ok(v) => json.insert(v),
+                Err(e) => {
+                    warn!("tap: encoding {msg:?} failed: {e}");
+                    return;
+                }
+            },
+        };
+        let kind = message.get("type").and_then(|t| t.as_str()).unwrap_or("");
+        if !tap.filter.wants_type(kind) {
+            continue;
+        }
+        tap.seq += 1;
+        let mut message = message.clone();
+        if tap.filter.redact_chat && matches!(msg, Message::ChatSend(_) | Message::ChatBroadcast(_))
+        {
+            redact(&mut message);
+        }
+        // A full backlog means the reader is behind; `seq` shows what it missed.
+        let _ = tap.tx.try_send(TapEvent {
+            seq: tap.seq,
+            at: OffsetDateTime::now_utc(),
+            world_id,
+            player_id,
+            direction,
+            message,
+        });
+    }
+}
+
+/// The file tap, if one is running.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileTapStatus {
+    pub path: PathBuf,
+    pub filter: TapFilter,
+    #[serde(with = "time::serde::rfc3339")]
+    pub until: OffsetDateTime,
+}
+
+struct FileTap {
+    status: FileTapStatus,
+    task: JoinHandle<()>,
+}
+
+fn tap_dir(root: &Path) -> PathBuf {
+    root.join("logs").join("tap")
+}
+
+/// Start writing matching messages to a new file under `<root>/logs/tap/`, replacing any file
+/// tap already running. It stops after `ttl` (10 minutes if `None`).
+pub fn start_file(root: &Path, filter: TapFilter, ttl: Option<Duration>) -> Result<FileTapStatus> {
+    let dir = tap_dir(root);
+    std::fs::create_dir_all(&dir).with_context(|| format!("create {}", dir.display()))?;
+    let now = OffsetDateTime::now_utc();
+    let path = dir.join(format!("{}.jsonl", now.unix_timestamp_nanos()));
+    let mut file =
+        std::fs::File::create(&path).with_context(|| format!("create {}", path.display()))?;
+    let ttl = ttl.unwrap_or(DEFAULT_FILE_TTL);
+    let status = FileTapStatus {
+        path: path.clone(),
+        filter: filter.clone(),
+        until: now + ttl,
+    };
+    let mut sub = subscribe(filter);
+    let task = tokio::spawn(async move {
+        let deadline = tokio::time::Instant::now() + ttl;
+        while let Ok(Some(event)) = tokio::time::timeout_at(deadline, sub.recv()).await {
+            let mut line = serde_json::to_vec(&event).unwrap_or_default();
+            line.push(b'\n');
+            if let Err(e) = file.write_all(&line) {
+                warn!("tap: writing {} failed: {e}", path.display());
+                break;
+            }
+        }
+        info!("tap to {} stopped", path.display());
+    });
+    let previous = FILE_TAP
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .replace(FileTap {
+            status: status.clone(),
+            task,
+        });
+    if let Some(previous) = previous {
+        previous.task.abort();
+    }
+    info!("tapping messages to {}", status.path.display());
+    Ok(status)
+}
+
+pub fn file_status() -> Option<FileTapStatus> {
+    let mut current = FILE_TAP.lock().unwrap_or_else(|e| e.into_inner());
+    if current.as_ref().is_some_and(|t| t.task.is_finished()) {
+        *current = None;
+    }
+    current.as_ref().map(|t| t.status.clone())
+}
+
+/// Stop the file tap; returns what it was.
+pub fn stop_file() -> Option<FileTapStatus> {
+    let stopped = FILE_TAP.lock().unwrap_or_else(|e| e.into_inner()).take()?;
+    stopped.task.abort();
+    Some(stopped.status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use owp_protocol::{ChatBroadcast, ChatChannel, PlayerPosition};
+
+    fn chat(text: &str) -> Message {
+        Message::ChatBroadcast(ChatBroadcast {
+            channel: ChatChannel::World,
+            from: None,
+            text: text.to_string(),
+            sent_at: OffsetDateTime::now_utc(),
+        })
+    }
+
+    #[tokio::test]
+    async fn taps_copy_matching_messages_until_dropped() {
+        // Other tests may tap too; a world of our own keeps their messages apart.
+        let world_id = Uuid::new_v4();
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut everything = subscribe(TapFilter {
+            world_id: Some(world_id),
+            ..TapFilter::default()
+        });
+        let mut alice_chat = subscribe(TapFilter {
+            world_id: Some(world_id),
+            player_id: Some(alice),
+            types: Some("chat_send, chat_broadcast".to_string()),
+            redact_chat: true,
+        });
+
+        let position = Message::PlayerPosition(PlayerPosition {
+            position: [1.0, 2.0, 3.0],
+        });
+        record(world_id, alice, Direction::In, &position);
+        record(world_id, bob, Direction::Out, &chat("for bob"));
+        record(world_id, alice, Direction::Out, &chat("for alice"));
+        record(Uuid::new_v4(), alice, Direction::Out, &chat("elsewhere"));
+
+        let seen: Vec<_> = (0..3)
+            .map(|_| everything.rx.try_recv().expect("event"))
+            .map(|e| (e.seq, e.player_id, e.direction))
+            .collect();
+        assert_eq!(
+            seen,
+            [
+                (1, alice, Direction::In),
+                (2, bob, Direction::Out),
+                (3, alice, Direction::Out)
+            ]
+        );
+        assert!(everything.rx.try_recv().is_err());
+
+        let only = alice_chat.rx.try_recv().expect("alice's chat");
+        assert_eq!(only.seq, 1);
+        assert_eq!(only.message["type"], "chat_broadcast");
+        assert_eq!(only.message["text"], "[9 chars redacted]");
+        assert!(alice_chat.rx.try_recv().is_err());
+
+        let id = alice_chat.id;
+        drop(alice_chat);
+        let taps = TAPS.lock().unwrap();
+        assert!(taps.iter().all(|t| t.id != id));
+    }
+
+    #[tokio::test]
+    async fn a_file_tap_writes_json_lines_until_stopped() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let world_id = Uuid::new_v4();
+        let filter = TapFilter {
+            world_id: Some(world_id),
+            ..TapFilter::default()
+        };
+        let status = start_file(root.path(), filter, None).expect("start");
+        assert!(status.path.starts_with(tap_dir(root.path())));
+        record(world_id, Uuid::nil(), Direction::Out, &chat("logged"));
+
+        // The writer runs on its own task; wait for the line to land.
+        let mut written = String::new();
+        for _ in 0..100 {
+            written = std::fs::read_to_string(&status.path).expect("read");
+            if !written.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let event: TapEvent = serde_json::from_str(written.trim()).expect("json line");
+        assert_eq!(event.message["text"], "logged");
+        assert_eq!(stop_file().map(|s| s.path), Some(status.path));
+        assert!(file_status().is_none());
+    }
+}
@@ -1,18 +1,98 @@
 use anyhow::{Context, Result};
-use owp_protocol::{wire, Message, Welcome, OWP_PROTOCOL_VERSION};
+use ed25519_dalek::SigningKey;
+use owp_discovery::attestation;
+use owp_protocol::avatar::validate_avatar;
+use owp_protocol::capability::{self, Capability};
+use owp_protocol::noise;
+use owp_protocol::session::{Event, ProtocolError, ProtocolStateMachine};
+use owp_protocol::{
+    wire::{self, WireError},
+    AssetToken, AvatarAnnounce, AvatarResult, AvatarSpecV1, ChunkCoord, Compression, Disconnect,
+    DisconnectReason, ErrorCode, Handoff, Hello, Message, PartyInfo, PartyInvited, PartyResult,
+    PathResult, PeerList, Ping, Welcome, WireFormat, WorldMoved, OWP_PROTOCOL_VERSION,
+};
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use time::OffsetDateTime;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
-use tracing::{info, warn};
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
 use uuid::Uuid;
 
+use crate::access::{AccessControl, ConnectionSlot};
+use crate::asset_token::{self, AssetTokenKey};
+use crate::assets::AssetIndex;
+use crate::authority;
+use crate::avatar;
+use crate::bandwidth::{self, CapState, Channel, GameMeter};
+use crate::chat::{self, ChatLog};
+use crate::chunks;
+use crate::cluster::{self, ClusterConfig, HandoffTicket};
+use crate::config::{LiveConfig, RateLimitConfig};
+use crate::crash;
+use crate::emotes;
+use crate::entry_fee;
+use crate::friends::{self, FriendsGuard};
+use crate::fsck;
+use crate::health::{HealthRegistry, ServiceState};
+use crate::migration::{self, MigrationPhase};
+use crate::minimap;
+use crate::net_quality::NetQuality;
+use crate::party::{Parties, PartyGuard};
+use crate::pathfind::NavGrid;
+use crate::peers::{PeerGuard, PeerRegistry};
+use crate::prefabs;
+use crate::presence::{self, Presence, PresenceGuard, Roster};
+use crate::privacy;
+use crate::quic;
+use crate::quota::{ConnectionPermit, Quotas, WorldBudget};
+use crate::rules;
+use crate::sim;
 use crate::storage::WorldStore;
+use crate::tap::{self, Direction};
+use crate::tls::{GamePortTls, Opened};
+use crate::wal;
+use crate::wallet_auth::{AuthStats, NonceCache};
+use crate::wardrobe;
+use crate::world_snapshot;
 
-pub async fn serve(store: WorldStore, world_id: Uuid, listen: Option<String>) -> Result<()> {
-    let world_dir = store.world_dir(world_id);
-    if !world_dir.exists() {
-        anyhow::bail!("world not found: {world_id}");
-    }
-    let manifest = store.read_manifest(&world_dir)?;
+/// How long after a drop a client can resume its session.
+const RESUME_WINDOW: Duration = Duration::from_secs(60);
+
+/// How long `disconnect_all` waits for goodbyes to go out.
+const GOODBYE_GRACE: Duration = Duration::from_millis(250);
+
+/// How often the server checks whether the world was migrated away.
+const MIGRATION_POLL: Duration = Duration::from_secs(2);
+
+/// Key for this world's game server in the health registry.
+pub fn health_key(world_id: Uuid) -> String {
+    format!("world:{world_id}")
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn serve(
+    store: WorldStore,
+    world_id: Uuid,
+    listen: Option<String>,
+    health: HealthRegistry,
+    config: LiveConfig,
+    parties: Parties,
+    roster: Roster,
+    quotas: Quotas,
+) -> Result<()> {
+    let health_key = health_key(world_id);
+    health.set(&health_key, ServiceState::Starting, None);
+    let _active = crash::world_started(world_id);
+    let world = GameWorld::open(store, world_id, config.clone(), parties, &roster, &quotas).await?;
+    let world_dir = world.store.world_dir(world_id);
+    let manifest = world.store.read_manifest(&world_dir)?;
 
     let listen = match listen {
         Some(v) => v,
@@ -21,63 +101,1277 @@ pub async fn serve(store: WorldStore, world_id: Uuid, listen: Option<String>) ->
     let addr: SocketAddr = listen.parse().context("invalid listen addr")?;
     let listener = TcpListener::bind(addr).await.context("bind")?;
     info!("OWP game server listening on tcp://{addr} (world_id={world_id})");
+    let tls = match &manifest.tls {
+        Some(settings) => {
+            let tls = GamePortTls::load(&world_dir, settings, world.shared.authority.as_ref())
+                .context("game port tls")?;
+            info!(
+                "TLS on tcp://{addr}{}",
+                if settings.required { " (required)" } else { "" }
+            );
+            Some(Arc::new(tls))
+        }
+        None => None,
+    };
+    let quic = match manifest.ports.quic_port {
+        Some(port) => {
+            let addr = SocketAddr::new(addr.ip(), port);
+            let endpoint = quic::endpoint(addr, world.shared.authority.as_ref())?;
+            info!("OWP game server listening on quic://{addr} (world_id={world_id})");
+            Some(endpoint)
+        }
+        None => None,
+    };
+    health.set(
+        &health_key,
+        ServiceState::Running,
+        Some(format!("tcp://{addr}")),
+    );
+
+    if let Some(cfg) = cluster_for(&config, world_id) {
+        let sessions = world.shared.sessions.clone();
+        cluster::spawn_internal(&cfg, move |ticket| {
+            sessions.adopt(&ticket.session_token, ticket.player_id)
+        })?;
+        info!(
+            "world {world_id} is clustered; this node is {:?}",
+            cfg.node_id
+        );
+    }
+
+    tokio::spawn(watch_migration(world_dir, world.shared.presence.clone()));
 
     loop {
-        let (stream, peer) = listener.accept().await.context("accept")?;
-        let store = store.clone();
+        let (accepted, peer) = tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, peer) = accepted.context("accept")?;
+                (Accepted::Tcp(stream), peer)
+            }
+            Some(incoming) = next_quic(quic.as_ref()) => {
+                let peer = incoming.remote_address();
+                (Accepted::Quic(Box::new(incoming)), peer)
+            }
+        };
+        let Some(admission) = world.admit(peer) else {
+            continue;
+        };
+        let world = world.clone();
+        let tls = tls.clone();
         tokio::spawn(async move {
-            if let Err(e) = handle_connection(store, world_id, stream, peer).await {
+            let handled = match accepted {
+                Accepted::Tcp(stream) => {
+                    world
+                        .tcp_session(admission, stream, peer, tls.as_deref())
+                        .await
+                }
+                Accepted::Quic(incoming) => world.quic_session(admission, *incoming, peer).await,
+            };
+            if let Err(e) = handled {
                 warn!("connection error from {peer}: {e:#}");
             }
         });
     }
 }
 
-async fn handle_connection(
+/// One world's game state, taking connections from any transport: `serve` hands it TCP and QUIC
+/// connections, tests hand it in-memory pipes. Timers and the instants they're compared with
+/// come from tokio's clock, so a paused runtime makes whole sessions deterministic.
+#[derive(Clone)]
+pub struct GameWorld {
     store: WorldStore,
     world_id: Uuid,
-    mut stream: TcpStream,
-    peer: SocketAddr,
-) -> Result<()> {
-    let msg = wire::read_message(&mut stream)
+    config: LiveConfig,
+    shared: Shared,
+    access: AccessControl,
+    budget: WorldBudget,
+}
+
+/// A connection past the accept filters and quotas, holding its slots while it lives.
+pub struct Admission {
+    _slot: ConnectionSlot,
+    _permit: ConnectionPermit,
+}
+
+impl GameWorld {
+    /// Recover and check the world, then start its simulation.
+    pub async fn open(
+        store: WorldStore,
+        world_id: Uuid,
+        config: LiveConfig,
+        parties: Parties,
+        roster: &Roster,
+        quotas: &Quotas,
+    ) -> Result<Self> {
+        let world_dir = store.world_dir(world_id);
+        if !world_dir.exists() {
+            anyhow::bail!("world not found: {world_id}");
+        }
+        let manifest = store.read_manifest(&world_dir)?;
+        wal::recover(&world_dir).context("wal recovery")?;
+        fsck::warn_issues(&fsck::FsckReport {
+            worlds_checked: 1,
+            issues: fsck::check_world(&store, &world_dir, Some(world_id), false),
+        });
+        let authority =
+            authority::load_or_create(&store, world_id).context("world authority key")?;
+        let noise_key = authority
+            .as_ref()
+            .map(|key| noise::server_static_secret(&key.to_bytes()));
+
+        let online = Arc::new(AtomicUsize::new(0));
+        let shared = Shared {
+            online: online.clone(),
+            sessions: Sessions::default(),
+            quality: NetQuality::load(&world_dir),
+            nonces: NonceCache::default(),
+            auth: AuthStats::load(&world_dir),
+            assets: AssetIndex::default(),
+            presence: roster.world(world_id),
+            parties,
+            nav: NavGrid::new(world_dir.clone()),
+            bandwidth: GameMeter::load(&world_dir),
+            peers: PeerRegistry::default(),
+            noise_key,
+            authority,
+            asset_key: AssetTokenKey::load_or_create(&store).context("asset token key")?,
+            chat_log: ChatLog::new(&world_dir),
+        };
+        shared.quality.spawn_flush(world_dir.clone());
+        shared.auth.spawn_flush(world_dir.clone());
+        shared.bandwidth.spawn_flush(world_dir.clone());
+        let budget = quotas.world(world_id, &config);
+        let sim_state = sim::load_for_startup(&world_dir, &manifest.simulation)?;
+        tokio::spawn(sim::run_tick_loop(
+            world_dir.clone(),
+            manifest.simulation.clone(),
+            Arc::new(Mutex::new(sim_state)),
+            online,
+            budget.clone(),
+        ));
+        Ok(Self {
+            store,
+            world_id,
+            config,
+            shared,
+            access: AccessControl::new(world_dir),
+            budget,
+        })
+    }
+
+    /// Apply the accept filters and the world's connection quota to `peer`.
+    pub fn admit(&self, peer: SocketAddr) -> Option<Admission> {
+        let slot = match self.access.admit(peer.ip(), &self.config.current()) {
+            Ok(slot) => slot,
+            Err(refusal) => {
+                debug!("refused connection from {peer}: {}", refusal.label());
+                return None;
+            }
+        };
+        match self.budget.connect() {
+            Ok(permit) => Some(Admission {
+                _slot: slot,
+                _permit: permit,
+            }),
+            Err(e) => {
+                debug!("refused connection from {peer}: {e}");
+                None
+            }
+        }
+    }
+
+    /// Run one player's session over `stream` until it ends. `datagrams` is the QUIC connection
+    /// `stream` belongs to, if it came over QUIC.
+    pub async fn session<S>(
+        &self,
+        admission: Admission,
+        stream: S,
+        peer: SocketAddr,
+        datagrams: Option<quinn::Connection>,
+    ) -> Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let _admission = admission;
+        handle_connection(
+            self.store.clone(),
+            self.world_id,
+            stream,
+            peer,
+            self.config.clone(),
+            self.shared.clone(),
+            datagrams,
+        )
         .await
-        .context("read hello")?;
-    let (request_id, requested_world) = match msg {
-        Message::Hello(h) => (h.request_id, h.world_id),
-        other => {
-            warn!("unexpected first message from {peer}: {other:?}");
-            return Ok(());
+    }
+
+    /// A TCP connection, over TLS if the world offers it and the client opened with it.
+    async fn tcp_session(
+        &self,
+        admission: Admission,
+        stream: TcpStream,
+        peer: SocketAddr,
+        tls: Option<&GamePortTls>,
+    ) -> Result<()> {
+        let Some(tls) = tls else {
+            return self.session(admission, stream, peer, None).await;
+        };
+        match tls.accept(stream).await? {
+            Opened::Tls(stream) => self.session(admission, *stream, peer, None).await,
+            Opened::Plain(stream) => self.session(admission, stream, peer, None).await,
         }
-    };
+    }
 
-    if let Some(w) = requested_world {
-        if w != world_id {
-            warn!("world_id mismatch from {peer}: requested={w} served={world_id}");
-            let welcome = Message::Welcome(Welcome {
-                protocol_version: OWP_PROTOCOL_VERSION.to_string(),
-                request_id,
-                world_id,
-                token_mint: None,
-                motd: Some("World id mismatch".to_string()),
-                capabilities: vec![],
-            });
-            wire::write_message(&mut stream, &welcome).await?;
+    /// Finish the QUIC handshake and run the session on the client's first bidirectional stream.
+    async fn quic_session(
+        &self,
+        admission: Admission,
+        incoming: quinn::Incoming,
+        peer: SocketAddr,
+    ) -> Result<()> {
+        let conn = incoming.await.context("quic handshake")?;
+        let (send, recv) = conn.accept_bi().await.context("quic stream")?;
+        let stream = tokio::io::join(recv, send);
+        self.session(admission, stream, peer, Some(conn)).await
+    }
+}
+
+enum Accepted {
+    Tcp(TcpStream),
+    Quic(Box<quinn::Incoming>),
+}
+
+/// The next QUIC connection attempt; never, for a world without `quic_port`.
+async fn next_quic(endpoint: Option<&quinn::Endpoint>) -> Option<quinn::Incoming> {
+    match endpoint {
+        Some(endpoint) => endpoint.accept().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn handle_connection<S>(
+    store: WorldStore,
+    world_id: Uuid,
+    mut stream: S,
+    peer: SocketAddr,
+    config: LiveConfig,
+    shared: Shared,
+    datagrams: Option<quinn::Connection>,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let mut proto = ProtocolStateMachine::server(world_id);
+    if let Some(key) = shared.noise_key {
+        proto = proto.with_noise_key(key);
+    }
+    let mut hello = match proto.next_handshake_event(&mut stream).await {
+        Ok(Event::Hello(h)) => h,
+        Ok(Event::WrongWorld { requested }) => {
+            warn!("world_id mismatch from {peer}: requested={requested} served={world_id}");
+            proto.flush(&mut stream).await?;
             return Ok(());
         }
-    }
+        Ok(Event::VersionMismatch { requested }) => {
+            warn!("refused {peer}: protocol version {requested} is incompatible");
+            proto.flush(&mut stream).await?;
+            return Ok(());
+        }
+        Err(ProtocolError::Unexpected { got, .. }) => {
+            warn!("unexpected first message from {peer}: {got:?}");
+            return Ok(());
+        }
+        Ok(other) => anyhow::bail!("unexpected event before hello: {other:?}"),
+        Err(e) => return Err(e).context("read hello"),
+    };
 
     let world_dir = store.world_dir(world_id);
     let manifest = store.read_manifest(&world_dir)?;
+    if bandwidth::cap_state(&manifest.bandwidth, shared.bandwidth.total()) == CapState::Capped {
+        warn!("refused {peer}: the world's monthly bandwidth cap is used up");
+        let message = "the world's monthly bandwidth cap is used up".to_string();
+        proto.reject(ErrorCode::Unavailable, message)?;
+        proto.flush(&mut stream).await?;
+        return Ok(());
+    }
     let token_mint = manifest.token.as_ref().map(|t| t.mint.clone());
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    let wallet_verified = hello.wallet_proof.as_ref().map(|proof| {
+        let outcome = shared
+            .nonces
+            .check(world_id, hello.wallet_pubkey.as_deref(), proof, now);
+        shared.auth.record(outcome);
+        if let Err(f) = outcome {
+            warn!("wallet proof from {peer} refused: {}", f.label());
+        }
+        outcome.is_ok()
+    });
+    if wallet_verified == Some(false) {
+        // Don't let a replayed or forged proof pass the wallet off as the player's.
+        hello.wallet_pubkey = None;
+    }
+    hello.profile_id = profile_of(&hello);
+    let current = config.current();
+    let resuming = hello
+        .resume_token
+        .as_deref()
+        .is_some_and(|t| shared.sessions.resumable(t));
+    if let Some(world_rules) = manifest.rules.as_ref().filter(|_| !resuming) {
+        let admitted = rules::admit(
+            &mut proto,
+            &mut stream,
+            &world_dir,
+            world_rules,
+            &hello,
+            peer,
+        )
+        .await?;
+        if !admitted {
+            return Ok(());
+        }
+    }
+    if let Some(fee) = manifest.entry_fee.as_ref().filter(|_| !resuming) {
+        let rpc_url = current
+            .solana_rpc_url
+            .clone()
+            .or_else(|| std::env::var("OWP_SOLANA_RPC_URL").ok());
+        let admitted = entry_fee::admit(
+            &mut proto,
+            &mut stream,
+            &world_dir,
+            fee,
+            &hello,
+            &manifest.name,
+            rpc_url.as_deref(),
+            peer,
+        )
+        .await?;
+        if !admitted {
+            return Ok(());
+        }
+    }
+    let attestation_cap = shared.authority.is_some() && current.public_endpoint.is_some();
+    let nonce = hello
+        .attestation_nonce
+        .as_deref()
+        .filter(|n| n.len() <= attestation::MAX_NONCE_LEN);
+    let attestation = match (&shared.authority, &current.public_endpoint, nonce) {
+        (Some(key), Some(endpoint), Some(nonce)) => {
+            Some(attestation::sign(key, world_id, endpoint, now, nonce))
+        }
+        _ => None,
+    };
+    let mut prefetch = shared
+        .assets
+        .prefetch_list(&world_dir, &manifest.assets)
+        .unwrap_or_else(|e| {
+            warn!("building prefetch list failed: {e:#}");
+            vec![]
+        });
+    if let Some(bundle) = &manifest.prefab_bundle {
+        prefetch.push(prefabs::prefetch_ref(bundle));
+    }
+
+    let peer_assist = manifest.assets.peer_assist;
+    let private_assets = manifest.assets.private;
 
-    let welcome = Message::Welcome(Welcome {
+    let (session_token, player_id, resumed) = shared.sessions.begin(hello.resume_token.as_deref());
+    if resumed {
+        info!("{peer} resumed its session");
+    }
+    let handoff_token = session_token.clone();
+    let _session = SessionGuard {
+        sessions: shared.sessions.clone(),
+        token: session_token.clone(),
+    };
+    let _online = OnlineGuard::enter(shared.online.clone());
+    // Replies and messages relayed from other players share one outbox, drained by a writer
+    // task (started after `Welcome`) so a slow reader never blocks other connections.
+    let (outbox, mut outgoing) = mpsc::channel::<Message>(presence::OUTBOX_CAPACITY);
+    let party = shared.parties.connect(
+        player_id,
+        world_id,
+        outbox.clone(),
+        hello.party_token.as_deref(),
+    );
+    let _party = PartyGuard {
+        parties: shared.parties.clone(),
+        player_id,
+    };
+    let supports: Vec<Capability> = [
+        Capability::Handshake,
+        Capability::ChunkDelta,
+        Capability::SessionResume,
+        Capability::NetReport,
+        Capability::AvatarSubmit,
+        Capability::AvatarWardrobe,
+        Capability::AvatarAnnounce,
+        Capability::Emote,
+        Capability::Chat,
+        Capability::Party,
+        Capability::FriendPresence,
+        Capability::PathQuery,
+        Capability::FrameProtection,
+        Capability::WorldSnapshot,
+        Capability::Batch,
+    ]
+    .into_iter()
+    .chain(WireFormat::ALL.map(Capability::WireFormat))
+    .chain(Compression::ALL.iter().map(|&c| Capability::Compression(c)))
+    .chain(shared.noise_key.map(|_| Capability::Noise))
+    .chain(datagrams.as_ref().map(|_| Capability::Datagrams))
+    .chain(attestation_cap.then_some(Capability::Attestation))
+    .chain(peer_assist.then_some(Capability::PeerAssist))
+    .chain(private_assets.then_some(Capability::AssetToken))
+    .chain(cluster_for(&config, world_id).map(|_| Capability::ClusterHandoff))
+    .collect();
+    let granted = capability::negotiate(&hello.capabilities, &supports);
+    proto.accept(Welcome {
         protocol_version: OWP_PROTOCOL_VERSION.to_string(),
-        request_id,
+        request_id: hello.request_id,
         world_id,
         token_mint,
-        motd: Some("Welcome to OWP (handshake-only server)".to_string()),
-        capabilities: vec!["handshake".to_string()],
+        motd: Some(current.motd),
+        capabilities: granted.clone(),
+        session_token: Some(session_token),
+        resumed,
+        prefetch,
+        player_id: Some(player_id),
+        party,
+        player_count: Some(shared.online.load(Ordering::Relaxed) as u32),
+        frame_protection: None,
+        wire_format: None,
+        compression: None,
+        attestation,
+        wallet_verified,
+    })?;
+    proto.flush(&mut stream).await?;
+    if let Some(moved) = moved_to(&world_dir) {
+        info!("{peer} connected to a world that moved; redirecting it");
+        proto.send(&moved)?;
+        proto.flush(&mut stream).await?;
+        return Ok(());
+    }
+
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    let format = proto.wire_format();
+    let mut encoder = proto.take_encoder()?;
+    let mut incoming_datagrams = datagrams
+        .clone()
+        .map(|conn| quic::spawn_datagram_reader(conn, format));
+    let meter = shared.bandwidth.clone();
+    let limits = manifest.bandwidth.clone();
+    // Ends when a write fails or after a goodbye; the connection ends with it.
+    let pushes = granted.clone();
+    let batches = granted.contains(&Capability::Batch);
+    let mut written_out = tokio::spawn(async move {
+        // Read while filling a batch, but not one that fits in it.
+        let mut held: Option<Message> = None;
+        loop {
+            let msg = match held.take() {
+                Some(msg) => msg,
+                None => match outgoing.recv().await {
+                    Some(msg) => msg,
+                    None => break,
+                },
+            };
+            // Features the client didn't ask for stay quiet.
+            if Capability::pushed_with(&msg).is_some_and(|c| !pushes.contains(&c)) {
+                continue;
+            }
+            tap::record(world_id, player_id, Direction::Out, &msg);
+            let datagram = datagrams
+                .as_ref()
+                .filter(|_| msg.is_unreliable())
+                .and_then(|conn| quic::send_datagram(conn, &msg, format));
+            if let Some(len) = datagram {
+                meter.record(Channel::of(&msg), len as u64);
+                continue;
+            }
+            // Relayed messages already queued behind this one go out with it in one frame.
+            let mut frame = vec![msg];
+            let relayed = |m: &Message| {
+                Channel::of(m) == Channel::Relay && !(datagrams.is_some() && m.is_unreliable())
+            };
+            if batches && relayed(&frame[0]) {
+                while frame.len() < wire::MAX_BATCH_LEN {
+                    let Ok(next) = outgoing.try_recv() else {
+                        break;
+                    };
+                    if Capability::pushed_with(&next).is_some_and(|c| !pushes.contains(&c)) {
+                        continue;
+                    }
+                    if !relayed(&next) {
+                        held = Some(next);
+                        break;
+                    }
+                    tap::record(world_id, player_id, Direction::Out, &next);
+                    frame.push(next);
+                }
+            }
+            let msg = &frame[0];
+            let encoded = match frame.len() {
+                1 => encoder.encode(msg),
+                _ => encoder.encode_batch(&frame),
+            };
+            let written = match encoded {
+                Ok(bytes) => writer.write_all(&bytes).await.map(|()| bytes.len()),
+                Err(e) => Err(std::io::Error::other(e)),
+            };
+            let len = match written {
+                Ok(len) => len as u64,
+                Err(e) => {
+                    warn!("write to {peer} failed: {e}");
+                    break;
+                }
+            };
+            meter.record(Channel::of(msg), len);
+            // Past the soft cap, pace this connection instead of cutting it off.
+            if bandwidth::cap_state(&limits, meter.total()) != CapState::Ok {
+                let rate = limits.throttle_bytes_per_sec.max(1) as f64;
+                tokio::time::sleep(Duration::from_secs_f64(len as f64 / rate)).await;
+            }
+            // Dropping the write half closes the connection; the client goes to the new host.
+            if matches!(msg, Message::WorldMoved(_) | Message::Disconnect(_)) {
+                break;
+            }
+        }
+    });
+    if granted.contains(&Capability::WorldSnapshot) {
+        match world_snapshot::build(&world_dir, &manifest, shared.presence.players(player_id)) {
+            Ok(snapshot) => outbox.send(Message::WorldSnapshot(snapshot)).await?,
+            Err(e) => warn!("building the world snapshot for {peer} failed: {e:#}"),
+        }
+    }
+    shared.presence.join(player_id, outbox.clone());
+    let _presence = PresenceGuard {
+        presence: shared.presence.clone(),
+        player_id,
+    };
+    let (ids, friend_ids) = identity(&store, &hello);
+    let listed = hello
+        .profile_id
+        .as_deref()
+        .filter(|p| friends::check_profile_id(p).is_ok())
+        .is_none_or(|p| privacy::load_or_default(&store, p).appear_in_rosters);
+    shared.presence.identify(player_id, ids, friend_ids, listed);
+    let share_avatar = hello
+        .profile_id
+        .as_deref()
+        .filter(|p| friends::check_profile_id(p).is_ok())
+        .is_none_or(|p| privacy::load_or_default(&store, p).share_avatar);
+    friends::announce(&shared.presence, world_id, player_id, true);
+    let _friends = FriendsGuard {
+        presence: shared.presence.clone(),
+        world_id,
+        player_id,
+    };
+    let _peers = PeerGuard {
+        peers: shared.peers.clone(),
+        player_id,
+    };
+    // Pick up where a resumed session left off.
+    let parked = shared.sessions.unpark(&handoff_token).unwrap_or_default();
+    if let Some(position) = parked.position {
+        shared.presence.set_position(player_id, position);
+    }
+    if let Some(avatar) = parked.avatar.filter(|_| share_avatar) {
+        shared.presence.set_avatar(player_id, avatar);
+    }
+    let mut parking = ParkGuard {
+        sessions: shared.sessions.clone(),
+        presence: shared.presence.clone(),
+        token: handoff_token.clone(),
+        player_id,
+        outfits: parked.outfits,
+    };
+    let mut budget = MessageBudget::new(&config.current().rate_limits);
+    let profile = hello
+        .profile_id
+        .clone()
+        .filter(|p| friends::check_profile_id(p).is_ok());
+    // Chunks already recorded as explored this session, to skip rewriting the file.
+    let mut explored: HashSet<ChunkCoord> = HashSet::new();
+    // Only a move into another chunk can cross a cluster region border.
+    let mut last_chunk: Option<ChunkCoord> = None;
+    let keepalive = config.current().keepalive;
+    let mut pings = (keepalive.interval_secs > 0).then(|| {
+        let every = Duration::from_secs(keepalive.interval_secs);
+        let mut interval = tokio::time::interval_at(Instant::now() + every, every);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        interval
     });
-    wire::write_message(&mut stream, &welcome).await?;
-    Ok(())
+    // Pings sent since the client last answered one.
+    let mut unanswered = 0u32;
+    // The first tick fires at once, so the token goes out right after `Welcome`.
+    let mut asset_tokens = private_assets.then(|| {
+        let mut interval = tokio::time::interval(asset_token::TOKEN_TTL / 2);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        interval
+    });
+
+    loop {
+        let read = tokio::select! {
+            read = proto.next_event(&mut reader) => read,
+            datagram = next_datagram(&mut incoming_datagrams) => match datagram {
+                Some(msg) => Ok(Event::Message(msg)),
+                None => {
+                    incoming_datagrams = None;
+                    continue;
+                }
+            },
+            _ = &mut written_out => return Ok(()),
+            () = next_tick(&mut asset_tokens) => {
+                let expires_at = OffsetDateTime::now_utc() + asset_token::TOKEN_TTL;
+                let expires_at = expires_at.unix_timestamp();
+                let token = shared.asset_key.issue(world_id, player_id, expires_at);
+                outbox
+                    .send(Message::AssetToken(AssetToken { token, expires_at }))
+                    .await?;
+                continue;
+            }
+            () = next_tick(&mut pings) => {
+                if unanswered >= keepalive.max_missed.max(1) {
+                    info!("{peer} missed {unanswered} pings in a row; dropping it");
+                    let bye = disconnect(DisconnectReason::IdleTimeout, "missed too many pings");
+                    outbox.send(bye).await?;
+                    return Ok(());
+                }
+                unanswered += 1;
+                outbox.send(Message::Ping(Ping::new(now_ms()))).await?;
+                continue;
+            }
+        };
+        let msg = match read {
+            Ok(Event::Message(m)) => m,
+            Ok(other) => anyhow::bail!("unexpected event on an open session: {other:?}"),
+            Err(ProtocolError::Wire(WireError::Io(e)))
+                if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+            {
+                return Ok(());
+            }
+            Err(e) => return Err(e).context("read message"),
+        };
+        tap::record(world_id, player_id, Direction::In, &msg);
+        if !budget.allow(&config.current().rate_limits) {
+            warn!("rate limit exceeded by {peer}; dropping message");
+            continue;
+        }
+        match msg {
+            Message::ChunkDeltaRequest(req) => {
+                let file = chunks::load_chunk(&world_dir, req.chunk)?;
+                let reply =
+                    Message::ChunkDelta(file.delta_since(req.request_id, req.since_version));
+                outbox.send(reply).await?;
+            }
+            Message::NetReport(report) => shared.quality.record(&report),
+            Message::Ping(ping) => outbox.send(Message::Pong(ping.pong(now_ms()))).await?,
+            Message::Pong(_) => unanswered = 0,
+            Message::Disconnect(bye) => {
+                info!("{peer} disconnected ({:?})", bye.reason);
+                // A player that said goodbye won't resume.
+                shared.sessions.forget(&handoff_token);
+                return Ok(());
+            }
+            Message::PathQuery(query) => {
+                let result = shared.nav.find_path(query.from, query.to);
+                outbox
+                    .send(Message::PathResult(PathResult {
+                        request_id: query.request_id,
+                        error: result.as_ref().err().map(|e| format!("{e:#}")),
+                        path: result.unwrap_or_default(),
+                    }))
+                    .await?;
+            }
+            Message::PeerAnnounce(announce) => {
+                if peer_assist {
+                    let addr = SocketAddr::new(peer.ip(), announce.port);
+                    shared.peers.announce(player_id, addr, announce.sha256);
+                }
+            }
+            Message::PeerQuery(query) => {
+                let peers = if peer_assist {
+                    shared.peers.peers_for(&query.sha256, player_id)
+                } else {
+                    vec![]
+                };
+                outbox
+                    .send(Message::PeerList(PeerList {
+                        request_id: query.request_id,
+                        sha256: query.sha256,
+                        peers,
+                    }))
+                    .await?;
+            }
+            Message::AvatarSubmit(submit) => {
+                let result = submit
+                    .wardrobe_name
+                    .as_deref()
+                    .map_or(Ok(()), wardrobe::check_name)
+                    .and_then(|()| avatar::prepare_submission(&submit.avatar, &submit.meshes))
+                    .and_then(|s| s.store_meshes(&store).map(|()| s.avatar));
+                if let (Ok(avatar), Some(name)) = (&result, submit.wardrobe_name) {
+                    if let Some(profile_id) = &profile {
+                        if let Err(e) =
+                            wardrobe::put_outfit(&store, profile_id, &name, Some(avatar))
+                        {
+                            warn!("saving outfit {name:?} for {profile_id} failed: {e:#}");
+                        }
+                    } else {
+                        let outfits = &mut parking.outfits;
+                        if outfits.len() < wardrobe::MAX_OUTFITS || outfits.contains_key(&name) {
+                            outfits.insert(name, avatar.clone());
+                        }
+                    }
+                }
+                if let (Ok(avatar), true) = (&result, share_avatar) {
+                    announce_avatar(&shared.presence, player_id, avatar.clone());
+                }
+                outbox
+                    .send(avatar_result(peer, submit.request_id, result))
+                    .await?;
+            }
+            Message::AvatarSwitch(switch) => {
+                let found = match &profile {
+                    Some(profile_id) => wardrobe::switch(&store, profile_id, &switch.name),
+                    None => Ok(parking.outfits.get(&switch.name).cloned()),
+                };
+                let result = found.and_then(|avatar| {
+                    avatar.with_context(|| format!("no avatar submitted as {:?}", switch.name))
+                });
+                if let (Ok(avatar), true) = (&result, share_avatar) {
+                    announce_avatar(&shared.presence, player_id, avatar.clone());
+                }
+                outbox
+                    .send(avatar_result(peer, switch.request_id, result))
+                    .await?;
+            }
+            Message::AvatarAnnounce(announce) => match validate_avatar(&announce.avatar) {
+                Ok(avatar) if share_avatar => {
+                    announce_avatar(&shared.presence, player_id, avatar);
+                }
+                Ok(_) => debug!("{peer} doesn't share its avatar; announcement dropped"),
+                Err(e) => warn!("avatar announcement from {peer} dropped: {e}"),
+            },
+            Message::AvatarRequest(req) => {
+                let reply = match shared.presence.avatar(req.player_id) {
+                    Some(avatar) => Message::AvatarAnnounce(AvatarAnnounce {
+                        request_id: Some(req.request_id),
+                        player_id: Some(req.player_id),
+                        avatar,
+                    }),
+                    None => Message::AvatarResult(AvatarResult {
+                        request_id: req.request_id,
+                        avatar: None,
+                        error: Some(format!("player {} has no avatar to share", req.player_id)),
+                    }),
+                };
+                outbox.send(reply).await?;
+            }
+            Message::PlayerPosition(p) => {
+                if p.position.iter().all(|v| v.is_finite()) {
+                    shared.presence.set_position(player_id, p.position);
+                    let chunk = chunks::chunk_for_position(p.position);
+                    if let Some(profile_id) = &profile {
+                        if explored.insert(chunk) {
+                            if let Err(e) = minimap::discover(&world_dir, profile_id, chunk) {
+                                warn!("recording exploration of {profile_id} failed: {e:#}");
+                            }
+                        }
+                    }
+                    if last_chunk.replace(chunk) == Some(chunk) {
+                        continue;
+                    }
+                    if let Some(handoff) =
+                        hand_off(&config, world_id, chunk, player_id, &handoff_token).await
+                    {
+                        info!(
+                            "{peer} walked into {chunk:?}; handed off to {}",
+                            handoff.addr
+                        );
+                        outbox.send(Message::Handoff(handoff)).await?;
+                        return Ok(());
+                    }
+                }
+            }
+            Message::Emote(emote) => match emotes::check(&manifest.emotes, &emote, player_id) {
+                Ok(emote) => {
+                    shared
+                        .presence
+                        .send_nearby(player_id, &Message::Emote(emote));
+                }
+                Err(e) => warn!("emote from {peer} dropped: {e:#}"),
+            },
+            Message::ChatSend(send) => {
+                match chat::deliver(&shared.presence, &shared.parties, player_id, send) {
+                    Ok(said) => {
+                        if let Err(e) = shared.chat_log.record(&said) {
+                            warn!("logging chat from {peer} failed: {e:#}");
+                        }
+                    }
+                    Err(e) => outbox.send(chat::system(format!("{e:#}"))).await?,
+                }
+            }
+            Message::PartyCreate(req) => {
+                let result = shared
+                    .parties
+                    .create(player_id, world_id, outbox.clone())
+                    .map(|(party, token)| (Some(party), Some(token)));
+                outbox
+                    .send(party_result(peer, req.request_id, result))
+                    .await?;
+            }
+            Message::PartyInvite(req) => {
+                let to = req.player_id;
+                let result = shared.parties.invite(player_id, to).and_then(|party| {
+                    let invited = Message::PartyInvited(PartyInvited {
+                        party_id: party.party_id,
+                        from: player_id,
+                    });
+                    if !shared.presence.send_to(to, &invited) {
+                        anyhow::bail!("player {to} is not here");
+                    }
+                    Ok((Some(party), None))
+                });
+                outbox
+                    .send(party_result(peer, req.request_id, result))
+                    .await?;
+            }
+            Message::PartyJoin(req) => {
+                let result = shared
+                    .parties
+                    .join(player_id, world_id, req.party_id, outbox.clone())
+                    .map(|(party, token)| (Some(party), Some(token)));
+                outbox
+                    .send(party_result(peer, req.request_id, result))
+                    .await?;
+            }
+            Message::PartyLeave(req) => {
+                let result = shared.parties.leave(player_id).map(|()| (None, None));
+                outbox
+                    .send(party_result(peer, req.request_id, result))
+                    .await?;
+            }
+            Message::PartyTravel(travel) => {
+                if let Err(e) = shared.parties.travel(player_id, &travel.connect) {
+                    outbox.send(chat::system(format!("{e:#}"))).await?;
+                }
+            }
+            other => {
+                warn!("unexpected message from {peer}: {other:?}");
+            }
+        }
+    }
+}
+
+fn disconnect(reason: DisconnectReason, message: &str) -> Message {
+    Message::Disconnect(Disconnect {
+        reason,
+        message: Some(message.to_string()),
+    })
+}
+
+/// Say goodbye to every player connected to this process, e.g. before it exits, and give the
+/// writers a moment to send it.
+pub async fn disconnect_all(roster: &Roster, reason: DisconnectReason, message: &str) {
+    let n = roster.send_all(&disconnect(reason, message));
+    if n > 0 {
+        info!("disconnecting {n} player(s): {message}");
+        tokio::time::sleep(GOODBYE_GRACE).await;
+    }
+}
+
+/// The next position the client sent as a datagram; never, without QUIC.
+async fn next_datagram(datagrams: &mut Option<mpsc::Receiver<Message>>) -> Option<Message> {
+    match datagrams {
+        Some(rx) => rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Resolves at the interval's next tick; never without one.
+async fn next_tick(interval: &mut Option<tokio::time::Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+fn now_ms() -> i64 {
+    (OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000) as i64
+}
+
+/// The cluster config, when `world_id` is clustered and the config is usable.
+fn cluster_for(config: &LiveConfig, world_id: Uuid) -> Option<ClusterConfig> {
+    let cfg = config
+        .current()
+        .cluster
+        .filter(|c| c.world_id == world_id)?;
+    match cfg.check() {
+        Ok(()) => Some(cfg),
+        Err(e) => {
+            warn!("ignoring cluster config: {e:#}");
+            None
+        }
+    }
+}
+
+/// The `WorldMoved` to send once the world has been migrated to another host.
+fn moved_to(world_dir: &Path) -> Option<Message> {
+    let state = migration::load(world_dir).unwrap_or_else(|e| {
+        warn!("reading migration state failed: {e:#}");
+        None
+    })?;
+    match (state.phase, state.connect) {
+        (MigrationPhase::Moved, Some(connect)) => Some(Message::WorldMoved(WorldMoved { connect })),
+        _ => None,
+    }
+}
+
+/// Redirect everyone online once the world has moved.
+async fn watch_migration(world_dir: PathBuf, presence: Presence) {
+    let mut announced = false;
+    loop {
+        tokio::time::sleep(MIGRATION_POLL).await;
+        match moved_to(&world_dir) {
+            Some(moved) if !announced => {
+                let n = presence.send_all(None, &moved);
+                info!("world moved; redirected {n} connected player(s)");
+                announced = true;
+            }
+            Some(_) => {}
+            None => announced = false,
+        }
+    }
 }
+
+/// Hand the player off when `chunk` belongs to another node and that node takes the session.
+/// Chunks outside every region stay with the current node.
+async fn hand_off(
+    config: &LiveConfig,
+    world_id: Uuid,
+    chunk: ChunkCoord,
+    player_id: Uuid,
+    session_token: &str,
+) -> Option<Handoff> {
+    let cfg = cluster_for(config, world_id)?;
+    let owner = cfg.owner(chunk).filter(|n| n.id != cfg.node_id)?;
+    let ticket = HandoffTicket {
+        world_id,
+        player_id,
+        session_token: session_token.to_string(),
+        from: cfg.node_id.clone(),
+    };
+    match cluster::send_handoff(&cfg, owner, &ticket).await {
+        Ok(()) => Some(Handoff {
+            addr: owner.game.clone(),
+            session_token: ticket.session_token,
+        }),
+        Err(e) => {
+            warn!("handing off player {player_id} failed: {e:#}");
+            None
+        }
+    }
+}
+
+/// Remember what `player_id` wears and show it to everyone else in the world.
+fn announce_avatar(presence: &Presence, player_id: Uuid, avatar: AvatarSpecV1) {
+    presence.set_avatar(player_id, avatar.clone());
+    let announce = Message::AvatarAnnounce(AvatarAnnounce {
+        request_id: None,
+        player_id: Some(player_id),
+        avatar,
+    });
+    presence.send_all(Some(player_id), &announce);
+}
+
+fn avatar_result(peer: SocketAddr, request_id: Uuid, result: Result<AvatarSpecV1>) -> Message {
+    match result {
+        Ok(avatar) => {
+            info!("{peer} now wears avatar {:?}", avatar.name);
+            Message::AvatarResult(AvatarResult {
+                request_id,
+                avatar: Some(avatar),
+                error: None,
+            })
+        }
+        Err(e) => {
+            warn!("avatar from {peer} rejected: {e:#}");
+            Message::AvatarResult(AvatarResult {
+                request_id,
+                avatar: None,
+                error: Some(format!("{e:#}")),
+            })
+        }
+    }
+}
+
+/// The profile the player acts as on this host, once `hello.wallet_pubkey` has been cleared unless
+/// proven: the one it names, or its wallet when it proved one and named none. A profile named
+/// after a wallet is only that wallet's to use, so a proof is what makes it a stable identity.
+fn profile_of(hello: &Hello) -> Option<String> {
+    let wallet = hello
+        .wallet_proof
+        .as_ref()
+        .and(hello.wallet_pubkey.as_deref())
+        .filter(|w| friends::kind_of(w).is_ok_and(|k| k == friends::FriendKind::Wallet));
+    let named = hello
+        .profile_id
+        .as_deref()
+        .filter(|p| friends::check_profile_id(p).is_ok());
+    match named {
+        Some(p) if friends::kind_of(p).is_ok_and(|k| k == friends::FriendKind::Wallet) => {
+            wallet.filter(|w| *w == p).map(String::from)
+        }
+        Some(p) => Some(p.to_string()),
+        None => wallet.map(String::from),
+    }
+}
+
+/// Ids the player claims in `Hello`, and the friends list of its profile on this host.
+fn identity(store: &WorldStore, hello: &Hello) -> (Vec<String>, HashSet<String>) {
+    let mut ids = vec![];
+    let mut friend_ids = HashSet::new();
+    if let Some(w) = hello.wallet_pubkey.as_deref() {
+        if friends::kind_of(w).is_ok_and(|k| k == friends::FriendKind::Wallet) {
+            ids.push(w.to_string());
+        }
+    }
+    if let Some(p) = hello.profile_id.as_deref() {
+        if friends::check_profile_id(p).is_ok() {
+            if !ids.iter().any(|id| id == p) {
+                ids.push(p.to_string());
+            }
+            match friends::load(store, p) {
+                Ok(f) => friend_ids = f.ids(),
+                Err(e) => warn!("loading friends of {p:?} failed: {e:#}"),
+            }
+        }
+    }
+    (ids, friend_ids)
+}
+
+fn party_result(
+    peer: SocketAddr,
+    request_id: Uuid,
+    result: Result<(Option<PartyInfo>, Option<String>)>,
+) -> Message {
+    let (party, token, error) = match result {
+        Ok((party, token)) => (party, token, None),
+        Err(e) => {
+            warn!("party request from {peer} failed: {e:#}");
+            (None, None, Some(format!("{e:#}")))
+        }
+    };
+    Message::PartyResult(PartyResult {
+        request_id,
+        party,
+        token,
+        error,
+    })
+}
+
+/// Per-connection token bucket. Limits are re-read on every message so config reloads
+/// apply to open connections.
+struct MessageBudget {
+    tokens: f64,
+    last: Instant,
+}
+
+impl MessageBudget {
+    fn new(limits: &RateLimitConfig) -> Self {
+        Self {
+            tokens: (limits.messages_per_sec + limits.burst) as f64,
+            last: Instant::now(),
+        }
+    }
+
+    fn allow(&mut self, limits: &RateLimitConfig) -> bool {
+        if limits.messages_per_sec == 0 {
+            return true;
+        }
+        let cap = (limits.messages_per_sec + limits.burst) as f64;
+        let now = Instant::now();
+        let refill = now.duration_since(self.last).as_secs_f64() * limits.messages_per_sec as f64;
+        self.tokens = (self.tokens + refill).min(cap);
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Counts a player as connected for as long as the guard lives.
+struct OnlineGuard(Arc<AtomicUsize>);
+
+impl OnlineGuard {
+    fn enter(counter: Arc<AtomicUsize>) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Self(counter)
+    }
+}
+
+impl Drop for OnlineGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// State shared by all connections to one world.
+#[derive(Clone)]
+struct Shared {
+    online: Arc<AtomicUsize>,
+    sessions: Sessions,
+    quality: NetQuality,
+    /// Wallet proofs already used.
+    nonces: NonceCache,
+    auth: AuthStats,
+    assets: AssetIndex,
+    presence: Presence,
+    parties: Parties,
+    nav: NavGrid,
+    bandwidth: GameMeter,
+    peers: PeerRegistry,
+    /// Noise static secret, from the world authority key; `None` serves plain clients only.
+    noise_key: Option<[u8; 32]>,
+    /// Signs `welcome.attestation`.
+    authority: Option<SigningKey>,
+    /// Signs `asset_token` messages for private assets.
+    asset_key: AssetTokenKey,
+    chat_log: ChatLog,
+}
+
+struct SessionSlot {
+    player_id: Uuid,
+    /// When the connection dropped; `None` while connected.
+    dropped: Option<Instant>,
+    /// The player's state when it dropped, for the session that resumes it.
+    parked: Option<Parked>,
+}
+
+/// What a player had in the world, kept through the resume window.
+#[derive(Default)]
+struct Parked {
+    position: Option<[f32; 3]>,
+    avatar: Option<AvatarSpecV1>,
+    /// Avatars submitted with a `wardrobe_name` by a player without a profile, for
+    /// `AvatarSwitch`; they last as long as the session. A profile keeps them in its wardrobe.
+    outfits: HashMap<String, AvatarSpecV1>,
+}
+
+/// Session tokens handed out in `Welcome`.
+#[derive(Clone, Default)]
+struct Sessions(Arc<std::sync::Mutex<HashMap<String, SessionSlot>>>);
+
+impl Sessions {
+    /// Resume `token` if it belongs to a recently dropped session, else start a new one.
+    /// Returns the token, the player id (kept across resumes) and whether it was resumed.
+    fn begin(&self, token: Option<&str>) -> (String, Uuid, bool) {
+        let mut map = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        map.retain(|_, s| s.dropped.is_none_or(|t| t.elapsed() < RESUME_WINDOW));
+        if let Some(t) = token {
+            // A token still held by a live connection can't be taken over.
+            if let Some(slot) = map.get_mut(t).filter(|s| s.dropped.is_some()) {
+                slot.dropped = None;
+                return (t.to_string(), slot.player_id, true);
+            }
+        }
+        let token = Uuid::new_v4().simple().to_string();
+        let player_id = Uuid::new_v4();
+        map.insert(
+            token.clone(),
+            SessionSlot {
+                player_id,
+                dropped: None,
+                parked: None,
+            },
+        );
+        (token, player_id, false)
+    }
+
+    /// Whether `token` belongs to a dropped session `begin` would resume.
+    fn resumable(&self, token: &str) -> bool {
+        let map = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        map.get(token)
+            .and_then(|s| s.dropped)
+            .is_some_and(|t| t.elapsed() < RESUME_WINDOW)
+    }
+
+    fn forget(&self, token: &str) {
+        let mut map = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        map.remove(token);
+    }
+
+    /// Take over a session another cluster node handed off, resumable for `RESUME_WINDOW`.
+    fn adopt(&self, token: &str, player_id: Uuid) {
+        let mut map = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        map.insert(
+            token.to_string(),
+            SessionSlot {
+                player_id,
+                dropped: Some(Instant::now()),
+                parked: None,
+            },
+        );
+    }
+
+    /// Keep `parked` for whoever resumes `token`; nothing, if the session was forgotten.
+    fn park(&self, token: &str, parked: Parked) {
+        let mut map = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(slot) = map.get_mut(token) {
+            slot.parked = Some(parked);
+        }
+    }
+
+    fn unpark(&self, token: &str) -> Option<Parked> {
+        let mut map = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        map.get_mut(token)?.parked.take()
+    }
+
+    fn end(&self, token: &str) {
+        let mut map = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(slot) = map.get_mut(token) {
+            slot.dropped = Some(Instant::now());
+        }
+    }
+}
+
+/// Starts the resume window when the connection goes away.
+struct SessionGuard {
+    sessions: Sessions,
+    token: String,
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.sessions.end(&self.token);
+    }
+}
+
+/// Parks the player's state in its session slot when the connection goes away. Declared after
+/// the `PresenceGuard`, so it reads the player's position and avatar before they're removed.
+struct ParkGuard {
+    sessions: Sessions,
+    presence: Presence,
+    token: String,
+    player_id: Uuid,
+    outfits: HashMap<String, AvatarSpecV1>,
+}
+
+impl Drop for ParkGuard {
+    fn drop(&mut self) {
+        let parked = Parked {
+            position: self.presence.position(self.player_id),
+            avatar: self.presence.avatar(self.player_id),
+            outfits: std::mem::take(&mut self.outfits),
+        };
+        self.sessions.park(&self.token, parked);
+    }
+}
+
+#[cfg(test)]
+mod tests;
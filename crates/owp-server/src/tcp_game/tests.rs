@@ -0,0 +1,465 @@
+//! Whole sessions against a `GameWorld`, over in-memory pipes on a paused clock: no sockets, no
+//! real sleeps, and the same interleaving on every run.
+
+use super::*;
+use owp_protocol::{
+    wire, ChatChannel, ChatSend, PlayerPosition, WalletProof, WorldSimulationConfig, WorldSnapshot,
+};
+use tokio::io::{duplex, DuplexStream};
+use tokio::task::JoinHandle;
+
+/// Room in each direction of a client's pipe.
+const PIPE_BYTES: usize = 256 * 1024;
+
+/// Longer than any scenario waits for one message, on the paused clock.
+const PATIENCE: Duration = Duration::from_secs(600);
+
+struct Harness {
+    _root: tempfile::TempDir,
+    world: GameWorld,
+    world_id: Uuid,
+    /// Each client gets its own port so they look like separate connections.
+    next_port: u16,
+}
+
+struct Client {
+    proto: ProtocolStateMachine,
+    stream: DuplexStream,
+    welcome: Welcome,
+    /// What the world looked like on joining.
+    snapshot: WorldSnapshot,
+    /// The server's side of the session.
+    task: JoinHandle<Result<()>>,
+}
+
+impl Harness {
+    async fn new() -> Self {
+        let root = tempfile::tempdir().expect("tempdir");
+        let store = WorldStore::with_root(root.path().to_path_buf()).expect("store");
+        let world_id = store.create_world("Harness", 0).expect("world").world_id;
+        let config = LiveConfig::load(&store).expect("config");
+        let world = GameWorld::open(
+            store,
+            world_id,
+            config,
+            Parties::default(),
+            &Roster::default(),
+            &Quotas::default(),
+        )
+        .await
+        .expect("open world");
+        Self {
+            _root: root,
+            world,
+            world_id,
+            next_port: 40_000,
+        }
+    }
+
+    /// A raw connection to the world, before any handshake.
+    fn connect(&mut self) -> (DuplexStream, JoinHandle<Result<()>>) {
+        let (client, server) = duplex(PIPE_BYTES);
+        let peer = SocketAddr::from(([127, 0, 0, 1], self.next_port));
+        self.next_port += 1;
+        let admission = self.world.admit(peer).expect("admitted");
+        let world = self.world.clone();
+        let task = tokio::spawn(async move { world.session(admission, server, peer, None).await });
+        (client, task)
+    }
+
+    fn hello(&self) -> Hello {
+        Hello {
+            protocol_version: OWP_PROTOCOL_VERSION.to_string(),
+            request_id: Uuid::new_v4(),
+            world_id: Some(self.world_id),
+            client_name: Some("harness".to_string()),
+            resume_token: None,
+            party_token: None,
+            wallet_pubkey: None,
+            wallet_proof: None,
+            profile_id: None,
+            frame_protection: vec![],
+            wire_format: vec![],
+            compression: vec![],
+            attestation_nonce: None,
+            capabilities: vec![],
+        }
+    }
+
+    async fn join_with(&mut self, hello: Hello) -> Client {
+        let (mut stream, task) = self.connect();
+        let mut proto = ProtocolStateMachine::client(hello).expect("client");
+        let welcome = match proto.next_handshake_event(&mut stream).await {
+            Ok(Event::Welcome(w)) => w,
+            other => panic!("expected welcome, got {other:?}"),
+        };
+        let snapshot = match proto.next_event(&mut stream).await {
+            Ok(Event::Message(Message::WorldSnapshot(s))) => s,
+            other => panic!("expected the world snapshot, got {other:?}"),
+        };
+        Client {
+            proto,
+            stream,
+            welcome,
+            snapshot,
+            task,
+        }
+    }
+
+    async fn join(&mut self) -> Client {
+        let hello = self.hello();
+        self.join_with(hello).await
+    }
+}
+
+impl Client {
+    fn player_id(&self) -> Uuid {
+        self.welcome.player_id.expect("player id")
+    }
+
+    async fn send(&mut self, msg: Message) {
+        self.proto.send(&msg).expect("queue");
+        self.proto.flush(&mut self.stream).await.expect("flush");
+    }
+
+    async fn recv(&mut self) -> Result<Message, ProtocolError> {
+        match tokio::time::timeout(PATIENCE, self.proto.next_event(&mut self.stream)).await {
+            Ok(Ok(Event::Message(m))) => Ok(m),
+            Ok(Ok(other)) => panic!("unexpected event: {other:?}"),
+            Ok(Err(e)) => Err(e),
+            Err(_) => panic!("nothing arrived in {PATIENCE:?}"),
+        }
+    }
+
+    /// Skip messages until one matches.
+    async fn recv_until(&mut self, want: impl Fn(&Message) -> bool) -> Message {
+        loop {
+            let msg = self.recv().await.expect("message");
+            if want(&msg) {
+                return msg;
+            }
+        }
+    }
+
+    /// Round-trip a ping, so everything sent before it has been handled.
+    async fn sync(&mut self) {
+        let ping = Ping::new(0);
+        let nonce = ping.nonce;
+        self.send(Message::Ping(ping)).await;
+        self.recv_until(|m| matches!(m, Message::Pong(p) if p.nonce == nonce))
+            .await;
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn players_see_each_other_move_and_chat() {
+    let mut h = Harness::new().await;
+    let mut alice = h.join().await;
+    alice
+        .send(Message::PlayerPosition(PlayerPosition {
+            position: [5.0, 0.0, -3.0],
+        }))
+        .await;
+    alice.sync().await;
+
+    let mut bob = h.join().await;
+    let seen: Vec<_> = bob
+        .snapshot
+        .players
+        .iter()
+        .map(|p| (p.player_id, p.position))
+        .collect();
+    assert_eq!(seen, [(alice.player_id(), Some([5.0, 0.0, -3.0]))]);
+
+    alice
+        .send(Message::ChatSend(ChatSend {
+            channel: ChatChannel::World,
+            text: "hello bob".to_string(),
+        }))
+        .await;
+    let alice_id = alice.welcome.player_id;
+    for client in [&mut alice, &mut bob] {
+        let Message::ChatBroadcast(chat) = client
+            .recv_until(|m| matches!(m, Message::ChatBroadcast(_)))
+            .await
+        else {
+            unreachable!();
+        };
+        assert_eq!(chat.text, "hello bob");
+        assert_eq!(chat.from, alice_id);
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn a_frame_cut_off_by_a_disconnect_leaves_a_resumable_session() {
+    let mut h = Harness::new().await;
+    let mut alice = h.join().await;
+    let frame = wire::encode_frame(&Message::PlayerPosition(PlayerPosition {
+        position: [1.0, 2.0, 3.0],
+    }))
+    .expect("frame");
+    alice
+        .stream
+        .write_all(&frame[..frame.len() / 2])
+        .await
+        .expect("write");
+    let Client {
+        stream,
+        welcome,
+        task,
+        ..
+    } = alice;
+    drop(stream);
+    task.await
+        .expect("join")
+        .expect("a dropped client isn't an error");
+
+    // Well inside the resume window.
+    tokio::time::sleep(RESUME_WINDOW / 2).await;
+    let mut hello = h.hello();
+    hello.resume_token = welcome.session_token.clone();
+    let back = h.join_with(hello).await;
+    assert!(back.welcome.resumed);
+    assert_eq!(back.welcome.player_id, welcome.player_id);
+}
+
+#[tokio::test(start_paused = true)]
+async fn a_resumed_player_keeps_its_place_in_the_world() {
+    let mut h = Harness::new().await;
+    let mut alice = h.join().await;
+    alice
+        .send(Message::PlayerPosition(PlayerPosition {
+            position: [7.0, 0.0, 2.0],
+        }))
+        .await;
+    alice.sync().await;
+    let token = alice.welcome.session_token.clone();
+    drop(alice.stream);
+    alice.task.await.expect("join").expect("session");
+
+    // Gone while disconnected...
+    let bob = h.join().await;
+    assert!(bob.snapshot.players.is_empty());
+
+    // ...and back where it was after resuming.
+    let mut hello = h.hello();
+    hello.resume_token = token;
+    let back = h.join_with(hello).await;
+    assert!(back.welcome.resumed);
+    let carol = h.join().await;
+    let alice_seen = carol
+        .snapshot
+        .players
+        .iter()
+        .find(|p| Some(p.player_id) == back.welcome.player_id)
+        .expect("alice in the snapshot");
+    assert_eq!(alice_seen.position, Some([7.0, 0.0, 2.0]));
+}
+
+#[tokio::test(start_paused = true)]
+async fn resume_window_closes_on_the_clock() {
+    let mut h = Harness::new().await;
+    let alice = h.join().await;
+    let token = alice.welcome.session_token.clone();
+    drop(alice.stream);
+    alice.task.await.expect("join").expect("session");
+
+    tokio::time::sleep(RESUME_WINDOW + Duration::from_secs(1)).await;
+    let mut hello = h.hello();
+    hello.resume_token = token;
+    let late = h.join_with(hello).await;
+    assert!(!late.welcome.resumed);
+    assert_ne!(late.welcome.player_id, alice.welcome.player_id);
+}
+
+#[tokio::test(start_paused = true)]
+async fn a_malformed_message_ends_only_that_session() {
+    let mut h = Harness::new().await;
+    let mut alice = h.join().await;
+    let mut bob = h.join().await;
+
+    let garbage = b"{\"type\": \"player_position\", \"position\": \"north\"}";
+    let mut frame = (garbage.len() as u32).to_be_bytes().to_vec();
+    frame.extend_from_slice(garbage);
+    alice.stream.write_all(&frame).await.expect("write");
+    let ended = (&mut alice.task).await.expect("join");
+    assert!(ended.is_err(), "the server should refuse the frame");
+    assert!(
+        alice.recv().await.is_err(),
+        "alice's connection should close"
+    );
+
+    bob.sync().await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn silent_clients_are_dropped_after_missed_pings() {
+    let mut h = Harness::new().await;
+    let mut alice = h.join().await;
+    let joined = Instant::now();
+    let keepalive = crate::config::KeepaliveConfig::default();
+
+    let mut pings = 0;
+    let bye = loop {
+        match alice.recv().await.expect("message") {
+            Message::Ping(_) => pings += 1,
+            Message::Disconnect(bye) => break bye,
+            _ => {}
+        }
+    };
+    assert_eq!(bye.reason, DisconnectReason::IdleTimeout);
+    assert_eq!(pings, keepalive.max_missed);
+    let every = Duration::from_secs(keepalive.interval_secs);
+    assert_eq!(joined.elapsed(), every * (keepalive.max_missed + 1));
+    alice.task.await.expect("join").expect("session");
+}
+
+#[tokio::test(start_paused = true)]
+async fn the_tick_loop_follows_the_clock() {
+    let h = Harness::new().await;
+    let world_dir = h.world.store.world_dir(h.world_id);
+    let state = Arc::new(Mutex::new(sim::load_state(&world_dir).expect("state")));
+    let online = Arc::new(AtomicUsize::new(0));
+    let cfg = WorldSimulationConfig {
+        run_while_empty: false,
+        tick_hz: 10,
+        max_catchup_secs: 0,
+    };
+    tokio::spawn(sim::run_tick_loop(
+        world_dir,
+        cfg,
+        state.clone(),
+        online.clone(),
+        h.world.budget.clone(),
+    ));
+
+    // Idle while nobody is online.
+    tokio::time::sleep(Duration::from_millis(1_050)).await;
+    assert_eq!(state.lock().await.tick, 0);
+
+    // Ticks at 1.1s, 1.2s, ... 2.0s.
+    online.store(1, Ordering::Relaxed);
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let st = state.lock().await;
+    assert_eq!(st.tick, 10);
+    assert!((st.sim_time_secs - 1.0).abs() < 1e-9);
+}
+
+#[tokio::test(start_paused = true)]
+async fn profiles_named_after_a_wallet_need_its_proof() {
+    let h = Harness::new().await;
+    let wallet = "9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin";
+    let proof = WalletProof {
+        timestamp: 0,
+        nonce: "n".to_string(),
+        signature: String::new(),
+    };
+
+    // A proven wallet is the player's profile unless it names another.
+    let proven = Hello {
+        wallet_pubkey: Some(wallet.to_string()),
+        wallet_proof: Some(proof.clone()),
+        ..h.hello()
+    };
+    assert_eq!(profile_of(&proven).as_deref(), Some(wallet));
+    let named = Hello {
+        profile_id: Some("alice".to_string()),
+        ..proven.clone()
+    };
+    assert_eq!(profile_of(&named).as_deref(), Some("alice"));
+
+    // Without the proof the wallet's profile isn't available, declared or not.
+    let declared = Hello {
+        wallet_pubkey: Some(wallet.to_string()),
+        profile_id: Some(wallet.to_string()),
+        ..h.hello()
+    };
+    assert_eq!(profile_of(&declared), None);
+    let refused = Hello {
+        wallet_pubkey: None,
+        ..proven
+    };
+    assert_eq!(profile_of(&refused), None);
+    let plain = Hello {
+        profile_id: Some("alice".to_string()),
+        ..h.hello()
+    };
+    assert_eq!(profile_of(&plain).as_deref(), Some("alice"));
+}
+
+#[tokio::test(start_paused = true)]
+async fn clients_only_hear_about_features_they_offered() {
+    let mut h = Harness::new().await;
+    let mut alice = h.join().await;
+    let legacy: Vec<_> = alice.welcome.capabilities.clone();
+    assert!(legacy.contains(&Capability::Chat));
+
+    let hello = Hello {
+        capabilities: vec![
+            Capability::WorldSnapshot,
+            Capability::Other("hologram_calls".to_string()),
+        ],
+        ..h.hello()
+    };
+    let mut bob = h.join_with(hello).await;
+    assert_eq!(bob.welcome.capabilities, [Capability::WorldSnapshot]);
+
+    alice
+        .send(Message::ChatSend(ChatSend {
+            channel: ChatChannel::World,
+            text: "anyone?".to_string(),
+        }))
+        .await;
+    alice
+        .recv_until(|m| matches!(m, Message::ChatBroadcast(_)))
+        .await;
+    // Bob's copy would have been queued before the reply to this ping.
+    let ping = Ping::new(0);
+    let nonce = ping.nonce;
+    bob.send(Message::Ping(ping)).await;
+    loop {
+        match bob.recv().await.expect("message") {
+            Message::Pong(p) if p.nonce == nonce => break,
+            Message::ChatBroadcast(_) => panic!("bob didn't offer chat"),
+            _ => {}
+        }
+    }
+}
+
+#[tokio::test]
+async fn batched_relays_arrive_in_order() {
+    let mut h = Harness::new().await;
+    let mut alice = h.join().await;
+    let hello = Hello {
+        capabilities: vec![
+            Capability::WorldSnapshot,
+            Capability::Chat,
+            Capability::Batch,
+        ],
+        ..h.hello()
+    };
+    let mut bob = h.join_with(hello).await;
+    assert!(bob.welcome.capabilities.contains(&Capability::Batch));
+    assert!(!alice.welcome.capabilities.contains(&Capability::Batch));
+
+    for i in 0..5 {
+        alice
+            .proto
+            .send(&Message::ChatSend(ChatSend {
+                channel: ChatChannel::World,
+                text: format!("line {i}"),
+            }))
+            .expect("queue");
+    }
+    alice.proto.flush(&mut alice.stream).await.expect("flush");
+    let mut heard = vec![];
+    while heard.len() < 5 {
+        if let Message::ChatBroadcast(c) = bob.recv().await.expect("message") {
+            heard.push(c.text);
+        }
+    }
+    assert_eq!(
+        heard,
+        (0..5).map(|i| format!("line {i}")).collect::<Vec<_>>()
+    );
+}
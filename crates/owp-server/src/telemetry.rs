@@ -0,0 +1,215 @@
+//! Opt-in usage stats (`telemetry` in server.json). Nothing is counted until a host turns it on;
+//! counters are kept in `telemetry.json` and only leave the machine when `owp-server telemetry
+//! --send` posts them to `telemetry.endpoint`. The report carries counts and averages only: no
+//! world ids, names, profiles, prompts or addresses. `GET /telemetry/preview` shows it as it
+//! would be sent.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+use time::OffsetDateTime;
+use tracing::{info, warn};
+
+use crate::config::LiveConfig;
+use crate::storage::{write_atomic, WorldStore};
+
+/// Serializes read-modify-write of the counters within this process.
+static TELEMETRY_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TelemetryConfig {
+    /// Count usage at all. Off by default.
+    #[serde(default)]
+    pub enabled: bool,
+    /// Where `owp-server telemetry --send` posts the report; kept local when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+}
+
+impl TelemetryConfig {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GenerationCounts {
+    pub succeeded: u64,
+    pub failed: u64,
+}
+
+/// Counters since the last report was sent.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TelemetryV1 {
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub since: Option<OffsetDateTime>,
+    #[serde(default)]
+    pub worlds_created: u64,
+    /// By assistant provider (`codex`, `claude`).
+    #[serde(default)]
+    pub generations: BTreeMap<String, GenerationCounts>,
+    #[serde(default)]
+    pub renders: u64,
+    #[serde(default)]
+    pub render_ms_total: u64,
+}
+
+/// Exactly what is posted to `telemetry.endpoint`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TelemetryReport {
+    pub version: u32,
+    pub owp_version: &'static str,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub since: Option<OffsetDateTime>,
+    pub worlds_created: u64,
+    pub generations: BTreeMap<String, GenerationCounts>,
+    /// Mean OpenSCAD render time of generated avatar meshes; absent before the first render.
+    pub average_render_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Event<'a> {
+    WorldCreated,
+    Generation { provider: &'a str, ok: bool },
+    Render(Duration),
+}
+
+pub fn telemetry_path(store: &WorldStore) -> PathBuf {
+    store.root_dir().join("telemetry.json")
+}
+
+pub fn load(store: &WorldStore) -> Result<TelemetryV1> {
+    let path = telemetry_path(store);
+    if !path.exists() {
+        return Ok(TelemetryV1::default());
+    }
+    let data = std::fs::read_to_string(&path).with_context(|| format!("read {path:?}"))?;
+    serde_json::from_str(&data).with_context(|| format!("parse {path:?}"))
+}
+
+fn save(store: &WorldStore, t: &TelemetryV1) -> Result<()> {
+    let json = serde_json::to_string_pretty(t).context("serialize telemetry")?;
+    write_atomic(&telemetry_path(store), format!("{json}\n").as_bytes())?;
+    Ok(())
+}
+
+impl TelemetryV1 {
+    fn apply(&mut self, event: Event, now: OffsetDateTime) {
+        self.since.get_or_insert(now);
+        match event {
+            Event::WorldCreated => self.worlds_created += 1,
+            Event::Generation { provider, ok } => {
+                let counts = self.generations.entry(provider.to_string()).or_default();
+                if ok {
+                    counts.succeeded += 1;
+                } else {
+                    counts.failed += 1;
+                }
+            }
+            Event::Render(took) => {
+                self.renders += 1;
+                self.render_ms_total += took.as_millis() as u64;
+            }
+        }
+    }
+
+    pub fn report(&self) -> TelemetryReport {
+        TelemetryReport {
+            version: 1,
+            owp_version: env!("CARGO_PKG_VERSION"),
+            since: self.since,
+            worlds_created: self.worlds_created,
+            generations: self.generations.clone(),
+            average_render_ms: (self.renders > 0).then(|| self.render_ms_total / self.renders),
+        }
+    }
+}
+
+/// Count `event` if the host opted in. Never fails the caller; problems are logged.
+pub fn record(store: &WorldStore, event: Event) {
+    let enabled = LiveConfig::load(store).map(|c| c.current().telemetry.enabled);
+    if !enabled.unwrap_or(false) {
+        return;
+    }
+    let _guard = TELEMETRY_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let result = load(store).and_then(|mut t| {
+        t.apply(event, OffsetDateTime::now_utc());
+        save(store, &t)
+    });
+    if let Err(e) = result {
+        warn!("recording telemetry failed: {e:#}");
+    }
+}
+
+/// The report as it would be sent now.
+pub fn preview(store: &WorldStore) -> Result<TelemetryReport> {
+    Ok(load(store)?.report())
+}
+
+/// Post the report to `cfg.endpoint` and start counting afresh.
+pub async fn send(store: &WorldStore, cfg: &TelemetryConfig) -> Result<TelemetryReport> {
+    if !cfg.enabled {
+        anyhow::bail!("telemetry is off; set `telemetry.enabled` in server.json");
+    }
+    let endpoint = cfg
+        .endpoint
+        .as_deref()
+        .context("no `telemetry.endpoint` in server.json")?;
+    let report = preview(store)?;
+    reqwest::Client::new()
+        .post(endpoint)
+        .timeout(Duration::from_secs(30))
+        .json(&report)
+        .send()
+        .await
+        .with_context(|| format!("post to {endpoint}"))?
+        .error_for_status()
+        .with_context(|| format!("post to {endpoint}"))?;
+    {
+        let _guard = TELEMETRY_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        save(store, &TelemetryV1::default())?;
+    }
+    info!("sent telemetry to {endpoint}");
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_only_after_opting_in() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let store = WorldStore::with_root(root.path().to_path_buf()).expect("store");
+        record(&store, Event::WorldCreated);
+        assert!(!telemetry_path(&store).exists());
+
+        let config = LiveConfig::load(&store).expect("config");
+        config
+            .update(|c| c.telemetry.enabled = true)
+            .expect("opt in");
+        record(&store, Event::WorldCreated);
+        for ok in [true, true, false] {
+            let provider = "codex";
+            record(&store, Event::Generation { provider, ok });
+        }
+        record(&store, Event::Render(Duration::from_millis(300)));
+        record(&store, Event::Render(Duration::from_millis(500)));
+
+        let report = preview(&store).expect("preview");
+        assert_eq!(report.worlds_created, 1);
+        assert_eq!(
+            report.generations["codex"],
+            GenerationCounts {
+                succeeded: 2,
+                failed: 1
+            }
+        );
+        assert_eq!(report.average_render_ms, Some(400));
+        assert!(report.since.is_some());
+        assert_eq!(TelemetryV1::default().report().average_render_ms, None);
+    }
+}
@@ -0,0 +1,262 @@
+//! TLS on the game port (`tls` in the world manifest). Clients choose per connection: a TLS
+//! client opens with a handshake record, which no plain frame or Noise preamble starts with, so
+//! one port serves all of them unless the world requires TLS.
+//!
+//! Without a certificate of its own the world presents a self-signed one for its authority key,
+//! which clients that know the world's `pubkey` pin as they would a Noise server.
+
+use anyhow::{Context, Result};
+use ed25519_dalek::SigningKey;
+use owp_protocol::WorldTls;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use tokio_rustls::rustls::{self, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+/// Content type of a TLS record carrying a handshake message, the first byte a client sends.
+const HANDSHAKE_RECORD: u8 = 0x16;
+
+/// How long a new connection has to send its first byte.
+const FIRST_BYTE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// PKCS#8 v1 wrapping of an ed25519 seed (RFC 8410).
+fn ed25519_pkcs8(seed: &[u8; 32]) -> Vec<u8> {
+    const PREFIX: [u8; 16] = [
+        0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04,
+        0x20,
+    ];
+    [&PREFIX[..], seed].concat()
+}
+
+/// A self-signed certificate for `key`.
+pub fn self_signed(key: &SigningKey) -> Result<(CertificateDer<'static>, PrivateKeyDer<'static>)> {
+    let pkcs8 = ed25519_pkcs8(&key.to_bytes());
+    let key_pair = rcgen::KeyPair::try_from(pkcs8.as_slice()).context("load tls key")?;
+    let cert = rcgen::CertificateParams::new(vec!["owp".to_string()])
+        .context("certificate params")?
+        .self_signed(&key_pair)
+        .context("self-sign certificate")?;
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(pkcs8));
+    Ok((cert.der().clone(), key))
+}
+
+/// The certificate chain and key the world presents.
+fn identity(
+    world_dir: &Path,
+    settings: &WorldTls,
+    authority: Option<&SigningKey>,
+) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    match (&settings.cert_path, &settings.key_path) {
+        (Some(cert_path), Some(key_path)) => {
+            let cert_path = world_dir.join(cert_path);
+            let key_path = world_dir.join(key_path);
+            let chain = CertificateDer::pem_file_iter(&cert_path)
+                .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+                .with_context(|| format!("read {}", cert_path.display()))?;
+            anyhow::ensure!(
+                !chain.is_empty(),
+                "no certificate in {}",
+                cert_path.display()
+            );
+            let key = PrivateKeyDer::from_pem_file(&key_path)
+                .with_context(|| format!("read {}", key_path.display()))?;
+            Ok((chain, key))
+        }
+        (None, None) => {
+            // A world whose authority lives elsewhere gets a throwaway key clients can't pin.
+            let key = match authority {
+                Some(key) => key.clone(),
+                None => SigningKey::generate(&mut rand::rngs::OsRng),
+            };
+            let (cert, key) = self_signed(&key)?;
+            Ok((vec![cert], key))
+        }
+        _ => anyhow::bail!("tls needs both cert_path and key_path, or neither"),
+    }
+}
+
+/// A connection on the game port, after looking at how it opened.
+pub enum Opened {
+    Tls(Box<TlsStream<TcpStream>>),
+    Plain(TcpStream),
+}
+
+/// TLS for one world's game port.
+pub struct GamePortTls {
+    acceptor: TlsAcceptor,
+    required: bool,
+}
+
+impl GamePortTls {
+    /// Load the certificate `settings` name (paths relative to `world_dir`), or self-sign for
+    /// `authority`.
+    pub fn load(
+        world_dir: &Path,
+        settings: &WorldTls,
+        authority: Option<&SigningKey>,
+    ) -> Result<Self> {
+        let (chain, key) = identity(world_dir, settings, authority)?;
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = ServerConfig::builder_with_provider(provider)
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .context("tls versions")?
+            .with_no_client_auth()
+            .with_single_cert(chain, key)
+            .context("tls certificate")?;
+        Ok(Self {
+            acceptor: TlsAcceptor::from(Arc::new(config)),
+            required: settings.required,
+        })
+    }
+
+    /// Finish the TLS handshake if the client opened with one. Anything else passes through as
+    /// is, unless the world requires TLS.
+    pub async fn accept(&self, stream: TcpStream) -> Result<Opened> {
+        let mut first = [0u8; 1];
+        let peeked = tokio::time::timeout(FIRST_BYTE_TIMEOUT, stream.peek(&mut first))
+            .await
+            .context("no data from client")?
+            .context("peek")?;
+        if peeked == 1 && first[0] == HANDSHAKE_RECORD {
+            let stream = self
+                .acceptor
+                .accept(stream)
+                .await
+                .context("tls handshake")?;
+            return Ok(Opened::Tls(Box::new(stream)));
+        }
+        anyhow::ensure!(!self.required, "this world requires tls");
+        Ok(Opened::Plain(stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use owp_protocol::{wire, ChatChannel, ChatSend, Message};
+    use tokio::net::TcpListener;
+
+    fn chat(text: &str) -> Message {
+        Message::ChatSend(ChatSend {
+            channel: ChatChannel::World,
+            text: text.to_string(),
+        })
+    }
+
+    /// Accept one connection with `tls` and read one message from it.
+    async fn serve_one(
+        tls: GamePortTls,
+    ) -> (
+        std::net::SocketAddr,
+        tokio::task::JoinHandle<Result<(bool, Message)>>,
+    ) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        let task = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await?;
+            Ok(match tls.accept(stream).await? {
+                Opened::Tls(mut stream) => (true, wire::read_message(&mut stream).await?),
+                Opened::Plain(mut stream) => (false, wire::read_message(&mut stream).await?),
+            })
+        });
+        (addr, task)
+    }
+
+    #[tokio::test]
+    async fn clients_pin_the_authority_key_and_plain_clients_share_the_port() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let authority = SigningKey::generate(&mut rand::rngs::OsRng);
+        let pinned = *authority.verifying_key().as_bytes();
+        let settings = WorldTls::default();
+
+        let tls = GamePortTls::load(dir.path(), &settings, Some(&authority)).expect("tls");
+        let (addr, server) = serve_one(tls).await;
+        let stream = TcpStream::connect(addr).await.expect("connect");
+        let mut stream = owp_discovery::tls::connect(stream, "127.0.0.1", Some(pinned))
+            .await
+            .expect("pinned handshake");
+        wire::write_message(&mut stream, &chat("over tls"))
+            .await
+            .expect("write");
+        let (was_tls, msg) = server.await.expect("join").expect("served");
+        assert!(was_tls);
+        assert!(matches!(msg, Message::ChatSend(c) if c.text == "over tls"));
+
+        // A certificate for another key is refused.
+        let other = SigningKey::generate(&mut rand::rngs::OsRng);
+        let tls = GamePortTls::load(dir.path(), &settings, Some(&other)).expect("tls");
+        let (addr, _server) = serve_one(tls).await;
+        let stream = TcpStream::connect(addr).await.expect("connect");
+        let refused = owp_discovery::tls::connect(stream, "127.0.0.1", Some(pinned)).await;
+        assert!(refused.is_err());
+
+        // Plain clients are served unless the world requires tls.
+        let tls = GamePortTls::load(dir.path(), &settings, Some(&authority)).expect("tls");
+        let (addr, server) = serve_one(tls).await;
+        let mut stream = TcpStream::connect(addr).await.expect("connect");
+        wire::write_message(&mut stream, &chat("in the clear"))
+            .await
+            .expect("write");
+        let (was_tls, _) = server.await.expect("join").expect("served");
+        assert!(!was_tls);
+
+        let required = WorldTls {
+            required: true,
+            ..settings
+        };
+        let tls = GamePortTls::load(dir.path(), &required, Some(&authority)).expect("tls");
+        let (addr, server) = serve_one(tls).await;
+        let mut stream = TcpStream::connect(addr).await.expect("connect");
+        wire::write_message(&mut stream, &chat("in the clear"))
+            .await
+            .expect("write");
+        assert!(server.await.expect("join").is_err());
+    }
+
+    fn pem(label: &str, der: &[u8]) -> String {
+        use base64::Engine;
+        let body = base64::engine::general_purpose::STANDARD.encode(der);
+        let lines: Vec<_> = body
+            .as_bytes()
+            .chunks(64)
+            .map(String::from_utf8_lossy)
+            .collect();
+        format!(
+            "-----BEGIN {label}-----\n{}\n-----END {label}-----\n",
+            lines.join("\n")
+        )
+    }
+
+    #[test]
+    fn certificates_load_from_the_world_directory() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let key_pair = rcgen::KeyPair::generate().expect("key");
+        let cert = rcgen::CertificateParams::new(vec!["world.example".to_string()])
+            .expect("params")
+            .self_signed(&key_pair)
+            .expect("cert");
+        std::fs::create_dir(dir.path().join("tls")).expect("mkdir");
+        let cert_pem = pem("CERTIFICATE", cert.der());
+        let key_pem = pem("PRIVATE KEY", &key_pair.serialize_der());
+        std::fs::write(dir.path().join("tls/cert.pem"), cert_pem).expect("cert");
+        std::fs::write(dir.path().join("tls/key.pem"), key_pem).expect("key");
+
+        let settings = WorldTls {
+            cert_path: Some("tls/cert.pem".to_string()),
+            key_path: Some("tls/key.pem".to_string()),
+            required: false,
+        };
+        GamePortTls::load(dir.path(), &settings, None).expect("load from pem");
+
+        let half = WorldTls {
+            key_path: None,
+            ..settings
+        };
+        assert!(GamePortTls::load(dir.path(), &half, None).is_err());
+    }
+}
@@ -0,0 +1,448 @@
+//! Creating a world's token from the host itself (`POST /worlds/:id/token/create`), for hosts
+//! without the private launch app: a funded local keypair pays for either a bare SPL mint (plus
+//! Metaplex metadata if asked) or a Meteora DBC pool, which mints the token as it is created.
+//! The result is recorded in the manifest exactly as `publish-result` would.
+
+use std::path::{Path, PathBuf};
+
+use ed25519_dalek::SigningKey;
+use owp_discovery::alias::find_program_address;
+use owp_discovery::proxy::Proxy;
+use owp_discovery::transaction::{
+    self, parse_pubkey, pubkey, AccountMeta, Instruction, Pubkey, SYSTEM_PROGRAM_ID,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+pub const SPL_TOKEN_PROGRAM_ID: &str = "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA";
+pub const TOKEN_METADATA_PROGRAM_ID: &str = "metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s";
+pub const DBC_PROGRAM_ID: &str = "dbcij3LWUppWqq96dh6gJWwBifmcGfLSB5D4DuSMaqN";
+pub const WRAPPED_SOL_MINT: &str = "So11111111111111111111111111111111111111112";
+
+/// `spl_token::state::Mint::LEN`.
+const MINT_LEN: usize = 82;
+/// Metaplex limits on `DataV2`.
+const MAX_NAME_LEN: usize = 32;
+const MAX_SYMBOL_LEN: usize = 10;
+const MAX_URI_LEN: usize = 200;
+
+/// Programs and network used by token launches; the defaults are the canonical mainnet/devnet
+/// deployments.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TokenLaunchConfig {
+    /// Cluster tokens are created on (`devnet`, `testnet`, `mainnet-beta`); recorded in the
+    /// manifest and used for explorer links.
+    #[serde(default = "default_network")]
+    pub network: String,
+    /// Falls back to the discovery RPC (`--solana-rpc-url`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rpc_url: Option<String>,
+    #[serde(default = "default_token_program")]
+    pub token_program: String,
+    #[serde(default = "default_metadata_program")]
+    pub metadata_program: String,
+    #[serde(default = "default_dbc_program")]
+    pub dbc_program: String,
+    /// DBC pool config (curve, fees, quote token) pools are created under; unset, DBC launches
+    /// are refused.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dbc_config: Option<String>,
+    /// Quote mint of `dbc_config`.
+    #[serde(default = "default_quote_mint")]
+    pub dbc_quote_mint: String,
+}
+
+fn default_network() -> String {
+    "devnet".to_string()
+}
+
+fn default_token_program() -> String {
+    SPL_TOKEN_PROGRAM_ID.to_string()
+}
+
+fn default_metadata_program() -> String {
+    TOKEN_METADATA_PROGRAM_ID.to_string()
+}
+
+fn default_dbc_program() -> String {
+    DBC_PROGRAM_ID.to_string()
+}
+
+fn default_quote_mint() -> String {
+    WRAPPED_SOL_MINT.to_string()
+}
+
+impl Default for TokenLaunchConfig {
+    fn default() -> Self {
+        Self {
+            network: default_network(),
+            rpc_url: None,
+            token_program: default_token_program(),
+            metadata_program: default_metadata_program(),
+            dbc_program: default_dbc_program(),
+            dbc_config: None,
+            dbc_quote_mint: default_quote_mint(),
+        }
+    }
+}
+
+impl TokenLaunchConfig {
+    pub fn is_default(&self) -> bool {
+        *self == TokenLaunchConfig::default()
+    }
+
+    fn is_mainnet(&self) -> bool {
+        matches!(self.network.as_str(), "mainnet" | "mainnet-beta")
+    }
+
+    /// Solana Explorer page for an address (`kind` = `address`) or transaction (`tx`).
+    pub fn explorer_url(&self, kind: &str, id: &str) -> String {
+        let url = format!("https://explorer.solana.com/{kind}/{id}");
+        if self.is_mainnet() {
+            url
+        } else {
+            format!("{url}?cluster={}", self.network)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TokenMetadata {
+    pub name: String,
+    pub symbol: String,
+    /// Off-chain JSON (image, description).
+    pub uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TokenCreateRequest {
+    /// Solana CLI keypair file (JSON array of 64 bytes) that pays and becomes mint authority.
+    pub keypair_path: PathBuf,
+    /// Ignored for DBC pools, whose config decides.
+    #[serde(default = "default_decimals")]
+    pub decimals: u8,
+    /// Required for a DBC pool.
+    #[serde(default)]
+    pub metadata: Option<TokenMetadata>,
+    /// Create a Meteora DBC pool (which mints the token) instead of a bare mint.
+    #[serde(default)]
+    pub dbc_pool: bool,
+    /// Must be set to launch on mainnet.
+    #[serde(default)]
+    pub confirm_mainnet: bool,
+}
+
+fn default_decimals() -> u8 {
+    6
+}
+
+/// Accounts and transactions a launch created, base58.
+#[derive(Debug, Clone)]
+pub struct Launched {
+    pub mint: String,
+    pub metadata: Option<String>,
+    pub dbc_pool: Option<String>,
+    pub tx_signatures: Vec<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum LaunchError {
+    /// A bad keypair or metadata, or mainnet without `confirm_mainnet`.
+    #[error("{0}")]
+    Invalid(String),
+    /// The launch needs a setting that isn't there (an RPC URL, `dbc_config`).
+    #[error("{0}")]
+    Unconfigured(String),
+    #[error(transparent)]
+    Chain(#[from] owp_discovery::Error),
+}
+
+/// Create the token described by `req`, paid for by its keypair. Nothing is recorded here.
+pub async fn launch(
+    cfg: &TokenLaunchConfig,
+    rpc_url: &str,
+    proxy: Option<&Proxy>,
+    req: &TokenCreateRequest,
+) -> Result<Launched, LaunchError> {
+    if cfg.is_mainnet() && !req.confirm_mainnet {
+        return Err(LaunchError::Invalid(
+            "launching on mainnet needs confirm_mainnet".to_string(),
+        ));
+    }
+    if let Some(m) = &req.metadata {
+        check_metadata(m)?;
+    }
+    let payer = read_keypair(&req.keypair_path)?;
+    let programs = Programs::parse(cfg)?;
+    let mint = SigningKey::generate(&mut rand::rngs::OsRng);
+
+    let (instructions, metadata, pool) = if req.dbc_pool {
+        let config = cfg
+            .dbc_config
+            .as_deref()
+            .ok_or_else(|| LaunchError::Unconfigured("DBC launches need dbc_config".to_string()))?;
+        let config = parse_pubkey("dbc_config", config)?;
+        let meta = req.metadata.as_ref().ok_or_else(|| {
+            LaunchError::Invalid("a DBC pool needs metadata (name, symbol, uri)".to_string())
+        })?;
+        let (ix, pool) =
+            dbc_pool_instruction(&programs, &config, &pubkey(&payer), &pubkey(&mint), meta);
+        let metadata = programs.metadata_address(&pubkey(&mint));
+        (vec![ix], Some(metadata), Some(pool))
+    } else {
+        let rent =
+            transaction::minimum_balance_for_rent_exemption(rpc_url, proxy, MINT_LEN).await?;
+        let mut ixs = mint_instructions(
+            &programs,
+            &pubkey(&payer),
+            &pubkey(&mint),
+            rent,
+            req.decimals,
+        );
+        let metadata = req.metadata.as_ref().map(|meta| {
+            let (ix, address) =
+                metadata_instruction(&programs, &pubkey(&payer), &pubkey(&mint), meta);
+            ixs.push(ix);
+            address
+        });
+        (ixs, metadata, None)
+    };
+
+    let signature =
+        transaction::send_transaction(rpc_url, proxy, &payer, &[&mint], &instructions).await?;
+    let b58 = |k: Pubkey| bs58::encode(k).into_string();
+    Ok(Launched {
+        mint: b58(pubkey(&mint)),
+        metadata: metadata.map(b58),
+        dbc_pool: pool.map(b58),
+        tx_signatures: vec![signature],
+    })
+}
+
+fn check_metadata(m: &TokenMetadata) -> Result<(), LaunchError> {
+    for (field, value, max) in [
+        ("name", &m.name, MAX_NAME_LEN),
+        ("symbol", &m.symbol, MAX_SYMBOL_LEN),
+        ("uri", &m.uri, MAX_URI_LEN),
+    ] {
+        if value.trim().is_empty() || value.len() > max {
+            return Err(LaunchError::Invalid(format!(
+                "metadata {field} must be 1 to {max} bytes"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// A Solana CLI keypair file: the 32-byte secret followed by the public key.
+fn read_keypair(path: &Path) -> Result<SigningKey, LaunchError> {
+    let invalid = |e: String| LaunchError::Invalid(format!("keypair {path:?}: {e}"));
+    let data = std::fs::read_to_string(path).map_err(|e| invalid(e.to_string()))?;
+    let bytes: Vec<u8> = serde_json::from_str(&data).map_err(|e| invalid(e.to_string()))?;
+    let bytes: [u8; 64] = bytes
+        .try_into()
+        .map_err(|_| invalid("expected 64 bytes".to_string()))?;
+    SigningKey::from_keypair_bytes(&bytes).map_err(|e| invalid(e.to_string()))
+}
+
+struct Programs {
+    token: Pubkey,
+    metadata: Pubkey,
+    dbc: Pubkey,
+    quote_mint: Pubkey,
+}
+
+impl Programs {
+    fn parse(cfg: &TokenLaunchConfig) -> Result<Self, LaunchError> {
+        Ok(Self {
+            token: parse_pubkey("token_program", &cfg.token_program)?,
+            metadata: parse_pubkey("metadata_program", &cfg.metadata_program)?,
+            dbc: parse_pubkey("dbc_program", &cfg.dbc_program)?,
+            quote_mint: parse_pubkey("dbc_quote_mint", &cfg.dbc_quote_mint)?,
+        })
+    }
+
+    fn metadata_address(&self, mint: &Pubkey) -> Pubkey {
+        self.pda(&[b"metadata", &self.metadata, mint], &self.metadata)
+    }
+
+    fn pda(&self, seeds: &[&[u8]], program: &Pubkey) -> Pubkey {
+        // Only fails with probability ~2^-256.
+        find_program_address(seeds, program)
+            .expect("a program address exists")
+            .0
+    }
+}
+
+/// System `CreateAccount` for the mint, then SPL Token `InitializeMint2` with `payer` as mint
+/// authority and no freeze authority.
+fn mint_instructions(
+    p: &Programs,
+    payer: &Pubkey,
+    mint: &Pubkey,
+    rent: u64,
+    decimals: u8,
+) -> Vec<Instruction> {
+    let mut create = 0u32.to_le_bytes().to_vec();
+    create.extend_from_slice(&rent.to_le_bytes());
+    create.extend_from_slice(&(MINT_LEN as u64).to_le_bytes());
+    create.extend_from_slice(&p.token);
+
+    let mut init = vec![20, decimals];
+    init.extend_from_slice(payer);
+    init.push(0);
+
+    vec![
+        Instruction {
+            program_id: SYSTEM_PROGRAM_ID,
+            accounts: vec![
+                AccountMeta::writable(*payer, true),
+                AccountMeta::writable(*mint, true),
+            ],
+            data: create,
+        },
+        Instruction {
+            program_id: p.token,
+            accounts: vec![AccountMeta::writable(*mint, false)],
+            data: init,
+        },
+    ]
+}
+
+/// Metaplex `CreateMetadataAccountV3`, mutable, with `payer` as update authority.
+fn metadata_instruction(
+    p: &Programs,
+    payer: &Pubkey,
+    mint: &Pubkey,
+    meta: &TokenMetadata,
+) -> (Instruction, Pubkey) {
+    let address = p.metadata_address(mint);
+    let mut data = vec![33];
+    borsh_string(&mut data, &meta.name);
+    borsh_string(&mut data, &meta.symbol);
+    borsh_string(&mut data, &meta.uri);
+    // No seller fee, creators, collection or uses; mutable; no collection details.
+    data.extend_from_slice(&[0, 0, 0, 0, 0, 1, 0]);
+    let ix = Instruction {
+        program_id: p.metadata,
+        accounts: vec![
+            AccountMeta::writable(address, false),
+            AccountMeta::readonly(*mint, false),
+            AccountMeta::readonly(*payer, true),
+            AccountMeta::writable(*payer, true),
+            AccountMeta::readonly(*payer, true),
+            AccountMeta::readonly(SYSTEM_PROGRAM_ID, false),
+        ],
+        data,
+    };
+    (ix, address)
+}
+
+/// DBC `initialize_virtual_pool_with_spl_token` under `config`, with `payer` as creator. The
+/// program creates the mint (and its metadata) itself. Accounts follow the program's IDL.
+fn dbc_pool_instruction(
+    p: &Programs,
+    config: &Pubkey,
+    payer: &Pubkey,
+    mint: &Pubkey,
+    meta: &TokenMetadata,
+) -> (Instruction, Pubkey) {
+    let quote = p.quote_mint;
+    let (first, second) = if *mint > quote {
+        (mint, &quote)
+    } else {
+        (&quote, mint)
+    };
+    let pool = p.pda(&[b"pool", config, first, second], &p.dbc);
+    let base_vault = p.pda(&[b"token_vault", mint, &pool], &p.dbc);
+    let quote_vault = p.pda(&[b"token_vault", &quote, &pool], &p.dbc);
+    let authority = p.pda(&[b"pool_authority"], &p.dbc);
+    let events = p.pda(&[b"__event_authority"], &p.dbc);
+
+    let mut data = Sha256::digest(b"global:initialize_virtual_pool_with_spl_token")[..8].to_vec();
+    borsh_string(&mut data, &meta.name);
+    borsh_string(&mut data, &meta.symbol);
+    borsh_string(&mut data, &meta.uri);
+    let ix = Instruction {
+        program_id: p.dbc,
+        accounts: vec![
+            AccountMeta::readonly(*config, false),
+            AccountMeta::readonly(authority, false),
+            AccountMeta::readonly(*payer, true),
+            AccountMeta::writable(*mint, true),
+            AccountMeta::readonly(quote, false),
+            AccountMeta::writable(pool, false),
+            AccountMeta::writable(base_vault, false),
+            AccountMeta::writable(quote_vault, false),
+            AccountMeta::writable(p.metadata_address(mint), false),
+            AccountMeta::readonly(p.metadata, false),
+            AccountMeta::writable(*payer, true),
+            AccountMeta::readonly(p.token, false),
+            AccountMeta::readonly(p.token, false),
+            AccountMeta::readonly(SYSTEM_PROGRAM_ID, false),
+            AccountMeta::readonly(events, false),
+            AccountMeta::readonly(p.dbc, false),
+        ],
+        data,
+    };
+    (ix, pool)
+}
+
+fn borsh_string(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(&(s.len() as u32).to_le_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn launch_instructions_match_the_program_layouts() {
+        let cfg = TokenLaunchConfig::default();
+        let p = Programs::parse(&cfg).expect("programs");
+        let payer = [1u8; 32];
+        let mint = [2u8; 32];
+
+        let ixs = mint_instructions(&p, &payer, &mint, 1_461_600, 6);
+        assert_eq!(ixs[0].data.len(), 4 + 8 + 8 + 32);
+        assert_eq!(&ixs[0].data[4..12], &1_461_600u64.to_le_bytes());
+        assert_eq!(&ixs[0].data[20..], &p.token);
+        assert_eq!(&ixs[1].data[..2], &[20, 6]);
+        assert_eq!(ixs[1].data.len(), 2 + 32 + 1);
+
+        let meta = TokenMetadata {
+            name: "Moonbase".to_string(),
+            symbol: "MOON".to_string(),
+            uri: "https://example.com/moon.json".to_string(),
+        };
+        check_metadata(&meta).expect("valid");
+        let (ix, address) = metadata_instruction(&p, &payer, &mint, &meta);
+        assert_eq!(ix.accounts[0].pubkey, address);
+        assert_eq!(&ix.data[..5], &[33, 8, 0, 0, 0]);
+        assert_eq!(ix.data.len(), 1 + (4 + 8) + (4 + 4) + (4 + 29) + 7);
+
+        let config = [3u8; 32];
+        let (ix, pool) = dbc_pool_instruction(&p, &config, &payer, &mint, &meta);
+        assert_eq!(ix.accounts.len(), 16);
+        assert_eq!(ix.accounts[5].pubkey, pool);
+        assert!(ix.accounts[3].is_signer && ix.accounts[3].is_writable);
+        assert_eq!(
+            &ix.data[..8],
+            &Sha256::digest(b"global:initialize_virtual_pool_with_spl_token")[..8]
+        );
+
+        let long = TokenMetadata {
+            symbol: "TOOLONGSYMBOL".to_string(),
+            ..meta
+        };
+        assert!(matches!(
+            check_metadata(&long),
+            Err(LaunchError::Invalid(_))
+        ));
+        assert_eq!(
+            cfg.explorer_url("tx", "abc"),
+            "https://explorer.solana.com/tx/abc?cluster=devnet"
+        );
+    }
+}
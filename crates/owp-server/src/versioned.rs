@@ -0,0 +1,168 @@
+//! The `{"version": N, "data": ...}` envelope around files the host keeps for a long time: the
+//! assistant config, companion history and the per-profile files. A bare file from before the
+//! envelope is version 0. Reading runs the type's migrations up to the version this build writes;
+//! a file from a newer build is refused, since reading it would drop the fields this build doesn't
+//! know and the next save would lose them for good.
+
+use serde::de::{DeserializeOwned, Error as _};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::path::Path;
+
+use crate::storage::{write_atomic, StorageError};
+
+/// A type stored in an envelope.
+pub trait Versioned: Serialize + DeserializeOwned {
+    /// The version this build writes.
+    const VERSION: u32;
+
+    /// Turn `data` written as version `from` into version `from + 1`. Version 0 is the bare file
+    /// from before the envelope; fields that were only added need no migration, as long as they
+    /// have a serde default.
+    fn migrate(from: u32, data: Value) -> serde_json::Result<Value> {
+        let _ = from;
+        Ok(data)
+    }
+}
+
+#[derive(Serialize)]
+struct Envelope<'a, T> {
+    version: u32,
+    data: &'a T,
+}
+
+#[derive(Deserialize)]
+struct RawEnvelope {
+    version: u32,
+    data: Value,
+}
+
+/// Whether `value` is an envelope rather than a bare version 0 file.
+fn is_envelope(value: &Map<String, Value>) -> bool {
+    value.len() == 2
+        && value.get("version").is_some_and(Value::is_u64)
+        && value.contains_key("data")
+}
+
+pub fn decode<T: Versioned>(text: &str) -> serde_json::Result<T> {
+    let value: Value = serde_json::from_str(text)?;
+    let (version, mut data) = match value {
+        Value::Object(ref map) if is_envelope(map) => {
+            let raw: RawEnvelope = serde_json::from_value(value)?;
+            (raw.version, raw.data)
+        }
+        bare => (0, bare),
+    };
+    if version > T::VERSION {
+        return Err(serde_json::Error::custom(format!(
+            "written by a newer server (version {version}; this one reads up to {})",
+            T::VERSION
+        )));
+    }
+    for from in version..T::VERSION {
+        data = T::migrate(from, data)?;
+    }
+    serde_json::from_value(data)
+}
+
+pub fn encode<T: Versioned>(value: &T) -> serde_json::Result<String> {
+    let json = serde_json::to_string_pretty(&Envelope {
+        version: T::VERSION,
+        data: value,
+    })?;
+    Ok(format!("{json}\n"))
+}
+
+/// Read `path`, or `None` if there is no such file.
+pub fn load<T: Versioned>(path: &Path) -> Result<Option<T>, StorageError> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(StorageError::io(format!("read {path:?}"))(e)),
+    };
+    decode(&text)
+        .map(Some)
+        .map_err(|source| StorageError::Corrupt {
+            path: path.to_path_buf(),
+            source,
+        })
+}
+
+/// Write `value` to `path` in the current version, creating the directory it goes in.
+pub fn save<T: Versioned>(path: &Path, value: &T) -> Result<(), StorageError> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(StorageError::io(format!("create {parent:?}")))?;
+    }
+    let json = encode(value).map_err(|source| StorageError::Corrupt {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    write_atomic(path, json.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Version 1 renamed `colour` to `color`; version 2 added `size`.
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Settings {
+        color: String,
+        #[serde(default)]
+        size: u32,
+    }
+
+    impl Versioned for Settings {
+        const VERSION: u32 = 2;
+
+        fn migrate(from: u32, mut data: Value) -> serde_json::Result<Value> {
+            if from == 0 {
+                if let Some(map) = data.as_object_mut() {
+                    if let Some(colour) = map.remove("colour") {
+                        map.insert("color".to_string(), colour);
+                    }
+                }
+            }
+            Ok(data)
+        }
+    }
+
+    #[test]
+    fn old_files_migrate_and_newer_ones_are_refused() {
+        let bare: Settings = decode(r#"{ "colour": "red" }"#).expect("bare file");
+        assert_eq!(
+            bare,
+            Settings {
+                color: "red".to_string(),
+                size: 0
+            }
+        );
+        let v1: Settings = decode(r#"{ "version": 1, "data": { "color": "blue" } }"#).expect("v1");
+        assert_eq!(v1.color, "blue");
+
+        let saved = Settings {
+            color: "green".to_string(),
+            size: 3,
+        };
+        let text = encode(&saved).expect("encode");
+        assert!(text.contains("\"version\": 2"));
+        assert_eq!(decode::<Settings>(&text).expect("round trip"), saved);
+
+        let newer = r#"{ "version": 3, "data": { "color": "red", "shape": "round" } }"#;
+        let err = decode::<Settings>(newer).expect_err("newer version");
+        assert!(err.to_string().contains("newer server"), "{err}");
+    }
+
+    #[test]
+    fn missing_files_load_as_none() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("nested/settings.json");
+        assert!(load::<Settings>(&path).expect("load").is_none());
+        let saved = Settings {
+            color: "red".to_string(),
+            size: 1,
+        };
+        save(&path, &saved).expect("save");
+        assert_eq!(load::<Settings>(&path).expect("load"), Some(saved));
+    }
+}
@@ -0,0 +1,597 @@
+use anyhow::{Context, Result};
+use owp_protocol::{ChunkChangeV1, ChunkCoord};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::SystemTime;
+use tracing::{info, warn};
+
+use crate::chunks;
+use crate::ledger;
+use crate::migration;
+use crate::storage::write_atomic;
+
+/// Serializes WAL access within this process and remembers where each world's log ends, so
+/// appends don't rescan it. Other processes are kept out by a lock on `wal/lock`.
+static WAL_LOCK: Mutex<BTreeMap<PathBuf, LogEnd>> = Mutex::new(BTreeMap::new());
+
+/// The end of a world's log as this process last left it. Stale once the file's length or
+/// modification time differs, i.e. another process wrote it since.
+#[derive(Debug, Clone, Copy)]
+struct LogEnd {
+    /// Byte length of the valid prefix of the log.
+    len: u64,
+    modified: Option<SystemTime>,
+    /// Sequence number of the last record in the log, 0 if it has none.
+    last_seq: u64,
+}
+
+/// Exclusive access to one world's log, in this process and across processes, until dropped.
+struct WalGuard {
+    _file: File,
+    ends: MutexGuard<'static, BTreeMap<PathBuf, LogEnd>>,
+}
+
+fn lock(world_dir: &Path) -> Result<WalGuard> {
+    let ends = WAL_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = wal_dir(world_dir);
+    fs::create_dir_all(&dir).with_context(|| format!("create {dir:?}"))?;
+    let path = dir.join("lock");
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("open {path:?}"))?;
+    file.lock().with_context(|| format!("lock {path:?}"))?;
+    Ok(WalGuard { _file: file, ends })
+}
+
+impl WalGuard {
+    /// Where the log ends, scanning it only if it changed since this process last wrote it.
+    fn log_end(&mut self, world_dir: &Path) -> Result<LogEnd> {
+        let meta = fs::metadata(wal_path(world_dir)).ok();
+        if let (Some(meta), Some(end)) = (&meta, self.ends.get(world_dir)) {
+            if meta.len() == end.len && meta.modified().ok() == end.modified {
+                return Ok(*end);
+            }
+        }
+        let scan = scan(world_dir)?;
+        Ok(LogEnd {
+            len: scan.valid_len,
+            modified: meta.and_then(|m| m.modified().ok()),
+            last_seq: scan.records.last().map_or(0, |r| r.seq),
+        })
+    }
+
+    /// Durably append `records` after `end`, dropping any torn tail past it first.
+    fn append(&mut self, world_dir: &Path, end: LogEnd, records: &[&WalRecord]) -> Result<()> {
+        let Some(last) = records.last() else {
+            return Ok(());
+        };
+        let mut encoded = String::new();
+        for rec in records {
+            encoded.push_str(&encode_record(rec.seq, &rec.op)?);
+        }
+        let path = wal_path(world_dir);
+        let mut f = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("open {path:?}"))?;
+        if f.metadata().map(|m| m.len()).ok() != Some(end.len) {
+            // Drop a torn tail before appending so the new record stays readable.
+            f.set_len(end.len).context("truncate damaged wal tail")?;
+        }
+        f.write_all(encoded.as_bytes()).context("append wal")?;
+        f.sync_data().context("fsync wal")?;
+        let meta = f.metadata().with_context(|| format!("stat {path:?}"))?;
+        self.ends.insert(
+            world_dir.to_path_buf(),
+            LogEnd {
+                len: meta.len(),
+                modified: meta.modified().ok(),
+                last_seq: last.seq,
+            },
+        );
+        Ok(())
+    }
+
+    /// Last sequence number in the log or, after a checkpoint, the checkpoint.
+    fn last_seq(&self, world_dir: &Path, end: &LogEnd) -> Result<u64> {
+        Ok(end.last_seq.max(load_checkpoint(world_dir)?.last_seq))
+    }
+
+    /// Forget the cached end after the log was rewritten.
+    fn forget(&mut self, world_dir: &Path) {
+        self.ends.remove(world_dir);
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum WalOp {
+    ChunkChange {
+        chunk: ChunkCoord,
+        change: ChunkChangeV1,
+    },
+    Inventory {
+        profile_id: String,
+        item: String,
+        delta: i64,
+    },
+    Currency {
+        profile_id: String,
+        delta: i64,
+    },
+    /// Drop a profile's balance and inventory (profile erasure).
+    EraseAccount {
+        profile_id: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WalRecord {
+    pub seq: u64,
+    pub op: WalOp,
+}
+
+#[derive(Debug, Default)]
+pub struct WalScan {
+    pub records: Vec<WalRecord>,
+    /// Byte length of the valid prefix of the log.
+    pub valid_len: u64,
+    /// True when trailing bytes failed to parse or checksum (torn write).
+    pub truncated: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CheckpointV1 {
+    last_seq: u64,
+}
+
+pub fn wal_dir(world_dir: &Path) -> PathBuf {
+    world_dir.join("wal")
+}
+
+pub fn wal_path(world_dir: &Path) -> PathBuf {
+    wal_dir(world_dir).join("world.wal")
+}
+
+fn checkpoint_path(world_dir: &Path) -> PathBuf {
+    wal_dir(world_dir).join("checkpoint.json")
+}
+
+fn record_crc(seq: u64, json: &str) -> u32 {
+    let mut h = crc32fast::Hasher::new();
+    h.update(seq.to_string().as_bytes());
+    h.update(b"\t");
+    h.update(json.as_bytes());
+    h.finalize()
+}
+
+fn encode_record(seq: u64, op: &WalOp) -> Result<String> {
+    let json = serde_json::to_string(op).context("serialize wal op")?;
+    Ok(format!("{seq}\t{:08x}\t{json}\n", record_crc(seq, &json)))
+}
+
+fn decode_line(line: &str) -> Option<WalRecord> {
+    let mut it = line.splitn(3, '\t');
+    let seq: u64 = it.next()?.parse().ok()?;
+    let crc = u32::from_str_radix(it.next()?, 16).ok()?;
+    let json = it.next()?;
+    if record_crc(seq, json) != crc {
+        return None;
+    }
+    let op: WalOp = serde_json::from_str(json).ok()?;
+    Some(WalRecord { seq, op })
+}
+
+/// Read the log, stopping at the first damaged record.
+pub fn scan(world_dir: &Path) -> Result<WalScan> {
+    let path = wal_path(world_dir);
+    if !path.exists() {
+        return Ok(WalScan::default());
+    }
+    let data = fs::read(&path).with_context(|| format!("read {path:?}"))?;
+    let mut out = WalScan::default();
+    let mut offset = 0usize;
+    while offset < data.len() {
+        let Some(nl) = data[offset..].iter().position(|b| *b == b'\n') else {
+            out.truncated = true;
+            break;
+        };
+        let line = std::str::from_utf8(&data[offset..offset + nl]).ok();
+        match line.and_then(decode_line) {
+            Some(rec) => out.records.push(rec),
+            None => {
+                out.truncated = true;
+                break;
+            }
+        }
+        offset += nl + 1;
+    }
+    out.valid_len = offset as u64;
+    Ok(out)
+}
+
+fn load_checkpoint(world_dir: &Path) -> Result<CheckpointV1> {
+    let path = checkpoint_path(world_dir);
+    if !path.exists() {
+        return Ok(CheckpointV1::default());
+    }
+    let data = fs::read_to_string(&path).with_context(|| format!("read {path:?}"))?;
+    serde_json::from_str(&data).with_context(|| format!("parse {path:?}"))
+}
+
+fn sync_file(path: &Path) -> Result<()> {
+    if path.exists() {
+        File::open(path)
+            .and_then(|f| f.sync_all())
+            .with_context(|| format!("fsync {path:?}"))?;
+    }
+    Ok(())
+}
+
+fn sync_dir(path: &Path) {
+    // Not supported on every platform (e.g. Windows); best effort.
+    if let Ok(f) = File::open(path) {
+        let _ = f.sync_all();
+    }
+}
+
+/// Validate `ops` in order, each against a copy of the ledger the ones before it were applied
+/// to, so rejected mutations never reach the log.
+fn precheck(world_dir: &Path, ops: &[WalOp]) -> Result<()> {
+    let mut ledger = None;
+    for op in ops {
+        if let WalOp::ChunkChange { .. } = op {
+            continue;
+        }
+        let l = match &mut ledger {
+            Some(l) => l,
+            slot => slot.insert(ledger::load_ledger(world_dir)?),
+        };
+        match op {
+            WalOp::ChunkChange { .. } => {}
+            WalOp::Inventory {
+                profile_id,
+                item,
+                delta,
+            } => {
+                l.check_inventory(profile_id, item, *delta)?;
+                l.apply_inventory(profile_id, item, *delta);
+            }
+            WalOp::Currency { profile_id, delta } => {
+                l.check_currency(profile_id, *delta)?;
+                l.apply_currency(profile_id, *delta);
+            }
+            WalOp::EraseAccount { profile_id } => {
+                l.accounts.remove(profile_id);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Apply one record to its target file unless the target already reflects it.
+/// Returns the new chunk version for chunk ops.
+fn apply_record(world_dir: &Path, rec: &WalRecord) -> Result<Option<u64>> {
+    match &rec.op {
+        WalOp::ChunkChange { chunk, change } => {
+            let mut file = chunks::load_chunk(world_dir, *chunk)?;
+            if file.applied_seq >= rec.seq {
+                return Ok(Some(file.version));
+            }
+            let version = file.apply(change.clone());
+            file.applied_seq = rec.seq;
+            chunks::save_chunk(world_dir, &file)?;
+            Ok(Some(version))
+        }
+        WalOp::Inventory {
+            profile_id,
+            item,
+            delta,
+        } => {
+            let mut l = ledger::load_ledger(world_dir)?;
+            if l.applied_seq < rec.seq {
+                l.apply_inventory(profile_id, item, *delta);
+                l.applied_seq = rec.seq;
+                ledger::save_ledger(world_dir, &l)?;
+            }
+            Ok(None)
+        }
+        WalOp::Currency { profile_id, delta } => {
+            let mut l = ledger::load_ledger(world_dir)?;
+            if l.applied_seq < rec.seq {
+                l.apply_currency(profile_id, *delta);
+                l.applied_seq = rec.seq;
+                ledger::save_ledger(world_dir, &l)?;
+            }
+            Ok(None)
+        }
+        WalOp::EraseAccount { profile_id } => {
+            let mut l = ledger::load_ledger(world_dir)?;
+            if l.applied_seq < rec.seq {
+                l.accounts.remove(profile_id);
+                l.applied_seq = rec.seq;
+                ledger::save_ledger(world_dir, &l)?;
+            }
+            Ok(None)
+        }
+    }
+}
+
+fn target_path(world_dir: &Path, op: &WalOp) -> PathBuf {
+    match op {
+        WalOp::ChunkChange { chunk, .. } => chunks::chunk_path(world_dir, *chunk),
+        WalOp::Inventory { .. } | WalOp::Currency { .. } | WalOp::EraseAccount { .. } => {
+            ledger::ledger_path(world_dir)
+        }
+    }
+}
+
+/// Durably log `op`, then apply it. Returns the new chunk version for chunk ops.
+pub fn mutate(world_dir: &Path, op: WalOp) -> Result<Option<u64>> {
+    Ok(mutate_batch(world_dir, vec![op])?.pop().flatten())
+}
+
+/// Durably log `ops` with a single fsync, then apply them in order. Every op is prechecked
+/// first, against the ledger as the ops before it leave it, so a rejected one keeps the whole
+/// batch out of the log; so does a migration freeze. Returns one result per op, as `mutate` does.
+pub fn mutate_batch(world_dir: &Path, ops: Vec<WalOp>) -> Result<Vec<Option<u64>>> {
+    let mut guard = lock(world_dir)?;
+    migration::check_writable(world_dir)?;
+    precheck(world_dir, &ops)?;
+
+    let end = guard.log_end(world_dir)?;
+    let last = guard.last_seq(world_dir, &end)?;
+    let records: Vec<WalRecord> = ops
+        .into_iter()
+        .zip(last + 1..)
+        .map(|(op, seq)| WalRecord { seq, op })
+        .collect();
+    guard.append(world_dir, end, &records.iter().collect::<Vec<_>>())?;
+
+    records
+        .iter()
+        .map(|rec| apply_record(world_dir, rec))
+        .collect()
+}
+
+fn checkpoint_locked(guard: &mut WalGuard, world_dir: &Path, scan: &WalScan) -> Result<usize> {
+    let mut targets = BTreeSet::new();
+    for rec in &scan.records {
+        targets.insert(target_path(world_dir, &rec.op));
+    }
+    for t in &targets {
+        sync_file(t)?;
+    }
+    sync_dir(&world_dir.join("chunks"));
+    sync_dir(&world_dir.join("snapshots"));
+
+    let prev = load_checkpoint(world_dir)?.last_seq;
+    let last_seq = scan.records.last().map(|r| r.seq).unwrap_or(0).max(prev);
+    let dir = wal_dir(world_dir);
+    fs::create_dir_all(&dir).with_context(|| format!("create {dir:?}"))?;
+    let json =
+        serde_json::to_string_pretty(&CheckpointV1 { last_seq }).context("serialize checkpoint")?;
+    let cp = checkpoint_path(world_dir);
+    write_atomic(&cp, format!("{json}\n").as_bytes())?;
+    sync_file(&cp)?;
+
+    let path = wal_path(world_dir);
+    if path.exists() {
+        let f = OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("open {path:?}"))?;
+        f.set_len(0).context("truncate wal")?;
+        f.sync_all().context("fsync wal")?;
+    }
+    sync_dir(&dir);
+    guard.forget(world_dir);
+    Ok(scan.records.len())
+}
+
+/// Flush everything the log covers into the target files and truncate it.
+pub fn checkpoint(world_dir: &Path) -> Result<usize> {
+    let mut guard = lock(world_dir)?;
+    let scan = scan(world_dir)?;
+    if scan.records.is_empty() && !scan.truncated {
+        return Ok(0);
+    }
+    checkpoint_locked(&mut guard, world_dir, &scan)
+}
+
+/// What a follower at `since` needs: the latest sequence number, the records after `since`,
+/// and, when the log no longer covers `since` (or it is `None`), `snapshot(last_seq)` taken
+/// while no mutation can land.
+pub fn tail<T>(
+    world_dir: &Path,
+    since: Option<u64>,
+    snapshot: impl FnOnce(u64) -> Result<T>,
+) -> Result<(u64, Vec<WalRecord>, Option<T>)> {
+    let _guard = lock(world_dir)?;
+    let scan = scan(world_dir)?;
+    let checkpointed = load_checkpoint(world_dir)?.last_seq;
+    let last = scan.records.last().map_or(0, |r| r.seq).max(checkpointed);
+    match since {
+        Some(since) if since >= checkpointed && since <= last => {
+            let records = scan.records.into_iter().filter(|r| r.seq > since).collect();
+            Ok((last, records, None))
+        }
+        _ => Ok((last, vec![], Some(snapshot(last)?))),
+    }
+}
+
+/// Log and apply records replicated from a leader, keeping their sequence numbers. Records
+/// this log already has are skipped.
+pub fn append_replicated(world_dir: &Path, records: &[WalRecord]) -> Result<u64> {
+    let mut guard = lock(world_dir)?;
+    let end = guard.log_end(world_dir)?;
+    let mut last = guard.last_seq(world_dir, &end)?;
+    let fresh: Vec<&WalRecord> = records.iter().filter(|r| r.seq > last).collect();
+    if fresh.is_empty() {
+        return Ok(last);
+    }
+    guard.append(world_dir, end, &fresh)?;
+    for rec in fresh {
+        apply_record(world_dir, rec).with_context(|| format!("apply wal record {}", rec.seq))?;
+        last = rec.seq;
+    }
+    Ok(last)
+}
+
+/// Start the log over at `last_seq` after the target files were replaced wholesale (a
+/// follower installing a snapshot).
+pub fn reset(world_dir: &Path, last_seq: u64) -> Result<()> {
+    let mut guard = lock(world_dir)?;
+    let json =
+        serde_json::to_string_pretty(&CheckpointV1 { last_seq }).context("serialize checkpoint")?;
+    write_atomic(&checkpoint_path(world_dir), format!("{json}\n").as_bytes())?;
+    let path = wal_path(world_dir);
+    if path.exists() {
+        fs::write(&path, b"").with_context(|| format!("truncate {path:?}"))?;
+    }
+    guard.forget(world_dir);
+    Ok(())
+}
+
+/// Replay the log after an unclean shutdown, then checkpoint. Safe to run on every startup.
+pub fn recover(world_dir: &Path) -> Result<usize> {
+    let mut guard = lock(world_dir)?;
+    let scan = scan(world_dir)?;
+    if scan.truncated {
+        warn!(
+            "wal for {world_dir:?} has a damaged tail; discarding bytes after offset {}",
+            scan.valid_len
+        );
+    }
+    if scan.records.is_empty() && !scan.truncated {
+        return Ok(0);
+    }
+    for rec in &scan.records {
+        apply_record(world_dir, rec).with_context(|| format!("replay wal record {}", rec.seq))?;
+    }
+    let n = checkpoint_locked(&mut guard, world_dir, &scan)?;
+    if n > 0 {
+        info!("replayed {n} wal record(s) for {world_dir:?}");
+    }
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay_is_idempotent_and_survives_torn_tail() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let world = dir.path();
+        mutate(
+            world,
+            WalOp::Currency {
+                profile_id: "p".to_string(),
+                delta: 10,
+            },
+        )
+        .expect("credit");
+        mutate(
+            world,
+            WalOp::Currency {
+                profile_id: "p".to_string(),
+                delta: -4,
+            },
+        )
+        .expect("debit");
+        assert!(mutate(
+            world,
+            WalOp::Currency {
+                profile_id: "p".to_string(),
+                delta: -100,
+            },
+        )
+        .is_err());
+
+        // Simulate a crash mid-append.
+        let mut f = OpenOptions::new()
+            .append(true)
+            .open(wal_path(world))
+            .expect("open wal");
+        f.write_all(b"3\tdeadbeef\t{\"op\":\"curr").expect("torn");
+
+        assert_eq!(scan(world).expect("scan").records.len(), 2);
+        assert_eq!(recover(world).expect("recover"), 2);
+        assert_eq!(recover(world).expect("recover again"), 0);
+        let l = ledger::load_ledger(world).expect("ledger");
+        assert_eq!(l.accounts["p"].balance, 6);
+
+        // Sequence numbers keep increasing across checkpoints.
+        mutate(
+            world,
+            WalOp::Currency {
+                profile_id: "p".to_string(),
+                delta: 1,
+            },
+        )
+        .expect("credit");
+        assert_eq!(scan(world).expect("scan").records[0].seq, 3);
+    }
+
+    #[test]
+    fn batches_are_checked_as_a_whole() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let world = dir.path();
+        let currency = |delta| WalOp::Currency {
+            profile_id: "p".to_string(),
+            delta,
+        };
+        mutate(world, currency(10)).expect("credit");
+
+        // Each debit fits the balance alone, but not both.
+        let err = mutate_batch(world, vec![currency(-6), currency(-6)]).expect_err("overdraw");
+        assert!(err.is::<ledger::Insufficient>());
+        assert_eq!(scan(world).expect("scan").records.len(), 1);
+
+        // A credit earlier in the batch pays for a later debit.
+        mutate_batch(world, vec![currency(5), currency(-15)]).expect("credit then debit");
+        assert_eq!(
+            ledger::load_ledger(world).expect("ledger").accounts["p"].balance,
+            0
+        );
+    }
+
+    #[test]
+    fn appends_notice_writes_from_other_processes() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let world = dir.path();
+        let credit = || WalOp::Currency {
+            profile_id: "p".to_string(),
+            delta: 1,
+        };
+        mutate(world, credit()).expect("credit");
+
+        // Another process appends behind this one's cached view of the log, then tears a write.
+        let mut f = OpenOptions::new()
+            .append(true)
+            .open(wal_path(world))
+            .expect("open wal");
+        let theirs = encode_record(2, &credit()).expect("encode");
+        f.write_all(theirs.as_bytes()).expect("append");
+        f.write_all(b"3\tdeadbeef\t{").expect("torn");
+
+        mutate(world, credit()).expect("credit");
+        let seqs: Vec<u64> = scan(world)
+            .expect("scan")
+            .records
+            .iter()
+            .map(|r| r.seq)
+            .collect();
+        assert_eq!(seqs, [1, 2, 3]);
+        assert!(!scan(world).expect("scan").truncated);
+    }
+}
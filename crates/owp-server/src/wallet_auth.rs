@@ -0,0 +1,240 @@
+use anyhow::{Context, Result};
+use owp_discovery::wallet_auth::{self, MAX_CLOCK_SKEW_SECS, MAX_NONCE_LEN};
+use owp_protocol::WalletProof;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::storage::write_atomic;
+
+/// Nonces remembered at once per world; proofs beyond that are refused until older ones expire.
+const MAX_NONCES: usize = 100_000;
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Why a `wallet_proof` was refused; the label in `owp_auth_failures_total`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthFailure {
+    /// No `wallet_pubkey`, or an oversized nonce.
+    Malformed,
+    BadSignature,
+    /// `timestamp` too far from our clock.
+    ClockSkew,
+    /// Nonce already used.
+    Replay,
+    /// Too many live nonces to remember another.
+    Overloaded,
+}
+
+impl AuthFailure {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Malformed => "malformed",
+            Self::BadSignature => "bad_signature",
+            Self::ClockSkew => "clock_skew",
+            Self::Replay => "replay",
+            Self::Overloaded => "overloaded",
+        }
+    }
+}
+
+/// Single-use nonces of accepted proofs, each kept until its timestamp leaves the skew window
+/// (after which the proof is refused as stale anyway).
+#[derive(Clone, Default)]
+pub struct NonceCache {
+    /// (wallet, nonce) -> unix second it can be forgotten.
+    seen: Arc<Mutex<HashMap<(String, String), i64>>>,
+}
+
+impl NonceCache {
+    /// Check `proof` for `wallet_pubkey` on `world_id` at `now` (unix seconds), consuming its
+    /// nonce.
+    pub fn check(
+        &self,
+        world_id: Uuid,
+        wallet_pubkey: Option<&str>,
+        proof: &WalletProof,
+        now: i64,
+    ) -> Result<(), AuthFailure> {
+        let wallet = wallet_pubkey.ok_or(AuthFailure::Malformed)?;
+        if proof.nonce.len() > MAX_NONCE_LEN {
+            return Err(AuthFailure::Malformed);
+        }
+        if proof.timestamp.abs_diff(now) > MAX_CLOCK_SKEW_SECS as u64 {
+            return Err(AuthFailure::ClockSkew);
+        }
+        if !wallet_auth::signature_valid(wallet, world_id, proof) {
+            return Err(AuthFailure::BadSignature);
+        }
+        let mut seen = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+        let key = (wallet.to_string(), proof.nonce.clone());
+        if seen.contains_key(&key) {
+            return Err(AuthFailure::Replay);
+        }
+        if seen.len() >= MAX_NONCES {
+            seen.retain(|_, expires| *expires > now);
+            if seen.len() >= MAX_NONCES {
+                return Err(AuthFailure::Overloaded);
+            }
+        }
+        seen.insert(key, proof.timestamp + MAX_CLOCK_SKEW_SECS);
+        Ok(())
+    }
+}
+
+/// Wallet proof outcomes of one world.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuthStatsV1 {
+    #[serde(default)]
+    pub verified: u64,
+    /// Refused proofs by `AuthFailure::label`.
+    #[serde(default)]
+    pub failures: BTreeMap<String, u64>,
+}
+
+pub fn auth_stats_path(world_dir: &Path) -> PathBuf {
+    world_dir.join("logs").join("auth.json")
+}
+
+pub fn load(world_dir: &Path) -> Result<AuthStatsV1> {
+    let path = auth_stats_path(world_dir);
+    if !path.exists() {
+        return Ok(AuthStatsV1::default());
+    }
+    let data = fs::read_to_string(&path).with_context(|| format!("read {path:?}"))?;
+    serde_json::from_str(&data).with_context(|| format!("parse {path:?}"))
+}
+
+fn save(world_dir: &Path, s: &AuthStatsV1) -> Result<()> {
+    let path = auth_stats_path(world_dir);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("create {parent:?}"))?;
+    }
+    let json = serde_json::to_string_pretty(s).context("serialize auth stats")?;
+    write_atomic(&path, format!("{json}\n").as_bytes())?;
+    Ok(())
+}
+
+/// Live counters shared by a game server's connections, flushed like `NetQuality`.
+#[derive(Clone, Default)]
+pub struct AuthStats {
+    inner: Arc<Mutex<AuthStatsV1>>,
+    dirty: Arc<AtomicBool>,
+}
+
+impl AuthStats {
+    pub fn load(world_dir: &Path) -> Self {
+        let s = load(world_dir).unwrap_or_else(|e| {
+            warn!("auth stats reset: {e:#}");
+            AuthStatsV1::default()
+        });
+        Self {
+            inner: Arc::new(Mutex::new(s)),
+            dirty: Arc::default(),
+        }
+    }
+
+    pub fn record(&self, outcome: Result<(), AuthFailure>) {
+        let mut s = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        match outcome {
+            Ok(()) => s.verified += 1,
+            Err(f) => *s.failures.entry(f.label().to_string()).or_default() += 1,
+        }
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    pub fn spawn_flush(&self, world_dir: PathBuf) {
+        let this = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+            loop {
+                interval.tick().await;
+                if this.dirty.swap(false, Ordering::Relaxed) {
+                    let snapshot = this.inner.lock().unwrap_or_else(|e| e.into_inner()).clone();
+                    if let Err(e) = save(&world_dir, &snapshot) {
+                        warn!("writing auth stats failed: {e:#}");
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// Prometheus text exposition, appended to `GET /metrics`.
+pub fn render_metrics(worlds: &[(Uuid, AuthStatsV1)]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "# HELP owp_auth_verified_total Wallet proofs accepted."
+    );
+    let _ = writeln!(out, "# TYPE owp_auth_verified_total counter");
+    for (world_id, s) in worlds {
+        let _ = writeln!(
+            out,
+            "owp_auth_verified_total{{world_id=\"{world_id}\"}} {}",
+            s.verified
+        );
+    }
+    let _ = writeln!(
+        out,
+        "# HELP owp_auth_failures_total Wallet proofs refused, by reason."
+    );
+    let _ = writeln!(out, "# TYPE owp_auth_failures_total counter");
+    for (world_id, s) in worlds {
+        for (reason, n) in &s.failures {
+            let _ = writeln!(
+                out,
+                "owp_auth_failures_total{{world_id=\"{world_id}\",reason=\"{reason}\"}} {n}"
+            );
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::SigningKey;
+
+    #[test]
+    fn proofs_are_single_use_and_fresh() {
+        let key = SigningKey::from_bytes(&[5u8; 32]);
+        let wallet = bs58::encode(key.verifying_key().as_bytes()).into_string();
+        let world_id = Uuid::new_v4();
+        let cache = NonceCache::default();
+        let stats = AuthStats::default();
+
+        let proof = wallet_auth::sign(&key, world_id, 10_000);
+        let check = |proof: &WalletProof, now| {
+            let r = cache.check(world_id, Some(&wallet), proof, now);
+            stats.record(r);
+            r
+        };
+        // Within the skew window either way.
+        assert_eq!(check(&proof, 10_000 - MAX_CLOCK_SKEW_SECS), Ok(()));
+        assert_eq!(check(&proof, 10_001), Err(AuthFailure::Replay));
+        let old = wallet_auth::sign(&key, world_id, 10_000 - MAX_CLOCK_SKEW_SECS - 1);
+        assert_eq!(check(&old, 10_000), Err(AuthFailure::ClockSkew));
+        let mut forged = wallet_auth::sign(&key, world_id, 10_000);
+        forged.nonce = "someone-else".to_string();
+        assert_eq!(check(&forged, 10_000), Err(AuthFailure::BadSignature));
+        assert_eq!(
+            cache.check(world_id, None, &proof, 10_000),
+            Err(AuthFailure::Malformed)
+        );
+
+        let s = stats.inner.lock().expect("stats").clone();
+        assert_eq!(s.verified, 1);
+        let text = render_metrics(&[(Uuid::nil(), s)]);
+        assert!(text.contains(
+            "owp_auth_failures_total{world_id=\"00000000-0000-0000-0000-000000000000\",reason=\"replay\"} 1"
+        ));
+    }
+}
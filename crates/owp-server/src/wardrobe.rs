@@ -0,0 +1,209 @@
+use anyhow::{Context, Result};
+use owp_protocol::avatar::validate_avatar;
+use owp_protocol::AvatarSpecV1;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::avatar as avatar_mod;
+use crate::avatar_history::RevisionSource;
+use crate::avatar_mesh as avatar_mesh_mod;
+use crate::content;
+use crate::storage::WorldStore;
+use crate::versioned::{self, Versioned};
+
+/// Serializes read-modify-write of wardrobe files within this process.
+static WARDROBE_LOCK: Mutex<()> = Mutex::new(());
+
+pub const MAX_OUTFITS: usize = 32;
+pub const MAX_OUTFIT_NAME_CHARS: usize = 32;
+
+/// Named avatars of one profile. Meshes are pinned in the content store, so switching never
+/// regenerates them and a later generation can't overwrite them.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WardrobeV1 {
+    /// Outfit currently worn, if it came from the wardrobe.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active: Option<String>,
+    #[serde(default)]
+    pub outfits: BTreeMap<String, AvatarSpecV1>,
+}
+
+impl Versioned for WardrobeV1 {
+    const VERSION: u32 = 1;
+}
+
+pub fn wardrobe_path(store: &WorldStore, profile_id: &str) -> PathBuf {
+    store.profiles_root().join(profile_id).join("wardrobe.json")
+}
+
+/// Outfit names are short slugs like "wizard" or "mech_2".
+pub fn check_name(name: &str) -> Result<()> {
+    let ok = !name.is_empty()
+        && name.chars().count() <= MAX_OUTFIT_NAME_CHARS
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-');
+    if !ok {
+        anyhow::bail!(
+            "outfit names are 1-{MAX_OUTFIT_NAME_CHARS} characters of a-z, 0-9, '_' and '-'"
+        );
+    }
+    Ok(())
+}
+
+pub fn load(store: &WorldStore, profile_id: &str) -> Result<WardrobeV1> {
+    let path = wardrobe_path(store, profile_id);
+    Ok(versioned::load(&path)?.unwrap_or_default())
+}
+
+fn save(store: &WorldStore, profile_id: &str, w: &WardrobeV1) -> Result<()> {
+    let path = wardrobe_path(store, profile_id);
+    versioned::save(&path, w)?;
+    Ok(())
+}
+
+/// Copy mesh files that still live in the profile's generated-mesh directory into the content
+/// store and point the spec at them.
+pub fn pin_meshes(store: &WorldStore, profile_id: &str, avatar: &mut AvatarSpecV1) -> Result<()> {
+    let Some(mesh) = avatar.mesh.as_mut() else {
+        return Ok(());
+    };
+    let mut refs = vec![(None, &mut mesh.uri, &mut mesh.sha256)];
+    for p in mesh.parts.iter_mut() {
+        let part = (p.id != "body").then(|| p.id.clone());
+        refs.push((part, &mut p.uri, &mut p.sha256));
+    }
+    for (part, uri, sha256) in refs {
+        if uri.starts_with("/content/") {
+            continue;
+        }
+        if !uri.starts_with("/avatar/mesh") {
+            // Hosted elsewhere; nothing to pin.
+            continue;
+        }
+        let bytes = avatar_mesh_mod::read_mesh_bytes(store, profile_id, part.as_deref())?;
+        let hash = content::hash(&bytes);
+        if sha256
+            .as_deref()
+            .is_some_and(|h| !h.eq_ignore_ascii_case(&hash))
+        {
+            anyhow::bail!("mesh at {uri} changed since the avatar was saved");
+        }
+        content::put(store, &bytes)?;
+        *uri = content::content_uri(&hash);
+        *sha256 = Some(hash);
+    }
+    Ok(())
+}
+
+/// Store `avatar` (or the profile's current avatar) under `name`, replacing an outfit of the
+/// same name.
+pub fn put_outfit(
+    store: &WorldStore,
+    profile_id: &str,
+    name: &str,
+    avatar: Option<&AvatarSpecV1>,
+) -> Result<AvatarSpecV1> {
+    check_name(name)?;
+    let mut avatar = match avatar {
+        Some(a) => a.clone(),
+        None => avatar_mod::load_avatar(store, profile_id)?.context("profile has no avatar")?,
+    };
+    pin_meshes(store, profile_id, &mut avatar)?;
+    let avatar = validate_avatar(&avatar).context("avatar rejected")?;
+
+    let _guard = WARDROBE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut w = load(store, profile_id)?;
+    if !w.outfits.contains_key(name) && w.outfits.len() >= MAX_OUTFITS {
+        anyhow::bail!("wardrobe is full ({MAX_OUTFITS} outfits)");
+    }
+    w.outfits.insert(name.to_string(), avatar.clone());
+    save(store, profile_id, &w)?;
+    Ok(avatar)
+}
+
+pub fn remove_outfit(store: &WorldStore, profile_id: &str, name: &str) -> Result<bool> {
+    let _guard = WARDROBE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut w = load(store, profile_id)?;
+    if w.outfits.remove(name).is_none() {
+        return Ok(false);
+    }
+    if w.active.as_deref() == Some(name) {
+        w.active = None;
+    }
+    save(store, profile_id, &w)?;
+    Ok(true)
+}
+
+/// Make an outfit the profile's avatar. `None` if there is no outfit by that name.
+pub fn switch(store: &WorldStore, profile_id: &str, name: &str) -> Result<Option<AvatarSpecV1>> {
+    let _guard = WARDROBE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut w = load(store, profile_id)?;
+    let Some(outfit) = w.outfits.get(name) else {
+        return Ok(None);
+    };
+    let avatar = avatar_mod::save_avatar(store, profile_id, outfit, RevisionSource::Wardrobe)?;
+    w.active = Some(name.to_string());
+    save(store, profile_id, &w)?;
+    Ok(Some(avatar))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use owp_protocol::AvatarMeshV1;
+
+    #[test]
+    fn outfits_keep_their_meshes_across_switches() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let store = WorldStore::with_root(root.path().to_path_buf()).expect("store");
+        let stl = avatar_mesh_mod::avatar_mesh_stl_path(&store, "local");
+        std::fs::create_dir_all(stl.parent().expect("parent")).expect("mkdir");
+        std::fs::write(&stl, b"wizard mesh").expect("write");
+        let wizard = AvatarSpecV1 {
+            version: "v1".to_string(),
+            name: "Wizard".to_string(),
+            primary_color: "#3300AA".to_string(),
+            secondary_color: "#FFFFFF".to_string(),
+            height: 1.0,
+            tags: vec![],
+            parts: vec![],
+            mesh: Some(AvatarMeshV1 {
+                format: "stl".to_string(),
+                uri: "/avatar/mesh?profile_id=local".to_string(),
+                sha256: Some(content::hash(b"wizard mesh")),
+                parts: vec![],
+            }),
+        };
+        avatar_mod::save_avatar(&store, "local", &wizard, RevisionSource::Upload).expect("save");
+        put_outfit(&store, "local", "wizard", None).expect("put");
+
+        // A new generation overwrites the mesh file; the outfit keeps its own copy.
+        std::fs::write(&stl, b"mech mesh").expect("write");
+        let mech = AvatarSpecV1 {
+            name: "Mech".to_string(),
+            mesh: None,
+            ..wizard.clone()
+        };
+        put_outfit(&store, "local", "mech", Some(&mech)).expect("put");
+
+        let worn = switch(&store, "local", "wizard")
+            .expect("switch")
+            .expect("outfit");
+        let mesh = worn.mesh.expect("mesh");
+        assert!(mesh.uri.starts_with("/content/"));
+        let bytes = content::get(&store, mesh.sha256.as_deref().expect("hash")).expect("get");
+        assert_eq!(bytes.as_deref(), Some(&b"wizard mesh"[..]));
+        assert_eq!(
+            load(&store, "local").expect("load").active.as_deref(),
+            Some("wizard")
+        );
+
+        assert!(switch(&store, "local", "knight").expect("switch").is_none());
+        assert!(check_name("Wizard Hat").is_err());
+        assert!(remove_outfit(&store, "local", "wizard").expect("remove"));
+        assert!(load(&store, "local").expect("load").active.is_none());
+    }
+}
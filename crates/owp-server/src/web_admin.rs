@@ -1,23 +1,77 @@
 use anyhow::{Context, Result};
 use axum::{
-    extract::{Path, State},
+    extract::{DefaultBodyLimit, Path, State},
     http::{HeaderMap, StatusCode},
+    response::sse::{Event as SseEvent, KeepAlive, Sse},
     response::IntoResponse,
-    routing::{get, post},
+    routing::{delete, get, post, put},
     Json, Router,
 };
-use owp_discovery;
-use owp_protocol::{AvatarSpecV1, WorldDirectoryEntry, WorldManifestV1};
+use owp_discovery::favorites::{self, favorites_path};
+use owp_discovery::proxy::Proxy;
+use owp_discovery::{alias, directory, probe, transaction};
+use owp_protocol::{
+    AssetRef, AvatarMeshBlob, AvatarSpecV1, ChunkChangeV1, ChunkCoord, Disconnect,
+    DisconnectReason, Message, PrefabBundleRef, PrefabBundleV1, SyncManifestV1, WorldAssetsConfig,
+    WorldBandwidthConfig, WorldDirectoryEntry, WorldEmotesConfig, WorldEntryFee, WorldManifestV1,
+    WorldRules, WorldSimulationConfig,
+};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::net::SocketAddr;
+use tokio_stream::StreamExt as _;
 use tower_http::cors::{Any, CorsLayer};
-use tracing::{error, info};
+use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
-use crate::assistant::{self, AssistantProviderId};
+use crate::access::{self, AccessFilter};
+use crate::accounts::{self, Sessions};
+use crate::asset_token::AssetTokenKey;
+use crate::assets::{self, AssetIndex};
+use crate::assistant::{self, AssistantError, AssistantProviderId};
 use crate::avatar as avatar_mod;
+use crate::avatar_history::{self, RevisionSource};
+use crate::avatar_import;
 use crate::avatar_mesh as avatar_mesh_mod;
-use crate::storage::WorldStore;
+use crate::backup;
+use crate::bandwidth::{self, CapState};
+use crate::chunks;
+use crate::config::{ConfigStatus, LiveConfig};
+use crate::content;
+use crate::crash::{self, CrashReportV1, CrashSummary};
+use crate::entry_fee::{self, Receipt};
+use crate::friends;
+use crate::fsck;
+use crate::generation_cache;
+use crate::grpc;
+use crate::health::{HealthRegistry, ServiceHealth, ServiceState};
+use crate::ledger;
+use crate::logging;
+use crate::migration;
+use crate::minimap;
+use crate::net_quality;
+use crate::pairing::{self, Pairing, RedeemError, Scope};
+use crate::prefabs;
+use crate::presence::Roster;
+use crate::privacy::{self, PrivacyPatch, PrivacyV1};
+use crate::profile_data;
+use crate::provider_health;
+use crate::qr::{self, QrCode, QrFormat};
+use crate::quota::{self, Quotas};
+use crate::replica;
+use crate::reputation;
+use crate::rules::{self, RulesAckRecord};
+use crate::scatter;
+use crate::sim;
+use crate::storage::{connect_string, directory_entry, StorageError, WorldStore};
+use crate::tap::{self, FileTapStatus, TapFilter};
+use crate::telemetry::{self, TelemetryReport};
+use crate::token_launch::{self, LaunchError, TokenCreateRequest};
+use crate::wal;
+use crate::wallet_auth;
+use crate::wardrobe;
+use crate::world_chat::{self, WorldChatResponse, WorldChatTurn};
+use crate::world_summary::{self, SummaryCache};
 
 #[derive(Clone)]
 pub enum AuthMode {
@@ -30,33 +84,156 @@ struct AppState {
     store: WorldStore,
     auth: AuthMode,
     discovery: DiscoveryConfig,
+    health: HealthRegistry,
+    config: LiveConfig,
+    roster: Roster,
+    summary: SummaryCache,
+    pairing: Pairing,
+    sessions: Sessions,
+    quotas: Quotas,
+    asset_key: AssetTokenKey,
+}
+
+fn bearer(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(axum::http::header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+/// The admin token, a paired API key of any scope, or an account session.
+fn require_auth(headers: &HeaderMap, st: &AppState) -> Result<(), StatusCode> {
+    require_scope(headers, st, Scope::Client)
+}
+
+/// As `require_auth`, but not a session of a non-admin account: it acts for one person, and
+/// this changes worlds everyone on the host shares.
+fn require_world_edit(headers: &HeaderMap, st: &AppState) -> Result<(), StatusCode> {
+    if bearer(headers)
+        .and_then(|t| st.sessions.lookup(t))
+        .is_some_and(|s| !s.admin)
+    {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    require_auth(headers, st)
 }
 
-fn require_auth(headers: &HeaderMap, auth: &AuthMode) -> Result<(), StatusCode> {
-    match auth {
+/// The admin token, or a paired API key with the `admin` scope.
+fn require_admin(headers: &HeaderMap, st: &AppState) -> Result<(), StatusCode> {
+    require_scope(headers, st, Scope::Admin)
+}
+
+fn require_scope(headers: &HeaderMap, st: &AppState, needed: Scope) -> Result<(), StatusCode> {
+    match &st.auth {
         AuthMode::Disabled => Ok(()),
         AuthMode::BearerToken(expected) => {
-            let Some(value) = headers.get(axum::http::header::AUTHORIZATION) else {
-                return Err(StatusCode::UNAUTHORIZED);
-            };
-            let Ok(value) = value.to_str() else {
+            let Some(token) = bearer(headers) else {
                 return Err(StatusCode::UNAUTHORIZED);
             };
-            let Some(token) = value.strip_prefix("Bearer ") else {
-                return Err(StatusCode::UNAUTHORIZED);
-            };
-            if token != expected {
-                return Err(StatusCode::FORBIDDEN);
+            if token == expected {
+                return Ok(());
+            }
+            let scope =
+                st.pairing.scope_of(token).or_else(|| {
+                    st.sessions.lookup(token).map(|s| {
+                        if s.admin {
+                            Scope::Admin
+                        } else {
+                            Scope::Client
+                        }
+                    })
+                });
+            match scope {
+                Some(Scope::Admin) => Ok(()),
+                Some(Scope::Client) if needed == Scope::Client => Ok(()),
+                _ => Err(StatusCode::FORBIDDEN),
             }
-            Ok(())
         }
     }
 }
 
+/// Count an assistant job against the world `profile_id` is playing in, if it's in one here.
+fn charge_assistant_job(st: &AppState, profile_id: &str) -> Result<(), StatusCode> {
+    let Some((world_id, _)) = st.roster.find(profile_id).into_iter().next() else {
+        return Ok(());
+    };
+    st.quotas
+        .world(world_id, &st.config)
+        .assistant_job()
+        .map_err(|e| {
+            warn!("assistant job for {profile_id} refused: {e}");
+            StatusCode::TOO_MANY_REQUESTS
+        })
+}
+
+/// Refuse an edit when the world is over its storage quota.
+fn check_storage(
+    st: &AppState,
+    world_id: Uuid,
+    world_dir: &std::path::Path,
+) -> Result<(), StatusCode> {
+    st.quotas
+        .world(world_id, &st.config)
+        .check_storage(world_dir)
+        .map_err(|_| StatusCode::INSUFFICIENT_STORAGE)
+}
+
+/// Profile a request acts on. A logged-in account gets its own and may only name another if it
+/// is an admin account; everyone else gets `local`, and only admin scope may name another.
+fn profile_for(
+    headers: &HeaderMap,
+    st: &AppState,
+    requested: Option<&str>,
+) -> Result<String, StatusCode> {
+    match bearer(headers).and_then(|t| st.sessions.lookup(t)) {
+        Some(session) => match requested {
+            Some(p) if p != session.profile_id && !session.admin => Err(StatusCode::FORBIDDEN),
+            Some(p) => Ok(p.to_string()),
+            None => Ok(session.profile_id),
+        },
+        None => match requested {
+            Some(p) if p != "local" => require_admin(headers, st).map(|()| p.to_string()),
+            _ => Ok("local".to_string()),
+        },
+    }
+}
+
+/// Profile whose avatar a request reads. Besides the caller's own (see `profile_for`), anyone
+/// may read a profile that shares its avatar; admins may read any.
+fn avatar_profile_for(
+    headers: &HeaderMap,
+    st: &AppState,
+    requested: Option<&str>,
+) -> Result<String, StatusCode> {
+    let own = bearer(headers)
+        .and_then(|t| st.sessions.lookup(t))
+        .map_or_else(|| "local".to_string(), |s| s.profile_id);
+    let Some(requested) = requested.filter(|p| *p != own) else {
+        return Ok(own);
+    };
+    if require_admin(headers, st).is_ok() {
+        return Ok(requested.to_string());
+    }
+    match privacy::load(&st.store, requested) {
+        Ok(p) if p.share_avatar => Ok(requested.to_string()),
+        Ok(_) => Err(StatusCode::FORBIDDEN),
+        Err(_) => Err(StatusCode::BAD_REQUEST),
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct DiscoveryConfig {
     pub solana_rpc_url: Option<String>,
     pub registry_program_id: Option<String>,
+    /// Static directory files (http(s) URLs or paths), see `owp-server export-directory`.
+    pub directory_urls: Vec<String>,
+    /// Base58 ed25519 publisher keys whose directory files count as verified.
+    pub trusted_directory_keys: Vec<String>,
+    /// Handshake with every listed world on `/discovery/worlds` and record the result.
+    pub probe_liveness: bool,
+    /// Tunnels discovery requests and probes.
+    pub proxy: Option<Proxy>,
 }
 
 #[derive(Debug, Serialize)]
@@ -72,28 +249,235 @@ async fn health() -> Json<HealthResponse> {
     })
 }
 
+#[derive(Debug, Serialize)]
+struct ServicesHealthResponse {
+    ok: bool,
+    services: BTreeMap<String, ServiceHealth>,
+}
+
+/// Aggregated health of every sub-service in this process. `503` unless all are running.
+async fn services_health(State(st): State<AppState>) -> (StatusCode, Json<ServicesHealthResponse>) {
+    let ok = st.health.all_running();
+    let status = if ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (
+        status,
+        Json(ServicesHealthResponse {
+            ok,
+            services: st.health.snapshot(),
+        }),
+    )
+}
+
+/// Prometheus text format. Game servers flush their connection quality aggregate every few
+/// seconds, so values can lag slightly.
+async fn metrics(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+) -> Result<axum::response::Response, StatusCode> {
+    require_auth(&headers, &st)?;
+    let manifests = st.store.list_worlds().map_err(|e| {
+        error!("list worlds failed: {e:#}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let mut worlds = Vec::new();
+    let mut auth = Vec::new();
+    let mut traffic = Vec::new();
+    for m in manifests {
+        let dir = st.store.world_dir(m.world_id);
+        match bandwidth::usage(&dir, &m.bandwidth) {
+            Ok(u) if u.total_bytes > 0 => traffic.push((m.world_id, u)),
+            Ok(_) => {}
+            Err(e) => error!("bandwidth of {} skipped: {e:#}", m.world_id),
+        }
+        match net_quality::load(&dir) {
+            Ok(q) if q.reports > 0 => worlds.push((m.world_id, q)),
+            Ok(_) => {}
+            Err(e) => error!("net quality of {} skipped: {e:#}", m.world_id),
+        }
+        match wallet_auth::load(&dir) {
+            Ok(s) if s.verified > 0 || !s.failures.is_empty() => auth.push((m.world_id, s)),
+            Ok(_) => {}
+            Err(e) => error!("auth stats of {} skipped: {e:#}", m.world_id),
+        }
+    }
+    Ok((
+        StatusCode::OK,
+        [(
+            axum::http::header::CONTENT_TYPE,
+            "text/plain; version=0.0.4",
+        )],
+        net_quality::render_metrics(&worlds)
+            + &wallet_auth::render_metrics(&auth)
+            + &bandwidth::render_metrics(&traffic)
+            + &quota::render_metrics(&st.quotas.usage()),
+    )
+        .into_response())
+}
+
+#[derive(Debug, Serialize)]
+struct ConfigResponse {
+    server: ConfigStatus,
+    /// Read from disk on every request, so it is always current.
+    assistant: assistant::AssistantConfig,
+}
+
+async fn get_config(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<ConfigResponse>, StatusCode> {
+    require_admin(&headers, &st)?;
+    let assistant =
+        assistant::load_config(&st.store).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(ConfigResponse {
+        server: st.config.status(),
+        assistant,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct LogLevelRequest {
+    filter: String,
+    /// Revert to the previous filter after this many seconds.
+    #[serde(default)]
+    ttl_secs: Option<u64>,
+}
+
+async fn get_log_level(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<logging::LogLevelStatus>, StatusCode> {
+    require_admin(&headers, &st)?;
+    logging::status()
+        .map(Json)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+async fn set_log_level(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<LogLevelRequest>,
+) -> Result<Json<logging::LogLevelStatus>, (StatusCode, String)> {
+    require_admin(&headers, &st).map_err(|s| (s, String::new()))?;
+    let ttl = req
+        .ttl_secs
+        .filter(|s| *s > 0)
+        .map(std::time::Duration::from_secs);
+    logging::set_filter(&req.filter, ttl)
+        .map(Json)
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("{e:#}")))
+}
+
+/// The latest log lines kept in memory, e.g. `?level=warn&module=tcp_game`.
+async fn get_recent_logs(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Query(q): axum::extract::Query<logging::RecentQuery>,
+) -> Result<Json<Vec<logging::LogLine>>, (StatusCode, String)> {
+    require_admin(&headers, &st).map_err(|s| (s, String::new()))?;
+    logging::recent(&q)
+        .map(Json)
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("{e:#}")))
+}
+
+/// Session messages as game servers in this process handle them, as server-sent events, e.g.
+/// `?world_id=...&types=chat_send,chat_broadcast&redact_chat=true`.
+async fn tap_messages(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Query(filter): axum::extract::Query<TapFilter>,
+) -> Result<impl IntoResponse, StatusCode> {
+    require_admin(&headers, &st)?;
+    let events =
+        tap::subscribe(filter).map(|event| SseEvent::default().event("message").json_data(event));
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+#[derive(Debug, Deserialize)]
+struct TapFileRequest {
+    #[serde(flatten)]
+    filter: TapFilter,
+    /// Stop after this many seconds (default 600).
+    #[serde(default)]
+    ttl_secs: Option<u64>,
+}
+
+async fn get_tap_file(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Option<FileTapStatus>>, StatusCode> {
+    require_admin(&headers, &st)?;
+    Ok(Json(tap::file_status()))
+}
+
+/// Start tapping into a new file under `logs/tap/`, replacing the running file tap if any.
+async fn start_tap_file(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<TapFileRequest>,
+) -> Result<Json<FileTapStatus>, (StatusCode, String)> {
+    require_admin(&headers, &st).map_err(|s| (s, String::new()))?;
+    let ttl = req
+        .ttl_secs
+        .filter(|s| *s > 0)
+        .map(std::time::Duration::from_secs);
+    tap::start_file(st.store.root_dir(), req.filter, ttl)
+        .map(Json)
+        .map_err(|e| {
+            error!("starting a file tap failed: {e:#}");
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("{e:#}"))
+        })
+}
+
+async fn stop_tap_file(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Option<FileTapStatus>>, StatusCode> {
+    require_admin(&headers, &st)?;
+    Ok(Json(tap::stop_file()))
+}
+
+async fn get_global_access(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<AccessFilter>, StatusCode> {
+    require_admin(&headers, &st)?;
+    Ok(Json(st.config.current().access))
+}
+
+/// Replace the accept filter in `server.json`; game servers apply it once they reload the file.
+async fn set_global_access(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Json(mut filter): Json<AccessFilter>,
+) -> Result<Json<AccessFilter>, (StatusCode, String)> {
+    require_admin(&headers, &st).map_err(|s| (s, String::new()))?;
+    filter
+        .normalize()
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("{e:#}")))?;
+    let cfg = st.config.update(|cfg| cfg.access = filter).map_err(|e| {
+        error!("updating server config failed: {e:#}");
+        (StatusCode::INTERNAL_SERVER_ERROR, String::new())
+    })?;
+    Ok(Json(cfg.access))
+}
+
 async fn list_worlds(
     State(st): State<AppState>,
     headers: HeaderMap,
 ) -> Result<Json<Vec<WorldDirectoryEntry>>, StatusCode> {
-    require_auth(&headers, &st.auth)?;
+    require_auth(&headers, &st)?;
 
     let manifests = st
         .store
         .list_worlds()
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
     let out = manifests
-        .into_iter()
-        .map(|m| WorldDirectoryEntry {
-            world_id: m.world_id,
-            name: m.name,
-            endpoint: "127.0.0.1".to_string(),
-            port: m.ports.game_port,
-            token_mint: m.token.as_ref().map(|t| t.mint.clone()),
-            dbc_pool: m.token.as_ref().and_then(|t| t.dbc_pool.clone()),
-            world_pubkey: m.world_authority_pubkey.clone(),
-            last_seen: None,
-        })
+        .iter()
+        .map(|m| directory_entry(m, "127.0.0.1"))
         .collect();
     Ok(Json(out))
 }
@@ -114,11 +498,12 @@ async fn create_world(
     headers: HeaderMap,
     Json(req): Json<CreateWorldRequest>,
 ) -> Result<Json<WorldManifestV1>, StatusCode> {
-    require_auth(&headers, &st.auth)?;
+    require_world_edit(&headers, &st)?;
     let manifest = st
         .store
         .create_world(&req.name, req.game_port)
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    telemetry::record(&st.store, telemetry::Event::WorldCreated);
     Ok(Json(manifest))
 }
 
@@ -127,7 +512,7 @@ async fn get_manifest(
     headers: HeaderMap,
     Path(world_id): Path<String>,
 ) -> Result<Json<WorldManifestV1>, StatusCode> {
-    require_auth(&headers, &st.auth)?;
+    require_auth(&headers, &st)?;
     let world_id = Uuid::parse_str(&world_id).map_err(|_| StatusCode::BAD_REQUEST)?;
     let dir = st.store.world_dir(world_id);
     if !dir.exists() {
@@ -156,7 +541,7 @@ async fn publish_result(
     Path(world_id): Path<String>,
     Json(req): Json<PublishResultRequest>,
 ) -> Result<Json<WorldManifestV1>, StatusCode> {
-    require_auth(&headers, &st.auth)?;
+    require_admin(&headers, &st)?;
     let world_id = Uuid::parse_str(&world_id).map_err(|_| StatusCode::BAD_REQUEST)?;
     let manifest = st
         .store
@@ -167,383 +552,3489 @@ async fn publish_result(
             req.dbc_pool,
             req.tx_signatures,
         )
-        .map_err(|e| {
-            if e.to_string().contains("not found") {
-                StatusCode::NOT_FOUND
-            } else {
+        .map_err(|e| match e {
+            StorageError::NotFound(_) => StatusCode::NOT_FOUND,
+            e => {
+                error!("publishing token info for {world_id} failed: {e:#}");
                 StatusCode::INTERNAL_SERVER_ERROR
             }
         })?;
     Ok(Json(manifest))
 }
 
-async fn assistant_status(
+#[derive(Debug, Serialize)]
+struct ExplorerLinks {
+    mint: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dbc_pool: Option<String>,
+    transactions: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct TokenCreateResponse {
+    manifest: WorldManifestV1,
+    explorer: ExplorerLinks,
+}
+
+/// Create the world's token on chain with a local keypair (see `token_launch.rs`) and record it
+/// as `publish-result` would. A world that already has a token is refused with 409.
+async fn create_token(
     State(st): State<AppState>,
     headers: HeaderMap,
-) -> Result<Json<assistant::AssistantStatus>, StatusCode> {
-    require_auth(&headers, &st.auth)?;
-    let status = assistant::status(&st.store)
+    Path(world_id): Path<String>,
+    Json(req): Json<TokenCreateRequest>,
+) -> Result<Json<TokenCreateResponse>, (StatusCode, String)> {
+    // The host's keypair signs and pays, so only the host may launch.
+    require_admin(&headers, &st).map_err(|s| (s, String::new()))?;
+    let world_id =
+        Uuid::parse_str(&world_id).map_err(|_| (StatusCode::BAD_REQUEST, String::new()))?;
+    let dir = st.store.world_dir(world_id);
+    if !dir.exists() {
+        return Err((StatusCode::NOT_FOUND, String::new()));
+    }
+    let manifest = st.store.read_manifest(&dir).map_err(|e| {
+        error!("reading manifest for {world_id} failed: {e:#}");
+        (StatusCode::INTERNAL_SERVER_ERROR, String::new())
+    })?;
+    if let Some(token) = manifest.token {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("world already has token {}", token.mint),
+        ));
+    }
+    let cfg = st.config.current().token_launch;
+    let Some(rpc_url) = cfg
+        .rpc_url
+        .clone()
+        .or_else(|| st.discovery.solana_rpc_url.clone())
+    else {
+        return Err((
+            StatusCode::PRECONDITION_FAILED,
+            "no Solana RPC configured (token_launch.rpc_url or --solana-rpc-url)".to_string(),
+        ));
+    };
+
+    let launched = token_launch::launch(&cfg, &rpc_url, st.discovery.proxy.as_ref(), &req)
         .await
+        .map_err(|e| {
+            let status = match &e {
+                LaunchError::Invalid(_) => StatusCode::BAD_REQUEST,
+                LaunchError::Unconfigured(_) => StatusCode::PRECONDITION_FAILED,
+                LaunchError::Chain(e) => discovery_status(e),
+            };
+            warn!("token launch for {world_id} failed: {e:#}");
+            (status, e.to_string())
+        })?;
+    info!("created token {} for world {world_id}", launched.mint);
+    let manifest = st
+        .store
+        .set_token_info(
+            world_id,
+            cfg.network.clone(),
+            launched.mint.clone(),
+            launched.dbc_pool.clone(),
+            launched.tx_signatures.clone(),
+        )
+        .map_err(|e| {
+            // The token exists on chain; say which so it can be attached by hand.
+            error!(
+                "recording token {} for {world_id} failed: {e:#}",
+                launched.mint
+            );
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("token {} was created but not recorded", launched.mint),
+            )
+        })?;
+    let address = |a: &str| cfg.explorer_url("address", a);
+    Ok(Json(TokenCreateResponse {
+        manifest,
+        explorer: ExplorerLinks {
+            mint: address(&launched.mint),
+            metadata: launched.metadata.as_deref().map(address),
+            dbc_pool: launched.dbc_pool.as_deref().map(address),
+            transactions: launched
+                .tx_signatures
+                .iter()
+                .map(|s| cfg.explorer_url("tx", s))
+                .collect(),
+        },
+    }))
+}
+
+async fn get_chunk(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path((world_id, x, z)): Path<(String, i32, i32)>,
+) -> Result<Json<chunks::ChunkFileV1>, StatusCode> {
+    require_auth(&headers, &st)?;
+    let world_id = Uuid::parse_str(&world_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let dir = st.store.world_dir(world_id);
+    if !dir.exists() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let file = chunks::load_chunk(&dir, ChunkCoord { x, z })
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    Ok(Json(status))
+    Ok(Json(file))
 }
 
 #[derive(Debug, Serialize)]
-struct AssistantConfigResponse {
-    #[serde(skip_serializing_if = "Option::is_none")]
-    provider: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    codex_model: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    codex_reasoning_effort: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    claude_model: Option<String>,
-    avatar_mesh_enabled: bool,
+struct ChunkChangeResponse {
+    chunk: ChunkCoord,
+    version: u64,
 }
 
-async fn get_assistant_config(
+async fn apply_chunk_change(
     State(st): State<AppState>,
     headers: HeaderMap,
-) -> Result<Json<AssistantConfigResponse>, StatusCode> {
-    require_auth(&headers, &st.auth)?;
-    let cfg = assistant::load_config(&st.store).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    Ok(Json(AssistantConfigResponse {
-        provider: cfg.provider.map(|p| p.as_str().to_string()),
-        codex_model: cfg.codex_model,
-        codex_reasoning_effort: cfg.codex_reasoning_effort,
-        claude_model: cfg.claude_model,
-        avatar_mesh_enabled: cfg.avatar_mesh_enabled,
-    }))
+    Path((world_id, x, z)): Path<(String, i32, i32)>,
+    Json(change): Json<ChunkChangeV1>,
+) -> Result<Json<ChunkChangeResponse>, StatusCode> {
+    require_world_edit(&headers, &st)?;
+    let world_id = Uuid::parse_str(&world_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let dir = st.store.world_dir(world_id);
+    if !dir.exists() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    check_storage(&st, world_id, &dir)?;
+    let chunk = ChunkCoord { x, z };
+    if let ChunkChangeV1::Upsert { ref object } = change {
+        if chunks::chunk_for_position(object.position) != chunk {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    }
+    let version = wal::mutate(&dir, wal::WalOp::ChunkChange { chunk, change })
+        .map_err(|e| {
+            if e.is::<migration::Frozen>() {
+                return StatusCode::LOCKED;
+            }
+            error!("chunk change failed: {e:#}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .unwrap_or_default();
+    Ok(Json(ChunkChangeResponse { chunk, version }))
 }
 
 #[derive(Debug, Deserialize)]
-struct SetAssistantConfigRequest {
+struct MinimapQuery {
     #[serde(default)]
-    provider: Option<String>,
+    x: Option<i32>,
     #[serde(default)]
-    codex_model: Option<String>,
+    z: Option<i32>,
     #[serde(default)]
-    codex_reasoning_effort: Option<String>,
+    radius: Option<i32>,
     #[serde(default)]
-    claude_model: Option<String>,
+    cell_m: Option<u32>,
+    /// Include this profile's explored chunks; a session's own profile by default.
     #[serde(default)]
-    avatar_mesh_enabled: Option<bool>,
+    profile_id: Option<String>,
 }
 
-fn normalize_optional_string(v: Option<String>) -> Option<String> {
-    v.and_then(|s| {
-        let t = s.trim().to_string();
-        if t.is_empty() || t == "default" {
-            None
-        } else {
-            Some(t)
+/// Map grid around a chunk (see `minimap.rs`). Honors `If-None-Match`, so clients can poll it
+/// and only download when objects or exploration changed.
+async fn get_minimap(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(world_id): Path<String>,
+    axum::extract::Query(q): axum::extract::Query<MinimapQuery>,
+) -> Result<axum::response::Response, (StatusCode, String)> {
+    use axum::http::header;
+
+    require_auth(&headers, &st).map_err(|s| (s, String::new()))?;
+    let world_id =
+        Uuid::parse_str(&world_id).map_err(|_| (StatusCode::BAD_REQUEST, String::new()))?;
+    let d = minimap::MinimapArea::default();
+    let area = minimap::MinimapArea {
+        x: q.x.unwrap_or(d.x),
+        z: q.z.unwrap_or(d.z),
+        radius: q.radius.unwrap_or(d.radius),
+        cell_m: q.cell_m.unwrap_or(d.cell_m),
+    };
+    minimap::check_area(&area).map_err(|e| (StatusCode::BAD_REQUEST, format!("{e:#}")))?;
+    let session = bearer(&headers).and_then(|t| st.sessions.lookup(t));
+    let profile_id = match (&q.profile_id, session) {
+        (None, None) => None,
+        (requested, _) => {
+            Some(profile_for(&headers, &st, requested.as_deref()).map_err(|s| (s, String::new()))?)
         }
-    })
+    };
+    if let Some(p) = &profile_id {
+        friends::check_profile_id(p).map_err(|e| (StatusCode::BAD_REQUEST, format!("{e:#}")))?;
+    }
+    let dir = st.store.world_dir(world_id);
+    if !dir.exists() {
+        return Err((StatusCode::NOT_FOUND, String::new()));
+    }
+    let map = minimap::render(&dir, &area, profile_id.as_deref()).map_err(|e| {
+        error!("minimap of {world_id} failed: {e:#}");
+        (StatusCode::INTERNAL_SERVER_ERROR, String::new())
+    })?;
+    let body =
+        serde_json::to_vec(&map).map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, String::new()))?;
+    let etag = format!("\"{}\"", &content::hash(&body)[..32]);
+    let not_modified = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| {
+            v.split(',')
+                .map(|t| t.trim().trim_start_matches("W/"))
+                .any(|t| t == etag || t == "*")
+        });
+    if not_modified {
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+    }
+    Ok((
+        StatusCode::OK,
+        [
+            (header::ETAG, etag),
+            (header::CONTENT_TYPE, "application/json".to_string()),
+        ],
+        body,
+    )
+        .into_response())
 }
 
-async fn set_assistant_config(
+/// Fill a region with a seeded prefab mix (see `scatter.rs`).
+async fn scatter_objects(
     State(st): State<AppState>,
     headers: HeaderMap,
-    Json(req): Json<SetAssistantConfigRequest>,
-) -> Result<Json<AssistantConfigResponse>, StatusCode> {
-    require_auth(&headers, &st.auth)?;
-
-    let mut cfg =
-        assistant::load_config(&st.store).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Path(world_id): Path<String>,
+    Json(req): Json<scatter::ScatterRequest>,
+) -> Result<Json<scatter::ScatterResult>, (StatusCode, String)> {
+    require_world_edit(&headers, &st).map_err(|s| (s, String::new()))?;
+    let world_id =
+        Uuid::parse_str(&world_id).map_err(|_| (StatusCode::BAD_REQUEST, String::new()))?;
+    scatter::validate(&req).map_err(|e| (StatusCode::BAD_REQUEST, format!("{e:#}")))?;
+    let dir = st.store.world_dir(world_id);
+    if !dir.exists() {
+        return Err((StatusCode::NOT_FOUND, String::new()));
+    }
+    check_storage(&st, world_id, &dir).map_err(|s| (s, String::new()))?;
+    let result = scatter::scatter(&dir, &req).map_err(|e| {
+        if e.is::<migration::Frozen>() {
+            return (StatusCode::LOCKED, e.to_string());
+        }
+        error!("scatter in {world_id} failed: {e:#}");
+        (StatusCode::INTERNAL_SERVER_ERROR, String::new())
+    })?;
+    Ok(Json(result))
+}
 
-    if let Some(p) = req.provider {
-        cfg.provider = match p.as_str() {
-            "" => None,
-            "codex" => Some(AssistantProviderId::Codex),
-            "claude" => Some(AssistantProviderId::Claude),
-            _ => return Err(StatusCode::BAD_REQUEST),
-        };
+/// The world's current prefab bundle.
+async fn get_prefab_bundle(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(world_id): Path<String>,
+) -> Result<Json<PrefabBundleV1>, StatusCode> {
+    require_auth(&headers, &st)?;
+    let world_id = Uuid::parse_str(&world_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let dir = st.store.world_dir(world_id);
+    if !dir.exists() {
+        return Err(StatusCode::NOT_FOUND);
     }
+    let bundle = prefabs::current(&st.store, &dir).map_err(|e| {
+        error!("loading prefab bundle for {world_id} failed: {e:#}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    bundle.map(Json).ok_or(StatusCode::NOT_FOUND)
+}
 
-    if req.codex_model.is_some() {
-        cfg.codex_model = normalize_optional_string(req.codex_model);
+/// Repack `assets/prefabs/` (see `prefabs.rs`); new connections get the result in `Welcome`.
+async fn build_prefab_bundle(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(world_id): Path<String>,
+) -> Result<Json<PrefabBundleRef>, (StatusCode, String)> {
+    require_admin(&headers, &st).map_err(|s| (s, String::new()))?;
+    let world_id =
+        Uuid::parse_str(&world_id).map_err(|_| (StatusCode::BAD_REQUEST, String::new()))?;
+    let dir = st.store.world_dir(world_id);
+    if !dir.exists() {
+        return Err((StatusCode::NOT_FOUND, String::new()));
     }
-    if req.codex_reasoning_effort.is_some() {
-        let v = normalize_optional_string(req.codex_reasoning_effort);
-        if let Some(ref e) = v {
-            match e.as_str() {
-                "low" | "medium" | "high" | "very_high" | "xhigh" => {}
-                _ => return Err(StatusCode::BAD_REQUEST),
-            }
+    let r =
+        prefabs::build(&st.store, &dir).map_err(|e| (StatusCode::BAD_REQUEST, format!("{e:#}")))?;
+    Ok(Json(r))
+}
+
+async fn get_ledger(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(world_id): Path<String>,
+) -> Result<Json<ledger::LedgerV1>, StatusCode> {
+    require_auth(&headers, &st)?;
+    let world_id = Uuid::parse_str(&world_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let dir = st.store.world_dir(world_id);
+    if !dir.exists() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let l = ledger::load_ledger(&dir).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(l))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum LedgerChangeRequest {
+    Inventory {
+        profile_id: String,
+        item: String,
+        delta: i64,
+    },
+    Currency {
+        profile_id: String,
+        delta: i64,
+    },
+}
+
+async fn apply_ledger_change(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(world_id): Path<String>,
+    Json(req): Json<LedgerChangeRequest>,
+) -> Result<Json<ledger::LedgerV1>, StatusCode> {
+    require_world_edit(&headers, &st)?;
+    let world_id = Uuid::parse_str(&world_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let dir = st.store.world_dir(world_id);
+    if !dir.exists() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let op = match req {
+        LedgerChangeRequest::Inventory {
+            profile_id,
+            item,
+            delta,
+        } => wal::WalOp::Inventory {
+            profile_id,
+            item,
+            delta,
+        },
+        LedgerChangeRequest::Currency { profile_id, delta } => {
+            wal::WalOp::Currency { profile_id, delta }
         }
-        cfg.codex_reasoning_effort = v.map(|e| {
-            if e == "very_high" {
-                "xhigh".to_string()
-            } else {
-                e
+    };
+    wal::mutate(&dir, op).map_err(|e| {
+        if e.is::<ledger::Insufficient>() {
+            StatusCode::CONFLICT
+        } else if e.is::<migration::Frozen>() {
+            StatusCode::LOCKED
+        } else {
+            error!("ledger change failed: {e:#}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    })?;
+    let l = ledger::load_ledger(&dir).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(l))
+}
+
+async fn get_sim_state(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(world_id): Path<String>,
+) -> Result<Json<sim::SimStateV1>, StatusCode> {
+    require_auth(&headers, &st)?;
+    let world_id = Uuid::parse_str(&world_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let dir = st.store.world_dir(world_id);
+    if !dir.exists() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let state = sim::load_state(&dir).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(state))
+}
+
+async fn set_simulation_config(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(world_id): Path<String>,
+    Json(cfg): Json<WorldSimulationConfig>,
+) -> Result<Json<WorldManifestV1>, StatusCode> {
+    require_admin(&headers, &st)?;
+    let world_id = Uuid::parse_str(&world_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let dir = st.store.world_dir(world_id);
+    if !dir.exists() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let mut manifest = st
+        .store
+        .read_manifest(&dir)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    manifest.simulation = cfg;
+    st.store
+        .write_manifest(&dir, &manifest)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(manifest))
+}
+
+async fn set_assets_config(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(world_id): Path<String>,
+    Json(cfg): Json<WorldAssetsConfig>,
+) -> Result<Json<WorldManifestV1>, StatusCode> {
+    require_admin(&headers, &st)?;
+    let world_id = Uuid::parse_str(&world_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let dir = st.store.world_dir(world_id);
+    if !dir.exists() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let mut manifest = st
+        .store
+        .read_manifest(&dir)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    manifest.assets = cfg;
+    st.store
+        .write_manifest(&dir, &manifest)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(manifest))
+}
+
+/// Replace the world's emote catalog; running game servers pick it up for new connections.
+async fn set_emotes_config(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(world_id): Path<String>,
+    Json(cfg): Json<WorldEmotesConfig>,
+) -> Result<Json<WorldManifestV1>, StatusCode> {
+    require_admin(&headers, &st)?;
+    let world_id = Uuid::parse_str(&world_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    if cfg.catalog.iter().any(|e| e.id.trim().is_empty()) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let dir = st.store.world_dir(world_id);
+    if !dir.exists() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let mut manifest = st
+        .store
+        .read_manifest(&dir)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    manifest.emotes = cfg;
+    st.store
+        .write_manifest(&dir, &manifest)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(manifest))
+}
+
+#[derive(Debug, Deserialize)]
+struct ListingRequest {
+    #[serde(default)]
+    tags: Vec<String>,
+    /// New icon image; the current icon is kept when absent.
+    #[serde(default)]
+    icon_base64: Option<String>,
+}
+
+/// Set the tags and icon that directory listings of the world carry.
+async fn set_listing(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(world_id): Path<String>,
+    Json(req): Json<ListingRequest>,
+) -> Result<Json<WorldManifestV1>, StatusCode> {
+    use base64::Engine as _;
+
+    require_admin(&headers, &st)?;
+    let world_id = Uuid::parse_str(&world_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    if let Err(e) = world_summary::check_tags(&req.tags) {
+        warn!("listing for {world_id} rejected: {e:#}");
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let icon = match req.icon_base64.as_deref() {
+        Some(b64) => {
+            let bytes = base64::engine::general_purpose::STANDARD
+                .decode(b64.trim())
+                .map_err(|_| StatusCode::BAD_REQUEST)?;
+            if bytes.is_empty() || bytes.len() > world_summary::MAX_ICON_BYTES {
+                warn!("icon for {world_id} rejected: {} bytes", bytes.len());
+                return Err(StatusCode::BAD_REQUEST);
             }
-        });
+            Some(bytes)
+        }
+        None => None,
+    };
+    let dir = st.store.world_dir(world_id);
+    if !dir.exists() {
+        return Err(StatusCode::NOT_FOUND);
     }
-    if req.claude_model.is_some() {
-        cfg.claude_model = normalize_optional_string(req.claude_model);
+    let mut manifest = st
+        .store
+        .read_manifest(&dir)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if let Some(bytes) = icon {
+        let sha256 = content::put(&st.store, &bytes).map_err(|e| {
+            error!("storing icon for {world_id} failed: {e:#}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        manifest.icon_sha256 = Some(sha256);
     }
-    if let Some(v) = req.avatar_mesh_enabled {
-        cfg.avatar_mesh_enabled = v;
+    manifest.tags = req.tags;
+    st.store
+        .write_manifest(&dir, &manifest)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(manifest))
+}
+
+async fn get_world_access(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(world_id): Path<String>,
+) -> Result<Json<AccessFilter>, StatusCode> {
+    require_admin(&headers, &st)?;
+    let world_id = Uuid::parse_str(&world_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let dir = st.store.world_dir(world_id);
+    if !dir.exists() {
+        return Err(StatusCode::NOT_FOUND);
     }
+    access::load(&dir).map(Json).map_err(|e| {
+        error!("access filter of {world_id}: {e:#}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
 
-    assistant::save_config(&st.store, &cfg).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+/// Replace the world's accept filter; its game server applies it from the next connection.
+async fn set_world_access(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(world_id): Path<String>,
+    Json(mut filter): Json<AccessFilter>,
+) -> Result<Json<AccessFilter>, (StatusCode, String)> {
+    require_admin(&headers, &st).map_err(|s| (s, String::new()))?;
+    let world_id =
+        Uuid::parse_str(&world_id).map_err(|_| (StatusCode::BAD_REQUEST, String::new()))?;
+    filter
+        .normalize()
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("{e:#}")))?;
+    let dir = st.store.world_dir(world_id);
+    if !dir.exists() {
+        return Err((StatusCode::NOT_FOUND, String::new()));
+    }
+    access::save(&dir, &filter).map_err(|e| {
+        error!("saving access filter of {world_id} failed: {e:#}");
+        (StatusCode::INTERNAL_SERVER_ERROR, String::new())
+    })?;
+    Ok(Json(filter))
+}
 
-    Ok(Json(AssistantConfigResponse {
-        provider: cfg.provider.map(|p| p.as_str().to_string()),
-        codex_model: cfg.codex_model,
-        codex_reasoning_effort: cfg.codex_reasoning_effort,
-        claude_model: cfg.claude_model,
-        avatar_mesh_enabled: cfg.avatar_mesh_enabled,
-    }))
+#[derive(Debug, Deserialize)]
+struct QrQuery {
+    #[serde(default)]
+    format: Option<QrFormat>,
+    /// Host to put in the code instead of `public_endpoint` / the request's host.
+    #[serde(default)]
+    endpoint: Option<String>,
+    /// Admin API base URL to put in a pairing code instead of the request's host.
+    #[serde(default)]
+    admin_url: Option<String>,
+    /// Device name for the key a pairing code is exchanged for.
+    #[serde(default)]
+    name: Option<String>,
+}
+
+/// Host part of the request's `Host` header.
+fn request_host(headers: &HeaderMap) -> Option<String> {
+    let host = headers.get(axum::http::header::HOST)?.to_str().ok()?;
+    let host = match host.strip_prefix('[') {
+        Some(v6) => v6.split(']').next()?,
+        None => host.split(':').next()?,
+    };
+    (!host.is_empty()).then(|| host.to_string())
+}
+
+fn qr_response(
+    data: &str,
+    format: Option<QrFormat>,
+) -> Result<axum::response::Response, (StatusCode, String)> {
+    let format = format.unwrap_or(QrFormat::Svg);
+    let code =
+        QrCode::encode(data.as_bytes()).map_err(|e| (StatusCode::BAD_REQUEST, format!("{e:#}")))?;
+    Ok((
+        StatusCode::OK,
+        [(axum::http::header::CONTENT_TYPE, format.content_type())],
+        qr::render(&code, format),
+    )
+        .into_response())
+}
+
+/// QR code of the world's connect string, at `endpoint`, else `public_endpoint`, else the host
+/// this request was sent to.
+async fn get_world_qr(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(world_id): Path<String>,
+    axum::extract::Query(q): axum::extract::Query<QrQuery>,
+) -> Result<axum::response::Response, (StatusCode, String)> {
+    require_auth(&headers, &st).map_err(|s| (s, String::new()))?;
+    let world_id =
+        Uuid::parse_str(&world_id).map_err(|_| (StatusCode::BAD_REQUEST, String::new()))?;
+    let dir = st.store.world_dir(world_id);
+    if !dir.exists() {
+        return Err((StatusCode::NOT_FOUND, String::new()));
+    }
+    let manifest = st
+        .store
+        .read_manifest(&dir)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, String::new()))?;
+    let endpoint = q
+        .endpoint
+        .or(st.config.current().public_endpoint)
+        .or_else(|| request_host(&headers))
+        .unwrap_or_else(|| "127.0.0.1".to_string());
+    let endpoint = owp_discovery::endpoint::normalize(&endpoint)
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("{e:#}")))?;
+    qr_response(&connect_string(&manifest, &endpoint), q.format)
+}
+
+/// QR code that pairs the Unity client with this admin API: its address and a fresh pairing code
+/// (none when auth is disabled).
+async fn get_pairing_qr(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Query(q): axum::extract::Query<QrQuery>,
+) -> Result<axum::response::Response, (StatusCode, String)> {
+    require_admin(&headers, &st).map_err(|s| (s, String::new()))?;
+    let admin_url = match q.admin_url {
+        Some(url) => url,
+        None => {
+            let host = headers
+                .get(axum::http::header::HOST)
+                .and_then(|h| h.to_str().ok())
+                .ok_or((StatusCode::BAD_REQUEST, "missing Host header".to_string()))?;
+            format!("http://{host}")
+        }
+    };
+    let code = match &st.auth {
+        AuthMode::Disabled => None,
+        AuthMode::BearerToken(_) => {
+            let name = q.name.as_deref().unwrap_or("Scanned device");
+            Some(st.pairing.start(name, Scope::Client).code)
+        }
+    };
+    let uri = qr::pairing_uri(&admin_url, code.as_deref())
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("{e:#}")))?;
+    qr_response(&uri, q.format)
 }
 
 #[derive(Debug, Deserialize)]
-struct SetProviderRequest {
-    provider: String,
+struct PairStartRequest {
+    /// Shown in the key list, e.g. "Living room PC".
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    scope: Scope,
 }
 
-async fn set_provider(
+/// Issue a one-time code to show on the host. The client redeems it with `/auth/pair/complete`.
+async fn pair_start(
     State(st): State<AppState>,
     headers: HeaderMap,
-    Json(req): Json<SetProviderRequest>,
-) -> Result<StatusCode, StatusCode> {
-    require_auth(&headers, &st.auth)?;
+    body: Option<Json<PairStartRequest>>,
+) -> Result<Json<pairing::PairingCode>, (StatusCode, String)> {
+    require_admin(&headers, &st).map_err(|s| (s, String::new()))?;
+    let (name, scope) = match body {
+        Some(Json(req)) => (req.name, req.scope),
+        None => (None, Scope::Client),
+    };
+    let name = name
+        .map(|n| n.trim().to_string())
+        .filter(|n| !n.is_empty())
+        .unwrap_or_else(|| "Unity client".to_string());
+    if name.chars().count() > 64 {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "name must be at most 64 characters".to_string(),
+        ));
+    }
+    let code = st.pairing.start(&name, scope);
+    info!(
+        "pairing code for {name:?} ({scope:?}): {} (valid {}s)",
+        code.code, code.expires_in_secs
+    );
+    Ok(Json(code))
+}
 
-    let provider = match req.provider.as_str() {
-        "codex" => AssistantProviderId::Codex,
-        "claude" => AssistantProviderId::Claude,
-        _ => return Err(StatusCode::BAD_REQUEST),
+#[derive(Debug, Deserialize)]
+struct LoginRequest {
+    username: String,
+    password: String,
+}
+
+/// Log a local account in. The token works like the admin token for everything but admin-only
+/// endpoints (unless the account is an admin) and pins profile APIs to the account's profile.
+async fn login(
+    State(st): State<AppState>,
+    Json(req): Json<LoginRequest>,
+) -> Result<Json<accounts::SessionToken>, (StatusCode, String)> {
+    let refused = || {
+        (
+            StatusCode::FORBIDDEN,
+            "wrong username or password".to_string(),
+        )
     };
+    if st.sessions.locked_out(&req.username) {
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            "too many wrong passwords; try again in a minute".to_string(),
+        ));
+    }
+    let store = st.store.clone();
+    let (username, password) = (req.username.clone(), req.password);
+    let account =
+        tokio::task::spawn_blocking(move || accounts::verify(&store, &username, &password))
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, String::new()))?
+            .map_err(|e| {
+                error!("login failed: {e:#}");
+                (StatusCode::INTERNAL_SERVER_ERROR, String::new())
+            })?;
+    let Some(account) = account else {
+        warn!("failed login for {:?}", req.username);
+        st.sessions.record_failure(&req.username);
+        return Err(refused());
+    };
+    info!("{} logged in", account.username);
+    Ok(Json(st.sessions.issue(&account)))
+}
 
-    let mut cfg =
-        assistant::load_config(&st.store).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    cfg.provider = Some(provider);
-    assistant::save_config(&st.store, &cfg).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+async fn logout(State(st): State<AppState>, headers: HeaderMap) -> StatusCode {
+    match bearer(&headers) {
+        Some(token) if st.sessions.end(token) => StatusCode::NO_CONTENT,
+        _ => StatusCode::NOT_FOUND,
+    }
+}
+
+async fn list_accounts(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<accounts::AccountV1>>, StatusCode> {
+    require_admin(&headers, &st)?;
+    accounts::list(&st.store).map(Json).map_err(|e| {
+        error!("listing accounts failed: {e:#}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateAccountRequest {
+    username: String,
+    password: String,
+    /// Defaults to the username.
+    #[serde(default)]
+    profile_id: Option<String>,
+    #[serde(default)]
+    admin: bool,
+}
+
+async fn create_account(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<CreateAccountRequest>,
+) -> Result<Json<accounts::AccountV1>, (StatusCode, String)> {
+    require_admin(&headers, &st).map_err(|s| (s, String::new()))?;
+    let store = st.store.clone();
+    tokio::task::spawn_blocking(move || {
+        accounts::add(
+            &store,
+            &req.username,
+            &req.password,
+            req.profile_id.as_deref(),
+            req.admin,
+        )
+    })
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, String::new()))?
+    .map(Json)
+    .map_err(|e| (StatusCode::BAD_REQUEST, format!("{e:#}")))
+}
+
+async fn delete_account(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(username): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    require_admin(&headers, &st)?;
+    match accounts::remove(&st.store, &username) {
+        Ok(true) => {
+            st.sessions.end_all(&username);
+            Ok(StatusCode::NO_CONTENT)
+        }
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!("removing account {username:?} failed: {e:#}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SetPasswordRequest {
+    password: String,
+}
+
+/// Change a password: admins for any account, an account for itself. Logs its sessions out.
+async fn set_account_password(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(username): Path<String>,
+    Json(req): Json<SetPasswordRequest>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let own = bearer(&headers)
+        .and_then(|t| st.sessions.lookup(t))
+        .is_some_and(|s| s.username == username);
+    if !own {
+        require_admin(&headers, &st).map_err(|s| (s, String::new()))?;
+    }
+    let store = st.store.clone();
+    let name = username.clone();
+    let changed =
+        tokio::task::spawn_blocking(move || accounts::set_password(&store, &name, &req.password))
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, String::new()))?
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("{e:#}")))?;
+    if !changed {
+        return Err((StatusCode::NOT_FOUND, String::new()));
+    }
+    st.sessions.end_all(&username);
     Ok(StatusCode::NO_CONTENT)
 }
 
-#[derive(Debug, Deserialize)]
-struct AssistantChatRequest {
-    message: String,
+#[derive(Debug, Deserialize)]
+struct PairCompleteRequest {
+    code: String,
+}
+
+/// Exchange a pairing code for an API key. Needs no token; the code is the credential.
+async fn pair_complete(
+    State(st): State<AppState>,
+    Json(req): Json<PairCompleteRequest>,
+) -> Result<Json<pairing::PairedKey>, (StatusCode, String)> {
+    match st.pairing.redeem(&req.code) {
+        Ok(Ok(key)) => {
+            info!("paired {:?} as key {}", key.key.name, key.key.id);
+            Ok(Json(key))
+        }
+        Ok(Err(RedeemError::InvalidCode)) => {
+            warn!("rejected pairing code");
+            Err((
+                StatusCode::FORBIDDEN,
+                "unknown or expired pairing code".to_string(),
+            ))
+        }
+        Ok(Err(RedeemError::Locked)) => Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            "too many wrong codes; start pairing again on the host".to_string(),
+        )),
+        Err(e) => {
+            error!("saving api key failed: {e:#}");
+            Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()))
+        }
+    }
+}
+
+async fn list_api_keys(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<pairing::ApiKeyV1>>, StatusCode> {
+    require_admin(&headers, &st)?;
+    Ok(Json(st.pairing.list()))
+}
+
+async fn revoke_api_key(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    require_admin(&headers, &st)?;
+    let id = Uuid::parse_str(&id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    match st.pairing.revoke(id) {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!("revoking api key {id} failed: {e:#}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Preview of the `prefetch` list the game server puts in `Welcome`.
+async fn get_prefetch(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(world_id): Path<String>,
+) -> Result<Json<Vec<AssetRef>>, StatusCode> {
+    require_auth(&headers, &st)?;
+    let world_id = Uuid::parse_str(&world_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let dir = st.store.world_dir(world_id);
+    if !dir.exists() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let manifest = st
+        .store
+        .read_manifest(&dir)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let list = AssetIndex::default()
+        .prefetch_list(&dir, &manifest.assets)
+        .map_err(|e| {
+            error!("prefetch list failed: {e:#}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(Json(list))
+}
+
+async fn assistant_status(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<assistant::AssistantStatus>, StatusCode> {
+    require_auth(&headers, &st)?;
+    let status = assistant::status(&st.store)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(status))
+}
+
+#[derive(Debug, Serialize)]
+struct AssistantConfigResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    provider: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    codex_model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    codex_reasoning_effort: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    claude_model: Option<String>,
+    avatar_mesh_enabled: bool,
+}
+
+async fn get_assistant_config(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<AssistantConfigResponse>, StatusCode> {
+    require_auth(&headers, &st)?;
+    let cfg = assistant::load_config(&st.store).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(AssistantConfigResponse {
+        provider: cfg.provider.map(|p| p.as_str().to_string()),
+        codex_model: cfg.codex_model,
+        codex_reasoning_effort: cfg.codex_reasoning_effort,
+        claude_model: cfg.claude_model,
+        avatar_mesh_enabled: cfg.avatar_mesh_enabled,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct SetAssistantConfigRequest {
+    #[serde(default)]
+    provider: Option<String>,
+    #[serde(default)]
+    codex_model: Option<String>,
+    #[serde(default)]
+    codex_reasoning_effort: Option<String>,
+    #[serde(default)]
+    claude_model: Option<String>,
+    #[serde(default)]
+    avatar_mesh_enabled: Option<bool>,
+}
+
+fn normalize_optional_string(v: Option<String>) -> Option<String> {
+    v.and_then(|s| {
+        let t = s.trim().to_string();
+        if t.is_empty() || t == "default" {
+            None
+        } else {
+            Some(t)
+        }
+    })
+}
+
+async fn set_assistant_config(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<SetAssistantConfigRequest>,
+) -> Result<Json<AssistantConfigResponse>, StatusCode> {
+    require_admin(&headers, &st)?;
+
+    let mut cfg =
+        assistant::load_config(&st.store).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    if let Some(p) = req.provider {
+        cfg.provider = match p.as_str() {
+            "" => None,
+            "codex" => Some(AssistantProviderId::Codex),
+            "claude" => Some(AssistantProviderId::Claude),
+            _ => return Err(StatusCode::BAD_REQUEST),
+        };
+    }
+
+    if req.codex_model.is_some() {
+        cfg.codex_model = normalize_optional_string(req.codex_model);
+    }
+    if req.codex_reasoning_effort.is_some() {
+        let v = normalize_optional_string(req.codex_reasoning_effort);
+        if let Some(ref e) = v {
+            match e.as_str() {
+                "low" | "medium" | "high" | "very_high" | "xhigh" => {}
+                _ => return Err(StatusCode::BAD_REQUEST),
+            }
+        }
+        cfg.codex_reasoning_effort = v.map(|e| {
+            if e == "very_high" {
+                "xhigh".to_string()
+            } else {
+                e
+            }
+        });
+    }
+    if req.claude_model.is_some() {
+        cfg.claude_model = normalize_optional_string(req.claude_model);
+    }
+    if let Some(v) = req.avatar_mesh_enabled {
+        cfg.avatar_mesh_enabled = v;
+    }
+
+    assistant::save_config(&st.store, &cfg).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(AssistantConfigResponse {
+        provider: cfg.provider.map(|p| p.as_str().to_string()),
+        codex_model: cfg.codex_model,
+        codex_reasoning_effort: cfg.codex_reasoning_effort,
+        claude_model: cfg.claude_model,
+        avatar_mesh_enabled: cfg.avatar_mesh_enabled,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct SetProviderRequest {
+    provider: String,
+}
+
+async fn set_provider(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<SetProviderRequest>,
+) -> Result<StatusCode, StatusCode> {
+    require_admin(&headers, &st)?;
+
+    let provider = match req.provider.as_str() {
+        "codex" => AssistantProviderId::Codex,
+        "claude" => AssistantProviderId::Claude,
+        _ => return Err(StatusCode::BAD_REQUEST),
+    };
+
+    let mut cfg =
+        assistant::load_config(&st.store).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    cfg.provider = Some(provider);
+    assistant::save_config(&st.store, &cfg).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+struct AssistantChatRequest {
+    message: String,
+    #[serde(default)]
+    profile_id: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct AssistantChatResponse {
+    reply: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    avatar: Option<AvatarSpecV1>,
+}
+
+/// Status for a failed assistant job: 503 when the provider couldn't run, 502 when its answer
+/// was unusable.
+fn assistant_failure(e: &AssistantError) -> StatusCode {
+    match e {
+        AssistantError::ProviderUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+        AssistantError::Corrupt(_) => StatusCode::BAD_GATEWAY,
+        AssistantError::PromptTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+        AssistantError::Storage(_) | AssistantError::Other(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Count a generation against the configured provider (if telemetry is on).
+fn record_generation(st: &AppState, cfg: &assistant::AssistantConfig, ok: bool) {
+    if let Some(provider) = cfg.provider {
+        let provider = provider.as_str();
+        telemetry::record(&st.store, telemetry::Event::Generation { provider, ok });
+    }
+}
+
+async fn assistant_chat(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<AssistantChatRequest>,
+) -> Result<Json<AssistantChatResponse>, StatusCode> {
+    require_auth(&headers, &st)?;
+
+    let cfg = assistant::load_config(&st.store).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if cfg.provider.is_none() {
+        return Err(StatusCode::PRECONDITION_FAILED);
+    };
+
+    let profile_id = &profile_for(&headers, &st, req.profile_id.as_deref())?;
+    charge_assistant_job(&st, profile_id)?;
+    let out = assistant::companion_chat(&st.store, &cfg, profile_id, &req.message).await;
+    record_generation(&st, &cfg, out.is_ok());
+    let out = out.map_err(|e| {
+        error!("assistant chat failed: {e:#}");
+        assistant_failure(&e)
+    })?;
+
+    Ok(Json(AssistantChatResponse {
+        reply: out.reply,
+        avatar: out.avatar,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct WorldChatRequest {
+    world_id: String,
+    message: String,
+}
+
+/// World-building chat (see `world_chat.rs`): the reply plus the object edits it applied.
+async fn world_chat(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<WorldChatRequest>,
+) -> Result<Json<WorldChatResponse>, StatusCode> {
+    require_world_edit(&headers, &st)?;
+    let world_id = Uuid::parse_str(&req.world_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let dir = st.store.world_dir(world_id);
+    if !dir.exists() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let cfg = assistant::load_config(&st.store).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if cfg.provider.is_none() {
+        return Err(StatusCode::PRECONDITION_FAILED);
+    };
+    check_storage(&st, world_id, &dir)?;
+    st.quotas
+        .world(world_id, &st.config)
+        .assistant_job()
+        .map_err(|e| {
+            warn!("world chat for {world_id} refused: {e}");
+            StatusCode::TOO_MANY_REQUESTS
+        })?;
+    let out = world_chat::chat(&st.store, &cfg, &dir, &req.message).await;
+    record_generation(&st, &cfg, out.is_ok());
+    let out = out.map_err(|e| {
+        error!("world chat in {world_id} failed: {e:#}");
+        match &e {
+            AssistantError::Other(e) if e.is::<migration::Frozen>() => StatusCode::LOCKED,
+            _ => assistant_failure(&e),
+        }
+    })?;
+    Ok(Json(out))
+}
+
+#[derive(Debug, Deserialize)]
+struct WorldChatQuery {
+    world_id: String,
+}
+
+#[derive(Debug, Serialize)]
+struct WorldChatHistoryResponse {
+    turns: Vec<WorldChatTurn>,
+}
+
+/// The world's design conversation, with the edits each assistant turn made.
+async fn world_chat_history(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Query(q): axum::extract::Query<WorldChatQuery>,
+) -> Result<Json<WorldChatHistoryResponse>, StatusCode> {
+    require_auth(&headers, &st)?;
+    let world_id = Uuid::parse_str(&q.world_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let dir = st.store.world_dir(world_id);
+    if !dir.exists() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let turns = world_chat::load_history(&dir).map_err(|e| {
+        error!("reading the world chat history of {world_id} failed: {e:#}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(WorldChatHistoryResponse { turns }))
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct AssistantUndoRequest {
+    #[serde(default)]
+    profile_id: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct CacheClearResponse {
+    removed: usize,
+}
+
+/// Drop every cached generation output.
+async fn clear_generation_cache(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<CacheClearResponse>, StatusCode> {
+    require_admin(&headers, &st)?;
+    let removed = generation_cache::clear(&st.store).map_err(|e| {
+        error!("clearing the generation cache failed: {e:#}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(CacheClearResponse { removed }))
+}
+
+/// Revert the latest assistant change to the profile's avatar, meshes included. 409 if there
+/// is nothing to undo.
+async fn assistant_undo(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    body: Option<Json<AssistantUndoRequest>>,
+) -> Result<Json<AvatarGenerateResponse>, StatusCode> {
+    assistant_step(st, headers, body, RevisionSource::Undo)
+}
+
+/// Re-apply the change the latest undo reverted. 409 if there is nothing to redo.
+async fn assistant_redo(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    body: Option<Json<AssistantUndoRequest>>,
+) -> Result<Json<AvatarGenerateResponse>, StatusCode> {
+    assistant_step(st, headers, body, RevisionSource::Redo)
+}
+
+fn assistant_step(
+    st: AppState,
+    headers: HeaderMap,
+    body: Option<Json<AssistantUndoRequest>>,
+    direction: RevisionSource,
+) -> Result<Json<AvatarGenerateResponse>, StatusCode> {
+    require_auth(&headers, &st)?;
+    let req = body.map(|Json(r)| r).unwrap_or_default();
+    let profile_id = &profile_for(&headers, &st, req.profile_id.as_deref())?;
+    let result = if direction == RevisionSource::Undo {
+        avatar_history::undo(&st.store, profile_id)
+    } else {
+        avatar_history::redo(&st.store, profile_id)
+    };
+    let avatar = result
+        .map_err(|e| {
+            error!("assistant {direction:?} failed: {e:#}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::CONFLICT)?;
+    Ok(Json(AvatarGenerateResponse { avatar }))
+}
+
+#[derive(Debug, Deserialize)]
+struct AvatarGenerateRequest {
+    prompt: String,
+    #[serde(default)]
+    profile_id: Option<String>,
+    /// Run the provider even if an identical request is cached.
+    #[serde(default)]
+    force: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct AvatarGenerateResponse {
+    avatar: AvatarSpecV1,
+}
+
+async fn get_avatar(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Query(q): axum::extract::Query<ProfileQuery>,
+) -> Result<Json<Option<AvatarSpecV1>>, StatusCode> {
+    require_auth(&headers, &st)?;
+    let profile_id = &avatar_profile_for(&headers, &st, q.profile_id.as_deref())?;
+    let avatar = avatar_mod::load_avatar(&st.store, profile_id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(avatar))
+}
+
+async fn generate_avatar(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<AvatarGenerateRequest>,
+) -> Result<Json<AvatarGenerateResponse>, StatusCode> {
+    require_auth(&headers, &st)?;
+
+    let cfg = assistant::load_config(&st.store).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if cfg.provider.is_none() {
+        return Err(StatusCode::PRECONDITION_FAILED);
+    };
+
+    let profile_id = &profile_for(&headers, &st, req.profile_id.as_deref())?;
+    charge_assistant_job(&st, profile_id)?;
+    let avatar = avatar_mod::generate_avatar(&st.store, &cfg, &req.prompt, req.force).await;
+    record_generation(&st, &cfg, avatar.is_ok());
+    let avatar = avatar.map_err(|e| {
+        error!("avatar generation failed: {e:#}");
+        e.downcast_ref()
+            .map_or(StatusCode::INTERNAL_SERVER_ERROR, assistant_failure)
+    })?;
+
+    let avatar = avatar_mod::save_avatar(&st.store, profile_id, &avatar, RevisionSource::Generate)
+        .map_err(|e| {
+            error!("saving avatar failed: {e:#}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(AvatarGenerateResponse { avatar }))
+}
+
+/// Room for every mesh file at its size limit, base64-encoded, plus the spec.
+const AVATAR_UPLOAD_MAX_BYTES: usize =
+    (owp_protocol::avatar::MAX_MESH_PARTS + 1) * owp_protocol::avatar::MAX_MESH_BYTES * 4 / 3
+        + 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+struct AvatarUploadRequest {
+    avatar: AvatarSpecV1,
+    #[serde(default)]
+    meshes: Vec<AvatarMeshBlob>,
+    #[serde(default)]
+    profile_id: Option<String>,
+}
+
+async fn put_avatar(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<AvatarUploadRequest>,
+) -> Result<Json<AvatarGenerateResponse>, StatusCode> {
+    require_auth(&headers, &st)?;
+
+    let submission = avatar_mod::prepare_submission(&req.avatar, &req.meshes).map_err(|e| {
+        warn!("avatar upload rejected: {e:#}");
+        StatusCode::BAD_REQUEST
+    })?;
+    let profile_id = &profile_for(&headers, &st, req.profile_id.as_deref())?;
+    let avatar = submission
+        .store_meshes(&st.store)
+        .and_then(|()| {
+            avatar_mod::save_avatar(
+                &st.store,
+                profile_id,
+                &submission.avatar,
+                RevisionSource::Upload,
+            )
+        })
+        .map_err(|e| {
+            error!("saving uploaded avatar failed: {e:#}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(AvatarGenerateResponse { avatar }))
+}
+
+async fn list_avatar_revisions(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Query(q): axum::extract::Query<ProfileQuery>,
+) -> Result<Json<Vec<avatar_history::RevisionSummary>>, StatusCode> {
+    require_auth(&headers, &st)?;
+    let profile_id = &profile_for(&headers, &st, q.profile_id.as_deref())?;
+    let revisions = avatar_history::list(&st.store, profile_id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(revisions))
+}
+
+async fn get_avatar_revision(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(revision): Path<u64>,
+    axum::extract::Query(q): axum::extract::Query<ProfileQuery>,
+) -> Result<Json<avatar_history::AvatarRevisionV1>, StatusCode> {
+    require_auth(&headers, &st)?;
+    let profile_id = &profile_for(&headers, &st, q.profile_id.as_deref())?;
+    avatar_history::get(&st.store, profile_id, revision)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+#[derive(Debug, Deserialize)]
+struct AvatarDiffQuery {
+    /// Defaults to the revision before `to`.
+    #[serde(default)]
+    from: Option<u64>,
+    /// Defaults to the latest revision.
+    #[serde(default)]
+    to: Option<u64>,
+    #[serde(default)]
+    profile_id: Option<String>,
+}
+
+/// What changed between two saved avatars; by default, what the last save changed.
+async fn get_avatar_diff(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Query(q): axum::extract::Query<AvatarDiffQuery>,
+) -> Result<Json<avatar_history::AvatarDiff>, StatusCode> {
+    require_auth(&headers, &st)?;
+    let profile_id = &profile_for(&headers, &st, q.profile_id.as_deref())?;
+    let revision = |rev: u64| {
+        avatar_history::get(&st.store, profile_id, rev)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+            .ok_or(StatusCode::NOT_FOUND)
+    };
+    let to = match q.to {
+        Some(rev) => revision(rev)?,
+        None => avatar_history::latest(&st.store, profile_id)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+            .ok_or(StatusCode::NOT_FOUND)?,
+    };
+    let from = revision(q.from.unwrap_or(to.revision.saturating_sub(1)))?;
+    let diff = avatar_history::diff(&from, &to).map_err(|e| {
+        error!("avatar diff failed: {e:#}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(diff))
+}
+
+#[derive(Debug, Deserialize)]
+struct ContentQuery {
+    /// The world a download counts against, as listed in its sync manifest.
+    #[serde(default)]
+    world_id: Option<Uuid>,
+}
+
+async fn get_content(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(sha256): Path<String>,
+    axum::extract::Query(q): axum::extract::Query<ContentQuery>,
+) -> Result<axum::response::Response, StatusCode> {
+    require_auth(&headers, &st)?;
+    let world_dir = q.world_id.map(|id| st.store.world_dir(id));
+    if let Some(dir) = &world_dir {
+        check_bandwidth(&st, dir)?;
+    }
+    let bytes = content::get(&st.store, &sha256)
+        .map_err(|e| {
+            error!("reading content {sha256} failed: {e:#}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let (resp, sent) = ranged_bytes(&headers, bytes);
+    if let Some(dir) = &world_dir {
+        meter_asset(dir, sent);
+    }
+    Ok(resp)
+}
+
+/// Refuse downloads for a world past its monthly cap.
+fn check_bandwidth(st: &AppState, world_dir: &std::path::Path) -> Result<(), StatusCode> {
+    if !world_dir.exists() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let manifest = st
+        .store
+        .read_manifest(world_dir)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let usage = bandwidth::usage(world_dir, &manifest.bandwidth).map_err(|e| {
+        error!("reading bandwidth of {world_dir:?} failed: {e:#}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    if usage.state == CapState::Capped {
+        return Err(StatusCode::SERVICE_UNAVAILABLE);
+    }
+    Ok(())
+}
+
+fn meter_asset(world_dir: &std::path::Path, sent: u64) {
+    if let Err(e) = bandwidth::record_asset(world_dir, sent) {
+        warn!("counting {sent} asset bytes for {world_dir:?} failed: {e:#}");
+    }
+}
+
+/// A blob reply honoring one `Range: bytes=<start>-[<end>]`, so interrupted asset syncs can
+/// resume. Other range forms get the whole body. Also returns the body length, for metering.
+fn ranged_bytes(headers: &HeaderMap, bytes: Vec<u8>) -> (axum::response::Response, u64) {
+    use axum::http::header;
+    let len = bytes.len() as u64;
+    let last = len.saturating_sub(1);
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("bytes="))
+        .filter(|v| !v.contains(','))
+        .and_then(|v| v.split_once('-'))
+        .and_then(|(start, end)| {
+            let start: u64 = start.trim().parse().ok()?;
+            let end = match end.trim() {
+                "" => last,
+                e => e.parse::<u64>().ok()?.min(last),
+            };
+            Some((start, end))
+        });
+    let octets = (header::CONTENT_TYPE, "application/octet-stream".to_string());
+    let ranges = (header::ACCEPT_RANGES, "bytes".to_string());
+    match range {
+        None => (
+            (StatusCode::OK, [octets, ranges], bytes).into_response(),
+            len,
+        ),
+        Some((start, end)) if start >= len || end < start => (
+            (
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [ranges, (header::CONTENT_RANGE, format!("bytes */{len}"))],
+            )
+                .into_response(),
+            0,
+        ),
+        Some((start, end)) => (
+            (
+                StatusCode::PARTIAL_CONTENT,
+                [
+                    octets,
+                    ranges,
+                    (header::CONTENT_RANGE, format!("bytes {start}-{end}/{len}")),
+                ],
+                bytes[start as usize..=end as usize].to_vec(),
+            )
+                .into_response(),
+            end - start + 1,
+        ),
+    }
+}
+
+/// Every asset the world needs, for clients syncing a local cache (see `AssetIndex::sync_manifest`).
+async fn get_sync_manifest(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(world_id): Path<String>,
+) -> Result<Json<SyncManifestV1>, StatusCode> {
+    require_auth(&headers, &st)?;
+    let world_id = Uuid::parse_str(&world_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let dir = st.store.world_dir(world_id);
+    if !dir.exists() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let sync = AssetIndex::default()
+        .sync_manifest(&st.store, &dir)
+        .map_err(|e| {
+            error!("sync manifest for {world_id} failed: {e:#}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(Json(sync))
+}
+
+/// One file from the world's `assets/`, for hosts without a CDN.
+#[derive(Debug, Deserialize)]
+struct AssetQuery {
+    /// An `asset_token` from the game server, for private assets.
+    #[serde(default)]
+    asset_token: Option<String>,
+}
+
+async fn get_world_asset(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path((world_id, path)): Path<(String, String)>,
+    axum::extract::Query(q): axum::extract::Query<AssetQuery>,
+) -> Result<axum::response::Response, StatusCode> {
+    let world_id = Uuid::parse_str(&world_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let dir = st.store.world_dir(world_id);
+    let private = dir.exists()
+        && st
+            .store
+            .read_manifest(&dir)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+            .assets
+            .private;
+    if private {
+        require_asset_token(&headers, q.asset_token.as_deref(), &st, world_id)?;
+    } else {
+        require_auth(&headers, &st)?;
+    }
+    let rel = assets::safe_relative(&path).ok_or(StatusCode::BAD_REQUEST)?;
+    let file = assets::assets_dir(&dir).join(rel);
+    if !file.is_file() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    check_bandwidth(&st, &dir)?;
+    let bytes = std::fs::read(&file).map_err(|e| {
+        error!("reading {file:?} failed: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let (resp, sent) = ranged_bytes(&headers, bytes);
+    meter_asset(&dir, sent);
+    Ok(resp)
+}
+
+/// Private assets need an asset token issued to a player of `world_id`, or the admin.
+fn require_asset_token(
+    headers: &HeaderMap,
+    query: Option<&str>,
+    st: &AppState,
+    world_id: Uuid,
+) -> Result<(), StatusCode> {
+    let header = headers
+        .get("x-owp-asset-token")
+        .and_then(|v| v.to_str().ok());
+    let Some(token) = query.or(header) else {
+        return require_admin(headers, st);
+    };
+    let now = time::OffsetDateTime::now_utc().unix_timestamp();
+    match st.asset_key.check(token, world_id, now) {
+        Ok(_player_id) => Ok(()),
+        Err(e) => {
+            debug!("asset token for {world_id} refused: {e}");
+            Err(StatusCode::FORBIDDEN)
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct BandwidthStatus {
+    config: WorldBandwidthConfig,
+    #[serde(flatten)]
+    usage: bandwidth::BandwidthUsageV1,
+}
+
+/// This month's bytes by channel and where they stand against the caps.
+async fn get_bandwidth(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(world_id): Path<String>,
+) -> Result<Json<BandwidthStatus>, StatusCode> {
+    require_auth(&headers, &st)?;
+    let world_id = Uuid::parse_str(&world_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let dir = st.store.world_dir(world_id);
+    if !dir.exists() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let manifest = st
+        .store
+        .read_manifest(&dir)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let usage = bandwidth::usage(&dir, &manifest.bandwidth).map_err(|e| {
+        error!("bandwidth of {world_id} failed: {e:#}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(BandwidthStatus {
+        config: manifest.bandwidth,
+        usage,
+    }))
+}
+
+async fn set_bandwidth_config(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(world_id): Path<String>,
+    Json(cfg): Json<WorldBandwidthConfig>,
+) -> Result<Json<WorldManifestV1>, StatusCode> {
+    require_admin(&headers, &st)?;
+    let world_id = Uuid::parse_str(&world_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let dir = st.store.world_dir(world_id);
+    if !dir.exists() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    if cfg.throttle_bytes_per_sec == 0 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let mut manifest = st
+        .store
+        .read_manifest(&dir)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    manifest.bandwidth = cfg;
+    st.store
+        .write_manifest(&dir, &manifest)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(manifest))
+}
+
+#[derive(Debug, Serialize)]
+struct EntryFeeStatus {
+    entry_fee: Option<WorldEntryFee>,
+    /// Every fee paid, oldest first.
+    receipts: Vec<Receipt>,
+}
+
+async fn get_entry_fee(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(world_id): Path<String>,
+) -> Result<Json<EntryFeeStatus>, StatusCode> {
+    require_auth(&headers, &st)?;
+    let world_id = Uuid::parse_str(&world_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let dir = st.store.world_dir(world_id);
+    if !dir.exists() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let manifest = st
+        .store
+        .read_manifest(&dir)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let receipts = entry_fee::load_receipts(&dir).map_err(|e| {
+        error!("receipts of {world_id} failed: {e:#}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(EntryFeeStatus {
+        entry_fee: manifest.entry_fee,
+        receipts,
+    }))
+}
+
+/// Set what the world charges to enter; `null` makes it free again.
+async fn set_entry_fee(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(world_id): Path<String>,
+    Json(fee): Json<Option<WorldEntryFee>>,
+) -> Result<Json<WorldManifestV1>, (StatusCode, String)> {
+    // Whoever sets the fee picks who gets paid.
+    require_admin(&headers, &st).map_err(|s| (s, String::new()))?;
+    let world_id =
+        Uuid::parse_str(&world_id).map_err(|_| (StatusCode::BAD_REQUEST, String::new()))?;
+    let dir = st.store.world_dir(world_id);
+    if !dir.exists() {
+        return Err((StatusCode::NOT_FOUND, String::new()));
+    }
+    if let Some(fee) = &fee {
+        let bad = |msg: String| (StatusCode::BAD_REQUEST, msg);
+        if fee.amount == 0 {
+            return Err(bad("amount must be positive".to_string()));
+        }
+        if fee.decimals > 19 {
+            return Err(bad("decimals must be at most 19".to_string()));
+        }
+        transaction::parse_pubkey("recipient", &fee.recipient).map_err(|e| bad(e.to_string()))?;
+        if let Some(mint) = &fee.mint {
+            transaction::parse_pubkey("mint", mint).map_err(|e| bad(e.to_string()))?;
+        }
+    }
+    let mut manifest = st
+        .store
+        .read_manifest(&dir)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, String::new()))?;
+    manifest.entry_fee = fee;
+    st.store
+        .write_manifest(&dir, &manifest)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, String::new()))?;
+    Ok(Json(manifest))
+}
+
+/// Longest rules document `POST /worlds/:world_id/rules` stores.
+const MAX_RULES_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Serialize)]
+struct RulesStatus {
+    rules: Option<WorldRules>,
+    /// Every acknowledgment, oldest first.
+    acks: Vec<RulesAckRecord>,
+}
+
+#[derive(Debug, Serialize)]
+struct TelemetryPreview {
+    enabled: bool,
+    endpoint: Option<String>,
+    report: TelemetryReport,
+}
+
+/// The usage report exactly as `owp-server telemetry --send` would post it.
+async fn get_telemetry_preview(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<TelemetryPreview>, StatusCode> {
+    require_admin(&headers, &st)?;
+    let report = telemetry::preview(&st.store).map_err(|e| {
+        error!("telemetry preview failed: {e:#}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let cfg = st.config.current().telemetry;
+    Ok(Json(TelemetryPreview {
+        enabled: cfg.enabled,
+        endpoint: cfg.endpoint,
+        report,
+    }))
+}
+
+/// Crash reports the panic hook left behind, newest first.
+async fn list_crashes(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<CrashSummary>>, StatusCode> {
+    require_admin(&headers, &st)?;
+    crash::list(&st.store).map(Json).map_err(|e| {
+        error!("list crash reports failed: {e:#}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+async fn get_crash(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Result<Json<CrashReportV1>, StatusCode> {
+    require_admin(&headers, &st)?;
+    crash::check_name(&name).map_err(|_| StatusCode::BAD_REQUEST)?;
+    if !crash::crashes_dir(&st.store).join(&name).is_file() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    crash::load(&st.store, &name).map(Json).map_err(|e| {
+        error!("read crash report failed: {e:#}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+async fn get_rules(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(world_id): Path<String>,
+) -> Result<Json<RulesStatus>, StatusCode> {
+    require_auth(&headers, &st)?;
+    let world_id = Uuid::parse_str(&world_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let dir = st.store.world_dir(world_id);
+    if !dir.exists() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let manifest = st
+        .store
+        .read_manifest(&dir)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let acks = rules::load_acks(&dir).map_err(|e| {
+        error!("rules acks of {world_id} failed: {e:#}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(RulesStatus {
+        rules: manifest.rules,
+        acks,
+    }))
+}
+
+/// Either the document itself, kept in the content store, or where it is hosted and its hash.
+#[derive(Debug, Deserialize)]
+struct RulesRequest {
+    #[serde(default)]
+    text: Option<String>,
+    #[serde(default)]
+    uri: Option<String>,
+    #[serde(default)]
+    sha256: Option<String>,
+}
+
+/// Set the rules players must accept to enter; `null` drops the requirement.
+async fn set_rules(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(world_id): Path<String>,
+    Json(req): Json<Option<RulesRequest>>,
+) -> Result<Json<WorldManifestV1>, (StatusCode, String)> {
+    require_admin(&headers, &st).map_err(|s| (s, String::new()))?;
+    let world_id =
+        Uuid::parse_str(&world_id).map_err(|_| (StatusCode::BAD_REQUEST, String::new()))?;
+    let dir = st.store.world_dir(world_id);
+    if !dir.exists() {
+        return Err((StatusCode::NOT_FOUND, String::new()));
+    }
+    let bad = |msg: &str| (StatusCode::BAD_REQUEST, msg.to_string());
+    let rules = match req {
+        None => None,
+        Some(RulesRequest {
+            text: Some(text),
+            uri: None,
+            sha256: None,
+        }) => {
+            if text.trim().is_empty() || text.len() > MAX_RULES_BYTES {
+                return Err(bad("text must be 1 to 65536 bytes"));
+            }
+            let sha256 = content::put(&st.store, text.as_bytes()).map_err(|e| {
+                error!("storing rules for {world_id} failed: {e:#}");
+                (StatusCode::INTERNAL_SERVER_ERROR, String::new())
+            })?;
+            Some(WorldRules {
+                uri: content::content_uri(&sha256),
+                sha256,
+            })
+        }
+        Some(RulesRequest {
+            text: None,
+            uri: Some(uri),
+            sha256: Some(sha256),
+        }) => {
+            if !content::is_sha256(&sha256) {
+                return Err(bad("sha256 must be 64 hex characters"));
+            }
+            if uri.trim().is_empty() {
+                return Err(bad("uri must not be empty"));
+            }
+            Some(WorldRules {
+                sha256: sha256.to_ascii_lowercase(),
+                uri,
+            })
+        }
+        Some(_) => return Err(bad("give either text, or uri and sha256")),
+    };
+    let mut manifest = st
+        .store
+        .read_manifest(&dir)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, String::new()))?;
+    manifest.rules = rules;
+    st.store
+        .write_manifest(&dir, &manifest)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, String::new()))?;
+    Ok(Json(manifest))
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct KickRequest {
+    /// Shown to the player.
+    #[serde(default)]
+    message: Option<String>,
+}
+
+/// Disconnect a player from a world this process serves; 404 if it isn't connected here.
+async fn kick_player(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path((world_id, player_id)): Path<(String, String)>,
+    req: Option<Json<KickRequest>>,
+) -> Result<StatusCode, StatusCode> {
+    require_admin(&headers, &st)?;
+    let world_id = Uuid::parse_str(&world_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let player_id = Uuid::parse_str(&player_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let Json(req) = req.unwrap_or_default();
+    let bye = Message::Disconnect(Disconnect {
+        reason: DisconnectReason::Kicked,
+        message: req.message,
+    });
+    if !st.roster.world(world_id).send_to(player_id, &bye) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    info!("kicked player {player_id} from world {world_id}");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+struct ProfileQuery {
+    #[serde(default)]
+    profile_id: Option<String>,
+}
+
+async fn get_wardrobe(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Query(q): axum::extract::Query<ProfileQuery>,
+) -> Result<Json<wardrobe::WardrobeV1>, StatusCode> {
+    require_auth(&headers, &st)?;
+    let profile_id = &profile_for(&headers, &st, q.profile_id.as_deref())?;
+    let w = wardrobe::load(&st.store, profile_id).map_err(|e| {
+        error!("load wardrobe failed: {e:#}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(w))
+}
+
+#[derive(Debug, Deserialize)]
+struct PutOutfitRequest {
+    name: String,
+    /// Avatar to store; the profile's current avatar when absent.
+    #[serde(default)]
+    avatar: Option<AvatarSpecV1>,
+    #[serde(default)]
+    profile_id: Option<String>,
+}
+
+async fn put_outfit(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<PutOutfitRequest>,
+) -> Result<Json<AvatarGenerateResponse>, StatusCode> {
+    require_auth(&headers, &st)?;
+    wardrobe::check_name(&req.name).map_err(|_| StatusCode::BAD_REQUEST)?;
+    if let Some(a) = &req.avatar {
+        owp_protocol::avatar::validate_avatar(a).map_err(|e| {
+            warn!("outfit {:?} rejected: {e}", req.name);
+            StatusCode::BAD_REQUEST
+        })?;
+    }
+    let profile_id = &profile_for(&headers, &st, req.profile_id.as_deref())?;
+    let avatar = wardrobe::put_outfit(&st.store, profile_id, &req.name, req.avatar.as_ref())
+        .map_err(|e| {
+            error!("saving outfit {:?} failed: {e:#}", req.name);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(Json(AvatarGenerateResponse { avatar }))
+}
+
+async fn remove_outfit(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+    axum::extract::Query(q): axum::extract::Query<ProfileQuery>,
+) -> Result<StatusCode, StatusCode> {
+    require_auth(&headers, &st)?;
+    let profile_id = &profile_for(&headers, &st, q.profile_id.as_deref())?;
+    match wardrobe::remove_outfit(&st.store, profile_id, &name) {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!("removing outfit {name:?} failed: {e:#}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn wear_outfit(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+    axum::extract::Query(q): axum::extract::Query<ProfileQuery>,
+) -> Result<Json<AvatarGenerateResponse>, StatusCode> {
+    require_auth(&headers, &st)?;
+    let profile_id = &profile_for(&headers, &st, q.profile_id.as_deref())?;
+    let avatar = wardrobe::switch(&st.store, profile_id, &name)
+        .map_err(|e| {
+            error!("switching to outfit {name:?} failed: {e:#}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(AvatarGenerateResponse { avatar }))
+}
+
+async fn list_friends(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Query(q): axum::extract::Query<ProfileQuery>,
+) -> Result<Json<friends::FriendsV1>, StatusCode> {
+    require_auth(&headers, &st)?;
+    let profile_id = &profile_for(&headers, &st, q.profile_id.as_deref())?;
+    friends::check_profile_id(profile_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let f = friends::load(&st.store, profile_id).map_err(|e| {
+        error!("load friends failed: {e:#}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(f))
+}
+
+#[derive(Debug, Deserialize)]
+struct AddFriendRequest {
+    /// Wallet pubkey or profile id.
+    id: String,
+    #[serde(default)]
+    nickname: Option<String>,
+    #[serde(default)]
+    profile_id: Option<String>,
+}
+
+async fn add_friend(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<AddFriendRequest>,
+) -> Result<Json<friends::FriendV1>, StatusCode> {
+    require_auth(&headers, &st)?;
+    let profile_id = &profile_for(&headers, &st, req.profile_id.as_deref())?;
+    friends::check_profile_id(profile_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    if let Err(e) = friends::kind_of(&req.id) {
+        warn!("friend {:?} rejected: {e:#}", req.id);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let friend = friends::add(&st.store, profile_id, &req.id, req.nickname).map_err(|e| {
+        error!("adding friend {:?} failed: {e:#}", req.id);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(friend))
+}
+
+async fn remove_friend(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    axum::extract::Query(q): axum::extract::Query<ProfileQuery>,
+) -> Result<StatusCode, StatusCode> {
+    require_auth(&headers, &st)?;
+    let profile_id = &profile_for(&headers, &st, q.profile_id.as_deref())?;
+    friends::check_profile_id(profile_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    match friends::remove(&st.store, profile_id, &id) {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!("removing friend {id:?} failed: {e:#}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn get_privacy(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(profile_id): Path<String>,
+) -> Result<Json<PrivacyV1>, StatusCode> {
+    require_auth(&headers, &st)?;
+    let profile_id = &profile_for(&headers, &st, Some(&profile_id))?;
+    friends::check_profile_id(profile_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let p = privacy::load(&st.store, profile_id).map_err(|e| {
+        error!("load privacy settings failed: {e:#}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(p))
+}
+
+/// Change some of a profile's privacy settings. Roster visibility applies to connected players
+/// right away; opting out of companion history deletes the history kept so far.
+async fn patch_privacy(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(profile_id): Path<String>,
+    Json(patch): Json<PrivacyPatch>,
+) -> Result<Json<PrivacyV1>, StatusCode> {
+    require_auth(&headers, &st)?;
+    let profile_id = &profile_for(&headers, &st, Some(&profile_id))?;
+    friends::check_profile_id(profile_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let p = privacy::update(&st.store, profile_id, &patch).map_err(|e| {
+        error!("updating privacy settings of {profile_id:?} failed: {e:#}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    st.roster.set_listed(profile_id, p.appear_in_rosters);
+    if !p.retain_companion_history {
+        assistant::forget_companion_history(&st.store, profile_id).map_err(|e| {
+            error!("deleting companion history of {profile_id:?} failed: {e:#}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    }
+    Ok(Json(p))
+}
+
+/// Everything kept about a profile, as a tar (see `profile_data::export`).
+async fn export_profile(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(profile_id): Path<String>,
+) -> Result<axum::response::Response, StatusCode> {
+    require_auth(&headers, &st)?;
+    let profile_id = profile_for(&headers, &st, Some(&profile_id))?;
+    friends::check_profile_id(&profile_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let store = st.store.clone();
+    let archive = tokio::task::spawn_blocking(move || profile_data::export(&store, &profile_id))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|e| {
+            error!("exporting a profile failed: {e:#}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok((
+        [(axum::http::header::CONTENT_TYPE, "application/x-tar")],
+        archive,
+    )
+        .into_response())
+}
+
+#[derive(Debug, Deserialize)]
+struct EraseProfileRequest {
+    /// Must repeat the profile id.
+    confirm: String,
+}
+
+/// Delete everything kept about a profile. The body must name it again as `confirm`.
+async fn erase_profile(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(profile_id): Path<String>,
+    Json(req): Json<EraseProfileRequest>,
+) -> Result<Json<profile_data::EraseReport>, (StatusCode, String)> {
+    require_auth(&headers, &st).map_err(|s| (s, String::new()))?;
+    let profile_id =
+        profile_for(&headers, &st, Some(&profile_id)).map_err(|s| (s, String::new()))?;
+    friends::check_profile_id(&profile_id)
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("{e:#}")))?;
+    if req.confirm != profile_id {
+        let message = format!("confirm must be {profile_id:?}");
+        return Err((StatusCode::BAD_REQUEST, message));
+    }
+    let store = st.store.clone();
+    let id = profile_id.clone();
+    let report = tokio::task::spawn_blocking(move || profile_data::erase(&store, &id))
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, String::new()))?
+        .map_err(|e| {
+            if e.is::<migration::Frozen>() {
+                return (StatusCode::LOCKED, format!("{e:#}"));
+            }
+            error!("erasing profile {profile_id:?} failed: {e:#}");
+            (StatusCode::INTERNAL_SERVER_ERROR, String::new())
+        })?;
+    Ok(Json(report))
+}
+
+#[derive(Debug, Serialize)]
+struct FriendLocation {
+    world_id: Uuid,
+    world_name: Option<String>,
+    player_id: Uuid,
+}
+
+#[derive(Debug, Serialize)]
+struct FriendStatus {
+    #[serde(flatten)]
+    friend: friends::FriendV1,
+    online: bool,
+    /// Worlds served by this host the friend is in right now.
+    worlds: Vec<FriendLocation>,
+}
+
+async fn friends_presence(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Query(q): axum::extract::Query<ProfileQuery>,
+) -> Result<Json<Vec<FriendStatus>>, StatusCode> {
+    require_auth(&headers, &st)?;
+    let profile_id = &profile_for(&headers, &st, q.profile_id.as_deref())?;
+    friends::check_profile_id(profile_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let f = friends::load(&st.store, profile_id).map_err(|e| {
+        error!("load friends failed: {e:#}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let names: BTreeMap<Uuid, String> = st
+        .store
+        .list_worlds()
+        .map_err(|e| {
+            error!("list worlds failed: {e:#}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .into_iter()
+        .map(|m| (m.world_id, m.name))
+        .collect();
+    let out = f
+        .friends
+        .into_iter()
+        .map(|friend| {
+            let worlds: Vec<FriendLocation> = st
+                .roster
+                .find_listed(&friend.id)
+                .into_iter()
+                .map(|(world_id, player_id)| FriendLocation {
+                    world_id,
+                    world_name: names.get(&world_id).cloned(),
+                    player_id,
+                })
+                .collect();
+            FriendStatus {
+                friend,
+                online: !worlds.is_empty(),
+                worlds,
+            }
+        })
+        .collect();
+    Ok(Json(out))
+}
+
+#[derive(Debug, Deserialize)]
+struct AvatarMeshGenerateRequest {
+    prompt: String,
+    #[serde(default)]
+    profile_id: Option<String>,
+    /// Run the provider even if an identical request is cached.
+    #[serde(default)]
+    force: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct AvatarMeshGenerateResponse {
+    avatar: AvatarSpecV1,
+}
+
+async fn generate_avatar_mesh(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<AvatarMeshGenerateRequest>,
+) -> Result<Json<AvatarMeshGenerateResponse>, StatusCode> {
+    require_auth(&headers, &st)?;
+
+    let cfg = assistant::load_config(&st.store).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if cfg.provider.is_none() {
+        return Err(StatusCode::PRECONDITION_FAILED);
+    };
+
+    let profile_id = &profile_for(&headers, &st, req.profile_id.as_deref())?;
+    charge_assistant_job(&st, profile_id)?;
+
+    let avatar =
+        avatar_mesh_mod::generate_avatar_mesh(&st.store, &cfg, profile_id, &req.prompt, req.force)
+            .await;
+    record_generation(&st, &cfg, avatar.is_ok());
+    let avatar = avatar.map_err(|e| {
+        error!("avatar mesh generation failed: {e:#}");
+        e.downcast_ref()
+            .map_or(StatusCode::INTERNAL_SERVER_ERROR, assistant_failure)
+    })?;
+
+    Ok(Json(AvatarMeshGenerateResponse { avatar }))
+}
+
+#[derive(Debug, Deserialize)]
+struct AvatarMeshImportQuery {
+    /// "obj", "gltf" or "glb"; guessed from the upload when absent.
+    #[serde(default)]
+    format: Option<String>,
+    #[serde(default)]
+    profile_id: Option<String>,
+}
+
+async fn import_avatar_mesh(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Query(q): axum::extract::Query<AvatarMeshImportQuery>,
+    body: axum::body::Bytes,
+) -> Result<Json<AvatarMeshGenerateResponse>, StatusCode> {
+    require_auth(&headers, &st)?;
+    let format = match q.format.as_deref() {
+        Some(f) => avatar_import::ImportFormat::parse(f).ok_or(StatusCode::BAD_REQUEST)?,
+        None => avatar_import::ImportFormat::sniff(&body),
+    };
+    let mesh = avatar_import::convert(&body, format).map_err(|e| {
+        warn!("avatar mesh import rejected: {e:#}");
+        StatusCode::BAD_REQUEST
+    })?;
+    let profile_id = &profile_for(&headers, &st, q.profile_id.as_deref())?;
+    let avatar = avatar_import::install(&st.store, profile_id, &mesh).map_err(|e| {
+        error!("installing imported avatar mesh failed: {e:#}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    info!(
+        "imported {format:?} avatar mesh for {profile_id} ({} triangles)",
+        mesh.triangles
+    );
+
+    Ok(Json(AvatarMeshGenerateResponse { avatar }))
+}
+
+#[derive(Debug, Deserialize)]
+struct AvatarMeshQuery {
+    #[serde(default)]
+    profile_id: Option<String>,
+    #[serde(default)]
+    part: Option<String>,
+}
+
+async fn get_avatar_mesh(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Query(q): axum::extract::Query<AvatarMeshQuery>,
+) -> Result<axum::response::Response, StatusCode> {
+    require_auth(&headers, &st)?;
+    let profile_id = &avatar_profile_for(&headers, &st, q.profile_id.as_deref())?;
+    let part = q.part.as_deref();
+    let exists = match part {
+        None => avatar_mesh_mod::avatar_mesh_exists(&st.store, profile_id),
+        Some("body") => avatar_mesh_mod::avatar_mesh_exists(&st.store, profile_id),
+        Some(p) => avatar_mesh_mod::avatar_mesh_part_exists(&st.store, profile_id, p),
+    };
+    if !exists {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let bytes = avatar_mesh_mod::read_mesh_bytes(&st.store, profile_id, part)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok((
+        StatusCode::OK,
+        [(axum::http::header::CONTENT_TYPE, "application/octet-stream")],
+        bytes,
+    )
+        .into_response())
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct FsckRequest {
+    #[serde(default)]
+    world_id: Option<String>,
+    #[serde(default)]
+    repair: bool,
+}
+
+async fn run_fsck(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<FsckRequest>,
+) -> Result<Json<fsck::FsckReport>, StatusCode> {
+    require_admin(&headers, &st)?;
+    let world_id = match req.world_id {
+        Some(id) => Some(Uuid::parse_str(&id).map_err(|_| StatusCode::BAD_REQUEST)?),
+        None => None,
+    };
+    let report = fsck::check_store(&st.store, world_id, req.repair).map_err(|e| {
+        error!("fsck failed: {e:#}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(report))
+}
+
+#[derive(Debug, Serialize)]
+struct BackupStatus {
+    configured: bool,
+    #[serde(flatten)]
+    state: backup::BackupStateV1,
+}
+
+async fn get_backup_status(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<BackupStatus>, StatusCode> {
+    require_admin(&headers, &st)?;
+    let state = backup::load_state(&st.store).map_err(|e| {
+        error!("reading backup state failed: {e:#}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(BackupStatus {
+        configured: st.config.current().backup.is_some(),
+        state,
+    }))
+}
+
+/// Back up now instead of waiting for the schedule.
+async fn run_backup(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<backup::BackupReport>, (StatusCode, String)> {
+    require_admin(&headers, &st).map_err(|s| (s, String::new()))?;
+    let cfg = st.config.current().backup.ok_or((
+        StatusCode::CONFLICT,
+        "no `backup` section in server.json".to_string(),
+    ))?;
+    let report = backup::run(&st.store, &cfg).await.map_err(|e| {
+        error!("backup failed: {e:#}");
+        (StatusCode::BAD_GATEWAY, format!("{e:#}"))
+    })?;
+    Ok(Json(report))
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ReplicationQuery {
+    /// Last sequence the follower applied; absent asks for a snapshot.
+    #[serde(default)]
+    since: Option<u64>,
+}
+
+/// What a read replica at `since` needs to catch up (see `replica.rs`).
+async fn get_replication(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(world_id): Path<String>,
+    axum::extract::Query(q): axum::extract::Query<ReplicationQuery>,
+) -> Result<Json<replica::ReplicationBatchV1>, StatusCode> {
+    require_admin(&headers, &st)?;
+    let world_id = Uuid::parse_str(&world_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let dir = st.store.world_dir(world_id);
+    if !dir.exists() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let store = st.store.clone();
+    let batch = tokio::task::spawn_blocking(move || replica::batch(&store, &dir, q.since))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|e| {
+            error!("replication batch for {world_id} failed: {e:#}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(Json(batch))
+}
+
+#[derive(Debug, Serialize)]
+struct QuotaStatus {
+    limits: quota::WorldQuota,
+    usage: quota::WorldUsageV1,
+}
+
+/// The world's budgets and what this process has seen it use.
+async fn get_quota(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(world_id): Path<String>,
+) -> Result<Json<QuotaStatus>, StatusCode> {
+    require_admin(&headers, &st)?;
+    let world_id = Uuid::parse_str(&world_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    if !st.store.world_dir(world_id).exists() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let budget = st.quotas.world(world_id, &st.config);
+    Ok(Json(QuotaStatus {
+        limits: budget.limits(),
+        usage: budget.usage(),
+    }))
+}
+
+/// The world's migration state; `null` when it isn't being moved.
+async fn get_migration(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(world_id): Path<String>,
+) -> Result<Json<Option<migration::MigrationStateV1>>, StatusCode> {
+    require_admin(&headers, &st)?;
+    let world_id = Uuid::parse_str(&world_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let dir = st.store.world_dir(world_id);
+    if !dir.exists() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let state = migration::load(&dir).map_err(|e| {
+        error!("reading migration state of {world_id} failed: {e:#}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(state))
+}
+
+/// Move the world to another host (see `migration.rs`).
+async fn start_migration(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(world_id): Path<String>,
+    Json(req): Json<migration::MigrateRequest>,
+) -> Result<Json<migration::MigrationStateV1>, (StatusCode, String)> {
+    require_admin(&headers, &st).map_err(|s| (s, String::new()))?;
+    let world_id =
+        Uuid::parse_str(&world_id).map_err(|_| (StatusCode::BAD_REQUEST, String::new()))?;
+    if !st.store.world_dir(world_id).exists() {
+        return Err((StatusCode::NOT_FOUND, String::new()));
+    }
+    let state = migration::migrate(&st.store, world_id, &req)
+        .await
+        .map_err(|e| {
+            error!("migrating {world_id} failed: {e:#}");
+            (StatusCode::BAD_GATEWAY, format!("{e:#}"))
+        })?;
+    info!(
+        "world {world_id} moved to {}",
+        state.connect.as_deref().unwrap_or_default()
+    );
+    Ok(Json(state))
+}
+
+/// Lift a migration freeze. After a completed move this also stops redirecting clients.
+async fn abort_migration(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(world_id): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    require_admin(&headers, &st)?;
+    let world_id = Uuid::parse_str(&world_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let dir = st.store.world_dir(world_id);
+    if !dir.exists() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let cleared = migration::clear(&dir).map_err(|e| {
+        error!("lifting the migration freeze of {world_id} failed: {e:#}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(if cleared {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    })
+}
+
+/// Freeze the world and download it as a tar, for a manual `POST /worlds/import` elsewhere.
+async fn export_world(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(world_id): Path<String>,
+) -> Result<axum::response::Response, StatusCode> {
+    require_admin(&headers, &st)?;
+    let world_id = Uuid::parse_str(&world_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let dir = st.store.world_dir(world_id);
+    if !dir.exists() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let store = st.store.clone();
+    let archive = tokio::task::spawn_blocking(move || migration::export(&store, &dir))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|e| {
+            error!("exporting {world_id} failed: {e:#}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok((
+        [(axum::http::header::CONTENT_TYPE, "application/x-tar")],
+        archive,
+    )
+        .into_response())
+}
+
+/// Install a world exported by another host.
+async fn import_world(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    body: axum::body::Bytes,
+) -> Result<Json<migration::ImportReport>, (StatusCode, String)> {
+    require_admin(&headers, &st).map_err(|s| (s, String::new()))?;
+    let store = st.store.clone();
+    let endpoint = st.config.current().public_endpoint;
+    let report = tokio::task::spawn_blocking(move || {
+        migration::import_report(&store, &body, endpoint.as_deref())
+    })
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, String::new()))?
+    .map_err(|e| {
+        let status = if e.is::<migration::AlreadyExists>() {
+            StatusCode::CONFLICT
+        } else {
+            StatusCode::BAD_REQUEST
+        };
+        warn!("world import rejected: {e:#}");
+        (status, format!("{e:#}"))
+    })?;
+    Ok(Json(report))
+}
+
+/// On a read replica, refuse anything that could change state: the leader owns it.
+async fn refuse_writes(
+    req: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    use axum::http::Method;
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return next.run(req).await;
+    }
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        "read replica; send changes to the leader",
+    )
+        .into_response()
+}
+
+/// The admin REST API. Also what the gRPC facade forwards to.
+#[allow(clippy::too_many_arguments)]
+pub fn router(
+    store: WorldStore,
+    auth: AuthMode,
+    discovery: DiscoveryConfig,
+    services: HealthRegistry,
+    config: LiveConfig,
+    roster: Roster,
+    pairing: Pairing,
+    asset_key: AssetTokenKey,
+    quotas: Quotas,
+) -> Router {
+    let cors = CorsLayer::new()
+        .allow_methods(Any)
+        .allow_headers(Any)
+        .allow_origin(Any);
+
+    Router::new()
+        .route("/health", get(health))
+        .route("/health/services", get(services_health))
+        .route("/assistant/status", get(assistant_status))
+        .route("/assistant/provider", post(set_provider))
+        .route(
+            "/assistant/config",
+            get(get_assistant_config).post(set_assistant_config),
+        )
+        .route("/assistant/chat", post(assistant_chat))
+        .route("/assistant/redo", post(assistant_redo))
+        .route("/assistant/undo", post(assistant_undo))
+        .route("/assistant/cache", delete(clear_generation_cache))
+        .route("/world/chat", get(world_chat_history).post(world_chat))
+        .route(
+            "/avatar",
+            get(get_avatar)
+                .put(put_avatar)
+                .layer(DefaultBodyLimit::max(AVATAR_UPLOAD_MAX_BYTES)),
+        )
+        .route("/avatar/diff", get(get_avatar_diff))
+        .route("/avatar/generate", post(generate_avatar))
+        .route("/avatar/mesh", get(get_avatar_mesh))
+        .route("/avatar/mesh/generate", post(generate_avatar_mesh))
+        .route("/avatar/revisions", get(list_avatar_revisions))
+        .route("/avatar/revisions/:revision", get(get_avatar_revision))
+        .route(
+            "/avatar/mesh/import",
+            post(import_avatar_mesh).layer(DefaultBodyLimit::max(avatar_import::MAX_IMPORT_BYTES)),
+        )
+        .route("/avatar/wardrobe", get(get_wardrobe).post(put_outfit))
+        .route("/avatar/wardrobe/:name", delete(remove_outfit))
+        .route("/avatar/wardrobe/:name/wear", post(wear_outfit))
+        .route("/friends", get(list_friends).post(add_friend))
+        .route("/friends/presence", get(friends_presence))
+        .route("/friends/:id", delete(remove_friend))
+        .route(
+            "/profiles/:profile_id/privacy",
+            get(get_privacy).patch(patch_privacy),
+        )
+        .route("/profiles/:profile_id/export", get(export_profile))
+        .route("/profiles/:profile_id/erase", post(erase_profile))
+        .route("/content/:sha256", get(get_content))
+        .route("/admin/log-level", get(get_log_level).put(set_log_level))
+        .route("/logs/recent", get(get_recent_logs))
+        .route("/admin/tap", get(tap_messages))
+        .route(
+            "/admin/tap/file",
+            get(get_tap_file).put(start_tap_file).delete(stop_tap_file),
+        )
+        .route("/admin/pairing/qr", get(get_pairing_qr))
+        .route("/auth/pair/start", post(pair_start))
+        .route("/auth/pair/complete", post(pair_complete))
+        .route("/auth/keys", get(list_api_keys))
+        .route("/auth/keys/:id", delete(revoke_api_key))
+        .route("/auth/login", post(login))
+        .route("/auth/logout", post(logout))
+        .route("/auth/accounts", get(list_accounts).post(create_account))
+        .route("/auth/accounts/:username", delete(delete_account))
+        .route(
+            "/auth/accounts/:username/password",
+            put(set_account_password),
+        )
+        .route(
+            "/admin/access",
+            get(get_global_access).put(set_global_access),
+        )
+        .route("/config", get(get_config))
+        .route("/metrics", get(metrics))
+        .route("/telemetry/preview", get(get_telemetry_preview))
+        .route("/crashes", get(list_crashes))
+        .route("/crashes/:name", get(get_crash))
+        .route("/fsck", post(run_fsck))
+        .route("/backup", get(get_backup_status).post(run_backup))
+        .route("/worlds", get(list_worlds).post(create_world))
+        .route(
+            "/worlds/import",
+            post(import_world).layer(DefaultBodyLimit::max(migration::MAX_ARCHIVE_BYTES)),
+        )
+        .route("/discovery/worlds", get(discovery_worlds))
+        .route("/discovery/worlds/summary", get(discovery_summary))
+        .route("/discovery/registry", get(discovery_registry))
+        .route("/discovery/aliases/:alias", get(resolve_alias))
+        .route("/favorites", get(list_favorites).post(add_favorite))
+        .route("/favorites/:world_id", delete(remove_favorite))
+        .route("/favorites/:world_id/joined", post(favorite_joined))
+        .route(
+            "/discovery/worlds/:world_id/reputation",
+            get(get_reputation),
+        )
+        .route("/discovery/worlds/:world_id/rating", post(rate_world))
+        .route("/discovery/worlds/:world_id/report", post(report_world))
+        .route("/worlds/:world_id/manifest", get(get_manifest))
+        .route("/worlds/:world_id/minimap", get(get_minimap))
+        .route("/worlds/:world_id/publish-result", post(publish_result))
+        .route("/worlds/:world_id/token/create", post(create_token))
+        .route("/worlds/:world_id/sim", get(get_sim_state))
+        .route(
+            "/worlds/:world_id/ledger",
+            get(get_ledger).post(apply_ledger_change),
+        )
+        .route("/worlds/:world_id/simulation", post(set_simulation_config))
+        .route("/worlds/:world_id/assets", post(set_assets_config))
+        .route("/worlds/:world_id/prefetch", get(get_prefetch))
+        .route("/worlds/:world_id/assets/*path", get(get_world_asset))
+        .route(
+            "/worlds/:world_id/bandwidth",
+            get(get_bandwidth).post(set_bandwidth_config),
+        )
+        .route(
+            "/worlds/:world_id/entry-fee",
+            get(get_entry_fee).post(set_entry_fee),
+        )
+        .route("/worlds/:world_id/rules", get(get_rules).post(set_rules))
+        .route(
+            "/worlds/:world_id/players/:player_id/kick",
+            post(kick_player),
+        )
+        .route("/worlds/:world_id/sync", get(get_sync_manifest))
+        .route("/worlds/:world_id/replication", get(get_replication))
+        .route("/worlds/:world_id/quota", get(get_quota))
+        .route(
+            "/worlds/:world_id/migration",
+            get(get_migration)
+                .post(start_migration)
+                .delete(abort_migration),
+        )
+        .route("/worlds/:world_id/export", get(export_world))
+        .route("/worlds/:world_id/emotes", post(set_emotes_config))
+        .route("/worlds/:world_id/listing", post(set_listing))
+        .route("/worlds/:world_id/qr", get(get_world_qr))
+        .route("/worlds/:world_id/scatter", post(scatter_objects))
+        .route("/worlds/:world_id/prefabs", get(get_prefab_bundle))
+        .route(
+            "/worlds/:world_id/prefabs/bundle",
+            post(build_prefab_bundle),
+        )
+        .route(
+            "/worlds/:world_id/access",
+            get(get_world_access).put(set_world_access),
+        )
+        .route(
+            "/worlds/:world_id/chunks/:x/:z",
+            get(get_chunk).post(apply_chunk_change),
+        )
+        .with_state(AppState {
+            store,
+            auth,
+            discovery,
+            health: services,
+            config,
+            roster,
+            summary: SummaryCache::default(),
+            pairing,
+            sessions: Sessions::default(),
+            quotas,
+            asset_key,
+        })
+        .layer(cors)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn serve(
+    listen: String,
+    store: WorldStore,
+    auth: AuthMode,
+    discovery: DiscoveryConfig,
+    services: HealthRegistry,
+    config: LiveConfig,
+    roster: Roster,
+    grpc_listen: Option<String>,
+    read_only: bool,
+    quotas: Quotas,
+) -> Result<()> {
+    services.set("admin", ServiceState::Starting, None);
+    let addr: SocketAddr = listen.parse().context("parse listen addr")?;
+
+    for m in store.list_worlds()? {
+        let dir = store.world_dir(m.world_id);
+        if let Err(e) = wal::recover(&dir) {
+            error!("wal recovery failed for {}: {e:#}", m.world_id);
+        }
+    }
+    match fsck::check_store(&store, None, false) {
+        Ok(report) => fsck::warn_issues(&report),
+        Err(e) => error!("startup integrity check failed: {e:#}"),
+    }
+    tokio::spawn(checkpoint_loop(store.clone()));
+    backup::spawn_schedule(store.clone(), config.clone());
+    provider_health::spawn_webhook(config.clone());
+    services.set(
+        "scheduler",
+        ServiceState::Running,
+        Some("wal checkpoints, backups".to_string()),
+    );
+
+    let pairing = Pairing::load(&store)?;
+    let asset_key = AssetTokenKey::load_or_create(&store)?;
+    let mut app = router(
+        store,
+        auth,
+        discovery,
+        services.clone(),
+        config,
+        roster,
+        pairing,
+        asset_key,
+        quotas,
+    );
+    if read_only {
+        app = app.layer(axum::middleware::from_fn(refuse_writes));
+    }
+    if let Some(listen) = grpc_listen {
+        let router = app.clone();
+        let services = services.clone();
+        tokio::spawn(async move {
+            if let Err(e) = grpc::serve(listen, router, services.clone()).await {
+                error!("grpc facade stopped: {e:#}");
+                services.set("grpc", ServiceState::Failed, Some(format!("{e:#}")));
+            }
+        });
+    }
+
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    info!("OWP admin API listening on http://{addr}");
+    services.set(
+        "admin",
+        ServiceState::Running,
+        Some(format!("http://{addr}")),
+    );
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+/// Periodically fold world WALs into chunk/ledger files.
+async fn checkpoint_loop(store: WorldStore) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(30));
+    loop {
+        interval.tick().await;
+        let Ok(worlds) = store.list_worlds() else {
+            continue;
+        };
+        for m in worlds {
+            let dir = store.world_dir(m.world_id);
+            if let Err(e) = wal::checkpoint(&dir) {
+                error!("wal checkpoint failed for {}: {e:#}", m.world_id);
+            }
+        }
+    }
+}
+
+/// Per-world handshake timeout when liveness probing is on.
+const DISCOVERY_PROBE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+#[derive(Debug, Default, Deserialize)]
+struct DiscoveryQuery {
+    /// Hide worlds whose reputation score is below this (0.0 - 1.0).
     #[serde(default)]
-    profile_id: Option<String>,
+    min_score: Option<f32>,
+    /// Hide worlds with more abuse reports than this.
+    #[serde(default)]
+    max_reports: Option<usize>,
+    /// Hide worlds that staked fewer lamports than this in the registry.
+    #[serde(default)]
+    min_stake: Option<u64>,
+    /// `score` sorts by reputation, best first; `latency` by the latest probe round trip,
+    /// fastest first (unprobed or unreachable worlds last); `stake` by registry stake, most
+    /// first.
+    #[serde(default)]
+    sort: Option<String>,
+    /// `region` groups the result by hosting region, keeping the sort order within a group.
+    #[serde(default)]
+    group: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
-struct AssistantChatResponse {
-    reply: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    avatar: Option<AvatarSpecV1>,
+/// Status for a failed discovery call: the caller's input, a missing resource, or an upstream
+/// (proxy, RPC node, directory host) that is down or misbehaving.
+fn discovery_status(e: &owp_discovery::Error) -> StatusCode {
+    use owp_discovery::Error;
+    match e {
+        Error::Invalid(_) => StatusCode::BAD_REQUEST,
+        Error::NotFound(_) => StatusCode::NOT_FOUND,
+        Error::ProviderUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+        Error::Unauthorized(_) | Error::RpcError(_) => StatusCode::BAD_GATEWAY,
+        Error::Corrupt(_) | Error::Io { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+    }
 }
 
-async fn assistant_chat(
-    State(st): State<AppState>,
-    headers: HeaderMap,
-    Json(req): Json<AssistantChatRequest>,
-) -> Result<Json<AssistantChatResponse>, StatusCode> {
-    require_auth(&headers, &st.auth)?;
-
-    let cfg = assistant::load_config(&st.store).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    if cfg.provider.is_none() {
+/// Worlds from the on-chain registry plus configured directory files.
+async fn listed_worlds(st: &AppState) -> Result<Vec<WorldDirectoryEntry>, StatusCode> {
+    let chain = match (
+        st.discovery.solana_rpc_url.as_deref(),
+        st.discovery.registry_program_id.as_deref(),
+    ) {
+        (Some(rpc_url), Some(program_id)) => Some((rpc_url, program_id)),
+        _ => None,
+    };
+    if chain.is_none() && st.discovery.directory_urls.is_empty() {
         return Err(StatusCode::PRECONDITION_FAILED);
+    }
+
+    let mut worlds = match chain {
+        Some((rpc_url, program_id)) => {
+            owp_discovery::fetch_worlds_from_rpc(rpc_url, program_id, st.discovery.proxy.as_ref())
+                .await
+                .map_err(|e| {
+                    error!("discovery fetch failed: {e:#}");
+                    discovery_status(&e)
+                })?
+        }
+        None => Vec::new(),
     };
 
-    let profile_id = req.profile_id.as_deref().unwrap_or("local");
-    let out = assistant::companion_chat(&st.store, &cfg, profile_id, &req.message)
+    // Directory files only add worlds the chain doesn't already list; a broken file is skipped.
+    for url in &st.discovery.directory_urls {
+        match directory::fetch_directory(
+            url,
+            &st.discovery.trusted_directory_keys,
+            st.discovery.proxy.as_ref(),
+        )
         .await
-        .map_err(|e| {
-            error!("assistant chat failed: {e:#}");
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+        {
+            Ok(entries) => {
+                for e in entries {
+                    if !worlds.iter().any(|w| w.world_id == e.world_id) {
+                        worlds.push(e);
+                    }
+                }
+            }
+            Err(e) => error!("directory {url} skipped: {e:#}"),
+        }
+    }
+    Ok(worlds)
+}
 
-    Ok(Json(AssistantChatResponse {
-        reply: out.reply,
-        avatar: out.avatar,
-    }))
+/// Probe `worlds` when liveness probing is on, recording the results for reputation.
+async fn probe_listed(st: &AppState, worlds: &[WorldDirectoryEntry]) -> Vec<probe::ProbeResult> {
+    if !st.discovery.probe_liveness {
+        return vec![];
+    }
+    let results =
+        probe::probe_all(worlds, DISCOVERY_PROBE_TIMEOUT, st.discovery.proxy.as_ref()).await;
+    if let Err(e) = reputation::record_probes(&st.store, &results) {
+        error!("recording probe results failed: {e:#}");
+    }
+    results
 }
 
-#[derive(Debug, Deserialize)]
-struct AvatarGenerateRequest {
-    prompt: String,
-    #[serde(default)]
-    profile_id: Option<String>,
+/// The on-chain registry as read: listed worlds plus counts of accounts that couldn't be read.
+async fn discovery_registry(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<owp_discovery::RegistryScan>, StatusCode> {
+    require_auth(&headers, &st)?;
+    let (Some(rpc_url), Some(program_id)) = (
+        st.discovery.solana_rpc_url.as_deref(),
+        st.discovery.registry_program_id.as_deref(),
+    ) else {
+        return Err(StatusCode::PRECONDITION_FAILED);
+    };
+    owp_discovery::scan_registry(rpc_url, program_id, st.discovery.proxy.as_ref())
+        .await
+        .map(Json)
+        .map_err(|e| {
+            error!("registry scan failed: {e:#}");
+            discovery_status(&e)
+        })
 }
 
 #[derive(Debug, Serialize)]
-struct AvatarGenerateResponse {
-    avatar: AvatarSpecV1,
+struct ResolvedAlias {
+    connect: String,
+    world: WorldDirectoryEntry,
 }
 
-async fn get_avatar(
+/// The world a registry alias points at, and its connect string.
+async fn resolve_alias(
     State(st): State<AppState>,
     headers: HeaderMap,
-) -> Result<Json<Option<AvatarSpecV1>>, StatusCode> {
-    require_auth(&headers, &st.auth)?;
-    let avatar = avatar_mod::load_avatar(&st.store, "local")
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    Ok(Json(avatar))
+    Path(alias): Path<String>,
+) -> Result<Json<ResolvedAlias>, StatusCode> {
+    require_auth(&headers, &st)?;
+    let (Some(rpc_url), Some(program_id)) = (
+        st.discovery.solana_rpc_url.as_deref(),
+        st.discovery.registry_program_id.as_deref(),
+    ) else {
+        return Err(StatusCode::PRECONDITION_FAILED);
+    };
+    let world = alias::resolve_alias(rpc_url, program_id, &alias, st.discovery.proxy.as_ref())
+        .await
+        .map_err(|e| {
+            error!("resolve alias {alias} failed: {e:#}");
+            discovery_status(&e)
+        })?;
+    Ok(Json(ResolvedAlias {
+        connect: alias::listing_connect_string(&world),
+        world,
+    }))
 }
 
-async fn generate_avatar(
+/// Compact, cached view of `/discovery/worlds` for launchers that poll it. Honors
+/// `If-None-Match`.
+async fn discovery_summary(
     State(st): State<AppState>,
     headers: HeaderMap,
-    Json(req): Json<AvatarGenerateRequest>,
-) -> Result<Json<AvatarGenerateResponse>, StatusCode> {
-    require_auth(&headers, &st.auth)?;
+) -> Result<axum::response::Response, StatusCode> {
+    use axum::http::header;
 
-    let cfg = assistant::load_config(&st.store).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    if cfg.provider.is_none() {
+    require_auth(&headers, &st)?;
+    let chain = st.discovery.solana_rpc_url.is_some() && st.discovery.registry_program_id.is_some();
+    if !chain && st.discovery.directory_urls.is_empty() {
         return Err(StatusCode::PRECONDITION_FAILED);
-    };
-
-    let avatar = avatar_mod::generate_avatar(&st.store, &cfg, &req.prompt)
+    }
+    let snapshot = st
+        .summary
+        .get(|| async {
+            let worlds = listed_worlds(&st)
+                .await
+                .map_err(|code| anyhow::anyhow!("listing worlds failed ({code})"))?;
+            let probes = probe_listed(&st, &worlds).await;
+            let rep = reputation::load(&st.store)?;
+            Ok(world_summary::summarize(worlds, &probes, &rep))
+        })
         .await
         .map_err(|e| {
-            error!("avatar generation failed: {e:#}");
+            error!("building world summary failed: {e:#}");
             StatusCode::INTERNAL_SERVER_ERROR
         })?;
 
-    let profile_id = req.profile_id.as_deref().unwrap_or("local");
-    avatar_mod::save_avatar(&st.store, profile_id, &avatar).map_err(|e| {
-        error!("saving avatar failed: {e:#}");
+    let cache_headers = [
+        (header::ETAG, snapshot.etag.clone()),
+        (
+            header::CACHE_CONTROL,
+            format!("max-age={}", snapshot.max_age_secs()),
+        ),
+    ];
+    let not_modified = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| {
+            v.split(',')
+                .map(|t| t.trim().trim_start_matches("W/"))
+                .any(|t| t == snapshot.etag || t == "*")
+        });
+    if not_modified {
+        return Ok((StatusCode::NOT_MODIFIED, cache_headers).into_response());
+    }
+    Ok((
+        StatusCode::OK,
+        cache_headers,
+        [(header::CONTENT_TYPE, "application/json")],
+        snapshot.body.clone(),
+    )
+        .into_response())
+}
+
+async fn discovery_worlds(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Query(q): axum::extract::Query<DiscoveryQuery>,
+) -> Result<Json<Vec<WorldDirectoryEntry>>, StatusCode> {
+    require_auth(&headers, &st)?;
+    let worlds = listed_worlds(&st).await?;
+    probe_listed(&st, &worlds).await;
+
+    let rep = reputation::load(&st.store).map_err(|e| {
+        error!("load reputation failed: {e:#}");
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
+    let mut scored: Vec<(f32, WorldDirectoryEntry)> = worlds
+        .into_iter()
+        .filter_map(|mut w| {
+            let r = rep.get(w.world_id);
+            w.rtt_ms = r.last_rtt_ms();
+            let score = r.score();
+            if q.min_score.is_some_and(|min| score < min)
+                || q.max_reports.is_some_and(|max| r.reports.len() > max)
+                || q.min_stake
+                    .is_some_and(|min| w.stake_lamports.unwrap_or(0) < min)
+            {
+                return None;
+            }
+            Some((score, w))
+        })
+        .collect();
+    if q.sort.as_deref() == Some("score") {
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+    }
 
-    Ok(Json(AvatarGenerateResponse { avatar }))
+    let mut worlds: Vec<WorldDirectoryEntry> = scored.into_iter().map(|(_, w)| w).collect();
+    let group_by_region = q.group.as_deref() == Some("region");
+    if q.sort.as_deref() == Some("stake") {
+        owp_discovery::sort_by_stake(&mut worlds);
+    }
+    if q.sort.as_deref() == Some("latency") {
+        probe::sort_by_latency(&mut worlds, group_by_region);
+    } else if group_by_region {
+        worlds.sort_by_key(|w| (w.region.is_none(), w.region.clone()));
+    }
+    Ok(Json(worlds))
+}
+
+async fn list_favorites(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<favorites::FavoritesV1>, StatusCode> {
+    require_auth(&headers, &st)?;
+    let f = favorites::load(&favorites_path(st.store.root_dir())).map_err(|e| {
+        error!("load favorites failed: {e:#}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(f))
 }
 
 #[derive(Debug, Deserialize)]
-struct AvatarMeshGenerateRequest {
-    prompt: String,
+struct AddFavoriteRequest {
+    connect: String,
     #[serde(default)]
-    profile_id: Option<String>,
+    name: Option<String>,
+    #[serde(default)]
+    note: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
-struct AvatarMeshGenerateResponse {
-    avatar: AvatarSpecV1,
+async fn add_favorite(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<AddFavoriteRequest>,
+) -> Result<Json<favorites::Favorite>, StatusCode> {
+    require_auth(&headers, &st)?;
+    let path = favorites_path(st.store.root_dir());
+    let (_, f) = favorites::add(&path, &req.connect, req.name, req.note).map_err(|e| {
+        if !matches!(e, owp_discovery::Error::Invalid(_)) {
+            error!("add favorite failed: {e:#}");
+        }
+        discovery_status(&e)
+    })?;
+    Ok(Json(f))
 }
 
-async fn generate_avatar_mesh(
+async fn remove_favorite(
     State(st): State<AppState>,
     headers: HeaderMap,
-    Json(req): Json<AvatarMeshGenerateRequest>,
-) -> Result<Json<AvatarMeshGenerateResponse>, StatusCode> {
-    require_auth(&headers, &st.auth)?;
-
-    let cfg = assistant::load_config(&st.store).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    if cfg.provider.is_none() {
-        return Err(StatusCode::PRECONDITION_FAILED);
-    };
-
-    let profile_id = req.profile_id.as_deref().unwrap_or("local");
+    Path(world_id): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    require_auth(&headers, &st)?;
+    let world_id = Uuid::parse_str(&world_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let removed =
+        favorites::remove(&favorites_path(st.store.root_dir()), world_id).map_err(|e| {
+            error!("remove favorite failed: {e:#}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(if removed {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    })
+}
 
-    let avatar = avatar_mesh_mod::generate_avatar_mesh(&st.store, &cfg, profile_id, &req.prompt)
-        .await
-        .map_err(|e| {
-            error!("avatar mesh generation failed: {e:#}");
+/// The launcher reports a successful join so `last_joined_at` stays current.
+async fn favorite_joined(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(world_id): Path<String>,
+) -> Result<Json<favorites::Favorite>, StatusCode> {
+    require_auth(&headers, &st)?;
+    let world_id = Uuid::parse_str(&world_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let f =
+        favorites::mark_joined(&favorites_path(st.store.root_dir()), world_id).map_err(|e| {
+            error!("update favorite failed: {e:#}");
             StatusCode::INTERNAL_SERVER_ERROR
         })?;
+    f.map(Json).ok_or(StatusCode::NOT_FOUND)
+}
 
-    Ok(Json(AvatarMeshGenerateResponse { avatar }))
+#[derive(Debug, Serialize)]
+struct ReputationResponse {
+    world_id: Uuid,
+    score: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uptime: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    average_rating: Option<f32>,
+    #[serde(flatten)]
+    detail: reputation::WorldReputation,
+}
+
+fn reputation_response(world_id: Uuid, r: reputation::WorldReputation) -> ReputationResponse {
+    ReputationResponse {
+        world_id,
+        score: r.score(),
+        uptime: r.uptime(),
+        average_rating: r.average_rating(),
+        detail: r,
+    }
+}
+
+async fn get_reputation(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(world_id): Path<String>,
+) -> Result<Json<ReputationResponse>, StatusCode> {
+    require_auth(&headers, &st)?;
+    let world_id = Uuid::parse_str(&world_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let rep = reputation::load(&st.store).map_err(|e| {
+        error!("load reputation failed: {e:#}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(reputation_response(world_id, rep.get(world_id))))
 }
 
 #[derive(Debug, Deserialize)]
-struct AvatarMeshQuery {
+struct RateWorldRequest {
+    /// Who is rating (e.g. a profile id); rating again replaces the previous value.
+    rater: String,
+    stars: u8,
+}
+
+async fn rate_world(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    Path(world_id): Path<String>,
+    Json(req): Json<RateWorldRequest>,
+) -> Result<Json<ReputationResponse>, StatusCode> {
+    require_auth(&headers, &st)?;
+    let world_id = Uuid::parse_str(&world_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    if !(1..=5).contains(&req.stars) || req.rater.trim().is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let r = reputation::rate(&st.store, world_id, req.rater.trim(), req.stars).map_err(|e| {
+        error!("rate world failed: {e:#}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(reputation_response(world_id, r)))
+}
+
+#[derive(Debug, Deserialize)]
+struct ReportWorldRequest {
+    reason: reputation::ReportReason,
     #[serde(default)]
-    profile_id: Option<String>,
+    reporter: Option<String>,
     #[serde(default)]
-    part: Option<String>,
+    note: Option<String>,
 }
 
-async fn get_avatar_mesh(
+async fn report_world(
     State(st): State<AppState>,
     headers: HeaderMap,
-    axum::extract::Query(q): axum::extract::Query<AvatarMeshQuery>,
-) -> Result<axum::response::Response, StatusCode> {
-    require_auth(&headers, &st.auth)?;
-    let profile_id = q.profile_id.as_deref().unwrap_or("local");
-    let part = q.part.as_deref();
-    let exists = match part {
-        None => avatar_mesh_mod::avatar_mesh_exists(&st.store, profile_id),
-        Some("body") => avatar_mesh_mod::avatar_mesh_exists(&st.store, profile_id),
-        Some(p) => avatar_mesh_mod::avatar_mesh_part_exists(&st.store, profile_id, p),
+    Path(world_id): Path<String>,
+    Json(req): Json<ReportWorldRequest>,
+) -> Result<Json<ReputationResponse>, StatusCode> {
+    require_auth(&headers, &st)?;
+    let world_id = Uuid::parse_str(&world_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let report = reputation::AbuseReport {
+        reason: req.reason,
+        reporter: req.reporter.filter(|r| !r.trim().is_empty()),
+        note: req.note.filter(|n| !n.trim().is_empty()),
+        at: time::OffsetDateTime::now_utc(),
     };
-    if !exists {
-        return Err(StatusCode::NOT_FOUND);
-    }
-    let bytes = avatar_mesh_mod::read_mesh_bytes(&st.store, profile_id, part)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    Ok((
-        StatusCode::OK,
-        [(axum::http::header::CONTENT_TYPE, "application/octet-stream")],
-        bytes,
-    )
-        .into_response())
+    let r = reputation::report(&st.store, world_id, report).map_err(|e| {
+        error!("report world failed: {e:#}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(reputation_response(world_id, r)))
 }
 
-pub async fn serve(
-    listen: String,
-    store: WorldStore,
-    auth: AuthMode,
-    discovery: DiscoveryConfig,
-) -> Result<()> {
-    let addr: SocketAddr = listen.parse().context("parse listen addr")?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+    use tower::ServiceExt;
 
-    let cors = CorsLayer::new()
-        .allow_methods(Any)
-        .allow_headers(Any)
-        .allow_origin(Any);
+    struct Host {
+        _root: tempfile::TempDir,
+        store: WorldStore,
+        pairing: Pairing,
+        app: Router,
+    }
 
-    let app = Router::new()
-        .route("/health", get(health))
-        .route("/assistant/status", get(assistant_status))
-        .route("/assistant/provider", post(set_provider))
-        .route(
-            "/assistant/config",
-            get(get_assistant_config).post(set_assistant_config),
-        )
-        .route("/assistant/chat", post(assistant_chat))
-        .route("/avatar", get(get_avatar))
-        .route("/avatar/generate", post(generate_avatar))
-        .route("/avatar/mesh", get(get_avatar_mesh))
-        .route("/avatar/mesh/generate", post(generate_avatar_mesh))
-        .route("/worlds", get(list_worlds).post(create_world))
-        .route("/discovery/worlds", get(discovery_worlds))
-        .route("/worlds/:world_id/manifest", get(get_manifest))
-        .route("/worlds/:world_id/publish-result", post(publish_result))
-        .with_state(AppState {
+    fn host() -> Host {
+        let root = tempfile::tempdir().expect("tempdir");
+        let store = WorldStore::with_root(root.path().to_path_buf()).expect("store");
+        let pairing = Pairing::load(&store).expect("pairing");
+        let app = router(
+            store.clone(),
+            AuthMode::BearerToken("secret".to_string()),
+            DiscoveryConfig {
+                solana_rpc_url: None,
+                registry_program_id: None,
+                directory_urls: vec![],
+                trusted_directory_keys: vec![],
+                probe_liveness: false,
+                proxy: None,
+            },
+            HealthRegistry::default(),
+            LiveConfig::load(&store).expect("config"),
+            Roster::default(),
+            pairing.clone(),
+            AssetTokenKey::load_or_create(&store).expect("asset key"),
+            Quotas::default(),
+        );
+        Host {
+            _root: root,
             store,
-            auth,
-            discovery,
-        })
-        .layer(cors);
+            pairing,
+            app,
+        }
+    }
 
-    info!("OWP admin API listening on http://{addr}");
-    axum::serve(tokio::net::TcpListener::bind(addr).await?, app).await?;
-    Ok(())
-}
+    impl Host {
+        fn paired_key(&self, scope: Scope) -> String {
+            let code = self.pairing.start("phone", scope).code;
+            self.pairing
+                .redeem(&code)
+                .expect("redeem")
+                .expect("valid code")
+                .api_key
+        }
 
-async fn discovery_worlds(
-    State(st): State<AppState>,
-    headers: HeaderMap,
-) -> Result<Json<Vec<WorldDirectoryEntry>>, StatusCode> {
-    require_auth(&headers, &st.auth)?;
+        async fn post(&self, token: &str, uri: &str, body: serde_json::Value) -> StatusCode {
+            let req = Request::post(uri)
+                .header("authorization", format!("Bearer {token}"))
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .expect("request");
+            self.app
+                .clone()
+                .oneshot(req)
+                .await
+                .expect("response")
+                .status()
+        }
 
-    let Some(rpc_url) = st.discovery.solana_rpc_url.as_deref() else {
-        return Err(StatusCode::PRECONDITION_FAILED);
-    };
-    let Some(program_id) = st.discovery.registry_program_id.as_deref() else {
-        return Err(StatusCode::PRECONDITION_FAILED);
-    };
+        async fn get(&self, token: &str, uri: &str) -> StatusCode {
+            let req = Request::get(uri)
+                .header("authorization", format!("Bearer {token}"))
+                .body(Body::empty())
+                .expect("request");
+            self.app
+                .clone()
+                .oneshot(req)
+                .await
+                .expect("response")
+                .status()
+        }
+    }
 
-    let worlds = owp_discovery::fetch_worlds_from_rpc(rpc_url, program_id)
-        .await
-        .map_err(|e| {
-            error!("discovery fetch failed: {e:#}");
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+    #[tokio::test]
+    async fn client_keys_cannot_launch_tokens() {
+        let host = host();
+        let world = host.store.create_world("Harbor", 7777).expect("world");
+        let client = host.paired_key(Scope::Client);
+        let uri = format!("/worlds/{}/token/create", world.world_id);
+        let body = serde_json::json!({ "keypair_path": "/nonexistent/id.json" });
+        assert_eq!(host.post(&client, &uri, body).await, StatusCode::FORBIDDEN);
+    }
 
-    Ok(Json(worlds))
+    #[tokio::test]
+    async fn client_keys_cannot_redirect_entry_fees() {
+        let host = host();
+        let world = host.store.create_world("Harbor", 7777).expect("world");
+        let client = host.paired_key(Scope::Client);
+        let uri = format!("/worlds/{}/entry-fee", world.world_id);
+        let fee = serde_json::json!({
+            "recipient": "11111111111111111111111111111111",
+            "amount": 5,
+        });
+        assert_eq!(
+            host.post(&client, &uri, fee.clone()).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(host.post("secret", &uri, fee).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn client_keys_keep_to_their_own_profile_and_out_of_host_settings() {
+        let host = host();
+        let world = host.store.create_world("Harbor", 7777).expect("world");
+        let client = host.paired_key(Scope::Client);
+        let admin = host.paired_key(Scope::Admin);
+
+        let kick = format!("/worlds/{}/players/{}/kick", world.world_id, Uuid::new_v4());
+        let refused = host.post(&client, &kick, serde_json::json!({})).await;
+        assert_eq!(refused, StatusCode::FORBIDDEN);
+        let provider = serde_json::json!({ "provider": "none" });
+        let refused = host.post(&client, "/assistant/provider", provider).await;
+        assert_eq!(refused, StatusCode::FORBIDDEN);
+
+        let export = "/profiles/alice/export";
+        assert_eq!(host.get(&client, export).await, StatusCode::FORBIDDEN);
+        let erase = serde_json::json!({ "confirm": "alice" });
+        let refused = host.post(&client, "/profiles/alice/erase", erase).await;
+        assert_eq!(refused, StatusCode::FORBIDDEN);
+        assert_ne!(host.get(&admin, export).await, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn member_sessions_only_reach_their_own_profile() {
+        let host = host();
+        let world = host.store.create_world("Harbor", 7777).expect("world");
+        accounts::add(&host.store, "bob", "hunter22", None, false).expect("account");
+        let login = Request::post("/auth/login")
+            .header("content-type", "application/json")
+            .body(Body::from(
+                serde_json::json!({ "username": "bob", "password": "hunter22" }).to_string(),
+            ))
+            .expect("request");
+        let res = host.app.clone().oneshot(login).await.expect("login");
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .expect("body");
+        let session: serde_json::Value = serde_json::from_slice(&body).expect("json");
+        let bob = session["token"].as_str().expect("token");
+
+        let rules = format!("/worlds/{}/rules", world.world_id);
+        let refused = host.post(bob, &rules, serde_json::json!(null)).await;
+        assert_eq!(refused, StatusCode::FORBIDDEN);
+        let world_body = serde_json::json!({ "name": "Mine" });
+        assert_eq!(
+            host.post(bob, "/worlds", world_body).await,
+            StatusCode::FORBIDDEN
+        );
+
+        assert_eq!(host.get(bob, "/profiles/bob/privacy").await, StatusCode::OK);
+        assert_eq!(
+            host.get(bob, "/profiles/local/privacy").await,
+            StatusCode::FORBIDDEN
+        );
+    }
 }
@@ -0,0 +1,470 @@
+//! World-building chat: a design conversation with the assistant about one world. Every answer
+//! comes with a few object edits, the upserts and removals `POST /worlds/:id/chunks/:x/:z` takes,
+//! applied through the WAL as one batch, so the world is tweaked turn by turn instead of being
+//! regenerated. The conversation is kept in `assistant/chat_history.json` in the world directory,
+//! and each assistant turn records the edits it made and the chunk versions they produced.
+
+use anyhow::{bail, Context, Result};
+use owp_protocol::{ChunkChangeV1, ChunkCoord, WorldObjectV1};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::assistant::{self, AssistantConfig, AssistantError};
+use crate::chunks;
+use crate::storage::WorldStore;
+use crate::versioned::{self, Versioned};
+use crate::wal::{self, WalOp};
+
+/// Edits one answer may make. Filling an area goes through the scatter brush instead.
+pub const MAX_EDITS_PER_TURN: usize = 64;
+
+/// Turns kept in the history file.
+const HISTORY_LEN: usize = 80;
+
+/// Turns of history put in a prompt, at most.
+const PROMPT_TURNS: usize = 16;
+
+/// Objects listed in a prompt, at most; the rest are only counted.
+const PROMPT_OBJECTS: usize = 400;
+
+const MAX_ID_CHARS: usize = 64;
+
+const MAX_KIND_CHARS: usize = 64;
+
+/// An edit as applied, with the version of its chunk afterwards.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppliedEdit {
+    pub chunk: ChunkCoord,
+    pub version: u64,
+    pub change: ChunkChangeV1,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorldChatTurn {
+    /// Counts up from 1 over the whole conversation, so it stays put as old turns are dropped.
+    pub turn: u64,
+    pub role: String, // "user" | "assistant"
+    pub content: String,
+    /// Assistant turns: the edits they made.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub edits: Vec<AppliedEdit>,
+}
+
+impl Versioned for Vec<WorldChatTurn> {
+    const VERSION: u32 = 1;
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WorldChatResponse {
+    /// The assistant turn this answer was recorded as.
+    pub turn: u64,
+    pub reply: String,
+    pub edits: Vec<AppliedEdit>,
+}
+
+/// What the provider returns; see `schema_json`.
+#[derive(Debug, Deserialize)]
+struct ProviderAnswer {
+    reply: String,
+    #[serde(default)]
+    edits: Vec<ProposedEdit>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProposedEdit {
+    op: String,
+    id: String,
+    #[serde(default)]
+    kind: Option<String>,
+    #[serde(default)]
+    position: Option<[f32; 3]>,
+    #[serde(default)]
+    rotation: Option<[f32; 3]>,
+    #[serde(default)]
+    scale: Option<[f32; 3]>,
+}
+
+fn history_path(world_dir: &Path) -> PathBuf {
+    world_dir.join("assistant").join("chat_history.json")
+}
+
+pub fn load_history(world_dir: &Path) -> Result<Vec<WorldChatTurn>> {
+    Ok(versioned::load(&history_path(world_dir))?.unwrap_or_default())
+}
+
+fn save_history(world_dir: &Path, turns: &[WorldChatTurn]) -> Result<()> {
+    versioned::save(&history_path(world_dir), &turns.to_vec())?;
+    Ok(())
+}
+
+fn schema_json() -> String {
+    // Flat edits with nullable fields, since Codex schemas want every property required.
+    format!(
+        r#"{{
+  "$schema": "https://json-schema.org/draft/2020-12/schema",
+  "type": "object",
+  "additionalProperties": false,
+  "required": ["reply","edits"],
+  "properties": {{
+    "reply": {{ "type": "string", "minLength": 1, "maxLength": 600 }},
+    "edits": {{
+      "type": "array",
+      "maxItems": {MAX_EDITS_PER_TURN},
+      "items": {{
+        "type": "object",
+        "additionalProperties": false,
+        "required": ["op","id","kind","position","rotation","scale"],
+        "properties": {{
+          "op": {{ "type": "string", "enum": ["upsert","remove"] }},
+          "id": {{ "type": "string", "minLength": 1, "maxLength": {MAX_ID_CHARS} }},
+          "kind": {{ "type": ["string","null"], "maxLength": {MAX_KIND_CHARS} }},
+          "position": {{ "type": ["array","null"], "items": {{ "type": "number" }}, "minItems": 3, "maxItems": 3 }},
+          "rotation": {{ "type": ["array","null"], "items": {{ "type": "number" }}, "minItems": 3, "maxItems": 3 }},
+          "scale": {{ "type": ["array","null"], "items": {{ "type": "number" }}, "minItems": 3, "maxItems": 3 }}
+        }}
+      }}
+    }}
+  }}
+}}"#
+    )
+}
+
+/// Every object in the world, by id.
+fn world_objects(world_dir: &Path) -> Result<BTreeMap<String, (ChunkCoord, WorldObjectV1)>> {
+    let mut objects = BTreeMap::new();
+    for file in chunks::list_chunks(world_dir)? {
+        for object in file.objects {
+            objects.insert(object.id.clone(), (file.chunk, object));
+        }
+    }
+    Ok(objects)
+}
+
+/// The prompt with as much recent history as `cfg.max_prompt_tokens` leaves room for.
+fn prompt(
+    cfg: &AssistantConfig,
+    world_name: &str,
+    objects: &BTreeMap<String, (ChunkCoord, WorldObjectV1)>,
+    history: &[WorldChatTurn],
+    message: &str,
+) -> String {
+    let recent = &history[history.len().saturating_sub(PROMPT_TURNS)..];
+    assistant::fit_turns(cfg, recent, |turns, omitted| {
+        compose_prompt(world_name, objects, turns, omitted, message)
+    })
+}
+
+fn compose_prompt(
+    world_name: &str,
+    objects: &BTreeMap<String, (ChunkCoord, WorldObjectV1)>,
+    turns: &[WorldChatTurn],
+    omitted: usize,
+    message: &str,
+) -> String {
+    let mut prompt = String::new();
+    prompt.push_str("You are the OWP world-building assistant for the world \"");
+    prompt.push_str(world_name);
+    prompt.push_str("\".\n");
+    for line in [
+        "You talk with its builder about the design and MAY edit the world's objects.",
+        "Return ONLY a JSON object matching the provided schema.",
+        "Do not include markdown, backticks, or explanations.",
+        "",
+        "Rules:",
+        "- Always set `reply` to a friendly, concise message saying what you changed.",
+        "- `edits` changes only what the builder asked for; never rebuild the world. Leave it empty when no change is needed.",
+        "- `upsert` places a new object or replaces the one with that `id`; it needs `kind`, `position`, `rotation` and `scale`.",
+        "- `remove` deletes the object with that `id`; set the other fields to null.",
+        "- Positions are meters [x, y, z] with y up; rotations are degrees; scale 1 is the prefab's size.",
+    ] {
+        prompt.push_str(line);
+        prompt.push('\n');
+    }
+    prompt.push_str(&format!(
+        "- At most {MAX_EDITS_PER_TURN} edits per answer. For forests or rock fields, suggest the scatter brush instead.\n"
+    ));
+    prompt.push_str("\nObjects (id kind x y z):\n");
+    for (id, (_, o)) in objects.iter().take(PROMPT_OBJECTS) {
+        let [x, y, z] = o.position;
+        prompt.push_str(&format!("{id} {} {x} {y} {z}\n", o.kind));
+    }
+    if objects.len() > PROMPT_OBJECTS {
+        prompt.push_str(&format!(
+            "({} more objects not listed)\n",
+            objects.len() - PROMPT_OBJECTS
+        ));
+    }
+    if objects.is_empty() {
+        prompt.push_str("(none yet)\n");
+    }
+    prompt.push_str("\nConversation:\n");
+    if omitted > 0 {
+        prompt.push_str(&format!("({omitted} earlier messages left out)\n"));
+    }
+    for t in turns {
+        let who = if t.role == "assistant" {
+            "Assistant"
+        } else {
+            "Builder"
+        };
+        prompt.push_str(who);
+        prompt.push_str(": ");
+        prompt.push_str(&t.content);
+        if !t.edits.is_empty() {
+            let ids: Vec<String> = t.edits.iter().map(|e| describe(&e.change)).collect();
+            prompt.push_str(&format!(" [edits: {}]", ids.join(", ")));
+        }
+        prompt.push('\n');
+    }
+    prompt.push_str("Builder: ");
+    prompt.push_str(message.trim());
+    prompt.push('\n');
+    prompt
+}
+
+fn describe(change: &ChunkChangeV1) -> String {
+    match change {
+        ChunkChangeV1::Upsert { object } => format!("upsert {}", object.id),
+        ChunkChangeV1::Remove { object_id } => format!("remove {object_id}"),
+    }
+}
+
+fn finite(v: [f32; 3]) -> bool {
+    v.iter().all(|c| c.is_finite())
+}
+
+/// Turn the provider's edits into chunk changes, in order. An object moved to another chunk is
+/// removed from its old one first. Any bad edit refuses the whole answer, so nothing is half
+/// applied.
+fn plan_edits(
+    objects: &BTreeMap<String, (ChunkCoord, WorldObjectV1)>,
+    proposed: Vec<ProposedEdit>,
+) -> Result<Vec<(ChunkCoord, ChunkChangeV1)>> {
+    if proposed.len() > MAX_EDITS_PER_TURN {
+        bail!(
+            "{} edits, over the limit of {MAX_EDITS_PER_TURN}",
+            proposed.len()
+        );
+    }
+    // Where each object is as the edits so far leave it.
+    let mut at: BTreeMap<String, ChunkCoord> = objects
+        .iter()
+        .map(|(id, (chunk, _))| (id.clone(), *chunk))
+        .collect();
+    let mut out = Vec::new();
+    for (i, edit) in proposed.into_iter().enumerate() {
+        let id = edit.id.trim().to_string();
+        if id.is_empty() || id.chars().count() > MAX_ID_CHARS {
+            bail!("edit {i}: bad object id {:?}", edit.id);
+        }
+        match edit.op.as_str() {
+            "remove" => {
+                let Some(chunk) = at.remove(&id) else {
+                    bail!("edit {i}: no object {id:?} to remove");
+                };
+                out.push((chunk, ChunkChangeV1::Remove { object_id: id }));
+            }
+            "upsert" => {
+                let (Some(kind), Some(position)) = (edit.kind, edit.position) else {
+                    bail!("edit {i}: upserting {id:?} needs a kind and a position");
+                };
+                let kind = kind.trim().to_string();
+                if kind.is_empty() || kind.chars().count() > MAX_KIND_CHARS {
+                    bail!("edit {i}: bad kind {kind:?}");
+                }
+                let rotation = edit.rotation.unwrap_or([0.0; 3]);
+                let scale = edit.scale.unwrap_or([1.0; 3]);
+                if !finite(position) || !finite(rotation) || !finite(scale) {
+                    bail!("edit {i}: {id:?} has a non-finite coordinate");
+                }
+                if scale.iter().any(|s| *s <= 0.0) {
+                    bail!("edit {i}: {id:?} has a non-positive scale");
+                }
+                let chunk = chunks::chunk_for_position(position);
+                if let Some(old) = at.insert(id.clone(), chunk).filter(|old| *old != chunk) {
+                    out.push((
+                        old,
+                        ChunkChangeV1::Remove {
+                            object_id: id.clone(),
+                        },
+                    ));
+                }
+                let object = WorldObjectV1 {
+                    id,
+                    kind,
+                    position,
+                    rotation,
+                    scale,
+                };
+                out.push((chunk, ChunkChangeV1::Upsert { object }));
+            }
+            other => bail!("edit {i}: unknown op {other:?}"),
+        }
+    }
+    Ok(out)
+}
+
+/// One exchange: ask the provider about `message`, apply the edits it answers with and record
+/// both turns.
+pub async fn chat(
+    store: &WorldStore,
+    cfg: &AssistantConfig,
+    world_dir: &Path,
+    message: &str,
+) -> Result<WorldChatResponse, AssistantError> {
+    let manifest = store
+        .read_manifest(world_dir)
+        .context("read world manifest")?;
+    let mut history = match load_history(world_dir) {
+        Ok(history) => history,
+        // Answering without it would save over it.
+        Err(e) => return Err(AssistantError::Other(e.context("read world chat history"))),
+    };
+    let objects = world_objects(world_dir).context("list world objects")?;
+    let prompt = prompt(cfg, &manifest.name, &objects, &history, message);
+    let raw_json =
+        assistant::run_structured(store, cfg, "world chat", &prompt, &schema_json()).await?;
+    let answer: ProviderAnswer = serde_json::from_str(&raw_json)
+        .map_err(|e| AssistantError::Corrupt(format!("parse world chat output: {e}")))?;
+    let changes = plan_edits(&objects, answer.edits)
+        .map_err(|e| AssistantError::Corrupt(format!("world chat edits: {e:#}")))?;
+
+    let ops = changes
+        .iter()
+        .map(|(chunk, change)| WalOp::ChunkChange {
+            chunk: *chunk,
+            change: change.clone(),
+        })
+        .collect();
+    let versions = wal::mutate_batch(world_dir, ops)?;
+    let edits: Vec<AppliedEdit> = changes
+        .into_iter()
+        .zip(versions)
+        .map(|((chunk, change), version)| AppliedEdit {
+            chunk,
+            version: version.unwrap_or_default(),
+            change,
+        })
+        .collect();
+    if !edits.is_empty() {
+        info!("world chat made {} edits", edits.len());
+    }
+
+    let next = history.last().map_or(1, |t| t.turn + 1);
+    let reply = answer.reply.trim().to_string();
+    history.push(WorldChatTurn {
+        turn: next,
+        role: "user".to_string(),
+        content: message.trim().to_string(),
+        edits: Vec::new(),
+    });
+    history.push(WorldChatTurn {
+        turn: next + 1,
+        role: "assistant".to_string(),
+        content: reply.clone(),
+        edits: edits.clone(),
+    });
+    if history.len() > HISTORY_LEN {
+        history = history.split_off(history.len() - HISTORY_LEN);
+    }
+    save_history(world_dir, &history).context("save world chat history")?;
+
+    Ok(WorldChatResponse {
+        turn: next + 1,
+        reply,
+        edits,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edit(op: &str, id: &str, position: Option<[f32; 3]>) -> ProposedEdit {
+        ProposedEdit {
+            op: op.to_string(),
+            id: id.to_string(),
+            kind: position.map(|_| "tree_pine".to_string()),
+            position,
+            rotation: None,
+            scale: None,
+        }
+    }
+
+    #[test]
+    fn edits_become_chunk_changes_and_bad_ones_refuse_the_answer() {
+        let mut objects = BTreeMap::new();
+        let rock = WorldObjectV1 {
+            id: "rock-1".to_string(),
+            kind: "rock_large".to_string(),
+            position: [1.0, 0.0, 1.0],
+            rotation: [0.0; 3],
+            scale: [1.0; 3],
+        };
+        objects.insert(rock.id.clone(), (ChunkCoord { x: 0, z: 0 }, rock));
+
+        let changes = plan_edits(
+            &objects,
+            vec![
+                edit("upsert", "tree-1", Some([40.0, 0.0, 5.0])),
+                // Moving the rock a chunk over takes it out of the old one first.
+                ProposedEdit {
+                    kind: Some("rock_large".to_string()),
+                    ..edit("upsert", "rock-1", Some([-3.0, 0.0, 1.0]))
+                },
+                edit("remove", "tree-1", None),
+            ],
+        )
+        .expect("valid edits");
+        let summary: Vec<_> = changes
+            .iter()
+            .map(|(c, change)| (c.x, c.z, describe(change)))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (1, 0, "upsert tree-1".to_string()),
+                (0, 0, "remove rock-1".to_string()),
+                (-1, 0, "upsert rock-1".to_string()),
+                (1, 0, "remove tree-1".to_string()),
+            ]
+        );
+
+        for bad in [
+            edit("remove", "nothing-here", None),
+            edit("upsert", "tree-2", None),
+            edit("upsert", "tree-2", Some([f32::NAN, 0.0, 0.0])),
+            edit("explode", "rock-1", None),
+        ] {
+            assert!(plan_edits(&objects, vec![bad]).is_err());
+        }
+        let too_many = (0..=MAX_EDITS_PER_TURN)
+            .map(|i| edit("upsert", &format!("t{i}"), Some([0.0; 3])))
+            .collect();
+        assert!(plan_edits(&objects, too_many).is_err());
+    }
+
+    #[test]
+    fn history_keeps_turn_numbers_and_edits() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        assert!(load_history(dir.path()).expect("load").is_empty());
+        let turns = vec![WorldChatTurn {
+            turn: 7,
+            role: "assistant".to_string(),
+            content: "Added a pine.".to_string(),
+            edits: vec![AppliedEdit {
+                chunk: ChunkCoord { x: 0, z: 0 },
+                version: 3,
+                change: ChunkChangeV1::Remove {
+                    object_id: "rock-1".to_string(),
+                },
+            }],
+        }];
+        save_history(dir.path(), &turns).expect("save");
+        let loaded = load_history(dir.path()).expect("load");
+        assert_eq!(loaded[0].turn, 7);
+        assert_eq!(loaded[0].edits[0].version, 3);
+    }
+}
@@ -0,0 +1,129 @@
+//! The `world_snapshot` pushed after `welcome`, so a client can draw the world from the game
+//! port alone.
+
+use anyhow::Result;
+use owp_protocol::{ChunkSnapshot, PlayerSnapshot, WorldManifestV1, WorldSnapshot};
+use std::path::Path;
+
+use crate::chunks::{self, ChunkFileV1};
+
+/// Objects carried inline; chunks past this are only named (`more_chunks`), which keeps the
+/// message well under the frame limit.
+pub const MAX_SNAPSHOT_OBJECTS: usize = 10_000;
+
+/// Snapshot of the world in `world_dir`, chunks nearest the origin first.
+pub fn build(
+    world_dir: &Path,
+    manifest: &WorldManifestV1,
+    players: Vec<PlayerSnapshot>,
+) -> Result<WorldSnapshot> {
+    Ok(from_chunks(
+        chunks::list_chunks(world_dir)?,
+        manifest,
+        players,
+        MAX_SNAPSHOT_OBJECTS,
+    ))
+}
+
+fn from_chunks(
+    mut files: Vec<ChunkFileV1>,
+    manifest: &WorldManifestV1,
+    players: Vec<PlayerSnapshot>,
+    max_objects: usize,
+) -> WorldSnapshot {
+    files.retain(|f| !f.objects.is_empty());
+    files.sort_by_key(|f| {
+        let (x, z) = (f.chunk.x as i64, f.chunk.z as i64);
+        (x * x + z * z, f.chunk.x, f.chunk.z)
+    });
+    let mut budget = max_objects;
+    let mut chunks = vec![];
+    let mut more_chunks = vec![];
+    for f in files {
+        if f.objects.len() <= budget && more_chunks.is_empty() {
+            budget -= f.objects.len();
+            chunks.push(ChunkSnapshot {
+                chunk: f.chunk,
+                version: f.version,
+                objects: f.objects,
+            });
+        } else {
+            more_chunks.push(f.chunk);
+        }
+    }
+    WorldSnapshot {
+        world_id: manifest.world_id,
+        name: manifest.name.clone(),
+        chunks,
+        more_chunks,
+        players,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::WorldStore;
+    use owp_protocol::{ChunkChangeV1, ChunkCoord, WorldObjectV1};
+    use uuid::Uuid;
+
+    fn object(id: &str, x: f32) -> WorldObjectV1 {
+        WorldObjectV1 {
+            id: id.to_string(),
+            kind: "rock".to_string(),
+            position: [x, 0.0, 0.0],
+            rotation: [0.0; 3],
+            scale: [1.0; 3],
+        }
+    }
+
+    #[test]
+    fn nearest_chunks_fill_the_snapshot_and_the_rest_are_named() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let store = WorldStore::with_root(root.path().to_path_buf()).expect("store");
+        let manifest = store.create_world("Snapshot", 7777).expect("world");
+        let dir = store.world_dir(manifest.world_id);
+        for (id, x) in [("near", 1.0), ("near2", 2.0), ("mid", 40.0), ("far", 100.0)] {
+            let chunk = chunks::chunk_for_position([x, 0.0, 0.0]);
+            let mut file = chunks::load_chunk(&dir, chunk).expect("load");
+            file.apply(ChunkChangeV1::Upsert {
+                object: object(id, x),
+            });
+            chunks::save_chunk(&dir, &file).expect("save");
+        }
+        // Removing the only object leaves an empty chunk, which isn't worth sending.
+        let mut empty = chunks::load_chunk(&dir, ChunkCoord { x: -1, z: 0 }).expect("load");
+        empty.apply(ChunkChangeV1::Upsert {
+            object: object("gone", -1.0),
+        });
+        empty.apply(ChunkChangeV1::Remove {
+            object_id: "gone".to_string(),
+        });
+        chunks::save_chunk(&dir, &empty).expect("save");
+
+        let player = PlayerSnapshot {
+            player_id: Uuid::new_v4(),
+            position: Some([1.0, 0.0, 1.0]),
+            avatar: None,
+        };
+        let player_id = player.player_id;
+        let snap = build(&dir, &manifest, vec![player]).expect("snapshot");
+        assert_eq!(snap.world_id, manifest.world_id);
+        assert_eq!(snap.players.len(), 1);
+        assert_eq!(snap.players[0].player_id, player_id);
+        let coords: Vec<i32> = snap.chunks.iter().map(|c| c.chunk.x).collect();
+        assert_eq!(coords, vec![0, 1, 3]);
+        assert_eq!(snap.chunks[0].objects.len(), 2);
+        assert_eq!(snap.chunks[0].version, 2);
+        assert!(snap.more_chunks.is_empty());
+
+        let small = from_chunks(
+            chunks::list_chunks(&dir).expect("chunks"),
+            &manifest,
+            vec![],
+            3,
+        );
+        assert_eq!(small.chunks.len(), 2);
+        assert_eq!(small.more_chunks, vec![ChunkCoord { x: 3, z: 0 }]);
+    }
+}
@@ -0,0 +1,245 @@
+use anyhow::{Context, Result};
+use owp_discovery::probe::ProbeResult;
+use owp_protocol::{ListingTrust, WorldDirectoryEntry, WorldRules};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::content;
+use crate::reputation::ReputationStoreV1;
+
+/// How long a summary is served before the registry, directories and probes are hit again.
+pub const SUMMARY_TTL: Duration = Duration::from_secs(10);
+
+pub const MAX_TAGS: usize = 8;
+pub const MAX_TAG_CHARS: usize = 24;
+pub const MAX_ICON_BYTES: usize = 256 * 1024;
+
+/// Tags are short lowercase labels like "pvp" or "creative".
+pub fn check_tags(tags: &[String]) -> Result<()> {
+    if tags.len() > MAX_TAGS {
+        anyhow::bail!("at most {MAX_TAGS} tags");
+    }
+    for t in tags {
+        let ok = !t.is_empty()
+            && t.chars().count() <= MAX_TAG_CHARS
+            && t.chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+        if !ok {
+            anyhow::bail!("tag {t:?} isn't 1-{MAX_TAG_CHARS} characters of a-z, 0-9 and '-'");
+        }
+    }
+    Ok(())
+}
+
+/// Round trips are rounded to this many ms so the ETag doesn't change on probe jitter alone.
+const RTT_BUCKET_MS: u32 = 10;
+
+/// One world as a launcher's server browser shows it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorldSummary {
+    pub world_id: Uuid,
+    pub name: String,
+    /// `owp://host:port?world=<uuid>`
+    pub connect: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon_sha256: Option<String>,
+    /// Whether the last probe got through; absent when probing is off.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub online: Option<bool>,
+    /// From this round's probe, else what the listing last published.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub player_count: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_players: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stake_lamports: Option<u64>,
+    /// Rules the world asks players to accept before entering.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rules: Option<WorldRules>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rtt_ms: Option<u32>,
+    pub score: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_mint: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trust: Option<ListingTrust>,
+}
+
+/// Join listings with this round's probe results and stored reputation. Reachable worlds come
+/// first, fastest first.
+pub fn summarize(
+    worlds: Vec<WorldDirectoryEntry>,
+    probes: &[ProbeResult],
+    rep: &ReputationStoreV1,
+) -> Vec<WorldSummary> {
+    let mut out: Vec<WorldSummary> = worlds
+        .into_iter()
+        .map(|w| {
+            let r = rep.get(w.world_id);
+            let probe = probes.iter().find(|p| p.world_id == w.world_id);
+            let rtt_ms = probe
+                .map_or_else(|| r.last_rtt_ms(), |p| p.rtt_ms)
+                .map(|ms| ms.div_ceil(RTT_BUCKET_MS) * RTT_BUCKET_MS);
+            WorldSummary {
+                world_id: w.world_id,
+                connect: format!("owp://{}:{}?world={}", w.endpoint, w.port, w.world_id),
+                name: w.name,
+                region: w.region,
+                tags: w.tags,
+                icon_sha256: w.icon_sha256.filter(|h| content::is_sha256(h)),
+                online: probe.map(|p| p.ok),
+                player_count: probe.and_then(|p| p.player_count).or(w.player_count),
+                max_players: w.max_players,
+                stake_lamports: w.stake_lamports,
+                rules: w.rules,
+                rtt_ms,
+                score: (r.score() * 100.0).round() / 100.0,
+                token_mint: w.token_mint,
+                trust: w.trust,
+            }
+        })
+        .collect();
+    out.sort_by_key(|s| (s.online == Some(false), s.rtt_ms.is_none(), s.rtt_ms));
+    out
+}
+
+/// A rendered summary and its ETag.
+pub struct Snapshot {
+    pub body: Vec<u8>,
+    pub etag: String,
+    built: Instant,
+}
+
+impl Snapshot {
+    pub fn new(worlds: &[WorldSummary]) -> Result<Self> {
+        let body = serde_json::to_vec(worlds).context("serialize summary")?;
+        let etag = format!("\"{}\"", &content::hash(&body)[..32]);
+        Ok(Self {
+            body,
+            etag,
+            built: Instant::now(),
+        })
+    }
+
+    /// Seconds until the snapshot is rebuilt, for `Cache-Control: max-age`.
+    pub fn max_age_secs(&self) -> u64 {
+        SUMMARY_TTL.saturating_sub(self.built.elapsed()).as_secs()
+    }
+}
+
+/// Last summary built. Only one rebuild runs at a time; requests arriving meanwhile wait for it
+/// instead of probing everything again.
+#[derive(Clone, Default)]
+pub struct SummaryCache(Arc<tokio::sync::Mutex<Option<Arc<Snapshot>>>>);
+
+impl SummaryCache {
+    pub async fn get<F, Fut>(&self, build: F) -> Result<Arc<Snapshot>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Vec<WorldSummary>>>,
+    {
+        let mut slot = self.0.lock().await;
+        if let Some(s) = slot.as_ref().filter(|s| s.built.elapsed() < SUMMARY_TTL) {
+            return Ok(s.clone());
+        }
+        let snapshot = Arc::new(Snapshot::new(&build().await?)?);
+        *slot = Some(snapshot.clone());
+        Ok(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn entry(name: &str) -> WorldDirectoryEntry {
+        WorldDirectoryEntry {
+            world_id: Uuid::new_v4(),
+            name: name.to_string(),
+            endpoint: "play.example.com".to_string(),
+            port: 7777,
+            token_mint: None,
+            dbc_pool: None,
+            world_pubkey: None,
+            last_seen: None,
+            trust: None,
+            region: None,
+            rtt_ms: None,
+            tags: vec!["pvp".to_string()],
+            icon_sha256: Some("not-a-hash".to_string()),
+            player_count: None,
+            max_players: None,
+            stake_lamports: None,
+            rules: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn summary_is_sorted_and_cached() {
+        let (mut slow, fast, down) = (entry("slow"), entry("fast"), entry("down"));
+        (slow.player_count, slow.max_players) = (Some(7), Some(32));
+        let probes = vec![
+            ProbeResult {
+                world_id: slow.world_id,
+                ok: true,
+                rtt_ms: Some(81),
+                player_count: Some(3),
+                attested: None,
+                error: None,
+            },
+            ProbeResult {
+                world_id: fast.world_id,
+                ok: true,
+                rtt_ms: Some(12),
+                player_count: Some(0),
+                attested: None,
+                error: None,
+            },
+            ProbeResult {
+                world_id: down.world_id,
+                ok: false,
+                rtt_ms: None,
+                player_count: None,
+                attested: None,
+                error: Some("refused".to_string()),
+            },
+        ];
+        let worlds = summarize(
+            vec![down, slow, fast],
+            &probes,
+            &ReputationStoreV1::default(),
+        );
+        let names: Vec<&str> = worlds.iter().map(|w| w.name.as_str()).collect();
+        assert_eq!(names, ["fast", "slow", "down"]);
+        assert_eq!(worlds[1].rtt_ms, Some(90));
+        assert_eq!(worlds[1].player_count, Some(3));
+        assert_eq!(worlds[1].max_players, Some(32));
+        assert!(worlds[0].icon_sha256.is_none());
+        assert!(worlds[0]
+            .connect
+            .starts_with("owp://play.example.com:7777?world="));
+
+        let cache = SummaryCache::default();
+        let builds = AtomicUsize::new(0);
+        let build = || async {
+            builds.fetch_add(1, Ordering::Relaxed);
+            Ok(worlds.clone())
+        };
+        let first = cache.get(build).await.expect("summary");
+        let second = cache.get(build).await.expect("summary");
+        assert_eq!(builds.load(Ordering::Relaxed), 1);
+        assert_eq!(first.etag, second.etag);
+        assert_ne!(
+            Snapshot::new(&worlds[..1]).expect("snapshot").etag,
+            first.etag
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use owp_registry_types::EntryError;
 use solana_program::program_error::ProgramError;
 
 #[repr(u32)]
@@ -8,6 +9,34 @@ pub enum RegistryError {
     StringTooLong = 4,
     AlreadyInitialized = 5,
     InvalidAccountData = 6,
+    /// Not a normalized host (see `owp_registry_types::is_valid_endpoint`).
+    InvalidEndpoint = 7,
+    /// `game_port` (or a set `asset_port`) is 0.
+    InvalidPort = 8,
+    /// The stake of a delisting world is still in its cooldown.
+    StakeLocked = 9,
+    /// The world is being delisted; it can't take more stake or aliases.
+    Delisting = 10,
+    /// No normalized form (see `owp_registry_types::normalize_alias`).
+    InvalidAlias = 11,
+    /// Another world holds the alias.
+    AliasTaken = 12,
+    /// `player_count` is over a known `max_players`.
+    TooManyPlayers = 13,
+}
+
+impl From<EntryError> for RegistryError {
+    fn from(e: EntryError) -> Self {
+        match e {
+            EntryError::InvalidEndpoint(_) => RegistryError::InvalidEndpoint,
+            EntryError::StringTooLong { .. } => RegistryError::StringTooLong,
+            EntryError::InvalidPort { .. } => RegistryError::InvalidPort,
+            EntryError::InvalidPubkey { .. }
+            | EntryError::BadMagic
+            | EntryError::UnsupportedVersion(_)
+            | EntryError::BadLength { .. } => RegistryError::InvalidAccountData,
+        }
+    }
 }
 
 impl From<RegistryError> for ProgramError {
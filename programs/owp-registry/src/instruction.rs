@@ -33,6 +33,32 @@ pub enum RegistryInstruction {
     },
 
     DelistWorld,
+
+    /// Publish current occupancy. Upgrades an older entry to the current version first, with
+    /// the authority paying the extra rent.
+    Heartbeat {
+        /// At most `max_players` when that is known, else `TooManyPlayers`.
+        player_count: u16,
+        /// 0 = unknown.
+        max_players: u16,
+    },
+
+    /// Lock `lamports` from the authority in the world account as stake. Send it in the same
+    /// transaction as `RegisterWorld` to list staked from the start. Upgrades older entries like
+    /// `Heartbeat`.
+    AddStake {
+        lamports: u64,
+    },
+
+    /// Point the alias PDA `["alias", sha256(normalized alias)]` at a world. First come, first
+    /// served: fails with `AliasTaken` while the alias account exists.
+    ClaimAlias {
+        alias: String,
+    },
+
+    /// Close an alias account, returning its rent to the authority that claimed it. The
+    /// authority can always release; anyone can once the world is delisted or delisting.
+    ReleaseAlias,
 }
 
 pub fn decode(input: &[u8]) -> Result<RegistryInstruction, ProgramError> {
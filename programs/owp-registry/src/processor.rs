@@ -1,13 +1,13 @@
-use borsh::{BorshDeserialize, BorshSerialize};
+use borsh::BorshSerialize;
 use owp_registry_types::{
-    read_fixed_string, write_fixed_string, WorldEntry, WORLD_ENTRY_MAGIC, WORLD_ENTRY_VERSION,
-    SEED_WORLD,
+    alias_hash, is_valid_endpoint, normalize_alias, write_fixed_string, AliasEntry, WorldEntry,
+    WorldEntryBuilder, SEED_ALIAS, SEED_WORLD, STAKE_COOLDOWN_SLOTS, WORLD_ENTRY_VERSION,
 };
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
     entrypoint::ProgramResult,
     msg,
-    program::invoke_signed,
+    program::{invoke, invoke_signed},
     program_error::ProgramError,
     pubkey::Pubkey,
     system_instruction,
@@ -70,6 +70,17 @@ impl Processor {
                 metadata_uri,
             ),
             RegistryInstruction::DelistWorld => Self::delist_world(program_id, accounts),
+            RegistryInstruction::Heartbeat {
+                player_count,
+                max_players,
+            } => Self::heartbeat(program_id, accounts, player_count, max_players),
+            RegistryInstruction::AddStake { lamports } => {
+                Self::add_stake(program_id, accounts, lamports)
+            }
+            RegistryInstruction::ClaimAlias { alias } => {
+                Self::claim_alias(program_id, accounts, alias)
+            }
+            RegistryInstruction::ReleaseAlias => Self::release_alias(program_id, accounts),
         }
     }
 
@@ -91,6 +102,9 @@ impl Processor {
         {
             return Err(RegistryError::StringTooLong.into());
         }
+        if !is_valid_endpoint(&endpoint) {
+            return Err(RegistryError::InvalidEndpoint.into());
+        }
 
         let account_info_iter = &mut accounts.iter();
         let payer = next_account_info(account_info_iter)?;
@@ -117,13 +131,13 @@ impl Processor {
         }
 
         let rent = Rent::get()?;
-        let lamports = rent.minimum_balance(WorldEntry::LEN);
+        let lamports = rent.minimum_balance(WorldEntry::LEN_V3);
         invoke_signed(
             &system_instruction::create_account(
                 payer.key,
                 world_entry_account.key,
                 lamports,
-                WorldEntry::LEN as u64,
+                WorldEntry::LEN_V3 as u64,
                 program_id,
             ),
             &[payer.clone(), world_entry_account.clone(), system_program.clone()],
@@ -132,36 +146,28 @@ impl Processor {
 
         let clock = Clock::get()?;
 
-        let mut entry = WorldEntry {
-            magic: WORLD_ENTRY_MAGIC,
-            version: WORLD_ENTRY_VERSION,
-            bump,
-            world_id,
-            authority: authority.key.to_bytes(),
-            name: [0u8; owp_registry_types::NAME_LEN],
-            endpoint: [0u8; owp_registry_types::ENDPOINT_LEN],
-            game_port,
-            asset_port: asset_port.unwrap_or(0),
-            token_mint: token_mint.unwrap_or([0u8; 32]),
-            dbc_pool: dbc_pool.unwrap_or([0u8; 32]),
-            metadata_uri: [0u8; owp_registry_types::METADATA_URI_LEN],
-            last_update_slot: clock.slot,
-        };
-
-        write_fixed_string(&mut entry.name, &name).map_err(|_| RegistryError::StringTooLong)?;
-        write_fixed_string(&mut entry.endpoint, &endpoint).map_err(|_| RegistryError::StringTooLong)?;
-        write_fixed_string(&mut entry.metadata_uri, &metadata_uri)
-            .map_err(|_| RegistryError::StringTooLong)?;
+        // Lengths were checked above, so the builder never truncates the name here.
+        let entry = WorldEntryBuilder::new(world_id, authority.key.to_bytes())
+            .bump(bump)
+            .name(&name)
+            .endpoint(&endpoint)
+            .game_port(game_port)
+            .asset_port(asset_port)
+            .token_mint(token_mint)
+            .dbc_pool(dbc_pool)
+            .metadata_uri(&metadata_uri)
+            .last_update_slot(clock.slot)
+            .build()
+            .map_err(RegistryError::from)?;
 
-        let mut data = world_entry_account.data.borrow_mut();
         entry
-            .serialize(&mut &mut data[..])
-            .map_err(|_| RegistryError::InvalidAccountData)?;
+            .write_to(&mut world_entry_account.data.borrow_mut())
+            .map_err(RegistryError::from)?;
 
         msg!(
             "registered world: {} at {}:{}",
-            read_fixed_string(&entry.name),
-            read_fixed_string(&entry.endpoint),
+            entry.name(),
+            entry.endpoint(),
             entry.game_port
         );
         Ok(())
@@ -189,11 +195,7 @@ impl Processor {
             return Err(ProgramError::IncorrectProgramId);
         }
 
-        let mut entry = WorldEntry::try_from_slice(&world_entry_account.data.borrow())
-            .map_err(|_| RegistryError::InvalidAccountData)?;
-        if entry.magic != WORLD_ENTRY_MAGIC || entry.version != WORLD_ENTRY_VERSION {
-            return Err(RegistryError::InvalidAccountData.into());
-        }
+        let mut entry = read_entry(world_entry_account)?;
 
         let (expected_pda, _) =
             Pubkey::find_program_address(&[SEED_WORLD, entry.world_id.as_ref()], program_id);
@@ -214,6 +216,9 @@ impl Processor {
             if v.as_bytes().len() > ENDPOINT_MAX_LEN {
                 return Err(RegistryError::StringTooLong.into());
             }
+            if !is_valid_endpoint(&v) {
+                return Err(RegistryError::InvalidEndpoint.into());
+            }
             write_fixed_string(&mut entry.endpoint, &v).map_err(|_| RegistryError::StringTooLong)?;
         }
         if let Some(v) = metadata_uri {
@@ -225,9 +230,15 @@ impl Processor {
         }
 
         if let Some(p) = game_port {
+            if p == 0 {
+                return Err(RegistryError::InvalidPort.into());
+            }
             entry.game_port = p;
         }
         if let Some(v) = asset_port {
+            if v == Some(0) {
+                return Err(RegistryError::InvalidPort.into());
+            }
             entry.asset_port = v.unwrap_or(0);
         }
         if let Some(v) = token_mint {
@@ -239,15 +250,14 @@ impl Processor {
 
         entry.last_update_slot = Clock::get()?.slot;
 
-        let mut data = world_entry_account.data.borrow_mut();
         entry
-            .serialize(&mut &mut data[..])
-            .map_err(|_| RegistryError::InvalidAccountData)?;
+            .write_to(&mut world_entry_account.data.borrow_mut())
+            .map_err(RegistryError::from)?;
 
         msg!(
             "updated world: {} at {}:{}",
-            read_fixed_string(&entry.name),
-            read_fixed_string(&entry.endpoint),
+            entry.name(),
+            entry.endpoint(),
             entry.game_port
         );
         Ok(())
@@ -265,11 +275,7 @@ impl Processor {
             return Err(ProgramError::IncorrectProgramId);
         }
 
-        let entry = WorldEntry::try_from_slice(&world_entry_account.data.borrow())
-            .map_err(|_| RegistryError::InvalidAccountData)?;
-        if entry.magic != WORLD_ENTRY_MAGIC || entry.version != WORLD_ENTRY_VERSION {
-            return Err(RegistryError::InvalidAccountData.into());
-        }
+        let mut entry = read_entry(world_entry_account)?;
         if entry.authority != authority.key.to_bytes() {
             return Err(RegistryError::Unauthorized.into());
         }
@@ -280,6 +286,26 @@ impl Processor {
             return Err(RegistryError::InvalidPda.into());
         }
 
+        // A staked world is delisted in two steps: the first call starts the cooldown (discovery
+        // stops listing it), a call after `unlock_slot` closes the account.
+        if entry.stake_lamports > 0 {
+            let slot = Clock::get()?.slot;
+            if !entry.is_delisting() {
+                entry.unlock_slot = slot.saturating_add(STAKE_COOLDOWN_SLOTS).max(1);
+                entry
+                    .write_to(&mut world_entry_account.data.borrow_mut())
+                    .map_err(RegistryError::from)?;
+                msg!(
+                    "delisting world; stake unlocks at slot {}",
+                    entry.unlock_slot
+                );
+                return Ok(());
+            }
+            if slot < entry.unlock_slot {
+                return Err(RegistryError::StakeLocked.into());
+            }
+        }
+
         // Drain lamports to authority and zero out data.
         let lamports = world_entry_account.lamports();
         **authority.lamports.borrow_mut() = authority
@@ -296,4 +322,284 @@ impl Processor {
         msg!("delisted world entry");
         Ok(())
     }
+
+    fn heartbeat(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        player_count: u16,
+        max_players: u16,
+    ) -> ProgramResult {
+        if max_players != 0 && player_count > max_players {
+            return Err(RegistryError::TooManyPlayers.into());
+        }
+        let (world_entry_account, authority, system_program, mut entry) =
+            Self::upgradable_entry(program_id, accounts)?;
+        upgrade(&mut entry, world_entry_account, authority, system_program)?;
+
+        entry.player_count = player_count;
+        entry.max_players = max_players;
+        entry.last_update_slot = Clock::get()?.slot;
+        entry
+            .write_to(&mut world_entry_account.data.borrow_mut())
+            .map_err(RegistryError::from)?;
+
+        msg!("heartbeat: {player_count}/{max_players} players");
+        Ok(())
+    }
+
+    fn add_stake(program_id: &Pubkey, accounts: &[AccountInfo], lamports: u64) -> ProgramResult {
+        let (world_entry_account, authority, system_program, mut entry) =
+            Self::upgradable_entry(program_id, accounts)?;
+        if entry.is_delisting() {
+            return Err(RegistryError::Delisting.into());
+        }
+        upgrade(&mut entry, world_entry_account, authority, system_program)?;
+
+        invoke(
+            &system_instruction::transfer(authority.key, world_entry_account.key, lamports),
+            &[
+                authority.clone(),
+                world_entry_account.clone(),
+                system_program.clone(),
+            ],
+        )?;
+        entry.stake_lamports = entry
+            .stake_lamports
+            .checked_add(lamports)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        entry
+            .write_to(&mut world_entry_account.data.borrow_mut())
+            .map_err(RegistryError::from)?;
+
+        msg!("staked {lamports} lamports, {} total", entry.stake_lamports);
+        Ok(())
+    }
+
+    fn claim_alias(program_id: &Pubkey, accounts: &[AccountInfo], alias: String) -> ProgramResult {
+        let alias = normalize_alias(&alias).ok_or(RegistryError::InvalidAlias)?;
+
+        let account_info_iter = &mut accounts.iter();
+        let payer = next_account_info(account_info_iter)?;
+        let alias_account = next_account_info(account_info_iter)?;
+        let world_entry_account = next_account_info(account_info_iter)?;
+        let authority = next_account_info(account_info_iter)?;
+        let system_program = next_account_info(account_info_iter)?;
+
+        if !payer.is_signer || !authority.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if world_entry_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        if *system_program.key != solana_program::system_program::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let entry = read_entry(world_entry_account)?;
+        let (world_pda, _) =
+            Pubkey::find_program_address(&[SEED_WORLD, entry.world_id.as_ref()], program_id);
+        if world_pda != *world_entry_account.key {
+            return Err(RegistryError::InvalidPda.into());
+        }
+        if entry.authority != authority.key.to_bytes() {
+            return Err(RegistryError::Unauthorized.into());
+        }
+        if entry.is_delisting() {
+            return Err(RegistryError::Delisting.into());
+        }
+
+        let hash = alias_hash(&alias);
+        let (alias_pda, bump) = Pubkey::find_program_address(&[SEED_ALIAS, &hash], program_id);
+        if alias_pda != *alias_account.key {
+            return Err(RegistryError::InvalidPda.into());
+        }
+        // Lamports alone don't make it taken: anyone can send some to the address first.
+        if alias_account.owner == program_id && alias_account.data.borrow().iter().any(|&b| b != 0)
+        {
+            return Err(RegistryError::AliasTaken.into());
+        }
+
+        create_pda(
+            program_id,
+            payer,
+            alias_account,
+            system_program,
+            AliasEntry::LEN,
+            &[SEED_ALIAS, &hash, &[bump]],
+        )?;
+
+        let alias_entry = AliasEntry::new(
+            &alias,
+            entry.world_id,
+            authority.key.to_bytes(),
+            bump,
+            Clock::get()?.slot,
+        )
+        .map_err(RegistryError::from)?;
+        alias_entry
+            .serialize(&mut &mut alias_account.data.borrow_mut()[..])
+            .map_err(|_| RegistryError::InvalidAccountData)?;
+
+        msg!("claimed alias {alias}");
+        Ok(())
+    }
+
+    /// Accounts `[alias, claiming authority, world entry]`; the authority must sign unless the
+    /// world entry is gone or delisting.
+    fn release_alias(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let alias_account = next_account_info(account_info_iter)?;
+        let authority = next_account_info(account_info_iter)?;
+        let world_entry_account = next_account_info(account_info_iter)?;
+
+        if alias_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let alias_entry =
+            AliasEntry::decode(&alias_account.data.borrow()).map_err(RegistryError::from)?;
+        if alias_entry.authority != authority.key.to_bytes() {
+            return Err(RegistryError::Unauthorized.into());
+        }
+
+        if !authority.is_signer {
+            let (world_pda, _) = Pubkey::find_program_address(
+                &[SEED_WORLD, alias_entry.world_id.as_ref()],
+                program_id,
+            );
+            if world_pda != *world_entry_account.key {
+                return Err(RegistryError::InvalidPda.into());
+            }
+            let world_gone = world_entry_account.lamports() == 0
+                || read_entry(world_entry_account).is_ok_and(|e| e.is_delisting());
+            if !world_gone {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+        }
+
+        let lamports = alias_account.lamports();
+        **authority.lamports.borrow_mut() = authority
+            .lamports()
+            .checked_add(lamports)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        **alias_account.lamports.borrow_mut() = 0;
+        alias_account.data.borrow_mut().fill(0);
+
+        msg!("released alias {}", alias_entry.alias());
+        Ok(())
+    }
+
+    /// Accounts `[world entry, authority (signer), system program]`, checked, and the entry.
+    fn upgradable_entry<'a, 'b>(
+        program_id: &Pubkey,
+        accounts: &'a [AccountInfo<'b>],
+    ) -> Result<
+        (
+            &'a AccountInfo<'b>,
+            &'a AccountInfo<'b>,
+            &'a AccountInfo<'b>,
+            WorldEntry,
+        ),
+        ProgramError,
+    > {
+        let account_info_iter = &mut accounts.iter();
+        let world_entry_account = next_account_info(account_info_iter)?;
+        let authority = next_account_info(account_info_iter)?;
+        let system_program = next_account_info(account_info_iter)?;
+
+        if !authority.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if world_entry_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        if *system_program.key != solana_program::system_program::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let entry = read_entry(world_entry_account)?;
+        let (expected_pda, _) =
+            Pubkey::find_program_address(&[SEED_WORLD, entry.world_id.as_ref()], program_id);
+        if expected_pda != *world_entry_account.key {
+            return Err(RegistryError::InvalidPda.into());
+        }
+        if entry.authority != authority.key.to_bytes() {
+            return Err(RegistryError::Unauthorized.into());
+        }
+        Ok((world_entry_account, authority, system_program, entry))
+    }
+}
+
+/// Grow an older account to the current layout, the authority paying the extra rent.
+fn upgrade<'a>(
+    entry: &mut WorldEntry,
+    account: &AccountInfo<'a>,
+    authority: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+) -> ProgramResult {
+    if entry.version >= WORLD_ENTRY_VERSION {
+        return Ok(());
+    }
+    let needed = Rent::get()?
+        .minimum_balance(WorldEntry::LEN_V3)
+        .saturating_sub(account.lamports().saturating_sub(entry.stake_lamports));
+    if needed > 0 {
+        invoke(
+            &system_instruction::transfer(authority.key, account.key, needed),
+            &[authority.clone(), account.clone(), system_program.clone()],
+        )?;
+    }
+    account.realloc(WorldEntry::LEN_V3, true)?;
+    entry.version = WORLD_ENTRY_VERSION;
+    Ok(())
+}
+
+/// Create the program-owned PDA `account` with `space` bytes, the payer paying rent. If the
+/// address already holds lamports, `create_account` would refuse it, so the account is topped up
+/// to rent exemption and then allocated and assigned instead.
+fn create_pda<'a>(
+    program_id: &Pubkey,
+    payer: &AccountInfo<'a>,
+    account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    space: usize,
+    seeds: &[&[u8]],
+) -> ProgramResult {
+    let rent = Rent::get()?.minimum_balance(space);
+    if account.lamports() == 0 {
+        return invoke_signed(
+            &system_instruction::create_account(
+                payer.key,
+                account.key,
+                rent,
+                space as u64,
+                program_id,
+            ),
+            &[payer.clone(), account.clone(), system_program.clone()],
+            &[seeds],
+        );
+    }
+    let needed = rent.saturating_sub(account.lamports());
+    if needed > 0 {
+        invoke(
+            &system_instruction::transfer(payer.key, account.key, needed),
+            &[payer.clone(), account.clone(), system_program.clone()],
+        )?;
+    }
+    invoke_signed(
+        &system_instruction::allocate(account.key, space as u64),
+        &[account.clone(), system_program.clone()],
+        &[seeds],
+    )?;
+    invoke_signed(
+        &system_instruction::assign(account.key, program_id),
+        &[account.clone(), system_program.clone()],
+        &[seeds],
+    )
+}
+
+/// Decode a world entry account the program can rewrite.
+fn read_entry(account: &AccountInfo) -> Result<WorldEntry, ProgramError> {
+    let entry = WorldEntry::decode(&account.data.borrow()).map_err(RegistryError::from)?;
+    entry.check_header().map_err(RegistryError::from)?;
+    Ok(entry)
 }
@@ -0,0 +1,449 @@
+//! Compute units and rent of every registry instruction, checked against budgets. Running out
+//! of compute or mis-sizing an account makes publishes fail on mainnet, so a change that pushes
+//! an instruction over its budget fails here first.
+//!
+//! Needs the SBF build of the program; native runs don't meter compute. Run
+//! `cargo xtask cu-report`, or `cargo test-sbf --manifest-path programs/owp-registry/Cargo.toml`.
+//! Under plain `cargo test` the tests skip themselves.
+
+use borsh::BorshSerialize;
+use owp_registry::{error::RegistryError, instruction::RegistryInstruction};
+use owp_registry_types::{
+    alias_hash, rent_exempt_lamports, AliasEntry, WorldEntry, WorldEntryBuilder, SEED_ALIAS,
+    SEED_WORLD, STAKE_COOLDOWN_SLOTS,
+};
+use solana_program_test::{ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    account::Account,
+    instruction::{AccountMeta, Instruction, InstructionError},
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_program,
+    transaction::{Transaction, TransactionError},
+};
+
+/// Most compute units each instruction may use (Solana's default limit is 200k per instruction).
+const BUDGETS: &[(&str, u64)] = &[
+    ("register_world", 60_000),
+    ("update_world", 30_000),
+    ("heartbeat", 30_000),
+    ("heartbeat_upgrade", 40_000),
+    ("add_stake", 30_000),
+    ("claim_alias", 60_000),
+    ("claim_prefunded_alias", 60_000),
+    ("release_alias", 20_000),
+    ("delist_world_start", 30_000),
+    ("delist_world_close", 30_000),
+];
+
+const WORLD_ID: [u8; 16] = [7u8; 16];
+const SOL: u64 = 1_000_000_000;
+
+fn sbf_build_available() -> bool {
+    let found =
+        std::env::var_os("SBF_OUT_DIR").is_some() || std::env::var_os("BPF_OUT_DIR").is_some();
+    if !found {
+        eprintln!("skipping: no SBF build of owp_registry (run `cargo xtask cu-report`)");
+    }
+    found
+}
+
+struct Harness {
+    ctx: ProgramTestContext,
+    program_id: Pubkey,
+    authority: Keypair,
+}
+
+impl Harness {
+    /// A bank with the program loaded, a funded authority and `accounts` preloaded.
+    async fn start(accounts: impl FnOnce(&Pubkey, &Keypair) -> Vec<(Pubkey, Account)>) -> Self {
+        let program_id = Pubkey::new_unique();
+        let authority = Keypair::new();
+        let mut pt = ProgramTest::new("owp_registry", program_id, None);
+        pt.prefer_bpf(true);
+        pt.add_account(
+            authority.pubkey(),
+            Account::new(10 * SOL, 0, &system_program::id()),
+        );
+        for (key, account) in accounts(&program_id, &authority) {
+            pt.add_account(key, account);
+        }
+        Self {
+            ctx: pt.start_with_context().await,
+            program_id,
+            authority,
+        }
+    }
+
+    fn world_pda(&self) -> Pubkey {
+        Pubkey::find_program_address(&[SEED_WORLD, &WORLD_ID], &self.program_id).0
+    }
+
+    fn alias_pda(&self, alias: &str) -> Pubkey {
+        Pubkey::find_program_address(&[SEED_ALIAS, &alias_hash(alias)], &self.program_id).0
+    }
+
+    fn ix(&self, ix: RegistryInstruction, accounts: Vec<AccountMeta>) -> Instruction {
+        Instruction {
+            program_id: self.program_id,
+            accounts,
+            data: ix.try_to_vec().expect("serialize instruction"),
+        }
+    }
+
+    /// Run `ix` signed by the payer and the authority, and check and report its compute units.
+    async fn run(&mut self, name: &str, ix: Instruction) -> u64 {
+        let blockhash = self
+            .ctx
+            .get_new_latest_blockhash()
+            .await
+            .expect("blockhash");
+        let tx = Transaction::new_signed_with_payer(
+            &[ix],
+            Some(&self.ctx.payer.pubkey()),
+            &[&self.ctx.payer, &self.authority],
+            blockhash,
+        );
+        let sim = self
+            .ctx
+            .banks_client
+            .simulate_transaction(tx.clone())
+            .await
+            .expect("simulate");
+        if let Some(Err(e)) = &sim.result {
+            panic!("{name} failed: {e:?}");
+        }
+        let units = sim
+            .simulation_details
+            .expect("simulation details")
+            .units_consumed;
+        self.ctx
+            .banks_client
+            .process_transaction(tx)
+            .await
+            .unwrap_or_else(|e| panic!("{name} failed: {e}"));
+
+        let budget = BUDGETS
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, b)| *b)
+            .expect("every measured instruction has a budget");
+        println!("cu-report: {name} {units} {budget}");
+        assert!(
+            units <= budget,
+            "{name} used {units} compute units, budget {budget}"
+        );
+        units
+    }
+
+    async fn account(&mut self, key: Pubkey) -> Option<Account> {
+        self.ctx
+            .banks_client
+            .get_account(key)
+            .await
+            .expect("get account")
+    }
+
+    /// The account holds exactly its rent-exempt minimum plus `extra`.
+    async fn assert_rent(&mut self, key: Pubkey, len: usize, extra: u64) {
+        let account = self.account(key).await.expect("account exists");
+        assert_eq!(account.data.len(), len);
+        let rent = self.ctx.banks_client.get_rent().await.expect("rent");
+        assert_eq!(rent.minimum_balance(len), rent_exempt_lamports(len));
+        assert_eq!(account.lamports, rent_exempt_lamports(len) + extra);
+    }
+
+    async fn slot(&mut self) -> u64 {
+        self.ctx.banks_client.get_root_slot().await.expect("slot")
+    }
+}
+
+#[tokio::test]
+async fn instruction_costs_stay_within_budget() {
+    if !sbf_build_available() {
+        return;
+    }
+    let mut h = Harness::start(|_, _| vec![]).await;
+    let (payer, authority) = (h.ctx.payer.pubkey(), h.authority.pubkey());
+    let world = h.world_pda();
+    let alias = h.alias_pda("my-world");
+    let signer = |key| AccountMeta::new(key, true);
+
+    let register = h.ix(
+        RegistryInstruction::RegisterWorld {
+            world_id: WORLD_ID,
+            name: "Budget world".to_string(),
+            endpoint: "play.example.com".to_string(),
+            game_port: 7777,
+            asset_port: Some(7778),
+            token_mint: Some([1u8; 32]),
+            dbc_pool: Some([2u8; 32]),
+            metadata_uri: "https://example.com/world.json".to_string(),
+        },
+        vec![
+            signer(payer),
+            AccountMeta::new(world, false),
+            AccountMeta::new_readonly(authority, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    );
+    h.run("register_world", register).await;
+    h.assert_rent(world, WorldEntry::LEN_V3, 0).await;
+
+    let update = h.ix(
+        RegistryInstruction::UpdateWorld {
+            name: Some("Budget world 2".to_string()),
+            endpoint: Some("play2.example.com".to_string()),
+            game_port: Some(7779),
+            asset_port: Some(None),
+            token_mint: None,
+            dbc_pool: None,
+            metadata_uri: Some("https://example.com/world2.json".to_string()),
+        },
+        vec![
+            AccountMeta::new(world, false),
+            AccountMeta::new_readonly(authority, true),
+        ],
+    );
+    h.run("update_world", update).await;
+
+    let with_system = |h: &Harness| {
+        vec![
+            AccountMeta::new(h.world_pda(), false),
+            AccountMeta::new(h.authority.pubkey(), true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ]
+    };
+    let heartbeat = h.ix(
+        RegistryInstruction::Heartbeat {
+            player_count: 12,
+            max_players: 32,
+        },
+        with_system(&h),
+    );
+    h.run("heartbeat", heartbeat).await;
+
+    let stake = SOL / 10;
+    let add_stake = h.ix(
+        RegistryInstruction::AddStake { lamports: stake },
+        with_system(&h),
+    );
+    h.run("add_stake", add_stake).await;
+    h.assert_rent(world, WorldEntry::LEN_V3, stake).await;
+
+    let claim = h.ix(
+        RegistryInstruction::ClaimAlias {
+            alias: "My-World".to_string(),
+        },
+        vec![
+            signer(payer),
+            AccountMeta::new(alias, false),
+            AccountMeta::new_readonly(world, false),
+            AccountMeta::new_readonly(authority, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    );
+    h.run("claim_alias", claim).await;
+    h.assert_rent(alias, AliasEntry::LEN, 0).await;
+
+    let release = h.ix(
+        RegistryInstruction::ReleaseAlias,
+        vec![
+            AccountMeta::new(alias, false),
+            AccountMeta::new(authority, true),
+            AccountMeta::new_readonly(world, false),
+        ],
+    );
+    h.run("release_alias", release).await;
+    assert!(h.account(alias).await.is_none());
+
+    let delist = |h: &Harness| {
+        h.ix(
+            RegistryInstruction::DelistWorld,
+            vec![
+                AccountMeta::new(h.world_pda(), false),
+                AccountMeta::new(h.authority.pubkey(), true),
+            ],
+        )
+    };
+    let ix = delist(&h);
+    h.run("delist_world_start", ix).await;
+    let slot = h.slot().await;
+    h.ctx
+        .warp_to_slot(slot + STAKE_COOLDOWN_SLOTS + 1)
+        .expect("warp past the cooldown");
+    let before = h.account(authority).await.expect("authority").lamports;
+    let ix = delist(&h);
+    h.run("delist_world_close", ix).await;
+    assert!(h.account(world).await.is_none());
+    let after = h.account(authority).await.expect("authority").lamports;
+    assert_eq!(
+        after - before,
+        rent_exempt_lamports(WorldEntry::LEN_V3) + stake
+    );
+}
+
+#[tokio::test]
+async fn v1_accounts_grow_to_the_current_layout_with_exact_rent() {
+    if !sbf_build_available() {
+        return;
+    }
+    let mut h = Harness::start(|program_id, authority| {
+        let (world, bump) = Pubkey::find_program_address(&[SEED_WORLD, &WORLD_ID], program_id);
+        let mut entry = WorldEntryBuilder::new(WORLD_ID, authority.pubkey().to_bytes())
+            .bump(bump)
+            .endpoint("10.0.0.1")
+            .game_port(7777)
+            .build()
+            .expect("entry");
+        entry.version = 1;
+        let mut data = vec![0u8; WorldEntry::LEN];
+        entry.write_to(&mut data).expect("write v1");
+        let account = Account {
+            lamports: rent_exempt_lamports(WorldEntry::LEN),
+            data,
+            owner: *program_id,
+            executable: false,
+            rent_epoch: 0,
+        };
+        vec![(world, account)]
+    })
+    .await;
+    let world = h.world_pda();
+
+    let heartbeat = h.ix(
+        RegistryInstruction::Heartbeat {
+            player_count: 3,
+            max_players: 0,
+        },
+        vec![
+            AccountMeta::new(world, false),
+            AccountMeta::new(h.authority.pubkey(), true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    );
+    h.run("heartbeat_upgrade", heartbeat).await;
+    h.assert_rent(world, WorldEntry::LEN_V3, 0).await;
+
+    let account = h.account(world).await.expect("world");
+    let entry = WorldEntry::decode(&account.data).expect("decode");
+    assert_eq!((entry.version, entry.player_count), (3, 3));
+}
+
+#[tokio::test]
+async fn lamports_sent_to_an_alias_address_do_not_block_the_claim() {
+    if !sbf_build_available() {
+        return;
+    }
+    let mut h = Harness::start(|program_id, _| {
+        let (alias, _) =
+            Pubkey::find_program_address(&[SEED_ALIAS, &alias_hash("my-world")], program_id);
+        vec![(alias, Account::new(1_000, 0, &system_program::id()))]
+    })
+    .await;
+    let (payer, authority) = (h.ctx.payer.pubkey(), h.authority.pubkey());
+    let world = h.world_pda();
+    let alias = h.alias_pda("my-world");
+
+    let register = h.ix(
+        RegistryInstruction::RegisterWorld {
+            world_id: WORLD_ID,
+            name: "Squatted world".to_string(),
+            endpoint: "play.example.com".to_string(),
+            game_port: 7777,
+            asset_port: None,
+            token_mint: None,
+            dbc_pool: None,
+            metadata_uri: String::new(),
+        },
+        vec![
+            AccountMeta::new(payer, true),
+            AccountMeta::new(world, false),
+            AccountMeta::new_readonly(authority, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    );
+    h.run("register_world", register).await;
+
+    let claim = h.ix(
+        RegistryInstruction::ClaimAlias {
+            alias: "my-world".to_string(),
+        },
+        vec![
+            AccountMeta::new(payer, true),
+            AccountMeta::new(alias, false),
+            AccountMeta::new_readonly(world, false),
+            AccountMeta::new_readonly(authority, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    );
+    h.run("claim_prefunded_alias", claim).await;
+    h.assert_rent(alias, AliasEntry::LEN, 0).await;
+
+    let account = h.account(alias).await.expect("alias");
+    assert_eq!(account.owner, h.program_id);
+    let entry = AliasEntry::decode(&account.data).expect("decode");
+    assert_eq!(entry.world_id, WORLD_ID);
+}
+
+#[tokio::test]
+async fn heartbeats_over_capacity_are_refused() {
+    if !sbf_build_available() {
+        return;
+    }
+    let mut h = Harness::start(|_, _| vec![]).await;
+    let (payer, authority) = (h.ctx.payer.pubkey(), h.authority.pubkey());
+    let world = h.world_pda();
+
+    let register = h.ix(
+        RegistryInstruction::RegisterWorld {
+            world_id: WORLD_ID,
+            name: "Full world".to_string(),
+            endpoint: "play.example.com".to_string(),
+            game_port: 7777,
+            asset_port: None,
+            token_mint: None,
+            dbc_pool: None,
+            metadata_uri: String::new(),
+        },
+        vec![
+            AccountMeta::new(payer, true),
+            AccountMeta::new(world, false),
+            AccountMeta::new_readonly(authority, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    );
+    h.run("register_world", register).await;
+
+    let heartbeat = h.ix(
+        RegistryInstruction::Heartbeat {
+            player_count: 33,
+            max_players: 32,
+        },
+        vec![
+            AccountMeta::new(world, false),
+            AccountMeta::new(authority, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    );
+    let blockhash = h.ctx.get_new_latest_blockhash().await.expect("blockhash");
+    let tx = Transaction::new_signed_with_payer(
+        &[heartbeat],
+        Some(&payer),
+        &[&h.ctx.payer, &h.authority],
+        blockhash,
+    );
+    let err = h
+        .ctx
+        .banks_client
+        .process_transaction(tx)
+        .await
+        .expect_err("33 of 32 players is refused");
+    assert_eq!(
+        err.unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(RegistryError::TooManyPlayers as u32)
+        )
+    );
+}
@@ -0,0 +1,238 @@
+//! `idl/owp_registry.json` describes the program for TypeScript and web tooling. These tests
+//! encode sample arguments the way the IDL says and compare with the Borsh encoding of
+//! `RegistryInstruction`, so the IDL can't drift from the Rust types.
+
+use borsh::BorshSerialize;
+use owp_registry::{error::RegistryError, instruction::RegistryInstruction};
+use owp_registry_types::{AliasEntry, WorldEntry};
+use serde_json::{json, Value};
+
+const IDL: &str = include_str!("../idl/owp_registry.json");
+
+fn idl() -> Value {
+    serde_json::from_str(IDL).expect("IDL is JSON")
+}
+
+/// Borsh-encode `value` as IDL type `ty`. Options are `null` or `{ "some": value }` so nested
+/// options stay unambiguous.
+fn encode(ty: &Value, value: &Value, out: &mut Vec<u8>) {
+    if let Some(ty) = ty.as_str() {
+        let n = || value.as_u64().expect("number");
+        match ty {
+            "u8" => out.push(u8::try_from(n()).expect("u8")),
+            "u16" => out.extend(u16::try_from(n()).expect("u16").to_le_bytes()),
+            "u64" => out.extend(n().to_le_bytes()),
+            "string" => {
+                let s = value.as_str().expect("string");
+                out.extend((s.len() as u32).to_le_bytes());
+                out.extend(s.as_bytes());
+            }
+            "publicKey" => encode(&json!({ "array": ["u8", 32] }), value, out),
+            other => panic!("IDL type {other} is not handled"),
+        }
+    } else if let Some(inner) = ty.get("option") {
+        match value.get("some") {
+            Some(v) => {
+                out.push(1);
+                encode(inner, v, out);
+            }
+            None => {
+                assert!(
+                    value.is_null(),
+                    "option sample must be null or {{\"some\": ..}}"
+                );
+                out.push(0);
+            }
+        }
+    } else if let Some([inner, len]) = ty.get("array").and_then(Value::as_array).map(Vec::as_slice)
+    {
+        let items = value.as_array().expect("array");
+        assert_eq!(items.len() as u64, len.as_u64().expect("array length"));
+        for item in items {
+            encode(inner, item, out);
+        }
+    } else {
+        panic!("IDL type {ty} is not handled");
+    }
+}
+
+fn size(ty: &Value) -> usize {
+    match ty.as_str() {
+        Some("u8") => 1,
+        Some("u16") => 2,
+        Some("u64") => 8,
+        Some("publicKey") => 32,
+        Some(other) => panic!("{other} has no fixed size"),
+        None => {
+            let [inner, len] = ty["array"].as_array().expect("array").as_slice() else {
+                panic!("{ty} has no fixed size");
+            };
+            size(inner) * len.as_u64().expect("array length") as usize
+        }
+    }
+}
+
+/// IDL name of a variant; the exhaustive match makes a new variant fail to compile here.
+fn idl_name(ix: &RegistryInstruction) -> &'static str {
+    match ix {
+        RegistryInstruction::RegisterWorld { .. } => "registerWorld",
+        RegistryInstruction::UpdateWorld { .. } => "updateWorld",
+        RegistryInstruction::DelistWorld => "delistWorld",
+        RegistryInstruction::Heartbeat { .. } => "heartbeat",
+        RegistryInstruction::AddStake { .. } => "addStake",
+        RegistryInstruction::ClaimAlias { .. } => "claimAlias",
+        RegistryInstruction::ReleaseAlias => "releaseAlias",
+    }
+}
+
+/// One value of every instruction, with its arguments as the IDL names them.
+fn samples() -> Vec<(RegistryInstruction, Value)> {
+    vec![
+        (
+            RegistryInstruction::RegisterWorld {
+                world_id: [7u8; 16],
+                name: "World".to_string(),
+                endpoint: "play.example.com".to_string(),
+                game_port: 7777,
+                asset_port: Some(7778),
+                token_mint: Some([1u8; 32]),
+                dbc_pool: None,
+                metadata_uri: "https://example.com/world.json".to_string(),
+            },
+            json!({
+                "worldId": vec![7u8; 16],
+                "name": "World",
+                "endpoint": "play.example.com",
+                "gamePort": 7777,
+                "assetPort": { "some": 7778 },
+                "tokenMint": { "some": vec![1u8; 32] },
+                "dbcPool": null,
+                "metadataUri": "https://example.com/world.json",
+            }),
+        ),
+        (
+            RegistryInstruction::UpdateWorld {
+                name: Some("Renamed".to_string()),
+                endpoint: None,
+                game_port: Some(7779),
+                asset_port: Some(None),
+                token_mint: Some(Some([2u8; 32])),
+                dbc_pool: None,
+                metadata_uri: None,
+            },
+            json!({
+                "name": { "some": "Renamed" },
+                "endpoint": null,
+                "gamePort": { "some": 7779 },
+                "assetPort": { "some": null },
+                "tokenMint": { "some": { "some": vec![2u8; 32] } },
+                "dbcPool": null,
+                "metadataUri": null,
+            }),
+        ),
+        (RegistryInstruction::DelistWorld, json!({})),
+        (
+            RegistryInstruction::Heartbeat {
+                player_count: 12,
+                max_players: 32,
+            },
+            json!({ "playerCount": 12, "maxPlayers": 32 }),
+        ),
+        (
+            RegistryInstruction::AddStake { lamports: 1 << 40 },
+            json!({ "lamports": 1u64 << 40 }),
+        ),
+        (
+            RegistryInstruction::ClaimAlias {
+                alias: "my-world".to_string(),
+            },
+            json!({ "alias": "my-world" }),
+        ),
+        (RegistryInstruction::ReleaseAlias, json!({})),
+    ]
+}
+
+#[test]
+fn idl_instructions_match_the_borsh_enum() {
+    let idl = idl();
+    let instructions = idl["instructions"].as_array().expect("instructions");
+    let samples = samples();
+    assert_eq!(instructions.len(), samples.len());
+
+    for (ix, args) in samples {
+        let name = idl_name(&ix);
+        let described = instructions
+            .iter()
+            .find(|i| i["name"] == name)
+            .unwrap_or_else(|| panic!("{name} missing from the IDL"));
+
+        let mut encoded = vec![u8::try_from(
+            described["discriminant"]["value"]
+                .as_u64()
+                .expect("discriminant"),
+        )
+        .expect("u8")];
+        let idl_args = described["args"].as_array().expect("args");
+        assert_eq!(
+            idl_args.len(),
+            args.as_object().expect("args").len(),
+            "{name}"
+        );
+        for arg in idl_args {
+            let arg_name = arg["name"].as_str().expect("arg name");
+            let value = args
+                .get(arg_name)
+                .unwrap_or_else(|| panic!("{name}: no sample for {arg_name}"));
+            encode(&arg["type"], value, &mut encoded);
+        }
+        assert_eq!(encoded, ix.try_to_vec().expect("serialize"), "{name}");
+    }
+}
+
+#[test]
+fn idl_accounts_and_errors_match_the_program() {
+    let idl = idl();
+    let account_len = |name: &str| -> usize {
+        let account = idl["accounts"]
+            .as_array()
+            .expect("accounts")
+            .iter()
+            .find(|a| a["name"] == name)
+            .unwrap_or_else(|| panic!("{name} missing from the IDL"));
+        account["type"]["fields"]
+            .as_array()
+            .expect("fields")
+            .iter()
+            .map(|f| size(&f["type"]))
+            .sum()
+    };
+    assert_eq!(account_len("WorldEntry"), WorldEntry::LEN_V3);
+    assert_eq!(account_len("AliasEntry"), AliasEntry::LEN);
+
+    let errors = [
+        (RegistryError::InvalidInstruction, "InvalidInstruction"),
+        (RegistryError::InvalidPda, "InvalidPda"),
+        (RegistryError::Unauthorized, "Unauthorized"),
+        (RegistryError::StringTooLong, "StringTooLong"),
+        (RegistryError::AlreadyInitialized, "AlreadyInitialized"),
+        (RegistryError::InvalidAccountData, "InvalidAccountData"),
+        (RegistryError::InvalidEndpoint, "InvalidEndpoint"),
+        (RegistryError::InvalidPort, "InvalidPort"),
+        (RegistryError::StakeLocked, "StakeLocked"),
+        (RegistryError::Delisting, "Delisting"),
+        (RegistryError::InvalidAlias, "InvalidAlias"),
+        (RegistryError::AliasTaken, "AliasTaken"),
+        (RegistryError::TooManyPlayers, "TooManyPlayers"),
+    ];
+    let described = idl["errors"].as_array().expect("errors");
+    assert_eq!(described.len(), errors.len());
+    for (error, name) in errors {
+        let code = error as u32;
+        assert!(
+            described
+                .iter()
+                .any(|e| e["name"] == name && e["code"] == code),
+            "{name} = {code} missing from the IDL"
+        );
+    }
+}
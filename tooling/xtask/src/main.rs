@@ -0,0 +1,121 @@
+//! Repo tasks: `cargo xtask <task>`.
+//!
+//! - `cu-report`: compute units of every registry instruction against its budget. Builds the
+//!   program for SBF, so it needs the Solana toolchain (`cargo build-sbf`).
+//! - `bench [<criterion args>]`: the wire codec, fan-out and chunk benchmarks in
+//!   `crates/owp-protocol/benches`. Arguments go to criterion, e.g. `--save-baseline before`, then
+//!   `--baseline before` after a change to compare against it.
+
+use std::path::PathBuf;
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
+
+fn main() -> Result<()> {
+    match std::env::args().nth(1).as_deref() {
+        Some("cu-report") => cu_report(),
+        Some("bench") => bench(std::env::args().skip(2).collect()),
+        _ => bail!("usage: cargo xtask cu-report | cargo xtask bench [<criterion args>]"),
+    }
+}
+
+fn repo_root() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../..")
+}
+
+fn cu_report() -> Result<()> {
+    let manifest = repo_root().join("programs/owp-registry/Cargo.toml");
+    let out = Command::new("cargo")
+        .arg("test-sbf")
+        .arg("--manifest-path")
+        .arg(&manifest)
+        .args([
+            "--test",
+            "compute_budget",
+            "--",
+            "--nocapture",
+            "--test-threads=1",
+        ])
+        .output()
+        .context("run cargo test-sbf (is the Solana toolchain installed?)")?;
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    let rows = parse_report(&stdout);
+    if !out.status.success() {
+        eprint!("{stdout}{}", String::from_utf8_lossy(&out.stderr));
+    }
+    if rows.is_empty() {
+        bail!("no compute unit measurements; did the SBF build run?");
+    }
+
+    println!(
+        "{:<22} {:>8} {:>8} {:>6}",
+        "instruction", "units", "budget", "used"
+    );
+    for row in &rows {
+        println!(
+            "{:<22} {:>8} {:>8} {:>5}%",
+            row.name,
+            row.units,
+            row.budget,
+            row.units * 100 / row.budget.max(1)
+        );
+    }
+    if !out.status.success() {
+        bail!("compute budget tests failed");
+    }
+    Ok(())
+}
+
+fn bench(args: Vec<String>) -> Result<()> {
+    let status = Command::new(std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string()))
+        .current_dir(repo_root())
+        .args(["bench", "-p", "owp-protocol", "--bench", "wire", "--"])
+        .args(&args)
+        .status()
+        .context("run cargo bench")?;
+    if !status.success() {
+        bail!("benchmarks failed ({status})");
+    }
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+struct Row {
+    name: String,
+    units: u64,
+    budget: u64,
+}
+
+/// The `cu-report: <instruction> <units> <budget>` lines the budget tests print.
+fn parse_report(output: &str) -> Vec<Row> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut words = line.strip_prefix("cu-report: ")?.split_whitespace();
+            Some(Row {
+                name: words.next()?.to_string(),
+                units: words.next()?.parse().ok()?,
+                budget: words.next()?.parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_lines_are_picked_out_of_test_output() {
+        let output = "running 2 tests\ncu-report: register_world 23117 60000\n\
+                      test instruction_costs_stay_within_budget ... ok\ncu-report: bad line\n";
+        assert_eq!(
+            parse_report(output),
+            [Row {
+                name: "register_world".to_string(),
+                units: 23117,
+                budget: 60000,
+            }]
+        );
+    }
+}